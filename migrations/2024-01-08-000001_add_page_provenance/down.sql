@@ -0,0 +1 @@
+ALTER TABLE embedding_pages DROP COLUMN IF EXISTS provenance;
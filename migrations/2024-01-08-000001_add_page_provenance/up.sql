@@ -0,0 +1 @@
+ALTER TABLE embedding_pages ADD COLUMN provenance TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+ALTER TABLE file_embeddings DROP COLUMN IF EXISTS superseded;
+ALTER TABLE file_embeddings DROP COLUMN IF EXISTS version;
@@ -0,0 +1,2 @@
+ALTER TABLE file_embeddings ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE file_embeddings ADD COLUMN superseded BOOLEAN NOT NULL DEFAULT false;
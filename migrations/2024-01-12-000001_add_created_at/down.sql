@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS embedding_tags_tag_index;
+DROP INDEX IF EXISTS pages_file_embedding_index;
+ALTER TABLE embedding_pages DROP COLUMN IF EXISTS created_at;
+ALTER TABLE file_embeddings DROP COLUMN IF EXISTS created_at;
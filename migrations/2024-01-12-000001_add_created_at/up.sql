@@ -0,0 +1,4 @@
+ALTER TABLE file_embeddings ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+ALTER TABLE embedding_pages ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+CREATE INDEX IF NOT EXISTS pages_file_embedding_index ON embedding_pages(file_embedding_id);
+CREATE INDEX IF NOT EXISTS embedding_tags_tag_index ON embedding_tags(tag_id);
@@ -0,0 +1,2 @@
+-- the simple configuration keeps identifiers and error codes as written, keyword queries must use the same expression
+CREATE INDEX IF NOT EXISTS pages_content_search_index ON embedding_pages USING gin (to_tsvector('simple', content));
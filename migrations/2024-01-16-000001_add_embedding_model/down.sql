@@ -0,0 +1,2 @@
+ALTER TABLE file_embeddings DROP COLUMN IF EXISTS embedding_dimensions;
+ALTER TABLE file_embeddings DROP COLUMN IF EXISTS embedding_model;
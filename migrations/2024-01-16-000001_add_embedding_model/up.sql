@@ -0,0 +1,2 @@
+ALTER TABLE file_embeddings ADD COLUMN embedding_model TEXT NOT NULL DEFAULT '';
+ALTER TABLE file_embeddings ADD COLUMN embedding_dimensions INT NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS pages_content_hash_index;
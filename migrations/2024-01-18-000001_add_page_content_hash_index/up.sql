@@ -0,0 +1,2 @@
+-- matches the expression of stored_chunk_hashes, which finds duplicate chunks while ingesting
+CREATE INDEX IF NOT EXISTS pages_content_hash_index ON embedding_pages (md5(content));
@@ -1,9 +1,20 @@
-use crate::app::{types::Model, messages::ChatMessage};
+use crate::app::{
+  attachments::AttachmentSummary,
+  citations::{Citation, RetrievalSettings},
+  embeddings::types::IngestedSource,
+  functions::{sandbox::Resource, unified_diff::PatchReview},
+  messages::ChatMessage,
+  model_comparison::ComparedResponse,
+  model_list::ModelListing,
+  session_stats::{ApiStatus, Transaction},
+  types::Model,
+};
+use async_openai::types::{ChatCompletionMessageToolCall, CreateChatCompletionResponse};
 use serde::{
   de::{self, Deserializer, Visitor},
   Deserialize, Serialize,
 };
-use std::fmt;
+use std::{fmt, path::PathBuf};
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Action {
@@ -22,9 +33,48 @@ pub enum Action {
   RequestChatCompletion(),
   AddMessage(ChatMessage),
   SelectModel(Model),
+  OpenModelPicker,
+  ModelListings(Vec<ModelListing>),
+  OpenSourceManager,
+  ConfirmRequest(String),
+  RequestConfirmed(bool),
+  RequestQueued(String),
+  CancelRequest,
+  ResponseInterrupted,
+  ResponseTimedOut,
+  RecordTransaction(Transaction),
+  // the second model's response to a prompt in a/b mode, None when its request failed
+  AlternativeResponse(ComparedResponse, Option<CreateChatCompletionResponse>),
+  // the next step of a replay, ignored if the replay was paused or stepped since it was scheduled
+  PlaybackStep(u64),
+  UpdateApiStatus(ApiStatus),
+  ConfirmToolCall(ChatCompletionMessageToolCall, String),
+  ToolCallConfirmed(ChatCompletionMessageToolCall, bool),
+  ToolCallFinished(String),
+  ReviewPatch(ChatCompletionMessageToolCall, PatchReview),
+  PatchReviewed(ChatCompletionMessageToolCall, PatchReview),
+  RequestPermission(ChatCompletionMessageToolCall, Vec<Resource>),
+  PermissionAnswered(ChatCompletionMessageToolCall, Vec<Resource>, bool),
+  // an example of the last response to run, by its index among the runnable examples, with its code, see :run
+  ConfirmExample(usize, String),
+  ExampleConfirmed(usize, bool),
+  ExampleOutput(usize, String),
+  IngestedSources(Vec<IngestedSource>),
+  // the stored chunks of an ingested source, for the preview in the source manager
+  SourceChunks(i64, Vec<String>),
+  SummarizeSource(String),
+  RetrieveContext(String, RetrievalSettings),
+  SummarizeAttachment(PathBuf),
+  // None when summarizing failed
+  AttachmentSummarized(PathBuf, Option<AttachmentSummary>),
+  AddCitations(Vec<Citation>),
+  ShowCitation(Citation),
+  SetOffline(bool),
   UpdateStatus(Option<String>),
   SetInputVsize(u16),
   SaveSession,
+  AutosaveDraft(String),
+  RestoreDraft(String),
   LoadSession(String),
   EnterVisual,
   EnterNormal,
@@ -63,6 +113,9 @@ impl<'de> Deserialize<'de> for Action {
           "Help" => Ok(Action::Help),
           "EnterInsert" => Ok(Action::EnterInsert),
           "EnterNormal" => Ok(Action::EnterNormal),
+          "OpenModelPicker" => Ok(Action::OpenModelPicker),
+          "OpenSourceManager" => Ok(Action::OpenSourceManager),
+          "CancelRequest" => Ok(Action::CancelRequest),
           data if data.starts_with("Error(") => {
             let error_msg = data.trim_start_matches("Error(").trim_end_matches(')');
             Ok(Action::Error(error_msg.to_string()))
@@ -1,22 +1,72 @@
-use crossterm::event::KeyEvent;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::prelude::Rect;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
+pub mod agent;
+pub mod attachments;
+pub mod auth;
+pub mod autosuggest;
+pub mod batch;
+pub mod brief;
+pub mod citations;
 pub mod color_math;
+pub mod compression;
 pub mod consts;
+pub mod credentials;
+pub mod database;
+pub mod dry_run;
 pub mod embeddings;
+pub mod encryption;
 pub mod errors;
+pub mod export;
+pub mod finetune;
 pub mod functions;
+pub mod guardrails;
 pub mod gpt_interface;
 pub mod helpers;
+pub mod hooks;
+pub mod i18n;
+pub mod input_history;
+pub mod inspector;
 pub mod messages;
+pub mod middleware;
+pub mod mock_provider;
+pub mod model_comparison;
+pub mod model_list;
+pub mod notifications;
+pub mod offline;
+pub mod personas;
+pub mod playback;
+pub mod profiles;
+pub mod project;
+pub mod providers;
+pub mod recovery;
+pub mod redaction;
 pub mod request_validation;
+pub mod response_cache;
+pub mod retention;
+pub mod retry;
+pub mod rpc;
 pub mod session_config;
 pub mod session_data;
+pub mod session_diff;
+pub mod session_list;
+pub mod session_migration;
+pub mod session_search;
+pub mod session_stats;
 pub mod session_view;
+pub mod server;
+pub mod setup;
+pub mod summarize;
+pub mod tee;
+pub mod theme;
+pub mod tool_call_stream;
 pub mod tools;
 pub mod types;
+pub mod undo;
+pub mod vcr;
+pub mod workflow;
 
 use crate::{
   action::Action,
@@ -25,7 +75,7 @@ use crate::{
   tui,
 };
 
-use self::errors::SazidError;
+use self::{errors::SazidError, rpc};
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Mode {
@@ -42,6 +92,8 @@ pub struct App {
   pub should_suspend: bool,
   pub mode: Mode,
   pub last_tick_key_events: Vec<KeyEvent>,
+  // whether a chat request is in flight, when esc and ctrl-c cancel it instead of their usual actions
+  pub processing: bool,
 }
 
 impl App {
@@ -58,6 +110,7 @@ impl App {
       config,
       mode,
       last_tick_key_events: Vec::new(),
+      processing: false,
     })
   }
 
@@ -82,6 +135,17 @@ impl App {
       component.init(tui.size().unwrap()).unwrap();
     }
 
+    let rpc_socket = match self.config.rpc.as_ref().filter(|rpc| rpc.enabled) {
+      Some(rpc) => match rpc::start(rpc, self.config.clone(), action_tx.clone()) {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+          log::error!("failed to open the rpc socket: {}", e);
+          None
+        },
+      },
+      None => None,
+    };
+
     loop {
       if let Some(e) = tui.next().await {
         match e {
@@ -89,6 +153,9 @@ impl App {
           tui::Event::Tick => action_tx.send(Action::Tick).unwrap(),
           tui::Event::Render => action_tx.send(Action::Render).unwrap(),
           tui::Event::Resize(x, y) => action_tx.send(Action::Resize(x, y)).unwrap(),
+          tui::Event::Key(key) if self.processing && is_cancel_key(&key) => {
+            action_tx.send(Action::CancelRequest).unwrap();
+          },
           tui::Event::Key(key) => {
             if let Some(keymap) = self.config.keybindings.get(&self.mode) {
               if let Some(action) = keymap.get(&vec![key]) {
@@ -127,6 +194,8 @@ impl App {
           Action::Quit => self.should_quit = true,
           Action::Suspend => self.should_suspend = true,
           Action::Resume => self.should_suspend = false,
+          Action::EnterProcessing => self.processing = true,
+          Action::ExitProcessing => self.processing = false,
           Action::Resize(w, h) => {
             //trace_dbg!("Action::Resize");
             tui.resize(Rect::new(0, 0, w, h)).unwrap();
@@ -176,6 +245,16 @@ impl App {
       }
     }
     tui.exit().unwrap();
+    // removes the socket file
+    drop(rpc_socket);
     Ok(())
   }
 }
+
+fn is_cancel_key(key: &KeyEvent) -> bool {
+  match key.code {
+    KeyCode::Esc => true,
+    KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+    _ => false,
+  }
+}
@@ -0,0 +1,162 @@
+use std::time::Instant;
+
+use async_openai::types::ChatCompletionMessageToolCall;
+
+pub const DEFAULT_AGENT_MAX_ITERATIONS: usize = 10;
+
+// observations are cut to this many characters in the scratchpad, the model still gets the whole tool output
+const OBSERVATION_PREVIEW_CHARS: usize = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentStatus {
+  Running,
+  // the model answered without calling a tool
+  Done,
+  Stopped(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+  Thought,
+  Action,
+  Observation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScratchpadEntry {
+  pub iteration: usize,
+  pub kind: EntryKind,
+  pub text: String,
+}
+
+// an experimental mode where the model calls tools and reads their output on its own until it reaches a goal, with
+// each step written to the scratchpad, started with the agent command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agent {
+  pub goal: String,
+  pub max_iterations: usize,
+  // requests sent so far, each one is an iteration
+  pub iterations: usize,
+  pub status: AgentStatus,
+  pub scratchpad: Vec<ScratchpadEntry>,
+  pub started: Instant,
+  // (tool call id, function name) of the actions taken, to label their observations
+  actions: Vec<(String, String)>,
+}
+
+impl Agent {
+  pub fn new(goal: &str, max_iterations: usize) -> Self {
+    Agent {
+      goal: goal.to_string(),
+      max_iterations,
+      iterations: 0,
+      status: AgentStatus::Running,
+      scratchpad: vec![],
+      started: Instant::now(),
+      actions: vec![],
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.status == AgentStatus::Running
+  }
+
+  // sent as a system message ahead of the goal
+  pub fn instructions(&self) -> String {
+    format!(
+      "You are working on your own toward this goal: {}\n\nWork in steps. In each response, first write a short \
+      note on what you learned and what you will do next, then call the tools you need. Functions that change \
+      files or the repository are only run once the user confirms them. When the goal is reached, or can't be \
+      reached, answer without calling any tools and summarize the outcome. You have at most {} responses.",
+      self.goal, self.max_iterations
+    )
+  }
+
+  // counts a request, false once the budget is spent, which stops the agent
+  pub fn start_iteration(&mut self) -> bool {
+    if !self.is_running() {
+      return false;
+    }
+    if self.iterations >= self.max_iterations {
+      self.stop(&format!("reached the limit of {} iterations", self.max_iterations));
+      return false;
+    }
+    self.iterations += 1;
+    true
+  }
+
+  // a complete response, without tool calls it is the final answer
+  pub fn record_response(&mut self, content: Option<&str>, tool_calls: &[ChatCompletionMessageToolCall]) {
+    if let Some(content) = content.map(str::trim).filter(|content| !content.is_empty()) {
+      self.push(EntryKind::Thought, content.to_string());
+    }
+    for tool_call in tool_calls {
+      self.push(EntryKind::Action, format!("{}({})", tool_call.function.name, tool_call.function.arguments));
+      self.actions.push((tool_call.id.clone(), tool_call.function.name.clone()));
+    }
+    if tool_calls.is_empty() && self.is_running() {
+      self.status = AgentStatus::Done;
+    }
+  }
+
+  pub fn record_observation(&mut self, tool_call_id: &str, output: &str) {
+    let name = self.actions.iter().find(|(id, _)| id == tool_call_id).map_or("tool", |(_, name)| name.as_str());
+    let mut preview = output.trim().chars().take(OBSERVATION_PREVIEW_CHARS).collect::<String>();
+    if output.trim().chars().count() > OBSERVATION_PREVIEW_CHARS {
+      preview.push('…');
+    }
+    self.push(EntryKind::Observation, format!("{}: {}", name, preview));
+  }
+
+  pub fn stop(&mut self, reason: &str) {
+    if self.is_running() {
+      self.status = AgentStatus::Stopped(reason.to_string());
+    }
+  }
+
+  pub fn describe(&self) -> String {
+    let status = match &self.status {
+      AgentStatus::Running => "running".to_string(),
+      AgentStatus::Done => "done".to_string(),
+      AgentStatus::Stopped(reason) => format!("stopped, {}", reason),
+    };
+    format!("agent {}, iteration {}/{}", status, self.iterations, self.max_iterations)
+  }
+
+  fn push(&mut self, kind: EntryKind, text: String) {
+    self.scratchpad.push(ScratchpadEntry { iteration: self.iterations, kind, text });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use async_openai::types::{ChatCompletionToolType, FunctionCall};
+
+  #[test]
+  fn test_agent_iterations() {
+    let mut agent = Agent::new("find the unused functions", 2);
+    assert!(agent.start_iteration());
+    let tool_call = ChatCompletionMessageToolCall {
+      id: "call_1".to_string(),
+      r#type: ChatCompletionToolType::Function,
+      function: FunctionCall { name: "file_search".to_string(), arguments: "{}".to_string() },
+    };
+    agent.record_response(Some("I will list the files first"), &[tool_call]);
+    agent.record_observation("call_1", &"src/main.rs\n".repeat(100));
+    assert!(agent.is_running());
+    assert_eq!(agent.scratchpad[1].text, "file_search({})");
+    assert!(agent.scratchpad[2].text.starts_with("file_search: src/main.rs"));
+    assert!(agent.scratchpad[2].text.ends_with('…'));
+
+    assert!(agent.start_iteration());
+    assert!(!agent.start_iteration());
+    assert_eq!(agent.status, AgentStatus::Stopped("reached the limit of 2 iterations".to_string()));
+
+    let mut agent = Agent::new("say hi", 2);
+    agent.start_iteration();
+    agent.record_response(Some("hi"), &[]);
+    assert_eq!(agent.status, AgentStatus::Done);
+    assert!(!agent.start_iteration());
+  }
+}
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use async_openai::types::ChatCompletionRequestSystemMessage;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{components::session::create_openai_client, config::Config};
+
+use super::{
+  errors::SazidError,
+  functions::argument_validation::count_tokens,
+  session_config::SessionConfig,
+  summarize::{map_reduce, Document},
+};
+
+pub const DEFAULT_ATTACHMENT_MAX_TOKENS: usize = 2000;
+
+// lines kept at each end of a large file while its summary is made
+const EXCERPT_LINES: usize = 40;
+
+const ATTACHMENT_MAP_PROMPT: &str = "Summarize this part of a file so that questions about it can be answered without \
+reading it. Keep names, signatures, numbers and decisions exact.";
+
+const ATTACHMENT_REDUCE_PROMPT: &str = "Merge these partial summaries of the same file into a single summary. Keep \
+names, signatures, numbers and decisions exact, and keep it terse.";
+
+// a file attached to a session with the attach command, read again for every request so that edits are picked up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Attachment {
+  pub path: PathBuf,
+  // the summary sent in place of a file over attachment_max_tokens
+  #[serde(default)]
+  pub summary: Option<AttachmentSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttachmentSummary {
+  // of the content that was summarized, a file that changed since is summarized again
+  pub checksum: String,
+  pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttachedContent {
+  Full(String),
+  Summary(String),
+  // the start and end of a large file, sent until its summary is made
+  Excerpt(String),
+  Unreadable(String),
+}
+
+// what an attachment adds to the next request
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachedFile {
+  pub path: PathBuf,
+  pub content: AttachedContent,
+  pub tokens: usize,
+}
+
+impl AttachedFile {
+  pub fn needs_summary(&self) -> bool {
+    matches!(self.content, AttachedContent::Excerpt(_))
+  }
+
+  pub fn describe(&self) -> &'static str {
+    match self.content {
+      AttachedContent::Full(_) => "full",
+      AttachedContent::Summary(_) => "summary",
+      AttachedContent::Excerpt(_) => "excerpt, summarizing",
+      AttachedContent::Unreadable(_) => "unreadable",
+    }
+  }
+}
+
+pub fn checksum(content: &str) -> String {
+  blake3::hash(content.as_bytes()).to_hex().to_string()
+}
+
+impl Attachment {
+  pub fn new(path: &Path) -> Self {
+    Attachment { path: path.to_path_buf(), summary: None }
+  }
+
+  // the file as it is now, whole when it fits in max_tokens, otherwise its summary when it is current
+  pub fn read(&self, max_tokens: usize) -> AttachedFile {
+    let content = match std::fs::read_to_string(&self.path) {
+      Ok(content) if count_tokens(&content) <= max_tokens => AttachedContent::Full(content),
+      Ok(content) => match &self.summary {
+        Some(summary) if summary.checksum == checksum(&content) => AttachedContent::Summary(summary.text.clone()),
+        _ => AttachedContent::Excerpt(excerpt(&content)),
+      },
+      Err(e) => AttachedContent::Unreadable(e.to_string()),
+    };
+    let tokens = match &content {
+      AttachedContent::Full(text) | AttachedContent::Summary(text) | AttachedContent::Excerpt(text) => {
+        count_tokens(text)
+      },
+      AttachedContent::Unreadable(_) => 0,
+    };
+    AttachedFile { path: self.path.clone(), content, tokens }
+  }
+}
+
+impl SessionConfig {
+  pub fn read_attachments(&self) -> Vec<AttachedFile> {
+    self.attached_files.iter().map(|attachment| attachment.read(self.attachment_max_tokens)).collect()
+  }
+}
+
+fn excerpt(content: &str) -> String {
+  let lines = content.lines().collect::<Vec<_>>();
+  if lines.len() <= 2 * EXCERPT_LINES {
+    return content.to_string();
+  }
+  format!(
+    "{}\n[{} lines elided]\n{}",
+    lines[..EXCERPT_LINES].join("\n"),
+    lines.len() - 2 * EXCERPT_LINES,
+    lines[lines.len() - EXCERPT_LINES..].join("\n")
+  )
+}
+
+// the system message that carries the attached files into a request, None without attachments
+pub fn attachments_message(files: &[AttachedFile]) -> Option<ChatCompletionRequestSystemMessage> {
+  if files.is_empty() {
+    return None;
+  }
+  let sections = files.iter().map(|file| {
+    let path = file.path.display();
+    match &file.content {
+      AttachedContent::Full(text) => format!("{}:\n```\n{}\n```", path, text.trim_end()),
+      AttachedContent::Summary(text) => format!("{} (a summary, the file is too large to include):\n{}", path, text),
+      AttachedContent::Excerpt(text) => {
+        format!("{} (the start and end, the file is too large to include):\n{}", path, text)
+      },
+      AttachedContent::Unreadable(e) => format!("{} (attached, but it can't be read: {})", path, e),
+    }
+  });
+  let content = format!(
+    "The user attached these files, they are read again for every message:\n\n{}",
+    sections.collect::<Vec<_>>().join("\n\n")
+  );
+  Some(ChatCompletionRequestSystemMessage { content: Some(content), ..Default::default() })
+}
+
+// summarizes the file as it is now with the session's model
+pub async fn summarize_attachment(config: &Config, path: &Path) -> Result<AttachmentSummary, SazidError> {
+  let content = std::fs::read_to_string(path)?;
+  let documents = vec![Document { name: path.display().to_string(), content: content.clone() }];
+  let client = create_openai_client(&config.session_config.openai_config);
+  let model = &config.session_config.model;
+  let text = map_reduce(&client, model, &documents, ATTACHMENT_MAP_PROMPT, ATTACHMENT_REDUCE_PROMPT, |_| {}).await?;
+  Ok(AttachmentSummary { checksum: checksum(&content), text })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_read_attachment() {
+    let dir = tempfile::tempdir().unwrap();
+    let small = dir.path().join("notes.md");
+    std::fs::write(&small, "ship on friday\n").unwrap();
+    let file = Attachment::new(&small).read(100);
+    assert_eq!(file.content, AttachedContent::Full("ship on friday\n".to_string()));
+    assert!(file.tokens > 0 && !file.needs_summary());
+
+    let large = dir.path().join("log.txt");
+    let content = (1..=200).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+    std::fs::write(&large, &content).unwrap();
+    let mut attachment = Attachment::new(&large);
+    let file = attachment.read(100);
+    assert!(file.needs_summary());
+    match &file.content {
+      AttachedContent::Excerpt(text) => assert!(text.contains("line 40\n[120 lines elided]\nline 161")),
+      content => panic!("expected an excerpt, got {:?}", content),
+    }
+    attachment.summary = Some(AttachmentSummary { checksum: checksum(&content), text: "200 lines".to_string() });
+    assert_eq!(attachment.read(100).content, AttachedContent::Summary("200 lines".to_string()));
+    std::fs::write(&large, format!("{}\nline 201", content)).unwrap();
+    assert!(attachment.read(100).needs_summary());
+
+    let missing = Attachment::new(&dir.path().join("gone.md")).read(100);
+    assert_eq!(missing.describe(), "unreadable");
+    let message = attachments_message(&[Attachment::new(&small).read(100), missing]).unwrap();
+    let content = message.content.unwrap();
+    assert!(content.contains(&format!("{}:\n```\nship on friday\n```", small.display())));
+    assert!(content.contains("gone.md (attached, but it can't be read"));
+    assert!(attachments_message(&[]).is_none());
+  }
+}
@@ -0,0 +1,85 @@
+use std::io::{self, IsTerminal, Read};
+
+use dialoguer::Password;
+
+use crate::cli::AuthCommand;
+
+use super::{
+  credentials::{
+    find_secret, mask_api_key, remove_secret, store_secret, KeySource, GITHUB_TOKEN_ENV, KEYED_PROVIDERS,
+    SERVE_TOKEN_ENV,
+  },
+  errors::SazidError,
+  providers::Provider,
+};
+
+pub fn run_auth(command: &AuthCommand) -> Result<(), SazidError> {
+  match command {
+    AuthCommand::Set { provider } => {
+      let (name, env) = parse_credential(provider)?;
+      // piped keys are read from stdin, so that they don't end up in the shell history
+      let api_key = match io::stdin().is_terminal() {
+        true => Password::new().with_prompt(format!("{} {}", name, key_label(env))).interact()?,
+        false => {
+          let mut api_key = String::new();
+          io::stdin().read_to_string(&mut api_key)?;
+          api_key
+        },
+      };
+      store_secret(env, &api_key)?;
+      println!("stored the {} {} in the keyring", name, key_label(env));
+      if std::env::var(env).is_ok() {
+        println!("{} is also set, the stored key is used instead, it can be removed from the shell profile", env);
+      }
+    },
+    AuthCommand::Status => {
+      let credentials = KEYED_PROVIDERS
+        .iter()
+        .filter_map(|provider| provider.api_key_env().map(|env| (provider_name(*provider), env)))
+        .chain([("github".to_string(), GITHUB_TOKEN_ENV), ("serve".to_string(), SERVE_TOKEN_ENV)]);
+      for (name, env) in credentials {
+        let status = match find_secret(env) {
+          Some((api_key, KeySource::Keyring)) => format!("{} from the keyring", mask_api_key(&api_key)),
+          Some((api_key, KeySource::Environment(env))) => format!("{} from {}", mask_api_key(&api_key), env),
+          None => "not set".to_string(),
+        };
+        println!("{}: {}", name, status);
+      }
+    },
+    AuthCommand::Remove { provider } => {
+      let (name, env) = parse_credential(provider)?;
+      match remove_secret(env)? {
+        true => println!("removed the {} {} from the keyring", name, key_label(env)),
+        false => println!("no {} {} is stored in the keyring", name, key_label(env)),
+      }
+    },
+  }
+  Ok(())
+}
+
+// a provider's api key, the github token used to share sessions as gists or the token of `sazid serve`
+fn parse_credential(name: &str) -> Result<(String, &'static str), SazidError> {
+  if name.eq_ignore_ascii_case("github") {
+    return Ok(("github".to_string(), GITHUB_TOKEN_ENV));
+  }
+  if name.eq_ignore_ascii_case("serve") {
+    return Ok(("serve".to_string(), SERVE_TOKEN_ENV));
+  }
+  serde_json::from_value::<Provider>(serde_json::Value::String(name.to_lowercase()))
+    .ok()
+    .and_then(|provider| provider.api_key_env().map(|env| (provider_name(provider), env)))
+    .ok_or_else(|| {
+      SazidError::Other(format!("unknown provider {}, expected openai, openrouter, github or serve", name))
+    })
+}
+
+fn key_label(env: &str) -> &'static str {
+  match env {
+    GITHUB_TOKEN_ENV | SERVE_TOKEN_ENV => "token",
+    _ => "API key",
+  }
+}
+
+fn provider_name(provider: Provider) -> String {
+  serde_json::to_value(provider).ok().and_then(|name| name.as_str().map(str::to_string)).unwrap_or_default()
+}
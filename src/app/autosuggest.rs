@@ -0,0 +1,129 @@
+use std::{collections::HashMap, path::Path};
+
+use serde_json::Value;
+
+use super::{
+  consts::{data_path, SESSIONS_DIR},
+  encryption,
+  helpers::list_files_ordered_by_date,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptEntry {
+  pub text: String,
+  pub count: usize,
+  pub last_used: usize,
+}
+
+// fish shell style autosuggestions, sourced from prompts submitted in this and previous sessions
+#[derive(Debug, Default, Clone)]
+pub struct PromptSuggester {
+  pub entries: HashMap<String, PromptEntry>,
+  counter: usize,
+}
+
+impl PromptSuggester {
+  pub fn load() -> Self {
+    let mut suggester = PromptSuggester::default();
+    if let Some(sessions_dir) = data_path(SESSIONS_DIR) {
+      suggester.load_sessions_dir(&sessions_dir);
+    }
+    suggester
+  }
+
+  pub fn load_sessions_dir(&mut self, sessions_dir: &Path) {
+    // oldest sessions are loaded first so that recent prompts have a higher last_used value
+    if let Ok(session_files) = list_files_ordered_by_date(sessions_dir) {
+      session_files
+        .iter()
+        .filter(|f| f.path().extension().map(|e| e == "json").unwrap_or(false))
+        .filter_map(|f| encryption::read_to_string(&f.path()).ok())
+        .for_each(|session_json| self.load_session_json(&session_json));
+    }
+  }
+
+  pub fn load_session_json(&mut self, session_json: &str) {
+    if let Ok(session) = serde_json::from_str::<Value>(session_json) {
+      if let Some(messages) = session["data"]["messages"].as_array() {
+        messages
+          .iter()
+          .filter(|m| m["message"]["role"] == "user")
+          .filter_map(|m| m["message"]["content"].as_str())
+          .for_each(|prompt| self.record(prompt));
+      }
+    }
+  }
+
+  pub fn record(&mut self, prompt: &str) {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+      return;
+    }
+    self.counter += 1;
+    let counter = self.counter;
+    let entry = self
+      .entries
+      .entry(prompt.to_string())
+      .or_insert(PromptEntry { text: prompt.to_string(), count: 0, last_used: counter });
+    entry.count += 1;
+    entry.last_used = counter;
+  }
+
+  // returns the remaining text of the best previous prompt that begins with the input
+  pub fn suggest(&self, input: &str) -> Option<String> {
+    if input.trim().is_empty() {
+      return None;
+    }
+    self
+      .entries
+      .values()
+      .filter(|e| e.text.len() > input.len() && e.text.starts_with(input))
+      .max_by(|a, b| self.score(a).partial_cmp(&self.score(b)).unwrap_or(std::cmp::Ordering::Equal))
+      .map(|e| e.text[input.len()..].to_string())
+  }
+
+  // frequency counts logarithmically so that a single recent prompt can outrank an old favorite
+  fn score(&self, entry: &PromptEntry) -> f64 {
+    let recency = entry.last_used as f64 / self.counter.max(1) as f64;
+    (entry.count as f64).ln_1p() + recency
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_suggest_returns_remaining_text() {
+    let mut suggester = PromptSuggester::default();
+    suggester.record("explain this error");
+    assert_eq!(suggester.suggest("explain"), Some(" this error".to_string()));
+    assert_eq!(suggester.suggest("explain this error"), None);
+    assert_eq!(suggester.suggest(""), None);
+  }
+
+  #[test]
+  fn test_suggest_prefers_frequent_and_recent_prompts() {
+    let mut suggester = PromptSuggester::default();
+    suggester.record("write a test for foo");
+    suggester.record("write a test for bar");
+    suggester.record("write a test for bar");
+    assert_eq!(suggester.suggest("write a test for "), Some("bar".to_string()));
+    suggester.record("write a test for baz");
+    suggester.record("write a test for baz");
+    assert_eq!(suggester.suggest("write a test for "), Some("baz".to_string()));
+  }
+
+  #[test]
+  fn test_load_session_json_extracts_user_prompts() {
+    let mut suggester = PromptSuggester::default();
+    suggester.load_session_json(
+      r#"{"data": {"messages": [
+        {"message": {"role": "system", "content": "be terse"}},
+        {"message": {"role": "user", "content": "list the files in src"}}
+      ]}}"#,
+    );
+    assert_eq!(suggester.suggest("list"), Some(" the files in src".to_string()));
+    assert_eq!(suggester.suggest("be"), None);
+  }
+}
@@ -0,0 +1,347 @@
+use std::io::{self, IsTerminal, Read, Write};
+
+use async_openai::{
+  error::OpenAIError,
+  types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage,
+    ChatCompletionRequestUserMessageContent, CreateChatCompletionRequest, Role,
+  },
+};
+use futures::StreamExt;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{cli::Cli, components::session::create_openai_client, config::Config, trace_dbg};
+
+use super::{
+  citations::{context_message, retrieve_file_citations, DEFAULT_RETRIEVED_CHUNKS},
+  compression::{compress_messages, message_text},
+  consts::{data_path, CHUNK_TOKEN_LIMIT, SESSIONS_DIR},
+  dry_run::describe_request,
+  encryption,
+  errors::SazidError,
+  hooks::{HookEffect, Hooks},
+  messages::ChatMessage,
+  middleware::MiddlewareChain,
+  retry::{create_stream_with_retry, retry_status, RetryPolicy},
+  session_config::SessionConfig,
+  session_data::SessionData,
+  session_migration::{migrate_session, SESSION_SCHEMA_VERSION},
+  tools::chunkifier::parse_input,
+  types::Model,
+};
+
+// the serialized portion of a Session, loaded without constructing any of the TUI state
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchSession {
+  #[serde(default)]
+  pub schema_version: u64,
+  pub data: SessionData,
+  pub config: SessionConfig,
+}
+
+impl BatchSession {
+  pub fn new(config: SessionConfig) -> Self {
+    BatchSession { schema_version: SESSION_SCHEMA_VERSION, data: SessionData::default(), config }
+  }
+
+  pub fn load(session_id: &str, config: SessionConfig) -> Result<Self, SazidError> {
+    let sessions_dir = data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()))?;
+    let session_file_path = sessions_dir.join(format!("{}.json", session_id));
+    let session_json = encryption::read_to_string(&session_file_path)
+      .map_err(|e| SazidError::Other(format!("Failed to load session {}: {}", session_file_path.display(), e)))?;
+    let mut session: BatchSession = migrate_session(&session_json)
+      .map_err(|e| SazidError::Other(format!("Failed to parse session {}: {}", session_file_path.display(), e)))?;
+    // the api configuration is not serialized with the session
+    session.config.openai_config = config.openai_config;
+    Ok(session)
+  }
+
+  pub fn save(&self) -> Result<(), SazidError> {
+    let save_dir = data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()))?;
+    std::fs::create_dir_all(&save_dir)?;
+    let session_file_path = save_dir.join(format!("{}.json", self.config.session_id));
+    let data = serde_json::to_string(&self).map_err(|e| SazidError::Other(e.to_string()))?;
+    let tmp_path = session_file_path.with_extension("json.tmp");
+    encryption::write(&tmp_path, data)?;
+    std::fs::rename(tmp_path, &session_file_path)?;
+    trace_dbg!("batch session saved to {}", session_file_path.display());
+    Ok(())
+  }
+
+  pub fn construct_request(&self) -> CreateChatCompletionRequest {
+    let messages: Vec<ChatCompletionRequestMessage> =
+      self.data.messages.iter().filter(|m| m.receive_complete).map(|m| m.message.clone()).collect();
+    let mut request = CreateChatCompletionRequest {
+      model: self.config.provider.model_id(&self.config.model.name),
+      messages,
+      stream: Some(true),
+      max_tokens: Some(self.config.response_max_tokens as u16),
+      ..Default::default()
+    };
+    self.config.request_parameters.apply(&mut request);
+    request
+  }
+
+  // positions of the pinned messages in the request from construct_request
+  pub fn pinned_request_indices(&self) -> Vec<usize> {
+    let messages = self.data.messages.iter().filter(|m| m.receive_complete);
+    messages.enumerate().filter(|(_, m)| m.pinned).map(|(i, _)| i).collect()
+  }
+
+  // the system prompt and the auto context files, added to a session that has no messages yet
+  pub fn add_initial_messages(&mut self) {
+    if !self.data.messages.is_empty() {
+      return;
+    }
+    if !self.config.prompt.is_empty() {
+      self.data.add_message(ChatMessage::System(ChatCompletionRequestSystemMessage {
+        content: Some(self.config.prompt.clone()),
+        ..Default::default()
+      }));
+    }
+    for message in self.config.auto_context_messages() {
+      self.data.add_message(ChatMessage::System(message));
+    }
+  }
+
+  // the request for the transcript, compressed as configured, with a notice of each change made to it, the middleware
+  // redacts it when it is sent
+  pub fn prepare_request(&self) -> Result<(CreateChatCompletionRequest, Vec<String>), SazidError> {
+    let mut request = self.construct_request();
+    let mut notices = vec![];
+    if self.config.compress_prompt {
+      let pinned = self.pinned_request_indices();
+      let threshold = self.config.compression_threshold_tokens;
+      if let Some(report) = compress_messages(&mut request.messages, threshold, &pinned) {
+        notices.push(report.to_string());
+      }
+    }
+    Ok((request, notices))
+  }
+
+  // sends the request through the middleware, passing each response to on_message as it streams in, and adds the
+  // responses to the transcript. on_notice gets the warnings of the middleware, such as secrets it redacted, before
+  // any response
+  pub async fn send_request<N, F>(
+    &mut self,
+    mut request: CreateChatCompletionRequest,
+    mut on_notice: N,
+    mut on_message: F,
+  ) -> Result<(), SazidError>
+  where
+    N: FnMut(&str),
+    F: FnMut(&ChatMessage) -> Result<(), SazidError>,
+  {
+    let middleware = MiddlewareChain::from_config(&self.config)?;
+    let pre_request = middleware.pre_request(&mut request).await;
+    middleware.take_notices().iter().for_each(|notice| on_notice(notice));
+    let mut responses = match pre_request? {
+      Some(responses) => {
+        responses.iter().try_for_each(&mut on_message)?;
+        responses
+      },
+      None => {
+        let retry_policy = &self.config.retry_policy;
+        let client = create_openai_client(&self.config.openai_config).with_backoff(RetryPolicy::disabled().backoff());
+        let on_retry =
+          |attempt, delay, error: &OpenAIError| eprintln!("{}", retry_status(attempt, retry_policy, delay, error));
+        let mut stream = match create_stream_with_retry(&client, &request, retry_policy, on_retry).await {
+          Ok(stream) => stream,
+          Err(e) => {
+            middleware.on_error(&request, &e).await;
+            return Err(e.into());
+          },
+        };
+        let mut responses = vec![];
+        while let Some(response_result) = stream.next().await {
+          let response = match response_result {
+            Ok(response) => response,
+            Err(e) => {
+              middleware.on_error(&request, &e).await;
+              return Err(e.into());
+            },
+          };
+          let mut message = ChatMessage::StreamResponse(vec![response]);
+          middleware.post_response(&request, &mut message).await?;
+          on_message(&message)?;
+          responses.push(message);
+        }
+        responses
+      },
+    };
+    middleware.on_complete(&request, &responses).await?;
+    responses.drain(..).for_each(|message| self.data.add_message(message));
+    Ok(())
+  }
+}
+
+// the prompt comes from the command line, or from stdin when no prompt argument or - is given
+// stdin is only read along with a prompt argument when a trailing - asks for it, and is then an attachment that is
+// sent ahead of the prompt, so that a prompt run from a script doesn't wait on a stdin no one writes to
+pub fn read_batch_input(args: &Cli) -> Result<(String, Option<String>), SazidError> {
+  let read_stdin = || -> Result<String, SazidError> {
+    let mut stdin = String::new();
+    io::stdin().read_to_string(&mut stdin)?;
+    Ok(stdin)
+  };
+  batch_input(args.prompt.as_deref(), args.attach_stdin.is_some(), io::stdin().is_terminal(), read_stdin)
+}
+
+fn batch_input(
+  prompt: Option<&str>,
+  attach_stdin: bool,
+  stdin_is_terminal: bool,
+  read_stdin: impl FnOnce() -> Result<String, SazidError>,
+) -> Result<(String, Option<String>), SazidError> {
+  match (prompt, attach_stdin) {
+    (None | Some("-"), _) if stdin_is_terminal => {
+      Err(SazidError::Other("batch mode requires a prompt argument or piped stdin".to_string()))
+    },
+    (None | Some("-"), _) => Ok((read_stdin()?, None)),
+    (Some(prompt), true) => Ok((prompt.to_string(), Some(read_stdin()?).filter(|a| !a.trim().is_empty()))),
+    (Some(prompt), false) => Ok((prompt.to_string(), None)),
+  }
+}
+
+pub fn attachment_messages(attachment: &str, model: &Model) -> Result<Vec<ChatMessage>, SazidError> {
+  let chunks = parse_input(attachment, CHUNK_TOKEN_LIMIT as usize, model.token_limit as usize)
+    .map_err(SazidError::ChunkifierError)?;
+  let chunk_count = chunks.len();
+  Ok(
+    chunks
+      .into_iter()
+      .enumerate()
+      .map(|(i, chunk)| {
+        let header = match chunk_count {
+          1 => "attached stdin:".to_string(),
+          _ => format!("attached stdin, part {} of {}:", i + 1, chunk_count),
+        };
+        ChatMessage::User(ChatCompletionRequestUserMessage {
+          role: Role::User,
+          content: Some(ChatCompletionRequestUserMessageContent::Text(format!("{}\n{}", header, chunk))),
+        })
+      })
+      .collect(),
+  )
+}
+
+// the text a response adds to the first choice
+pub fn response_text(message: &ChatMessage) -> String {
+  let contents: Vec<&String> = match message {
+    ChatMessage::StreamResponse(srvec) => srvec
+      .iter()
+      .flat_map(|sr| sr.choices.iter().filter(|c| c.index == 0))
+      .filter_map(|c| c.delta.content.as_ref())
+      .collect(),
+    ChatMessage::Response(response) => {
+      response.choices.iter().filter(|c| c.index == 0).filter_map(|c| c.message.content.as_ref()).collect()
+    },
+    _ => vec![],
+  };
+  contents.into_iter().map(String::as_str).collect()
+}
+
+// only the first choice is written to stdout
+fn print_batch_response(message: &ChatMessage) -> Result<(), SazidError> {
+  let mut stdout = io::stdout().lock();
+  stdout.write_all(response_text(message).as_bytes())?;
+  stdout.flush()?;
+  Ok(())
+}
+
+// there is no status bar or command line in batch mode, so statuses go to stderr and commands are skipped
+fn apply_batch_hook_effects(session: &mut BatchSession, hooks: &Hooks) {
+  for effect in hooks.take_effects() {
+    match effect {
+      HookEffect::Tag(tag) => session.data.add_tag(&tag),
+      HookEffect::Status(status) => eprintln!("{}", status),
+      HookEffect::Command(command) => log::warn!("hook command {} is skipped in batch mode", command),
+    }
+  }
+}
+
+pub async fn run_batch(args: Cli, config: Config) -> Result<(), SazidError> {
+  let mut session = match &args.session {
+    Some(session_id) => BatchSession::load(session_id, config.session_config.clone())?,
+    None => BatchSession::new(config.session_config.clone()),
+  };
+  if let Some(model) = &args.model {
+    session.config.model = Model::from_name(model);
+  }
+
+  let (prompt, attachment) = read_batch_input(&args)?;
+  if prompt.trim().is_empty() {
+    return Err(SazidError::Other("batch mode received an empty prompt".to_string()));
+  }
+  let hooks = session.config.hooks_dir.as_deref().map(Hooks::load).transpose()?.flatten();
+  let prompt = match &hooks {
+    Some(hooks) => hooks.on_message_submit(&prompt)?,
+    None => prompt,
+  };
+
+  session.add_initial_messages();
+  if let Some(attachment) = attachment {
+    attachment_messages(&attachment, &session.config.model)?
+      .into_iter()
+      .for_each(|message| session.data.add_message(message));
+  }
+  if let Some(path) = &args.with {
+    let citations = retrieve_file_citations(&config, path, &prompt, DEFAULT_RETRIEVED_CHUNKS).await?;
+    let citations = session.data.add_citations(citations);
+    session.data.add_message(ChatMessage::System(ChatCompletionRequestSystemMessage {
+      content: Some(context_message(&citations)),
+      ..Default::default()
+    }));
+  }
+  session.data.add_message(ChatMessage::User(ChatCompletionRequestUserMessage {
+    role: Role::User,
+    content: Some(ChatCompletionRequestUserMessageContent::Text(prompt)),
+  }));
+
+  let (request, notices) = session.prepare_request()?;
+  notices.iter().for_each(|notice| eprintln!("{}", notice));
+  // the session isn't saved, so the prompt can be run again once the request looks right
+  if args.dry_run {
+    println!("{}", describe_request(&request));
+    return Ok(());
+  }
+  session.send_request(request, |notice| eprintln!("{}", notice), print_batch_response).await?;
+  writeln!(io::stdout())?;
+  if let Some(hooks) = &hooks {
+    let response = session.data.messages.last().filter(|m| m.role() == "assistant");
+    if let Some(response) = response.and_then(|m| message_text(&m.message)).cloned() {
+      hooks.on_response(&response)?;
+    }
+    apply_batch_hook_effects(&mut session, hooks);
+  }
+  if let Some(response) = session.data.messages.last().filter(|m| !m.cited_sources.is_empty()) {
+    writeln!(io::stdout(), "\nSources:\n{}", response.cited_sources.join("\n"))?;
+  }
+
+  if args.session.is_some() {
+    session.save()?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_batch_input() {
+    let piped = || Ok("error: disk full\n".to_string());
+    let unread = || -> Result<String, SazidError> { panic!("stdin was read") };
+    assert_eq!(
+      batch_input(Some("explain this log"), false, false, unread).unwrap(),
+      ("explain this log".to_string(), None)
+    );
+    assert_eq!(
+      batch_input(Some("explain this log"), true, false, piped).unwrap(),
+      ("explain this log".to_string(), Some("error: disk full\n".to_string()))
+    );
+    assert_eq!(batch_input(None, false, false, piped).unwrap(), ("error: disk full\n".to_string(), None));
+    assert_eq!(batch_input(Some("-"), false, false, piped).unwrap(), ("error: disk full\n".to_string(), None));
+    assert!(batch_input(None, false, true, unread).is_err());
+  }
+}
@@ -0,0 +1,52 @@
+use std::path::{Path, PathBuf};
+
+use crate::{components::session::create_openai_client, config::Config};
+
+use super::{
+  consts::{data_path, BRIEFS_DIR},
+  errors::SazidError,
+  summarize::{estimate_map_reduce, map_reduce, source_documents},
+};
+
+const BRIEF_MAP_PROMPT: &str = "You are reading part of a software project in order to write a brief for a new \
+contributor. Write terse notes on what this part shows about: the purpose of the project, its architecture, the key \
+modules and their responsibilities, how to build and test it, and anything unclear or unfinished. Skip anything this \
+part does not show.";
+
+const BRIEF_REDUCE_PROMPT: &str = "Merge these notes about a software project into a single project brief in \
+markdown with exactly these sections: # Overview, # Architecture, # Key Modules, # Build and Test, # Open Questions. \
+Keep it terse, remove duplicates, and keep file and module names exact.";
+
+pub fn brief_path(target: &str) -> Result<PathBuf, SazidError> {
+  let name = Path::new(target)
+    .canonicalize()
+    .ok()
+    .and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+    .unwrap_or(target.to_string());
+  let stem: String = name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '-' }).collect();
+  let briefs_dir = data_path(BRIEFS_DIR).ok_or(SazidError::Other("home directory not found".to_string()))?;
+  Ok(briefs_dir.join(format!("{}.md", stem)))
+}
+
+// writes a brief for a directory, or for an ingested collection when target is not a path
+// returns the path the brief was saved to
+pub async fn run_brief(target: &str, output: Option<&PathBuf>, config: &Config) -> Result<PathBuf, SazidError> {
+  let documents = source_documents(config, target).await?;
+  let model = &config.session_config.model;
+  let estimate = estimate_map_reduce(model, &documents, BRIEF_MAP_PROMPT, BRIEF_REDUCE_PROMPT, &config.model_pricing);
+  eprintln!("summarizing {} documents from {}: {}", documents.len(), target, estimate);
+  let client = create_openai_client(&config.session_config.openai_config);
+  let brief = map_reduce(&client, model, &documents, BRIEF_MAP_PROMPT, BRIEF_REDUCE_PROMPT, |progress| {
+    eprintln!("{}", progress);
+  })
+  .await?;
+  let path = match output {
+    Some(output) => output.clone(),
+    None => brief_path(target)?,
+  };
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  std::fs::write(&path, brief)?;
+  Ok(path)
+}
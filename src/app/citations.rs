@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+use super::{
+  embeddings::{embeddings_models::EmbeddingModel, provenance::ChunkProvenance, types::EmbeddingPage, EmbeddingsManager},
+  errors::SazidError,
+};
+
+// a retrieved chunk added to a request, referred to by its number in the answer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Citation {
+  pub number: usize,
+  // the file or url the chunk was ingested from
+  pub origin: String,
+  pub content: String,
+}
+
+impl Citation {
+  pub fn from_page(number: usize, page: &EmbeddingPage) -> Self {
+    let origin = match ChunkProvenance::from_json(&page.provenance) {
+      Some(ChunkProvenance { source_path, section: Some(section), page_number, .. }) => {
+        format!("{} > {} (chunk {})", source_path, section, page_number + 1)
+      },
+      Some(provenance) => format!("{} (chunk {})", provenance.source_path, provenance.page_number + 1),
+      None => format!("chunk {}", page.id),
+    };
+    Citation { number, origin, content: page.content.clone() }
+  }
+
+  pub fn label(&self) -> String {
+    format!("[{}] {}", self.number, self.origin)
+  }
+}
+
+// which ingested chunks are added to each request, set with the rag command
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RetrievalSettings {
+  // only chunks of this collection, or of every source
+  pub collection: Option<String>,
+  pub chunks: usize,
+}
+
+pub const DEFAULT_RETRIEVED_CHUNKS: usize = 5;
+
+// the system message that adds the retrieved chunks to a request
+pub fn context_message(citations: &[Citation]) -> String {
+  let sources = citations
+    .iter()
+    .map(|citation| format!("{}\n{}", citation.label(), citation.content))
+    .collect::<Vec<String>>()
+    .join("\n\n");
+  format!(
+    "Answer with the numbered sources below where they are relevant. Cite each source you use with its number in \
+     square brackets, such as [{}], and don't cite sources you didn't use.\n\n{}",
+    citations.first().map(|citation| citation.number).unwrap_or(1),
+    sources
+  )
+}
+
+// the numbers cited in text, as [2] or [1, 3], in the order they are first cited
+pub fn cited_numbers(text: &str) -> Vec<usize> {
+  let mut numbers: Vec<usize> = vec![];
+  for (start, _) in text.match_indices('[') {
+    let Some(end) = text[start..].find(']') else {
+      continue;
+    };
+    let cited = text[start + 1..start + end].split(',').map(|n| n.trim().parse::<usize>()).collect::<Vec<_>>();
+    if cited.iter().all(|n| n.is_ok()) {
+      for number in cited.into_iter().flatten() {
+        if !numbers.contains(&number) {
+          numbers.push(number);
+        }
+      }
+    }
+  }
+  numbers
+}
+
+// the chunks that best match query, numbered from 1
+pub async fn retrieve_citations(
+  config: &Config,
+  query: &str,
+  settings: &RetrievalSettings,
+) -> Result<Vec<Citation>, SazidError> {
+  let model = EmbeddingModel::Ada002(config.session_config.openai_config.clone());
+  let mut manager = EmbeddingsManager::init(config.clone(), model).await?;
+  manager.set_collection(settings.collection.clone());
+  let pages = manager.hybrid_search(query, settings.chunks as i64, false).await?;
+  Ok(numbered(&pages))
+}
+
+// the chunks of a single file that best match query, the file is indexed in memory and nothing is stored
+pub async fn retrieve_file_citations(
+  config: &Config,
+  path: &Path,
+  query: &str,
+  chunks: usize,
+) -> Result<Vec<Citation>, SazidError> {
+  let model = EmbeddingModel::Ada002(config.session_config.openai_config.clone());
+  let mut manager = EmbeddingsManager::ephemeral(config.clone(), model);
+  manager.add_textfile_embedding(&path.to_string_lossy()).await?;
+  let pages = manager.hybrid_search(query, chunks as i64, false).await?;
+  Ok(numbered(&pages))
+}
+
+fn numbered(pages: &[EmbeddingPage]) -> Vec<Citation> {
+  pages.iter().enumerate().map(|(i, page)| Citation::from_page(i + 1, page)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cited_numbers() {
+    assert_eq!(cited_numbers("uses a pool [2], configured in [1, 2] and [3]"), vec![2, 1, 3]);
+    assert_eq!(cited_numbers("vec[i] and [link](url) cite nothing"), Vec::<usize>::new());
+  }
+
+  #[test]
+  fn test_context_message_numbers_sources() {
+    let citations = vec![
+      Citation { number: 4, origin: "src/main.rs (chunk 1)".to_string(), content: "fn main() {}".to_string() },
+      Citation { number: 5, origin: "README.md (chunk 2)".to_string(), content: "usage".to_string() },
+    ];
+    let message = context_message(&citations);
+    assert!(message.contains("such as [4]"));
+    assert!(message.contains("[4] src/main.rs (chunk 1)\nfn main() {}\n\n[5] README.md (chunk 2)\nusage"));
+  }
+}
@@ -0,0 +1,225 @@
+use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent};
+
+use super::functions::argument_validation::count_tokens;
+
+// the most recent messages are never compressed, since they usually carry the question being asked
+pub const UNCOMPRESSED_TAIL_MESSAGES: usize = 2;
+
+// words that rarely change the meaning of a sentence, dropped from older messages outside of code blocks
+const FILLER_WORDS: &[&str] = &[
+  "a",
+  "actually",
+  "an",
+  "basically",
+  "just",
+  "kindly",
+  "please",
+  "quite",
+  "really",
+  "so",
+  "the",
+  "very",
+];
+
+// lines kept at each end of a tool or function result when it is elided
+const ELIDED_RESULT_LINES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionReport {
+  pub original_tokens: usize,
+  pub compressed_tokens: usize,
+}
+
+impl CompressionReport {
+  pub fn tokens_saved(&self) -> usize {
+    self.original_tokens.saturating_sub(self.compressed_tokens)
+  }
+}
+
+impl std::fmt::Display for CompressionReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(
+      f,
+      "prompt compressed from {} to {} tokens, saved {} tokens",
+      self.original_tokens,
+      self.compressed_tokens,
+      self.tokens_saved()
+    )
+  }
+}
+
+pub fn count_message_tokens(messages: &[ChatCompletionRequestMessage]) -> usize {
+  messages.iter().map(|m| message_text(m).map(|t| count_tokens(t)).unwrap_or(0)).sum()
+}
+
+// applies increasingly lossy passes to the older messages until the request fits under the threshold
+// returns None when the messages are already under the threshold, pinned messages are indices that are kept as they are
+pub fn compress_messages(
+  messages: &mut [ChatCompletionRequestMessage],
+  threshold: usize,
+  pinned: &[usize],
+) -> Option<CompressionReport> {
+  let original_tokens = count_message_tokens(messages);
+  if original_tokens <= threshold {
+    return None;
+  }
+  let passes: [fn(&mut ChatCompletionRequestMessage); 3] = [collapse_whitespace, drop_filler_words, elide_results];
+  let compressible = messages.len().saturating_sub(UNCOMPRESSED_TAIL_MESSAGES);
+  for pass in passes.iter() {
+    messages[..compressible]
+      .iter_mut()
+      .enumerate()
+      .filter(|(i, m)| !matches!(m, ChatCompletionRequestMessage::System(_)) && !pinned.contains(i))
+      .for_each(|(_, m)| pass(m));
+    if count_message_tokens(messages) <= threshold {
+      break;
+    }
+  }
+  Some(CompressionReport { original_tokens, compressed_tokens: count_message_tokens(messages) })
+}
+
+pub fn message_text(message: &ChatCompletionRequestMessage) -> Option<&String> {
+  match message {
+    ChatCompletionRequestMessage::System(m) => m.content.as_ref(),
+    ChatCompletionRequestMessage::User(m) => match &m.content {
+      Some(ChatCompletionRequestUserMessageContent::Text(text)) => Some(text),
+      _ => None,
+    },
+    ChatCompletionRequestMessage::Assistant(m) => m.content.as_ref(),
+    ChatCompletionRequestMessage::Tool(m) => m.content.as_ref(),
+    ChatCompletionRequestMessage::Function(m) => m.content.as_ref(),
+  }
+}
+
+pub fn message_text_mut(message: &mut ChatCompletionRequestMessage) -> Option<&mut String> {
+  match message {
+    ChatCompletionRequestMessage::System(m) => m.content.as_mut(),
+    ChatCompletionRequestMessage::User(m) => match &mut m.content {
+      Some(ChatCompletionRequestUserMessageContent::Text(text)) => Some(text),
+      _ => None,
+    },
+    ChatCompletionRequestMessage::Assistant(m) => m.content.as_mut(),
+    ChatCompletionRequestMessage::Tool(m) => m.content.as_mut(),
+    ChatCompletionRequestMessage::Function(m) => m.content.as_mut(),
+  }
+}
+
+// applies f to each line of text that is not inside a fenced code block
+fn map_prose_lines(text: &str, f: impl Fn(&str) -> Option<String>) -> String {
+  let mut in_code_block = false;
+  text
+    .lines()
+    .filter_map(|line| {
+      if line.trim_start().starts_with("```") {
+        in_code_block = !in_code_block;
+        return Some(line.to_string());
+      }
+      match in_code_block {
+        true => Some(line.to_string()),
+        false => f(line),
+      }
+    })
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+fn collapse_whitespace(message: &mut ChatCompletionRequestMessage) {
+  if let Some(text) = message_text_mut(message) {
+    *text = map_prose_lines(text, |line| match line.trim().is_empty() {
+      true => None,
+      false => Some(line.split_whitespace().collect::<Vec<&str>>().join(" ")),
+    });
+  }
+}
+
+fn drop_filler_words(message: &mut ChatCompletionRequestMessage) {
+  if matches!(message, ChatCompletionRequestMessage::Tool(_) | ChatCompletionRequestMessage::Function(_)) {
+    return;
+  }
+  if let Some(text) = message_text_mut(message) {
+    *text = map_prose_lines(text, |line| {
+      Some(
+        line
+          .split_whitespace()
+          .filter(|word| !FILLER_WORDS.contains(&word.to_lowercase().as_str()))
+          .collect::<Vec<&str>>()
+          .join(" "),
+      )
+    });
+  }
+}
+
+fn elide_results(message: &mut ChatCompletionRequestMessage) {
+  if !matches!(message, ChatCompletionRequestMessage::Tool(_) | ChatCompletionRequestMessage::Function(_)) {
+    return;
+  }
+  if let Some(text) = message_text_mut(message) {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() > ELIDED_RESULT_LINES * 2 {
+      let elided = lines[ELIDED_RESULT_LINES..lines.len() - ELIDED_RESULT_LINES].join("\n");
+      *text = format!(
+        "{}\n[... {} tokens elided ...]\n{}",
+        lines[..ELIDED_RESULT_LINES].join("\n"),
+        count_tokens(&elided),
+        lines[lines.len() - ELIDED_RESULT_LINES..].join("\n")
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage, Role};
+
+  use super::*;
+
+  fn user_message(text: &str) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text(text.to_string())),
+    })
+  }
+
+  #[test]
+  fn test_compress_messages_under_threshold_is_untouched() {
+    let mut messages = vec![user_message("please   explain the   error")];
+    assert_eq!(compress_messages(&mut messages, 1000, &[]), None);
+    assert_eq!(message_text(&messages[0]).unwrap(), "please   explain the   error");
+  }
+
+  #[test]
+  fn test_compress_messages_preserves_recent_messages_and_code() {
+    let mut messages = vec![
+      user_message("please   explain the error\n\n```\nlet   the = 1;\n```"),
+      user_message("just   a question"),
+      user_message("the   last question"),
+    ];
+    let report = compress_messages(&mut messages, 0, &[]).unwrap();
+    assert!(report.tokens_saved() > 0);
+    assert_eq!(message_text(&messages[0]).unwrap(), "explain error\n```\nlet   the = 1;\n```");
+    assert_eq!(message_text(&messages[2]).unwrap(), "the   last question");
+  }
+
+  #[test]
+  fn test_compress_messages_leaves_pinned_messages() {
+    let mut messages =
+      vec![user_message("please   keep the   whole   spec"), user_message("just   a question"), user_message("ok")];
+    compress_messages(&mut messages, 0, &[0]).unwrap();
+    assert_eq!(message_text(&messages[0]).unwrap(), "please   keep the   whole   spec");
+  }
+
+  #[test]
+  fn test_elide_results_keeps_head_and_tail() {
+    let output = (0..100).map(|i| i.to_string()).collect::<Vec<String>>().join("\n");
+    let mut message = ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
+      role: Role::Tool,
+      content: Some(output),
+      tool_call_id: "call_0".to_string(),
+    });
+    elide_results(&mut message);
+    let text = message_text(&message).unwrap();
+    assert!(text.starts_with("0\n1\n"));
+    assert!(text.contains("tokens elided"));
+    assert!(text.ends_with("98\n99"));
+  }
+}
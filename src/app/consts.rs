@@ -1,12 +1,47 @@
 use crate::app::types::Model;
+use dirs_next::home_dir;
 use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
 use std::path::PathBuf;
 
 pub const MAX_FUNCTION_CALL_DEPTH: u32 = 0;
 pub const CHUNK_TOKEN_LIMIT: u32 = 4096u32;
+// sent to resume a response that was cut off
+pub const CONTINUE_PROMPT: &str =
+  "Your last response was cut off. Continue exactly where it stopped, without repeating anything or adding a preamble.";
+// the most choices a response can be asked for, as many as are compared side by side
+pub const MAX_CHOICES: u8 = 4;
 
+// the data paths below are relative to the home directory, and start with DATA_DIR
+pub const DATA_DIR: &str = ".local/share/sazid/data";
 pub const SESSIONS_DIR: &str = ".local/share/sazid/data/sessions";
 pub const INGESTED_DIR: &str = ".local/share/sazid/data/ingested";
+pub const CACHE_DIR: &str = ".local/share/sazid/data/cache";
+pub const BRIEFS_DIR: &str = ".local/share/sazid/data/briefs";
+pub const INGEST_MANIFEST: &str = ".local/share/sazid/data/ingest_manifest.json";
+pub const EMBEDDED_VECTOR_STORE: &str = ".local/share/sazid/data/vector_store.bin";
+pub const PROMPT_HISTORY: &str = ".local/share/sazid/data/prompt_history.jsonl";
+pub const RECOVERY_DIR: &str = ".local/share/sazid/data/recovery";
+pub const TEE_DIR: &str = ".local/share/sazid/data/tee";
+pub const ENCRYPTION_SALT: &str = ".local/share/sazid/data/encryption_salt";
+pub const PROVENANCE_KEY: &str = ".local/share/sazid/data/provenance_key";
+// how often the input draft and the transcript are saved for crash recovery
+pub const AUTOSAVE_INTERVAL_SECS: u64 = 5;
+
+// set from the data_dir setting, replacing DATA_DIR under the home directory
+static DATA_DIR_OVERRIDE: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn set_data_dir(dir: PathBuf) {
+  DATA_DIR_OVERRIDE.set(dir).ok();
+}
+
+// where one of the data paths above is, under the configured data directory if there is one
+pub fn data_path(path: &str) -> Option<PathBuf> {
+  match (DATA_DIR_OVERRIDE.get(), path.strip_prefix(DATA_DIR)) {
+    (Some(dir), Some(relative)) => Some(dir.join(relative.trim_start_matches('/'))),
+    _ => home_dir().map(|home_dir| home_dir.join(path)),
+  }
+}
 
 lazy_static! {
     // model constants
@@ -35,6 +70,13 @@ lazy_static! {
         endpoint: "https://api.openai.com/v1/completions".to_string(),
         token_limit: 8192,
     };
+    pub static ref AVAILABLE_MODELS: Vec<Model> = vec![
+        GPT4_TURBO.clone(),
+        GPT4.clone(),
+        GPT3_TURBO_16K.clone(),
+        GPT3_TURBO.clone(),
+        WIZARDLM.clone(),
+    ];
     // logging constants
     pub static ref PROJECT_NAME: String = env!("CARGO_CRATE_NAME").to_uppercase().to_string();
     pub static ref DATA_FOLDER: Option<PathBuf> =
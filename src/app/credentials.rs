@@ -0,0 +1,101 @@
+use super::{errors::SazidError, providers::Provider};
+
+// api keys and tokens are stored in the system keyring under this service, with their environment variable as the user
+pub const KEYRING_SERVICE: &str = "sazid";
+
+// the providers with an api key, in the order `sazid auth status` lists them
+pub const KEYED_PROVIDERS: [Provider; 2] = [Provider::OpenAI, Provider::OpenRouter];
+
+// the token gists are created with, it needs the gist scope
+pub const GITHUB_TOKEN_ENV: &str = "GITHUB_TOKEN";
+
+// the bearer token requests to `sazid serve` have to send, when one is set
+pub const SERVE_TOKEN_ENV: &str = "SAZID_SERVE_TOKEN";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeySource {
+  Keyring,
+  Environment(&'static str),
+}
+
+fn keyring_entry(env: &str) -> Result<keyring::Entry, SazidError> {
+  keyring::Entry::new(KEYRING_SERVICE, env).map_err(|e| SazidError::Other(format!("keyring unavailable: {}", e)))
+}
+
+fn api_key_env(provider: Provider) -> Result<&'static str, SazidError> {
+  provider.api_key_env().ok_or_else(|| SazidError::Other(format!("the {:?} provider doesn't use an api key", provider)))
+}
+
+// the api key for a provider and where it came from, the keyring is read first and the environment variable is the
+// fallback, for machines without a keyring
+pub fn find_api_key(provider: Provider) -> Option<(String, KeySource)> {
+  find_secret(provider.api_key_env()?)
+}
+
+pub fn find_secret(env: &'static str) -> Option<(String, KeySource)> {
+  match keyring_entry(env).and_then(|entry| entry.get_password().map_err(|e| SazidError::Other(e.to_string()))) {
+    Ok(api_key) if !api_key.trim().is_empty() => Some((api_key, KeySource::Keyring)),
+    _ => match std::env::var(env) {
+      Ok(api_key) if !api_key.trim().is_empty() => Some((api_key, KeySource::Environment(env))),
+      _ => None,
+    },
+  }
+}
+
+pub fn load_api_key(provider: Provider) -> Option<String> {
+  find_api_key(provider).map(|(api_key, _)| api_key)
+}
+
+pub fn store_api_key(provider: Provider, api_key: &str) -> Result<(), SazidError> {
+  store_secret(api_key_env(provider)?, api_key)
+}
+
+pub fn store_secret(env: &str, secret: &str) -> Result<(), SazidError> {
+  if secret.trim().is_empty() {
+    return Err(SazidError::Other("the key is empty".to_string()));
+  }
+  keyring_entry(env)?
+    .set_password(secret.trim())
+    .map_err(|e| SazidError::Other(format!("failed to store the key in the keyring: {}", e)))
+}
+
+// false when the keyring had no key for the provider
+pub fn remove_api_key(provider: Provider) -> Result<bool, SazidError> {
+  remove_secret(api_key_env(provider)?)
+}
+
+pub fn remove_secret(env: &str) -> Result<bool, SazidError> {
+  match keyring_entry(env)?.delete_password() {
+    Ok(()) => Ok(true),
+    Err(keyring::Error::NoEntry) => Ok(false),
+    Err(e) => Err(SazidError::Other(format!("failed to remove the key from the keyring: {}", e))),
+  }
+}
+
+pub fn missing_api_key_message(provider: Provider) -> String {
+  format!(
+    "no api key is stored in the keyring and {} is not set, run `sazid auth set` or `sazid setup` to store one",
+    provider.api_key_env().unwrap_or("the api key variable")
+  )
+}
+
+// enough of a key to tell which one it is, without showing it
+pub fn mask_api_key(api_key: &str) -> String {
+  let chars = api_key.trim().chars().collect::<Vec<_>>();
+  match chars.len() {
+    0..=12 => "*".repeat(chars.len()),
+    len => format!("{}...{}", chars[..3].iter().collect::<String>(), chars[len - 4..].iter().collect::<String>()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_mask_api_key() {
+    assert_eq!(mask_api_key("sk-abcdefghijklmnopqrstuvwxyz1234"), "sk-...1234");
+    assert_eq!(mask_api_key("short"), "*****");
+    assert_eq!(mask_api_key(""), "");
+  }
+}
@@ -0,0 +1,452 @@
+use std::fmt;
+
+use postgres::{
+  fallible_iterator::FallibleIterator,
+  types::{FromSql, Kind, ToSql, Type},
+  NoTls,
+};
+use rusqlite::{types::ValueRef, OpenFlags};
+use serde_derive::{Deserialize, Serialize};
+
+use super::errors::SazidError;
+
+// rows a query returns unless the database sets max_rows, the rest are cut off
+pub const DEFAULT_MAX_ROWS: usize = 100;
+
+// a postgres query is cancelled after this long
+const STATEMENT_TIMEOUT_SECS: u64 = 30;
+
+// statements a query may start with, anything else is refused before it reaches the database
+const READ_ONLY_STATEMENTS: [&str; 5] = ["select", "with", "values", "table", "explain"];
+
+// a database the query_database function and --ingest-database can read, by name in the databases setting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatabaseConfig {
+  // postgres:// or postgresql:// urls, or sqlite:// followed by the path of the database file
+  pub url: String,
+  #[serde(default = "default_max_rows")]
+  pub max_rows: usize,
+}
+
+fn default_max_rows() -> usize {
+  DEFAULT_MAX_ROWS
+}
+
+impl DatabaseConfig {
+  // the host a postgres database is reached at, None for sqlite files and postgres over a local socket
+  pub fn host(&self) -> Option<String> {
+    let url = url::Url::parse(&self.url).ok()?;
+    match url.scheme() {
+      "postgres" | "postgresql" => url.host_str().filter(|host| !host.is_empty()).map(|host| host.to_string()),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+  pub name: String,
+  pub data_type: String,
+  pub nullable: bool,
+  pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSchema {
+  // qualified by its schema in postgres, such as public.users
+  pub name: String,
+  pub comment: Option<String>,
+  pub columns: Vec<ColumnSchema>,
+}
+
+impl fmt::Display for TableSchema {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "table {}", self.name)?;
+    if let Some(comment) = &self.comment {
+      write!(f, " -- {}", comment)?;
+    }
+    for column in &self.columns {
+      write!(f, "\n  {} {}", column.name, column.data_type)?;
+      if !column.nullable {
+        write!(f, " not null")?;
+      }
+      if let Some(comment) = &column.comment {
+        write!(f, " -- {}", comment)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryResult {
+  pub columns: Vec<String>,
+  pub rows: Vec<Vec<String>>,
+  // more rows matched than were returned
+  pub truncated: bool,
+}
+
+impl fmt::Display for QueryResult {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.rows.is_empty() {
+      return write!(f, "no rows");
+    }
+    write!(f, "{}", self.columns.join(" | "))?;
+    for row in &self.rows {
+      write!(f, "\n{}", row.join(" | "))?;
+    }
+    if self.truncated {
+      write!(f, "\n(only the first {} rows are shown, add a LIMIT or narrow the query)", self.rows.len())?;
+    }
+    Ok(())
+  }
+}
+
+// refuses anything but a single statement that reads, the connection is read only as well
+pub fn ensure_read_only(sql: &str) -> Result<(), SazidError> {
+  let sql = sql.trim().trim_end_matches(';').trim();
+  let first = sql.split_whitespace().next().unwrap_or_default().to_lowercase();
+  if !READ_ONLY_STATEMENTS.contains(&first.as_str()) {
+    return Err(SazidError::Other(format!("only read only queries can run, not {}", first)));
+  }
+  if has_second_statement(sql) {
+    return Err(SazidError::Other("only a single statement can run at once".to_string()));
+  }
+  Ok(())
+}
+
+fn is_identifier_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// the $tag$ that opens a dollar quoted string at i, a $ after a name or before a digit, as in $1, doesn't open one
+fn dollar_quote_tag(chars: &[char], i: usize) -> Option<String> {
+  if i > 0 && is_identifier_char(chars[i - 1]) {
+    return None;
+  }
+  let end = (i + 1..chars.len()).find(|&j| !is_identifier_char(chars[j]))?;
+  (chars[end] == '$' && !chars.get(i + 1).map_or(false, |c| c.is_ascii_digit()))
+    .then(|| chars[i..=end].iter().collect())
+}
+
+// anything but whitespace and comments after a ; that isn't in a string, a quoted name or a comment
+fn has_second_statement(sql: &str) -> bool {
+  let chars = sql.chars().collect::<Vec<char>>();
+  let starts_with = |i: usize, s: &str| s.chars().enumerate().all(|(k, c)| chars.get(i + k) == Some(&c));
+  let mut ended = false;
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    if starts_with(i, "--") {
+      i = (i..chars.len()).find(|&j| chars[j] == '\n').unwrap_or(chars.len());
+      continue;
+    }
+    // block comments nest in postgres
+    if starts_with(i, "/*") {
+      let mut depth = 0;
+      while i < chars.len() {
+        if starts_with(i, "/*") {
+          (depth, i) = (depth + 1, i + 2);
+        } else if starts_with(i, "*/") {
+          (depth, i) = (depth - 1, i + 2);
+          if depth == 0 {
+            break;
+          }
+        } else {
+          i += 1;
+        }
+      }
+      continue;
+    }
+    if c.is_whitespace() {
+      i += 1;
+      continue;
+    }
+    if ended {
+      return true;
+    }
+    match c {
+      ';' => ended = true,
+      '\'' | '"' => {
+        // a backslash escapes the next character in an E'...' string
+        let escapes =
+          c == '\'' && i > 0 && matches!(chars[i - 1], 'e' | 'E') && (i < 2 || !is_identifier_char(chars[i - 2]));
+        i += 1;
+        while i < chars.len() && chars[i] != c {
+          i += if escapes && chars[i] == '\\' { 2 } else { 1 };
+        }
+      },
+      '$' => {
+        if let Some(tag) = dollar_quote_tag(&chars, i) {
+          let body = i + tag.chars().count();
+          i = (body..chars.len()).find(|&j| starts_with(j, &tag)).map_or(chars.len(), |j| j + tag.chars().count() - 1);
+        }
+      },
+      _ => {},
+    }
+    i += 1;
+  }
+  false
+}
+
+// the text of a postgres value, whatever its type, for query results that are read rather than computed with
+struct TextValue(String);
+
+impl<'a> FromSql<'a> for TextValue {
+  fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+    let text = match *ty {
+      Type::BOOL => bool::from_sql(ty, raw)?.to_string(),
+      Type::INT2 => i16::from_sql(ty, raw)?.to_string(),
+      Type::INT4 => i32::from_sql(ty, raw)?.to_string(),
+      Type::INT8 => i64::from_sql(ty, raw)?.to_string(),
+      Type::OID => u32::from_sql(ty, raw)?.to_string(),
+      Type::FLOAT4 => f32::from_sql(ty, raw)?.to_string(),
+      Type::FLOAT8 => f64::from_sql(ty, raw)?.to_string(),
+      Type::NUMERIC => numeric_text(raw)?,
+      Type::DATE => chrono::NaiveDate::from_sql(ty, raw)?.to_string(),
+      Type::TIMESTAMP => chrono::NaiveDateTime::from_sql(ty, raw)?.to_string(),
+      Type::TIMESTAMPTZ => chrono::DateTime::<chrono::Utc>::from_sql(ty, raw)?.to_string(),
+      Type::JSON | Type::JSONB => serde_json::Value::from_sql(ty, raw)?.to_string(),
+      Type::UUID if raw.len() == 16 => {
+        let hex = raw.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+      },
+      // text types and enums are sent as their text
+      _ if <&str as FromSql>::accepts(ty) || matches!(ty.kind(), Kind::Enum(_)) => {
+        String::from_utf8_lossy(raw).to_string()
+      },
+      _ => format!("<{}>", ty.name()),
+    };
+    Ok(TextValue(text))
+  }
+
+  fn from_sql_null(_: &Type) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+    Ok(TextValue("NULL".to_string()))
+  }
+
+  fn accepts(_: &Type) -> bool {
+    true
+  }
+}
+
+// a numeric in the binary format, a count of base 10000 digits, the weight of the first, the sign and the scale
+fn numeric_text(raw: &[u8]) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+  let word = |i: usize| raw.get(2 * i..2 * i + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or("short numeric");
+  let (ndigits, weight, sign, scale) = (word(0)? as usize, word(1)? as i16 as i64, word(2)?, word(3)? as usize);
+  match sign {
+    0xC000 => return Ok("NaN".to_string()),
+    0xD000 => return Ok("Infinity".to_string()),
+    0xF000 => return Ok("-Infinity".to_string()),
+    _ => {},
+  }
+  let digits = (0..ndigits).map(|i| word(4 + i)).collect::<Result<Vec<u16>, _>>()?;
+  let digit = |i: i64| usize::try_from(i).ok().and_then(|i| digits.get(i).copied()).unwrap_or(0);
+  let mut text = if sign == 0x4000 { "-".to_string() } else { String::new() };
+  match weight < 0 {
+    true => text.push('0'),
+    false => {
+      text.push_str(&digit(0).to_string());
+      (1..=weight).for_each(|i| text.push_str(&format!("{:04}", digit(i))));
+    },
+  }
+  if scale > 0 {
+    let fraction = (1..=(scale as i64 + 3) / 4).map(|k| format!("{:04}", digit(weight + k))).collect::<String>();
+    text.push('.');
+    text.push_str(&fraction[..scale]);
+  }
+  Ok(text)
+}
+
+pub enum DatabaseConnection {
+  Postgres(postgres::Client),
+  Sqlite(rusqlite::Connection),
+}
+
+fn sqlite_error(e: rusqlite::Error) -> SazidError {
+  SazidError::Other(format!("sqlite: {}", e))
+}
+
+impl DatabaseConnection {
+  // blocks, a postgres connection runs its own runtime and can't be opened from async code
+  pub fn connect(config: &DatabaseConfig) -> Result<Self, SazidError> {
+    match config.url.split_once("://") {
+      Some(("postgres" | "postgresql", _)) => {
+        Ok(DatabaseConnection::Postgres(postgres::Client::connect(&config.url, NoTls)?))
+      },
+      Some(("sqlite", path)) => {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        Ok(DatabaseConnection::Sqlite(rusqlite::Connection::open_with_flags(path, flags).map_err(sqlite_error)?))
+      },
+      _ => Err(SazidError::Other(format!("{} is not a postgres:// or sqlite:// url", config.url))),
+    }
+  }
+
+  // the tables and views of the database with their columns and comments
+  pub fn schema(&mut self) -> Result<Vec<TableSchema>, SazidError> {
+    match self {
+      DatabaseConnection::Postgres(client) => postgres_schema(client),
+      DatabaseConnection::Sqlite(connection) => sqlite_schema(connection).map_err(sqlite_error),
+    }
+  }
+
+  pub fn query(&mut self, sql: &str, max_rows: usize) -> Result<QueryResult, SazidError> {
+    ensure_read_only(sql)?;
+    let sql = sql.trim().trim_end_matches(';');
+    match self {
+      DatabaseConnection::Postgres(client) => {
+        let mut transaction = client.build_transaction().read_only(true).start()?;
+        transaction.batch_execute(&format!("SET LOCAL statement_timeout = '{}s'", STATEMENT_TIMEOUT_SECS))?;
+        // a prepared statement is a single statement, postgres refuses to prepare several
+        let statement = transaction.prepare(sql)?;
+        let columns = statement.columns().iter().map(|column| column.name().to_string()).collect::<Vec<String>>();
+        let mut result = QueryResult { columns, ..Default::default() };
+        let mut rows = transaction.query_raw(&statement, std::iter::empty::<&dyn ToSql>())?;
+        while let Some(row) = rows.next()? {
+          if result.rows.len() == max_rows {
+            result.truncated = true;
+            break;
+          }
+          let values = (0..row.len()).map(|i| row.try_get::<_, TextValue>(i).map(|value| value.0));
+          result.rows.push(values.collect::<Result<Vec<String>, _>>()?);
+        }
+        drop(rows);
+        transaction.rollback()?;
+        Ok(result)
+      },
+      DatabaseConnection::Sqlite(connection) => sqlite_query(connection, sql, max_rows).map_err(sqlite_error),
+    }
+  }
+}
+
+fn postgres_schema(client: &mut postgres::Client) -> Result<Vec<TableSchema>, SazidError> {
+  let rows = client.query(
+    "SELECT c.table_schema::text, c.table_name::text, c.column_name::text, c.data_type::text, \
+     c.is_nullable::text = 'YES', \
+     col_description(format('%I.%I', c.table_schema, c.table_name)::regclass, c.ordinal_position::int), \
+     obj_description(format('%I.%I', c.table_schema, c.table_name)::regclass, 'pg_class') \
+     FROM information_schema.columns c \
+     WHERE c.table_schema NOT IN ('pg_catalog', 'information_schema') \
+     ORDER BY c.table_schema, c.table_name, c.ordinal_position",
+    &[],
+  )?;
+  let mut tables: Vec<TableSchema> = vec![];
+  for row in rows {
+    let name = format!("{}.{}", row.get::<_, String>(0), row.get::<_, String>(1));
+    let column = ColumnSchema { name: row.get(2), data_type: row.get(3), nullable: row.get(4), comment: row.get(5) };
+    match tables.last_mut().filter(|table| table.name == name) {
+      Some(table) => table.columns.push(column),
+      None => tables.push(TableSchema { name, comment: row.get(6), columns: vec![column] }),
+    }
+  }
+  Ok(tables)
+}
+
+// sqlite keeps no comments, so only the names and types are known
+fn sqlite_schema(connection: &rusqlite::Connection) -> Result<Vec<TableSchema>, rusqlite::Error> {
+  let mut statement = connection.prepare(
+    "SELECT name FROM sqlite_master WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' ORDER BY name",
+  )?;
+  let names = statement.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>, _>>()?;
+  let mut tables = vec![];
+  for name in names {
+    let mut statement = connection.prepare("SELECT name, type, \"notnull\" FROM pragma_table_info(?1)")?;
+    let columns = statement
+      .query_map([&name], |row| {
+        Ok(ColumnSchema { name: row.get(0)?, data_type: row.get(1)?, nullable: !row.get::<_, bool>(2)?, comment: None })
+      })?
+      .collect::<Result<Vec<ColumnSchema>, _>>()?;
+    tables.push(TableSchema { name, comment: None, columns });
+  }
+  Ok(tables)
+}
+
+fn sqlite_query(connection: &rusqlite::Connection, sql: &str, max_rows: usize) -> Result<QueryResult, rusqlite::Error> {
+  let mut statement = connection.prepare(sql)?;
+  let columns = statement.column_names().into_iter().map(String::from).collect::<Vec<String>>();
+  let mut result = QueryResult { columns, ..Default::default() };
+  let mut rows = statement.query([])?;
+  while let Some(row) = rows.next()? {
+    if result.rows.len() == max_rows {
+      result.truncated = true;
+      break;
+    }
+    let mut values = vec![];
+    for i in 0..result.columns.len() {
+      values.push(match row.get_ref(i)? {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(n) => n.to_string(),
+        ValueRef::Real(n) => n.to_string(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).to_string(),
+        ValueRef::Blob(blob) => format!("<{} bytes>", blob.len()),
+      });
+    }
+    result.rows.push(values);
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sqlite_database(dir: &tempfile::TempDir) -> DatabaseConfig {
+    let path = dir.path().join("shop.db");
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection
+      .execute_batch(
+        "CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, email TEXT);
+         INSERT INTO customers (name, email) VALUES ('Ada', 'ada@example.com'), ('Grace', NULL), ('Linus', NULL);",
+      )
+      .unwrap();
+    DatabaseConfig { url: format!("sqlite://{}", path.display()), max_rows: 2 }
+  }
+
+  #[test]
+  fn test_sqlite_schema_and_query() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = sqlite_database(&dir);
+    let mut connection = DatabaseConnection::connect(&config).unwrap();
+    let schema = connection.schema().unwrap();
+    assert_eq!(schema.len(), 1);
+    assert_eq!(schema[0].to_string(), "table customers\n  id INTEGER\n  name TEXT not null\n  email TEXT");
+
+    let result = connection.query("select name, email from customers order by id;", config.max_rows).unwrap();
+    assert_eq!(result.rows, vec![vec!["Ada", "ada@example.com"], vec!["Grace", "NULL"]]);
+    assert!(result.truncated);
+    assert!(result.to_string().starts_with("name | email\nAda | ada@example.com\nGrace | NULL\n(only the first 2"));
+
+    // the connection is read only even for a statement that gets past the check
+    assert!(connection.query("delete from customers", 10).is_err());
+    assert!(connection.query("with gone as (select 1) delete from customers", 10).is_err());
+    assert_eq!(connection.query("select count(*) from customers", 10).unwrap().rows, vec![vec!["3"]]);
+  }
+
+  #[test]
+  fn test_ensure_read_only() {
+    assert!(ensure_read_only("  SELECT * FROM users;").is_ok());
+    assert!(ensure_read_only("explain select 1").is_ok());
+    assert!(ensure_read_only("drop table users").is_err());
+    assert!(ensure_read_only("select 1; drop table users").is_err());
+    assert!(ensure_read_only("select * from users where name = 'a;b';").is_ok());
+    assert!(ensure_read_only(r#"select "x;y" from t where note = 'it''s; fine'"#).is_ok());
+    assert!(ensure_read_only("select ';'; delete from users").is_err());
+    assert!(ensure_read_only("select 1 -- ';\n; commit; delete from t").is_err());
+    assert!(ensure_read_only("select 1 /* ; */ from t; -- done").is_ok());
+    assert!(ensure_read_only("select 1 /* /* */ ; */ from t").is_ok());
+    assert!(ensure_read_only("select $$;$$, $body$ ' $$ ; $body$ from t").is_ok());
+    assert!(ensure_read_only("select $$ ' $$; delete from t").is_err());
+    assert!(ensure_read_only(r"select E'\'; commit'; delete from t").is_err());
+    assert!(ensure_read_only("select a$b from t where c = $1; delete from t").is_err());
+  }
+
+  #[test]
+  fn test_numeric_text() {
+    let numeric = |words: &[u16]| words.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<u8>>();
+    // 12345.678, the digits 1, 2345 and 6780 with the first at weight 1
+    assert_eq!(numeric_text(&numeric(&[3, 1, 0, 3, 1, 2345, 6780])).unwrap(), "12345.678");
+    // -0.05, the digit 500 at weight -1
+    assert_eq!(numeric_text(&numeric(&[1, 0xFFFF, 0x4000, 2, 500])).unwrap(), "-0.05");
+    assert_eq!(numeric_text(&numeric(&[0, 0, 0, 0])).unwrap(), "0");
+    assert_eq!(numeric_text(&numeric(&[0, 0, 0xC000, 0])).unwrap(), "NaN");
+  }
+}
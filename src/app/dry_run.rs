@@ -0,0 +1,62 @@
+use async_openai::types::CreateChatCompletionRequest;
+
+use super::{compression::message_text, functions::argument_validation::count_tokens, guardrails::describe_message};
+
+// the request as it would be sent, with the tokens of each message and of the function schemas, for --dry-run
+pub fn describe_request(request: &CreateChatCompletionRequest) -> String {
+  let message_tokens = request
+    .messages
+    .iter()
+    .map(|message| {
+      let text = message_text(message).map(|t| t.as_str()).unwrap_or_default();
+      (describe_message(message, text), count_tokens(text))
+    })
+    .collect::<Vec<(String, usize)>>();
+  let function_tokens =
+    request.tools.as_ref().map(|tools| count_tokens(&serde_json::to_string(tools).unwrap_or_default())).unwrap_or(0);
+  let prompt_tokens = message_tokens.iter().map(|(_, tokens)| tokens).sum::<usize>() + function_tokens;
+  let mut lines = vec![format!("dry run: {} prompt tokens to {}, nothing was sent", prompt_tokens, request.model)];
+  for (description, tokens) in message_tokens.iter() {
+    lines.push(format!("  {:>7} tokens  {}", tokens, description));
+  }
+  if let Some(tools) = &request.tools {
+    lines.push(format!("  {:>7} tokens  {} function schemas", function_tokens, tools.len()));
+  }
+  lines.push(String::new());
+  lines.push(serde_json::to_string_pretty(request).unwrap_or_default());
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage,
+    ChatCompletionRequestUserMessageContent, Role,
+  };
+
+  use super::*;
+
+  #[test]
+  fn test_describe_request() {
+    let request = CreateChatCompletionRequest {
+      model: "gpt-4".to_string(),
+      messages: vec![
+        ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+          role: Role::System,
+          content: Some("you are terse".to_string()),
+        }),
+        ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+          role: Role::User,
+          content: Some(ChatCompletionRequestUserMessageContent::Text("what is rust?".to_string())),
+        }),
+      ],
+      ..Default::default()
+    };
+    let description = describe_request(&request);
+    let lines = description.lines().collect::<Vec<&str>>();
+    assert!(lines[0].starts_with("dry run: ") && lines[0].ends_with("tokens to gpt-4, nothing was sent"));
+    assert!(lines[1].ends_with("system: you are terse"));
+    assert!(lines[2].ends_with("user: what is rust?"));
+    assert!(description.contains("\"model\": \"gpt-4\""));
+  }
+}
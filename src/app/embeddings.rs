@@ -1,40 +1,158 @@
+use std::{
+  collections::{HashMap, HashSet},
+  path::Path,
+  time::Instant,
+};
+
 use crate::app::errors::SazidError;
+use crate::app::{
+  consts::CHUNK_TOKEN_LIMIT,
+  database::DatabaseConfig,
+  functions::argument_validation::count_tokens,
+  hooks::Hooks,
+  offline::ensure_online,
+  project::ingest_filter,
+  redaction::{describe_redactions, RedactionConfig, Redactor},
+  retry::RetryPolicy,
+  summarize::{collect_documents, Document},
+  tools::{
+    chunkifier::chunkify_text,
+    ocr::{document_text, extraction_step},
+  },
+};
 use crate::{cli::Cli, config::Config};
-use diesel::prelude::*;
-use diesel::sql_query;
-use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use async_openai::config::OpenAIConfig;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
 use dotenv::dotenv;
-use pgvector::{Vector, VectorExpressionMethods};
+use globset::GlobSet;
+use pgvector::Vector;
 
+use self::archive::{archive_documents, archive_member_name, ArchiveFormat};
+use self::audio::{is_audio_file, transcript_chunks, TRANSCRIPTION_MODEL};
+use self::embedded_store::EmbeddedVectorStore;
 use self::embeddings_models::EmbeddingModel;
+use self::git::DEFAULT_GIT_MAX_COMMITS;
+use self::hybrid::HybridSearchConfig;
+use self::ingest::{chunk_hash, cosine_similarity, DuplicateDetection, IngestReport, DEFAULT_INGEST_CONCURRENCY};
+use self::manifest::IngestManifest;
+use self::pipeline::{embed_batched, EmbeddingPipelineConfig};
+use self::postgres_store::PgVectorStore;
+use self::provenance::ChunkProvenance;
+use self::sections::{section_chunks, split_sections, SectionFormat};
+use self::store::{SearchFilter, VectorStore, VectorStoreKind};
+use self::tabular::{Table, TabularFormat};
 use self::types::*;
+use self::versions::{ChangeSummary, ReingestPolicy};
 use dialoguer;
 
+pub mod archive;
+pub mod audio;
+pub mod collections;
+pub mod database;
+pub mod embedded_store;
 pub mod embeddings_models;
+pub mod git;
+pub mod hybrid;
+pub mod index;
+pub mod ingest;
+pub mod manifest;
+pub mod pipeline;
+pub mod postgres_store;
+pub mod provenance;
 pub mod schema;
+pub mod sections;
+pub mod store;
+pub mod tabular;
 pub mod treesitter_extraction;
 pub mod types;
+pub mod versions;
+pub mod watch;
+
+// the migrations directory, applied when the embeddings manager connects to postgres
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// results returned by --search-embeddings
+const SEARCH_RESULTS: i64 = 10;
+
+// embedding requests per manifest update, as a multiple of the concurrency limit
+const INGEST_BATCH_REQUESTS: usize = 1;
+
+// the stored chunks closest to a new chunk that are compared with it for near duplicates
+const NEAR_DUPLICATE_CANDIDATES: i64 = 5;
+
+// the model the sources of a collection are embedded with, model when none is configured for it
+pub fn configured_embedding_model(config: &Config, collection: Option<&str>, model: EmbeddingModel) -> EmbeddingModel {
+  collection
+    .and_then(|c| config.collection_embedding_models.get(c))
+    .or(config.embedding_model.as_ref())
+    .map(EmbeddingModel::from_settings)
+    .unwrap_or(model)
+}
+
+// a file split for embedding
+#[derive(Debug, Clone, Default)]
+struct FileChunks {
+  chunks: Vec<String>,
+  // the heading path of each chunk of a markdown or html file or the time range of each chunk of a recording, None
+  // for other files
+  sections: Vec<Option<String>>,
+  // recorded in the provenance of each chunk
+  transformations: Vec<String>,
+}
 
 pub struct EmbeddingsManager {
-  client: AsyncPgConnection,
+  store: Box<dyn VectorStore>,
+  // the model of the current collection
   model: EmbeddingModel,
+  default_model: EmbeddingModel,
+  // collections embedded with a model other than the default
+  collection_models: HashMap<String, EmbeddingModel>,
+  // the number of embedding requests sent at once while ingesting
+  concurrency: usize,
+  pipeline: EmbeddingPipelineConfig,
+  retry_policy: RetryPolicy,
+  // in offline mode only models served on this machine can embed
+  offline: bool,
+  reingest_policy: ReingestPolicy,
+  duplicates: DuplicateDetection,
+  hybrid_search: HybridSearchConfig,
+  // chunks are redacted before they are embedded or retrieved as context
+  redactor: Redactor,
+  // when set, ingested files are added to this collection and searches only return its chunks
+  collection: Option<String>,
+  // on_ingest hooks can rewrite or skip each document before it is chunked
+  hooks: Option<Hooks>,
+  // the collection used when the command line gives none, from the project config
+  default_collection: Option<String>,
+  // the only files ingested from a directory, by their path relative to it
+  ingest_globs: Option<GlobSet>,
+  // files left out of a directory or archive even when they match the ingest globs, from --exclude
+  exclude_globs: Option<GlobSet>,
+  // the databases whose schema --ingest-database can ingest, by name
+  databases: HashMap<String, DatabaseConfig>,
+  // the chat api, whose transcriptions endpoint transcribes ingested recordings
+  openai_config: OpenAIConfig,
+  // why the openai embedding model can't be used, such as when there is no openai api key
+  openai_unavailable: Option<String>,
 }
 
 impl EmbeddingsManager {
   pub async fn run(&mut self, args: Cli) -> Result<Option<String>, SazidError> {
     println!("args: {:#?}", args);
+    self.set_collection(args.collection.clone().or_else(|| self.default_collection.clone()));
+    self.set_ingest_filters(&args.include, &args.exclude)?;
     Ok(match args {
       Cli { list_embeddings: true, .. } => {
         // let categories = self.list_embeddings_categories().await?;
-        let embeddings = self.get_all_embeddings().await?;
+        let sources = self.list_ingested_sources().await?;
 
-        if embeddings.len() == 0 {
+        if sources.len() == 0 {
           Some("No embeddings found".to_string())
         } else {
           Some(
-            embeddings
+            sources
               .into_iter()
-              .map(|(fe, vec_ep)| format!("{} -- {} pages", fe.filepath, vec_ep.len()))
+              .map(|source| format!("{} -- {} pages", source.filepath, source.chunk_count))
               .collect::<Vec<String>>()
               .join("\n"),
           )
@@ -55,8 +173,8 @@ impl EmbeddingsManager {
           false => Some("cancelled".to_string()),
         }
       },
-      Cli { search_embeddings: Some(text), .. } => {
-        let embeddings = self.search_all_embeddings(&text).await?;
+      Cli { search_embeddings: Some(text), include_versions, .. } => {
+        let embeddings = self.search_all_embeddings(&text, include_versions).await?;
         if embeddings.len() == 0 {
           Some("No embeddings found".to_string())
         } else {
@@ -69,27 +187,227 @@ impl EmbeddingsManager {
         Some("parse_source_embeddings".to_string())
       },
       Cli { add_text_file_embeddings: Some(filepath), .. } => {
-        // read the file at filepath
-        match self.add_textfile_embedding(&filepath).await {
-          Ok(_) => Some(format!("Added embedding for file at {}", filepath)),
+        match self.ingest_path(Path::new(&filepath)).await {
+          Ok(report) => Some(report.to_string()),
           Err(e) => Some(format!("Error adding embedding for file at {}: {}", filepath, e)),
         }
       },
+      Cli { ingest_git: Some(repo), git_max_commits, .. } => {
+        match self.ingest_git_repository(&repo, git_max_commits.unwrap_or(DEFAULT_GIT_MAX_COMMITS)).await {
+          Ok(report) => Some(report.to_string()),
+          Err(e) => Some(format!("Error ingesting git repository at {}: {}", repo.display(), e)),
+        }
+      },
+      Cli { ingest_database: Some(database), .. } => match self.ingest_database(&database).await {
+        Ok(report) => Some(report.to_string()),
+        Err(e) => Some(format!("Error ingesting the schema of database {}: {}", database, e)),
+      },
       Cli { add_text_embeddings: Some(_text), .. } => Some("deprecated".to_string()),
+      Cli { watch: true, .. } => {
+        self.watch_ingested_directories().await?;
+        Some("stopped watching".to_string())
+      },
+      Cli { create_index: Some(kind), index_lists, index_m, index_ef_construction, .. } => {
+        let store = self.postgres_store()?;
+        let index =
+          store.default_vector_index(kind).await?.with_parameters(index_lists, index_m, index_ef_construction);
+        store.create_vector_index(index).await?;
+        Some(format!("Created {} similarity index", index))
+      },
+      Cli { reindex: true, .. } => {
+        let store = self.postgres_store()?;
+        store.maintain_vector_index().await?;
+        match store.vector_index_status().await? {
+          Some(status) => Some(format!("Rebuilt the similarity index\n{}", status)),
+          None => Some("Analyzed embeddings, there is no similarity index, create one with --create-index".to_string()),
+        }
+      },
+      Cli { export_provenance: Some(path), .. } => {
+        let provenance = self.export_provenance().await?;
+        let count = provenance.lines().count();
+        std::fs::write(&path, provenance)?;
+        Some(format!("Exported provenance of {} chunks to {}", count, path.display()))
+      },
       _ => None,
     })
   }
 
-  pub async fn search_all_embeddings(&mut self, text: &str) -> Result<Vec<EmbeddingPage>, SazidError> {
-    // create a vector of text, and then do a search for a similar vector
-    let vector = self.model.create_embedding_vector(text).await?;
-    self.get_similar_embeddings(vector, 10).await
+  pub async fn search_all_embeddings(
+    &mut self,
+    text: &str,
+    include_versions: bool,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    self.hybrid_search(text, SEARCH_RESULTS, include_versions).await
+  }
+
+  // ranks chunks by vector similarity and by keyword matches, and merges the rankings with reciprocal rank fusion
+  // a keyword weight of 1 needs no embedding of the search text, so it also works offline
+  pub async fn hybrid_search(
+    &mut self,
+    text: &str,
+    limit: i64,
+    include_versions: bool,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let filter = SearchFilter { include_versions, collection: self.collection.clone() };
+    let candidates = limit * self.hybrid_search.candidate_multiplier.max(1);
+    let vector_pages = match self.hybrid_search.uses_vectors() {
+      true => {
+        self.ensure_can_embed("embedding the search text")?;
+        self.ensure_compatible_store().await?;
+        let vector = self.model.create_embedding_vector(text).await?;
+        self.store.similar_pages(&vector, candidates, &filter).await?
+      },
+      false => vec![],
+    };
+    let keyword_pages = match self.hybrid_search.uses_keywords() {
+      true => self.store.keyword_pages(text, candidates, &filter).await?,
+      false => vec![],
+    };
+    let ranked = self.hybrid_search.fuse(
+      &vector_pages.iter().map(|page| page.id).collect::<Vec<i64>>(),
+      &keyword_pages.iter().map(|page| page.id).collect::<Vec<i64>>(),
+    );
+    let mut pages: HashMap<i64, EmbeddingPage> =
+      vector_pages.into_iter().chain(keyword_pages).map(|page| (page.id, page)).collect();
+    Ok(ranked.into_iter().take(limit as usize).filter_map(|id| pages.remove(&id)).collect())
+  }
+
+  // model is used when no embedding_model is configured
+  pub async fn init(config: Config, model: EmbeddingModel) -> Result<Self, SazidError> {
+    let store: Box<dyn VectorStore> = match config.vector_store {
+      VectorStoreKind::Postgres => {
+        dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")
+          .map_err(|_| SazidError::Other("DATABASE_URL is not set, or set vector_store to embedded".to_string()))?;
+        Box::new(PgVectorStore::connect(&database_url, &config.vector_search.clone().unwrap_or_default()).await?)
+      },
+      VectorStoreKind::Embedded => Box::new(EmbeddedVectorStore::open(&EmbeddedVectorStore::default_path()?)?),
+    };
+    Ok(Self::with_store(config, model, store))
+  }
+
+  // indexes nothing persistently, for questions about a single file
+  pub fn ephemeral(config: Config, model: EmbeddingModel) -> Self {
+    Self::with_store(config, model, Box::new(EmbeddedVectorStore::in_memory()))
+  }
+
+  fn with_store(config: Config, model: EmbeddingModel, store: Box<dyn VectorStore>) -> Self {
+    let model = config.embedding_model.as_ref().map(EmbeddingModel::from_settings).unwrap_or(model);
+    let collection_models = config
+      .collection_embedding_models
+      .iter()
+      .map(|(collection, settings)| (collection.clone(), EmbeddingModel::from_settings(settings)))
+      .collect();
+    let redaction = config.session_config.redaction.clone();
+    let redactor = Redactor::new(&redaction).unwrap_or_else(|e| {
+      log::error!("{}, ingested chunks are redacted with the built in patterns only", e);
+      Redactor::new(&RedactionConfig { patterns: vec![], ..redaction }).unwrap_or_default()
+    });
+    let hooks = config.session_config.hooks_dir.as_deref().map(Hooks::load).transpose().unwrap_or_else(|e| {
+      log::error!("{}, documents are ingested without hooks", e);
+      None
+    });
+    let ingest_globs = ingest_filter(&config.ingest_globs).unwrap_or_else(|e| {
+      log::error!("{}, every file is ingested", e);
+      None
+    });
+    EmbeddingsManager {
+      store,
+      model: model.clone(),
+      default_model: model,
+      collection_models,
+      concurrency: config.ingest_concurrency.unwrap_or(DEFAULT_INGEST_CONCURRENCY),
+      pipeline: config.embedding_pipeline.unwrap_or_default(),
+      retry_policy: config.session_config.retry_policy.clone(),
+      offline: config.session_config.offline,
+      reingest_policy: config.reingest_policy.unwrap_or_default(),
+      duplicates: config.duplicate_detection.clone().unwrap_or_default(),
+      hybrid_search: config.hybrid_search.unwrap_or_default(),
+      redactor,
+      collection: None,
+      hooks: hooks.flatten(),
+      default_collection: config.collection.clone(),
+      ingest_globs,
+      exclude_globs: None,
+      databases: config.databases.clone(),
+      openai_config: config.session_config.openai_config.clone(),
+      openai_unavailable: None,
+    }
+  }
+
+  // embedding with the openai model fails with the reason, models configured with embedding_model still embed
+  pub fn disable_openai_embeddings(&mut self, reason: &str) {
+    self.openai_unavailable = Some(reason.to_string());
+  }
+
+  // searches and ingests the collection with its configured model, or the default model
+  pub fn set_collection(&mut self, collection: Option<String>) {
+    self.model = self.model_for(collection.as_deref());
+    self.collection = collection;
   }
 
-  pub async fn init(_config: Config, model: EmbeddingModel) -> Result<Self, SazidError> {
-    dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL").unwrap();
-    Ok(EmbeddingsManager { client: AsyncPgConnection::establish(&database_url).await.unwrap(), model })
+  // --include replaces the configured ingest globs for this run, --exclude leaves out what either would ingest
+  pub fn set_ingest_filters(&mut self, include: &[String], exclude: &[String]) -> Result<(), SazidError> {
+    if !include.is_empty() {
+      self.ingest_globs = ingest_filter(include)?;
+    }
+    self.exclude_globs = ingest_filter(exclude)?;
+    Ok(())
+  }
+
+  // whether a file of an ingested directory or archive passes the filters, by its path relative to it
+  fn is_ingested(&self, relative: &str) -> bool {
+    self.ingest_globs.as_ref().map_or(true, |globs| globs.is_match(relative))
+      && !self.exclude_globs.as_ref().map_or(false, |globs| globs.is_match(relative))
+  }
+
+  fn model_for(&self, collection: Option<&str>) -> EmbeddingModel {
+    collection.and_then(|c| self.collection_models.get(c)).unwrap_or(&self.default_model).clone()
+  }
+
+  fn ensure_can_embed(&self, action: &str) -> Result<(), SazidError> {
+    if let (EmbeddingModel::Ada002(_), Some(reason)) = (&self.model, &self.openai_unavailable) {
+      return Err(SazidError::Other(format!("{} is not possible, {}", action, reason)));
+    }
+    match self.model.is_local() {
+      true => Ok(()),
+      false => ensure_online(self.offline, action),
+    }
+  }
+
+  // vectors from different models can't be compared, so a collection, or a store when no collection is set, only
+  // holds the embeddings of one model
+  async fn ensure_compatible_store(&mut self) -> Result<(), SazidError> {
+    let current = (self.model.model_string(), self.model.dimensions() as i32);
+    let others = self
+      .store
+      .embedding_models(self.collection.as_deref())
+      .await?
+      .into_iter()
+      .filter(|model| *model != current)
+      .map(|(model, dimensions)| format!("{} ({} dimensions)", model, dimensions))
+      .collect::<Vec<String>>();
+    let reembed = match &self.collection {
+      Some(collection) => format!("sazid index reembed {}", collection),
+      None => "sazid index reembed [<collection>]".to_string(),
+    };
+    match others.is_empty() {
+      true => Ok(()),
+      false => Err(SazidError::Other(format!(
+        "the vector store has chunks embedded with {}, which can't be compared with {} embeddings, \
+         re-embed them with `{}`, or delete those sources or use another collection or store",
+        others.join(", "),
+        current.0,
+        reembed
+      ))),
+    }
+  }
+
+  fn postgres_store(&mut self) -> Result<&mut PgVectorStore, SazidError> {
+    self
+      .store
+      .as_postgres()
+      .ok_or(SazidError::Other("similarity indexes are only managed in the postgres vector store".to_string()))
   }
 
   pub async fn add_embedding(
@@ -97,76 +415,534 @@ impl EmbeddingsManager {
     embedding: &InsertableFileEmbedding,
     pages: Vec<&InsertablePage>,
   ) -> Result<i64, SazidError> {
-    let embedding_id = diesel::insert_into(self::schema::file_embeddings::table)
-      .values(embedding)
-      .on_conflict(self::schema::file_embeddings::dsl::checksum)
-      .do_update()
-      .set(embedding)
-      .returning(self::schema::file_embeddings::id)
-      .get_result(&mut self.client)
-      .await?;
-    println!("embedding_id: {}", embedding_id);
-
-    for p in pages {
-      diesel::insert_into(self::schema::embedding_pages::table)
-        .values((
-          schema::embedding_pages::content.eq(p.content.clone()),
-          schema::embedding_pages::page_number.eq(p.page_number.clone()),
-          schema::embedding_pages::checksum.eq(p.checksum.clone()),
-          schema::embedding_pages::file_embedding_id.eq(embedding_id),
-          schema::embedding_pages::embedding.eq(p.embedding.clone()),
-        ))
-        .execute(&mut self.client)
-        .await?;
-    }
+    let embedding_id = self.store.add_source(embedding).await?;
+    self.store.add_pages(embedding_id, pages).await?;
+    self.store.flush().await?;
     Ok(embedding_id)
   }
 
-  pub async fn get_all_embeddings(&mut self) -> Result<Vec<(FileEmbedding, Vec<EmbeddingPage>)>, SazidError> {
-    // use schema::embedding_pages::dsl::*;
-    use schema::file_embeddings::dsl::*;
+  // superseded versions of changed files are only included when include_versions is set
+  // when the manager has a collection, only chunks of sources in that collection are returned
+  pub async fn get_similar_embeddings(
+    &mut self,
+    vector: Vector,
+    limit: i64,
+    include_versions: bool,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let filter = SearchFilter { include_versions, collection: self.collection.clone() };
+    self.store.similar_pages(&vector, limit, &filter).await
+  }
 
-    let all_files = file_embeddings.select(FileEmbedding::as_select()).load(&mut self.client).await?;
+  pub async fn add_textfile_embedding(&mut self, filepath: &str) -> Result<i64, SazidError> {
+    let content = std::fs::read_to_string(filepath)?;
+    let file = self.file_chunks(filepath, &content);
+    let mut pages = vec![];
+    for (i, vector) in self.embed_chunks(filepath, &file.chunks).await?.into_iter().enumerate() {
+      let embedding = vector.map_err(SazidError::Other)?;
+      pages.push(self.page(filepath, &content, &file, i, embedding));
+    }
+    self.store_file(filepath, &content, 1, pages).await
+  }
 
-    let pages =
-      EmbeddingPage::belonging_to(&all_files).select(EmbeddingPage::as_select()).load(&mut self.client).await?;
+  // splits a file into chunks that fit the embedding model once the file path is added as a header
+  // csv, tsv and json lines files are split by groups of rows, markdown and html files at their headings, transcripts
+  // of recordings between segments, and text files and files that fail to parse by tokens
+  fn file_chunks(&self, filepath: &str, content: &str) -> FileChunks {
+    let tokens = self.chunk_tokens(filepath);
+    let audio = is_audio_file(Path::new(filepath));
+    let mut transformations = match audio {
+      true => vec![format!("transcribed with {}, one line per segment with its time range", TRANSCRIPTION_MODEL)],
+      false => vec![extraction_step(Path::new(filepath)).to_string()],
+    };
+    let table = TabularFormat::from_path(filepath).and_then(|format| match Table::parse(content, format) {
+      Ok(table) => Some(table),
+      Err(e) => {
+        log::warn!("{}: {}, splitting it as text", filepath, e);
+        None
+      },
+    });
+    let (chunks, sections) = match (table, SectionFormat::from_path(filepath)) {
+      (Some(table), _) => {
+        transformations.extend(table.transformations(tokens));
+        let chunks = table.chunks(tokens);
+        let sections = vec![None; chunks.len()];
+        (chunks, sections)
+      },
+      (None, Some(format)) => {
+        transformations.push(format!("parsed as {} and split at its headings into sections", format));
+        transformations.push(format!(
+          "each section split into chunks of at most {} tokens that start with its heading path",
+          tokens
+        ));
+        section_chunks(&split_sections(content, format), tokens).into_iter().unzip()
+      },
+      (None, None) if audio => {
+        transformations.push(format!("split between segments into chunks of at most {} tokens", tokens));
+        transcript_chunks(content, tokens).into_iter().unzip()
+      },
+      (None, None) => {
+        transformations.push(format!("split into chunks of at most {} tokens", tokens));
+        let chunks = chunkify_text(content, tokens);
+        let sections = vec![None; chunks.len()];
+        (chunks, sections)
+      },
+    };
+    transformations.push("prefixed with the source path before embedding".to_string());
+    let (chunks, labels): (Vec<String>, Vec<Vec<String>>) =
+      chunks.iter().map(|chunk| self.redactor.redact(chunk)).unzip();
+    if let Some(warning) = describe_redactions(&labels.concat(), "while ingesting") {
+      log::warn!("{}: {}", filepath, warning);
+    }
+    let sections = sections.into_iter().map(|section| section.map(|s| self.redactor.redact(&s).0)).collect();
+    FileChunks { chunks, sections, transformations }
+  }
 
-    Ok(
-      pages
-        .grouped_by(&all_files)
-        .into_iter()
-        .zip(all_files)
-        .map(|(pages, file)| (file, pages.into_iter().map(|page| page).collect()))
-        .collect::<Vec<(FileEmbedding, Vec<EmbeddingPage>)>>(),
-    )
+  fn chunk_tokens(&self, filepath: &str) -> usize {
+    let header_tokens = count_tokens(filepath) + 1;
+    self.model.token_limit().saturating_sub(header_tokens).min(CHUNK_TOKEN_LIMIT as usize)
   }
 
-  pub async fn get_similar_embeddings(&mut self, vector: Vector, limit: i64) -> Result<Vec<EmbeddingPage>, SazidError> {
-    let query = self::schema::embedding_pages::table
-      .select(EmbeddingPage::as_select())
-      .order(schema::embedding_pages::embedding.cosine_distance(&vector))
-      .limit(limit);
-    let embeddings = query.load::<EmbeddingPage>(&mut self.client).await?;
-    Ok(embeddings)
+  // embeds the chunks in batches with up to concurrency requests at once, a failed batch does not stop the others
+  async fn embed_chunks(
+    &self,
+    filepath: &str,
+    chunks: &[String],
+  ) -> Result<Vec<Result<Vector, String>>, SazidError> {
+    let texts = chunks.iter().map(|chunk| format!("{}\n{}", filepath, chunk)).collect::<Vec<String>>();
+    self.embed_texts(texts).await
   }
 
-  pub async fn add_embedding_tag(&mut self, tag_name: &str) -> Result<usize, SazidError> {
-    Ok(diesel::insert_into(schema::tags::table).values(schema::tags::tag.eq(tag_name)).execute(&mut self.client).await?)
+  async fn embed_texts(&self, texts: Vec<String>) -> Result<Vec<Result<Vector, String>>, SazidError> {
+    self.ensure_can_embed("embedding")?;
+    embed_batched(&self.model, texts, &self.pipeline, &self.retry_policy, self.concurrency).await
   }
 
-  pub async fn add_textfile_embedding(&mut self, filepath: &str) -> Result<i64, SazidError> {
-    let content = std::fs::read_to_string(filepath)?;
+  fn page(
+    &self,
+    filepath: &str,
+    content: &str,
+    file: &FileChunks,
+    page_number: usize,
+    embedding: Vector,
+  ) -> InsertablePage {
+    let source_checksum = blake3::hash(content.as_bytes()).to_hex().to_string();
+    let chunk = &file.chunks[page_number];
+    let transformations = file.transformations.clone();
+    let section = file.sections[page_number].clone();
+    self.source_page(filepath, &source_checksum, page_number, chunk, embedding, transformations, section)
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  fn source_page(
+    &self,
+    filepath: &str,
+    source_checksum: &str,
+    page_number: usize,
+    chunk: &str,
+    embedding: Vector,
+    transformations: Vec<String>,
+    section: Option<String>,
+  ) -> InsertablePage {
+    // page checksums are unique across the database, so they cover the file as well as the page
+    let checksum = blake3::hash(format!("{}:{}", source_checksum, page_number).as_bytes()).to_hex().to_string();
+    let provenance = ChunkProvenance::new(
+      filepath,
+      source_checksum,
+      chunk,
+      page_number as i32,
+      &self.model.model_string(),
+      self.model.dimensions(),
+      transformations,
+    )
+    .with_section(section);
+    InsertablePage {
+      content: chunk.to_string(),
+      page_number: page_number as i32,
+      checksum,
+      embedding,
+      provenance: provenance.to_json(),
+    }
+  }
+
+  async fn store_file(
+    &mut self,
+    filepath: &str,
+    content: &str,
+    version: i32,
+    pages: Vec<InsertablePage>,
+  ) -> Result<i64, SazidError> {
     let checksum = blake3::hash(content.as_bytes()).to_hex().to_string();
-    let vector_content = vec![filepath.to_string(), content.to_string()].join("\n");
-    let embedding = self.model.create_embedding_vector(&vector_content).await?;
-    let new_embedding = InsertableFileEmbedding { filepath: filepath.to_string(), checksum: checksum.clone() };
-    let new_page = InsertablePage { content, page_number: 0, checksum, embedding };
-    Ok(self.add_embedding(&new_embedding, vec![&new_page]).await?)
-  }
-  // Method to retrieve indexing progress information
-  pub async fn get_indexing_progress(&mut self) -> Result<Vec<PgVectorIndexInfo>, SazidError> {
-    let progress_info =
-      sql_query("SELECT * FROM pg_vector_index_info;").load::<PgVectorIndexInfo>(&mut self.client).await?;
-    Ok(progress_info)
+    let new_embedding = InsertableFileEmbedding {
+      filepath: filepath.to_string(),
+      checksum,
+      version,
+      embedding_model: self.model.model_string(),
+      embedding_dimensions: self.model.dimensions() as i32,
+    };
+    self.add_embedding(&new_embedding, pages.iter().collect()).await
+  }
+
+  // ingests a file, every text file under a directory or every text file in a zip or tar archive, skipping files that
+  // are unchanged since they were ingested
+  pub async fn ingest_path(&mut self, path: &Path) -> Result<IngestReport, SazidError> {
+    // stored paths are absolute, so that they match the paths reported by the watcher
+    let path = &path.canonicalize()?;
+    let archive = ArchiveFormat::from_path(path).filter(|_| path.is_file());
+    let documents = match (archive, path.is_dir()) {
+      (Some(format), _) => archive_documents(path, format)?
+        .into_iter()
+        .filter(|document| self.is_ingested(&document.name))
+        .map(|document| Document { name: archive_member_name(path, &document.name), ..document })
+        .collect(),
+      (None, true) => {
+        let manifest_path = IngestManifest::default_path()?;
+        let mut manifest = IngestManifest::load(&manifest_path);
+        manifest.add_root(path);
+        manifest.save(&manifest_path)?;
+        collect_documents(path)
+          .into_iter()
+          .filter(|document| self.is_ingested(&document.name))
+          .map(|document| Document { name: path.join(&document.name).to_string_lossy().to_string(), ..document })
+          .collect()
+      },
+      (None, false) => {
+        let content = match is_audio_file(path) {
+          true => self.transcribe(path).await?,
+          false => document_text(path)?,
+        };
+        vec![Document { name: path.to_string_lossy().to_string(), content }]
+      },
+    };
+    self.ingest_documents(documents).await
+  }
+
+  // embeds and stores the chunks of each document that are not already recorded in the ingest manifest
+  // the manifest is saved after every batch of chunks, so an interrupted run resumes from the last batch
+  pub async fn ingest_documents(&mut self, documents: Vec<Document>) -> Result<IngestReport, SazidError> {
+    let started = Instant::now();
+    let documents = self.hook_documents(documents)?;
+    self.ensure_compatible_store().await?;
+    let manifest_path = IngestManifest::default_path()?;
+    let mut manifest = IngestManifest::load(&manifest_path);
+    let mut report = IngestReport::default();
+    // (document index, chunk index) for every chunk still to be stored
+    let mut pending: Vec<(usize, usize)> = vec![];
+    let mut source_ids: Vec<i64> = vec![];
+    let mut files: Vec<FileChunks> = vec![];
+    // the hashes of the chunks to be stored, so that a chunk repeated in this run is only stored once
+    let mut pending_hashes: HashSet<String> = HashSet::new();
+    for (i, document) in documents.iter().enumerate() {
+      let checksum = blake3::hash(document.content.as_bytes()).to_hex().to_string();
+      if manifest.is_current(&document.name, &checksum) {
+        report.skipped += 1;
+        source_ids.push(0);
+        files.push(FileChunks::default());
+        continue;
+      }
+      let mut version = 1;
+      if let Some(stale_id) = manifest.stale_entry(&document.name, &checksum).map(|entry| entry.source_id) {
+        version = self.resolve_changed_source(stale_id, &document.name, &document.content).await?;
+        manifest.remove_source(stale_id);
+      }
+      let file = self.file_chunks(&document.name, &document.content);
+      let source_id = self.store_file(&document.name, &document.content, version, vec![]).await?;
+      if let Some(collection) = self.collection.clone() {
+        self.store.add_source_to_collection(source_id, &collection).await?;
+      }
+      manifest.start(&document.name, source_id, &checksum, file.chunks.len());
+      source_ids.push(source_id);
+      let hashes = file.chunks.iter().map(|chunk| chunk_hash(chunk)).collect::<Vec<String>>();
+      let stored_hashes = match self.duplicates.skip_exact {
+        true => self.store.stored_chunk_hashes(&hashes, &document.name, self.collection.as_deref()).await?,
+        false => HashSet::new(),
+      };
+      for (c, hash) in hashes.into_iter().enumerate() {
+        if manifest.is_stored(&document.name, c) {
+          continue;
+        }
+        if self.duplicates.skip_exact && (stored_hashes.contains(&hash) || !pending_hashes.insert(hash)) {
+          manifest.mark_stored(&document.name, c);
+          report.duplicates += 1;
+          continue;
+        }
+        pending.push((i, c));
+      }
+      files.push(file);
+    }
+    self.store.flush().await?;
+    manifest.save(&manifest_path)?;
+    let source_paths = match self.duplicates.similarity_threshold {
+      Some(_) => self.list_ingested_sources().await?.into_iter().map(|s| (s.id, s.filepath)).collect(),
+      None => HashMap::new(),
+    };
+
+    let mut errors: Vec<Option<String>> = vec![None; documents.len()];
+    for batch in pending.chunks(self.concurrency.max(1) * INGEST_BATCH_REQUESTS * self.pipeline.batch_size.max(1)) {
+      let texts = batch.iter().map(|(i, c)| format!("{}\n{}", documents[*i].name, files[*i].chunks[*c])).collect();
+      let vectors = self.embed_texts(texts).await?;
+      for ((i, c), vector) in batch.iter().zip(vectors) {
+        let document = &documents[*i];
+        match vector {
+          Ok(embedding) => {
+            if self.is_near_duplicate(&embedding, &document.name, &source_paths).await? {
+              manifest.mark_stored(&document.name, *c);
+              report.duplicates += 1;
+              continue;
+            }
+            let page = self.page(&document.name, &document.content, &files[*i], *c, embedding);
+            self.store.add_pages(source_ids[*i], vec![&page]).await?;
+            manifest.mark_stored(&document.name, *c);
+            report.chunks += 1;
+            report.tokens += count_tokens(&page.content);
+          },
+          Err(e) => errors[*i] = Some(e),
+        }
+      }
+      // the store is written before the manifest, so the manifest never records a chunk the store lost
+      self.store.flush().await?;
+      manifest.save(&manifest_path)?;
+    }
+    for (document, error) in documents.iter().zip(errors) {
+      match error {
+        Some(error) => report.failed.push((document.name.clone(), error)),
+        None => report.files += 1,
+      }
+    }
+    report.files -= report.skipped;
+    report.elapsed = started.elapsed();
+    Ok(report)
+  }
+
+  // whether a stored chunk of another file is at least as similar to the embedding as the similarity threshold
+  async fn is_near_duplicate(
+    &mut self,
+    embedding: &Vector,
+    filepath: &str,
+    source_paths: &HashMap<i64, String>,
+  ) -> Result<bool, SazidError> {
+    let Some(threshold) = self.duplicates.similarity_threshold else {
+      return Ok(false);
+    };
+    let filter = SearchFilter { include_versions: false, collection: self.collection.clone() };
+    let nearest = self.store.similar_pages(embedding, NEAR_DUPLICATE_CANDIDATES, &filter).await?;
+    let vector = embedding.to_vec();
+    Ok(nearest.iter().any(|page| {
+      source_paths.get(&page.source_id()).map_or(true, |path| path != filepath)
+        && cosine_similarity(&vector, &page.embedding.to_vec()) >= threshold
+    }))
+  }
+
+  // the documents as the on_ingest hooks rewrote them, without the ones a hook skipped
+  fn hook_documents(&self, documents: Vec<Document>) -> Result<Vec<Document>, SazidError> {
+    let Some(hooks) = &self.hooks else {
+      return Ok(documents);
+    };
+    let mut hooked = vec![];
+    for document in documents {
+      match hooks.on_ingest(&document.name, &document.content)? {
+        Some(content) => hooked.push(Document { content, ..document }),
+        None => log::info!("{} is skipped by a hook", document.name),
+      }
+    }
+    // there is no session to tag while ingesting
+    hooks.take_effects();
+    Ok(hooked)
+  }
+
+  // summarizes how a stored source differs from the new content of its file, and replaces, versions or keeps it
+  // according to the reingest policy, returns the version number for the new content
+  async fn resolve_changed_source(
+    &mut self,
+    source_id: i64,
+    filepath: &str,
+    content: &str,
+  ) -> Result<i32, SazidError> {
+    let previous_version = self.store.source_version(source_id).await?.unwrap_or_default();
+    let changes = ChangeSummary::new(&self.source_content(source_id).await?, content);
+    match self.reingest_policy.resolve(filepath, &changes)? {
+      ReingestPolicy::Version => self.store.mark_superseded(source_id).await?,
+      ReingestPolicy::KeepBoth => {},
+      ReingestPolicy::Replace | ReingestPolicy::Ask => self.delete_source(source_id).await?,
+    }
+    Ok(previous_version + 1)
+  }
+
+  // the stored text of a source, its pages joined in order
+  pub async fn source_content(&mut self, source_id: i64) -> Result<String, SazidError> {
+    Ok(self.source_chunks(source_id).await?.join("\n"))
+  }
+
+  // the stored chunks of a source in order
+  pub async fn source_chunks(&mut self, source_id: i64) -> Result<Vec<String>, SazidError> {
+    Ok(self.store.source_pages(source_id).await?.into_iter().map(|page| page.content).collect())
+  }
+
+  pub async fn list_ingested_sources(&mut self) -> Result<Vec<IngestedSource>, SazidError> {
+    self.store.list_sources().await
+  }
+
+  // the pages of every source in a collection, in file and page order
+  pub async fn get_collection_pages(&mut self, collection: &str) -> Result<Vec<CollectionPage>, SazidError> {
+    self.store.collection_pages(collection).await
+  }
+
+  pub async fn delete_source(&mut self, source_id: i64) -> Result<(), SazidError> {
+    let manifest_path = IngestManifest::default_path()?;
+    let mut manifest = IngestManifest::load(&manifest_path);
+    manifest.remove_source(source_id);
+    manifest.save(&manifest_path)?;
+    self.store.delete_source(source_id).await?;
+    self.store.flush().await
+  }
+
+  // replaces the stored chunks with the current contents of the file, keeping its collections
+  pub async fn reingest_source(&mut self, source: &IngestedSource) -> Result<i64, SazidError> {
+    self.delete_source(source.id).await?;
+    // embedded with the model of its collection, whatever collection is current
+    let model = self.model_for(source.collection());
+    let current_model = std::mem::replace(&mut self.model, model);
+    let source_id = self.add_textfile_embedding(&source.filepath).await;
+    self.model = current_model;
+    let source_id = source_id?;
+    for collection in source.collections.split(',').filter(|c| !c.is_empty()) {
+      self.add_source_to_collection(source_id, collection).await?;
+    }
+    Ok(source_id)
+  }
+
+  pub async fn add_source_to_collection(&mut self, source_id: i64, collection: &str) -> Result<(), SazidError> {
+    self.store.add_source_to_collection(source_id, collection).await?;
+    self.store.flush().await
+  }
+
+  // false when the collection already exists
+  pub async fn create_collection(&mut self, collection: &str) -> Result<bool, SazidError> {
+    let created = self.store.create_collection(collection).await?;
+    self.store.flush().await?;
+    Ok(created)
+  }
+
+  pub async fn collection_stats(&mut self) -> Result<Vec<CollectionStats>, SazidError> {
+    self.store.collection_stats().await
+  }
+
+  // removes a collection, and unless keep_sources is set the sources in no other collection, returns how many
+  // sources were deleted
+  pub async fn delete_collection(&mut self, collection: &str, keep_sources: bool) -> Result<usize, SazidError> {
+    let only_in_collection = match keep_sources {
+      true => vec![],
+      false => self.list_ingested_sources().await?.into_iter().filter(|s| s.collections == collection).collect(),
+    };
+    for source in &only_in_collection {
+      self.delete_source(source.id).await?;
+    }
+    self.store.delete_collection(collection).await?;
+    self.store.flush().await?;
+    Ok(only_in_collection.len())
+  }
+
+  // embeds the stored chunks of the sources in collection, or of the sources in no collection, again with the model
+  // configured for into, or for the collection when into is none, and moves them into that collection. sources
+  // already embedded with the model are skipped, so an interrupted run resumes where it stopped. returns how many
+  // sources were re-embedded
+  pub async fn reembed_collection(
+    &mut self,
+    collection: Option<&str>,
+    into: Option<&str>,
+    mut progress: impl FnMut(String),
+  ) -> Result<usize, SazidError> {
+    let target = into.or(collection);
+    let model = self.model_for(target);
+    let current_model = std::mem::replace(&mut self.model, model);
+    let result = self.reembed_sources(collection, target, &mut progress).await;
+    self.model = current_model;
+    result
+  }
+
+  async fn reembed_sources(
+    &mut self,
+    collection: Option<&str>,
+    target: Option<&str>,
+    progress: &mut impl FnMut(String),
+  ) -> Result<usize, SazidError> {
+    let model = self.model.model_string();
+    let sources = self
+      .list_ingested_sources()
+      .await?
+      .into_iter()
+      .filter(|source| match collection {
+        Some(collection) => source.collections.split(',').any(|c| c == collection),
+        None => source.collections.is_empty(),
+      })
+      .collect::<Vec<IngestedSource>>();
+    let pending = sources.iter().filter(|source| source.embedding_model != model).collect::<Vec<_>>();
+    progress(format!("{} of {} sources to re-embed with {}", pending.len(), sources.len(), model));
+    for (i, source) in pending.iter().enumerate() {
+      let stored = self.store.source_pages(source.id).await?;
+      let chunks = stored.iter().map(|page| page.content.clone()).collect::<Vec<String>>();
+      let vectors = self
+        .embed_chunks(&source.filepath, &chunks)
+        .await?
+        .into_iter()
+        .collect::<Result<Vec<Vector>, String>>()
+        .map_err(|e| SazidError::Other(format!("failed to re-embed {}: {}", source.filepath, e)))?;
+      let transformations = vec![format!("re-embedded from the stored chunk with {}", model)];
+      let pages = stored
+        .iter()
+        .zip(vectors)
+        .enumerate()
+        .map(|(n, (page, vector))| {
+          // the heading path of a chunk is kept from its stored provenance
+          let section = ChunkProvenance::from_json(&page.provenance).and_then(|provenance| provenance.section);
+          let transformations = transformations.clone();
+          self.source_page(&source.filepath, &source.checksum, n, &page.content, vector, transformations, section)
+        })
+        .collect::<Vec<InsertablePage>>();
+      // the embeddings are replaced only once every chunk is embedded, a source with the same checksum keeps its id
+      self.store.delete_pages(source.id).await?;
+      let embedding = InsertableFileEmbedding {
+        filepath: source.filepath.clone(),
+        checksum: source.checksum.clone(),
+        version: source.version,
+        embedding_model: model.clone(),
+        embedding_dimensions: self.model.dimensions() as i32,
+      };
+      let source_id = self.store.add_source(&embedding).await?;
+      self.store.add_pages(source_id, pages.iter().collect()).await?;
+      if target != collection {
+        self.store.remove_source_from_collections(source_id).await?;
+        let kept = source.collections.split(',').filter(|c| !c.is_empty() && Some(*c) != collection);
+        for c in kept.chain(target) {
+          self.store.add_source_to_collection(source_id, c).await?;
+        }
+      }
+      self.store.flush().await?;
+      progress(format!("re-embedded {}/{} {} ({} chunks)", i + 1, pending.len(), source.filepath, chunks.len()));
+    }
+    Ok(pending.len())
+  }
+
+  // moves a source into a single collection, or out of all collections when collection is empty
+  pub async fn set_source_collection(&mut self, source_id: i64, collection: &str) -> Result<(), SazidError> {
+    self.store.remove_source_from_collections(source_id).await?;
+    if !collection.is_empty() {
+      self.store.add_source_to_collection(source_id, collection).await?;
+    }
+    self.store.flush().await
+  }
+
+  // the provenance of every stored chunk as json lines, chunks whose entry no longer verifies are marked unverified
+  pub async fn export_provenance(&mut self) -> Result<String, SazidError> {
+    let pages = self.store.all_pages().await?;
+    let lines = pages
+      .iter()
+      .filter_map(|page| {
+        let provenance = ChunkProvenance::from_json(&page.provenance)?;
+        let mut entry = serde_json::to_value(&provenance).ok()?;
+        entry["verified"] = serde_json::Value::Bool(provenance.verify(&page.content));
+        Some(entry.to_string())
+      })
+      .collect::<Vec<String>>();
+    Ok(lines.join("\n"))
   }
 }
@@ -0,0 +1,168 @@
+use std::{
+  fs::{self, File},
+  io::{self, Read},
+  path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::app::{
+  errors::SazidError,
+  summarize::{collect_documents, Document, MAX_FILE_BYTES},
+};
+
+// between the path of an archive and the path of a file in it, as in docs.zip!/guide/install.md
+pub const ARCHIVE_MEMBER_SEPARATOR: &str = "!/";
+
+// extraction stops once this many bytes are written, so that a small archive can't fill the disk
+const MAX_EXTRACTED_BYTES: u64 = 256 * 1024 * 1024;
+
+// an archive whose files are ingested without unpacking it by hand
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+  Zip,
+  Tar,
+  TarGz,
+}
+
+impl ArchiveFormat {
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+      Some(ArchiveFormat::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+      Some(ArchiveFormat::TarGz)
+    } else if name.ends_with(".tar") {
+      Some(ArchiveFormat::Tar)
+    } else {
+      None
+    }
+  }
+}
+
+// the name a file in an archive is stored under, keeping its path inside the archive
+pub fn archive_member_name(archive: &Path, member: &str) -> String {
+  format!("{}{}{}", archive.display(), ARCHIVE_MEMBER_SEPARATOR, member)
+}
+
+// the path of a member relative to the extraction directory, None for absolute paths and paths with .. that would be
+// written outside it
+fn member_path(name: &Path) -> Option<PathBuf> {
+  let mut path = PathBuf::new();
+  for component in name.components() {
+    match component {
+      Component::Normal(part) => path.push(part),
+      Component::CurDir => {},
+      _ => return None,
+    }
+  }
+  (!path.as_os_str().is_empty()).then_some(path)
+}
+
+// writes at most one byte more than MAX_FILE_BYTES, so that a member whose header understates its size is still
+// skipped as too large by collect_documents
+fn write_member(reader: &mut impl Read, path: &Path, extracted: u64) -> Result<u64, SazidError> {
+  if extracted > MAX_EXTRACTED_BYTES {
+    return Err(SazidError::Other(format!("archive expands to more than {} bytes", MAX_EXTRACTED_BYTES)));
+  }
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let mut file = File::create(path)?;
+  Ok(io::copy(&mut reader.take(MAX_FILE_BYTES + 1), &mut file)?)
+}
+
+fn extract_zip(archive: &Path, dir: &Path) -> Result<(), SazidError> {
+  let zip_error = |e: zip::result::ZipError| SazidError::Other(format!("zip: {}", e));
+  let mut zip = zip::ZipArchive::new(File::open(archive)?).map_err(zip_error)?;
+  let mut extracted = 0;
+  for i in 0..zip.len() {
+    let mut member = zip.by_index(i).map_err(zip_error)?;
+    if member.is_dir() || member.size() > MAX_FILE_BYTES {
+      continue;
+    }
+    if let Some(path) = member_path(Path::new(member.name())) {
+      extracted += write_member(&mut member, &dir.join(path), extracted)?;
+    }
+  }
+  Ok(())
+}
+
+// only regular files are written, links and devices in the archive are skipped
+fn extract_tar(reader: impl Read, dir: &Path) -> Result<(), SazidError> {
+  let mut archive = tar::Archive::new(reader);
+  let mut extracted = 0;
+  for member in archive.entries()? {
+    let mut member = member?;
+    if !member.header().entry_type().is_file() || member.size() > MAX_FILE_BYTES {
+      continue;
+    }
+    let path = member_path(&member.path()?);
+    if let Some(path) = path {
+      extracted += write_member(&mut member, &dir.join(path), extracted)?;
+    }
+  }
+  Ok(())
+}
+
+// the text files of an archive, named by their path inside it, read from a temporary directory that is removed after
+pub fn archive_documents(archive: &Path, format: ArchiveFormat) -> Result<Vec<Document>, SazidError> {
+  let dir = tempfile::tempdir()?;
+  match format {
+    ArchiveFormat::Zip => extract_zip(archive, dir.path())?,
+    ArchiveFormat::Tar => extract_tar(File::open(archive)?, dir.path())?,
+    ArchiveFormat::TarGz => extract_tar(GzDecoder::new(File::open(archive)?), dir.path())?,
+  }
+  Ok(collect_documents(dir.path()))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::GzEncoder, Compression};
+
+  use super::*;
+
+  #[test]
+  fn test_zip_documents() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("docs.zip");
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    for (name, content) in
+      [("guide/install.md", "# Install"), ("../escape.md", "outside"), (".git/config", "hidden"), ("a.txt", "a")]
+    {
+      zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+      zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    assert_eq!(ArchiveFormat::from_path(&path), Some(ArchiveFormat::Zip));
+    let documents = archive_documents(&path, ArchiveFormat::Zip).unwrap();
+    let names = documents.iter().map(|document| document.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["a.txt", "guide/install.md"]);
+    assert_eq!(documents[1].content, "# Install");
+    assert_eq!(archive_member_name(&path, &documents[1].name), format!("{}!/guide/install.md", path.display()));
+  }
+
+  #[test]
+  fn test_tar_gz_documents() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.tar.gz");
+    let mut tar = tar::Builder::new(GzEncoder::new(File::create(&path).unwrap(), Compression::default()));
+    let content = b"remember the milk";
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, "./notes/today.txt", &content[..]).unwrap();
+    tar.into_inner().unwrap().finish().unwrap();
+
+    assert_eq!(ArchiveFormat::from_path(Path::new("NOTES.TGZ")), Some(ArchiveFormat::TarGz));
+    let documents = archive_documents(&path, ArchiveFormat::TarGz).unwrap();
+    assert_eq!(
+      documents,
+      vec![Document { name: "notes/today.txt".to_string(), content: "remember the milk".to_string() }]
+    );
+  }
+}
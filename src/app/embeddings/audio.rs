@@ -0,0 +1,169 @@
+use std::path::Path;
+
+use async_openai::config::Config;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::Deserialize;
+
+use crate::app::{
+  errors::SazidError,
+  functions::argument_validation::count_tokens,
+  offline::{ensure_online, is_local_api_base},
+  tools::chunkifier::chunkify_text,
+};
+
+use super::EmbeddingsManager;
+
+pub const TRANSCRIPTION_MODEL: &str = "whisper-1";
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "m4a"];
+
+// the largest file the transcriptions endpoint accepts
+const MAX_AUDIO_BYTES: u64 = 25 * 1024 * 1024;
+
+lazy_static! {
+  // a line of a transcript, as in [00:01:05-00:01:09] text
+  static ref TRANSCRIPT_LINE: Regex = Regex::new(r"^\[(\d+:\d\d:\d\d)-(\d+:\d\d:\d\d)\] ?(.*)$").unwrap();
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .map_or(false, |extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TranscriptSegment {
+  // seconds from the start of the recording
+  pub start: f64,
+  pub end: f64,
+  pub text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct VerboseTranscription {
+  #[serde(default)]
+  segments: Vec<TranscriptSegment>,
+}
+
+pub fn format_timestamp(seconds: f64) -> String {
+  let seconds = seconds.max(0.0) as u64;
+  format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// the text stored for a recording, one line per segment starting with its time range, so that the timestamps survive
+// hooks and re-embedding and can be searched for
+pub fn transcript_text(segments: &[TranscriptSegment]) -> String {
+  segments
+    .iter()
+    .map(|s| format!("[{}-{}] {}", format_timestamp(s.start), format_timestamp(s.end), s.text.trim()))
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+// consecutive lines of a transcript grouped into chunks of at most tokens_per_chunk tokens, with the time range each
+// covers, as in 00:01:05-00:03:10
+pub fn transcript_chunks(content: &str, tokens_per_chunk: usize) -> Vec<(String, Option<String>)> {
+  let mut chunks = vec![];
+  // the lines of the chunk being built, their tokens and the first and last timestamps
+  let mut lines: Vec<&str> = vec![];
+  let mut tokens = 0;
+  let mut range: Option<(String, String)> = None;
+  let label = |range: &Option<(String, String)>| range.as_ref().map(|(start, end)| format!("{}-{}", start, end));
+  for line in content.lines().filter(|line| !line.trim().is_empty()) {
+    let line_tokens = count_tokens(line) + 1;
+    if !lines.is_empty() && tokens + line_tokens > tokens_per_chunk {
+      chunks.push((lines.join("\n"), label(&range)));
+      lines.clear();
+      tokens = 0;
+      range = None;
+    }
+    if let Some(captures) = TRANSCRIPT_LINE.captures(line) {
+      let start = range.take().map_or(captures[1].to_string(), |(start, _)| start);
+      range = Some((start, captures[2].to_string()));
+    }
+    // a segment too long for a chunk on its own is split, every part keeps its time range
+    if line_tokens > tokens_per_chunk {
+      chunks.extend(chunkify_text(line, tokens_per_chunk).into_iter().map(|part| (part, label(&range))));
+      range = None;
+      continue;
+    }
+    lines.push(line);
+    tokens += line_tokens;
+  }
+  if !lines.is_empty() {
+    chunks.push((lines.join("\n"), label(&range)));
+  }
+  chunks
+}
+
+impl EmbeddingsManager {
+  // the transcript of a recording from the transcriptions endpoint of the chat api
+  pub async fn transcribe(&self, path: &Path) -> Result<String, SazidError> {
+    if !is_local_api_base(self.openai_config.api_base()) {
+      ensure_online(self.offline, "transcribing audio")?;
+    }
+    if path.metadata()?.len() > MAX_AUDIO_BYTES {
+      return Err(SazidError::Other(format!(
+        "{} is larger than the {} bytes that can be transcribed, split it first",
+        path.display(),
+        MAX_AUDIO_BYTES
+      )));
+    }
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let form = reqwest::multipart::Form::new()
+      .text("model", TRANSCRIPTION_MODEL)
+      .text("response_format", "verbose_json")
+      .part("file", reqwest::multipart::Part::bytes(tokio::fs::read(path).await?).file_name(file_name));
+    let response = reqwest::Client::new()
+      .post(self.openai_config.url("/audio/transcriptions"))
+      .headers(self.openai_config.headers())
+      .multipart(form)
+      .send()
+      .await
+      .map_err(|e| SazidError::Other(format!("failed to transcribe {}: {}", path.display(), e)))?
+      .error_for_status()
+      .map_err(|e| SazidError::Other(format!("failed to transcribe {}: {}", path.display(), e)))?;
+    let transcription = response
+      .json::<VerboseTranscription>()
+      .await
+      .map_err(|e| SazidError::Other(format!("failed to parse the transcript of {}: {}", path.display(), e)))?;
+    Ok(transcript_text(&transcription.segments))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_transcript_chunks() {
+    let segment = |start, end, text: &str| TranscriptSegment { start, end, text: text.to_string() };
+    let segments = vec![
+      segment(0.0, 4.2, " Welcome to the weekly sync."),
+      segment(4.2, 65.9, " First, the release is on track."),
+      segment(3725.0, 3731.5, " Any other business?"),
+    ];
+    let content = transcript_text(&segments);
+    assert_eq!(
+      content,
+      "[00:00:00-00:00:04] Welcome to the weekly sync.\n[00:00:04-00:01:05] First, the release is on track.\n\
+       [01:02:05-01:02:11] Any other business?"
+    );
+    assert!(is_audio_file(Path::new("standup.M4A")));
+
+    let chunks = transcript_chunks(&content, 1000);
+    assert_eq!(chunks, vec![(content.clone(), Some("00:00:00-01:02:11".to_string()))]);
+
+    // room for the first two lines only
+    let budget = content.lines().take(2).map(|line| count_tokens(line) + 1).sum();
+    let chunks = transcript_chunks(&content, budget);
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].1.as_deref(), Some("00:00:00-00:01:05"));
+    assert_eq!(
+      chunks[1],
+      ("[01:02:05-01:02:11] Any other business?".to_string(), Some("01:02:05-01:02:11".to_string()))
+    );
+  }
+}
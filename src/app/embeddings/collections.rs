@@ -0,0 +1,90 @@
+use std::io::IsTerminal;
+
+use crate::{app::errors::SazidError, cli::IndexCommand, config::Config};
+
+use super::{configured_embedding_model, embeddings_models::EmbeddingModel, EmbeddingsManager};
+
+// collections are stored joined with commas, so a name can't contain one
+fn validate_collection_name(collection: &str) -> Result<(), SazidError> {
+  match collection.trim() {
+    "" => Err(SazidError::Other("a collection needs a name".to_string())),
+    name if name.contains(',') => Err(SazidError::Other(format!("collection {} can't contain a comma", name))),
+    _ => Ok(()),
+  }
+}
+
+// the index subcommands, which keep separate named corpora such as a work repository, notes and papers
+pub async fn run_index(command: &IndexCommand, config: &Config) -> Result<(), SazidError> {
+  let default_model = EmbeddingModel::Ada002(config.session_config.openai_config.clone());
+  let mut manager = EmbeddingsManager::init(config.clone(), default_model.clone()).await?;
+  match command {
+    IndexCommand::Create { collection } => {
+      validate_collection_name(collection)?;
+      match manager.create_collection(collection).await? {
+        true => println!(
+          "created collection {}, ingest into it with --textfile <path> --collection {}",
+          collection, collection
+        ),
+        false => println!("collection {} already exists", collection),
+      }
+    },
+    IndexCommand::List => {
+      let collections = manager.collection_stats().await?;
+      if collections.is_empty() {
+        eprintln!("no collections, create one with `sazid index create <collection>`");
+      }
+      collections.iter().for_each(|collection| println!("{}", collection));
+    },
+    IndexCommand::Stats { collection } => {
+      let stats = manager.collection_stats().await?.into_iter().find(|c| &c.name == collection);
+      let stats = stats.ok_or_else(|| SazidError::Other(format!("no collection named {}", collection)))?;
+      println!("{}", stats);
+      let model = configured_embedding_model(config, Some(collection), default_model).model_string();
+      println!("new sources are embedded with {}", model);
+      if stats.embedding_models.split(',').any(|m| !m.is_empty() && m != model) {
+        println!(
+          "some sources were embedded with another model, `sazid index reembed {}` embeds them again",
+          collection
+        );
+      }
+      for source in manager.list_ingested_sources().await? {
+        if !source.superseded && source.collections.split(',').any(|c| c == collection) {
+          println!("  {} -- {} chunks", source.filepath, source.chunk_count);
+        }
+      }
+    },
+    IndexCommand::Reembed { collection, into } => {
+      if let Some(into) = into {
+        validate_collection_name(into)?;
+      }
+      let reembedded = manager
+        .reembed_collection(collection.as_deref(), into.as_deref(), |progress| eprintln!("{}", progress))
+        .await?;
+      match into.as_ref().or(collection.as_ref()) {
+        Some(target) => println!("re-embedded {} sources into collection {}", reembedded, target),
+        None => println!("re-embedded {} sources", reembedded),
+      }
+    },
+    IndexCommand::Delete { collection, keep_sources, yes } => {
+      let stats = manager.collection_stats().await?.into_iter().find(|c| &c.name == collection);
+      let stats = stats.ok_or_else(|| SazidError::Other(format!("no collection named {}", collection)))?;
+      let prompt = match keep_sources {
+        true => format!("Delete collection {}, keeping its {} sources?", collection, stats.sources),
+        false => format!("Delete collection {} with the sources in no other collection?", collection),
+      };
+      let confirmed = match (yes, std::io::stdin().is_terminal()) {
+        (true, _) => true,
+        (false, true) => dialoguer::Confirm::new().with_prompt(prompt).default(false).interact()?,
+        (false, false) => {
+          eprintln!("run with --yes to delete it");
+          false
+        },
+      };
+      if confirmed {
+        let deleted = manager.delete_collection(collection, *keep_sources).await?;
+        println!("deleted collection {} and {} sources", collection, deleted);
+      }
+    },
+  }
+  Ok(())
+}
@@ -0,0 +1,72 @@
+use crate::app::{
+  database::{DatabaseConnection, TableSchema},
+  errors::SazidError,
+  summarize::Document,
+};
+
+use super::{ingest::IngestReport, EmbeddingsManager};
+
+// the name a table is stored under, so that the model can tell query_database which database to query
+pub fn table_document_name(database: &str, table: &str) -> String {
+  format!("database {}/{}", database, table)
+}
+
+// one document for each table, with its columns, their types and the comments on them
+pub fn schema_documents(database: &str, tables: &[TableSchema]) -> Vec<Document> {
+  tables
+    .iter()
+    .map(|table| Document {
+      name: table_document_name(database, &table.name),
+      content: format!("database {}, query it with query_database\n{}", database, table),
+    })
+    .collect()
+}
+
+impl EmbeddingsManager {
+  // ingests the schema of a configured database, tables that didn't change since they were ingested are skipped
+  pub async fn ingest_database(&mut self, name: &str) -> Result<IngestReport, SazidError> {
+    let database = self
+      .databases
+      .get(name)
+      .cloned()
+      .ok_or_else(|| SazidError::Other(format!("no database named {} is configured", name)))?;
+    // errors are sent back as text, since a SazidError can't be sent between threads
+    let tables = tokio::task::spawn_blocking(move || {
+      DatabaseConnection::connect(&database).and_then(|mut connection| connection.schema()).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| SazidError::Other(e.to_string()))?
+    .map_err(SazidError::Other)?;
+    self.ingest_documents(schema_documents(name, &tables)).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::database::ColumnSchema;
+
+  #[test]
+  fn test_schema_documents() {
+    let tables = vec![TableSchema {
+      name: "public.orders".to_string(),
+      comment: Some("one row per checkout".to_string()),
+      columns: vec![
+        ColumnSchema { name: "id".to_string(), data_type: "bigint".to_string(), nullable: false, comment: None },
+        ColumnSchema {
+          name: "total".to_string(),
+          data_type: "numeric".to_string(),
+          nullable: true,
+          comment: Some("in cents".to_string()),
+        },
+      ],
+    }];
+    let documents = schema_documents("shop", &tables);
+    assert_eq!(documents[0].name, "database shop/public.orders");
+    assert_eq!(
+      documents[0].content,
+      "database shop, query it with query_database\ntable public.orders -- one row per checkout\n  id bigint not null\n  \
+       total numeric -- in cents"
+    );
+  }
+}
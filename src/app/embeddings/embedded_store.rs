@@ -0,0 +1,506 @@
+use std::{
+  collections::{BTreeMap, BTreeSet, HashSet},
+  path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use pgvector::Vector;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::{
+  consts::{data_path, EMBEDDED_VECTOR_STORE},
+  encryption,
+  errors::SazidError,
+};
+
+use super::{
+  hybrid::bm25_scores,
+  ingest::chunk_hash,
+  store::{SearchFilter, VectorStore},
+  types::{CollectionPage, CollectionStats, EmbeddingPage, IngestedSource, InsertableFileEmbedding, InsertablePage},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredSource {
+  filepath: String,
+  checksum: String,
+  version: i32,
+  superseded: bool,
+  // seconds since the unix epoch
+  updated_at: i64,
+  collections: BTreeSet<String>,
+  embedding_model: String,
+  embedding_dimensions: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredPage {
+  id: i64,
+  source_id: i64,
+  page_number: i32,
+  content: String,
+  checksum: String,
+  embedding: Vec<f32>,
+  provenance: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct StoreData {
+  next_id: i64,
+  sources: BTreeMap<i64, StoredSource>,
+  pages: Vec<StoredPage>,
+  // collections created before anything was ingested into them
+  collections: BTreeSet<String>,
+}
+
+impl StoreData {
+  fn next_id(&mut self) -> i64 {
+    self.next_id += 1;
+    self.next_id
+  }
+}
+
+// keeps every chunk in memory and searches them exhaustively, which stays fast for the tens of thousands of chunks
+// of a personal corpus, changes are written to a single file when the store is flushed
+pub struct EmbeddedVectorStore {
+  // none for a store that is never written
+  path: Option<PathBuf>,
+  data: StoreData,
+  dirty: bool,
+}
+
+fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+  let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+  let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+  match norms {
+    norms if norms > 0.0 => 1.0 - dot / norms,
+    _ => 1.0,
+  }
+}
+
+fn store_error(path: &Path, e: bincode::Error) -> SazidError {
+  SazidError::Other(format!("failed to read or write the vector store {}: {}", path.display(), e))
+}
+
+impl EmbeddedVectorStore {
+  pub fn default_path() -> Result<PathBuf, SazidError> {
+    Ok(data_path(EMBEDDED_VECTOR_STORE).ok_or(SazidError::Other("home directory not found".to_string()))?)
+  }
+
+  // a missing file starts an empty store, an unreadable one is an error so that its chunks are not overwritten
+  pub fn open(path: &Path) -> Result<Self, SazidError> {
+    let data = match encryption::read(path) {
+      Ok(bytes) => bincode::deserialize(&bytes).map_err(|e| store_error(path, e))?,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoreData::default(),
+      Err(e) => return Err(e.into()),
+    };
+    Ok(EmbeddedVectorStore { path: Some(path.to_path_buf()), data, dirty: false })
+  }
+
+  // an empty store that is discarded when it is dropped
+  pub fn in_memory() -> Self {
+    EmbeddedVectorStore { path: None, data: StoreData::default(), dirty: false }
+  }
+
+  fn embedding_page(page: &StoredPage) -> EmbeddingPage {
+    EmbeddingPage::new(
+      page.id,
+      page.source_id,
+      page.page_number,
+      page.content.clone(),
+      page.checksum.clone(),
+      Vector::from(page.embedding.clone()),
+      page.provenance.clone(),
+    )
+  }
+
+  fn matches(&self, page: &StoredPage, filter: &SearchFilter) -> bool {
+    match self.data.sources.get(&page.source_id) {
+      Some(source) => {
+        (filter.include_versions || !source.superseded)
+          && filter.collection.as_ref().map(|c| source.collections.contains(c)).unwrap_or(true)
+      },
+      None => false,
+    }
+  }
+
+  fn source_pages_in_order(&self, source_id: i64) -> Vec<&StoredPage> {
+    let mut pages = self.data.pages.iter().filter(|page| page.source_id == source_id).collect::<Vec<_>>();
+    pages.sort_by_key(|page| page.page_number);
+    pages
+  }
+}
+
+#[async_trait]
+impl VectorStore for EmbeddedVectorStore {
+  async fn add_source(&mut self, source: &InsertableFileEmbedding) -> Result<i64, SazidError> {
+    self.dirty = true;
+    let updated_at = chrono::Utc::now().timestamp();
+    if let Some((id, stored)) = self.data.sources.iter_mut().find(|(_, stored)| stored.checksum == source.checksum) {
+      stored.filepath = source.filepath.clone();
+      stored.version = source.version;
+      stored.updated_at = updated_at;
+      stored.embedding_model = source.embedding_model.clone();
+      stored.embedding_dimensions = source.embedding_dimensions;
+      return Ok(*id);
+    }
+    let id = self.data.next_id();
+    let stored = StoredSource {
+      filepath: source.filepath.clone(),
+      checksum: source.checksum.clone(),
+      version: source.version,
+      superseded: false,
+      updated_at,
+      collections: BTreeSet::new(),
+      embedding_model: source.embedding_model.clone(),
+      embedding_dimensions: source.embedding_dimensions,
+    };
+    self.data.sources.insert(id, stored);
+    Ok(id)
+  }
+
+  async fn add_pages(&mut self, source_id: i64, pages: Vec<&InsertablePage>) -> Result<(), SazidError> {
+    for page in pages {
+      if self.data.pages.iter().any(|stored| stored.checksum == page.checksum) {
+        continue;
+      }
+      let id = self.data.next_id();
+      self.data.pages.push(StoredPage {
+        id,
+        source_id,
+        page_number: page.page_number,
+        content: page.content.clone(),
+        checksum: page.checksum.clone(),
+        embedding: page.embedding.to_vec(),
+        provenance: page.provenance.clone(),
+      });
+      self.dirty = true;
+    }
+    Ok(())
+  }
+
+  async fn source_version(&mut self, source_id: i64) -> Result<Option<i32>, SazidError> {
+    Ok(self.data.sources.get(&source_id).map(|source| source.version))
+  }
+
+  async fn mark_superseded(&mut self, source_id: i64) -> Result<(), SazidError> {
+    if let Some(source) = self.data.sources.get_mut(&source_id) {
+      source.superseded = true;
+      self.dirty = true;
+    }
+    Ok(())
+  }
+
+  async fn source_pages(&mut self, source_id: i64) -> Result<Vec<EmbeddingPage>, SazidError> {
+    Ok(self.source_pages_in_order(source_id).into_iter().map(Self::embedding_page).collect())
+  }
+
+  async fn list_sources(&mut self) -> Result<Vec<IngestedSource>, SazidError> {
+    let mut sources = self
+      .data
+      .sources
+      .iter()
+      .map(|(id, source)| IngestedSource {
+        id: *id,
+        filepath: source.filepath.clone(),
+        checksum: source.checksum.clone(),
+        updated_at: source.updated_at,
+        chunk_count: self.data.pages.iter().filter(|page| page.source_id == *id).count() as i64,
+        collections: source.collections.iter().cloned().collect::<Vec<String>>().join(","),
+        version: source.version,
+        superseded: source.superseded,
+        embedding_model: source.embedding_model.clone(),
+      })
+      .collect::<Vec<IngestedSource>>();
+    sources.sort_by(|a, b| a.filepath.cmp(&b.filepath));
+    Ok(sources)
+  }
+
+  async fn collection_pages(&mut self, collection: &str) -> Result<Vec<CollectionPage>, SazidError> {
+    let mut sources = self
+      .data
+      .sources
+      .iter()
+      .filter(|(_, source)| !source.superseded && source.collections.contains(collection))
+      .collect::<Vec<_>>();
+    sources.sort_by(|(_, a), (_, b)| a.filepath.cmp(&b.filepath));
+    Ok(
+      sources
+        .into_iter()
+        .flat_map(|(id, source)| {
+          self
+            .source_pages_in_order(*id)
+            .into_iter()
+            .map(|page| CollectionPage { filepath: source.filepath.clone(), content: page.content.clone() })
+        })
+        .collect(),
+    )
+  }
+
+  async fn all_pages(&mut self) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let mut pages = self.data.pages.iter().collect::<Vec<_>>();
+    pages.sort_by_key(|page| (page.source_id, page.page_number));
+    Ok(pages.into_iter().map(Self::embedding_page).collect())
+  }
+
+  async fn stored_chunk_hashes(
+    &mut self,
+    hashes: &[String],
+    filepath: &str,
+    collection: Option<&str>,
+  ) -> Result<HashSet<String>, SazidError> {
+    let hashes = hashes.iter().collect::<HashSet<_>>();
+    Ok(
+      self
+        .data
+        .pages
+        .iter()
+        .filter(|page| match self.data.sources.get(&page.source_id) {
+          Some(source) => {
+            !source.superseded
+              && source.filepath != filepath
+              && collection.map_or(true, |c| source.collections.contains(c))
+          },
+          None => false,
+        })
+        .map(|page| chunk_hash(&page.content))
+        .filter(|hash| hashes.contains(hash))
+        .collect(),
+    )
+  }
+
+  async fn similar_pages(
+    &mut self,
+    vector: &Vector,
+    limit: i64,
+    filter: &SearchFilter,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let query = vector.to_vec();
+    let mut scored = self
+      .data
+      .pages
+      .iter()
+      .filter(|page| self.matches(page, filter))
+      .map(|page| (cosine_distance(&query, &page.embedding), page))
+      .collect::<Vec<_>>();
+    scored.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    Ok(scored.into_iter().take(limit.max(0) as usize).map(|(_, page)| Self::embedding_page(page)).collect())
+  }
+
+  async fn keyword_pages(
+    &mut self,
+    query: &str,
+    limit: i64,
+    filter: &SearchFilter,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let pages = self.data.pages.iter().filter(|page| self.matches(page, filter)).collect::<Vec<&StoredPage>>();
+    let mut scores = bm25_scores(query, pages.iter().enumerate().map(|(i, page)| (i as i64, page.content.as_str())));
+    scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    Ok(scores.into_iter().take(limit.max(0) as usize).map(|(i, _)| Self::embedding_page(pages[i as usize])).collect())
+  }
+
+  async fn embedding_models(&mut self, collection: Option<&str>) -> Result<Vec<(String, i32)>, SazidError> {
+    let models = self
+      .data
+      .sources
+      .values()
+      .filter(|source| !source.embedding_model.is_empty())
+      .filter(|source| collection.map(|c| source.collections.contains(c)).unwrap_or(true))
+      .map(|source| (source.embedding_model.clone(), source.embedding_dimensions))
+      .collect::<BTreeSet<(String, i32)>>();
+    Ok(models.into_iter().collect())
+  }
+
+  async fn delete_pages(&mut self, source_id: i64) -> Result<(), SazidError> {
+    self.data.pages.retain(|page| page.source_id != source_id);
+    self.dirty = true;
+    Ok(())
+  }
+
+  async fn delete_source(&mut self, source_id: i64) -> Result<(), SazidError> {
+    self.delete_pages(source_id).await?;
+    self.data.sources.remove(&source_id);
+    self.dirty = true;
+    Ok(())
+  }
+
+  async fn add_source_to_collection(&mut self, source_id: i64, collection: &str) -> Result<(), SazidError> {
+    if let Some(source) = self.data.sources.get_mut(&source_id) {
+      source.collections.insert(collection.to_string());
+      self.dirty = true;
+    }
+    Ok(())
+  }
+
+  async fn remove_source_from_collections(&mut self, source_id: i64) -> Result<(), SazidError> {
+    if let Some(source) = self.data.sources.get_mut(&source_id) {
+      source.collections.clear();
+      self.dirty = true;
+    }
+    Ok(())
+  }
+
+  async fn create_collection(&mut self, collection: &str) -> Result<bool, SazidError> {
+    let exists = self.data.sources.values().any(|source| source.collections.contains(collection));
+    let created = self.data.collections.insert(collection.to_string()) && !exists;
+    self.dirty = true;
+    Ok(created)
+  }
+
+  async fn collection_stats(&mut self) -> Result<Vec<CollectionStats>, SazidError> {
+    let mut collections = self.data.collections.clone();
+    collections.extend(self.data.sources.values().flat_map(|source| source.collections.iter().cloned()));
+    Ok(
+      collections
+        .into_iter()
+        .map(|name| {
+          let sources = self
+            .data
+            .sources
+            .iter()
+            .filter(|(_, source)| !source.superseded && source.collections.contains(&name))
+            .collect::<Vec<_>>();
+          let pages = self
+            .data
+            .pages
+            .iter()
+            .filter(|page| sources.iter().any(|(id, _)| **id == page.source_id))
+            .collect::<Vec<_>>();
+          let models = sources
+            .iter()
+            .map(|(_, source)| source.embedding_model.clone())
+            .filter(|model| !model.is_empty())
+            .collect::<BTreeSet<String>>();
+          CollectionStats {
+            sources: sources.len() as i64,
+            chunks: pages.len() as i64,
+            characters: pages.iter().map(|page| page.content.chars().count() as i64).sum(),
+            embedding_models: models.into_iter().collect::<Vec<String>>().join(","),
+            updated_at: sources.iter().map(|(_, source)| source.updated_at).max(),
+            name,
+          }
+        })
+        .collect(),
+    )
+  }
+
+  async fn delete_collection(&mut self, collection: &str) -> Result<(), SazidError> {
+    self.data.collections.remove(collection);
+    self.data.sources.values_mut().for_each(|source| {
+      source.collections.remove(collection);
+    });
+    self.dirty = true;
+    Ok(())
+  }
+
+  // written to a temporary file first so that an interruption can't leave a truncated store
+  async fn flush(&mut self) -> Result<(), SazidError> {
+    let Some(path) = self.path.as_ref().filter(|_| self.dirty) else {
+      return Ok(());
+    };
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let bytes = bincode::serialize(&self.data).map_err(|e| store_error(path, e))?;
+    let tmp_path = path.with_extension("bin.tmp");
+    encryption::write(&tmp_path, bytes)?;
+    std::fs::rename(tmp_path, path)?;
+    self.dirty = false;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::embeddings::types::EmbeddingStatus;
+
+  fn page(checksum: &str, page_number: i32, embedding: Vec<f32>) -> InsertablePage {
+    InsertablePage {
+      content: format!("page {}", page_number),
+      page_number,
+      checksum: checksum.to_string(),
+      embedding: Vector::from(embedding),
+      provenance: String::new(),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_embedded_store_searches_and_persists() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vector_store.bin");
+    let mut store = EmbeddedVectorStore::open(&path).unwrap();
+    let source = |filepath: &str, checksum: &str| InsertableFileEmbedding {
+      filepath: filepath.to_string(),
+      checksum: checksum.to_string(),
+      version: 1,
+      embedding_model: "text-embedding-ada-002".to_string(),
+      embedding_dimensions: 2,
+    };
+    let notes = source("notes.md", "a");
+    let notes_id = store.add_source(&notes).await.unwrap();
+    let (near, far) = (page("p1", 1, vec![1.0, 0.0]), page("p0", 0, vec![0.0, 1.0]));
+    store.add_pages(notes_id, vec![&near, &far, &near]).await.unwrap();
+    store.add_source_to_collection(notes_id, "docs").await.unwrap();
+    let old = source("old.md", "b");
+    let old_id = store.add_source(&old).await.unwrap();
+    store.add_pages(old_id, vec![&page("p2", 0, vec![1.0, 0.1])]).await.unwrap();
+    store.mark_superseded(old_id).await.unwrap();
+    store.flush().await.unwrap();
+    assert_eq!(store.embedding_models(None).await.unwrap(), vec![("text-embedding-ada-002".to_string(), 2)]);
+    assert!(store.embedding_models(Some("other")).await.unwrap().is_empty());
+
+    let mut store = EmbeddedVectorStore::open(&path).unwrap();
+    let query = Vector::from(vec![1.0, 0.0]);
+    let results = store.similar_pages(&query, 10, &SearchFilter::default()).await.unwrap();
+    assert_eq!(results.iter().map(|page| page.content.as_str()).collect::<Vec<_>>(), vec!["page 1", "page 0"]);
+    let with_versions = SearchFilter { include_versions: true, collection: None };
+    assert_eq!(store.similar_pages(&query, 10, &with_versions).await.unwrap().len(), 3);
+    let other_collection = SearchFilter { include_versions: true, collection: Some("other".to_string()) };
+    assert!(store.similar_pages(&query, 10, &other_collection).await.unwrap().is_empty());
+    let pages = store.source_pages(notes_id).await.unwrap();
+    assert_eq!(pages.iter().map(|page| page.content.as_str()).collect::<Vec<_>>(), vec!["page 0", "page 1"]);
+    assert_eq!(store.collection_pages("docs").await.unwrap().len(), 2);
+    let keyword_results = store.keyword_pages("page 1", 10, &SearchFilter::default()).await.unwrap();
+    assert_eq!(keyword_results[0].content, "page 1");
+
+    store.delete_source(notes_id).await.unwrap();
+    let sources = store.list_sources().await.unwrap();
+    assert_eq!(sources.iter().map(|s| (s.filepath.as_str(), s.chunk_count)).collect::<Vec<_>>(), vec![("old.md", 1)]);
+    assert_eq!(sources[0].embedding_status("text-embedding-ada-002"), EmbeddingStatus::Current);
+    let outdated = EmbeddingStatus::Outdated("nomic-embed-text".to_string());
+    assert_eq!(sources[0].embedding_status("nomic-embed-text"), outdated);
+  }
+
+  #[tokio::test]
+  async fn test_embedded_store_collections() {
+    let mut store = EmbeddedVectorStore::in_memory();
+    assert!(store.create_collection("papers").await.unwrap());
+    assert!(!store.create_collection("papers").await.unwrap());
+    let notes = InsertableFileEmbedding {
+      filepath: "notes.md".to_string(),
+      checksum: "a".to_string(),
+      version: 1,
+      embedding_model: "text-embedding-ada-002".to_string(),
+      embedding_dimensions: 2,
+    };
+    let notes_id = store.add_source(&notes).await.unwrap();
+    store.add_pages(notes_id, vec![&page("p0", 0, vec![1.0, 0.0]), &page("p1", 1, vec![0.0, 1.0])]).await.unwrap();
+    store.add_source_to_collection(notes_id, "notes").await.unwrap();
+    assert!(!store.create_collection("notes").await.unwrap());
+
+    let stats = store.collection_stats().await.unwrap();
+    assert_eq!(stats.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["notes", "papers"]);
+    assert_eq!((stats[0].sources, stats[0].chunks, stats[0].characters), (1, 2, 12));
+    assert_eq!(stats[0].embedding_models, "text-embedding-ada-002");
+    assert_eq!(stats[1], CollectionStats { name: "papers".to_string(), ..Default::default() });
+    assert_eq!(stats[1].to_string(), "papers: 0 sources, 0 chunks, 0 characters");
+
+    store.delete_collection("notes").await.unwrap();
+    store.delete_collection("papers").await.unwrap();
+    assert!(store.collection_stats().await.unwrap().is_empty());
+    assert_eq!(store.list_sources().await.unwrap()[0].collections, "");
+    store.delete_pages(notes_id).await.unwrap();
+    assert_eq!(store.list_sources().await.unwrap()[0].chunk_count, 0);
+  }
+}
@@ -1,10 +1,16 @@
-use async_openai::{config::OpenAIConfig, types::CreateEmbeddingRequestArgs};
+use async_openai::{
+  config::{Config, OpenAIConfig},
+  error::OpenAIError,
+  types::CreateEmbeddingRequestArgs,
+};
 use pgvector::Vector;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::{
   app::{
     errors::{ParseError, SazidError},
     functions::argument_validation::count_tokens,
+    offline::is_local_api_base,
   },
   components::session::create_openai_client,
 };
@@ -12,9 +18,29 @@ use crate::{
 #[derive(Clone)]
 pub enum EmbeddingModel {
   Ada002(OpenAIConfig),
+  // any openai compatible embeddings endpoint, such as ollama or a local text-embeddings-inference server
+  Custom(OpenAIConfig, EmbeddingModelConfig),
 }
 
-#[derive(Clone)]
+// an embedding model served from an openai compatible endpoint, configured with embedding_model
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmbeddingModelSettings {
+  // e.g. http://localhost:11434/v1 for ollama
+  pub api_base: String,
+  pub model: String,
+  pub dimensions: usize,
+  #[serde(default = "default_token_limit")]
+  pub token_limit: usize,
+  // read from this environment variable, local servers usually don't need one
+  #[serde(default)]
+  pub api_key_env: Option<String>,
+}
+
+fn default_token_limit() -> usize {
+  2048
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct EmbeddingModelConfig {
   pub model_string: String,
   pub token_limit: usize,
@@ -23,6 +49,17 @@ pub struct EmbeddingModelConfig {
 }
 
 impl EmbeddingModel {
+  pub fn from_settings(settings: &EmbeddingModelSettings) -> Self {
+    let api_key = settings.api_key_env.as_ref().and_then(|env| std::env::var(env).ok()).unwrap_or_default();
+    let config = EmbeddingModelConfig {
+      model_string: settings.model.clone(),
+      token_limit: settings.token_limit,
+      embedding_suffix: settings.model.replace(['/', ':'], "-"),
+      vector_dimensions: settings.dimensions,
+    };
+    Self::Custom(OpenAIConfig::new().with_api_base(&settings.api_base).with_api_key(api_key), config)
+  }
+
   pub fn config(&self) -> EmbeddingModelConfig {
     match self {
       Self::Ada002(_) => EmbeddingModelConfig {
@@ -31,9 +68,21 @@ impl EmbeddingModel {
         token_limit: 8192,
         vector_dimensions: 1536,
       },
+      Self::Custom(_, config) => config.clone(),
     }
   }
 
+  fn openai_config(&self) -> &OpenAIConfig {
+    match self {
+      Self::Ada002(openai_config) | Self::Custom(openai_config, _) => openai_config,
+    }
+  }
+
+  // embedding with a model served on this machine needs no network, so it is allowed in offline mode
+  pub fn is_local(&self) -> bool {
+    is_local_api_base(self.openai_config().api_base())
+  }
+
   pub fn model_string(&self) -> String {
     self.config().model_string
   }
@@ -64,15 +113,13 @@ impl EmbeddingModel {
       );
     }
 
-    let vector = match self {
-      Self::Ada002(openai_config) => {
-        let client = create_openai_client(openai_config);
-        let request = CreateEmbeddingRequestArgs::default().model(self.model_string()).input(text).build().unwrap();
-        let embedding_response = client.embeddings().create(request).await.unwrap();
-        // embedding_response.data.iter().map(|e| e.embedding.clone()).collect::<Vec<Vec<f32>>>();
-        //let embedding = embedding_response.data.first().unwrap().embedding.clone();
-        embedding_response
-      },
+    let vector = {
+      let client = create_openai_client(self.openai_config());
+      let request = CreateEmbeddingRequestArgs::default().model(self.model_string()).input(text).build().unwrap();
+      let embedding_response = client.embeddings().create(request).await?;
+      // embedding_response.data.iter().map(|e| e.embedding.clone()).collect::<Vec<Vec<f32>>>();
+      //let embedding = embedding_response.data.first().unwrap().embedding.clone();
+      embedding_response
     }
     .data
     .iter()
@@ -82,4 +129,14 @@ impl EmbeddingModel {
 
     Ok(vector.into())
   }
+
+  // embeds every text in one request, the vectors are in the order of texts
+  pub async fn create_embedding_vectors(&self, texts: &[String]) -> Result<Vec<Vector>, OpenAIError> {
+    let client = create_openai_client(self.openai_config());
+    let request = CreateEmbeddingRequestArgs::default().model(self.model_string()).input(texts.to_vec()).build()?;
+    let response = client.embeddings().create(request).await?;
+    let mut data = response.data;
+    data.sort_by_key(|embedding| embedding.index);
+    Ok(data.into_iter().map(|embedding| Vector::from(embedding.embedding)).collect())
+  }
 }
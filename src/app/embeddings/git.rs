@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use git2::{Repository, Sort};
+
+use crate::app::{
+  errors::SazidError,
+  functions::git_repository::patch_text,
+  summarize::{collect_documents, Document},
+};
+
+use super::{ingest::IngestReport, EmbeddingsManager};
+
+// commits ingested by --ingest-git unless --git-max-commits is given, newest first
+pub const DEFAULT_GIT_MAX_COMMITS: usize = 1000;
+
+// the diff of a commit is cut off after this many bytes, so that vendored or generated changes don't flood the store
+const MAX_DIFF_BYTES: usize = 64 * 1024;
+
+fn git_error(e: git2::Error) -> SazidError {
+  SazidError::Other(format!("failed to read the git repository: {}", e))
+}
+
+// the name a commit is stored under, which keeps its hash with every chunk
+pub fn commit_document_name(repo: &Path, hash: &str) -> String {
+  format!("{}@{}", repo.display(), hash)
+}
+
+// the message and diff of each commit reachable from HEAD, newest first
+pub fn commit_documents(repo_path: &Path, max_commits: usize) -> Result<Vec<Document>, SazidError> {
+  let repo = Repository::open(repo_path).map_err(git_error)?;
+  let mut revwalk = repo.revwalk().map_err(git_error)?;
+  revwalk.push_head().map_err(git_error)?;
+  revwalk.set_sorting(Sort::TIME).map_err(git_error)?;
+  let mut documents = vec![];
+  for oid in revwalk.take(max_commits) {
+    let commit = repo.find_commit(oid.map_err(git_error)?).map_err(git_error)?;
+    let parent_tree = match commit.parent_count() {
+      0 => None,
+      _ => Some(commit.parent(0).and_then(|parent| parent.tree()).map_err(git_error)?),
+    };
+    let tree = commit.tree().map_err(git_error)?;
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None).map_err(git_error)?;
+    let patch = patch_text(&diff, MAX_DIFF_BYTES).map_err(git_error)?;
+    let hash = commit.id().to_string();
+    let author = commit.author();
+    let date = chrono::DateTime::from_timestamp(commit.time().seconds(), 0).map(|date| date.to_rfc3339());
+    let content = format!(
+      "commit {}\nAuthor: {} <{}>\nDate: {}\n\n{}\n\n{}",
+      hash,
+      author.name().unwrap_or_default(),
+      author.email().unwrap_or_default(),
+      date.unwrap_or_default(),
+      commit.message().unwrap_or_default().trim(),
+      patch
+    );
+    documents.push(Document { name: commit_document_name(repo_path, &hash), content });
+  }
+  Ok(documents)
+}
+
+impl EmbeddingsManager {
+  // ingests the working tree files and the history of a repository, commits that were already ingested are skipped
+  pub async fn ingest_git_repository(&mut self, repo: &Path, max_commits: usize) -> Result<IngestReport, SazidError> {
+    let repo = &repo.canonicalize()?;
+    let mut documents = collect_documents(repo)
+      .into_iter()
+      .map(|document| Document { name: repo.join(&document.name).to_string_lossy().to_string(), ..document })
+      .collect::<Vec<Document>>();
+    documents.extend(commit_documents(repo, max_commits)?);
+    self.ingest_documents(documents).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_commit_documents_include_message_and_diff() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    std::fs::write(dir.path().join("retry.rs"), "fn retry() {}\n").unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("retry.rs")).unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("dev", "dev@example.com").unwrap();
+    let oid = repo.commit(Some("HEAD"), &signature, &signature, "add retry handling", &tree, &[]).unwrap();
+
+    let documents = commit_documents(dir.path(), 10).unwrap();
+    assert_eq!(documents.len(), 1);
+    assert_eq!(documents[0].name, commit_document_name(dir.path(), &oid.to_string()));
+    assert!(documents[0].content.starts_with(&format!("commit {}\nAuthor: dev <dev@example.com>", oid)));
+    assert!(documents[0].content.contains("add retry handling"));
+    assert!(documents[0].content.contains("+fn retry() {}"));
+  }
+}
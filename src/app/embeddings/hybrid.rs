@@ -0,0 +1,142 @@
+use serde_derive::{Deserialize, Serialize};
+
+// how keyword and vector rankings are blended when searching
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HybridSearchConfig {
+  // 0 ranks by vector similarity only, 1 by keyword matches only
+  #[serde(default = "default_keyword_weight")]
+  pub keyword_weight: f64,
+  // damps the difference between the first ranks, 60 is the value from the original reciprocal rank fusion paper
+  #[serde(default = "default_rrf_k")]
+  pub rrf_k: f64,
+  // candidates taken from each ranking, as a multiple of the number of results
+  #[serde(default = "default_candidate_multiplier")]
+  pub candidate_multiplier: i64,
+}
+
+fn default_keyword_weight() -> f64 {
+  0.3
+}
+
+fn default_rrf_k() -> f64 {
+  60.0
+}
+
+fn default_candidate_multiplier() -> i64 {
+  4
+}
+
+impl Default for HybridSearchConfig {
+  fn default() -> Self {
+    HybridSearchConfig {
+      keyword_weight: default_keyword_weight(),
+      rrf_k: default_rrf_k(),
+      candidate_multiplier: default_candidate_multiplier(),
+    }
+  }
+}
+
+impl HybridSearchConfig {
+  pub fn uses_keywords(&self) -> bool {
+    self.keyword_weight > 0.0
+  }
+
+  pub fn uses_vectors(&self) -> bool {
+    self.keyword_weight < 1.0
+  }
+
+  // merges two rankings of ids, best first, into one, an id ranked by both scores the sum of its weighted ranks
+  pub fn fuse(&self, vector_ranked: &[i64], keyword_ranked: &[i64]) -> Vec<i64> {
+    let keyword_weight = self.keyword_weight.clamp(0.0, 1.0);
+    let mut fused: Vec<(i64, f64)> = vec![];
+    for (ranked, weight) in [(vector_ranked, 1.0 - keyword_weight), (keyword_ranked, keyword_weight)] {
+      for (rank, id) in ranked.iter().enumerate() {
+        let score = weight / (self.rrf_k + rank as f64 + 1.0);
+        match fused.iter_mut().find(|(fused_id, _)| fused_id == id) {
+          Some((_, total)) => *total += score,
+          None => fused.push((*id, score)),
+        }
+      }
+    }
+    // a stable sort, so that ties keep the vector order, then the keyword order
+    fused.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    fused.into_iter().filter(|(_, score)| *score > 0.0).map(|(id, _)| id).collect()
+  }
+}
+
+// lowercase words of letters, digits and underscores, so that identifiers and error codes stay whole
+pub fn keyword_terms(text: &str) -> Vec<String> {
+  text
+    .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+    .filter(|term| !term.is_empty())
+    .map(|term| term.to_lowercase())
+    .collect()
+}
+
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+// okapi bm25 scores of documents for a query, documents without any query term are left out
+pub fn bm25_scores<'a>(query: &str, documents: impl Iterator<Item = (i64, &'a str)>) -> Vec<(i64, f64)> {
+  let query_terms = keyword_terms(query);
+  if query_terms.is_empty() {
+    return vec![];
+  }
+  let documents = documents.map(|(id, text)| (id, keyword_terms(text))).collect::<Vec<(i64, Vec<String>)>>();
+  let count = documents.len() as f64;
+  let average_length = documents.iter().map(|(_, terms)| terms.len()).sum::<usize>() as f64 / count.max(1.0);
+  let document_frequency = |term: &String| documents.iter().filter(|(_, terms)| terms.contains(term)).count() as f64;
+  let idf = query_terms
+    .iter()
+    .map(|term| {
+      let frequency = document_frequency(term);
+      (term, ((count - frequency + 0.5) / (frequency + 0.5) + 1.0).ln())
+    })
+    .collect::<Vec<_>>();
+  documents
+    .iter()
+    .filter_map(|(id, terms)| {
+      let length_norm = 1.0 - BM25_B + BM25_B * terms.len() as f64 / average_length.max(1.0);
+      let score = idf
+        .iter()
+        .map(|(term, idf)| {
+          let frequency = terms.iter().filter(|t| t == term).count() as f64;
+          idf * frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1 * length_norm)
+        })
+        .sum::<f64>();
+      (score > 0.0).then_some((*id, score))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fuse_blends_rankings() {
+    let vector_ranked = vec![1, 2, 3];
+    let keyword_ranked = vec![3, 4];
+    let vector_only = HybridSearchConfig { keyword_weight: 0.0, ..Default::default() };
+    assert_eq!(vector_only.fuse(&vector_ranked, &keyword_ranked), vec![1, 2, 3]);
+    let keyword_only = HybridSearchConfig { keyword_weight: 1.0, ..Default::default() };
+    assert_eq!(keyword_only.fuse(&vector_ranked, &keyword_ranked), vec![3, 4]);
+    let even = HybridSearchConfig { keyword_weight: 0.5, ..Default::default() };
+    assert_eq!(even.fuse(&vector_ranked, &keyword_ranked)[0], 3);
+  }
+
+  #[test]
+  fn test_bm25_prefers_exact_identifiers() {
+    let documents = vec![
+      (1, "error E0308 mismatched types in parse_config"),
+      (2, "the config parser returns an error when types mismatch"),
+      (3, "unrelated notes"),
+    ];
+    let mut scores = bm25_scores("E0308", documents.iter().map(|(id, text)| (*id, *text)));
+    assert_eq!(scores.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1]);
+    scores = bm25_scores("parse_config error", documents.iter().map(|(id, text)| (*id, *text)));
+    scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    assert_eq!(scores[0].0, 1);
+    assert_eq!(scores.len(), 2);
+  }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, QueryDsl, QueryableByName};
+use diesel_async::RunQueryDsl;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::errors::SazidError;
+
+use super::{postgres_store::PgVectorStore, schema};
+
+// the similarity index created by the migrations, replaced by create_vector_index
+pub const VECTOR_INDEX_NAME: &str = "pages_cosine_index";
+
+// above this many chunks ivfflat uses sqrt(rows) lists instead of rows / 1000, as recommended by pgvector
+const IVFFLAT_SQRT_ROWS: i64 = 1_000_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexKind {
+  // slower to build and larger, better recall at the same speed, can be built on an empty table
+  Hnsw,
+  // quick to build, its lists are chosen from the rows present when it is built
+  Ivfflat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorIndex {
+  Hnsw { m: u32, ef_construction: u32 },
+  IvfFlat { lists: u32 },
+}
+
+impl VectorIndex {
+  // pgvector's defaults for hnsw, and the recommended number of lists for ivfflat
+  pub fn new(kind: VectorIndexKind, rows: i64) -> Self {
+    match kind {
+      VectorIndexKind::Hnsw => VectorIndex::Hnsw { m: 16, ef_construction: 64 },
+      VectorIndexKind::Ivfflat => VectorIndex::IvfFlat { lists: recommended_lists(rows) },
+    }
+  }
+
+  // parameters that don't apply to the kind of index are ignored
+  pub fn with_parameters(self, lists: Option<u32>, m: Option<u32>, ef_construction: Option<u32>) -> Self {
+    match self {
+      VectorIndex::Hnsw { m: default_m, ef_construction: default_ef } => VectorIndex::Hnsw {
+        m: m.unwrap_or(default_m),
+        ef_construction: ef_construction.unwrap_or(default_ef),
+      },
+      VectorIndex::IvfFlat { lists: default_lists } => VectorIndex::IvfFlat { lists: lists.unwrap_or(default_lists) },
+    }
+  }
+
+  pub fn create_sql(&self) -> String {
+    let (method, options) = match self {
+      VectorIndex::Hnsw { m, ef_construction } => ("hnsw", format!("m = {}, ef_construction = {}", m, ef_construction)),
+      VectorIndex::IvfFlat { lists } => ("ivfflat", format!("lists = {}", lists)),
+    };
+    format!(
+      "CREATE INDEX {} ON embedding_pages USING {} (embedding vector_cosine_ops) WITH ({});",
+      VECTOR_INDEX_NAME, method, options
+    )
+  }
+}
+
+impl fmt::Display for VectorIndex {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      VectorIndex::Hnsw { m, ef_construction } => write!(f, "hnsw (m = {}, ef_construction = {})", m, ef_construction),
+      VectorIndex::IvfFlat { lists } => write!(f, "ivfflat (lists = {})", lists),
+    }
+  }
+}
+
+pub fn recommended_lists(rows: i64) -> u32 {
+  let lists = match rows {
+    rows if rows > IVFFLAT_SQRT_ROWS => (rows as f64).sqrt() as i64,
+    rows => rows / 1000,
+  };
+  lists.max(1) as u32
+}
+
+// query time settings, trading recall for speed, applied to the connection when the postgres store connects
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct VectorSearchConfig {
+  // candidates kept while searching an hnsw index, pgvector defaults to 40
+  #[serde(default)]
+  pub ef_search: Option<u32>,
+  // ivfflat lists searched, pgvector defaults to 1
+  #[serde(default)]
+  pub probes: Option<u32>,
+}
+
+impl VectorSearchConfig {
+  pub fn set_sql(&self) -> Vec<String> {
+    let mut statements = vec![];
+    if let Some(ef_search) = self.ef_search {
+      statements.push(format!("SET hnsw.ef_search = {};", ef_search));
+    }
+    if let Some(probes) = self.probes {
+      statements.push(format!("SET ivfflat.probes = {};", probes));
+    }
+    statements
+  }
+}
+
+#[derive(QueryableByName, Debug)]
+pub struct VectorIndexStatus {
+  #[diesel(sql_type = Text)]
+  pub definition: String,
+  #[diesel(sql_type = BigInt)]
+  pub size_bytes: i64,
+  #[diesel(sql_type = BigInt)]
+  pub rows: i64,
+}
+
+impl fmt::Display for VectorIndexStatus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}\n{} chunks, index size {} MB", self.definition, self.rows, self.size_bytes / (1024 * 1024))
+  }
+}
+
+impl PgVectorStore {
+  // the recommended parameters for the chunks currently stored
+  pub async fn default_vector_index(&mut self, kind: VectorIndexKind) -> Result<VectorIndex, SazidError> {
+    let rows: i64 = schema::embedding_pages::table.count().get_result(&mut self.client).await?;
+    Ok(VectorIndex::new(kind, rows))
+  }
+
+  // replaces the similarity index, building it can take minutes on a large corpus
+  pub async fn create_vector_index(&mut self, index: VectorIndex) -> Result<(), SazidError> {
+    sql_query(format!("DROP INDEX IF EXISTS {};", VECTOR_INDEX_NAME)).execute(&mut self.client).await?;
+    sql_query(index.create_sql()).execute(&mut self.client).await?;
+    Ok(())
+  }
+
+  pub async fn tune_vector_search(&mut self, config: &VectorSearchConfig) -> Result<(), SazidError> {
+    for statement in config.set_sql() {
+      sql_query(statement).execute(&mut self.client).await?;
+    }
+    Ok(())
+  }
+
+  // refreshes the planner statistics and rebuilds the similarity index, which degrades as chunks are deleted
+  pub async fn maintain_vector_index(&mut self) -> Result<(), SazidError> {
+    sql_query("ANALYZE embedding_pages;").execute(&mut self.client).await?;
+    sql_query(format!("REINDEX INDEX {};", VECTOR_INDEX_NAME)).execute(&mut self.client).await?;
+    Ok(())
+  }
+
+  // none when the similarity index was dropped
+  pub async fn vector_index_status(&mut self) -> Result<Option<VectorIndexStatus>, SazidError> {
+    let status = sql_query(
+      "SELECT i.indexdef AS definition, pg_relation_size(c.oid) AS size_bytes, \
+       (SELECT count(*) FROM embedding_pages) AS rows \
+       FROM pg_indexes i JOIN pg_class c ON c.relname = i.indexname \
+       WHERE i.tablename = 'embedding_pages' AND i.indexname = $1;",
+    )
+    .bind::<Text, _>(VECTOR_INDEX_NAME)
+    .load::<VectorIndexStatus>(&mut self.client)
+    .await?;
+    Ok(status.into_iter().next())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_vector_index_parameters() {
+    assert_eq!(recommended_lists(0), 1);
+    assert_eq!(recommended_lists(250_000), 250);
+    assert_eq!(recommended_lists(4_000_000), 2000);
+    let index = VectorIndex::new(VectorIndexKind::Ivfflat, 500_000);
+    assert_eq!(index, VectorIndex::IvfFlat { lists: 500 });
+    assert!(index.create_sql().ends_with("USING ivfflat (embedding vector_cosine_ops) WITH (lists = 500);"));
+    let tuned = VectorIndex::new(VectorIndexKind::Hnsw, 0).with_parameters(Some(100), Some(32), None);
+    assert_eq!(tuned, VectorIndex::Hnsw { m: 32, ef_construction: 64 });
+    let search = VectorSearchConfig { ef_search: Some(100), probes: None };
+    assert_eq!(search.set_sql(), vec!["SET hnsw.ef_search = 100;".to_string()]);
+  }
+}
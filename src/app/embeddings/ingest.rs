@@ -0,0 +1,152 @@
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::app::errors::SazidError;
+
+// used when ingest_concurrency is not configured
+pub const DEFAULT_INGEST_CONCURRENCY: usize = 8;
+
+// which chunks are not stored because a chunk of another file, in the collection being ingested into, already has
+// their text, so that ingesting overlapping directories doesn't store the same chunks twice
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DuplicateDetection {
+  // chunks with the same text, found by their hash before they are embedded
+  pub skip_exact: bool,
+  // also chunks at least this similar by cosine similarity once embedded, null to only skip exact copies
+  pub similarity_threshold: Option<f32>,
+}
+
+impl Default for DuplicateDetection {
+  fn default() -> Self {
+    DuplicateDetection { skip_exact: true, similarity_threshold: None }
+  }
+}
+
+// the hash stored chunks are compared by, postgres computes the same with md5(content)
+pub fn chunk_hash(chunk: &str) -> String {
+  format!("{:x}", md5::compute(chunk.as_bytes()))
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+  let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+  let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+  match norms {
+    norms if norms > 0.0 => dot / norms,
+    _ => 0.0,
+  }
+}
+
+// runs f on every item with at most limit running at once, returning the results in the order of items
+pub async fn map_bounded<T, R, F, Fut>(items: Vec<T>, limit: usize, f: F) -> Result<Vec<R>, SazidError>
+where
+  T: Send + 'static,
+  R: Send + 'static,
+  F: Fn(T) -> Fut,
+  Fut: Future<Output = R> + Send + 'static,
+{
+  let semaphore = Arc::new(Semaphore::new(limit.max(1)));
+  let handles = items
+    .into_iter()
+    .map(|item| {
+      let semaphore = semaphore.clone();
+      let task = f(item);
+      tokio::spawn(async move {
+        let _permit = semaphore.acquire_owned().await;
+        task.await
+      })
+    })
+    .collect::<Vec<_>>();
+  let mut results = Vec::with_capacity(handles.len());
+  for handle in handles {
+    results.push(handle.await.map_err(|e| SazidError::Other(format!("ingestion task failed: {}", e)))?);
+  }
+  Ok(results)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IngestReport {
+  pub files: usize,
+  pub chunks: usize,
+  pub tokens: usize,
+  // files whose chunks were all stored by an earlier run
+  pub skipped: usize,
+  // chunks not stored because another file has the same or a near identical chunk
+  pub duplicates: usize,
+  pub elapsed: Duration,
+  // files that could not be ingested, with the reason
+  pub failed: Vec<(String, String)>,
+}
+
+impl IngestReport {
+  pub fn chunks_per_sec(&self) -> f64 {
+    match self.elapsed.as_secs_f64() {
+      secs if secs > 0.0 => self.chunks as f64 / secs,
+      _ => 0.0,
+    }
+  }
+}
+
+impl fmt::Display for IngestReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "ingested {} files, {} chunks ({} tokens) in {:.1}s, {:.1} chunks/s",
+      self.files,
+      self.chunks,
+      self.tokens,
+      self.elapsed.as_secs_f64(),
+      self.chunks_per_sec()
+    )?;
+    if self.skipped > 0 {
+      write!(f, ", skipped {} unchanged files", self.skipped)?;
+    }
+    if self.duplicates > 0 {
+      write!(f, ", skipped {} duplicate chunks", self.duplicates)?;
+    }
+    for (filepath, error) in self.failed.iter() {
+      write!(f, "\nfailed to ingest {}: {}", filepath, error)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_map_bounded_keeps_order_and_limit() {
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let results = map_bounded((0..20).collect::<Vec<u64>>(), 3, |i| {
+      let (running, peak) = (running.clone(), peak.clone());
+      async move {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20 - i)).await;
+        running.fetch_sub(1, Ordering::SeqCst);
+        i * 2
+      }
+    })
+    .await
+    .unwrap();
+    assert_eq!(results, (0..20).map(|i| i * 2).collect::<Vec<u64>>());
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+  }
+
+  #[test]
+  fn test_duplicates() {
+    assert_eq!(chunk_hash("fn main() {}"), chunk_hash("fn main() {}"));
+    assert_ne!(chunk_hash("fn main() {}"), chunk_hash("fn main() { }"));
+    assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+    // postgres pads vectors with zeros, which leaves the similarity unchanged
+    assert!((cosine_similarity(&[1.0, 1.0], &[1.0, 0.0, 0.0]) - 0.70710677).abs() < 1e-6);
+    let report = IngestReport { files: 2, chunks: 3, duplicates: 4, ..Default::default() };
+    assert!(report.to_string().ends_with("chunks/s, skipped 4 duplicate chunks"));
+  }
+}
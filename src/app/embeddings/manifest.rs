@@ -0,0 +1,125 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::{
+  consts::{data_path, INGEST_MANIFEST},
+  encryption,
+  errors::SazidError,
+};
+
+// the chunks of a file that have been embedded and stored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+  pub source_id: i64,
+  // blake3 of the file contents, a different checksum means the file changed since it was ingested
+  pub checksum: String,
+  pub chunk_count: usize,
+  pub stored_chunks: BTreeSet<usize>,
+}
+
+impl ManifestEntry {
+  pub fn is_complete(&self) -> bool {
+    self.stored_chunks.len() >= self.chunk_count
+  }
+}
+
+// tracks ingestion progress per file, so that an interrupted ingestion resumes where it stopped
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IngestManifest {
+  pub files: HashMap<String, ManifestEntry>,
+  // directories that were ingested as a whole, watched by --watch
+  #[serde(default)]
+  pub roots: BTreeSet<String>,
+}
+
+impl IngestManifest {
+  pub fn default_path() -> Result<PathBuf, SazidError> {
+    Ok(data_path(INGEST_MANIFEST).ok_or(SazidError::Other("home directory not found".to_string()))?)
+  }
+
+  // a missing or unreadable manifest starts empty, which only costs re-embedding
+  pub fn load(path: &Path) -> Self {
+    encryption::read_to_string(path).ok().and_then(|json| serde_json::from_str(&json).ok()).unwrap_or_default()
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), SazidError> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string(self).map_err(|e| SazidError::Other(e.to_string()))?;
+    // written to a temporary file first so that an interruption can't leave a truncated manifest
+    let tmp_path = path.with_extension("json.tmp");
+    encryption::write(&tmp_path, json)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+  }
+
+  // whether every chunk of this version of the file is already stored
+  pub fn is_current(&self, filepath: &str, checksum: &str) -> bool {
+    self.files.get(filepath).map(|entry| entry.checksum == checksum && entry.is_complete()).unwrap_or(false)
+  }
+
+  // the entry for a previous version of the file, which needs to be removed before the new version is stored
+  pub fn stale_entry(&self, filepath: &str, checksum: &str) -> Option<&ManifestEntry> {
+    self.files.get(filepath).filter(|entry| entry.checksum != checksum)
+  }
+
+  pub fn start(&mut self, filepath: &str, source_id: i64, checksum: &str, chunk_count: usize) {
+    match self.files.get_mut(filepath) {
+      Some(entry) if entry.checksum == checksum && entry.source_id == source_id => entry.chunk_count = chunk_count,
+      _ => {
+        let checksum = checksum.to_string();
+        let entry = ManifestEntry { source_id, checksum, chunk_count, stored_chunks: BTreeSet::new() };
+        self.files.insert(filepath.to_string(), entry);
+      },
+    }
+  }
+
+  pub fn mark_stored(&mut self, filepath: &str, chunk: usize) {
+    if let Some(entry) = self.files.get_mut(filepath) {
+      entry.stored_chunks.insert(chunk);
+    }
+  }
+
+  pub fn is_stored(&self, filepath: &str, chunk: usize) -> bool {
+    self.files.get(filepath).map(|entry| entry.stored_chunks.contains(&chunk)).unwrap_or(false)
+  }
+
+  pub fn add_root(&mut self, root: &Path) {
+    self.roots.insert(root.to_string_lossy().to_string());
+  }
+
+  pub fn remove_source(&mut self, source_id: i64) {
+    self.files.retain(|_, entry| entry.source_id != source_id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_manifest_tracks_progress_and_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("manifest.json");
+    let mut manifest = IngestManifest::load(&path);
+    manifest.start("notes.md", 1, "abc", 2);
+    manifest.mark_stored("notes.md", 0);
+    manifest.save(&path).unwrap();
+
+    let mut manifest = IngestManifest::load(&path);
+    assert!(manifest.is_stored("notes.md", 0));
+    assert!(!manifest.is_stored("notes.md", 1));
+    assert!(!manifest.is_current("notes.md", "abc"));
+    manifest.mark_stored("notes.md", 1);
+    assert!(manifest.is_current("notes.md", "abc"));
+    assert!(manifest.stale_entry("notes.md", "abc").is_none());
+    assert_eq!(manifest.stale_entry("notes.md", "def").map(|entry| entry.source_id), Some(1));
+    manifest.remove_source(1);
+    assert!(manifest.files.is_empty());
+  }
+}
@@ -0,0 +1,181 @@
+use std::{
+  collections::VecDeque,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use pgvector::Vector;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::app::{errors::SazidError, functions::argument_validation::count_tokens, retry::RetryPolicy};
+
+use super::{embeddings_models::EmbeddingModel, ingest::map_bounded};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EmbeddingPipelineConfig {
+  // inputs sent in one embedding request
+  pub batch_size: usize,
+  // tokens sent in one embedding request
+  pub max_batch_tokens: usize,
+  // the account's embedding tokens per minute limit, requests wait rather than exceed it, null to not wait
+  pub tokens_per_minute: Option<usize>,
+}
+
+impl Default for EmbeddingPipelineConfig {
+  fn default() -> Self {
+    EmbeddingPipelineConfig { batch_size: 64, max_batch_tokens: 100_000, tokens_per_minute: Some(1_000_000) }
+  }
+}
+
+// groups consecutive texts into batches of at most batch_size texts and max_tokens tokens
+// a text larger than max_tokens is sent on its own
+pub fn batch_indices(token_counts: &[usize], batch_size: usize, max_tokens: usize) -> Vec<Vec<usize>> {
+  let mut batches: Vec<Vec<usize>> = vec![];
+  let mut batch_tokens = 0;
+  for (i, tokens) in token_counts.iter().enumerate() {
+    match batches.last_mut() {
+      Some(batch) if batch.len() < batch_size.max(1) && batch_tokens + tokens <= max_tokens => {
+        batch.push(i);
+        batch_tokens += tokens;
+      },
+      _ => {
+        batches.push(vec![i]);
+        batch_tokens = *tokens;
+      },
+    }
+  }
+  batches
+}
+
+// tokens sent in the last minute, so that requests wait for the window to move instead of being rate limited
+#[derive(Debug, Default)]
+pub struct TokenRateLimiter {
+  tokens_per_minute: Option<usize>,
+  sent: VecDeque<(Instant, usize)>,
+}
+
+impl TokenRateLimiter {
+  pub fn new(tokens_per_minute: Option<usize>) -> Self {
+    TokenRateLimiter { tokens_per_minute, sent: VecDeque::new() }
+  }
+
+  // how long until tokens can be sent without exceeding the limit
+  pub fn wait_time(&mut self, now: Instant, tokens: usize) -> Duration {
+    let Some(limit) = self.tokens_per_minute else {
+      return Duration::ZERO;
+    };
+    while self.sent.front().map(|(at, _)| now.duration_since(*at) >= RATE_WINDOW).unwrap_or(false) {
+      self.sent.pop_front();
+    }
+    let mut in_window = self.sent.iter().map(|(_, t)| t).sum::<usize>();
+    // a request larger than the limit waits for an empty window rather than forever
+    let allowed = limit.max(tokens);
+    for (at, sent) in self.sent.iter() {
+      if in_window + tokens <= allowed {
+        break;
+      }
+      in_window -= sent;
+      if in_window + tokens <= allowed {
+        return (*at + RATE_WINDOW).saturating_duration_since(now);
+      }
+    }
+    Duration::ZERO
+  }
+
+  pub fn record(&mut self, now: Instant, tokens: usize) {
+    self.sent.push_back((now, tokens));
+  }
+}
+
+// embeds texts in batches, with up to concurrency requests at once, waiting for the tokens per minute limit
+// a batch that still fails after its retries fails only its own texts
+pub async fn embed_batched(
+  model: &EmbeddingModel,
+  texts: Vec<String>,
+  config: &EmbeddingPipelineConfig,
+  retry_policy: &RetryPolicy,
+  concurrency: usize,
+) -> Result<Vec<Result<Vector, String>>, SazidError> {
+  let token_counts = texts.iter().map(|text| count_tokens(text)).collect::<Vec<usize>>();
+  let batches = batch_indices(&token_counts, config.batch_size, config.max_batch_tokens)
+    .into_iter()
+    .map(|batch| {
+      let tokens = batch.iter().map(|i| token_counts[*i]).sum::<usize>();
+      (batch.iter().map(|i| texts[*i].clone()).collect::<Vec<String>>(), tokens)
+    })
+    .collect::<Vec<_>>();
+  let limiter = Arc::new(Mutex::new(TokenRateLimiter::new(config.tokens_per_minute)));
+  let results = map_bounded(batches, concurrency, |(batch, tokens)| {
+    let (model, limiter, retry_policy) = (model.clone(), limiter.clone(), retry_policy.clone());
+    async move {
+      let count = batch.len();
+      match embed_batch(&model, batch, tokens, &limiter, &retry_policy).await {
+        Ok(vectors) => vectors.into_iter().map(Ok).collect::<Vec<_>>(),
+        Err(e) => vec![Err(e.to_string()); count],
+      }
+    }
+  })
+  .await?;
+  Ok(results.into_iter().flatten().collect())
+}
+
+async fn embed_batch(
+  model: &EmbeddingModel,
+  batch: Vec<String>,
+  tokens: usize,
+  limiter: &Mutex<TokenRateLimiter>,
+  retry_policy: &RetryPolicy,
+) -> Result<Vec<Vector>, SazidError> {
+  let started = Instant::now();
+  let mut attempt = 1;
+  loop {
+    // the limiter is held while waiting, so that waiting requests are sent in the order they arrived
+    {
+      let mut limiter = limiter.lock().await;
+      let wait = limiter.wait_time(Instant::now(), tokens);
+      if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+      }
+      limiter.record(Instant::now(), tokens);
+    }
+    match model.create_embedding_vectors(&batch).await {
+      Ok(vectors) => return Ok(vectors),
+      Err(error) => match retry_policy.retry_delay(attempt, started.elapsed(), &error) {
+        Some(delay) => {
+          tokio::time::sleep(delay).await;
+          attempt += 1;
+        },
+        None => return Err(error.into()),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_batch_indices_respects_size_and_tokens() {
+    assert_eq!(batch_indices(&[10, 10, 10, 10, 10], 2, 1000), vec![vec![0, 1], vec![2, 3], vec![4]]);
+    assert_eq!(batch_indices(&[60, 30, 20, 200, 10], 10, 100), vec![vec![0, 1], vec![2], vec![3], vec![4]]);
+    assert!(batch_indices(&[], 10, 100).is_empty());
+  }
+
+  #[test]
+  fn test_rate_limiter_waits_for_the_window() {
+    let start = Instant::now();
+    let mut limiter = TokenRateLimiter::new(Some(100));
+    assert_eq!(limiter.wait_time(start, 60), Duration::ZERO);
+    limiter.record(start, 60);
+    limiter.record(start + Duration::from_secs(10), 30);
+    assert_eq!(limiter.wait_time(start + Duration::from_secs(20), 10), Duration::ZERO);
+    assert_eq!(limiter.wait_time(start + Duration::from_secs(20), 50), Duration::from_secs(40));
+    assert_eq!(limiter.wait_time(start + Duration::from_secs(61), 50), Duration::ZERO);
+    assert_eq!(TokenRateLimiter::new(None).wait_time(start, 1_000_000), Duration::ZERO);
+  }
+}
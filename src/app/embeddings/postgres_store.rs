@@ -0,0 +1,367 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use diesel_migrations::MigrationHarness;
+use pgvector::{Vector, VectorExpressionMethods};
+
+use crate::app::errors::SazidError;
+
+use super::{
+  hybrid::keyword_terms,
+  index::VectorSearchConfig,
+  schema,
+  store::{SearchFilter, VectorStore},
+  types::*,
+  MIGRATIONS,
+};
+
+// the size of the embedding column
+const PG_VECTOR_DIMENSIONS: usize = 1536;
+
+#[derive(QueryableByName)]
+struct RankedPage {
+  #[diesel(sql_type = diesel::sql_types::BigInt)]
+  id: i64,
+}
+
+#[derive(QueryableByName)]
+struct ChunkHash {
+  #[diesel(sql_type = diesel::sql_types::Text)]
+  hash: String,
+}
+
+pub struct PgVectorStore {
+  pub(super) client: AsyncPgConnection,
+}
+
+impl PgVectorStore {
+  // applies any pending migrations, then connects with the search settings applied
+  pub async fn connect(database_url: &str, vector_search: &VectorSearchConfig) -> Result<Self, SazidError> {
+    run_migrations(database_url).await?;
+    let client = AsyncPgConnection::establish(database_url).await.map_err(|e| SazidError::Other(e.to_string()))?;
+    let mut store = PgVectorStore { client };
+    store.tune_vector_search(vector_search).await?;
+    Ok(store)
+  }
+
+  // Method to retrieve indexing progress information
+  pub async fn get_indexing_progress(&mut self) -> Result<Vec<PgVectorIndexInfo>, SazidError> {
+    let progress_info =
+      sql_query("SELECT * FROM pg_vector_index_info;").load::<PgVectorIndexInfo>(&mut self.client).await?;
+    Ok(progress_info)
+  }
+}
+
+#[async_trait]
+impl VectorStore for PgVectorStore {
+  async fn add_source(&mut self, source: &InsertableFileEmbedding) -> Result<i64, SazidError> {
+    let source_id = diesel::insert_into(schema::file_embeddings::table)
+      .values(source)
+      .on_conflict(schema::file_embeddings::dsl::checksum)
+      .do_update()
+      .set(source)
+      .returning(schema::file_embeddings::id)
+      .get_result(&mut self.client)
+      .await?;
+    Ok(source_id)
+  }
+
+  async fn add_pages(&mut self, source_id: i64, pages: Vec<&InsertablePage>) -> Result<(), SazidError> {
+    for p in pages {
+      diesel::insert_into(schema::embedding_pages::table)
+        .values((
+          schema::embedding_pages::content.eq(p.content.clone()),
+          schema::embedding_pages::page_number.eq(p.page_number),
+          schema::embedding_pages::checksum.eq(p.checksum.clone()),
+          schema::embedding_pages::file_embedding_id.eq(source_id),
+          schema::embedding_pages::embedding.eq(padded(&p.embedding)?),
+          schema::embedding_pages::provenance.eq(p.provenance.clone()),
+        ))
+        .on_conflict(schema::embedding_pages::checksum)
+        .do_nothing()
+        .execute(&mut self.client)
+        .await?;
+    }
+    Ok(())
+  }
+
+  async fn source_version(&mut self, source_id: i64) -> Result<Option<i32>, SazidError> {
+    let version = schema::file_embeddings::table
+      .filter(schema::file_embeddings::id.eq(source_id))
+      .select(schema::file_embeddings::version)
+      .first(&mut self.client)
+      .await
+      .optional()?;
+    Ok(version)
+  }
+
+  async fn mark_superseded(&mut self, source_id: i64) -> Result<(), SazidError> {
+    diesel::update(schema::file_embeddings::table.filter(schema::file_embeddings::id.eq(source_id)))
+      .set(schema::file_embeddings::superseded.eq(true))
+      .execute(&mut self.client)
+      .await?;
+    Ok(())
+  }
+
+  async fn source_pages(&mut self, source_id: i64) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let pages = schema::embedding_pages::table
+      .filter(schema::embedding_pages::file_embedding_id.eq(source_id))
+      .order(schema::embedding_pages::page_number)
+      .select(EmbeddingPage::as_select())
+      .load::<EmbeddingPage>(&mut self.client)
+      .await?;
+    Ok(pages)
+  }
+
+  async fn list_sources(&mut self) -> Result<Vec<IngestedSource>, SazidError> {
+    let sources = sql_query(
+      "SELECT f.id, f.filepath, f.checksum, extract(epoch from f.updated_at)::bigint AS updated_at, \
+       count(DISTINCT p.id) AS chunk_count, coalesce(string_agg(DISTINCT t.tag, ','), '') AS collections, \
+       f.version, f.superseded, f.embedding_model \
+       FROM file_embeddings f \
+       LEFT JOIN embedding_pages p ON p.file_embedding_id = f.id \
+       LEFT JOIN embedding_tags et ON et.file_embedding_id = f.id \
+       LEFT JOIN tags t ON t.id = et.tag_id \
+       GROUP BY f.id ORDER BY f.filepath;",
+    )
+    .load::<IngestedSource>(&mut self.client)
+    .await?;
+    Ok(sources)
+  }
+
+  async fn collection_pages(&mut self, collection: &str) -> Result<Vec<CollectionPage>, SazidError> {
+    let pages = sql_query(
+      "SELECT f.filepath, p.content FROM file_embeddings f \
+       JOIN embedding_tags et ON et.file_embedding_id = f.id \
+       JOIN tags t ON t.id = et.tag_id \
+       JOIN embedding_pages p ON p.file_embedding_id = f.id \
+       WHERE t.tag = $1 AND NOT f.superseded ORDER BY f.filepath, p.page_number;",
+    )
+    .bind::<diesel::sql_types::Text, _>(collection)
+    .load::<CollectionPage>(&mut self.client)
+    .await?;
+    Ok(pages)
+  }
+
+  async fn all_pages(&mut self) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let pages = schema::embedding_pages::table
+      .select(EmbeddingPage::as_select())
+      .order((schema::embedding_pages::file_embedding_id, schema::embedding_pages::page_number))
+      .load::<EmbeddingPage>(&mut self.client)
+      .await?;
+    Ok(pages)
+  }
+
+  // the expression matches pages_content_hash_index
+  async fn stored_chunk_hashes(
+    &mut self,
+    hashes: &[String],
+    filepath: &str,
+    collection: Option<&str>,
+  ) -> Result<HashSet<String>, SazidError> {
+    let stored = sql_query(
+      "SELECT DISTINCT md5(p.content) AS hash FROM embedding_pages p \
+       JOIN file_embeddings f ON f.id = p.file_embedding_id \
+       WHERE md5(p.content) = ANY($1) AND f.filepath <> $2 AND NOT f.superseded \
+       AND ($3::text IS NULL OR f.id IN (SELECT et.file_embedding_id FROM embedding_tags et \
+       JOIN tags t ON t.id = et.tag_id WHERE t.tag = $3));",
+    )
+    .bind::<diesel::sql_types::Array<diesel::sql_types::Text>, _>(hashes)
+    .bind::<diesel::sql_types::Text, _>(filepath)
+    .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(collection)
+    .load::<ChunkHash>(&mut self.client)
+    .await?;
+    Ok(stored.into_iter().map(|chunk| chunk.hash).collect())
+  }
+
+  async fn similar_pages(
+    &mut self,
+    vector: &Vector,
+    limit: i64,
+    filter: &SearchFilter,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let mut query = schema::embedding_pages::table
+      .inner_join(schema::file_embeddings::table)
+      .select(EmbeddingPage::as_select())
+      .order(schema::embedding_pages::embedding.cosine_distance(padded(vector)?))
+      .limit(limit)
+      .into_boxed();
+    if !filter.include_versions {
+      query = query.filter(schema::file_embeddings::superseded.eq(false));
+    }
+    if let Some(collection) = &filter.collection {
+      let collection_sources = schema::embedding_tags::table
+        .inner_join(schema::tags::table)
+        .filter(schema::tags::tag.eq(collection.clone()))
+        .select(schema::embedding_tags::file_embedding_id);
+      query = query.filter(schema::file_embeddings::id.eq_any(collection_sources));
+    }
+    let pages = query.load::<EmbeddingPage>(&mut self.client).await?;
+    Ok(pages)
+  }
+
+  // matches any of the words, ranked by ts_rank_cd, the expression matches pages_content_search_index
+  async fn keyword_pages(
+    &mut self,
+    query: &str,
+    limit: i64,
+    filter: &SearchFilter,
+  ) -> Result<Vec<EmbeddingPage>, SazidError> {
+    let terms = keyword_terms(query);
+    if terms.is_empty() {
+      return Ok(vec![]);
+    }
+    let ranked = sql_query(
+      "SELECT p.id FROM embedding_pages p JOIN file_embeddings f ON f.id = p.file_embedding_id \
+       WHERE to_tsvector('simple', p.content) @@ to_tsquery('simple', $1) AND ($2 OR NOT f.superseded) \
+       AND ($3::text IS NULL OR f.id IN (SELECT et.file_embedding_id FROM embedding_tags et \
+       JOIN tags t ON t.id = et.tag_id WHERE t.tag = $3)) \
+       ORDER BY ts_rank_cd(to_tsvector('simple', p.content), to_tsquery('simple', $1)) DESC LIMIT $4;",
+    )
+    .bind::<diesel::sql_types::Text, _>(terms.join(" | "))
+    .bind::<diesel::sql_types::Bool, _>(filter.include_versions)
+    .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(filter.collection.clone())
+    .bind::<diesel::sql_types::BigInt, _>(limit)
+    .load::<RankedPage>(&mut self.client)
+    .await?
+    .into_iter()
+    .map(|page| page.id)
+    .collect::<Vec<i64>>();
+    let mut pages = schema::embedding_pages::table
+      .filter(schema::embedding_pages::id.eq_any(&ranked))
+      .select(EmbeddingPage::as_select())
+      .load::<EmbeddingPage>(&mut self.client)
+      .await?;
+    pages.sort_by_key(|page| ranked.iter().position(|id| *id == page.id));
+    Ok(pages)
+  }
+
+  async fn embedding_models(&mut self, collection: Option<&str>) -> Result<Vec<(String, i32)>, SazidError> {
+    let mut query = schema::file_embeddings::table
+      .filter(schema::file_embeddings::embedding_model.ne(""))
+      .select((schema::file_embeddings::embedding_model, schema::file_embeddings::embedding_dimensions))
+      .distinct()
+      .into_boxed();
+    if let Some(collection) = collection {
+      let collection_sources = schema::embedding_tags::table
+        .inner_join(schema::tags::table)
+        .filter(schema::tags::tag.eq(collection.to_string()))
+        .select(schema::embedding_tags::file_embedding_id);
+      query = query.filter(schema::file_embeddings::id.eq_any(collection_sources));
+    }
+    let models = query.load::<(String, i32)>(&mut self.client).await?;
+    Ok(models)
+  }
+
+  async fn delete_pages(&mut self, source_id: i64) -> Result<(), SazidError> {
+    diesel::delete(schema::embedding_pages::table.filter(schema::embedding_pages::file_embedding_id.eq(source_id)))
+      .execute(&mut self.client)
+      .await?;
+    Ok(())
+  }
+
+  async fn delete_source(&mut self, source_id: i64) -> Result<(), SazidError> {
+    self.delete_pages(source_id).await?;
+    self.remove_source_from_collections(source_id).await?;
+    diesel::delete(schema::file_embeddings::table.filter(schema::file_embeddings::id.eq(source_id)))
+      .execute(&mut self.client)
+      .await?;
+    Ok(())
+  }
+
+  async fn add_source_to_collection(&mut self, source_id: i64, collection: &str) -> Result<(), SazidError> {
+    let tag_id: i64 = diesel::insert_into(schema::tags::table)
+      .values(schema::tags::tag.eq(collection))
+      .on_conflict(schema::tags::tag)
+      .do_update()
+      .set(schema::tags::tag.eq(collection))
+      .returning(schema::tags::id)
+      .get_result(&mut self.client)
+      .await?;
+    diesel::insert_into(schema::embedding_tags::table)
+      .values((schema::embedding_tags::file_embedding_id.eq(source_id), schema::embedding_tags::tag_id.eq(tag_id)))
+      .on_conflict_do_nothing()
+      .execute(&mut self.client)
+      .await?;
+    Ok(())
+  }
+
+  async fn remove_source_from_collections(&mut self, source_id: i64) -> Result<(), SazidError> {
+    diesel::delete(schema::embedding_tags::table.filter(schema::embedding_tags::file_embedding_id.eq(source_id)))
+      .execute(&mut self.client)
+      .await?;
+    Ok(())
+  }
+
+  async fn create_collection(&mut self, collection: &str) -> Result<bool, SazidError> {
+    let created: Option<i64> = diesel::insert_into(schema::tags::table)
+      .values(schema::tags::tag.eq(collection))
+      .on_conflict(schema::tags::tag)
+      .do_nothing()
+      .returning(schema::tags::id)
+      .get_result(&mut self.client)
+      .await
+      .optional()?;
+    Ok(created.is_some())
+  }
+
+  async fn collection_stats(&mut self) -> Result<Vec<CollectionStats>, SazidError> {
+    let collections = sql_query(
+      "SELECT t.tag AS name, count(DISTINCT f.id) AS sources, count(p.id) AS chunks, \
+       coalesce(sum(length(p.content)), 0)::bigint AS characters, \
+       coalesce(string_agg(DISTINCT nullif(f.embedding_model, ''), ','), '') AS embedding_models, \
+       extract(epoch from max(f.updated_at))::bigint AS updated_at \
+       FROM tags t \
+       LEFT JOIN embedding_tags et ON et.tag_id = t.id \
+       LEFT JOIN file_embeddings f ON f.id = et.file_embedding_id AND NOT f.superseded \
+       LEFT JOIN embedding_pages p ON p.file_embedding_id = f.id \
+       GROUP BY t.tag ORDER BY t.tag;",
+    )
+    .load::<CollectionStats>(&mut self.client)
+    .await?;
+    Ok(collections)
+  }
+
+  async fn delete_collection(&mut self, collection: &str) -> Result<(), SazidError> {
+    let tag_ids = schema::tags::table.filter(schema::tags::tag.eq(collection.to_string())).select(schema::tags::id);
+    diesel::delete(schema::embedding_tags::table.filter(schema::embedding_tags::tag_id.eq_any(tag_ids)))
+      .execute(&mut self.client)
+      .await?;
+    diesel::delete(schema::tags::table.filter(schema::tags::tag.eq(collection))).execute(&mut self.client).await?;
+    Ok(())
+  }
+
+  fn as_postgres(&mut self) -> Option<&mut PgVectorStore> {
+    Some(self)
+  }
+}
+
+// the embedding column has a fixed size, smaller vectors are padded with zeros, which leaves cosine distances
+// between them unchanged
+fn padded(vector: &Vector) -> Result<Vector, SazidError> {
+  let mut values = vector.to_vec();
+  if values.len() > PG_VECTOR_DIMENSIONS {
+    return Err(SazidError::Other(format!(
+      "{} dimension embeddings don't fit the {} dimensions of the postgres vector store",
+      values.len(),
+      PG_VECTOR_DIMENSIONS
+    )));
+  }
+  values.resize(PG_VECTOR_DIMENSIONS, 0.0);
+  Ok(Vector::from(values))
+}
+
+// applies any migrations the database is missing, on a blocking connection since the harness is synchronous
+async fn run_migrations(database_url: &str) -> Result<(), SazidError> {
+  let database_url = database_url.to_string();
+  tokio::task::spawn_blocking(move || {
+    let mut connection = PgConnection::establish(&database_url).map_err(|e| SazidError::Other(e.to_string()))?;
+    connection.run_pending_migrations(MIGRATIONS).map_err(|e| SazidError::Other(format!("migration failed: {}", e)))?;
+    Ok(())
+  })
+  .await
+  .map_err(|e| SazidError::Other(e.to_string()))?
+}
@@ -0,0 +1,172 @@
+use std::{io, io::Write, path::Path};
+
+use once_cell::sync::OnceCell;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::{
+  consts::{data_path, PROVENANCE_KEY},
+  errors::SazidError,
+};
+
+// the key entries are signed with, kept in the data directory and readable only by the user
+static SIGNING_KEY: OnceCell<[u8; 32]> = OnceCell::new();
+
+fn signing_key() -> &'static [u8; 32] {
+  SIGNING_KEY.get_or_init(|| match data_path(PROVENANCE_KEY).map(|path| load_or_create_key(&path)) {
+    Some(Ok(key)) => key,
+    Some(Err(e)) => {
+      log::warn!("could not load the provenance key, entries ingested now won't verify later: {}", e);
+      rand::random()
+    },
+    None => rand::random(),
+  })
+}
+
+fn load_or_create_key(path: &Path) -> Result<[u8; 32], SazidError> {
+  match std::fs::read(path) {
+    Ok(key) => key.try_into().map_err(|_| SazidError::Other(format!("invalid provenance key {}", path.display()))),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      let key: [u8; 32] = rand::random();
+      if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      let mut options = std::fs::OpenOptions::new();
+      options.write(true).create_new(true);
+      #[cfg(unix)]
+      std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+      options.open(path)?.write_all(&key)?;
+      Ok(key)
+    },
+    Err(e) => Err(e.into()),
+  }
+}
+
+// where a stored chunk came from and what was done to it before it was sent to the embedding model
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ChunkProvenance {
+  pub source_path: String,
+  // blake3 of the whole source file at ingestion time
+  pub source_checksum: String,
+  // blake3 of the chunk text as stored
+  pub chunk_checksum: String,
+  pub page_number: i32,
+  // rfc3339
+  pub ingested_at: String,
+  pub embedding_model: String,
+  pub embedding_dimensions: usize,
+  pub sazid_version: String,
+  // each step applied between reading the source and sending the chunk, in order
+  pub transformations: Vec<String>,
+  // the heading path of the section of a markdown or html file the chunk is in, such as Guide > Installation, or the
+  // time range of a recording it covers, such as 00:01:05-00:03:10
+  // left out of the json when there is none, so that entries stored before it still verify
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub section: Option<String>,
+  // keyed blake3 over every other field, with a key only this user can read, so that an entry that was edited
+  // after ingestion fails verify, entries signed before the key existed are reported unverified until re-ingested
+  pub signature: String,
+}
+
+impl ChunkProvenance {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    source_path: &str,
+    source_checksum: &str,
+    chunk: &str,
+    page_number: i32,
+    embedding_model: &str,
+    embedding_dimensions: usize,
+    transformations: Vec<String>,
+  ) -> Self {
+    let mut provenance = ChunkProvenance {
+      source_path: source_path.to_string(),
+      source_checksum: source_checksum.to_string(),
+      chunk_checksum: blake3::hash(chunk.as_bytes()).to_hex().to_string(),
+      page_number,
+      ingested_at: chrono::Utc::now().to_rfc3339(),
+      embedding_model: embedding_model.to_string(),
+      embedding_dimensions,
+      sazid_version: env!("CARGO_PKG_VERSION").to_string(),
+      transformations,
+      section: None,
+      signature: String::new(),
+    };
+    provenance.signature = provenance.compute_signature();
+    provenance
+  }
+
+  pub fn with_section(mut self, section: Option<String>) -> Self {
+    self.section = section;
+    self.signature = self.compute_signature();
+    self
+  }
+
+  fn compute_signature(&self) -> String {
+    let unsigned = ChunkProvenance { signature: String::new(), ..self.clone() };
+    let json = serde_json::to_string(&unsigned).unwrap_or_default();
+    blake3::keyed_hash(signing_key(), json.as_bytes()).to_hex().to_string()
+  }
+
+  // whether the entry is unchanged since ingestion and still describes chunk
+  pub fn verify(&self, chunk: &str) -> bool {
+    let chunk_checksum = blake3::hash(chunk.as_bytes()).to_hex().to_string();
+    self.signature == self.compute_signature() && self.chunk_checksum == chunk_checksum
+  }
+
+  // entries stored before provenance was recorded are empty
+  pub fn from_json(json: &str) -> Option<Self> {
+    serde_json::from_str::<ChunkProvenance>(json).ok().filter(|provenance| !provenance.signature.is_empty())
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_provenance_signature_detects_changes() {
+    let steps = vec!["read as utf-8".to_string()];
+    let provenance = ChunkProvenance::new("notes.md", "abc", "chunk", 0, "text-embedding-ada-002", 1536, steps);
+    assert!(provenance.verify("chunk"));
+    assert!(!provenance.verify("edited chunk"));
+    let tampered = ChunkProvenance { embedding_model: "other".to_string(), ..provenance.clone() };
+    assert!(!tampered.verify("chunk"));
+    // without the key an edited entry can't be signed again
+    let unsigned = ChunkProvenance { signature: String::new(), ..tampered.clone() };
+    let resigned = ChunkProvenance {
+      signature: blake3::hash(serde_json::to_string(&unsigned).unwrap().as_bytes()).to_hex().to_string(),
+      ..tampered
+    };
+    assert!(!resigned.verify("chunk"));
+    assert!(!provenance.to_json().contains("section"));
+    assert_eq!(ChunkProvenance::from_json(&provenance.to_json()), Some(provenance.clone()));
+    assert_eq!(ChunkProvenance::from_json("{}"), None);
+
+    let section = provenance.with_section(Some("Guide > Installation".to_string()));
+    assert!(section.verify("chunk"));
+    assert_eq!(
+      ChunkProvenance::from_json(&section.to_json()).unwrap().section.as_deref(),
+      Some("Guide > Installation")
+    );
+  }
+
+  #[test]
+  fn test_load_or_create_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data").join("provenance_key");
+    let key = load_or_create_key(&path).unwrap();
+    assert_eq!(load_or_create_key(&path).unwrap(), key);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    std::fs::write(&path, b"short").unwrap();
+    assert!(load_or_create_key(&path).is_err());
+  }
+}
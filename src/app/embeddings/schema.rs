@@ -32,6 +32,8 @@ diesel::table! {
         page_number -> Int4,
         updated_at -> Timestamptz,
         file_embedding_id -> Int8,
+        provenance -> Text,
+        created_at -> Timestamptz,
     }
 }
 
@@ -54,6 +56,11 @@ diesel::table! {
         filepath -> Text,
         checksum -> Text,
         updated_at -> Timestamptz,
+        version -> Int4,
+        superseded -> Bool,
+        created_at -> Timestamptz,
+        embedding_model -> Text,
+        embedding_dimensions -> Int4,
     }
 }
 
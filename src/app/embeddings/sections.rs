@@ -0,0 +1,201 @@
+use std::{fmt, ops::Range, path::Path};
+
+use lazy_static::lazy_static;
+use pulldown_cmark::{Event, Parser, Tag};
+use regex::Regex;
+use tiktoken_rs::cl100k_base;
+
+use crate::app::tools::chunkifier::chunkify_text;
+
+// headings in a heading path are joined with this, as in Guide > Installation > Linux
+pub const HEADING_SEPARATOR: &str = " > ";
+
+lazy_static! {
+  static ref HTML_IGNORED: Regex =
+    Regex::new(r"(?is)<(script|style|head|nav)\b.*?</(script|style|head|nav)\s*>").unwrap();
+  static ref HTML_HEADING: Regex = Regex::new(r"(?is)<h([1-6])\b[^>]*>(.*?)</h[1-6]\s*>").unwrap();
+  static ref HTML_BREAK: Regex = Regex::new(r"(?i)<(br|/p|/div|/li|/tr|/pre|/blockquote|/table)\b[^>]*>").unwrap();
+  static ref HTML_TAG: Regex = Regex::new(r"(?s)<!--.*?-->|<[^>]*>").unwrap();
+  static ref BLANK_LINES: Regex = Regex::new(r"\n\s*\n\s*").unwrap();
+}
+
+// a file split at its headings, so that each chunk stays within a section and carries the path of headings above it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionFormat {
+  Markdown,
+  Html,
+}
+
+impl SectionFormat {
+  pub fn from_path(path: &str) -> Option<Self> {
+    match Path::new(path).extension()?.to_str()?.to_lowercase().as_str() {
+      "md" | "markdown" | "mdx" => Some(SectionFormat::Markdown),
+      "html" | "htm" | "xhtml" => Some(SectionFormat::Html),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for SectionFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SectionFormat::Markdown => write!(f, "markdown"),
+      SectionFormat::Html => write!(f, "html"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+  // the headings above the section and its own, outermost first, empty for text before the first heading
+  pub headings: Vec<String>,
+  pub text: String,
+}
+
+impl Section {
+  pub fn heading_path(&self) -> String {
+    self.headings.join(HEADING_SEPARATOR)
+  }
+}
+
+// a heading, its level and the bytes it covers in the file
+struct Heading {
+  level: usize,
+  text: String,
+  range: Range<usize>,
+}
+
+// the sections between headings, each with the text under it up to the next heading of any level
+fn split_at_headings(content: &str, headings: &[Heading], text: impl Fn(&str) -> String) -> Vec<Section> {
+  let mut sections = vec![];
+  let preamble = text(&content[..headings.first().map_or(content.len(), |h| h.range.start)]);
+  if !preamble.is_empty() {
+    sections.push(Section { headings: vec![], text: preamble });
+  }
+  // the levels and texts of the headings the current one is under
+  let mut path: Vec<(usize, String)> = vec![];
+  for (i, heading) in headings.iter().enumerate() {
+    while path.last().map_or(false, |(level, _)| *level >= heading.level) {
+      path.pop();
+    }
+    path.push((heading.level, heading.text.clone()));
+    let end = headings.get(i + 1).map_or(content.len(), |next| next.range.start);
+    let body = text(&content[heading.range.end..end]);
+    if !body.is_empty() {
+      sections.push(Section { headings: path.iter().map(|(_, text)| text.clone()).collect(), text: body });
+    }
+  }
+  sections
+}
+
+pub fn markdown_sections(content: &str) -> Vec<Section> {
+  let mut headings = vec![];
+  let mut current: Option<Heading> = None;
+  for (event, range) in Parser::new(content).into_offset_iter() {
+    match event {
+      Event::Start(Tag::Heading(level, ..)) => {
+        current = Some(Heading { level: level as usize, text: String::new(), range })
+      },
+      Event::Text(text) | Event::Code(text) => {
+        if let Some(heading) = current.as_mut() {
+          heading.text.push_str(&text);
+        }
+      },
+      Event::End(Tag::Heading(..)) => {
+        if let Some(heading) = current.take() {
+          headings.push(Heading { text: heading.text.trim().to_string(), ..heading });
+        }
+      },
+      _ => {},
+    }
+  }
+  split_at_headings(content, &headings, |text| text.trim().to_string())
+}
+
+// the text of an html fragment, with blocks on their own lines and the common entities decoded
+fn html_text(html: &str) -> String {
+  let text = HTML_TAG.replace_all(&HTML_BREAK.replace_all(html, "\n"), "");
+  let text = text
+    .replace("&nbsp;", " ")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&amp;", "&");
+  let lines = text.lines().map(|line| line.split_whitespace().collect::<Vec<_>>().join(" ")).collect::<Vec<_>>();
+  BLANK_LINES.replace_all(lines.join("\n").trim(), "\n\n").to_string()
+}
+
+pub fn html_sections(content: &str) -> Vec<Section> {
+  let content = HTML_IGNORED.replace_all(content, "");
+  let headings = HTML_HEADING
+    .captures_iter(&content)
+    .map(|captures| Heading {
+      level: captures[1].parse().unwrap_or(1),
+      text: html_text(&captures[2]).replace('\n', " "),
+      range: captures.get(0).unwrap().range(),
+    })
+    .collect::<Vec<Heading>>();
+  split_at_headings(&content, &headings, html_text)
+}
+
+pub fn split_sections(content: &str, format: SectionFormat) -> Vec<Section> {
+  match format {
+    SectionFormat::Markdown => markdown_sections(content),
+    SectionFormat::Html => html_sections(content),
+  }
+}
+
+// each section split into chunks of at most tokens_per_chunk tokens that start with its heading path, with the
+// heading path of each chunk
+pub fn section_chunks(sections: &[Section], tokens_per_chunk: usize) -> Vec<(String, Option<String>)> {
+  let bpe = cl100k_base().unwrap();
+  let mut chunks = vec![];
+  for section in sections {
+    if section.headings.is_empty() {
+      chunks.extend(chunkify_text(&section.text, tokens_per_chunk).into_iter().map(|chunk| (chunk, None)));
+      continue;
+    }
+    let heading_path = section.heading_path();
+    let budget = tokens_per_chunk.saturating_sub(bpe.encode_with_special_tokens(&heading_path).len() + 1).max(1);
+    for part in chunkify_text(&section.text, budget) {
+      chunks.push((format!("{}\n{}", heading_path, part), Some(heading_path.clone())));
+    }
+  }
+  chunks
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_markdown_sections() {
+    let content = "Intro text.\n\n# Guide\n\nRead this first.\n\n## Installation\n\n### Linux\n\n```sh\n# not a \
+                   heading\napt install sazid\n```\n\n### macOS\n\nbrew install sazid\n\n## Usage\nRun `sazid`.\n";
+    let sections = markdown_sections(content);
+    let paths = sections.iter().map(Section::heading_path).collect::<Vec<_>>();
+    assert_eq!(
+      paths,
+      vec!["", "Guide", "Guide > Installation > Linux", "Guide > Installation > macOS", "Guide > Usage"]
+    );
+    assert_eq!(sections[0].text, "Intro text.");
+    assert_eq!(sections[2].text, "```sh\n# not a heading\napt install sazid\n```");
+
+    let chunks = section_chunks(&sections, 1000);
+    assert_eq!(chunks[3], ("Guide > Installation > macOS\nbrew install sazid".to_string(), Some(paths[3].clone())));
+    assert_eq!(chunks[0], ("Intro text.".to_string(), None));
+  }
+
+  #[test]
+  fn test_html_sections() {
+    let content = "<html><head><title>Docs</title></head><body><nav>Home</nav><h1 id=\"guide\">Guide</h1>\
+                   <p>Read&nbsp;this <b>first</b>.</p><h2>Install &amp; run</h2><ul><li>one</li><li>two</li></ul>\
+                   <script>var h1 = 1;</script></body></html>";
+    let sections = html_sections(content);
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0], Section { headings: vec!["Guide".to_string()], text: "Read this first.".to_string() });
+    assert_eq!(sections[1].heading_path(), "Guide > Install & run");
+    assert_eq!(sections[1].text, "one\ntwo");
+  }
+}
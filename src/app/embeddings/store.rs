@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use pgvector::Vector;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::errors::SazidError;
+
+use super::{
+  postgres_store::PgVectorStore,
+  types::{CollectionPage, CollectionStats, EmbeddingPage, IngestedSource, InsertableFileEmbedding, InsertablePage},
+};
+
+// where ingested chunks and their embeddings are kept
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorStoreKind {
+  // postgres with pgvector, connected to with DATABASE_URL
+  #[default]
+  Postgres,
+  // a file in the data directory, searched in process, no database needed
+  Embedded,
+}
+
+// which chunks a similarity search considers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilter {
+  // include superseded versions of changed files
+  pub include_versions: bool,
+  // only chunks of sources in this collection
+  pub collection: Option<String>,
+}
+
+#[async_trait]
+pub trait VectorStore: Send {
+  // stores a source, or updates the stored source with the same checksum, and returns its id
+  async fn add_source(&mut self, source: &InsertableFileEmbedding) -> Result<i64, SazidError>;
+  // a page that is already stored is left as it is, so that resumed ingestions can repeat pages
+  async fn add_pages(&mut self, source_id: i64, pages: Vec<&InsertablePage>) -> Result<(), SazidError>;
+  async fn source_version(&mut self, source_id: i64) -> Result<Option<i32>, SazidError>;
+  async fn mark_superseded(&mut self, source_id: i64) -> Result<(), SazidError>;
+  // the pages of a source in page order
+  async fn source_pages(&mut self, source_id: i64) -> Result<Vec<EmbeddingPage>, SazidError>;
+  async fn list_sources(&mut self) -> Result<Vec<IngestedSource>, SazidError>;
+  // the pages of every current source in a collection, in file and page order
+  async fn collection_pages(&mut self, collection: &str) -> Result<Vec<CollectionPage>, SazidError>;
+  // every stored page, in source and page order
+  async fn all_pages(&mut self) -> Result<Vec<EmbeddingPage>, SazidError>;
+  // those of the hashes, see chunk_hash, whose chunk is stored for a current source of another file than filepath, in
+  // collection when one is given
+  async fn stored_chunk_hashes(
+    &mut self,
+    hashes: &[String],
+    filepath: &str,
+    collection: Option<&str>,
+  ) -> Result<HashSet<String>, SazidError>;
+  // the pages closest to vector by cosine distance, closest first
+  async fn similar_pages(
+    &mut self,
+    vector: &Vector,
+    limit: i64,
+    filter: &SearchFilter,
+  ) -> Result<Vec<EmbeddingPage>, SazidError>;
+  // the pages that best match the words of query, best first
+  async fn keyword_pages(
+    &mut self,
+    query: &str,
+    limit: i64,
+    filter: &SearchFilter,
+  ) -> Result<Vec<EmbeddingPage>, SazidError>;
+  // the distinct models and dimensions the sources in collection, or every source, were embedded with, sources
+  // without a recorded model are left out
+  async fn embedding_models(&mut self, collection: Option<&str>) -> Result<Vec<(String, i32)>, SazidError>;
+  // removes the pages of a source, keeping the source
+  async fn delete_pages(&mut self, source_id: i64) -> Result<(), SazidError>;
+  // removes the source with its pages and collection memberships
+  async fn delete_source(&mut self, source_id: i64) -> Result<(), SazidError>;
+  async fn add_source_to_collection(&mut self, source_id: i64, collection: &str) -> Result<(), SazidError>;
+  async fn remove_source_from_collections(&mut self, source_id: i64) -> Result<(), SazidError>;
+  // a collection with no sources yet, false when it already exists
+  async fn create_collection(&mut self, collection: &str) -> Result<bool, SazidError>;
+  // every collection, created or added to by ingesting, in name order
+  async fn collection_stats(&mut self) -> Result<Vec<CollectionStats>, SazidError>;
+  // removes the collection and its memberships, its sources are kept
+  async fn delete_collection(&mut self, collection: &str) -> Result<(), SazidError>;
+  // writes changes the store buffers, stores that write each change as it is made don't need to
+  async fn flush(&mut self) -> Result<(), SazidError> {
+    Ok(())
+  }
+  // index management is specific to pgvector
+  fn as_postgres(&mut self) -> Option<&mut PgVectorStore> {
+    None
+  }
+}
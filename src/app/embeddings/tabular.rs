@@ -0,0 +1,255 @@
+use std::{fmt, path::Path};
+
+use serde_json::Value;
+use tiktoken_rs::cl100k_base;
+
+use crate::app::{errors::SazidError, tools::chunkifier::chunkify_text};
+
+// a file of rows, chunked by groups of rows with the header repeated in each chunk so that a retrieved chunk can be
+// read on its own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TabularFormat {
+  Csv,
+  Tsv,
+  Jsonl,
+}
+
+impl TabularFormat {
+  pub fn from_path(path: &str) -> Option<Self> {
+    match Path::new(path).extension()?.to_str()?.to_lowercase().as_str() {
+      "csv" => Some(TabularFormat::Csv),
+      "tsv" | "tab" => Some(TabularFormat::Tsv),
+      "jsonl" | "ndjson" => Some(TabularFormat::Jsonl),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for TabularFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TabularFormat::Csv => write!(f, "csv"),
+      TabularFormat::Tsv => write!(f, "tsv"),
+      TabularFormat::Jsonl => write!(f, "json lines"),
+    }
+  }
+}
+
+// the type every value of a column has, empty values aside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+  Empty,
+  Integer,
+  Number,
+  Boolean,
+  Text,
+}
+
+impl ColumnType {
+  fn of(value: &str) -> Self {
+    match value.trim() {
+      "" => ColumnType::Empty,
+      v if v.parse::<i64>().is_ok() => ColumnType::Integer,
+      v if v.parse::<f64>().is_ok() => ColumnType::Number,
+      "true" | "false" | "TRUE" | "FALSE" | "True" | "False" => ColumnType::Boolean,
+      _ => ColumnType::Text,
+    }
+  }
+
+  fn of_json(value: &Value) -> Self {
+    match value {
+      Value::Null => ColumnType::Empty,
+      Value::Number(n) if n.is_i64() || n.is_u64() => ColumnType::Integer,
+      Value::Number(_) => ColumnType::Number,
+      Value::Bool(_) => ColumnType::Boolean,
+      _ => ColumnType::Text,
+    }
+  }
+
+  fn merge(self, other: ColumnType) -> Self {
+    match (self, other) {
+      (ColumnType::Empty, other) | (other, ColumnType::Empty) => other,
+      (a, b) if a == b => a,
+      (ColumnType::Integer, ColumnType::Number) | (ColumnType::Number, ColumnType::Integer) => ColumnType::Number,
+      _ => ColumnType::Text,
+    }
+  }
+}
+
+impl fmt::Display for ColumnType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ColumnType::Empty => write!(f, "empty"),
+      ColumnType::Integer => write!(f, "integer"),
+      ColumnType::Number => write!(f, "number"),
+      ColumnType::Boolean => write!(f, "boolean"),
+      ColumnType::Text => write!(f, "text"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+  pub name: String,
+  pub column_type: ColumnType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+  pub format: TabularFormat,
+  pub columns: Vec<Column>,
+  // the first line of each chunk, the header row of csv and tsv files, the column names of json lines
+  header: String,
+  // the rows as they are written in chunks, csv and tsv rows in their format and json lines as they are
+  rows: Vec<String>,
+}
+
+impl Table {
+  pub fn parse(content: &str, format: TabularFormat) -> Result<Self, SazidError> {
+    match format {
+      TabularFormat::Csv => Table::parse_delimited(content, b',', format),
+      TabularFormat::Tsv => Table::parse_delimited(content, b'\t', format),
+      TabularFormat::Jsonl => Table::parse_jsonl(content),
+    }
+  }
+
+  fn parse_delimited(content: &str, delimiter: u8, format: TabularFormat) -> Result<Self, SazidError> {
+    let error = |e: csv::Error| SazidError::Other(format!("failed to parse {}: {}", format, e));
+    let mut reader = csv::ReaderBuilder::new().delimiter(delimiter).flexible(true).from_reader(content.as_bytes());
+    let headers = reader.headers().map_err(error)?.clone();
+    let mut columns = headers
+      .iter()
+      .map(|name| Column { name: name.to_string(), column_type: ColumnType::Empty })
+      .collect::<Vec<Column>>();
+    let mut rows = vec![];
+    for record in reader.records() {
+      let record = record.map_err(error)?;
+      for (column, value) in columns.iter_mut().zip(record.iter()) {
+        column.column_type = column.column_type.merge(ColumnType::of(value));
+      }
+      rows.push(write_record(&record, delimiter).map_err(error)?);
+    }
+    Ok(Table { format, columns, header: write_record(&headers, delimiter).map_err(error)?, rows })
+  }
+
+  fn parse_jsonl(content: &str) -> Result<Self, SazidError> {
+    let mut columns: Vec<Column> = vec![];
+    let mut rows = vec![];
+    for (number, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+      let value: Value = serde_json::from_str(line)
+        .map_err(|e| SazidError::Other(format!("failed to parse json lines, line {}: {}", number + 1, e)))?;
+      // a line that isn't an object is a row with a single value
+      let fields = match value {
+        Value::Object(fields) => fields.into_iter().collect::<Vec<(String, Value)>>(),
+        value => vec![("value".to_string(), value)],
+      };
+      for (name, value) in fields {
+        let column_type = ColumnType::of_json(&value);
+        match columns.iter_mut().find(|column| column.name == name) {
+          Some(column) => column.column_type = column.column_type.merge(column_type),
+          None => columns.push(Column { name, column_type }),
+        }
+      }
+      rows.push(line.trim().to_string());
+    }
+    let header = format!("columns: {}", columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", "));
+    Ok(Table { format: TabularFormat::Jsonl, columns, header, rows })
+  }
+
+  pub fn row_count(&self) -> usize {
+    self.rows.len()
+  }
+
+  // consecutive rows of at most tokens_per_chunk tokens with the header and the numbers of the rows, a row too large
+  // for a chunk is split over as many as it needs
+  pub fn chunks(&self, tokens_per_chunk: usize) -> Vec<String> {
+    let bpe = cl100k_base().unwrap();
+    let count = |text: &str| bpe.encode_with_special_tokens(text).len();
+    let total = self.rows.len();
+    let header_tokens = count(&format!("rows {} to {} of {}\n{}\n", total, total, total, self.header));
+    let budget = tokens_per_chunk.saturating_sub(header_tokens).max(1);
+    let chunk = |start: usize, end: usize, rows: &str| {
+      format!("rows {} to {} of {}\n{}\n{}", start + 1, end, total, self.header, rows)
+    };
+    let mut chunks = vec![];
+    // the index of the first row of the group, its rows and their tokens
+    let mut start = 0;
+    let mut group: Vec<&str> = vec![];
+    let mut group_tokens = 0;
+    for (i, row) in self.rows.iter().enumerate() {
+      let row_tokens = count(row) + 1;
+      if !group.is_empty() && group_tokens + row_tokens > budget {
+        chunks.push(chunk(start, i, &group.join("\n")));
+        group.clear();
+      }
+      if row_tokens > budget {
+        chunks.extend(chunkify_text(row, budget).iter().map(|part| chunk(i, i + 1, part)));
+        continue;
+      }
+      if group.is_empty() {
+        start = i;
+        group_tokens = 0;
+      }
+      group.push(row);
+      group_tokens += row_tokens;
+    }
+    if !group.is_empty() {
+      chunks.push(chunk(start, total, &group.join("\n")));
+    }
+    match chunks.is_empty() {
+      true => vec![self.header.clone()],
+      false => chunks,
+    }
+  }
+
+  // the column metadata and how the rows were chunked, recorded in the provenance of each chunk
+  pub fn transformations(&self, tokens_per_chunk: usize) -> Vec<String> {
+    let columns = self.columns.iter().map(|c| format!("{} ({})", c.name, c.column_type)).collect::<Vec<_>>();
+    vec![
+      format!("parsed as {} with {} rows and the columns {}", self.format, self.rows.len(), columns.join(", ")),
+      format!("split into groups of rows of at most {} tokens, each with the header", tokens_per_chunk),
+    ]
+  }
+}
+
+fn write_record(record: &csv::StringRecord, delimiter: u8) -> Result<String, csv::Error> {
+  let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(vec![]);
+  writer.write_record(record)?;
+  let bytes = writer.into_inner().map_err(|e| csv::Error::from(e.into_error()))?;
+  Ok(String::from_utf8_lossy(&bytes).trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_csv_chunks() {
+    let content = "name,city,age\nAda,\"London, UK\",36\nGrace,New York,85\nLinus,Helsinki,\n";
+    let table = Table::parse(content, TabularFormat::from_path("people.csv").unwrap()).unwrap();
+    let types = table.columns.iter().map(|c| (c.name.as_str(), c.column_type)).collect::<Vec<_>>();
+    assert_eq!(types, vec![("name", ColumnType::Text), ("city", ColumnType::Text), ("age", ColumnType::Integer)]);
+    assert_eq!(table.chunks(1000), vec![format!("rows 1 to 3 of 3\n{}", content.trim_end())]);
+
+    // each chunk repeats the header
+    let chunks = table.chunks(25);
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|chunk| chunk.lines().nth(1) == Some("name,city,age")));
+    assert!(chunks[0].starts_with("rows 1 to 1 of 3\nname,city,age\nAda,\"London, UK\",36"));
+    assert!(table.transformations(25)[0].ends_with("3 rows and the columns name (text), city (text), age (integer)"));
+  }
+
+  #[test]
+  fn test_jsonl_chunks() {
+    let content = "{\"id\": 1, \"ok\": true}\n\n{\"id\": 2.5, \"note\": \"late\"}\n";
+    let table = Table::parse(content, TabularFormat::Jsonl).unwrap();
+    let types = table.columns.iter().map(|c| (c.name.as_str(), c.column_type)).collect::<Vec<_>>();
+    assert_eq!(types, vec![("id", ColumnType::Number), ("ok", ColumnType::Boolean), ("note", ColumnType::Text)]);
+    assert_eq!(table.row_count(), 2);
+    assert_eq!(
+      table.chunks(1000),
+      vec!["rows 1 to 2 of 2\ncolumns: id, ok, note\n{\"id\": 1, \"ok\": true}\n{\"id\": 2.5, \"note\": \"late\"}"]
+    );
+    assert!(Table::parse("{\"id\": 1}\nnot json", TabularFormat::Jsonl).is_err());
+  }
+}
@@ -15,13 +15,15 @@ use pgvector::Vector;
 #[diesel(belongs_to(FileEmbedding))]
 #[diesel(table_name = embedding_pages)]
 pub struct EmbeddingPage {
-  id: i64,
-  content: String,
+  pub id: i64,
+  pub content: String,
   checksum: String,
   page_number: i32,
   #[serde(skip)]
   pub embedding: Vector,
   file_embedding_id: i64,
+  // json, see ChunkProvenance
+  pub provenance: String,
 }
 
 #[derive(Insertable, Debug, Clone, PartialEq, AsChangeset)]
@@ -31,6 +33,7 @@ pub struct InsertablePage {
   pub page_number: i32,
   pub checksum: String,
   pub embedding: Vector,
+  pub provenance: String,
 }
 
 #[derive(Serialize, Queryable, Selectable, Debug, Clone, PartialEq, Identifiable, AsChangeset, ValidGrouping)]
@@ -46,6 +49,10 @@ pub struct FileEmbedding {
 pub struct InsertableFileEmbedding {
   pub filepath: String,
   pub checksum: String,
+  pub version: i32,
+  // empty for sources stored before the model was recorded
+  pub embedding_model: String,
+  pub embedding_dimensions: i32,
 }
 
 #[derive(Queryable, Selectable, Debug, Clone, PartialEq, Identifiable, AsChangeset)]
@@ -86,6 +93,23 @@ impl FileEmbedding {
 }
 
 impl EmbeddingPage {
+  // pages read from stores other than postgres
+  pub fn new(
+    id: i64,
+    file_embedding_id: i64,
+    page_number: i32,
+    content: String,
+    checksum: String,
+    embedding: Vector,
+    provenance: String,
+  ) -> Self {
+    EmbeddingPage { id, content, checksum, page_number, embedding, file_embedding_id, provenance }
+  }
+
+  pub fn source_id(&self) -> i64 {
+    self.file_embedding_id
+  }
+
   pub async fn get_embedding_from_page(&self, conn: &mut AsyncPgConnection) -> Result<FileEmbedding, SazidError> {
     let embedding = file_embeddings::table
       .filter(file_embeddings::id.eq(self.file_embedding_id))
@@ -119,8 +143,152 @@ impl EmbeddingPage {
   }
 }
 
-use diesel::sql_types::{Bool, Int4, Text};
-use serde::Serialize;
+use diesel::sql_types::{BigInt, Bool, Int4, Nullable, Text};
+use serde::{Deserialize, Serialize};
+
+// an ingested file or url, with the number of chunks stored for it
+#[derive(QueryableByName, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IngestedSource {
+  #[diesel(sql_type = BigInt)]
+  pub id: i64,
+  #[diesel(sql_type = Text)]
+  pub filepath: String,
+  #[diesel(sql_type = Text)]
+  pub checksum: String,
+  // seconds since the unix epoch
+  #[diesel(sql_type = BigInt)]
+  pub updated_at: i64,
+  #[diesel(sql_type = BigInt)]
+  pub chunk_count: i64,
+  // comma separated tags, which group sources into collections
+  #[diesel(sql_type = Text)]
+  pub collections: String,
+  #[diesel(sql_type = Int4)]
+  pub version: i32,
+  // an older version kept when a changed file was ingested, only searched with --include-versions
+  #[diesel(sql_type = Bool)]
+  pub superseded: bool,
+  // empty for sources stored before the model was recorded
+  #[diesel(sql_type = Text)]
+  #[serde(default)]
+  pub embedding_model: String,
+}
+
+#[derive(QueryableByName, Debug, Clone, PartialEq)]
+pub struct CollectionPage {
+  #[diesel(sql_type = Text)]
+  pub filepath: String,
+  #[diesel(sql_type = Text)]
+  pub content: String,
+}
+
+// a named collection with the size of its current sources, superseded versions are left out
+#[derive(QueryableByName, Debug, Clone, Default, PartialEq)]
+pub struct CollectionStats {
+  #[diesel(sql_type = Text)]
+  pub name: String,
+  #[diesel(sql_type = BigInt)]
+  pub sources: i64,
+  #[diesel(sql_type = BigInt)]
+  pub chunks: i64,
+  #[diesel(sql_type = BigInt)]
+  pub characters: i64,
+  // the models its sources were embedded with, comma separated
+  #[diesel(sql_type = Text)]
+  pub embedding_models: String,
+  // seconds since the unix epoch, none for an empty collection
+  #[diesel(sql_type = Nullable<BigInt>)]
+  pub updated_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SourceStatus {
+  Current,
+  Modified,
+  Missing,
+  Remote,
+}
+
+// whether a source's chunks were embedded with the model its collection is configured with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum EmbeddingStatus {
+  Current,
+  // the configured model, which the source can be re-embedded with
+  Outdated(String),
+  Unrecorded,
+}
+
+impl IngestedSource {
+  pub fn embedding_status(&self, configured_model: &str) -> EmbeddingStatus {
+    match self.embedding_model.as_str() {
+      "" => EmbeddingStatus::Unrecorded,
+      model if model == configured_model => EmbeddingStatus::Current,
+      _ => EmbeddingStatus::Outdated(configured_model.to_string()),
+    }
+  }
+
+  // the first of its collections, which decides the model it is embedded with
+  pub fn collection(&self) -> Option<&str> {
+    self.collections.split(',').find(|c| !c.is_empty())
+  }
+
+  pub fn is_remote(&self) -> bool {
+    self.filepath.starts_with("http://") || self.filepath.starts_with("https://") || self.filepath.starts_with("git@")
+  }
+
+  // compares the stored checksum against the file on disk
+  pub fn status(&self) -> SourceStatus {
+    if self.is_remote() {
+      return SourceStatus::Remote;
+    }
+    match std::fs::read_to_string(&self.filepath) {
+      Ok(content) if blake3::hash(content.as_bytes()).to_hex().to_string() == self.checksum => SourceStatus::Current,
+      Ok(_) => SourceStatus::Modified,
+      Err(_) => SourceStatus::Missing,
+    }
+  }
+}
+
+impl fmt::Display for EmbeddingStatus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EmbeddingStatus::Current => write!(f, "current"),
+      EmbeddingStatus::Outdated(model) => write!(f, "outdated, configured {}", model),
+      EmbeddingStatus::Unrecorded => write!(f, "unrecorded"),
+    }
+  }
+}
+
+impl fmt::Display for CollectionStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let updated_at = self
+      .updated_at
+      .and_then(|updated_at| chrono::NaiveDateTime::from_timestamp_opt(updated_at, 0))
+      .map(|d| format!(", updated {}", d.format("%Y-%m-%d %H:%M")))
+      .unwrap_or_default();
+    let models = match self.embedding_models.as_str() {
+      "" => String::new(),
+      models => format!(", embedded with {}", models),
+    };
+    write!(
+      f,
+      "{}: {} sources, {} chunks, {} characters{}{}",
+      self.name, self.sources, self.chunks, self.characters, models, updated_at
+    )
+  }
+}
+
+impl fmt::Display for SourceStatus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SourceStatus::Current => write!(f, "current"),
+      SourceStatus::Modified => write!(f, "stale"),
+      SourceStatus::Missing => write!(f, "missing"),
+      SourceStatus::Remote => write!(f, "remote"),
+    }
+  }
+}
+
 #[derive(QueryableByName, Debug)]
 pub struct PgVectorIndexInfo {
   #[diesel(sql_type = Int4)]
@@ -0,0 +1,146 @@
+use std::{collections::BTreeSet, fmt, io::IsTerminal};
+
+use serde_derive::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+use crate::app::errors::SazidError;
+
+// the fraction of changed lines above which the user is asked what to do with the previous version
+const SIGNIFICANT_CHANGE_RATIO: f64 = 0.2;
+// the number of section headings listed in the summary for each side
+const LISTED_SECTIONS: usize = 5;
+
+// what to do with the stored version of a document when a changed version is ingested
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReingestPolicy {
+  // ask when the change is significant, replace otherwise
+  #[default]
+  Ask,
+  // remove the previous version
+  Replace,
+  // keep the previous version, searchable only with --include-versions
+  Version,
+  // keep both versions searchable
+  KeepBoth,
+}
+
+impl fmt::Display for ReingestPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ReingestPolicy::Ask => write!(f, "ask"),
+      ReingestPolicy::Replace => write!(f, "replace the previous version"),
+      ReingestPolicy::Version => write!(f, "keep the previous version as an older version"),
+      ReingestPolicy::KeepBoth => write!(f, "keep both versions searchable"),
+    }
+  }
+}
+
+impl ReingestPolicy {
+  // the choice for a changed document, asking on the terminal when the policy is ask and the change is significant
+  // without a terminal to ask on, the previous version is replaced
+  pub fn resolve(&self, filepath: &str, changes: &ChangeSummary) -> Result<ReingestPolicy, SazidError> {
+    match self {
+      ReingestPolicy::Ask if changes.is_significant() && std::io::stdin().is_terminal() => {
+        println!("{} changed: {}", filepath, changes);
+        let choices = [ReingestPolicy::Replace, ReingestPolicy::Version, ReingestPolicy::KeepBoth];
+        let selection = dialoguer::Select::new()
+          .with_prompt("What should happen to the previous version?")
+          .items(&choices)
+          .default(0)
+          .interact()
+          .map_err(SazidError::from)?;
+        Ok(choices[selection])
+      },
+      ReingestPolicy::Ask => Ok(ReingestPolicy::Replace),
+      policy => Ok(*policy),
+    }
+  }
+}
+
+// what changed between two versions of a document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeSummary {
+  pub added_lines: usize,
+  pub removed_lines: usize,
+  pub total_lines: usize,
+  pub added_sections: Vec<String>,
+  pub removed_sections: Vec<String>,
+}
+
+// markdown headings and top level definitions, used to describe which parts of a document changed
+fn sections(text: &str) -> BTreeSet<String> {
+  text
+    .lines()
+    .filter(|line| {
+      line.starts_with('#')
+        || ["fn ", "pub fn ", "struct ", "pub struct ", "impl ", "class ", "def "].iter().any(|p| line.starts_with(p))
+    })
+    .map(|line| line.trim_end_matches('{').trim().to_string())
+    .collect()
+}
+
+impl ChangeSummary {
+  pub fn new(previous: &str, current: &str) -> Self {
+    let diff = TextDiff::from_lines(previous, current);
+    let mut summary = ChangeSummary::default();
+    for change in diff.iter_all_changes() {
+      match change.tag() {
+        ChangeTag::Insert => summary.added_lines += 1,
+        ChangeTag::Delete => summary.removed_lines += 1,
+        ChangeTag::Equal => {},
+      }
+    }
+    summary.total_lines = previous.lines().count().max(current.lines().count());
+    let (previous_sections, current_sections) = (sections(previous), sections(current));
+    summary.added_sections = current_sections.difference(&previous_sections).cloned().collect();
+    summary.removed_sections = previous_sections.difference(&current_sections).cloned().collect();
+    summary
+  }
+
+  pub fn change_ratio(&self) -> f64 {
+    match self.total_lines {
+      0 => 0.0,
+      total => (self.added_lines + self.removed_lines) as f64 / (2 * total) as f64,
+    }
+  }
+
+  pub fn is_significant(&self) -> bool {
+    self.change_ratio() > SIGNIFICANT_CHANGE_RATIO || !self.removed_sections.is_empty()
+  }
+}
+
+impl fmt::Display for ChangeSummary {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "+{} -{} lines ({:.0}% changed)", self.added_lines, self.removed_lines, self.change_ratio() * 100.0)?;
+    for (label, sections) in [("added", &self.added_sections), ("removed", &self.removed_sections)] {
+      if !sections.is_empty() {
+        let listed = sections.iter().take(LISTED_SECTIONS).cloned().collect::<Vec<String>>().join(", ");
+        let more = sections.len().saturating_sub(LISTED_SECTIONS);
+        write!(f, "\n  {} sections: {}", label, listed)?;
+        if more > 0 {
+          write!(f, " and {} more", more)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_change_summary_lists_sections() {
+    let previous = "# Intro\nhello\n# Setup\nrun make\n";
+    let current = "# Intro\nhello\n# Usage\nrun sazid\n";
+    let changes = ChangeSummary::new(previous, current);
+    assert_eq!((changes.added_lines, changes.removed_lines), (2, 2));
+    assert_eq!(changes.added_sections, vec!["# Usage".to_string()]);
+    assert_eq!(changes.removed_sections, vec!["# Setup".to_string()]);
+    assert!(changes.is_significant());
+    assert!(!ChangeSummary::new("a\nb\nc\nd\ne\nf\n", "a\nb\nc\nd\ne\ng\n").is_significant());
+    assert_eq!(ReingestPolicy::Version.resolve("notes.md", &changes).unwrap(), ReingestPolicy::Version);
+  }
+}
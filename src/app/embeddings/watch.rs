@@ -0,0 +1,139 @@
+use std::{
+  collections::BTreeSet,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::app::{
+  errors::SazidError,
+  summarize::{is_skipped_path, Document, MAX_FILE_BYTES},
+};
+
+use super::{manifest::IngestManifest, EmbeddingsManager};
+
+// changes are collected for this long after the first one, so that an editor save that touches a file several times
+// is only ingested once
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+fn watch_error(e: notify::Error) -> SazidError {
+  SazidError::Other(format!("failed to watch ingested directories: {}", e))
+}
+
+// the paths of an event that are under a watched root and would have been ingested with it
+pub fn watched_paths(roots: &[PathBuf], event: &Event) -> Vec<PathBuf> {
+  if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+    return vec![];
+  }
+  event
+    .paths
+    .iter()
+    .filter(|path| roots.iter().any(|root| path.starts_with(root) && !is_skipped_path(root, path)))
+    .cloned()
+    .collect()
+}
+
+impl EmbeddingsManager {
+  // watches every directory ingested as a whole until the watcher stops
+  pub async fn watch_ingested_directories(&mut self) -> Result<(), SazidError> {
+    let manifest = IngestManifest::load(&IngestManifest::default_path()?);
+    let roots: Vec<PathBuf> = manifest.roots.iter().map(PathBuf::from).filter(|root| root.is_dir()).collect();
+    if roots.is_empty() {
+      return Err(SazidError::Other("no ingested directories to watch, ingest one with --textfile".to_string()));
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+      tx.send(event).ok();
+    })
+    .map_err(watch_error)?;
+    for root in roots.iter() {
+      watcher.watch(root, RecursiveMode::Recursive).map_err(watch_error)?;
+      println!("watching {}", root.display());
+    }
+
+    while let Some(event) = rx.recv().await {
+      let mut paths: BTreeSet<PathBuf> = BTreeSet::new();
+      paths.extend(watched_paths(&roots, &event.map_err(watch_error)?));
+      let debounce = tokio::time::sleep(DEBOUNCE);
+      tokio::pin!(debounce);
+      loop {
+        tokio::select! {
+          _ = &mut debounce => break,
+          Some(event) = rx.recv() => {
+            if let Ok(event) = event {
+              paths.extend(watched_paths(&roots, &event));
+            }
+          },
+        }
+      }
+      // files left out by the ingest globs stay out when they change, removals still go through
+      if let Some(globs) = &self.ingest_globs {
+        paths.retain(|path| {
+          !path.is_file() || roots.iter().any(|root| path.strip_prefix(root).map_or(false, |p| globs.is_match(p)))
+        });
+      }
+      if let Err(e) = self.sync_paths(paths).await {
+        eprintln!("failed to sync changes: {}", e);
+      }
+    }
+    Ok(())
+  }
+
+  // re-ingests the paths that are files and removes the stored chunks of paths that no longer exist
+  async fn sync_paths(&mut self, paths: BTreeSet<PathBuf>) -> Result<(), SazidError> {
+    let mut documents = vec![];
+    for path in paths {
+      let name = path.to_string_lossy().to_string();
+      if path.is_file() {
+        let small_enough = path.metadata().map(|m| m.len() <= MAX_FILE_BYTES).unwrap_or(false);
+        // binary files can't be read as text and are skipped, as they are when a directory is ingested
+        if let Some(content) = std::fs::read_to_string(&path).ok().filter(|_| small_enough) {
+          documents.push(Document { name, content });
+        }
+      } else if !path.exists() {
+        let removed = self.delete_sources_under(&path).await?;
+        if removed > 0 {
+          println!("removed {} ({} sources)", name, removed);
+        }
+      }
+    }
+    if !documents.is_empty() {
+      println!("{}", self.ingest_documents(documents).await?);
+    }
+    Ok(())
+  }
+
+  // removes the sources stored for a deleted file, or for every file under a deleted directory
+  async fn delete_sources_under(&mut self, path: &Path) -> Result<usize, SazidError> {
+    let sources = self.list_ingested_sources().await?;
+    let matching = sources.iter().filter(|source| Path::new(&source.filepath).starts_with(path)).collect::<Vec<_>>();
+    for source in matching.iter() {
+      self.delete_source(source.id).await?;
+    }
+    Ok(matching.len())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use notify::event::{AccessKind, CreateKind, RemoveKind};
+
+  use super::*;
+
+  #[test]
+  fn test_watched_paths_filters_roots_and_skipped_dirs() {
+    let roots = vec![PathBuf::from("/project")];
+    let event = Event::new(EventKind::Create(CreateKind::File))
+      .add_path(PathBuf::from("/project/src/main.rs"))
+      .add_path(PathBuf::from("/project/target/debug/out.rs"))
+      .add_path(PathBuf::from("/project/.git/HEAD"))
+      .add_path(PathBuf::from("/elsewhere/notes.md"));
+    assert_eq!(watched_paths(&roots, &event), vec![PathBuf::from("/project/src/main.rs")]);
+    let removed = Event::new(EventKind::Remove(RemoveKind::Folder)).add_path(PathBuf::from("/project/docs"));
+    assert_eq!(watched_paths(&roots, &removed), vec![PathBuf::from("/project/docs")]);
+    let access = Event::new(EventKind::Access(AccessKind::Any)).add_path(PathBuf::from("/project/src/main.rs"));
+    assert!(watched_paths(&roots, &access).is_empty());
+  }
+}
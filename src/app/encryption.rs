@@ -0,0 +1,188 @@
+use std::{
+  collections::HashMap,
+  io,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+  aead::{Aead, KeyInit},
+  Key, XChaCha20Poly1305, XNonce,
+};
+use once_cell::sync::OnceCell;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  consts::{data_path, ENCRYPTION_SALT},
+  errors::SazidError,
+};
+
+// read when the config sets neither a passphrase nor a key file
+pub const PASSPHRASE_ENV: &str = "SAZID_PASSPHRASE";
+
+// an encrypted file is this header, the salt its key was derived with, a random nonce and the ciphertext
+const MAGIC: &[u8] = b"sazid-encrypted-v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+// at-rest encryption of session files, autosaves, the prompt history, cached responses and ingested content
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EncryptionConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  // the secret is the contents of this file, used instead of the passphrase when both are set
+  #[serde(default)]
+  pub key_file: Option<PathBuf>,
+  #[serde(default)]
+  pub passphrase: Option<String>,
+}
+
+pub struct Cipher {
+  secret: Vec<u8>,
+  // new files are encrypted with a key derived with this salt, files written with another salt still decrypt
+  salt: [u8; SALT_LEN],
+  // deriving a key is deliberately slow, so each salt is only derived once
+  keys: Mutex<HashMap<[u8; SALT_LEN], [u8; 32]>>,
+}
+
+static CIPHER: OnceCell<Cipher> = OnceCell::new();
+
+impl Cipher {
+  pub fn new(secret: Vec<u8>, salt: [u8; SALT_LEN]) -> Self {
+    Cipher { secret, salt, keys: Mutex::new(HashMap::new()) }
+  }
+
+  pub fn from_config(config: &EncryptionConfig, salt: [u8; SALT_LEN]) -> Result<Self, SazidError> {
+    let secret = match (&config.key_file, &config.passphrase) {
+      (Some(key_file), _) => std::fs::read(key_file)
+        .map_err(|e| SazidError::Other(format!("could not read the key file {}: {}", key_file.display(), e)))?,
+      (None, Some(passphrase)) => passphrase.as_bytes().to_vec(),
+      (None, None) => std::env::var(PASSPHRASE_ENV)
+        .map_err(|_| {
+          SazidError::Other(format!("encryption is enabled, set a key_file, a passphrase or {}", PASSPHRASE_ENV))
+        })?
+        .into_bytes(),
+    };
+    match secret.iter().all(|b| b.is_ascii_whitespace()) {
+      true => Err(SazidError::Other("the encryption passphrase or key file is empty".to_string())),
+      false => Ok(Cipher::new(secret, salt)),
+    }
+  }
+
+  fn key(&self, salt: &[u8; SALT_LEN]) -> Result<[u8; 32], SazidError> {
+    let mut keys = self.keys.lock().unwrap();
+    if let Some(key) = keys.get(salt) {
+      return Ok(*key);
+    }
+    let mut key = [0u8; 32];
+    Argon2::default()
+      .hash_password_into(&self.secret, salt, &mut key)
+      .map_err(|e| SazidError::Other(format!("failed to derive the encryption key: {}", e)))?;
+    keys.insert(*salt, key);
+    Ok(key)
+  }
+
+  pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, SazidError> {
+    let key = self.key(&self.salt)?;
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(&key))
+      .encrypt(XNonce::from_slice(&nonce), plaintext)
+      .map_err(|_| SazidError::Other("encryption failed".to_string()))?;
+    Ok([MAGIC, &self.salt, &nonce, &ciphertext].concat())
+  }
+
+  pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, SazidError> {
+    let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+    if !is_encrypted(data) || data.len() < header_len {
+      return Err(SazidError::Other("not an encrypted file".to_string()));
+    }
+    let salt: [u8; SALT_LEN] = data[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into().unwrap();
+    let key = self.key(&salt)?;
+    XChaCha20Poly1305::new(Key::from_slice(&key))
+      .decrypt(XNonce::from_slice(&data[MAGIC.len() + SALT_LEN..header_len]), &data[header_len..])
+      .map_err(|_| SazidError::Other("decryption failed, the passphrase or key file is wrong".to_string()))
+  }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+  data.starts_with(MAGIC)
+}
+
+// called once at startup, the reads and writes below use the cipher from then on
+pub fn init(config: Option<&EncryptionConfig>) -> Result<(), SazidError> {
+  let Some(config) = config.filter(|config| config.enabled) else {
+    return Ok(());
+  };
+  let salt_path = data_path(ENCRYPTION_SALT).ok_or(SazidError::Other("home directory not found".to_string()))?;
+  let cipher = Cipher::from_config(config, load_or_create_salt(&salt_path)?)?;
+  // the first configuration wins if init is called again
+  CIPHER.set(cipher).ok();
+  Ok(())
+}
+
+pub fn is_enabled() -> bool {
+  CIPHER.get().is_some()
+}
+
+fn load_or_create_salt(path: &Path) -> Result<[u8; SALT_LEN], SazidError> {
+  match std::fs::read(path) {
+    Ok(salt) => salt.try_into().map_err(|_| SazidError::Other(format!("invalid encryption salt {}", path.display()))),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      let salt: [u8; SALT_LEN] = rand::random();
+      if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      std::fs::write(path, salt)?;
+      Ok(salt)
+    },
+    Err(e) => Err(e.into()),
+  }
+}
+
+fn invalid_data(e: SazidError, path: &Path) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+// like std::fs::read, decrypting encrypted files, plain files are still read so that enabling encryption
+// doesn't lose anything written before, they are encrypted the next time they are saved
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+  let data = std::fs::read(path)?;
+  match (is_encrypted(&data), CIPHER.get()) {
+    (false, _) => Ok(data),
+    (true, Some(cipher)) => cipher.decrypt(&data).map_err(|e| invalid_data(e, path)),
+    (true, None) => Err(invalid_data(SazidError::Other("encrypted, but encryption is not enabled".to_string()), path)),
+  }
+}
+
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+  String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// like std::fs::write, encrypting when encryption is enabled
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+  match CIPHER.get() {
+    Some(cipher) => std::fs::write(path, cipher.encrypt(contents.as_ref()).map_err(|e| invalid_data(e, path))?),
+    None => std::fs::write(path, contents),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_encrypt_and_decrypt() {
+    let cipher = Cipher::new(b"correct horse".to_vec(), [1; SALT_LEN]);
+    let encrypted = cipher.encrypt(b"confidential").unwrap();
+    assert!(is_encrypted(&encrypted));
+    assert!(!encrypted.windows(12).any(|w| w == b"confidential"));
+    assert_eq!(cipher.decrypt(&encrypted).unwrap(), b"confidential");
+
+    // written by another run, with another salt
+    let other_run = Cipher::new(b"correct horse".to_vec(), [2; SALT_LEN]);
+    assert_eq!(other_run.decrypt(&encrypted).unwrap(), b"confidential");
+    let wrong = Cipher::new(b"battery staple".to_vec(), [1; SALT_LEN]);
+    assert!(wrong.decrypt(&encrypted).is_err());
+  }
+}
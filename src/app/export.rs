@@ -0,0 +1,353 @@
+use std::path::{Path, PathBuf};
+
+use async_openai::types::{ChatCompletionRequestMessage, Role};
+use chrono::NaiveDateTime;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use self::{
+  html::HtmlExporter, json::JsonExporter, markdown::MarkdownExporter, obsidian::ObsidianExporter, org::OrgExporter,
+  pdf::PdfExporter,
+};
+
+use super::{
+  batch::BatchSession,
+  consts::{data_path, SESSIONS_DIR},
+  encryption,
+  errors::SazidError,
+  helpers::list_files_ordered_by_date,
+  messages::RenderedChatMessage,
+  session_config::SessionConfig,
+  session_data::SessionData,
+  session_migration::migrate_session,
+};
+
+pub mod html;
+pub mod json;
+pub mod markdown;
+pub mod obsidian;
+pub mod org;
+pub mod pdf;
+pub mod share;
+
+// a session flattened to plain text messages, shared by all of the exporters
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Transcript {
+  pub session_id: String,
+  pub name: String,
+  pub model: String,
+  pub messages: Vec<RenderedChatMessage>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  // the profile the session was last run with
+  #[serde(default)]
+  pub profile: Option<String>,
+  // files read or written by tool calls during the session
+  #[serde(default)]
+  pub source_files: Vec<String>,
+}
+
+impl Transcript {
+  pub fn from_session(session: &BatchSession) -> Self {
+    Self::from_session_data(&session.config, &session.data)
+  }
+
+  pub fn from_session_data(config: &SessionConfig, data: &SessionData) -> Self {
+    Transcript {
+      session_id: config.session_id.clone(),
+      name: config.name.clone(),
+      model: config.model.name.clone(),
+      messages: data
+        .messages
+        .iter()
+        .filter(|m| m.receive_complete)
+        .map(RenderedChatMessage::from)
+        .filter(|m| !m.content.trim().is_empty())
+        .collect(),
+      tags: data.tags.clone(),
+      profile: config.profile.clone(),
+      source_files: source_files(data),
+    }
+  }
+
+  // only the messages in a range such as 3 or 3-7, numbered from 1
+  pub fn select(mut self, range: &str) -> Result<Self, SazidError> {
+    let invalid = || SazidError::Other(format!("invalid message range {}, expected a number such as 3 or 3-7", range));
+    let (start, end) = match range.split_once('-') {
+      Some((start, end)) => (start.trim().parse::<usize>(), end.trim().parse::<usize>()),
+      None => (range.trim().parse::<usize>(), range.trim().parse::<usize>()),
+    };
+    let (start, end) = (start.map_err(|_| invalid())?, end.map_err(|_| invalid())?);
+    if start == 0 || start > end || end > self.messages.len() {
+      return Err(SazidError::Other(format!("{} is not a range of the {} messages", range, self.messages.len())));
+    }
+    self.messages = self.messages.drain(start - 1..end).collect();
+    Ok(self)
+  }
+
+  // session ids are the unix timestamp of when the session was created
+  pub fn date(&self) -> Option<String> {
+    let timestamp = self.session_id.parse::<i64>().ok()?;
+    NaiveDateTime::from_timestamp_opt(timestamp, 0).map(|d| d.format("%Y-%m-%d").to_string())
+  }
+
+  pub fn title(&self) -> String {
+    self
+      .messages
+      .iter()
+      .find(|m| m.role == Some(Role::User))
+      .and_then(|m| m.content.lines().find(|l| !l.trim().is_empty()))
+      .map(|l| l.trim().chars().take(60).collect())
+      .unwrap_or_else(|| self.name.clone())
+  }
+
+  // a filename that stays the same across exports of the same session, so that notes can link to it
+  pub fn stable_file_stem(&self) -> String {
+    let slug = self
+      .title()
+      .to_lowercase()
+      .split(|c: char| !c.is_alphanumeric())
+      .filter(|w| !w.is_empty())
+      .take(8)
+      .collect::<Vec<&str>>()
+      .join("-");
+    match self.date() {
+      Some(date) => format!("{}-{}-{}", date, slug, self.session_id),
+      None => format!("{}-{}", slug, self.session_id),
+    }
+  }
+
+  pub fn load(session_id: &str) -> Result<Self, SazidError> {
+    let sessions_dir = data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()))?;
+    let session_file_path = sessions_dir.join(format!("{}.json", session_id));
+    Self::load_file(&session_file_path)
+  }
+
+  pub fn load_file(session_file_path: &Path) -> Result<Self, SazidError> {
+    let session_json = encryption::read_to_string(session_file_path)
+      .map_err(|e| SazidError::Other(format!("Failed to load session {}: {}", session_file_path.display(), e)))?;
+    let session: BatchSession = migrate_session(&session_json)
+      .map_err(|e| SazidError::Other(format!("Failed to parse session {}: {}", session_file_path.display(), e)))?;
+    Ok(Self::from_session(&session))
+  }
+}
+
+fn source_files(data: &SessionData) -> Vec<String> {
+  let mut source_files: Vec<String> = vec![];
+  let mut add_path = |path: &str| {
+    if !path.is_empty() && !source_files.iter().any(|p| p == path) {
+      source_files.push(path.to_string());
+    }
+  };
+  data
+    .messages
+    .iter()
+    .filter_map(|m| match &m.message {
+      ChatCompletionRequestMessage::Assistant(message) => message.tool_calls.as_ref(),
+      _ => None,
+    })
+    .flatten()
+    .filter_map(|tool_call| serde_json::from_str::<Value>(&tool_call.function.arguments).ok())
+    .for_each(|arguments| {
+      if let Some(arguments) = arguments.as_object() {
+        arguments.iter().filter(|(key, _)| key.contains("path")).for_each(|(_, value)| match value {
+          Value::String(path) => path.split(',').for_each(|p| add_path(p.trim())),
+          Value::Array(paths) => paths.iter().filter_map(|p| p.as_str()).for_each(|p| add_path(p.trim())),
+          _ => {},
+        });
+      }
+    });
+  source_files
+}
+
+pub fn role_label(role: &Option<Role>) -> &'static str {
+  match role {
+    Some(Role::System) => "System",
+    Some(Role::User) => "You",
+    Some(Role::Assistant) => "Assistant",
+    Some(Role::Tool) => "Tool",
+    Some(Role::Function) => "Function",
+    None => "Unknown",
+  }
+}
+
+pub trait Exporter {
+  // the name used to select the exporter with --format
+  fn name(&self) -> &'static str;
+  fn extension(&self) -> &'static str;
+  fn file_name(&self, transcript: &Transcript) -> String {
+    format!("{}.{}", transcript.session_id, self.extension())
+  }
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError>;
+}
+
+pub struct ExporterRegistry {
+  exporters: Vec<Box<dyn Exporter>>,
+}
+
+impl Default for ExporterRegistry {
+  fn default() -> Self {
+    ExporterRegistry { exporters: vec![] }
+      .with(Box::new(MarkdownExporter))
+      .with(Box::new(HtmlExporter))
+      .with(Box::new(JsonExporter))
+      .with(Box::new(OrgExporter))
+      .with(Box::new(ObsidianExporter))
+      .with(Box::new(PdfExporter::default()))
+  }
+}
+
+impl ExporterRegistry {
+  // registering an exporter with an existing name replaces it
+  pub fn with(mut self, exporter: Box<dyn Exporter>) -> Self {
+    self.exporters.retain(|e| e.name() != exporter.name());
+    self.exporters.push(exporter);
+    self
+  }
+
+  pub fn get(&self, name: &str) -> Option<&dyn Exporter> {
+    self.exporters.iter().find(|e| e.name() == name).map(|e| e.as_ref())
+  }
+
+  pub fn names(&self) -> Vec<&'static str> {
+    self.exporters.iter().map(|e| e.name()).collect()
+  }
+
+  // writes the transcript to the output path, or to stdout when no path is given
+  pub fn export(
+    &self,
+    format: &str,
+    transcript: &Transcript,
+    output: Option<&PathBuf>,
+  ) -> Result<Option<PathBuf>, SazidError> {
+    let exporter = self.get(format).ok_or_else(|| {
+      SazidError::Other(format!("unknown export format {}, available formats: {}", format, self.names().join(", ")))
+    })?;
+    let contents = exporter.export(transcript)?;
+    match output {
+      Some(path) => {
+        let path = match path.is_dir() {
+          true => path.join(exporter.file_name(transcript)),
+          false => path.clone(),
+        };
+        std::fs::write(&path, contents)?;
+        Ok(Some(path))
+      },
+      None => {
+        std::io::Write::write_all(&mut std::io::stdout(), &contents)?;
+        Ok(None)
+      },
+    }
+  }
+}
+
+pub fn most_recent_session_id() -> Result<String, SazidError> {
+  let sessions_dir = data_path(SESSIONS_DIR).unwrap();
+  list_files_ordered_by_date(&sessions_dir)?
+    .iter()
+    .rev()
+    .map(|f| f.path())
+    .find(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+    .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+    .ok_or_else(|| SazidError::Other(format!("no sessions found in {}", sessions_dir.display())))
+}
+
+pub fn run_export(
+  format: &str,
+  session_id: Option<&str>,
+  output: Option<&PathBuf>,
+  all: bool,
+) -> Result<(), SazidError> {
+  let registry = ExporterRegistry::default();
+  if all {
+    let output = output
+      .filter(|o| o.is_dir())
+      .ok_or_else(|| SazidError::Other("exporting all sessions requires an output directory".to_string()))?;
+    let sessions_dir = data_path(SESSIONS_DIR).unwrap();
+    for entry in list_files_ordered_by_date(&sessions_dir)? {
+      if entry.path().extension().map(|e| e == "json").unwrap_or(false) {
+        match Transcript::load_file(&entry.path()) {
+          Ok(transcript) if !transcript.messages.is_empty() => {
+            registry.export(format, &transcript, Some(output))?;
+          },
+          Ok(_) => {},
+          Err(e) => eprintln!("skipping {}: {}", entry.path().display(), e),
+        }
+      }
+    }
+    eprintln!("exported sessions to {}", output.display());
+    return Ok(());
+  }
+  let session_id = match session_id {
+    Some(session_id) => session_id.to_string(),
+    None => most_recent_session_id()?,
+  };
+  let transcript = Transcript::load(&session_id)?;
+  if let Some(path) = registry.export(format, &transcript, output)? {
+    eprintln!("exported session {} to {}", session_id, path.display());
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::FinishReason;
+
+  use super::*;
+
+  fn transcript() -> Transcript {
+    Transcript {
+      session_id: "1700000000".to_string(),
+      name: "test session".to_string(),
+      model: "gpt-4".to_string(),
+      messages: vec![
+        RenderedChatMessage {
+          role: Some(Role::User),
+          content: "what is 1 + 1?".to_string(),
+          model: None,
+          finish_reason: None,
+        },
+        RenderedChatMessage {
+          role: Some(Role::Assistant),
+          content: "1 + 1 is <b>2</b>".to_string(),
+          model: Some("gpt-4".to_string()),
+          finish_reason: Some(FinishReason::Stop),
+        },
+      ],
+      tags: vec!["rust".to_string()],
+      profile: None,
+      source_files: vec!["src/main.rs".to_string()],
+    }
+  }
+
+  #[test]
+  fn test_registry_exports_each_format() {
+    let registry = ExporterRegistry::default();
+    assert!(registry.get("docx").is_none());
+    let markdown = String::from_utf8(registry.get("markdown").unwrap().export(&transcript()).unwrap()).unwrap();
+    assert!(markdown.contains("## You\n\nwhat is 1 + 1?"));
+    let html = String::from_utf8(registry.get("html").unwrap().export(&transcript()).unwrap()).unwrap();
+    assert!(html.contains("1 + 1 is &lt;b&gt;2&lt;/b&gt;"));
+    let json = registry.get("json").unwrap().export(&transcript()).unwrap();
+    assert_eq!(serde_json::from_slice::<Transcript>(&json).unwrap(), transcript());
+    let org = String::from_utf8(registry.get("org").unwrap().export(&transcript()).unwrap()).unwrap();
+    assert!(org.contains("* You\nwhat is 1 + 1?"));
+    assert!(org.contains("[[file:src/main.rs]]"));
+    let obsidian = String::from_utf8(registry.get("obsidian").unwrap().export(&transcript()).unwrap()).unwrap();
+    assert!(obsidian.starts_with("---\ntitle: \"what is 1 + 1?\"\ndate: 2023-11-14\n"));
+    assert!(obsidian.contains("- [[src/main.rs]]"));
+  }
+
+  #[test]
+  fn test_select_messages() {
+    assert_eq!(transcript().select("2").unwrap().messages, transcript().messages[1..].to_vec());
+    assert_eq!(transcript().select("1-2").unwrap().messages.len(), 2);
+    assert!(transcript().select("2-3").is_err());
+    assert!(transcript().select("x").is_err());
+  }
+
+  #[test]
+  fn test_stable_file_stem() {
+    assert_eq!(transcript().stable_file_stem(), "2023-11-14-what-is-1-1-1700000000");
+  }
+}
@@ -0,0 +1,86 @@
+use crate::app::errors::SazidError;
+
+use super::{role_label, Exporter, Transcript};
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: auto; } \
+  section { border-top: 1px solid #ccc; } \
+  pre { background: #f4f4f4; padding: 0.5em; overflow-x: auto; }";
+
+pub struct HtmlExporter;
+
+pub fn escape_html(text: &str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// fenced code blocks become pre elements, and the remaining text is split into paragraphs on blank lines
+fn render_content(content: &str) -> String {
+  let mut html = String::new();
+  let mut paragraph: Vec<String> = vec![];
+  let mut code: Option<Vec<String>> = None;
+  let flush_paragraph = |html: &mut String, paragraph: &mut Vec<String>| {
+    if !paragraph.is_empty() {
+      html.push_str(&format!("<p>{}</p>\n", paragraph.join("<br>\n")));
+      paragraph.clear();
+    }
+  };
+  for line in content.lines() {
+    match (line.trim_start().starts_with("```"), code.as_mut()) {
+      (true, Some(lines)) => {
+        html.push_str(&format!("<pre><code>{}</code></pre>\n", lines.join("\n")));
+        code = None;
+      },
+      (true, None) => {
+        flush_paragraph(&mut html, &mut paragraph);
+        code = Some(vec![]);
+      },
+      (false, Some(lines)) => lines.push(escape_html(line)),
+      (false, None) if line.trim().is_empty() => flush_paragraph(&mut html, &mut paragraph),
+      (false, None) => paragraph.push(escape_html(line)),
+    }
+  }
+  if let Some(lines) = code {
+    html.push_str(&format!("<pre><code>{}</code></pre>\n", lines.join("\n")));
+  }
+  flush_paragraph(&mut html, &mut paragraph);
+  html
+}
+
+impl HtmlExporter {
+  pub fn render(transcript: &Transcript) -> String {
+    let mut html = format!(
+      "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n\
+       <body>\n",
+      escape_html(&transcript.name),
+      STYLE
+    );
+    html.push_str(&format!(
+      "<h1>{}</h1>\n<p>session: {}<br>\nmodel: {}</p>\n",
+      escape_html(&transcript.name),
+      escape_html(&transcript.session_id),
+      escape_html(&transcript.model)
+    ));
+    for message in transcript.messages.iter() {
+      let heading = match &message.model {
+        Some(model) => format!("{} ({})", role_label(&message.role), escape_html(model)),
+        None => role_label(&message.role).to_string(),
+      };
+      html.push_str(&format!("<section>\n<h2>{}</h2>\n{}</section>\n", heading, render_content(&message.content)));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+  }
+}
+
+impl Exporter for HtmlExporter {
+  fn name(&self) -> &'static str {
+    "html"
+  }
+
+  fn extension(&self) -> &'static str {
+    "html"
+  }
+
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError> {
+    Ok(Self::render(transcript).into_bytes())
+  }
+}
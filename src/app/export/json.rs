@@ -0,0 +1,19 @@
+use crate::app::errors::SazidError;
+
+use super::{Exporter, Transcript};
+
+pub struct JsonExporter;
+
+impl Exporter for JsonExporter {
+  fn name(&self) -> &'static str {
+    "json"
+  }
+
+  fn extension(&self) -> &'static str {
+    "json"
+  }
+
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError> {
+    serde_json::to_vec_pretty(transcript).map_err(|e| SazidError::Other(e.to_string()))
+  }
+}
@@ -0,0 +1,34 @@
+use crate::app::errors::SazidError;
+
+use super::{role_label, Exporter, Transcript};
+
+pub struct MarkdownExporter;
+
+impl MarkdownExporter {
+  pub fn render(transcript: &Transcript) -> String {
+    let mut markdown =
+      format!("# {}\n\nsession: {}  \nmodel: {}\n", transcript.name, transcript.session_id, transcript.model);
+    for message in transcript.messages.iter() {
+      let heading = match &message.model {
+        Some(model) => format!("{} ({})", role_label(&message.role), model),
+        None => role_label(&message.role).to_string(),
+      };
+      markdown.push_str(&format!("\n## {}\n\n{}\n", heading, message.content.trim_end()));
+    }
+    markdown
+  }
+}
+
+impl Exporter for MarkdownExporter {
+  fn name(&self) -> &'static str {
+    "markdown"
+  }
+
+  fn extension(&self) -> &'static str {
+    "md"
+  }
+
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError> {
+    Ok(Self::render(transcript).into_bytes())
+  }
+}
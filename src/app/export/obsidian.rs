@@ -0,0 +1,52 @@
+use crate::app::errors::SazidError;
+
+use super::{markdown::MarkdownExporter, Exporter, Transcript};
+
+// markdown with yaml frontmatter and wiki-links, for writing sessions into an obsidian vault
+pub struct ObsidianExporter;
+
+fn yaml_string(value: &str) -> String {
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl ObsidianExporter {
+  pub fn render(transcript: &Transcript) -> String {
+    let mut frontmatter = vec!["---".to_string(), format!("title: {}", yaml_string(&transcript.title()))];
+    if let Some(date) = transcript.date() {
+      frontmatter.push(format!("date: {}", date));
+    }
+    frontmatter.push(format!("model: {}", yaml_string(&transcript.model)));
+    frontmatter.push(format!("session: {}", yaml_string(&transcript.session_id)));
+    if let Some(profile) = &transcript.profile {
+      frontmatter.push(format!("profile: {}", yaml_string(profile)));
+    }
+    let tags = std::iter::once("sazid".to_string()).chain(transcript.tags.iter().cloned()).collect::<Vec<String>>();
+    frontmatter.push(format!("tags: [{}]", tags.join(", ")));
+    frontmatter.push("---".to_string());
+
+    let mut note = format!("{}\n{}", frontmatter.join("\n"), MarkdownExporter::render(transcript));
+    if !transcript.source_files.is_empty() {
+      note.push_str("\n## Sources\n\n");
+      transcript.source_files.iter().for_each(|path| note.push_str(&format!("- [[{}]]\n", path)));
+    }
+    note
+  }
+}
+
+impl Exporter for ObsidianExporter {
+  fn name(&self) -> &'static str {
+    "obsidian"
+  }
+
+  fn extension(&self) -> &'static str {
+    "md"
+  }
+
+  fn file_name(&self, transcript: &Transcript) -> String {
+    format!("{}.{}", transcript.stable_file_stem(), self.extension())
+  }
+
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError> {
+    Ok(Self::render(transcript).into_bytes())
+  }
+}
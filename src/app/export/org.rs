@@ -0,0 +1,73 @@
+use crate::app::errors::SazidError;
+
+use super::{role_label, Exporter, Transcript};
+
+pub struct OrgExporter;
+
+// converts fenced code blocks to source blocks, and keeps lines that start with * from being read as headings
+pub fn render_org_content(content: &str) -> String {
+  let mut in_code_block = false;
+  content
+    .lines()
+    .map(|line| match line.trim_start().strip_prefix("```") {
+      Some(_) if in_code_block => {
+        in_code_block = false;
+        "#+END_SRC".to_string()
+      },
+      Some(language) => {
+        in_code_block = true;
+        format!("#+BEGIN_SRC {}", language.trim()).trim_end().to_string()
+      },
+      None if in_code_block && (line.starts_with('*') || line.starts_with("#+")) => format!(",{}", line),
+      None if line.starts_with('*') => format!(" {}", line),
+      None => line.to_string(),
+    })
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+impl OrgExporter {
+  pub fn render(transcript: &Transcript) -> String {
+    let mut org = format!(
+      "#+TITLE: {}\n#+PROPERTY: session {}\n#+PROPERTY: model {}\n",
+      transcript.title(),
+      transcript.session_id,
+      transcript.model
+    );
+    if let Some(date) = transcript.date() {
+      org.push_str(&format!("#+DATE: <{}>\n", date));
+    }
+    let tags = std::iter::once("sazid".to_string()).chain(transcript.tags.iter().cloned()).collect::<Vec<String>>();
+    org.push_str(&format!("#+FILETAGS: :{}:\n", tags.join(":")));
+    for message in transcript.messages.iter() {
+      let heading = match &message.model {
+        Some(model) => format!("{} ({})", role_label(&message.role), model),
+        None => role_label(&message.role).to_string(),
+      };
+      org.push_str(&format!("* {}\n{}\n", heading, render_org_content(message.content.trim_end())));
+    }
+    if !transcript.source_files.is_empty() {
+      org.push_str("* Sources\n");
+      transcript.source_files.iter().for_each(|path| org.push_str(&format!("- [[file:{}]]\n", path)));
+    }
+    org
+  }
+}
+
+impl Exporter for OrgExporter {
+  fn name(&self) -> &'static str {
+    "org"
+  }
+
+  fn extension(&self) -> &'static str {
+    "org"
+  }
+
+  fn file_name(&self, transcript: &Transcript) -> String {
+    format!("{}.{}", transcript.stable_file_stem(), self.extension())
+  }
+
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError> {
+    Ok(Self::render(transcript).into_bytes())
+  }
+}
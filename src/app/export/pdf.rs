@@ -0,0 +1,56 @@
+use std::{
+  io::Write,
+  process::{Command, Stdio},
+};
+
+use crate::app::errors::SazidError;
+
+use super::{html::HtmlExporter, Exporter, Transcript};
+
+// renders the html export to pdf with an external command, weasyprint by default
+// the command reads html from stdin and the output path is appended as the last argument
+pub struct PdfExporter {
+  pub command: Vec<String>,
+}
+
+impl Default for PdfExporter {
+  fn default() -> Self {
+    PdfExporter { command: vec!["weasyprint".to_string(), "-".to_string()] }
+  }
+}
+
+impl Exporter for PdfExporter {
+  fn name(&self) -> &'static str {
+    "pdf"
+  }
+
+  fn extension(&self) -> &'static str {
+    "pdf"
+  }
+
+  fn export(&self, transcript: &Transcript) -> Result<Vec<u8>, SazidError> {
+    let html = HtmlExporter::render(transcript);
+    let dir = tempfile::tempdir()?;
+    let output_path = dir.path().join("transcript.pdf");
+    let mut child = Command::new(&self.command[0])
+      .args(&self.command[1..])
+      .arg(&output_path)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::null())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| SazidError::Other(format!("failed to run {}: {}", self.command[0], e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin.write_all(html.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+      return Err(SazidError::Other(format!(
+        "{} failed: {}",
+        self.command[0],
+        String::from_utf8_lossy(&output.stderr)
+      )));
+    }
+    Ok(std::fs::read(output_path)?)
+  }
+}
@@ -0,0 +1,173 @@
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::app::{
+  credentials::{find_secret, GITHUB_TOKEN_ENV},
+  errors::SazidError,
+  offline::ensure_online,
+  redaction::{describe_redactions, RedactionConfig, Redactor},
+  session_config::SessionConfig,
+};
+
+use super::{markdown::MarkdownExporter, most_recent_session_id, Exporter, Transcript};
+
+const GISTS_API: &str = "https://api.github.com/gists";
+// takes the raw text as the body and answers with the url of the paste
+const PASTE_API: &str = "https://paste.rs";
+// github rejects api requests without a user agent
+const USER_AGENT: &str = "sazid";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ShareTarget {
+  // a secret gist unless public is set, needs a github token with the gist scope
+  #[default]
+  Gist,
+  // anyone with the link can read it, and it can't be deleted later
+  Paste,
+}
+
+impl FromStr for ShareTarget {
+  type Err = SazidError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "gist" => Ok(ShareTarget::Gist),
+      "paste" => Ok(ShareTarget::Paste),
+      _ => Err(SazidError::Other(format!("unknown share target {}, expected gist or paste", s))),
+    }
+  }
+}
+
+// the transcript with its name and messages redacted as configured, since sharing publishes it
+fn redacted_markdown(transcript: &Transcript, redaction: &RedactionConfig) -> Result<(Transcript, String), SazidError> {
+  let redactor = Redactor::new(redaction)?;
+  let transcript = Transcript { name: redactor.redact(&transcript.name).0, ..transcript.clone() };
+  let (markdown, labels) = redactor.redact(&MarkdownExporter::render(&transcript));
+  if let Some(warning) = describe_redactions(&labels, "before sharing") {
+    log::warn!("{}", warning);
+  }
+  Ok((transcript, markdown))
+}
+
+// uploads the transcript as markdown and returns the url to share
+pub async fn share(
+  transcript: &Transcript,
+  target: ShareTarget,
+  public: bool,
+  offline: bool,
+  redaction: &RedactionConfig,
+) -> Result<String, SazidError> {
+  ensure_online(offline, "sharing a session")?;
+  let (transcript, markdown) = redacted_markdown(transcript, redaction)?;
+  match target {
+    ShareTarget::Gist => create_gist(&transcript, &markdown, public).await,
+    ShareTarget::Paste => create_paste(&markdown).await,
+  }
+}
+
+pub fn gist_body(transcript: &Transcript, markdown: &str, public: bool) -> Value {
+  let mut files = serde_json::Map::new();
+  files.insert(MarkdownExporter.file_name(transcript), json!({ "content": markdown }));
+  json!({ "description": format!("sazid: {}", transcript.title()), "public": public, "files": files })
+}
+
+async fn create_gist(transcript: &Transcript, markdown: &str, public: bool) -> Result<String, SazidError> {
+  let (token, _) = find_secret(GITHUB_TOKEN_ENV).ok_or_else(|| {
+    SazidError::Other(format!(
+      "no github token is stored in the keyring and {} is not set, run `sazid auth set github` to store one",
+      GITHUB_TOKEN_ENV
+    ))
+  })?;
+  let response = reqwest::Client::new()
+    .post(GISTS_API)
+    .bearer_auth(token)
+    .header(reqwest::header::USER_AGENT, USER_AGENT)
+    .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+    .json(&gist_body(transcript, markdown, public))
+    .send()
+    .await
+    .map_err(|e| SazidError::Other(format!("failed to create the gist: {}", e)))?
+    .error_for_status()
+    .map_err(|e| SazidError::Other(format!("failed to create the gist: {}", e)))?;
+  let gist = response
+    .json::<Value>()
+    .await
+    .map_err(|e| SazidError::Other(format!("failed to parse the created gist: {}", e)))?;
+  gist["html_url"]
+    .as_str()
+    .map(str::to_string)
+    .ok_or_else(|| SazidError::Other("the created gist has no url".to_string()))
+}
+
+async fn create_paste(markdown: &str) -> Result<String, SazidError> {
+  let response = reqwest::Client::new()
+    .post(PASTE_API)
+    .header(reqwest::header::USER_AGENT, USER_AGENT)
+    .body(markdown.to_string())
+    .send()
+    .await
+    .map_err(|e| SazidError::Other(format!("failed to create the paste: {}", e)))?
+    .error_for_status()
+    .map_err(|e| SazidError::Other(format!("failed to create the paste: {}", e)))?;
+  let url = response.text().await.map_err(|e| SazidError::Other(format!("failed to read the paste url: {}", e)))?;
+  // the markdown extension makes paste.rs render the transcript instead of showing the raw text
+  Ok(format!("{}.md", url.trim()))
+}
+
+pub async fn run_share(
+  target: &str,
+  session_id: Option<&str>,
+  messages: Option<&str>,
+  public: bool,
+  session_config: &SessionConfig,
+) -> Result<(), SazidError> {
+  let target = ShareTarget::from_str(target)?;
+  let session_id = match session_id {
+    Some(session_id) => session_id.to_string(),
+    None => most_recent_session_id()?,
+  };
+  let transcript = match messages {
+    Some(range) => Transcript::load(&session_id)?.select(range)?,
+    None => Transcript::load(&session_id)?,
+  };
+  println!("{}", share(&transcript, target, public, session_config.offline, &session_config.redaction).await?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn transcript(name: &str) -> Transcript {
+    Transcript {
+      session_id: "1700000000".to_string(),
+      name: name.to_string(),
+      model: "gpt-4".to_string(),
+      messages: vec![],
+      tags: vec![],
+      profile: None,
+      source_files: vec![],
+    }
+  }
+
+  #[test]
+  fn test_gist_body() {
+    let transcript = transcript("test session");
+    let body = gist_body(&transcript, "# test session", false);
+    assert_eq!(body["public"], json!(false));
+    assert_eq!(body["files"]["1700000000.md"]["content"], json!("# test session"));
+    assert_eq!(body["description"], json!("sazid: test session"));
+  }
+
+  #[tokio::test]
+  async fn test_share_redacts_and_respects_offline() {
+    let transcript = transcript("mail ada@example.com");
+    let (redacted, markdown) = redacted_markdown(&transcript, &RedactionConfig::default()).unwrap();
+    assert!(!redacted.name.contains("ada@example.com"));
+    assert!(!markdown.contains("ada@example.com"));
+
+    let result = share(&transcript, ShareTarget::Paste, false, true, &RedactionConfig::default()).await;
+    assert!(result.unwrap_err().to_string().contains("offline mode"));
+  }
+}
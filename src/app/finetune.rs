@@ -0,0 +1,161 @@
+use std::{io::Write, path::PathBuf};
+
+use async_openai::types::{ChatCompletionRequestMessage, Role};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  batch::BatchSession,
+  consts::{data_path, SESSIONS_DIR},
+  encryption,
+  errors::SazidError,
+  helpers::list_files_ordered_by_date,
+  messages::{MessageContainer, Rating, RenderedChatMessage},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FinetuneMessage {
+  pub role: Role,
+  pub content: String,
+}
+
+// one line of an openai chat fine-tuning dataset
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FinetuneExample {
+  pub messages: Vec<FinetuneMessage>,
+}
+
+// the conversation up to and including each response rated up, one example per rated response
+// tool calls and their results are left out, since a dataset of plain chat messages can't pair them,
+// and so are responses rated down, so that an accepted answer is never trained on after a rejected one
+pub fn finetune_examples(messages: &[MessageContainer]) -> Vec<FinetuneExample> {
+  let mut examples = vec![];
+  let mut conversation: Vec<FinetuneMessage> = vec![];
+  for message in messages.iter().filter(|m| m.receive_complete) {
+    let is_tool_call = match &message.message {
+      ChatCompletionRequestMessage::Assistant(assistant) => assistant.tool_calls.is_some(),
+      ChatCompletionRequestMessage::Tool(_) | ChatCompletionRequestMessage::Function(_) => true,
+      _ => false,
+    };
+    let rating = message.feedback.as_ref().map(|feedback| feedback.rating);
+    let rendered = RenderedChatMessage::from(message);
+    if is_tool_call || rating == Some(Rating::Down) || rendered.content.trim().is_empty() {
+      continue;
+    }
+    let role = rendered.role.unwrap_or(Role::User);
+    conversation.push(FinetuneMessage { role: role.clone(), content: rendered.content });
+    let has_user_message = conversation.iter().any(|m| m.role == Role::User);
+    if role == Role::Assistant && rating == Some(Rating::Up) && has_user_message {
+      examples.push(FinetuneExample { messages: conversation.clone() });
+    }
+  }
+  examples
+}
+
+// writes the rated exchanges of every saved session as jsonl to the output path, or to stdout when no path is given
+pub fn run_export_finetune(output: Option<&PathBuf>) -> Result<(), SazidError> {
+  let sessions_dir = data_path(SESSIONS_DIR).unwrap();
+  let mut lines = vec![];
+  for entry in list_files_ordered_by_date(&sessions_dir)? {
+    if !entry.path().extension().map(|e| e == "json").unwrap_or(false) {
+      continue;
+    }
+    let session = encryption::read_to_string(&entry.path())
+      .map_err(SazidError::from)
+      .and_then(|json| serde_json::from_str::<BatchSession>(&json).map_err(|e| SazidError::Other(e.to_string())));
+    match session {
+      Ok(session) => {
+        for example in finetune_examples(&session.data.messages) {
+          lines.push(serde_json::to_string(&example).map_err(|e| SazidError::Other(e.to_string()))?);
+        }
+      },
+      Err(e) => eprintln!("skipping {}: {}", entry.path().display(), e),
+    }
+  }
+  let contents = lines.iter().map(|line| format!("{}\n", line)).collect::<String>();
+  match output {
+    Some(path) => {
+      std::fs::write(path, contents)?;
+      eprintln!("exported {} examples to {}", lines.len(), path.display());
+    },
+    None => std::io::stdout().write_all(contents.as_bytes())?,
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+    ChatCompletionToolType, FunctionCall,
+  };
+
+  use super::*;
+  use crate::app::messages::Feedback;
+
+  fn user(text: &str) -> MessageContainer {
+    MessageContainer::new_from_completed_message(ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text(text.to_string())),
+    }))
+  }
+
+  fn assistant(text: &str, rating: Option<Rating>) -> MessageContainer {
+    let mut message = MessageContainer::new_from_completed_message(ChatCompletionRequestMessage::Assistant(
+      ChatCompletionRequestAssistantMessage {
+        role: Role::Assistant,
+        content: Some(text.to_string()),
+        tool_calls: None,
+        function_call: None,
+      },
+    ));
+    message.feedback = rating.map(|rating| Feedback { rating, comment: None });
+    message
+  }
+
+  #[test]
+  fn test_finetune_examples_keep_rated_exchanges() {
+    let system = MessageContainer::new_from_completed_message(ChatCompletionRequestMessage::System(
+      ChatCompletionRequestSystemMessage { role: Role::System, content: Some("be brief".to_string()) },
+    ));
+    let tool_call = MessageContainer::new_from_completed_message(ChatCompletionRequestMessage::Assistant(
+      ChatCompletionRequestAssistantMessage {
+        role: Role::Assistant,
+        content: None,
+        tool_calls: Some(vec![ChatCompletionMessageToolCall {
+          id: "call_1".to_string(),
+          r#type: ChatCompletionToolType::Function,
+          function: FunctionCall { name: "read_file".to_string(), arguments: "{}".to_string() },
+        }]),
+        function_call: None,
+      },
+    ));
+    let tool_result = MessageContainer::new_from_completed_message(ChatCompletionRequestMessage::Tool(
+      ChatCompletionRequestToolMessage {
+        role: Role::Tool,
+        content: Some("fn main() {}".to_string()),
+        tool_call_id: "call_1".to_string(),
+      },
+    ));
+    let messages = vec![
+      system,
+      user("what is rust?"),
+      assistant("a language", Some(Rating::Up)),
+      user("show me main"),
+      tool_call,
+      tool_result,
+      assistant("wrong answer", Some(Rating::Down)),
+      assistant("fn main() {}", Some(Rating::Up)),
+      user("thanks"),
+      assistant("you're welcome", None),
+    ];
+    let examples = finetune_examples(&messages);
+    assert_eq!(examples.len(), 2);
+    let roles = |example: &FinetuneExample| example.messages.iter().map(|m| m.role.clone()).collect::<Vec<Role>>();
+    assert_eq!(roles(&examples[0]), vec![Role::System, Role::User, Role::Assistant]);
+    assert_eq!(roles(&examples[1]), vec![Role::System, Role::User, Role::Assistant, Role::User, Role::Assistant]);
+    assert_eq!(examples[1].messages.last().unwrap().content, "fn main() {}");
+    let line = serde_json::to_string(&examples[0]).unwrap();
+    assert!(line.starts_with(r#"{"messages":[{"role":"system","content":"be brief"}"#));
+  }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::session_config::SessionConfig;
+
+use super::{
+  errors::ToolCallError,
+  tool_call::ToolCallTrait,
+  types::{FunctionCall, FunctionParameters, FunctionProperties},
+  unified_diff::PatchReview,
+};
+
+// in the tui the patch is reviewed hunk by hunk first, see handle_tool_call
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApplyPatchFunction {
+  pub name: String,
+  pub description: String,
+  pub required_properties: Vec<FunctionProperties>,
+  pub optional_properties: Vec<FunctionProperties>,
+}
+
+impl ApplyPatchFunction {
+  // the patch validated against the files in the working directory
+  pub fn review(function_args: &HashMap<String, serde_json::Value>) -> Result<PatchReview, ToolCallError> {
+    match function_args.get("patch").and_then(|p| p.as_str()) {
+      Some(patch) => PatchReview::parse(patch, &std::env::current_dir()?),
+      None => Err(ToolCallError::new("patch argument is required")),
+    }
+  }
+}
+
+impl ToolCallTrait for ApplyPatchFunction {
+  fn init() -> Self {
+    ApplyPatchFunction {
+      name: "apply_patch".to_string(),
+      description: "change files in the working directory with a unified diff, the user accepts or rejects each \
+        hunk before it is written. paths are relative to the working directory, use /dev/null as the old path to \
+        create a file. read the files first so that context lines match exactly"
+        .to_string(),
+      required_properties: vec![FunctionProperties {
+        name: "patch".to_string(),
+        required: true,
+        property_type: "string".to_string(),
+        description: Some("unified diff with --- and +++ headers and @@ hunks".to_string()),
+        enum_values: None,
+      }],
+      optional_properties: vec![],
+    }
+  }
+
+  fn call(
+    &self,
+    function_args: HashMap<String, serde_json::Value>,
+    _session_config: SessionConfig,
+  ) -> Result<Option<String>, ToolCallError> {
+    Ok(Some(ApplyPatchFunction::review(&function_args)?.apply()?))
+  }
+
+  fn function_definition(&self) -> FunctionCall {
+    let mut properties: HashMap<String, FunctionProperties> = HashMap::new();
+
+    self.required_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+    self.optional_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+
+    FunctionCall {
+      name: self.name.clone(),
+      description: Some(self.description.clone()),
+      parameters: Some(FunctionParameters {
+        param_type: "object".to_string(),
+        required: self.required_properties.clone().into_iter().map(|p| p.name).collect(),
+        properties,
+      }),
+    }
+  }
+}
@@ -127,3 +127,50 @@ pub fn count_tokens(text: &str) -> usize {
   let bpe = tiktoken_rs::cl100k_base().unwrap();
   bpe.encode_with_special_tokens(text).len()
 }
+
+// kept out of the budget of truncate_tokens for the marker and the newlines around it
+const TRUNCATION_MARKER_TOKENS: usize = 16;
+
+// the text cut to at most max_tokens, its start and end kept around a [truncated N tokens] marker
+pub fn truncate_tokens(text: &str, max_tokens: usize) -> String {
+  let bpe = tiktoken_rs::cl100k_base().unwrap();
+  let tokens = bpe.encode_with_special_tokens(text);
+  if tokens.len() <= max_tokens {
+    return text.to_string();
+  }
+  let budget = max_tokens.saturating_sub(TRUNCATION_MARKER_TOKENS);
+  let (tail_budget, head_budget) = (budget / 2, budget - budget / 2);
+  // a token can hold part of a character, so the cuts move inward until both ends decode
+  let (head, head_len) = (0..=head_budget.min(3))
+    .map(|shrink| head_budget - shrink)
+    .find_map(|len| bpe.decode(tokens[..len].to_vec()).ok().map(|text| (text, len)))
+    .unwrap_or_default();
+  let (tail, tail_len) = (0..=tail_budget.min(3))
+    .map(|shrink| tail_budget - shrink)
+    .find_map(|len| bpe.decode(tokens[tokens.len() - len..].to_vec()).ok().map(|text| (text, len)))
+    .unwrap_or_default();
+  format!("{}\n[truncated {} tokens]\n{}", head, tokens.len() - head_len - tail_len, tail)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_truncate_tokens() {
+    assert_eq!(truncate_tokens("short output", 100), "short output");
+
+    let output = (1..=2000).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+    let truncated = truncate_tokens(&output, 200);
+    assert!(count_tokens(&truncated) <= 200);
+    assert!(truncated.starts_with("line 1\nline 2\n"));
+    assert!(truncated.ends_with("line 1999\nline 2000"));
+    let dropped = truncated.split("[truncated ").nth(1).unwrap().split(' ').next().unwrap().parse::<usize>().unwrap();
+    assert!(dropped > count_tokens(&output) - 200);
+
+    let wide = "日本語のテキスト🦀".repeat(200);
+    let truncated = truncate_tokens(&wide, 50);
+    assert!(count_tokens(&truncated) <= 50);
+    assert!(truncated.contains("[truncated "));
+  }
+}
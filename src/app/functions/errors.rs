@@ -51,6 +51,12 @@ impl From<io::Error> for ToolCallError {
   }
 }
 
+impl From<git2::Error> for ToolCallError {
+  fn from(error: git2::Error) -> Self {
+    ToolCallError { message: format!("Git Error: {}", error.message()), source: Some(Box::new(error)) }
+  }
+}
+
 impl From<String> for ToolCallError {
   fn from(message: String) -> Self {
     ToolCallError { message, source: None }
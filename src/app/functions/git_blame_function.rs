@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::session_config::SessionConfig;
+
+use super::{
+  errors::ToolCallError,
+  git_repository::{function_result, GitRepository},
+  tool_call::ToolCallTrait,
+  types::{FunctionCall, FunctionParameters, FunctionProperties},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GitBlameFunction {
+  pub name: String,
+  pub description: String,
+  pub required_properties: Vec<FunctionProperties>,
+  pub optional_properties: Vec<FunctionProperties>,
+}
+
+impl ToolCallTrait for GitBlameFunction {
+  fn init() -> Self {
+    GitBlameFunction {
+      name: "git_blame".to_string(),
+      description: "show the commit, author and date that last changed each line of a file".to_string(),
+      required_properties: vec![FunctionProperties {
+        name: "path".to_string(),
+        required: true,
+        property_type: "string".to_string(),
+        description: Some("path to the file, relative to the repository root".to_string()),
+        enum_values: None,
+      }],
+      optional_properties: vec![
+        FunctionProperties {
+          name: "start_line".to_string(),
+          required: false,
+          property_type: "integer".to_string(),
+          description: Some("first line to show, default: 1".to_string()),
+          enum_values: None,
+        },
+        FunctionProperties {
+          name: "end_line".to_string(),
+          required: false,
+          property_type: "integer".to_string(),
+          description: Some("last line to show, default: EOF".to_string()),
+          enum_values: None,
+        },
+      ],
+    }
+  }
+
+  fn call(
+    &self,
+    function_args: HashMap<String, serde_json::Value>,
+    session_config: SessionConfig,
+  ) -> Result<Option<String>, ToolCallError> {
+    let start_line = function_args.get("start_line").and_then(|s| s.as_u64().map(|u| u as usize));
+    let end_line = function_args.get("end_line").and_then(|s| s.as_u64().map(|u| u as usize));
+    match function_args.get("path").and_then(|p| p.as_str()) {
+      Some(path) => {
+        let blame = GitRepository::discover(&std::env::current_dir()?)?.blame(path, start_line, end_line)?;
+        Ok(Some(function_result(blame, session_config.function_result_max_tokens)))
+      },
+      None => Err(ToolCallError::new("path argument is required")),
+    }
+  }
+
+  fn function_definition(&self) -> FunctionCall {
+    let mut properties: HashMap<String, FunctionProperties> = HashMap::new();
+
+    self.required_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+    self.optional_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+
+    FunctionCall {
+      name: self.name.clone(),
+      description: Some(self.description.clone()),
+      parameters: Some(FunctionParameters {
+        param_type: "object".to_string(),
+        required: self.required_properties.clone().into_iter().map(|p| p.name).collect(),
+        properties,
+      }),
+    }
+  }
+}
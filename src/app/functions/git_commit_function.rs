@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::session_config::SessionConfig;
+
+use super::{
+  errors::ToolCallError,
+  git_repository::GitRepository,
+  tool_call::ToolCallTrait,
+  types::{FunctionCall, FunctionParameters, FunctionProperties},
+};
+
+// only called once the user confirms the commit, see handle_tool_call
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GitCommitFunction {
+  pub name: String,
+  pub description: String,
+  pub required_properties: Vec<FunctionProperties>,
+  pub optional_properties: Vec<FunctionProperties>,
+}
+
+impl GitCommitFunction {
+  // what the user is asked to confirm
+  pub fn confirmation(function_args: &HashMap<String, serde_json::Value>) -> String {
+    let message = function_args.get("message").and_then(|m| m.as_str()).unwrap_or_default();
+    format!("Commit the staged changes with this message?\n\n{}", message)
+  }
+}
+
+impl ToolCallTrait for GitCommitFunction {
+  fn init() -> Self {
+    GitCommitFunction {
+      name: "git_commit".to_string(),
+      description: "commit the staged changes with a message, after the user confirms it, \
+        read the staged changes with git_diff first and propose a message that summarizes them"
+        .to_string(),
+      required_properties: vec![FunctionProperties {
+        name: "message".to_string(),
+        required: true,
+        property_type: "string".to_string(),
+        description: Some("commit message, a short summary line, then a blank line and details".to_string()),
+        enum_values: None,
+      }],
+      optional_properties: vec![],
+    }
+  }
+
+  fn call(
+    &self,
+    function_args: HashMap<String, serde_json::Value>,
+    _session_config: SessionConfig,
+  ) -> Result<Option<String>, ToolCallError> {
+    match function_args.get("message").and_then(|m| m.as_str()) {
+      Some(message) => Ok(Some(GitRepository::discover(&std::env::current_dir()?)?.commit(message)?)),
+      None => Err(ToolCallError::new("message argument is required")),
+    }
+  }
+
+  fn function_definition(&self) -> FunctionCall {
+    let mut properties: HashMap<String, FunctionProperties> = HashMap::new();
+
+    self.required_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+    self.optional_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+
+    FunctionCall {
+      name: self.name.clone(),
+      description: Some(self.description.clone()),
+      parameters: Some(FunctionParameters {
+        param_type: "object".to_string(),
+        required: self.required_properties.clone().into_iter().map(|p| p.name).collect(),
+        properties,
+      }),
+    }
+  }
+}
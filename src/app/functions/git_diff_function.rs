@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::session_config::SessionConfig;
+
+use super::{
+  errors::ToolCallError,
+  git_repository::{function_result, GitRepository},
+  tool_call::ToolCallTrait,
+  types::{FunctionCall, FunctionParameters, FunctionProperties},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GitDiffFunction {
+  pub name: String,
+  pub description: String,
+  pub required_properties: Vec<FunctionProperties>,
+  pub optional_properties: Vec<FunctionProperties>,
+}
+
+impl ToolCallTrait for GitDiffFunction {
+  fn init() -> Self {
+    GitDiffFunction {
+      name: "git_diff".to_string(),
+      description: "show the uncommitted changes of the git repository as a patch".to_string(),
+      required_properties: vec![],
+      optional_properties: vec![
+        FunctionProperties {
+          name: "staged".to_string(),
+          required: false,
+          property_type: "boolean".to_string(),
+          description: Some("show the changes staged for the next commit instead, default: false".to_string()),
+          enum_values: None,
+        },
+        FunctionProperties {
+          name: "path".to_string(),
+          required: false,
+          property_type: "string".to_string(),
+          description: Some("only show changes to this path, relative to the repository root".to_string()),
+          enum_values: None,
+        },
+      ],
+    }
+  }
+
+  fn call(
+    &self,
+    function_args: HashMap<String, serde_json::Value>,
+    session_config: SessionConfig,
+  ) -> Result<Option<String>, ToolCallError> {
+    let staged = function_args.get("staged").and_then(|s| s.as_bool()).unwrap_or(false);
+    let path = function_args.get("path").and_then(|p| p.as_str());
+    let diff = GitRepository::discover(&std::env::current_dir()?)?.diff(staged, path)?;
+    match diff.is_empty() {
+      true => Ok(Some("no changes".to_string())),
+      false => Ok(Some(function_result(diff, session_config.function_result_max_tokens))),
+    }
+  }
+
+  fn function_definition(&self) -> FunctionCall {
+    let mut properties: HashMap<String, FunctionProperties> = HashMap::new();
+
+    self.required_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+    self.optional_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+
+    FunctionCall {
+      name: self.name.clone(),
+      description: Some(self.description.clone()),
+      parameters: Some(FunctionParameters {
+        param_type: "object".to_string(),
+        required: self.required_properties.clone().into_iter().map(|p| p.name).collect(),
+        properties,
+      }),
+    }
+  }
+}
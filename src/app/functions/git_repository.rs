@@ -0,0 +1,152 @@
+use std::path::{Component, Path, PathBuf};
+
+use git2::{Diff, DiffFormat, DiffOptions, Repository};
+
+use super::{argument_validation::count_tokens, errors::ToolCallError};
+
+// the git functions only read the repository, except commit, which records what the user already staged
+pub struct GitRepository {
+  repo: Repository,
+}
+
+// the text of a diff in patch format, cut off after max_bytes
+pub fn patch_text(diff: &Diff, max_bytes: usize) -> Result<String, git2::Error> {
+  let mut patch = String::new();
+  diff.print(DiffFormat::Patch, |_, _, line| {
+    if patch.len() < max_bytes {
+      if matches!(line.origin(), '+' | '-' | ' ') {
+        patch.push(line.origin());
+      }
+      patch.push_str(&String::from_utf8_lossy(line.content()));
+    }
+    true
+  })?;
+  if patch.len() >= max_bytes {
+    patch.push_str("\n[diff truncated]\n");
+  }
+  Ok(patch)
+}
+
+// the output, or a note that it is over the function result token limit
+pub fn function_result(output: String, max_tokens: usize) -> String {
+  match count_tokens(&output) {
+    tokens if tokens > max_tokens => {
+      format!("Function Token limit exceeded: {} tokens, narrow the request with path or line arguments", tokens)
+    },
+    _ => output,
+  }
+}
+
+// diffs larger than this are truncated before they are counted against the function result token limit
+const MAX_DIFF_BYTES: usize = 256 * 1024;
+
+impl GitRepository {
+  // the repository that contains path
+  pub fn discover(path: &Path) -> Result<Self, ToolCallError> {
+    Ok(GitRepository { repo: Repository::discover(path)? })
+  }
+
+  fn workdir(&self) -> Result<&Path, ToolCallError> {
+    self.repo.workdir().ok_or(ToolCallError::new("the repository has no working tree"))
+  }
+
+  // paths are relative to the working tree and can't leave it
+  fn relative_path(&self, path: &str) -> Result<PathBuf, ToolCallError> {
+    let path = Path::new(path);
+    match path.is_absolute() || path.components().any(|c| matches!(c, Component::ParentDir)) {
+      true => Err(ToolCallError::new("path must be relative to the repository root and stay inside it")),
+      false => Ok(path.to_path_buf()),
+    }
+  }
+
+  // the unstaged changes, or the changes staged for the next commit
+  pub fn diff(&self, staged: bool, path: Option<&str>) -> Result<String, ToolCallError> {
+    let mut options = DiffOptions::new();
+    if let Some(path) = path {
+      options.pathspec(self.relative_path(path)?);
+    }
+    let diff = match staged {
+      true => {
+        let head = self.repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+        self.repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))?
+      },
+      false => self.repo.diff_index_to_workdir(None, Some(&mut options))?,
+    };
+    Ok(patch_text(&diff, MAX_DIFF_BYTES)?)
+  }
+
+  // each line of a file with the commit, author and date that last changed it
+  pub fn blame(&self, path: &str, start_line: Option<usize>, end_line: Option<usize>) -> Result<String, ToolCallError> {
+    let relative = self.relative_path(path)?;
+    let blame = self.repo.blame_file(&relative, None)?;
+    let content = std::fs::read_to_string(self.workdir()?.join(&relative))?;
+    let start = start_line.unwrap_or(1).max(1);
+    let end = end_line.unwrap_or(usize::MAX);
+    let lines = content
+      .lines()
+      .enumerate()
+      .map(|(i, line)| (i + 1, line))
+      .filter(|(number, _)| (start..=end).contains(number))
+      .map(|(number, line)| match blame.get_line(number) {
+        Some(hunk) => {
+          let signature = hunk.final_signature();
+          let date = chrono::DateTime::from_timestamp(signature.when().seconds(), 0)
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+          let hash = hunk.final_commit_id().to_string();
+          format!("{} {} {} {}: {}", &hash[..8], signature.name().unwrap_or_default(), date, number, line)
+        },
+        None => format!("uncommitted {}: {}", number, line),
+      })
+      .collect::<Vec<String>>();
+    Ok(lines.join("\n"))
+  }
+
+  // commits what is staged with the user's git identity, nothing is staged here
+  pub fn commit(&self, message: &str) -> Result<String, ToolCallError> {
+    if message.trim().is_empty() {
+      return Err(ToolCallError::new("the commit message is empty"));
+    }
+    if self.diff(true, None)?.is_empty() {
+      return Err(ToolCallError::new("nothing is staged, the user needs to stage the changes to commit"));
+    }
+    let signature = self.repo.signature()?;
+    let tree = self.repo.find_tree(self.repo.index()?.write_tree()?)?;
+    let parent = self.repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents = parent.iter().collect::<Vec<_>>();
+    let oid = self.repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)?;
+    Ok(format!("committed {}", oid))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_diff_blame_and_commit() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "dev").unwrap();
+    config.set_str("user.email", "dev@example.com").unwrap();
+    std::fs::write(dir.path().join("retry.rs"), "fn retry() {}\n").unwrap();
+    let git = GitRepository::discover(dir.path()).unwrap();
+    assert!(git.commit("add retry").is_err());
+
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("retry.rs")).unwrap();
+    index.write().unwrap();
+    assert!(git.diff(true, None).unwrap().contains("+fn retry() {}"));
+    assert!(git.commit("add retry").unwrap().starts_with("committed "));
+    assert!(git.diff(true, None).unwrap().is_empty());
+
+    std::fs::write(dir.path().join("retry.rs"), "fn retry() {}\nfn backoff() {}\n").unwrap();
+    assert!(git.diff(false, Some("retry.rs")).unwrap().contains("+fn backoff() {}"));
+    let blame = git.blame("retry.rs", None, None).unwrap();
+    assert!(blame.contains(" dev "));
+    assert!(blame.contains("1: fn retry() {}"));
+    assert!(blame.contains("uncommitted 2: fn backoff() {}"));
+    assert!(git.blame("../outside.rs", None, None).is_err());
+  }
+}
@@ -7,25 +7,39 @@ use tokio::sync::mpsc::UnboundedSender;
 
 use self::modify_file_function::ModifyFileFunction;
 use self::{
-  create_file_function::CreateFileFunction, errors::ToolCallError, file_search_function::FileSearchFunction,
-  read_file_lines_function::ReadFileLinesFunction, types::FunctionCall,
+  apply_patch_function::ApplyPatchFunction, create_file_function::CreateFileFunction, errors::ToolCallError,
+  file_search_function::FileSearchFunction, git_blame_function::GitBlameFunction,
+  git_commit_function::GitCommitFunction, git_diff_function::GitDiffFunction, plugin_function::PluginFunction,
+  query_database_function::QueryDatabaseFunction, read_file_lines_function::ReadFileLinesFunction,
+  sandbox::{tool_call_resources, Resource},
+  types::FunctionCall,
+  unified_diff::PatchReview,
 };
 
 use super::session_config::SessionConfig;
 
+pub mod apply_patch_function;
 pub mod argument_validation;
 pub mod cargo_check_function;
 pub mod create_file_function;
 pub mod errors;
 pub mod file_search_function;
+pub mod git_blame_function;
+pub mod git_commit_function;
+pub mod git_diff_function;
+pub mod git_repository;
 pub mod grep_function;
 pub mod modify_file_function;
 pub mod patch_files_function;
 pub mod pcre2grep_function;
+pub mod plugin_function;
+pub mod query_database_function;
 pub mod read_file_lines_function;
+pub mod sandbox;
 pub mod tool_call;
 pub mod tool_call_template;
 pub mod types;
+pub mod unified_diff;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum CallableFunction {
@@ -35,6 +49,12 @@ pub enum CallableFunction {
   ReadFileLinesFunction(ReadFileLinesFunction),
   ModifyFileFunction(ModifyFileFunction),
   CreateFileFunction(CreateFileFunction),
+  GitDiffFunction(GitDiffFunction),
+  GitBlameFunction(GitBlameFunction),
+  GitCommitFunction(GitCommitFunction),
+  ApplyPatchFunction(ApplyPatchFunction),
+  QueryDatabaseFunction(QueryDatabaseFunction),
+  PluginFunction(PluginFunction),
   //PatchFileFunction(PatchFileFunction),
   //CargoCheckFunction(CargoCheckFunction),
 }
@@ -47,6 +67,12 @@ impl From<&CallableFunction> for FunctionCall {
       CallableFunction::ReadFileLinesFunction(f) => f.function_definition(),
       CallableFunction::ModifyFileFunction(f) => f.function_definition(),
       CallableFunction::CreateFileFunction(f) => f.function_definition(),
+      CallableFunction::GitDiffFunction(f) => f.function_definition(),
+      CallableFunction::GitBlameFunction(f) => f.function_definition(),
+      CallableFunction::GitCommitFunction(f) => f.function_definition(),
+      CallableFunction::ApplyPatchFunction(f) => f.function_definition(),
+      CallableFunction::QueryDatabaseFunction(f) => f.function_definition(),
+      CallableFunction::PluginFunction(f) => f.definition.clone(),
       //CallableFunction::PatchFileFunction(f) => f.command_definition(),
       // CallableFunction::CargoCheckFunction(f) => f.command_definition(),
     }
@@ -61,10 +87,97 @@ pub fn all_functions() -> Vec<CallableFunction> {
     CallableFunction::ReadFileLinesFunction(ReadFileLinesFunction::init()),
     // CallableFunction::ModifyFileFunction(ModifyFileFunction::init()),
     CallableFunction::CreateFileFunction(CreateFileFunction::init()),
+    CallableFunction::GitDiffFunction(GitDiffFunction::init()),
+    CallableFunction::GitBlameFunction(GitBlameFunction::init()),
+    CallableFunction::GitCommitFunction(GitCommitFunction::init()),
+    CallableFunction::ApplyPatchFunction(ApplyPatchFunction::init()),
     // CallableFunction::CargoCheckFunction(CargoCheckFunction::init()),
   ]
 }
 
+// the functions that need something configured first, such as a database to query
+pub fn configured_functions(session_config: &SessionConfig) -> Vec<CallableFunction> {
+  let mut functions = vec![];
+  if !session_config.databases.is_empty() {
+    functions
+      .push(CallableFunction::QueryDatabaseFunction(QueryDatabaseFunction::for_databases(&session_config.databases)));
+  }
+  functions
+}
+
+fn parse_function_args(fn_name: &str, fn_args: &str) -> Result<HashMap<String, serde_json::Value>, ToolCallError> {
+  let function_args_result: Result<HashMap<String, serde_json::Value>, serde_json::Error> =
+    serde_json::from_str(fn_args);
+  trace_dbg!("tool call: {}\narguments:\n{:#?}", fn_name, function_args_result);
+  function_args_result.map_err(|e| {
+    ToolCallError::new(
+      format!("Failed to parse function arguments:\nfunction:{:?}\nargs:{:?}\nerror:{:?}", fn_name, fn_args, e)
+        .as_str(),
+    )
+  })
+}
+
+// adds the output of a tool call to the session, the next completion is requested once every call of the response
+// has finished
+fn send_tool_output(
+  tx: &UnboundedSender<Action>,
+  tool_call_id: String,
+  output: Result<Option<String>, ToolCallError>,
+) {
+  match output {
+    Ok(Some(output)) => {
+      //self.data.add_message(ChatMessage::FunctionResult(FunctionResult { name: fn_name, response: output }));
+      trace_dbg!("tool output:\n{}", output);
+      tx.send(Action::AddMessage(ChatMessage::Tool(ChatCompletionRequestToolMessage {
+        tool_call_id: tool_call_id.clone(),
+        content: Some(output),
+        role: Role::Tool,
+      })))
+      .unwrap();
+    },
+    Ok(None) => {},
+    Err(e) => {
+      // self.data.add_message(ChatMessage::FunctionResult(FunctionResult {
+      //   name: fn_name,
+      //   response: format!("Error: {:?}", e),
+      // }));
+      tx.send(Action::AddMessage(ChatMessage::Tool(ChatCompletionRequestToolMessage {
+        tool_call_id: tool_call_id.clone(),
+        content: Some(format!("Error: {:?}", e)),
+        role: Role::Tool,
+      })))
+      .unwrap();
+    },
+  }
+  tx.send(Action::ToolCallFinished(tool_call_id)).unwrap();
+}
+
+// tells the model a tool call didn't run, such as when a hook refused it
+pub fn decline_tool_call(tx: &UnboundedSender<Action>, tool_call: &ChatCompletionMessageToolCall, reason: &str) {
+  send_tool_output(tx, tool_call.id.clone(), Ok(Some(reason.to_string())));
+}
+
+// the plugin with this name, if one was loaded for the session
+fn find_plugin(session_config: &SessionConfig, name: &str) -> Option<PluginFunction> {
+  session_config.available_functions.iter().find_map(|f| match f {
+    CallableFunction::PluginFunction(plugin) if plugin.name() == name => Some(plugin.clone()),
+    _ => None,
+  })
+}
+
+// what a call touches, plugins are programs of their own and are allowed like a command
+pub fn call_resources(
+  session_config: &SessionConfig,
+  fn_name: &str,
+  function_args: &HashMap<String, serde_json::Value>,
+) -> Vec<Resource> {
+  let mut resources = tool_call_resources(fn_name, function_args, &session_config.databases);
+  if find_plugin(session_config, fn_name).is_some() {
+    resources.push(Resource::Command(fn_name.to_string()));
+  }
+  resources
+}
+
 pub fn handle_tool_call(
   tx: UnboundedSender<Action>,
   tool_call: &ChatCompletionMessageToolCall,
@@ -72,58 +185,120 @@ pub fn handle_tool_call(
 ) {
   let fn_name = tool_call.function.name.clone();
   let fn_args = tool_call.function.arguments.clone();
+  // the user is asked about anything the sandbox policy doesn't cover yet, see Action::PermissionAnswered, a call
+  // whose arguments can't be read is refused rather than run unchecked
+  let function_args = match parse_function_args(&fn_name, &fn_args) {
+    Ok(function_args) => function_args,
+    Err(e) => {
+      send_tool_output(&tx, tool_call.id.clone(), Err(e));
+      return;
+    },
+  };
+  match session_config.sandbox.check(&call_resources(&session_config, &fn_name, &function_args)) {
+    Err(resource) => {
+      let message = format!("access to {} is denied by the sandbox policy", resource);
+      send_tool_output(&tx, tool_call.id.clone(), Err(ToolCallError::new(&message)));
+      return;
+    },
+    Ok(ask) if !ask.is_empty() => {
+      tx.send(Action::RequestPermission(tool_call.clone(), ask)).unwrap();
+      return;
+    },
+    Ok(_) => {},
+  }
+  // an agent runs without the user reading along, so every call that changes something waits for the user, commits
+  // and patches are confirmed or reviewed below either way
+  let confirmed_below = matches!(fn_name.as_str(), "git_commit" | "apply_patch");
+  if session_config.confirm_side_effects && has_side_effects(&session_config, &fn_name) && !confirmed_below {
+    let description = format!("The agent wants to call {} with\n\n{}", fn_name, fn_args);
+    tx.send(Action::ConfirmToolCall(tool_call.clone(), description)).unwrap();
+    return;
+  }
+  // functions that change the repository wait for the user, see handle_confirmed_tool_call
+  if fn_name == "git_commit" {
+    tx.send(Action::ConfirmToolCall(tool_call.clone(), GitCommitFunction::confirmation(&function_args))).unwrap();
+    return;
+  }
+  if fn_name == "apply_patch" {
+    match ApplyPatchFunction::review(&function_args) {
+      Ok(review) => tx.send(Action::ReviewPatch(tool_call.clone(), review)).unwrap(),
+      Err(e) => send_tool_output(&tx, tool_call.id.clone(), Err(e)),
+    }
+    return;
+  }
   let tc_clone = tool_call.clone();
-  tokio::spawn(async move {
-    match {
-      async move {
-        let function_args_result: Result<HashMap<String, serde_json::Value>, serde_json::Error> =
-          serde_json::from_str(fn_args.as_str());
-        trace_dbg!("tool call: {}\narguments:\n{:#?}", fn_name.clone(), function_args_result);
-        match function_args_result {
-          Ok(function_args) => match fn_name.as_str() {
-            "create_file" => CreateFileFunction::init().call(function_args, session_config),
-            //"git_apply" => PatchFileFunction::init().call(function_args, session_config),
-            //"grep" => GrepFunction::init().call(function_args, session_config),
-            "file_search" => FileSearchFunction::init().call(function_args, session_config),
-            "read_file" => ReadFileLinesFunction::init().call(function_args, session_config),
-            //"modify_file" => ModifyFileFunction::init().call(function_args, session_config),
-            //"cargo_check" => CargoCheckFunction::init().call(function_args, session_config),
-            //"pcre2grep" => Pcre2GrepFunction::init().call(function_args, session_config),
-            _ => Ok(Some("function not found".to_string())),
-          },
-          Err(e) => Err(ToolCallError::new(
-            format!("Failed to parse function arguments:\nfunction:{:?}\nargs:{:?}\nerror:{:?}", fn_name, fn_args, e)
-              .as_str(),
-          )),
+  // the functions block, so each call runs on its own thread and the calls of a response run in parallel
+  tokio::task::spawn_blocking(move || {
+    let output = call_function(&fn_name, function_args, session_config);
+    send_tool_output(&tx, tc_clone.id, output);
+  });
+}
+
+// calls a function that runs without the user's confirmation, blocking until it returns
+pub fn call_function(
+  fn_name: &str,
+  function_args: HashMap<String, serde_json::Value>,
+  session_config: SessionConfig,
+) -> Result<Option<String>, ToolCallError> {
+  let max_tokens = session_config.function_result_max_tokens;
+  let output = match fn_name {
+    "create_file" => CreateFileFunction::init().call(function_args, session_config),
+    //"git_apply" => PatchFileFunction::init().call(function_args, session_config),
+    //"grep" => GrepFunction::init().call(function_args, session_config),
+    "file_search" => FileSearchFunction::init().call(function_args, session_config),
+    "read_file" => ReadFileLinesFunction::init().call(function_args, session_config),
+    "git_diff" => GitDiffFunction::init().call(function_args, session_config),
+    "git_blame" => GitBlameFunction::init().call(function_args, session_config),
+    "query_database" => QueryDatabaseFunction::init().call(function_args, session_config),
+    //"modify_file" => ModifyFileFunction::init().call(function_args, session_config),
+    //"cargo_check" => CargoCheckFunction::init().call(function_args, session_config),
+    //"pcre2grep" => Pcre2GrepFunction::init().call(function_args, session_config),
+    _ => match find_plugin(&session_config, fn_name) {
+      Some(plugin) => plugin.call(function_args),
+      None => Ok(Some("function not found".to_string())),
+    },
+  };
+  // output over function_result_max_tokens is cut down here, so that it can't fill the context of any front end
+  output.map(|output| output.map(|output| argument_validation::truncate_tokens(&output, max_tokens)))
+}
+
+// functions that write files or the repository, plugins are included since what they do is unknown
+pub fn has_side_effects(session_config: &SessionConfig, fn_name: &str) -> bool {
+  matches!(fn_name, "create_file" | "git_commit" | "apply_patch") || find_plugin(session_config, fn_name).is_some()
+}
+
+// runs a tool call the user confirmed, or tells the model the user declined it
+pub fn handle_confirmed_tool_call(
+  tx: UnboundedSender<Action>,
+  tool_call: ChatCompletionMessageToolCall,
+  confirmed: bool,
+  session_config: SessionConfig,
+) {
+  tokio::task::spawn_blocking(move || {
+    let output = match confirmed {
+      true => parse_function_args(&tool_call.function.name, &tool_call.function.arguments).and_then(|function_args| {
+        match tool_call.function.name.as_str() {
+          "git_commit" => GitCommitFunction::init().call(function_args, session_config),
+          name => call_function(name, function_args, session_config),
         }
-      }
-    }
-    .await
-    {
-      Ok(Some(output)) => {
-        //self.data.add_message(ChatMessage::FunctionResult(FunctionResult { name: fn_name, response: output }));
-        trace_dbg!("tool output:\n{}", output);
-        tx.send(Action::AddMessage(ChatMessage::Tool(ChatCompletionRequestToolMessage {
-          tool_call_id: tc_clone.id,
-          content: Some(output),
-          role: Role::Tool,
-        })))
-        .unwrap();
-      },
-      Ok(None) => {},
-      Err(e) => {
-        // self.data.add_message(ChatMessage::FunctionResult(FunctionResult {
-        //   name: fn_name,
-        //   response: format!("Error: {:?}", e),
-        // }));
-        tx.send(Action::AddMessage(ChatMessage::Tool(ChatCompletionRequestToolMessage {
-          tool_call_id: tc_clone.id,
-          content: Some(format!("Error: {:?}", e)),
-          role: Role::Tool,
-        })))
-        .unwrap();
-      },
-    }
-    tx.send(Action::RequestChatCompletion()).unwrap();
+      }),
+      false => Ok(Some(format!("the user declined the {} call", tool_call.function.name))),
+    };
+    send_tool_output(&tx, tool_call.id, output);
+  });
+}
+
+// writes the hunks the user accepted, a patch with none accepted was rejected as a whole
+pub fn handle_reviewed_patch(
+  tx: UnboundedSender<Action>,
+  tool_call: ChatCompletionMessageToolCall,
+  review: PatchReview,
+) {
+  tokio::task::spawn_blocking(move || {
+    let output = match review.accepted_count() {
+      0 => Ok(Some("the user rejected the patch, nothing was written".to_string())),
+      _ => review.apply().map(Some),
+    };
+    send_tool_output(&tx, tool_call.id, output);
   });
 }
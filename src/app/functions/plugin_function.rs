@@ -0,0 +1,123 @@
+use std::{
+  collections::HashMap,
+  io::Write,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{errors::ToolCallError, types::FunctionCall};
+
+// an executable in the plugins directory, `<plugin> --manifest` prints its function definition as json, then each
+// call runs it with the arguments as json on stdin and stdout as the result
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PluginFunction {
+  pub path: PathBuf,
+  pub definition: FunctionCall,
+}
+
+impl PluginFunction {
+  pub fn load(path: &Path) -> Result<Self, ToolCallError> {
+    let output = Command::new(path).arg("--manifest").stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+      return Err(ToolCallError::new(&format!(
+        "{} --manifest failed: {}",
+        path.display(),
+        String::from_utf8_lossy(&output.stderr).trim()
+      )));
+    }
+    let mut definition: FunctionCall = serde_json::from_slice(&output.stdout)?;
+    // property names are the keys of the properties map
+    if let Some(parameters) = definition.parameters.as_mut() {
+      for (name, property) in parameters.properties.iter_mut() {
+        property.name = name.clone();
+        property.required = parameters.required.contains(name);
+      }
+    }
+    Ok(PluginFunction { path: path.to_path_buf(), definition })
+  }
+
+  pub fn name(&self) -> &str {
+    &self.definition.name
+  }
+
+  pub fn call(&self, function_args: HashMap<String, serde_json::Value>) -> Result<Option<String>, ToolCallError> {
+    let mut child =
+      Command::new(&self.path).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin.write_all(serde_json::to_string(&function_args)?.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    match output.status.success() {
+      true => Ok(Some(String::from_utf8_lossy(&output.stdout).to_string())),
+      false => Err(ToolCallError::new(&format!(
+        "plugin {} exited with {}: {}",
+        self.name(),
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+      ))),
+    }
+  }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+  use std::os::unix::fs::PermissionsExt;
+  path.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+  path.is_file()
+}
+
+// every executable in dir with a valid manifest, plugins that fail to load are logged and skipped
+pub fn load_plugins(dir: &Path) -> Vec<PluginFunction> {
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return vec![];
+  };
+  let mut paths = entries.flatten().map(|entry| entry.path()).filter(|path| is_executable(path)).collect::<Vec<_>>();
+  paths.sort();
+  paths
+    .iter()
+    .filter_map(|path| match PluginFunction::load(path) {
+      Ok(plugin) => Some(plugin),
+      Err(e) => {
+        log::warn!("failed to load plugin {}: {}", path.display(), e);
+        None
+      },
+    })
+    .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use std::os::unix::fs::PermissionsExt;
+
+  use super::*;
+
+  #[test]
+  fn test_plugin_manifest_and_call() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shout");
+    let script = r#"#!/bin/sh
+if [ "$1" = "--manifest" ]; then
+  echo '{"name": "shout", "description": "upper cases text", "parameters": {"type": "object", "required": ["text"],
+    "properties": {"text": {"type": "string", "description": "text to shout"}}}}'
+else
+  tr a-z A-Z
+fi
+"#;
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(dir.path().join("README"), "not a plugin").unwrap();
+
+    let plugins = load_plugins(dir.path());
+    assert_eq!(plugins.len(), 1);
+    assert_eq!(plugins[0].name(), "shout");
+    assert!(plugins[0].definition.parameters.as_ref().unwrap().properties["text"].required);
+    let args = HashMap::from([("text".to_string(), serde_json::json!("hi"))]);
+    assert_eq!(plugins[0].call(args).unwrap().unwrap(), "{\"TEXT\":\"HI\"}");
+  }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::app::{
+  database::{DatabaseConfig, DatabaseConnection},
+  session_config::SessionConfig,
+};
+
+use super::{
+  errors::ToolCallError,
+  git_repository::function_result,
+  tool_call::ToolCallTrait,
+  types::{FunctionCall, FunctionParameters, FunctionProperties},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryDatabaseFunction {
+  pub name: String,
+  pub description: String,
+  pub required_properties: Vec<FunctionProperties>,
+  pub optional_properties: Vec<FunctionProperties>,
+}
+
+impl QueryDatabaseFunction {
+  // the function offered when databases are configured, with their names to choose from
+  pub fn for_databases(databases: &HashMap<String, DatabaseConfig>) -> Self {
+    let mut function = Self::init();
+    let mut names = databases.keys().cloned().collect::<Vec<String>>();
+    names.sort();
+    function.optional_properties[0].enum_values = Some(names);
+    function
+  }
+
+  // the database named in the arguments, or the only one configured
+  pub fn database<'a>(
+    databases: &'a HashMap<String, DatabaseConfig>,
+    name: Option<&str>,
+  ) -> Result<&'a DatabaseConfig, ToolCallError> {
+    match (name, databases.len()) {
+      (Some(name), _) => {
+        databases.get(name).ok_or_else(|| ToolCallError::new(&format!("no database is named {}", name)))
+      },
+      (None, 1) => Ok(databases.values().next().unwrap()),
+      (None, 0) => Err(ToolCallError::new("no databases are configured")),
+      (None, _) => Err(ToolCallError::new("database argument is required when several databases are configured")),
+    }
+  }
+}
+
+impl ToolCallTrait for QueryDatabaseFunction {
+  fn init() -> Self {
+    QueryDatabaseFunction {
+      name: "query_database".to_string(),
+      description: "run a read only SQL query against a configured database and return the rows, the tables of an \
+                    ingested database are described in the retrieved context"
+        .to_string(),
+      required_properties: vec![FunctionProperties {
+        name: "sql".to_string(),
+        required: true,
+        property_type: "string".to_string(),
+        description: Some("a single SELECT, WITH, VALUES or EXPLAIN statement".to_string()),
+        enum_values: None,
+      }],
+      optional_properties: vec![FunctionProperties {
+        name: "database".to_string(),
+        required: false,
+        property_type: "string".to_string(),
+        description: Some("name of the database, default: the only one configured".to_string()),
+        enum_values: None,
+      }],
+    }
+  }
+
+  fn call(
+    &self,
+    function_args: HashMap<String, serde_json::Value>,
+    session_config: SessionConfig,
+  ) -> Result<Option<String>, ToolCallError> {
+    let sql =
+      function_args.get("sql").and_then(|s| s.as_str()).ok_or(ToolCallError::new("sql argument is required"))?;
+    let name = function_args.get("database").and_then(|d| d.as_str());
+    let database = Self::database(&session_config.databases, name)?;
+    let result = DatabaseConnection::connect(database)
+      .and_then(|mut connection| connection.query(sql, database.max_rows))
+      .map_err(|e| ToolCallError::new(&e.to_string()))?;
+    Ok(Some(function_result(result.to_string(), session_config.function_result_max_tokens)))
+  }
+
+  fn function_definition(&self) -> FunctionCall {
+    let mut properties: HashMap<String, FunctionProperties> = HashMap::new();
+
+    self.required_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+    self.optional_properties.iter().for_each(|p| {
+      properties.insert(p.name.clone(), p.clone());
+    });
+
+    FunctionCall {
+      name: self.name.clone(),
+      description: Some(self.description.clone()),
+      parameters: Some(FunctionParameters {
+        param_type: "object".to_string(),
+        required: self.required_properties.clone().into_iter().map(|p| p.name).collect(),
+        properties,
+      }),
+    }
+  }
+}
@@ -14,7 +14,7 @@ pub struct FunctionProperties {
   pub enum_values: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FunctionParameters {
   #[serde(rename = "type")]
   pub param_type: String,
@@ -22,7 +22,7 @@ pub struct FunctionParameters {
   pub properties: std::collections::HashMap<String, FunctionProperties>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FunctionCall {
   pub name: String,
   pub description: Option<String>,
@@ -0,0 +1,262 @@
+use std::path::{Component, Path, PathBuf};
+
+use patch::{Line, Patch};
+use serde_derive::{Deserialize, Serialize};
+
+use super::errors::ToolCallError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FileChange {
+  Modify,
+  Create,
+  Delete,
+}
+
+// a hunk of a patch the model proposed, written only when it is accepted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReviewHunk {
+  pub header: String,
+  // the line the hunk starts at in the current file, from 1
+  pub old_start: usize,
+  pub old_lines: Vec<String>,
+  pub new_lines: Vec<String>,
+  // the hunk as it appears in the diff, for the review pane
+  pub diff_lines: Vec<String>,
+  pub accepted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReviewFile {
+  // relative to the working directory
+  pub path: String,
+  pub change: FileChange,
+  pub hunks: Vec<ReviewHunk>,
+}
+
+// a validated unified diff, every hunk is known to apply to the current files
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PatchReview {
+  pub root: PathBuf,
+  pub files: Vec<ReviewFile>,
+}
+
+// git headers the patch parser doesn't know, the --- and +++ lines carry the paths
+fn strip_git_headers(patch: &str) -> String {
+  let mut text = patch
+    .lines()
+    .filter(|line| {
+      !["diff --git ", "index ", "new file mode ", "deleted file mode ", "similarity index ", "old mode ", "new mode "]
+        .iter()
+        .any(|header| line.starts_with(header))
+    })
+    .collect::<Vec<&str>>()
+    .join("\n");
+  text.push('\n');
+  text
+}
+
+// diff paths are relative to the working directory, with an optional a/ or b/ prefix, and can't leave it
+fn sandboxed_path(path: &str) -> Result<String, ToolCallError> {
+  let path = path.strip_prefix("a/").or_else(|| path.strip_prefix("b/")).unwrap_or(path);
+  let relative = Path::new(path);
+  match relative.is_absolute() || relative.components().any(|c| matches!(c, Component::ParentDir)) {
+    true => Err(ToolCallError::new(&format!("{} is outside the working directory", path))),
+    false => Ok(path.to_string()),
+  }
+}
+
+// the index of the first line of the hunk in lines, the match closest to where the hunk says it starts wins
+fn hunk_position(lines: &[&str], from: usize, hunk: &ReviewHunk) -> Option<usize> {
+  let expected = hunk.old_start.saturating_sub(1).max(from);
+  if hunk.old_lines.is_empty() {
+    return Some(hunk.old_start.clamp(from, lines.len()));
+  }
+  let matches_at = |i: usize| {
+    lines.len() >= i + hunk.old_lines.len() && hunk.old_lines.iter().zip(&lines[i..]).all(|(old, line)| old == line)
+  };
+  (from..=lines.len().saturating_sub(hunk.old_lines.len()))
+    .filter(|&i| matches_at(i))
+    .min_by_key(|&i| (i as i64 - expected as i64).abs())
+}
+
+// content with the hunks applied in order
+fn apply_hunks(path: &str, content: &str, hunks: &[&ReviewHunk]) -> Result<String, ToolCallError> {
+  let lines = content.lines().collect::<Vec<&str>>();
+  let mut result: Vec<&str> = vec![];
+  let mut cursor = 0;
+  for hunk in hunks {
+    let position = hunk_position(&lines, cursor, hunk).ok_or_else(|| {
+      ToolCallError::new(&format!("hunk {} does not match the current content of {}", hunk.header, path))
+    })?;
+    result.extend(&lines[cursor..position]);
+    result.extend(hunk.new_lines.iter().map(|line| line.as_str()));
+    cursor = position + hunk.old_lines.len();
+  }
+  result.extend(&lines[cursor..]);
+  let mut text = result.join("\n");
+  if !text.is_empty() && (content.ends_with('\n') || content.is_empty()) {
+    text.push('\n');
+  }
+  Ok(text)
+}
+
+impl PatchReview {
+  // parses a unified diff and checks that each hunk applies to the files under root
+  pub fn parse(patch: &str, root: &Path) -> Result<Self, ToolCallError> {
+    let text = strip_git_headers(patch);
+    let patches = Patch::from_multiple(&text).map_err(|e| ToolCallError::new(&format!("invalid unified diff: {}", e)))?;
+    if patches.is_empty() {
+      return Err(ToolCallError::new("the patch contains no changes"));
+    }
+    let mut files = vec![];
+    for patch in patches {
+      let (path, change) = match (patch.old.path.as_ref(), patch.new.path.as_ref()) {
+        ("/dev/null", new) => (sandboxed_path(new)?, FileChange::Create),
+        (old, "/dev/null") => (sandboxed_path(old)?, FileChange::Delete),
+        (_, new) => (sandboxed_path(new)?, FileChange::Modify),
+      };
+      let hunks = patch
+        .hunks
+        .iter()
+        .map(|hunk| {
+          let mut old_lines = vec![];
+          let mut new_lines = vec![];
+          let mut diff_lines = vec![];
+          for line in &hunk.lines {
+            match line {
+              Line::Context(text) => {
+                old_lines.push(text.to_string());
+                new_lines.push(text.to_string());
+                diff_lines.push(format!(" {}", text));
+              },
+              Line::Remove(text) => {
+                old_lines.push(text.to_string());
+                diff_lines.push(format!("-{}", text));
+              },
+              Line::Add(text) => {
+                new_lines.push(text.to_string());
+                diff_lines.push(format!("+{}", text));
+              },
+            }
+          }
+          ReviewHunk {
+            header: format!(
+              "@@ -{},{} +{},{} @@",
+              hunk.old_range.start, hunk.old_range.count, hunk.new_range.start, hunk.new_range.count
+            ),
+            old_start: hunk.old_range.start as usize,
+            old_lines,
+            new_lines,
+            diff_lines,
+            accepted: true,
+          }
+        })
+        .collect::<Vec<ReviewHunk>>();
+      files.push(ReviewFile { path, change, hunks });
+    }
+    let review = PatchReview { root: root.to_path_buf(), files };
+    for file in &review.files {
+      review.patched_content(file, true)?;
+    }
+    Ok(review)
+  }
+
+  pub fn hunk_count(&self) -> usize {
+    self.files.iter().map(|file| file.hunks.len()).sum()
+  }
+
+  pub fn accepted_count(&self) -> usize {
+    self.files.iter().flat_map(|file| &file.hunks).filter(|hunk| hunk.accepted).count()
+  }
+
+  pub fn hunk_mut(&mut self, index: usize) -> Option<&mut ReviewHunk> {
+    self.files.iter_mut().flat_map(|file| file.hunks.iter_mut()).nth(index)
+  }
+
+  pub fn set_all_accepted(&mut self, accepted: bool) {
+    self.files.iter_mut().flat_map(|file| file.hunks.iter_mut()).for_each(|hunk| hunk.accepted = accepted);
+  }
+
+  // the file content with the accepted hunks, or every hunk, applied
+  fn patched_content(&self, file: &ReviewFile, all: bool) -> Result<String, ToolCallError> {
+    let current = match file.change {
+      FileChange::Create if self.root.join(&file.path).exists() => {
+        return Err(ToolCallError::new(&format!("{} already exists", file.path)))
+      },
+      FileChange::Create => String::new(),
+      _ => std::fs::read_to_string(self.root.join(&file.path))
+        .map_err(|e| ToolCallError::new(&format!("failed to read {}: {}", file.path, e)))?,
+    };
+    let hunks = file.hunks.iter().filter(|hunk| all || hunk.accepted).collect::<Vec<&ReviewHunk>>();
+    apply_hunks(&file.path, &current, &hunks)
+  }
+
+  // writes the accepted hunks and describes what was written and what was rejected
+  pub fn apply(&self) -> Result<String, ToolCallError> {
+    let mut report = vec![];
+    for file in &self.files {
+      let accepted = file.hunks.iter().filter(|hunk| hunk.accepted).count();
+      let path = self.root.join(&file.path);
+      match file.change {
+        _ if accepted == 0 => {},
+        FileChange::Delete if accepted == file.hunks.len() => std::fs::remove_file(&path)?,
+        // a delete that is only partly accepted leaves the remaining lines
+        _ => {
+          let content = self.patched_content(file, false)?;
+          if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+          }
+          std::fs::write(&path, content)?;
+        },
+      }
+      report.push(format!("{}: {} of {} hunks applied", file.path, accepted, file.hunks.len()));
+    }
+    if self.accepted_count() < self.hunk_count() {
+      report.push("the user rejected the hunks that were not applied".to_string());
+    }
+    Ok(report.join("\n"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PATCH: &str = "diff --git a/retry.rs b/retry.rs
+--- a/retry.rs
++++ b/retry.rs
+@@ -1,2 +1,2 @@
+-fn retry() {}
++fn retry(attempts: u32) {}
+ fn main() {}
+@@ -3,2 +3,3 @@
+ fn backoff() {}
+ fn jitter() {}
++fn timeout() {}
+";
+
+  #[test]
+  fn test_apply_accepted_hunks_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = "fn retry() {}\nfn main() {}\nfn backoff() {}\nfn jitter() {}\n";
+    std::fs::write(dir.path().join("retry.rs"), content).unwrap();
+    let mut review = PatchReview::parse(PATCH, dir.path()).unwrap();
+    assert_eq!(review.hunk_count(), 2);
+    review.hunk_mut(1).unwrap().accepted = false;
+
+    assert_eq!(review.apply().unwrap().lines().next().unwrap(), "retry.rs: 1 of 2 hunks applied");
+    assert_eq!(
+      std::fs::read_to_string(dir.path().join("retry.rs")).unwrap(),
+      "fn retry(attempts: u32) {}\nfn main() {}\nfn backoff() {}\nfn jitter() {}\n"
+    );
+  }
+
+  #[test]
+  fn test_parse_rejects_mismatched_and_escaping_patches() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("retry.rs"), "fn other() {}\n").unwrap();
+    assert!(PatchReview::parse(PATCH, dir.path()).is_err());
+    let escaping = PATCH.replace("a/retry.rs", "../retry.rs").replace("b/retry.rs", "../retry.rs");
+    assert!(PatchReview::parse(&escaping, dir.path()).is_err());
+  }
+}
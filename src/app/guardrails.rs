@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fmt};
+
+use async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequest};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{compression::message_text, functions::argument_validation::count_tokens, model_list::ModelPricing};
+
+// the number of messages listed as driving the size of a request
+const LARGEST_MESSAGES: usize = 3;
+
+// requests over either threshold need to be confirmed before they are sent, None disables the check
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfirmThresholds {
+  pub tokens: Option<usize>,
+  // dollars, estimated from model_pricing
+  pub cost: Option<f64>,
+}
+
+impl Default for ConfirmThresholds {
+  fn default() -> Self {
+    ConfirmThresholds { tokens: Some(50_000), cost: Some(0.50) }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestEstimate {
+  pub model: String,
+  pub prompt_tokens: usize,
+  // assumes the response uses all of max_tokens, so the estimate is an upper bound
+  pub cost: Option<f64>,
+  // the largest messages in the request, with their token counts
+  pub largest_messages: Vec<(String, usize)>,
+}
+
+impl RequestEstimate {
+  pub fn new(request: &CreateChatCompletionRequest, pricing: &HashMap<String, ModelPricing>) -> Self {
+    let mut message_tokens: Vec<(String, usize)> = request
+      .messages
+      .iter()
+      .map(|message| {
+        let text = message_text(message).map(|t| t.as_str()).unwrap_or_default();
+        (describe_message(message, text), count_tokens(text))
+      })
+      .collect();
+    let prompt_tokens = message_tokens.iter().map(|(_, tokens)| tokens).sum();
+    message_tokens.sort_by(|a, b| b.1.cmp(&a.1));
+    message_tokens.truncate(LARGEST_MESSAGES);
+    let unqualified_model = request.model.rsplit('/').next().unwrap_or(&request.model);
+    let cost = pricing.get(&request.model).or_else(|| pricing.get(unqualified_model)).map(|pricing| {
+      let completion_tokens = request.max_tokens.unwrap_or_default() as f64;
+      (prompt_tokens as f64 * pricing.prompt + completion_tokens * pricing.completion) / 1000.0
+    });
+    RequestEstimate { model: request.model.clone(), prompt_tokens, cost, largest_messages: message_tokens }
+  }
+
+  // the reasons the request needs to be confirmed, empty when it can be sent without asking
+  pub fn exceeded(&self, thresholds: &ConfirmThresholds) -> Vec<String> {
+    let mut reasons = vec![];
+    if let Some(max_tokens) = thresholds.tokens.filter(|max| self.prompt_tokens > *max) {
+      reasons.push(format!("{} prompt tokens is over the {} token threshold", self.prompt_tokens, max_tokens));
+    }
+    if let (Some(cost), Some(max_cost)) = (self.cost, thresholds.cost) {
+      if cost > max_cost {
+        reasons.push(format!("estimated cost of ${:.2} is over the ${:.2} threshold", cost, max_cost));
+      }
+    }
+    reasons
+  }
+}
+
+impl fmt::Display for RequestEstimate {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} prompt tokens to {}", self.prompt_tokens, self.model)?;
+    if let Some(cost) = self.cost {
+      write!(f, ", up to ${:.2}", cost)?;
+    }
+    writeln!(f, "\nlargest messages:")?;
+    for (description, tokens) in self.largest_messages.iter() {
+      writeln!(f, "  {:>7} tokens  {}", tokens, description)?;
+    }
+    Ok(())
+  }
+}
+
+pub fn describe_message(message: &ChatCompletionRequestMessage, text: &str) -> String {
+  let role = match message {
+    ChatCompletionRequestMessage::System(_) => "system",
+    ChatCompletionRequestMessage::User(_) => "user",
+    ChatCompletionRequestMessage::Assistant(_) => "assistant",
+    ChatCompletionRequestMessage::Tool(_) => "tool result",
+    ChatCompletionRequestMessage::Function(_) => "function result",
+  };
+  let first_line = text.lines().find(|l| !l.trim().is_empty()).unwrap_or_default().trim();
+  match first_line.char_indices().nth(50) {
+    Some((i, _)) => format!("{}: {}...", role, &first_line[..i]),
+    None => format!("{}: {}", role, first_line),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role};
+
+  use super::*;
+
+  fn request(model: &str, texts: &[&str]) -> CreateChatCompletionRequest {
+    CreateChatCompletionRequest {
+      model: model.to_string(),
+      max_tokens: Some(1000),
+      messages: texts
+        .iter()
+        .map(|text| {
+          ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+            role: Role::User,
+            content: Some(ChatCompletionRequestUserMessageContent::Text(text.to_string())),
+          })
+        })
+        .collect(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_request_estimate_thresholds() {
+    let pricing = HashMap::from([("gpt-4".to_string(), ModelPricing { prompt: 0.03, completion: 0.06 })]);
+    let large = "word ".repeat(2000);
+    let estimate = RequestEstimate::new(&request("openai/gpt-4", &["small question", &large]), &pricing);
+    assert!(estimate.prompt_tokens >= 2000);
+    assert!(estimate.largest_messages[0].0.starts_with("user: word word"));
+    let cost = estimate.cost.unwrap();
+    assert!(cost > 0.06 && cost < 0.2);
+    assert!(estimate.exceeded(&ConfirmThresholds::default()).is_empty());
+    assert_eq!(estimate.exceeded(&ConfirmThresholds { tokens: Some(1000), cost: Some(0.05) }).len(), 2);
+    assert!(estimate.exceeded(&ConfirmThresholds { tokens: None, cost: None }).is_empty());
+  }
+}
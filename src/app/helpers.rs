@@ -1,10 +1,10 @@
 use async_openai::types::{
   ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk, ChatCompletionRequestAssistantMessage,
   ChatCompletionResponseStreamMessage, ChatCompletionStreamResponseDelta, CreateChatCompletionResponse,
-  CreateChatCompletionStreamResponse, FinishReason, FunctionCall, FunctionCallStream, Role,
+  CreateChatCompletionStreamResponse, FinishReason, FunctionCallStream, Role,
 };
 
-use super::errors::ParseError;
+use super::{errors::ParseError, tool_call_stream::ToolCallAssembler};
 
 pub fn concatenate_option_strings(a: Option<String>, b: Option<String>) -> Option<String> {
   match (a, b) {
@@ -58,7 +58,7 @@ pub fn concatenate_stream_delta(
   delta2: ChatCompletionStreamResponseDelta,
 ) -> ChatCompletionStreamResponseDelta {
   ChatCompletionStreamResponseDelta {
-    role: delta1.role,
+    role: delta1.role.or(delta2.role),
     content: concatenate_option_strings(delta1.content, delta2.content),
     tool_calls: concatenate_option_vecs::<ChatCompletionMessageToolCallChunk>(delta1.tool_calls, delta2.tool_calls),
     function_call: concatenate_function_call_streams(delta1.function_call, delta2.function_call),
@@ -72,12 +72,9 @@ pub fn concatenate_create_chat_completion_stream_response(
   if sr1.id != sr2.id {
     Err(ParseError::new("Cannot concatenate two stream responses with different ids"))
   } else {
-    let mut combined_choices = Vec::new();
-    combined_choices.extend(sr1.choices.clone());
-    combined_choices.extend(sr2.choices.clone());
     Ok(CreateChatCompletionStreamResponse {
       id: sr1.id.clone(),
-      choices: combined_choices,
+      choices: merge_stream_choices(&[sr1.clone(), sr2.clone()]),
       created: sr2.created,
       model: sr2.model.clone(),
       system_fingerprint: sr2.system_fingerprint.clone(),
@@ -108,65 +105,57 @@ pub fn concatenate_stream_response_messages(
     Ok(ChatCompletionResponseStreamMessage {
       index: sr1.index,
       delta: concatenate_stream_delta(sr1.delta.clone(), sr2.delta.clone()),
-      finish_reason: concatenate_finish_reason(sr1.finish_reason, sr2.finish_reason).unwrap(),
+      finish_reason: concatenate_finish_reason(sr1.finish_reason, sr2.finish_reason).unwrap_or(sr1.finish_reason),
     })
   }
 }
 
-pub fn convert_tool_chunk_to_tool_call(chunk: &ChatCompletionMessageToolCallChunk) -> ChatCompletionMessageToolCall {
-  ChatCompletionMessageToolCall {
-    id: chunk.id.clone().unwrap_or("".to_string()),
-    r#type: chunk.r#type.clone().unwrap_or_default(),
-    function: FunctionCall {
-      name: chunk.function.clone().unwrap().name.unwrap_or("".to_string()),
-      arguments: chunk.function.clone().unwrap().arguments.unwrap_or("".to_string()),
-    },
-  }
-}
-pub fn append_tool_call_chunk_to_tool_call(
-  call: &mut ChatCompletionMessageToolCall,
-  chunk: &ChatCompletionMessageToolCallChunk,
-) {
-  let (name, arguments) = match &chunk.function {
-    Some(fc) => (fc.name.clone().unwrap_or("".to_string()), fc.arguments.clone().unwrap_or("".to_string())),
-    None => ("".to_string(), "".to_string()),
-  };
-  call.id += &chunk.id.clone().unwrap_or("".to_string());
-  call.function.name += name.as_str();
-  call.function.arguments += arguments.as_str();
-}
-
 pub fn collate_tool_call_chunks_into_tool_calls(
   tc_chunks: Vec<ChatCompletionMessageToolCallChunk>,
 ) -> Option<Vec<ChatCompletionMessageToolCall>> {
-  let mut tc_calls: Vec<ChatCompletionMessageToolCall> = Vec::new();
-  tc_chunks.iter().for_each(|tc_chunk| {
-    // trace_dbg!("tc_chunk: {:?}", tc_chunk);
-    // trace_dbg!("tc_calls.len(): {:?}", tc_calls.len());
-    match tc_calls.get_mut(tc_chunk.index as usize) {
-      Some(tc_call) => append_tool_call_chunk_to_tool_call(tc_call, tc_chunk),
-      None => tc_calls.push(convert_tool_chunk_to_tool_call(tc_chunk)),
+  let mut assembler = ToolCallAssembler::default();
+  tc_chunks.iter().for_each(|tc_chunk| assembler.push(tc_chunk));
+  assembler.tool_calls()
+}
+
+// the deltas of each choice of a stream merged in the order they arrived, one message per choice in index order.
+// chunks without choices, such as the content filter results some providers send first, add nothing, and a role or
+// finish reason repeated by a later chunk of the choice is dropped
+pub fn merge_stream_choices(srvec: &[CreateChatCompletionStreamResponse]) -> Vec<ChatCompletionResponseStreamMessage> {
+  let mut merged: Vec<ChatCompletionResponseStreamMessage> = Vec::new();
+  for choice in srvec.iter().flat_map(|sr| &sr.choices) {
+    match merged.iter_mut().find(|m| m.index == choice.index) {
+      Some(m) => {
+        m.delta = concatenate_stream_delta(m.delta.clone(), choice.delta.clone());
+        m.finish_reason = m.finish_reason.or(choice.finish_reason);
+      },
+      None => merged.push(choice.clone()),
     }
-  });
-  if tc_calls.is_empty() {
-    None
-  } else {
-    Some(tc_calls)
   }
+  merged.sort_by_key(|m| m.index);
+  merged
 }
 
+// the message of the choice so far, empty until a chunk of the choice arrives
 pub fn get_assistant_message_from_create_chat_completion_stream_response(
   choice_index: usize,
   srvec: &[CreateChatCompletionStreamResponse],
 ) -> Result<ChatCompletionRequestAssistantMessage, ParseError> {
-  let mut smvec = Vec::new();
-  srvec.iter().for_each(|sr| {
-    sr.choices
-      .iter()
-      .filter(|choice| choice.index as usize == choice_index)
-      .for_each(|choice| smvec.push(choice.clone()))
-  });
-  fold_stream_responses_into_assistant_message(smvec)
+  let choice = merge_stream_choices(srvec).into_iter().find(|choice| choice.index as usize == choice_index);
+  Ok(match choice {
+    Some(choice) => ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      content: choice.delta.content,
+      function_call: None,
+      tool_calls: collate_tool_call_chunks_into_tool_calls(choice.delta.tool_calls.unwrap_or_default()),
+    },
+    None => ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      content: None,
+      function_call: None,
+      tool_calls: None,
+    },
+  })
 }
 
 pub fn get_assistant_message_from_create_chat_completion_response(
@@ -184,27 +173,6 @@ pub fn get_assistant_message_from_create_chat_completion_response(
     })
   }
 }
-pub fn fold_stream_responses_into_assistant_message(
-  smvec: Vec<ChatCompletionResponseStreamMessage>,
-) -> Result<ChatCompletionRequestAssistantMessage, ParseError> {
-  let concatenated_message =
-    smvec.iter().skip(1).try_fold(smvec[0].clone(), |acc, sr| concatenate_stream_response_messages(&acc, sr))?;
-
-  // let function_call = match concatenated_message.delta.function_call {
-  //   Some(fc) => {
-  //     Some(FunctionCall { name: fc.name.unwrap_or("".to_string()), arguments: fc.arguments.unwrap_or("".to_string()) })
-  //   },
-  //   None => None,
-  // };
-
-  Ok(ChatCompletionRequestAssistantMessage {
-    role: Role::Assistant,
-    content: concatenated_message.delta.content,
-    function_call: None,
-    tool_calls: collate_tool_call_chunks_into_tool_calls(concatenated_message.delta.tool_calls.unwrap_or(Vec::new())),
-  })
-}
-
 use std::fs::{self, DirEntry};
 use std::io;
 use std::path::Path;
@@ -217,3 +185,46 @@ pub fn list_files_ordered_by_date<P: AsRef<Path>>(path: P) -> io::Result<Vec<Dir
 
   Ok(entries)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn recorded_stream(contents: &str) -> Vec<CreateChatCompletionStreamResponse> {
+    serde_json::Deserializer::from_str(contents)
+      .into_iter::<CreateChatCompletionStreamResponse>()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap()
+  }
+
+  #[test]
+  fn test_merge_stream_choices() {
+    let srvec = recorded_stream(include_str!("../../tests/assets/interleaved_stream_response.json"));
+    let choices = merge_stream_choices(&srvec);
+    assert_eq!(choices.iter().map(|choice| choice.index).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(choices[0].delta.content.as_deref(), Some("Roses are red."));
+    assert_eq!(choices[1].delta.content.as_deref(), Some("Violets are blue."));
+    assert_eq!(choices[0].delta.role, Some(Role::Assistant));
+    // the repeated finish reason of the first choice is kept once
+    assert!(choices.iter().all(|choice| choice.finish_reason == Some(FinishReason::Stop)));
+
+    let message = get_assistant_message_from_create_chat_completion_stream_response(1, &srvec).unwrap();
+    assert_eq!(message.content.as_deref(), Some("Violets are blue."));
+    assert!(message.tool_calls.is_none());
+    // before the first chunk of the choice arrives
+    let message = get_assistant_message_from_create_chat_completion_stream_response(1, &srvec[..2]).unwrap();
+    assert!(message.content.is_none());
+
+    let merged = srvec
+      .iter()
+      .skip(1)
+      .try_fold(srvec[0].clone(), |acc, sr| concatenate_create_chat_completion_stream_response(&acc, sr));
+    assert_eq!(merged.unwrap().choices, choices);
+
+    let srvec = recorded_stream(include_str!("../../tests/assets/saved_stream_response.json"));
+    let choices = merge_stream_choices(&srvec);
+    assert_eq!(choices.len(), 1);
+    assert!(choices[0].delta.content.as_deref().unwrap().starts_with("Title: The Night Lily Bloomed"));
+    assert_eq!(choices[0].finish_reason, Some(FinishReason::Stop));
+  }
+}
@@ -0,0 +1,228 @@
+use std::{
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  sync::{Arc, Mutex},
+};
+
+use rhai::{Dynamic, Engine, Scope, AST};
+
+use super::errors::SazidError;
+
+// a script that loops forever fails instead of freezing the tui
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// what a hook asks of the session besides its return value
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookEffect {
+  // tag("name") adds a tag to the session
+  Tag(String),
+  // status("text") shows the text in the status bar
+  Status(String),
+  // command("share gist") runs a session command, as if it were typed after :
+  Command(String),
+}
+
+// the rhai scripts in the hooks directory, each may define any of
+//   on_message_submit(text), returns the text to send in its place, or () to send it as is
+//   on_response(text), called with the whole response once it is complete
+//   on_tool_call(name, arguments), returns arguments to call the tool with instead, false to refuse the call, or ()
+//   on_ingest(path, content), returns the content to ingest in its place, false to skip the file, or ()
+// scripts run in file name order, each one gets what the previous one returned
+pub struct Hooks {
+  engine: Engine,
+  scripts: Vec<(PathBuf, AST)>,
+  effects: Arc<Mutex<Vec<HookEffect>>>,
+}
+
+impl std::fmt::Debug for Hooks {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Hooks").field("scripts", &self.scripts.iter().map(|(path, _)| path).collect::<Vec<_>>()).finish()
+  }
+}
+
+impl Hooks {
+  // None when the directory has no scripts
+  pub fn load(dir: &Path) -> Result<Option<Self>, SazidError> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+      return Ok(None);
+    };
+    let mut paths = entries
+      .flatten()
+      .map(|entry| entry.path())
+      .filter(|path| path.is_file() && path.extension().map_or(false, |e| e == "rhai"))
+      .collect::<Vec<_>>();
+    if paths.is_empty() {
+      return Ok(None);
+    }
+    paths.sort();
+    let effects = Arc::new(Mutex::new(vec![]));
+    let engine = Self::engine(effects.clone());
+    let scripts = paths
+      .into_iter()
+      .map(|path| match engine.compile_file(path.clone()) {
+        Ok(ast) => Ok((path, ast)),
+        Err(e) => Err(SazidError::Other(format!("failed to compile hook {}: {}", path.display(), e))),
+      })
+      .collect::<Result<Vec<_>, SazidError>>()?;
+    Ok(Some(Hooks { engine, scripts, effects }))
+  }
+
+  fn engine(effects: Arc<Mutex<Vec<HookEffect>>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_print(|text| log::info!("hook: {}", text));
+    engine.on_debug(|text, _, _| log::debug!("hook: {}", text));
+    let functions: [(&str, fn(String) -> HookEffect); 3] =
+      [("tag", HookEffect::Tag), ("status", HookEffect::Status), ("command", HookEffect::Command)];
+    for (name, effect) in functions {
+      let effects = effects.clone();
+      engine.register_fn(name, move |text: &str| effects.lock().unwrap().push(effect(text.to_string())));
+    }
+    // run("notify-send done") starts a shell command without waiting for it
+    engine.register_fn("run", |command: &str| {
+      let spawned = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+      if let Err(e) = spawned {
+        log::error!("hook failed to run {}: {}", command, e);
+      }
+    });
+    engine
+  }
+
+  // the effects of the hooks called since the last take
+  pub fn take_effects(&self) -> Vec<HookEffect> {
+    std::mem::take(&mut *self.effects.lock().unwrap())
+  }
+
+  // calls the hook in every script that defines it, f gets each script's return value and returns the next arguments,
+  // or None to stop calling the rest
+  fn call<F>(&self, hook: &str, mut args: Vec<Dynamic>, mut f: F) -> Result<(), SazidError>
+  where
+    F: FnMut(&Path, Dynamic, Vec<Dynamic>) -> Result<Option<Vec<Dynamic>>, SazidError>,
+  {
+    for (path, ast) in self.scripts.iter() {
+      if !ast.iter_functions().any(|function| function.name == hook && function.params.len() == args.len()) {
+        continue;
+      }
+      let result = self
+        .engine
+        .call_fn::<Dynamic>(&mut Scope::new(), ast, hook, args.clone())
+        .map_err(|e| SazidError::Other(format!("{} in {} failed: {}", hook, path.display(), e)))?;
+      match f(path, result, args)? {
+        Some(next) => args = next,
+        None => break,
+      }
+    }
+    Ok(())
+  }
+
+  pub fn on_message_submit(&self, text: &str) -> Result<String, SazidError> {
+    let mut text = text.to_string();
+    self.call("on_message_submit", vec![text.clone().into()], |path, result, _| {
+      if !result.is_unit() {
+        text = result.into_string().map_err(|_| invalid_return("on_message_submit", path, "a string or ()"))?;
+      }
+      Ok(Some(vec![text.clone().into()]))
+    })?;
+    Ok(text)
+  }
+
+  pub fn on_response(&self, text: &str) -> Result<(), SazidError> {
+    self.call("on_response", vec![text.into()], |_, _, args| Ok(Some(args)))
+  }
+
+  // the json arguments to call the tool with, None when a hook refused the call
+  pub fn on_tool_call(&self, name: &str, arguments: &str) -> Result<Option<String>, SazidError> {
+    let value = serde_json::from_str::<serde_json::Value>(arguments).unwrap_or(serde_json::Value::Null);
+    let mut arguments = Some(to_dynamic(&value)?);
+    self.call("on_tool_call", vec![name.into(), arguments.clone().unwrap()], |path, result, args| {
+      if result.as_bool() == Ok(false) {
+        arguments = None;
+        return Ok(None);
+      }
+      if !result.is_unit() && !result.is_map() {
+        return Err(invalid_return("on_tool_call", path, "a map, false or ()"));
+      }
+      if result.is_map() {
+        arguments = Some(result);
+      }
+      Ok(Some(vec![args[0].clone(), arguments.clone().unwrap()]))
+    })?;
+    arguments
+      .map(|arguments| {
+        rhai::serde::from_dynamic::<serde_json::Value>(&arguments)
+          .map(|value| value.to_string())
+          .map_err(|e| SazidError::Other(format!("on_tool_call returned arguments that aren't json: {}", e)))
+      })
+      .transpose()
+  }
+
+  // the content to ingest, None when a hook skipped the file
+  pub fn on_ingest(&self, path: &str, content: &str) -> Result<Option<String>, SazidError> {
+    let mut content = Some(content.to_string());
+    self.call("on_ingest", vec![path.into(), content.clone().unwrap().into()], |script, result, args| {
+      if result.as_bool() == Ok(false) {
+        content = None;
+        return Ok(None);
+      }
+      if !result.is_unit() {
+        content = Some(result.into_string().map_err(|_| invalid_return("on_ingest", script, "a string, false or ()"))?);
+      }
+      Ok(Some(vec![args[0].clone(), content.clone().unwrap_or_default().into()]))
+    })?;
+    Ok(content)
+  }
+}
+
+fn to_dynamic(value: &serde_json::Value) -> Result<Dynamic, SazidError> {
+  rhai::serde::to_dynamic(value).map_err(|e| SazidError::Other(format!("failed to pass arguments to a hook: {}", e)))
+}
+
+fn invalid_return(hook: &str, path: &Path, expected: &str) -> SazidError {
+  SazidError::Other(format!("{} in {} must return {}", hook, path.display(), expected))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_hooks() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(Hooks::load(dir.path()).unwrap().is_none());
+    let prompts = r#"
+      fn on_message_submit(text) { if text.starts_with("!") { text.sub_string(1) + ", briefly" } }
+      fn on_response(text) { if text.contains("rust") { tag("rust"); } status("done"); }
+    "#;
+    std::fs::write(dir.path().join("10-prompts.rhai"), prompts).unwrap();
+    let tools = r#"
+      fn on_tool_call(name, arguments) {
+        if name == "run_command" { return false; }
+        arguments.path = "src/" + arguments.path;
+        arguments
+      }
+      fn on_ingest(path, content) { if path.ends_with(".lock") { false } else { content.to_upper() } }
+    "#;
+    std::fs::write(dir.path().join("20-tools.rhai"), tools).unwrap();
+    let hooks = Hooks::load(dir.path()).unwrap().unwrap();
+
+    assert_eq!(hooks.on_message_submit("!explain traits").unwrap(), "explain traits, briefly");
+    assert_eq!(hooks.on_message_submit("explain traits").unwrap(), "explain traits");
+    hooks.on_response("rust has traits").unwrap();
+    assert_eq!(hooks.take_effects(), vec![HookEffect::Tag("rust".to_string()), HookEffect::Status("done".to_string())]);
+    assert!(hooks.take_effects().is_empty());
+
+    assert_eq!(hooks.on_tool_call("run_command", "{}").unwrap(), None);
+    assert_eq!(hooks.on_tool_call("read_file", r#"{"path":"main.rs"}"#).unwrap().unwrap(), r#"{"path":"src/main.rs"}"#);
+    assert_eq!(hooks.on_ingest("Cargo.lock", "...").unwrap(), None);
+    assert_eq!(hooks.on_ingest("notes.md", "hi").unwrap().unwrap(), "HI");
+
+    std::fs::write(dir.path().join("30-broken.rhai"), "fn on_response(text) {").unwrap();
+    assert!(Hooks::load(dir.path()).is_err());
+  }
+}
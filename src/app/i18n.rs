@@ -0,0 +1,172 @@
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::RwLock,
+};
+
+use once_cell::sync::Lazy;
+
+use super::errors::SazidError;
+
+// the catalogs built into sazid, <locales_dir>/<language>.toml adds to one or adds a language
+const BUILTIN_CATALOGS: [(&str, &str); 2] =
+  [("de", include_str!("../../assets/locales/de.toml")), ("es", include_str!("../../assets/locales/es.toml"))];
+
+static CURRENT: Lazy<RwLock<Catalog>> = Lazy::new(|| RwLock::new(Catalog::default()));
+
+// the translations of the interface into a language, gettext style: each text is looked up by its english text, and
+// text without a translation stays in english
+#[derive(Debug, Clone, PartialEq)]
+pub struct Catalog {
+  pub language: String,
+  messages: HashMap<String, String>,
+}
+
+impl Default for Catalog {
+  fn default() -> Self {
+    Catalog { language: "en".to_string(), messages: HashMap::new() }
+  }
+}
+
+impl Catalog {
+  // the built in catalog of the language with <locales_dir>/<language>.toml over it, english needs neither
+  pub fn load(language: &str, locales_dir: &Path) -> Result<Self, SazidError> {
+    let language = language_code(language).unwrap_or_else(|| "en".to_string());
+    if language == "en" {
+      return Ok(Catalog::default());
+    }
+    let builtin = BUILTIN_CATALOGS.iter().find(|(name, _)| *name == language);
+    let mut messages = HashMap::new();
+    if let Some((_, contents)) = builtin {
+      messages.extend(
+        parse_catalog(contents).map_err(|e| SazidError::Other(format!("invalid {} catalog: {}", language, e)))?,
+      );
+    }
+    let path = locales_dir.join(format!("{}.toml", language));
+    match std::fs::read_to_string(&path) {
+      Ok(contents) => messages.extend(
+        parse_catalog(&contents)
+          .map_err(|e| SazidError::Other(format!("invalid catalog {}: {}", path.display(), e)))?,
+      ),
+      Err(_) if builtin.is_some() => {},
+      Err(e) => return Err(SazidError::Other(format!("no catalog for {}, {}: {}", language, path.display(), e))),
+    }
+    Ok(Catalog { language, messages })
+  }
+
+  pub fn translate<'a>(&'a self, text: &'a str) -> &'a str {
+    self.messages.get(text).map(String::as_str).unwrap_or(text)
+  }
+
+  // english, the built in catalogs and the catalogs in locales_dir
+  pub fn available(locales_dir: &Path) -> Vec<String> {
+    let mut languages = vec!["en".to_string()];
+    languages.extend(BUILTIN_CATALOGS.iter().map(|(name, _)| name.to_string()));
+    if let Ok(entries) = std::fs::read_dir(locales_dir) {
+      let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "toml"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .filter(|name| !languages.contains(name))
+        .collect::<Vec<_>>();
+      files.sort();
+      languages.extend(files);
+    }
+    languages
+  }
+}
+
+fn parse_catalog(contents: &str) -> Result<HashMap<String, String>, toml::de::Error> {
+  toml::from_str(contents)
+}
+
+pub fn locales_dir() -> PathBuf {
+  crate::utils::get_config_dir().join("locales")
+}
+
+// the language of a locale such as de_DE.UTF-8 or a language tag such as pt-BR, None for the C locale
+pub fn language_code(locale: &str) -> Option<String> {
+  let code = locale.split(['_', '-', '.', '@']).next().unwrap_or_default().trim().to_lowercase();
+  match code.as_str() {
+    "" | "c" | "posix" => None,
+    _ => Some(code),
+  }
+}
+
+// the language of the environment, from LC_ALL, LC_MESSAGES or LANG
+pub fn detect_language() -> Option<String> {
+  ["LC_ALL", "LC_MESSAGES", "LANG"]
+    .iter()
+    .filter_map(|name| std::env::var(name).ok())
+    .find(|value| !value.is_empty())
+    .and_then(|value| language_code(&value))
+}
+
+// the english name of a language code, for the model, anything else is passed on as it is
+pub fn language_name(language: &str) -> &str {
+  match language {
+    "de" => "German",
+    "en" => "English",
+    "es" => "Spanish",
+    "fr" => "French",
+    "it" => "Italian",
+    "ja" => "Japanese",
+    "ko" => "Korean",
+    "nl" => "Dutch",
+    "pl" => "Polish",
+    "pt" => "Portuguese",
+    "ru" => "Russian",
+    "sv" => "Swedish",
+    "tr" => "Turkish",
+    "uk" => "Ukrainian",
+    "zh" => "Chinese",
+    _ => language,
+  }
+}
+
+// the system message that asks the model to respond in the language
+pub fn response_language_prompt(language: &str) -> String {
+  format!("Respond in {} unless the user asks for another language.", language_name(language))
+}
+
+// the catalog everything is drawn with, switched with the lang command
+pub fn current() -> Catalog {
+  CURRENT.read().unwrap().clone()
+}
+
+pub fn set_current(catalog: Catalog) {
+  *CURRENT.write().unwrap() = catalog;
+}
+
+// the text in the interface's language
+pub fn tr(text: &str) -> String {
+  CURRENT.read().unwrap().translate(text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_load_catalog() {
+    assert_eq!(language_code("de_DE.UTF-8").as_deref(), Some("de"));
+    assert_eq!(language_code("pt-BR").as_deref(), Some("pt"));
+    assert_eq!(language_code("C"), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    let catalog = Catalog::load("de", dir.path()).unwrap();
+    assert_eq!(catalog.translate("Normal Mode"), "Normalmodus");
+    assert_eq!(catalog.translate("not translated"), "not translated");
+    assert_eq!(Catalog::load("en_US.UTF-8", dir.path()).unwrap(), Catalog::default());
+    assert!(Catalog::load("fr", dir.path()).is_err());
+
+    std::fs::write(dir.path().join("fr.toml"), "\"Normal Mode\" = \"Mode normal\"\n").unwrap();
+    std::fs::write(dir.path().join("de.toml"), "\"Normal Mode\" = \"Normal\"\n").unwrap();
+    assert_eq!(Catalog::load("fr", dir.path()).unwrap().translate("Normal Mode"), "Mode normal");
+    let catalog = Catalog::load("de", dir.path()).unwrap();
+    assert_eq!(catalog.translate("Normal Mode"), "Normal");
+    assert_eq!(catalog.translate("Insert Mode"), "Einfügemodus");
+    assert_eq!(Catalog::available(dir.path()), vec!["en", "de", "es", "fr"]);
+    assert_eq!(response_language_prompt("es"), "Respond in Spanish unless the user asks for another language.");
+  }
+}
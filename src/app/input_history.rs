@@ -0,0 +1,151 @@
+use std::{
+  collections::HashSet,
+  fs::OpenOptions,
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+use super::{
+  consts::{data_path, PROMPT_HISTORY},
+  encryption,
+};
+
+// the oldest prompts are dropped from the history file beyond this
+const MAX_HISTORY: usize = 1000;
+
+// submitted prompts, kept across sessions and recalled like a shell history
+#[derive(Debug, Default, Clone)]
+pub struct InputHistory {
+  pub entries: Vec<String>,
+  pub path: Option<PathBuf>,
+  // the entry shown while browsing with up and down, and the input typed before browsing started
+  browsing: Option<(usize, String)>,
+}
+
+impl InputHistory {
+  pub fn load() -> Self {
+    match data_path(PROMPT_HISTORY) {
+      Some(path) => InputHistory::load_file(&path),
+      None => InputHistory::default(),
+    }
+  }
+
+  // one json string per line, so that multi-line prompts stay on one line of the file
+  pub fn load_file(path: &Path) -> Self {
+    let entries = encryption::read_to_string(path)
+      .map(|contents| contents.lines().filter_map(|line| serde_json::from_str::<String>(line).ok()).collect())
+      .unwrap_or_else(|_| vec![]);
+    let mut history = InputHistory { entries, path: Some(path.to_path_buf()), browsing: None };
+    history.truncate();
+    history
+  }
+
+  pub fn record(&mut self, prompt: &str) -> std::io::Result<()> {
+    self.browsing = None;
+    let prompt = prompt.trim();
+    if prompt.is_empty() || self.entries.last().map_or(false, |last| last == prompt) {
+      return Ok(());
+    }
+    self.entries.push(prompt.to_string());
+    let Some(path) = self.path.clone() else { return Ok(()) };
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    // the file is rewritten once it grows past the limit, or when it's encrypted, otherwise the prompt is appended
+    match self.truncate() || encryption::is_enabled() {
+      true => {
+        let lines = self.entries.iter().map(|entry| format!("{}\n", serde_json::json!(entry))).collect::<String>();
+        encryption::write(&path, lines)
+      },
+      false => {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::json!(prompt))
+      },
+    }
+  }
+
+  fn truncate(&mut self) -> bool {
+    let excess = self.entries.len().saturating_sub(MAX_HISTORY);
+    self.entries.drain(..excess);
+    excess > 0
+  }
+
+  pub fn is_browsing(&self) -> bool {
+    self.browsing.is_some()
+  }
+
+  pub fn reset(&mut self) {
+    self.browsing = None;
+  }
+
+  // the previous prompt starting with what was typed before browsing started, None past the oldest one
+  pub fn previous(&mut self, input: &str) -> Option<String> {
+    let (position, prefix) = self.browsing.clone().unwrap_or_else(|| (self.entries.len(), input.to_string()));
+    let found = self.entries[..position].iter().rposition(|entry| entry.starts_with(&prefix))?;
+    self.browsing = Some((found, prefix));
+    Some(self.entries[found].clone())
+  }
+
+  // the next matching prompt, or what was typed before browsing started once past the newest one
+  pub fn next(&mut self) -> Option<String> {
+    let (position, prefix) = self.browsing.take()?;
+    match self.entries.iter().enumerate().skip(position + 1).find(|(_, entry)| entry.starts_with(&prefix)) {
+      Some((found, entry)) => {
+        let entry = entry.clone();
+        self.browsing = Some((found, prefix));
+        Some(entry)
+      },
+      None => Some(prefix),
+    }
+  }
+
+  // most recent first, prompts starting with the query before prompts that only contain its letters in order
+  pub fn search(&self, query: &str) -> Vec<String> {
+    let query = query.to_lowercase();
+    let mut seen = HashSet::new();
+    let (prefix_matches, rest): (Vec<&String>, Vec<&String>) = self
+      .entries
+      .iter()
+      .rev()
+      .filter(|entry| seen.insert(entry.as_str()))
+      .partition(|entry| entry.to_lowercase().starts_with(&query));
+    prefix_matches
+      .into_iter()
+      .chain(rest.into_iter().filter(|entry| is_subsequence(&query, &entry.to_lowercase())))
+      .cloned()
+      .collect()
+  }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+  let mut chars = haystack.chars();
+  needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_history_recall_and_search() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data/prompt_history.jsonl");
+    let mut history = InputHistory::load_file(&path);
+    for prompt in ["explain lifetimes", "fix the tests\nin session.rs", "explain traits", "explain traits", " "] {
+      history.record(prompt).unwrap();
+    }
+    let mut history = InputHistory::load_file(&path);
+    assert_eq!(history.entries, vec!["explain lifetimes", "fix the tests\nin session.rs", "explain traits"]);
+
+    assert_eq!(history.previous("expl").as_deref(), Some("explain traits"));
+    assert_eq!(history.previous("ignored while browsing").as_deref(), Some("explain lifetimes"));
+    assert_eq!(history.previous(""), None);
+    assert_eq!(history.next().as_deref(), Some("explain traits"));
+    assert_eq!(history.next().as_deref(), Some("expl"));
+    assert!(!history.is_browsing());
+
+    assert_eq!(history.search("explain"), vec!["explain traits", "explain lifetimes"]);
+    assert_eq!(history.search("ftt"), vec!["fix the tests\nin session.rs"]);
+    assert_eq!(history.search("").len(), 3);
+  }
+}
@@ -0,0 +1,93 @@
+use async_openai::{
+  config::{Config, OpenAIConfig},
+  types::CreateChatCompletionRequest,
+};
+use reqwest::header::HeaderMap;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  messages::ChatMessage,
+  providers::{is_openrouter, openrouter_headers},
+};
+
+// header names containing any of these have their values hidden
+const SECRET_HEADERS: &[&str] = &["authorization", "organization", "key", "token", "secret", "cookie"];
+
+// the json sent to the api for one request and what came back, for the inspector
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RawExchange {
+  pub url: String,
+  // with secrets redacted
+  pub headers: Vec<(String, String)>,
+  pub request: String,
+  // a streamed response is the list of chunks in the order they arrived
+  pub response: String,
+}
+
+impl RawExchange {
+  pub fn new(
+    openai_config: &OpenAIConfig,
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+    error: Option<&str>,
+  ) -> Self {
+    let mut headers = openai_config.headers();
+    if is_openrouter(openai_config) {
+      headers.extend(openrouter_headers());
+    }
+    let response = match error {
+      Some(error) => error.to_string(),
+      None => {
+        let bodies = responses
+          .iter()
+          .filter_map(|response| match response {
+            ChatMessage::Response(response) => serde_json::to_value(response).ok(),
+            ChatMessage::StreamResponse(chunks) => serde_json::to_value(chunks).ok(),
+            _ => None,
+          })
+          .collect::<Vec<serde_json::Value>>();
+        let bodies = match bodies.len() {
+          1 => bodies.into_iter().next().unwrap(),
+          _ => serde_json::Value::Array(bodies),
+        };
+        serde_json::to_string_pretty(&bodies).unwrap_or_default()
+      },
+    };
+    RawExchange {
+      url: openai_config.url("/chat/completions"),
+      headers: redacted_headers(&headers),
+      request: serde_json::to_string_pretty(request).unwrap_or_default(),
+      response,
+    }
+  }
+}
+
+pub fn redacted_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+  headers
+    .iter()
+    .map(|(name, value)| {
+      let name = name.as_str().to_string();
+      let value = match SECRET_HEADERS.iter().any(|secret| name.to_lowercase().contains(secret)) {
+        true => "<redacted>".to_string(),
+        false => value.to_str().unwrap_or("<binary>").to_string(),
+      };
+      (name, value)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_secret_headers_are_redacted() {
+    let config = OpenAIConfig::new().with_api_key("sk-secret").with_org_id("org-secret");
+    let request = CreateChatCompletionRequest { model: "gpt-4".to_string(), ..Default::default() };
+    let exchange = RawExchange::new(&config, &request, &[], Some("502 bad gateway"));
+    assert!(exchange.headers.iter().any(|(name, value)| name == "authorization" && value == "<redacted>"));
+    assert!(!format!("{:?}", exchange).contains("secret"));
+    assert!(exchange.request.contains("\"model\": \"gpt-4\""));
+    assert_eq!(exchange.response, "502 bad gateway");
+  }
+}
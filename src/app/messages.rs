@@ -1,9 +1,8 @@
-use std::{
-  collections::HashSet,
-  fmt::{self, Formatter},
-};
+use std::fmt::{self, Formatter};
 
 use color_eyre::owo_colors::OwoColorize;
+use lazy_static::lazy_static;
+use regex::Regex;
 use ropey::Rope;
 use serde_derive::{Deserialize, Serialize};
 
@@ -13,7 +12,8 @@ use async_openai::{
     ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage, ChatCompletionRequestFunctionMessage,
     ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart, ChatCompletionRequestSystemMessage,
     ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
-    CreateChatCompletionResponse, CreateChatCompletionStreamResponse, FunctionCall, FunctionCallStream, Role,
+    CreateChatCompletionResponse, CreateChatCompletionStreamResponse, FinishReason, FunctionCall, FunctionCallStream,
+    Role,
   },
 };
 
@@ -23,10 +23,15 @@ use super::{
   errors::ParseError,
   helpers::{
     get_assistant_message_from_create_chat_completion_response,
-    get_assistant_message_from_create_chat_completion_stream_response,
+    get_assistant_message_from_create_chat_completion_stream_response, merge_stream_choices,
   },
+  theme::{self, paint, Theme, ThemeColor},
 };
 
+lazy_static! {
+  static ref ANSI_ESCAPE: Regex = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MessageContainer {
   pub message: ChatCompletionRequestMessage,
@@ -42,6 +47,77 @@ pub struct MessageContainer {
   #[serde(skip)]
   pub stylized: Rope,
   pub token_usage: usize,
+  // the model that produced the response, shown in the transcript
+  #[serde(default)]
+  pub model: Option<String>,
+  // the persona that responded, shown in place of the role
+  #[serde(default)]
+  pub persona: Option<String>,
+  // queued while offline, sent once the api can be reached again
+  #[serde(default)]
+  pub pending: bool,
+  // the user's rating of a response, used to select fine-tuning examples
+  #[serde(default)]
+  pub feedback: Option<Feedback>,
+  // labels of the retrieved sources the response cites, listed under it
+  #[serde(default)]
+  pub cited_sources: Vec<String>,
+  // kept in the transcript but left out of requests
+  #[serde(default)]
+  pub excluded: bool,
+  // always kept in requests as it is, compression leaves it intact
+  #[serde(default)]
+  pub pinned: bool,
+  // the response was cancelled while streaming, the content is what arrived before that
+  #[serde(default)]
+  pub interrupted: bool,
+  // the response stalled or ran past the request timeout, the content is what arrived before that
+  #[serde(default)]
+  pub timed_out: bool,
+  // shown in full while the transcript is compact
+  #[serde(skip)]
+  pub expanded: bool,
+  // unix time the message was added, None in sessions saved before it was recorded
+  #[serde(default)]
+  pub created_at: Option<i64>,
+  // the message or its code blocks are shown as one line, toggled with z and Z
+  #[serde(skip)]
+  pub fold: Fold,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Fold {
+  #[default]
+  Open,
+  Message,
+  CodeBlocks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+  Up,
+  Down,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Feedback {
+  pub rating: Rating,
+  #[serde(default)]
+  pub comment: Option<String>,
+}
+
+impl fmt::Display for Feedback {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let rating = match self.rating {
+      Rating::Up => "+1",
+      Rating::Down => "-1",
+    };
+    match &self.comment {
+      Some(comment) => write!(f, "{}: {}", rating, comment),
+      None => write!(f, "{}", rating),
+    }
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -88,6 +164,17 @@ impl From<ChatMessage> for MessageContainer {
 }
 impl fmt::Display for MessageContainer {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let theme = theme::current();
+    let user_header = match self.pending {
+      true => "You (pending):",
+      false => "You:",
+    };
+    if self.excluded {
+      writeln!(f, "{}", "(excluded from context)".dimmed())?;
+    }
+    if self.pinned {
+      writeln!(f, "{}", "(pinned)".bright_yellow())?;
+    }
     write!(
       f,
       "{}",
@@ -96,12 +183,12 @@ impl fmt::Display for MessageContainer {
                     .content
                 {
                     Some(content) => {
-                        format!("{}\n{}", "System:".bright_magenta(), content)
+                        format!("{}\n{}", paint(theme.roles.system, "System:"), content)
                     }
                     None => {
                         format!(
                             "{}\n{}",
-                            "System:".bright_magenta(),
+                            paint(theme.roles.system, "System:"),
                             "no content"
                         )
                     }
@@ -112,7 +199,7 @@ impl fmt::Display for MessageContainer {
                     Some(ChatCompletionRequestUserMessageContent::Text(
                         content,
                     )) => {
-                        format!("{}\n{}", "You:".bright_blue(), content)
+                        format!("{}\n{}", paint(theme.roles.user, user_header), content)
                     }
                     Some(ChatCompletionRequestUserMessageContent::Array(
                         parts,
@@ -121,30 +208,60 @@ impl fmt::Display for MessageContainer {
                         for part in parts {
                             content.push(match part {
                 ChatCompletionRequestMessageContentPart::Text(content) => {
-                  format!("{}\n{}", "You:".bright_blue(), content.text)
+                  format!("{}\n{}", paint(theme.roles.user, user_header), content.text)
                 },
                 ChatCompletionRequestMessageContentPart::Image(content) => {
-                  format!("{}\n{}", "You <Image>:".bright_blue(), content.image_url.url)
+                  format!("{}\n{}", paint(theme.roles.user, "You <Image>:"), content.image_url.url)
                 },
               })
                         }
                         content.join("\n")
                     }
                     None => {
-                        format!("{}\n{}", "You:".bright_blue(), "no content")
+                        format!("{}\n{}", paint(theme.roles.user, "You:"), "no content")
                     }
                 },
                 ChatCompletionRequestMessage::Assistant(message) => {
                     let mut content: Vec<String> = Vec::new();
+                    let header = match (&self.model, &self.feedback) {
+                        (Some(model), Some(feedback)) => format!("Assistant ({}) [{}]:", model, feedback),
+                        (Some(model), None) => format!("Assistant ({}):", model),
+                        (None, Some(feedback)) => format!("Assistant [{}]:", feedback),
+                        (None, None) => "Assistant:".to_string(),
+                    };
+                    let header = match (self.interrupted, self.timed_out) {
+                        (_, true) => format!("{} (timed out)", header),
+                        (true, false) => format!("{} (interrupted)", header),
+                        (false, false) if self.refused() => format!("{} (refused)", header),
+                        (false, false) => header,
+                    };
                     content.push(match &message.content {
+                        // a refusal stands apart from an answer, whatever arrived before the filter stopped it
+                        _ if self.refused() => format!(
+                            "{}\n{}\n",
+                            paint(theme.roles.assistant, &header),
+                            paint(
+                                theme.roles.refusal,
+                                message.content.as_deref().filter(|content| !content.is_empty()).unwrap_or(
+                                    "the response was withheld by the content filter"
+                                )
+                            )
+                        ),
+                        Some(content) if !self.cited_sources.is_empty() => format!(
+                            "{}\n{}\n\n{}\n{}\n",
+                            paint(theme.roles.assistant, &header),
+                            content,
+                            paint(theme.roles.sources, "Sources:"),
+                            self.cited_sources.join("\n")
+                        ),
                         Some(content) => format!(
                             "{}\n{}\n",
-                            "Assistant:".bright_yellow(),
+                            paint(theme.roles.assistant, &header),
                             content
                         ),
                         None => format!(
                             "{}\n{}\n",
-                            "Assistant:".bright_yellow(),
+                            paint(theme.roles.assistant, &header),
                             "no content"
                         ),
                     });
@@ -153,12 +270,12 @@ impl fmt::Display for MessageContainer {
                             for tool_call in tool_calls {
                                 content.push(format!(
                                     "{}\n{}",
-                                    "Tool:".bright_green(),
+                                    paint(theme.roles.tool, "Tool:"),
                                     tool_call.function.name
                                 ));
                                 content.push(format!(
                                     "{}\n{}",
-                                    "Arguments:".bright_green(),
+                                    paint(theme.roles.tool, "Arguments:"),
                                     tool_call.function.arguments
                                 ));
                             }
@@ -171,7 +288,7 @@ impl fmt::Display for MessageContainer {
                     let mut content: Vec<String> = Vec::new();
                     content.push(format!(
                         "{}\n{}",
-                        "Tool:".bright_green(),
+                        paint(theme.roles.tool, "Tool:"),
                         message.tool_call_id
                     ));
                     content.push(match &message.content {
@@ -184,7 +301,7 @@ impl fmt::Display for MessageContainer {
                     let mut content: Vec<String> = Vec::new();
                     content.push(format!(
                         "{}\n{}",
-                        "Function:".bright_green(),
+                        paint(theme.roles.tool, "Function:"),
                         message.name
                     ));
                     content.push(match &message.content {
@@ -299,9 +416,126 @@ impl MessageContainer {
       tools_called: false,
       response_count: 0,
       token_usage: 0,
+      model: None,
+      persona: None,
+      pending: false,
+      feedback: None,
+      cited_sources: Vec::new(),
+      excluded: false,
+      pinned: false,
+      interrupted: false,
+      timed_out: false,
+      expanded: false,
+      created_at: Some(chrono::Utc::now().timestamp()),
+      fold: Fold::Open,
     }
   }
 
+  pub fn role(&self) -> &'static str {
+    match &self.message {
+      ChatCompletionRequestMessage::System(_) => "system",
+      ChatCompletionRequestMessage::User(_) => "user",
+      ChatCompletionRequestMessage::Assistant(_) => "assistant",
+      ChatCompletionRequestMessage::Tool(_) => "tool",
+      ChatCompletionRequestMessage::Function(_) => "function",
+    }
+  }
+
+  // the role and the first line of the content, for lists of messages
+  pub fn summary(&self) -> String {
+    let content = match &self.message {
+      ChatCompletionRequestMessage::System(message) => message.content.clone().unwrap_or_default(),
+      ChatCompletionRequestMessage::User(message) => match &message.content {
+        Some(ChatCompletionRequestUserMessageContent::Text(text)) => text.clone(),
+        Some(ChatCompletionRequestUserMessageContent::Array(_)) => "<image>".to_string(),
+        None => String::new(),
+      },
+      ChatCompletionRequestMessage::Assistant(message) => match (&message.content, &message.tool_calls) {
+        (Some(content), _) => content.clone(),
+        (None, Some(tool_calls)) => tool_calls.iter().map(|c| c.function.name.clone()).collect::<Vec<_>>().join(", "),
+        (None, None) => String::new(),
+      },
+      ChatCompletionRequestMessage::Tool(message) => message.content.clone().unwrap_or_default(),
+      ChatCompletionRequestMessage::Function(message) => message.name.clone(),
+    };
+    format!("{}: {}", self.role(), content.lines().find(|line| !line.trim().is_empty()).unwrap_or_default())
+  }
+
+  pub fn role_color(&self, theme: &Theme) -> ThemeColor {
+    match &self.message {
+      ChatCompletionRequestMessage::System(_) => theme.roles.system,
+      ChatCompletionRequestMessage::User(_) => theme.roles.user,
+      ChatCompletionRequestMessage::Assistant(_) => theme.roles.assistant,
+      ChatCompletionRequestMessage::Tool(_) | ChatCompletionRequestMessage::Function(_) => theme.roles.tool,
+    }
+  }
+
+  // the role and what is known about the message, for the header of its block
+  pub fn block_title(&self) -> String {
+    let role = match (&self.message, self.pending, self.interrupted, self.timed_out) {
+      (ChatCompletionRequestMessage::System(_), ..) => "System",
+      (ChatCompletionRequestMessage::User(_), true, ..) => "You (pending)",
+      (ChatCompletionRequestMessage::User(_), false, ..) => "You",
+      (ChatCompletionRequestMessage::Assistant(_), _, _, true) => "Assistant (timed out)",
+      (ChatCompletionRequestMessage::Assistant(_), _, true, false) => "Assistant (interrupted)",
+      (ChatCompletionRequestMessage::Assistant(_), ..) if self.refused() => "Assistant (refused)",
+      (ChatCompletionRequestMessage::Assistant(_), ..) => "Assistant",
+      (ChatCompletionRequestMessage::Tool(_), ..) => "Tool",
+      (ChatCompletionRequestMessage::Function(_), ..) => "Function",
+    };
+    let mut parts = vec![match &self.persona {
+      Some(persona) => role.replacen("Assistant", persona, 1),
+      None => role.to_string(),
+    }];
+    if let Some(created_at) = self.created_at.and_then(|t| chrono::DateTime::from_timestamp(t, 0)) {
+      let created_at = created_at.with_timezone(&chrono::Local);
+      parts.push(match created_at.date_naive() == chrono::Local::now().date_naive() {
+        true => created_at.format("%H:%M").to_string(),
+        false => created_at.format("%Y-%m-%d %H:%M").to_string(),
+      });
+    }
+    parts.extend(self.model.clone());
+    if self.token_usage > 0 {
+      parts.push(format!("{} tokens", self.token_usage));
+    }
+    parts.extend(self.finish_reason().map(|finish_reason| finish_reason_label(finish_reason).to_string()));
+    parts.extend(self.feedback.as_ref().map(|feedback| format!("[{}]", feedback)));
+    parts.join(" · ")
+  }
+
+  // the rendered message without its role line, which the block header replaces
+  pub fn body(&self) -> String {
+    let rendered = self.to_string();
+    let mut lines = rendered.lines().collect::<Vec<_>>();
+    let headers = ["System", "You", "Assistant", "Tool", "Function"];
+    let is_header = |line: &&str| headers.iter().any(|header| ANSI_ESCAPE.replace_all(line, "").starts_with(header));
+    if let Some(index) = lines.iter().position(is_header) {
+      lines.remove(index);
+    }
+    lines.join("\n")
+  }
+
+  // the summary on one line of at most width characters, for the compact transcript
+  pub fn preview(&self, width: usize) -> String {
+    let theme = theme::current();
+    let color = self.role_color(&theme);
+    let summary = self.summary();
+    let content = summary.split_once(": ").map_or("", |(_, content)| content);
+    let available = width.saturating_sub(self.role().len() + 4);
+    let content = match content.chars().count() > available {
+      true => format!("{}…", content.chars().take(available.saturating_sub(1)).collect::<String>()),
+      false => content.to_string(),
+    };
+    format!("▸ {} {}", paint(color, &format!("{}:", self.role())), content)
+  }
+
+  // the preview of a folded message, with the number of lines that are hidden
+  pub fn fold_preview(&self, width: usize) -> String {
+    let folded = format!(" [{} lines folded]", self.to_string().lines().count());
+    let preview = self.preview(width.saturating_sub(folded.chars().count()));
+    format!("{}{}", preview, paint(theme::current().hint, &folded))
+  }
+
   pub fn new_from_completed_message(message: ChatCompletionRequestMessage) -> Self {
     let mut message_container = MessageContainer::new(message);
     message_container.receive_complete = true;
@@ -315,6 +549,7 @@ impl MessageContainer {
           get_assistant_message_from_create_chat_completion_response(0, response).unwrap(),
         ));
         message.receive_buffer = Some(receive_buffer.clone());
+        message.model = Some(response.model.clone());
         message
       },
       ReceiveBuffer::StreamResponse(response) => {
@@ -323,6 +558,7 @@ impl MessageContainer {
         ));
         message.receive_buffer = Some(receive_buffer.clone());
         message.stream_id = Some(response[0].id.clone());
+        message.model = Some(response[0].model.clone());
         message
       },
     }
@@ -350,25 +586,86 @@ impl MessageContainer {
     }
   }
 
+  // why the selected choice of a response stopped, None while it is streaming and for messages that weren't received
+  pub fn finish_reason(&self) -> Option<FinishReason> {
+    match &self.receive_buffer {
+      Some(ReceiveBuffer::Response(response)) => response
+        .choices
+        .iter()
+        .find(|choice| choice.index as usize == self.selected_choice)
+        .and_then(|choice| choice.finish_reason),
+      Some(ReceiveBuffer::StreamResponse(srvec)) => merge_stream_choices(srvec)
+        .into_iter()
+        .find(|choice| choice.index as usize == self.selected_choice)
+        .and_then(|choice| choice.finish_reason),
+      None => None,
+    }
+  }
+
+  // the response stopped because it reached max_tokens
+  pub fn hit_token_limit(&self) -> bool {
+    self.finish_reason() == Some(FinishReason::Length)
+  }
+
+  // the response was withheld or cut off by the provider's content filter
+  pub fn refused(&self) -> bool {
+    self.finish_reason() == Some(FinishReason::ContentFilter)
+  }
+
+  // the message of each choice of a response by its index, a response asked for with n > 1 has several
+  pub fn choices(&self) -> Vec<(usize, ChatCompletionRequestAssistantMessage)> {
+    match &self.receive_buffer {
+      Some(ReceiveBuffer::Response(response)) => response
+        .choices
+        .iter()
+        .enumerate()
+        .filter_map(|(position, choice)| {
+          let message = get_assistant_message_from_create_chat_completion_response(position, response).ok()?;
+          Some((choice.index as usize, message))
+        })
+        .collect(),
+      Some(ReceiveBuffer::StreamResponse(srvec)) => merge_stream_choices(srvec)
+        .iter()
+        .filter_map(|choice| {
+          let index = choice.index as usize;
+          get_assistant_message_from_create_chat_completion_stream_response(index, srvec).ok().map(|m| (index, m))
+        })
+        .collect(),
+      None => vec![],
+    }
+  }
+
+  // keeps one choice of the response and discards the others, false when there is no such choice
+  pub fn keep_choice(&mut self, index: usize) -> bool {
+    if !self.choices().iter().any(|(i, _)| *i == index) {
+      return false;
+    }
+    let message = match &mut self.receive_buffer {
+      Some(ReceiveBuffer::Response(response)) => {
+        response.choices.retain(|choice| choice.index as usize == index);
+        get_assistant_message_from_create_chat_completion_response(0, response)
+      },
+      Some(ReceiveBuffer::StreamResponse(srvec)) => {
+        srvec.iter_mut().for_each(|sr| sr.choices.retain(|choice| choice.index as usize == index));
+        get_assistant_message_from_create_chat_completion_stream_response(index, srvec)
+      },
+      None => return false,
+    };
+    if let Ok(message) = message {
+      self.message = ChatCompletionRequestMessage::Assistant(message);
+    }
+    self.selected_choice = index;
+    self.stylize_complete = false;
+    true
+  }
+
   pub fn check_if_receive_is_complete(&mut self) {
     if match &self.receive_buffer {
       Some(ReceiveBuffer::Response(response)) => response.choices.iter().all(|c| c.finish_reason.is_some()),
       Some(ReceiveBuffer::StreamResponse(srvec)) => {
-        let mut indexes_with_finish_reason = HashSet::new();
-
-        // First, insert indices that have a finish_reason into the set.
-        srvec.iter().for_each(|response| {
-          response.choices.iter().for_each(|choice| {
-            if choice.finish_reason.is_some() {
-              indexes_with_finish_reason.insert(choice.index);
-            }
-          });
-        });
-
-        // Now, check if every index has a corresponding finish_reason.
-        let res = srvec
-          .iter()
-          .all(|response| response.choices.iter().all(|choice| indexes_with_finish_reason.contains(&choice.index)));
+        // every choice seen so far has finished, chunks without choices don't count
+        let choices = merge_stream_choices(srvec);
+        let res = !choices.is_empty() && choices.iter().all(|choice| choice.finish_reason.is_some());
         if res {
           trace_dbg!("message finished: {:#?}", self.stream_id.bright_magenta());
         } else {
@@ -387,6 +684,64 @@ impl MessageContainer {
 pub struct RenderedChatMessage {
   pub role: Option<Role>,
   pub content: String,
+  #[serde(default)]
+  pub model: Option<String>,
+  #[serde(default)]
+  pub finish_reason: Option<FinishReason>,
+}
+
+// the plain text of a message, without the terminal styling used in the transcript view
+impl From<&MessageContainer> for RenderedChatMessage {
+  fn from(message_container: &MessageContainer) -> Self {
+    let (role, content) = match &message_container.message {
+      ChatCompletionRequestMessage::System(message) => (Role::System, message.content.clone().unwrap_or_default()),
+      ChatCompletionRequestMessage::User(message) => (
+        Role::User,
+        match &message.content {
+          Some(ChatCompletionRequestUserMessageContent::Text(text)) => text.clone(),
+          Some(ChatCompletionRequestUserMessageContent::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| match part {
+              ChatCompletionRequestMessageContentPart::Text(text) => Some(text.text.clone()),
+              _ => None,
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+          None => String::new(),
+        },
+      ),
+      ChatCompletionRequestMessage::Assistant(message) => {
+        let mut content = vec![message.content.clone().unwrap_or_default()];
+        if let Some(tool_calls) = &message.tool_calls {
+          tool_calls.iter().for_each(|tool_call| {
+            content.push(format!("tool call: {}({})", tool_call.function.name, tool_call.function.arguments))
+          });
+        }
+        (Role::Assistant, content.into_iter().filter(|c| !c.is_empty()).collect::<Vec<String>>().join("\n"))
+      },
+      ChatCompletionRequestMessage::Tool(message) => (Role::Tool, message.content.clone().unwrap_or_default()),
+      ChatCompletionRequestMessage::Function(message) => {
+        (Role::Function, message.content.clone().unwrap_or_default())
+      },
+    };
+    RenderedChatMessage {
+      role: Some(role),
+      content,
+      model: message_container.model.clone(),
+      finish_reason: message_container.finish_reason(),
+    }
+  }
+}
+
+// how a finish reason is shown in the transcript
+pub fn finish_reason_label(finish_reason: FinishReason) -> &'static str {
+  match finish_reason {
+    FinishReason::Stop => "stop",
+    FinishReason::Length => "length",
+    FinishReason::ToolCalls => "tool calls",
+    FinishReason::ContentFilter => "content filter",
+    FinishReason::FunctionCall => "function call",
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -455,3 +810,41 @@ impl AsRef<ChatMessage> for ChatMessage {
     self
   }
 }
+
+// replaces the content of each fenced code block with a line saying how many lines it had, an unterminated block of
+// a response that is still streaming is folded as well
+pub fn fold_code_blocks(text: &str) -> String {
+  let mut folded = Vec::new();
+  let mut block: Option<(&str, usize)> = None;
+  for line in text.lines() {
+    let is_fence = line.trim_start().starts_with("```");
+    block = match (block, is_fence) {
+      (None, true) => Some((line, 0)),
+      (None, false) => {
+        folded.push(line.to_string());
+        None
+      },
+      (Some((fence, count)), true) => {
+        folded.extend([fence.to_string(), format!("⋯ {} lines folded", count), line.to_string()]);
+        None
+      },
+      (Some((fence, count)), false) => Some((fence, count + 1)),
+    };
+  }
+  if let Some((fence, count)) = block {
+    folded.extend([fence.to_string(), format!("⋯ {} lines folded", count)]);
+  }
+  folded.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fold_code_blocks() {
+    let text = "Here it is:\n```rust\nfn main() {\n  println!(\"hi\");\n}\n```\nand then\n```\nstreaming";
+    assert_eq!(fold_code_blocks(text), "Here it is:\n```rust\n⋯ 3 lines folded\n```\nand then\n```\n⋯ 1 lines folded");
+    assert_eq!(fold_code_blocks("no code"), "no code");
+  }
+}
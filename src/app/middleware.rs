@@ -0,0 +1,366 @@
+use std::time::{Duration, Instant};
+
+use async_openai::{config::OpenAIConfig, error::OpenAIError, types::CreateChatCompletionRequest};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+
+use crate::trace_dbg;
+
+use super::{
+  errors::SazidError,
+  functions::argument_validation::count_tokens,
+  messages::ChatMessage,
+  mock_provider::MockProvider,
+  offline::OfflineMiddleware,
+  providers::{fetch_generation_stats, Provider},
+  redaction::RedactionMiddleware,
+  response_cache::CacheMiddleware,
+  session_config::SessionConfig,
+  vcr::{VcrMiddleware, VcrMode},
+};
+
+pub const DEFAULT_MIDDLEWARE: &[&str] = &["cache", "logging", "rate_limit", "usage"];
+
+// hooks that run around every chat completion request
+// pre_request hooks run in the configured order, post_response and on_complete hooks run in reverse order
+#[async_trait]
+pub trait Middleware: Send + Sync {
+  fn name(&self) -> &'static str;
+
+  // returning Some(responses) short circuits the request, and the responses are used in place of an api call
+  async fn pre_request(
+    &self,
+    _request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    Ok(None)
+  }
+
+  // called for each response, or for each chunk of a streamed response
+  async fn post_response(
+    &self,
+    _request: &CreateChatCompletionRequest,
+    _response: &mut ChatMessage,
+  ) -> Result<(), SazidError> {
+    Ok(())
+  }
+
+  // called once all responses to a request have been received
+  async fn on_complete(
+    &self,
+    _request: &CreateChatCompletionRequest,
+    _responses: &[ChatMessage],
+  ) -> Result<(), SazidError> {
+    Ok(())
+  }
+
+  async fn on_error(&self, _request: &CreateChatCompletionRequest, _error: &OpenAIError) {}
+
+  // warnings for the user about what pre_request changed in the request, such as the secrets it redacted
+  fn take_notices(&self) -> Vec<String> {
+    vec![]
+  }
+}
+
+#[derive(Default)]
+pub struct MiddlewareChain {
+  pub middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+  pub fn from_config(config: &SessionConfig) -> Result<Self, SazidError> {
+    let mut chain = MiddlewareChain::default();
+    // redaction comes first unless the middleware setting places it, so that no other middleware sees the secrets
+    if !config.middleware.iter().any(|name| name == "redaction") {
+      chain.middleware.push(Self::create_middleware("redaction", config)?);
+    }
+    // a replay stands in for the api, so it comes before everything else
+    if let Some(VcrMode::Replay(path)) = &config.vcr {
+      chain.middleware.push(Box::new(VcrMiddleware { mode: VcrMode::Replay(path.clone()) }));
+    }
+    if config.provider == Provider::Mock {
+      chain.middleware.push(Box::new(MockProvider::load(config.mock_fixtures.as_deref())?));
+    }
+    if config.offline {
+      chain.middleware.push(Box::new(OfflineMiddleware::new(config)));
+    }
+    for name in config.middleware.iter() {
+      chain.middleware.push(Self::create_middleware(name, config)?);
+    }
+    // a recording comes last, so that it sees exactly what the api returned
+    if let Some(VcrMode::Record(path)) = &config.vcr {
+      chain.middleware.push(Box::new(VcrMiddleware { mode: VcrMode::Record(path.clone()) }));
+    }
+    Ok(chain)
+  }
+
+  pub fn create_middleware(name: &str, config: &SessionConfig) -> Result<Box<dyn Middleware>, SazidError> {
+    match name {
+      "cache" => Ok(Box::new(CacheMiddleware::new(&config.response_cache)?)),
+      "logging" => Ok(Box::new(LoggingMiddleware)),
+      "rate_limit" => Ok(Box::new(RateLimitMiddleware::new(config.requests_per_minute))),
+      "redaction" => Ok(Box::new(RedactionMiddleware::new(&config.redaction)?)),
+      "usage" => Ok(Box::new(UsageTrackerMiddleware::new(config))),
+      _ => Err(SazidError::Other(format!("unknown middleware: {}", name))),
+    }
+  }
+
+  pub fn with(mut self, middleware: Box<dyn Middleware>) -> Self {
+    self.middleware.push(middleware);
+    self
+  }
+
+  pub fn take_notices(&self) -> Vec<String> {
+    self.middleware.iter().flat_map(|middleware| middleware.take_notices()).collect()
+  }
+
+  pub async fn pre_request(
+    &self,
+    request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    for middleware in self.middleware.iter() {
+      if let Some(responses) = middleware.pre_request(request).await? {
+        trace_dbg!("request short circuited by {} middleware", middleware.name());
+        return Ok(Some(responses));
+      }
+    }
+    Ok(None)
+  }
+
+  pub async fn post_response(
+    &self,
+    request: &CreateChatCompletionRequest,
+    response: &mut ChatMessage,
+  ) -> Result<(), SazidError> {
+    for middleware in self.middleware.iter().rev() {
+      middleware.post_response(request, response).await?;
+    }
+    Ok(())
+  }
+
+  pub async fn on_complete(
+    &self,
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+  ) -> Result<(), SazidError> {
+    for middleware in self.middleware.iter().rev() {
+      middleware.on_complete(request, responses).await?;
+    }
+    Ok(())
+  }
+
+  pub async fn on_error(&self, request: &CreateChatCompletionRequest, error: &OpenAIError) {
+    for middleware in self.middleware.iter().rev() {
+      middleware.on_error(request, error).await;
+    }
+  }
+}
+
+pub struct LoggingMiddleware;
+
+#[async_trait]
+impl Middleware for LoggingMiddleware {
+  fn name(&self) -> &'static str {
+    "logging"
+  }
+
+  async fn pre_request(
+    &self,
+    request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    tracing::info!(model = request.model, messages = request.messages.len(), "sending chat completion request");
+    trace_dbg!("request: {:#?}", request);
+    Ok(None)
+  }
+
+  async fn on_complete(
+    &self,
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+  ) -> Result<(), SazidError> {
+    tracing::info!(model = request.model, responses = responses.len(), "chat completion request complete");
+    Ok(())
+  }
+
+  async fn on_error(&self, request: &CreateChatCompletionRequest, error: &OpenAIError) {
+    // the debug form carries the fields of the api's error body
+    tracing::error!(model = request.model, error = ?error, "chat completion request failed: {}", error);
+  }
+}
+
+lazy_static! {
+  static ref LAST_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+// spaces requests out so that no more than requests_per_minute are sent, 0 disables the limit
+pub struct RateLimitMiddleware {
+  pub min_interval: Option<Duration>,
+}
+
+impl RateLimitMiddleware {
+  pub fn new(requests_per_minute: u32) -> Self {
+    RateLimitMiddleware {
+      min_interval: match requests_per_minute {
+        0 => None,
+        rpm => Some(Duration::from_secs_f64(60.0 / rpm as f64)),
+      },
+    }
+  }
+}
+
+#[async_trait]
+impl Middleware for RateLimitMiddleware {
+  fn name(&self) -> &'static str {
+    "rate_limit"
+  }
+
+  async fn pre_request(
+    &self,
+    _request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    if let Some(min_interval) = self.min_interval {
+      let mut last_request = LAST_REQUEST.lock().await;
+      if let Some(last) = *last_request {
+        let elapsed = last.elapsed();
+        if elapsed < min_interval {
+          trace_dbg!("rate limiting request for {:?}", min_interval - elapsed);
+          tokio::time::sleep(min_interval - elapsed).await;
+        }
+      }
+      *last_request = Some(Instant::now());
+    }
+    Ok(None)
+  }
+}
+
+pub struct UsageTrackerMiddleware {
+  // set when requests go through openrouter, which reports the cost of each generation
+  pub openrouter_config: Option<OpenAIConfig>,
+}
+
+impl UsageTrackerMiddleware {
+  pub fn new(config: &SessionConfig) -> Self {
+    UsageTrackerMiddleware {
+      openrouter_config: match config.provider {
+        Provider::OpenRouter => Some(config.openai_config.clone()),
+        _ => None,
+      },
+    }
+  }
+}
+
+#[async_trait]
+impl Middleware for UsageTrackerMiddleware {
+  fn name(&self) -> &'static str {
+    "usage"
+  }
+
+  async fn on_complete(
+    &self,
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+  ) -> Result<(), SazidError> {
+    let prompt_tokens = count_tokens(&serde_json::to_string(&request.messages).unwrap_or_default());
+    let completion_tokens: usize = responses
+      .iter()
+      .map(|response| match response {
+        ChatMessage::Response(response) => {
+          response.choices.iter().filter_map(|c| c.message.content.as_ref()).map(|c| count_tokens(c)).sum()
+        },
+        ChatMessage::StreamResponse(srvec) => srvec
+          .iter()
+          .flat_map(|sr| sr.choices.iter())
+          .filter_map(|c| c.delta.content.as_ref())
+          .map(|c| count_tokens(c))
+          .sum(),
+        _ => 0,
+      })
+      .sum();
+    tracing::info!(model = request.model, prompt_tokens, completion_tokens, "chat completion usage");
+    let generation_id = responses.iter().find_map(|response| match response {
+      ChatMessage::Response(response) => Some(response.id.clone()),
+      ChatMessage::StreamResponse(srvec) => srvec.first().map(|sr| sr.id.clone()),
+      _ => None,
+    });
+    if let (Some(openrouter_config), Some(generation_id)) = (self.openrouter_config.clone(), generation_id) {
+      // openrouter accounts for usage after the response completes, so the stats are fetched in the background
+      tokio::spawn(async move {
+        match fetch_generation_stats(&openrouter_config, &generation_id).await {
+          Ok(stats) => tracing::info!(
+            model = stats.model,
+            prompt_tokens = stats.native_tokens_prompt,
+            completion_tokens = stats.native_tokens_completion,
+            cost = stats.total_cost,
+            "openrouter generation usage"
+          ),
+          Err(e) => tracing::warn!("{}", e),
+        }
+      });
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
+  };
+
+  use super::*;
+
+  struct CannedResponseMiddleware;
+
+  #[async_trait]
+  impl Middleware for CannedResponseMiddleware {
+    fn name(&self) -> &'static str {
+      "canned"
+    }
+
+    async fn pre_request(
+      &self,
+      _request: &mut CreateChatCompletionRequest,
+    ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+      Ok(Some(vec![]))
+    }
+  }
+
+  #[test]
+  fn test_from_config_rejects_unknown_middleware() {
+    let config = SessionConfig { middleware: vec!["logging".to_string(), "bogus".to_string()], ..Default::default() };
+    assert!(MiddlewareChain::from_config(&config).is_err());
+    let config = SessionConfig { middleware: vec!["logging".to_string(), "usage".to_string()], ..Default::default() };
+    let chain = MiddlewareChain::from_config(&config).unwrap();
+    let names = chain.middleware.iter().map(|middleware| middleware.name()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["redaction", "logging", "usage"]);
+  }
+
+  #[tokio::test]
+  async fn test_redaction_middleware() {
+    let chain = MiddlewareChain::from_config(&SessionConfig::default()).unwrap();
+    let mut request = CreateChatCompletionRequest {
+      messages: vec![ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text("mail me@example.com".to_string())),
+      })],
+      ..Default::default()
+    };
+    chain.pre_request(&mut request).await.unwrap();
+    assert_eq!(serde_json::to_value(&request.messages[0]).unwrap()["content"], "mail [redacted email]");
+    assert_eq!(chain.take_notices(), vec!["redacted 1 secret before sending (email)"]);
+    assert!(chain.take_notices().is_empty());
+  }
+
+  #[test]
+  fn test_rate_limit_interval() {
+    assert_eq!(RateLimitMiddleware::new(0).min_interval, None);
+    assert_eq!(RateLimitMiddleware::new(30).min_interval, Some(Duration::from_secs(2)));
+  }
+
+  #[tokio::test]
+  async fn test_pre_request_short_circuits() {
+    let chain = MiddlewareChain::default().with(Box::new(CannedResponseMiddleware)).with(Box::new(LoggingMiddleware));
+    let mut request = CreateChatCompletionRequest::default();
+    assert_eq!(chain.pre_request(&mut request).await.unwrap(), Some(vec![]));
+  }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use async_openai::types::{
+  ChatChoice, ChatCompletionMessageToolCall, ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent,
+  ChatCompletionResponseMessage, ChatCompletionToolType, CreateChatCompletionRequest, CreateChatCompletionResponse,
+  FinishReason, FunctionCall, Role,
+};
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{errors::SazidError, messages::ChatMessage, middleware::Middleware};
+
+// the fixture file replayed by the mock provider, set with SAZID_PROVIDER=mock
+pub const MOCK_FIXTURES_ENV: &str = "SAZID_MOCK_FIXTURES";
+
+// a scripted response, a fixture file is a json list of these
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MockResponse {
+  // answers any request whose last user message contains this, responses without it are replayed in order
+  #[serde(default)]
+  pub when: Option<String>,
+  #[serde(default)]
+  pub content: Option<String>,
+  #[serde(default)]
+  pub tool_calls: Vec<MockToolCall>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MockToolCall {
+  pub name: String,
+  #[serde(default)]
+  pub arguments: serde_json::Value,
+}
+
+// answers requests with canned responses instead of calling the api, so that the tui, session persistence and
+// the tool loop can be tested without a network or api keys
+pub struct MockProvider {
+  pub responses: Vec<MockResponse>,
+}
+
+impl MockProvider {
+  // without fixtures every request is answered by echoing the last user message
+  pub fn load(fixtures: Option<&Path>) -> Result<Self, SazidError> {
+    let responses = match fixtures {
+      Some(path) => {
+        let contents = std::fs::read_to_string(path)
+          .map_err(|e| SazidError::Other(format!("could not read mock fixtures {}: {}", path.display(), e)))?;
+        json5::from_str(&contents)
+          .map_err(|e| SazidError::Other(format!("invalid mock fixtures {}: {}", path.display(), e)))?
+      },
+      None => vec![],
+    };
+    Ok(MockProvider { responses })
+  }
+
+  // the scripted responses are replayed in order by counting the assistant messages already in the request,
+  // so a replayed session and each turn of the tool loop get the same response every time
+  pub fn respond(&self, request: &CreateChatCompletionRequest) -> CreateChatCompletionResponse {
+    let turn = request.messages.iter().filter(|m| matches!(m, ChatCompletionRequestMessage::Assistant(_))).count();
+    let last_user_message = last_user_message(request);
+    let scripted = self
+      .responses
+      .iter()
+      .find(|r| r.when.as_ref().map_or(false, |when| last_user_message.contains(when.as_str())))
+      .or_else(|| self.responses.iter().filter(|r| r.when.is_none()).nth(turn));
+    let (content, tool_calls) = match scripted {
+      Some(response) => {
+        let tool_calls = response
+          .tool_calls
+          .iter()
+          .enumerate()
+          .map(|(i, call)| ChatCompletionMessageToolCall {
+            id: format!("mock-call-{}-{}", turn, i),
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionCall { name: call.name.clone(), arguments: call.arguments.to_string() },
+          })
+          .collect::<Vec<ChatCompletionMessageToolCall>>();
+        (response.content.clone(), Some(tool_calls).filter(|calls| !calls.is_empty()))
+      },
+      None => (Some(format!("mock response to: {}", last_user_message)), None),
+    };
+    let finish_reason = match tool_calls {
+      Some(_) => FinishReason::ToolCalls,
+      None => FinishReason::Stop,
+    };
+    CreateChatCompletionResponse {
+      id: format!("mock-{}", turn),
+      object: "chat.completion".to_string(),
+      created: 0,
+      model: request.model.clone(),
+      choices: vec![ChatChoice {
+        index: 0,
+        message: ChatCompletionResponseMessage { role: Role::Assistant, content, tool_calls, function_call: None },
+        finish_reason: Some(finish_reason),
+      }],
+      usage: None,
+      system_fingerprint: None,
+    }
+  }
+}
+
+fn last_user_message(request: &CreateChatCompletionRequest) -> String {
+  request
+    .messages
+    .iter()
+    .rev()
+    .find_map(|message| match message {
+      ChatCompletionRequestMessage::User(user) => match &user.content {
+        Some(ChatCompletionRequestUserMessageContent::Text(text)) => Some(text.clone()),
+        _ => None,
+      },
+      _ => None,
+    })
+    .unwrap_or_default()
+}
+
+#[async_trait]
+impl Middleware for MockProvider {
+  fn name(&self) -> &'static str {
+    "mock"
+  }
+
+  async fn pre_request(
+    &self,
+    request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    Ok(Some(vec![ChatMessage::Response(self.respond(request))]))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{ChatCompletionRequestAssistantMessage, ChatCompletionRequestUserMessage};
+
+  use super::*;
+
+  fn user(text: &str) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text(text.to_string())),
+    })
+  }
+
+  fn assistant() -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      content: Some("ok".to_string()),
+      tool_calls: None,
+      function_call: None,
+    })
+  }
+
+  #[test]
+  fn test_mock_provider_replays_fixtures() {
+    let dir = tempfile::tempdir().unwrap();
+    let fixtures = dir.path().join("fixtures.json5");
+    std::fs::write(
+      &fixtures,
+      r#"[
+        { tool_calls: [{ name: "read_file", arguments: { path: "README.md" } }] },
+        { content: "the readme describes sazid" },
+        { when: "hello", content: "hi there" },
+      ]"#,
+    )
+    .unwrap();
+    let provider = MockProvider::load(Some(&fixtures)).unwrap();
+    let messages = vec![user("summarize")];
+    let mut request = CreateChatCompletionRequest { model: "gpt-4".to_string(), messages, ..Default::default() };
+
+    let response = provider.respond(&request);
+    let tool_calls = response.choices[0].message.tool_calls.clone().unwrap();
+    assert_eq!(tool_calls[0].function.name, "read_file");
+    assert_eq!(tool_calls[0].function.arguments, r#"{"path":"README.md"}"#);
+    assert_eq!(response.choices[0].finish_reason, Some(FinishReason::ToolCalls));
+
+    request.messages.push(assistant());
+    assert_eq!(provider.respond(&request).choices[0].message.content.as_deref(), Some("the readme describes sazid"));
+
+    request.messages.push(assistant());
+    request.messages.push(user("hello"));
+    assert_eq!(provider.respond(&request).choices[0].message.content.as_deref(), Some("hi there"));
+
+    request.messages.push(user("what now?"));
+    let response = provider.respond(&request);
+    assert_eq!(response.choices[0].message.content.as_deref(), Some("mock response to: what now?"));
+    assert_eq!(response.model, "gpt-4");
+  }
+}
@@ -0,0 +1,203 @@
+use std::{collections::HashMap, time::Instant};
+
+use async_openai::{
+  config::OpenAIConfig,
+  types::{CreateChatCompletionRequest, CreateChatCompletionResponse},
+  Client,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  messages::{ChatMessage, MessageContainer, RenderedChatMessage},
+  middleware::MiddlewareChain,
+  model_list::ModelPricing,
+  retry::{create_with_retry, RetryPolicy},
+  session_stats::Transaction,
+};
+
+// what one model responded to a prompt sent to two models, kept with the transaction of the prompt
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComparedResponse {
+  pub model: String,
+  pub content: String,
+  pub latency_ms: u64,
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+  // dollars, None when pricing is not configured for the model
+  pub cost: Option<f64>,
+  #[serde(default)]
+  pub error: Option<String>,
+  // the response kept in the transcript
+  #[serde(default)]
+  pub kept: bool,
+}
+
+impl ComparedResponse {
+  pub fn new(transaction: &Transaction, content: String) -> Self {
+    ComparedResponse {
+      model: transaction.model.clone(),
+      content,
+      latency_ms: transaction.latency_ms,
+      prompt_tokens: transaction.prompt_tokens,
+      completion_tokens: transaction.completion_tokens,
+      cost: transaction.cost,
+      error: transaction.error.clone(),
+      kept: false,
+    }
+  }
+
+  // the model with its latency, cost and tokens, for the header of its pane
+  pub fn annotation(&self) -> String {
+    if let Some(error) = &self.error {
+      return format!("{} · failed: {}", self.model, error);
+    }
+    let cost = self.cost.map_or("no pricing".to_string(), |cost| format!("${:.4}", cost));
+    format!(
+      "{} · {:.1}s · {} · {} tokens",
+      self.model,
+      self.latency_ms as f64 / 1000.0,
+      cost,
+      self.prompt_tokens + self.completion_tokens
+    )
+  }
+}
+
+// a/b mode, each prompt goes to the session's model as usual and to a second model alongside it, and the two
+// responses are shown side by side to keep one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelComparison {
+  // the model compared with the session's model
+  pub model: String,
+  // a prompt was sent to both models and the responses haven't been compared yet
+  pub awaiting: bool,
+  // the session's model has finished responding to it
+  pub first_done: bool,
+  // the second model's response once it arrives, with the response to put in the transcript if it is kept
+  pub alternative: Option<(ComparedResponse, Option<CreateChatCompletionResponse>)>,
+}
+
+impl ModelComparison {
+  pub fn new(model: &str) -> Self {
+    ModelComparison { model: model.to_string(), ..Default::default() }
+  }
+
+  // a prompt was sent to both models
+  pub fn start(&mut self) {
+    (self.awaiting, self.first_done, self.alternative) = (true, false, None);
+  }
+
+  pub fn finish(&mut self) {
+    (self.awaiting, self.first_done, self.alternative) = (false, false, None);
+  }
+
+  // both models have responded to the prompt
+  pub fn is_ready(&self) -> bool {
+    self.awaiting && self.first_done && self.alternative.is_some()
+  }
+}
+
+// sends the request to the second model without streaming, through the same middleware as the session's request, the
+// response is compared once both have arrived
+pub async fn request_alternative(
+  client: &Client<OpenAIConfig>,
+  middleware: &MiddlewareChain,
+  mut request: CreateChatCompletionRequest,
+  retry_policy: &RetryPolicy,
+  pricing: &HashMap<String, ModelPricing>,
+) -> (ComparedResponse, Option<CreateChatCompletionResponse>) {
+  let (timestamp, started) = (chrono::Utc::now().timestamp(), Instant::now());
+  let failed = |request: &CreateChatCompletionRequest, error: String| {
+    let transaction = Transaction::failed(request, timestamp, started.elapsed(), error);
+    (ComparedResponse::new(&transaction, String::new()), None)
+  };
+  let response = match middleware.pre_request(&mut request).await {
+    // a middleware answered in place of the api, as the cache, a replay or the mock provider do
+    Ok(Some(responses)) => match responses.into_iter().find_map(|message| match message {
+      ChatMessage::Response(response) => Some(response),
+      _ => None,
+    }) {
+      Some(response) => response,
+      None => return failed(&request, "no response from the middleware".to_string()),
+    },
+    Ok(None) => match create_with_retry(client, &request, retry_policy, |_, _, _| {}).await {
+      Ok(response) => {
+        let mut message = ChatMessage::Response(response);
+        if let Err(e) = middleware.post_response(&request, &mut message).await {
+          return failed(&request, e.to_string());
+        }
+        if let Err(e) = middleware.on_complete(&request, std::slice::from_ref(&message)).await {
+          log::warn!("{}", e);
+        }
+        match message {
+          ChatMessage::Response(response) => response,
+          _ => return failed(&request, "no response from the api".to_string()),
+        }
+      },
+      Err(e) => {
+        middleware.on_error(&request, &e).await;
+        return failed(&request, e.to_string());
+      },
+    },
+    Err(e) => return failed(&request, e.to_string()),
+  };
+  let responses = vec![ChatMessage::Response(response.clone())];
+  let transaction = Transaction::new(&request, &responses, timestamp, started.elapsed(), pricing);
+  let message = MessageContainer::from(ChatMessage::Response(response.clone()));
+  (ComparedResponse::new(&transaction, RenderedChatMessage::from(&message).content), Some(response))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
+  };
+
+  use super::*;
+  use crate::app::{
+    mock_provider::MockProvider,
+    redaction::{RedactionConfig, RedactionMiddleware},
+  };
+
+  #[test]
+  fn test_compared_response_annotation() {
+    let request = CreateChatCompletionRequest { model: "gpt-4".to_string(), ..Default::default() };
+    let mut transaction = Transaction::failed(&request, 0, Duration::from_millis(2500), "rate limited".to_string());
+    assert_eq!(ComparedResponse::new(&transaction, String::new()).annotation(), "gpt-4 · failed: rate limited");
+
+    (transaction.error, transaction.prompt_tokens, transaction.completion_tokens) = (None, 300, 12);
+    transaction.cost = Some(0.0093);
+    let compared = ComparedResponse::new(&transaction, "an answer".to_string());
+    assert_eq!(compared.annotation(), "gpt-4 · 2.5s · $0.0093 · 312 tokens");
+
+    let mut comparison = ModelComparison::new("gpt-3.5-turbo");
+    comparison.start();
+    comparison.first_done = true;
+    assert!(!comparison.is_ready());
+    comparison.alternative = Some((compared, None));
+    assert!(comparison.is_ready());
+    comparison.finish();
+    assert!(!comparison.awaiting && comparison.alternative.is_none());
+  }
+
+  #[tokio::test]
+  async fn test_request_alternative_goes_through_middleware() {
+    let middleware = MiddlewareChain::default()
+      .with(Box::new(RedactionMiddleware::new(&RedactionConfig::default()).unwrap()))
+      .with(Box::new(MockProvider::load(None).unwrap()));
+    let request = CreateChatCompletionRequest {
+      model: "gpt-3.5-turbo".to_string(),
+      messages: vec![ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text("mail me@example.com".to_string())),
+      })],
+      ..Default::default()
+    };
+    let (compared, response) =
+      request_alternative(&Client::new(), &middleware, request, &RetryPolicy::default(), &HashMap::new()).await;
+    assert_eq!((compared.model.as_str(), compared.error), ("gpt-3.5-turbo", None));
+    assert!(compared.content.contains("mock response to: mail [redacted email]"));
+    assert!(response.is_some());
+  }
+}
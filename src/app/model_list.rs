@@ -0,0 +1,80 @@
+use std::{collections::HashMap, fmt};
+
+use async_openai::config::OpenAIConfig;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::components::session::create_openai_client;
+
+use super::{consts::AVAILABLE_MODELS, errors::SazidError, types::Model};
+
+// prices in dollars per 1000 tokens, configured under model_pricing
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ModelPricing {
+  pub prompt: f64,
+  pub completion: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelListing {
+  pub model: Model,
+  pub pricing: Option<ModelPricing>,
+  // whether the api lists the model for the configured account
+  pub available: bool,
+}
+
+impl fmt::Display for ModelListing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:<28} {:>7} tokens", self.model.name, self.model.token_limit)?;
+    match &self.pricing {
+      Some(pricing) => write!(f, "  ${:.4}/1k prompt  ${:.4}/1k completion", pricing.prompt, pricing.completion)?,
+      None => write!(f, "  pricing not configured")?,
+    }
+    if !self.available {
+      write!(f, "  (unavailable)")?;
+    }
+    Ok(())
+  }
+}
+
+// merges the known models with the models reported by the api, available models first
+pub fn model_listings(api_models: &[String], pricing: &HashMap<String, ModelPricing>) -> Vec<ModelListing> {
+  let mut models: Vec<Model> = AVAILABLE_MODELS.clone();
+  api_models.iter().filter(|name| !models.iter().any(|m| &&m.name == name)).for_each(|name| {
+    models.push(Model::from_name(name));
+  });
+  let mut listings: Vec<ModelListing> = models
+    .into_iter()
+    .map(|model| ModelListing {
+      pricing: pricing.get(&model.name).cloned(),
+      available: api_models.contains(&model.name),
+      model,
+    })
+    .collect();
+  listings.sort_by(|a, b| b.available.cmp(&a.available).then(a.model.name.cmp(&b.model.name)));
+  listings
+}
+
+pub async fn fetch_model_listings(
+  openai_config: &OpenAIConfig,
+  pricing: &HashMap<String, ModelPricing>,
+) -> Result<Vec<ModelListing>, SazidError> {
+  let client = create_openai_client(openai_config);
+  let response = client.models().list().await?;
+  let api_models: Vec<String> = response.data.into_iter().map(|model| model.id).collect();
+  Ok(model_listings(&api_models, pricing))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_model_listings_merges_api_models() {
+    let pricing = HashMap::from([("gpt-4".to_string(), ModelPricing { prompt: 0.03, completion: 0.06 })]);
+    let listings = model_listings(&["gpt-4".to_string(), "my-local-model".to_string()], &pricing);
+    assert_eq!(listings.len(), AVAILABLE_MODELS.len() + 1);
+    assert!(listings[0].available && listings[1].available);
+    assert_eq!(listings.iter().find(|l| l.model.name == "gpt-4").unwrap().pricing.as_ref().unwrap().prompt, 0.03);
+    assert!(!listings.iter().find(|l| l.model.name == "gpt-3.5-turbo").unwrap().available);
+  }
+}
@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::errors::SazidError;
+
+// jobs that finish sooner are assumed to be watched, and don't notify
+const DEFAULT_MIN_DURATION_SECS: u64 = 60;
+
+// the jobs that can notify when they finish
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Job {
+  // ingesting files or a git repository from the command line
+  Ingest,
+  Agent,
+  Batch,
+  Workflow,
+}
+
+impl Job {
+  pub const ALL: [Job; 4] = [Job::Ingest, Job::Agent, Job::Batch, Job::Workflow];
+}
+
+impl std::fmt::Display for Job {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      Job::Ingest => "ingestion",
+      Job::Agent => "agent",
+      Job::Batch => "batch",
+      Job::Workflow => "workflow",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+// how long running jobs report that they finished, with a desktop notification, a json POST to a webhook, or both
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct NotificationConfig {
+  pub desktop: bool,
+  pub webhook: Option<String>,
+  pub min_duration_secs: u64,
+  pub jobs: Vec<Job>,
+}
+
+impl Default for NotificationConfig {
+  fn default() -> Self {
+    NotificationConfig {
+      desktop: false,
+      webhook: None,
+      min_duration_secs: DEFAULT_MIN_DURATION_SECS,
+      jobs: Job::ALL.to_vec(),
+    }
+  }
+}
+
+impl NotificationConfig {
+  // set with --notify, the job notifies however long it takes, on the desktop when nothing else is configured
+  pub fn forced(mut self) -> Self {
+    self.desktop = self.desktop || self.webhook.is_none();
+    self.min_duration_secs = 0;
+    self.jobs = Job::ALL.to_vec();
+    self
+  }
+
+  pub fn notifies(&self, job: Job, elapsed: Duration) -> bool {
+    (self.desktop || self.webhook.is_some())
+      && self.jobs.contains(&job)
+      && elapsed >= Duration::from_secs(self.min_duration_secs)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+  pub job: Job,
+  pub succeeded: bool,
+  // what finished or why it failed
+  pub detail: String,
+  pub elapsed: Duration,
+}
+
+impl Notification {
+  pub fn new<T>(job: Job, started: Instant, result: &Result<T, SazidError>, detail: &str) -> Self {
+    let (succeeded, detail) = match result {
+      Ok(_) => (true, detail.to_string()),
+      Err(e) => (false, e.to_string()),
+    };
+    Notification { job, succeeded, detail, elapsed: started.elapsed() }
+  }
+
+  pub fn title(&self) -> String {
+    let outcome = if self.succeeded { "finished" } else { "failed" };
+    format!("sazid {} {} after {}", self.job, outcome, format_elapsed(self.elapsed))
+  }
+
+  fn webhook_body(&self) -> serde_json::Value {
+    json!({
+      // shown by chat webhooks such as slack's
+      "text": format!("{}: {}", self.title(), self.detail),
+      "job": self.job,
+      "succeeded": self.succeeded,
+      "detail": self.detail,
+      "elapsed_secs": self.elapsed.as_secs(),
+    })
+  }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+  let secs = elapsed.as_secs();
+  match secs {
+    0..=59 => format!("{}s", secs),
+    60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+    _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+  }
+}
+
+// sends the notification where the config asks, a notification that can't be sent is logged, the job's result stands
+pub async fn notify(config: &NotificationConfig, notification: Notification, offline: bool) {
+  if !config.notifies(notification.job, notification.elapsed) {
+    return;
+  }
+  if config.desktop {
+    let (title, detail) = (notification.title(), notification.detail.clone());
+    let shown = tokio::task::spawn_blocking(move || {
+      notify_rust::Notification::new().summary(&title).body(&detail).show().map(|_| ()).map_err(|e| e.to_string())
+    });
+    if let Ok(Err(e)) = shown.await {
+      log::error!("failed to show a desktop notification: {}", e);
+    }
+  }
+  match (&config.webhook, offline) {
+    (Some(_), true) => log::warn!("the webhook isn't called in offline mode"),
+    (Some(webhook), false) => {
+      let sent = reqwest::Client::new()
+        .post(webhook)
+        .json(&notification.webhook_body())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+      if let Err(e) = sent {
+        log::error!("failed to call the notification webhook: {}", e);
+      }
+    },
+    (None, _) => {},
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_notifies() {
+    let config = NotificationConfig::default();
+    assert!(!config.notifies(Job::Ingest, Duration::from_secs(3600)));
+    let config = NotificationConfig { desktop: true, jobs: vec![Job::Ingest, Job::Agent], ..config };
+    assert!(config.notifies(Job::Ingest, Duration::from_secs(60)));
+    assert!(!config.notifies(Job::Ingest, Duration::from_secs(59)));
+    assert!(!config.notifies(Job::Batch, Duration::from_secs(3600)));
+    assert!(NotificationConfig::default().forced().notifies(Job::Batch, Duration::ZERO));
+
+    let result: Result<(), SazidError> = Err(SazidError::Other("database is down".to_string()));
+    let mut notification = Notification::new(Job::Ingest, Instant::now(), &result, "ingested src");
+    notification.elapsed = Duration::from_secs(3725);
+    assert_eq!(notification.title(), "sazid ingestion failed after 1h 2m");
+    assert_eq!(
+      notification.webhook_body()["text"],
+      json!("sazid ingestion failed after 1h 2m: Error: database is down")
+    );
+    assert_eq!(notification.webhook_body()["job"], json!("ingest"));
+  }
+}
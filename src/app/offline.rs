@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use async_openai::{config::Config, error::OpenAIError, types::CreateChatCompletionRequest};
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+
+use super::{errors::SazidError, messages::ChatMessage, middleware::Middleware, session_config::SessionConfig};
+
+// how often connectivity is checked while requests are queued
+pub const CONNECTIVITY_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// errors where the request never reached the api, so it can be sent again unchanged once the network is back
+pub fn is_network_error(error: &OpenAIError) -> bool {
+  match error {
+    OpenAIError::Reqwest(e) => e.is_connect() || e.is_timeout(),
+    OpenAIError::StreamError(e) => {
+      let e = e.to_lowercase();
+      ["error sending request", "transport", "connection", "dns", "timed out"].iter().any(|s| e.contains(s))
+    },
+    _ => false,
+  }
+}
+
+// whether a tcp connection can be opened to the host of the api base url
+pub async fn is_api_reachable(api_base: &str) -> bool {
+  let (host, port) = match url::Url::parse(api_base) {
+    Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+      (Some(host), Some(port)) => (host.to_string(), port),
+      _ => return false,
+    },
+    Err(_) => return false,
+  };
+  matches!(tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host.as_str(), port))).await, Ok(Ok(_)))
+}
+
+// resolves once the api can be reached
+pub async fn wait_for_connectivity(api_base: &str) {
+  while !is_api_reachable(api_base).await {
+    tokio::time::sleep(CONNECTIVITY_CHECK_INTERVAL).await;
+  }
+}
+
+// whether the api runs on this machine, and so can still be used in offline mode
+pub fn is_local_api_base(api_base: &str) -> bool {
+  match url::Url::parse(api_base).ok().and_then(|url| url.host_str().map(|host| host.to_string())) {
+    Some(host) => matches!(host.as_str(), "localhost" | "127.0.0.1" | "[::1]" | "::1"),
+    None => false,
+  }
+}
+
+pub fn ensure_online(offline: bool, action: &str) -> Result<(), SazidError> {
+  match offline {
+    true => Err(SazidError::Other(format!("{} needs the network, which is disabled in offline mode", action))),
+    false => Ok(()),
+  }
+}
+
+// rejects requests to remote apis, the chain puts it first in offline mode so that no other middleware runs
+pub struct OfflineMiddleware {
+  pub api_base: String,
+}
+
+impl OfflineMiddleware {
+  pub fn new(config: &SessionConfig) -> Self {
+    OfflineMiddleware { api_base: config.openai_config.api_base().to_string() }
+  }
+}
+
+#[async_trait]
+impl Middleware for OfflineMiddleware {
+  fn name(&self) -> &'static str {
+    "offline"
+  }
+
+  async fn pre_request(
+    &self,
+    _request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    match is_local_api_base(&self.api_base) {
+      true => Ok(None),
+      false => Err(SazidError::Other(format!("offline mode blocks requests to {}", self.api_base))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_is_network_error() {
+    let error = OpenAIError::StreamError("Transport error: error sending request for url".to_string());
+    assert!(is_network_error(&error));
+    assert!(!is_network_error(&OpenAIError::StreamError("Invalid status code: 401 Unauthorized".to_string())));
+    assert!(!is_network_error(&OpenAIError::InvalidArgument("bad".to_string())));
+  }
+
+  #[test]
+  fn test_is_local_api_base() {
+    assert!(is_local_api_base("http://localhost:1234/v1"));
+    assert!(is_local_api_base("http://127.0.0.1:8080"));
+    assert!(!is_local_api_base("https://api.openai.com/v1"));
+    assert!(!is_local_api_base("not a url"));
+  }
+}
@@ -0,0 +1,165 @@
+use async_openai::types::{
+  ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+  ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
+};
+use serde_derive::{Deserialize, Serialize};
+
+// a system prompt, and optionally a model, that responds under its own name, see Panel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Persona {
+  pub prompt: String,
+  // None for the session's model
+  #[serde(default)]
+  pub model: Option<String>,
+}
+
+// personas that take turns responding, started with the personas command. each input starts a round in which every
+// persona responds once, in order, and the user interjects by cancelling a turn or by sending the next input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Panel {
+  pub personas: Vec<(String, Persona)>,
+  // the persona responding, None between rounds
+  turn: Option<usize>,
+}
+
+impl Panel {
+  pub fn new(personas: Vec<(String, Persona)>) -> Self {
+    Panel { personas, turn: None }
+  }
+
+  pub fn start_round(&mut self) {
+    self.turn = Some(0);
+  }
+
+  pub fn end_round(&mut self) {
+    self.turn = None;
+  }
+
+  // the persona responding, None between rounds
+  pub fn speaker(&self) -> Option<&(String, Persona)> {
+    self.turn.and_then(|turn| self.personas.get(turn))
+  }
+
+  // passes the turn to the next persona, false once every persona has responded, which ends the round
+  pub fn next_turn(&mut self) -> bool {
+    self.turn = self.turn.map(|turn| turn + 1).filter(|turn| *turn < self.personas.len());
+    self.turn.is_some()
+  }
+
+  pub fn describe(&self) -> String {
+    let names = self.personas.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ");
+    match self.speaker() {
+      Some((name, _)) => format!("personas {}, {} is responding", names, name),
+      None => format!("personas {}, waiting for input", names),
+    }
+  }
+
+  // the messages of the request for the persona responding, each paired with the persona that wrote it. the
+  // persona's prompt follows the leading system messages, and the other personas' answers are passed on as what they
+  // said, the answers that called tools stay as they are so that the tool results still follow their calls
+  pub fn address(
+    &self,
+    messages: Vec<(Option<&str>, ChatCompletionRequestMessage)>,
+  ) -> Vec<ChatCompletionRequestMessage> {
+    let Some((speaker, persona)) = self.speaker() else {
+      return messages.into_iter().map(|(_, message)| message).collect();
+    };
+    let others = self.personas.iter().map(|(name, _)| name.as_str()).filter(|name| name != speaker);
+    let instructions = format!(
+      "You are {}, in a conversation with the user and {}. Messages that start with a name and \"said:\" were \
+      written by the others.\n\n{}",
+      speaker,
+      others.collect::<Vec<_>>().join(", "),
+      persona.prompt
+    );
+    let mut addressed = messages
+      .into_iter()
+      .map(|(author, message)| match (author, message) {
+        (
+          Some(author),
+          ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+            content: Some(content),
+            tool_calls,
+            ..
+          }),
+        ) if author != speaker && tool_calls.as_ref().map_or(true, Vec::is_empty) => {
+          ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+            role: Role::User,
+            content: Some(ChatCompletionRequestUserMessageContent::Text(format!("{} said:\n{}", author, content))),
+          })
+        },
+        (_, message) => message,
+      })
+      .collect::<Vec<_>>();
+    let position = addressed
+      .iter()
+      .position(|message| !matches!(message, ChatCompletionRequestMessage::System(_)))
+      .unwrap_or(addressed.len());
+    addressed.insert(
+      position,
+      ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+        role: Role::System,
+        content: Some(instructions),
+      }),
+    );
+    addressed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn persona(prompt: &str) -> Persona {
+    Persona { prompt: prompt.to_string(), model: None }
+  }
+
+  fn assistant(content: &str) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      content: Some(content.to_string()),
+      tool_calls: None,
+      function_call: None,
+    })
+  }
+
+  #[test]
+  fn test_panel_turns() {
+    let mut panel = Panel::new(vec![
+      ("architect".to_string(), persona("Propose a design.")),
+      ("reviewer".to_string(), persona("Find the flaws in the design.")),
+    ]);
+    assert!(panel.speaker().is_none());
+    panel.start_round();
+    assert_eq!(panel.speaker().unwrap().0, "architect");
+    assert!(panel.next_turn());
+    assert_eq!(panel.speaker().unwrap().0, "reviewer");
+
+    let system = ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+      role: Role::System,
+      content: Some("You are helpful.".to_string()),
+    });
+    let messages = vec![(None, system.clone()), (Some("architect"), assistant("use a queue"))];
+    let addressed = panel.address(messages);
+    assert_eq!(addressed.len(), 3);
+    assert_eq!(addressed[0], system);
+    match &addressed[1] {
+      ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage { content: Some(content), .. }) => {
+        assert!(content.starts_with("You are reviewer, in a conversation with the user and architect."));
+        assert!(content.ends_with("Find the flaws in the design."));
+      },
+      message => panic!("expected the persona's prompt, got {:?}", message),
+    }
+    match &addressed[2] {
+      ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        content: Some(ChatCompletionRequestUserMessageContent::Text(text)),
+        ..
+      }) => assert_eq!(text, "architect said:\nuse a queue"),
+      message => panic!("expected the architect's answer as a user message, got {:?}", message),
+    }
+    assert_eq!(panel.address(vec![(Some("reviewer"), assistant("too slow"))])[1], assistant("too slow"));
+
+    assert!(!panel.next_turn());
+    assert!(panel.speaker().is_none());
+  }
+}
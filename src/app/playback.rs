@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use async_openai::types::CreateChatCompletionStreamResponse;
+
+use super::{
+  messages::{ChatMessage, MessageContainer, ReceiveBuffer},
+  session_data::SessionData,
+  session_stats::Transaction,
+};
+
+// the pause between messages when the original timing isn't followed
+pub const PLAYBACK_STEP_MS: u64 = 1500;
+// the longest pause between messages with the original timing, so a user thinking for minutes doesn't stall it
+pub const PLAYBACK_MAX_PAUSE_MS: u64 = 5000;
+
+// a saved session played back message by message, for demos and reviews. nothing is sent while it plays, the
+// transcript shows the messages played so far and the session's own transcript is put back when it ends
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback {
+  pub session_id: String,
+  messages: Vec<MessageContainer>,
+  transactions: Vec<Transaction>,
+  // the messages shown so far
+  pub position: usize,
+  // the chunks shown so far of the streamed response being played
+  pub chunks: Option<usize>,
+  pub playing: bool,
+  // the pauses between messages and the chunks of streamed responses follow the original timing
+  pub timed: bool,
+  // each scheduled step carries the generation it was scheduled in, so that a step scheduled before a pause or a
+  // skip is ignored
+  pub generation: u64,
+  // the session's transcript, while the playback takes its place
+  pub original: SessionData,
+}
+
+impl Playback {
+  pub fn new(session_id: &str, data: SessionData, timed: bool, original: SessionData) -> Self {
+    Playback {
+      session_id: session_id.to_string(),
+      messages: data.messages,
+      transactions: data.transactions,
+      position: 0,
+      chunks: None,
+      playing: false,
+      timed,
+      generation: 0,
+      original,
+    }
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.position >= self.messages.len()
+  }
+
+  // the whole of the next message
+  pub fn step_forward(&mut self) {
+    self.chunks = None;
+    self.position = (self.position + 1).min(self.messages.len());
+    self.generation += 1;
+  }
+
+  pub fn step_back(&mut self) {
+    match self.chunks.take() {
+      Some(_) => {},
+      None => self.position = self.position.saturating_sub(1),
+    }
+    self.generation += 1;
+  }
+
+  pub fn toggle(&mut self) -> bool {
+    self.playing = !self.playing && !self.is_finished();
+    self.generation += 1;
+    self.playing
+  }
+
+  // the next step while playing, a chunk of a streamed response when the original timing is followed
+  pub fn advance(&mut self) {
+    let chunks = self.stream_chunks(self.position).filter(|_| self.timed).map_or(0, |chunks| chunks.len());
+    match self.chunks.map_or(1, |shown| shown + 1) {
+      shown if shown < chunks => self.chunks = Some(shown),
+      _ => {
+        self.chunks = None;
+        self.position = (self.position + 1).min(self.messages.len());
+      },
+    }
+    if self.is_finished() {
+      self.playing = false;
+    }
+  }
+
+  // how long to wait before the next step while playing
+  pub fn delay(&self) -> Duration {
+    let Some(next) = self.messages.get(self.position).filter(|_| self.timed) else {
+      return Duration::from_millis(PLAYBACK_STEP_MS);
+    };
+    let transaction = self.transaction(next);
+    let chunks = self.stream_chunks(self.position).map_or(0, |chunks| chunks.len());
+    let ms = match (self.chunks, transaction) {
+      // the time the response took to stream, spread over its chunks
+      (Some(_), Some(transaction)) if chunks > 0 => {
+        transaction.latency_ms.saturating_sub(transaction.response_latency_ms()) / chunks as u64
+      },
+      // the time the model took to start responding
+      (None, Some(transaction)) => transaction.response_latency_ms(),
+      (Some(_), _) => 0,
+      // the time between the message and the one before it
+      (None, None) => {
+        let previous = self.position.checked_sub(1).and_then(|i| self.messages[i].created_at);
+        match (previous, next.created_at) {
+          (Some(previous), Some(created_at)) => (created_at - previous).max(0) as u64 * 1000,
+          _ => PLAYBACK_STEP_MS,
+        }
+      },
+    };
+    Duration::from_millis(ms.min(PLAYBACK_MAX_PAUSE_MS))
+  }
+
+  // the transcript as far as it has been played
+  pub fn shown(&self) -> SessionData {
+    let mut messages = self.messages[..self.position].to_vec();
+    if let (Some(shown), Some(chunks)) = (self.chunks, self.stream_chunks(self.position)) {
+      messages.push(MessageContainer::from(ChatMessage::StreamResponse(chunks[..shown].to_vec())));
+    }
+    messages.iter_mut().for_each(|m| m.stylize_complete = false);
+    SessionData { messages, citations: self.original.citations.clone(), ..Default::default() }
+  }
+
+  pub fn describe(&self) -> String {
+    let state = match (self.playing, self.is_finished()) {
+      (true, _) => "playing",
+      (false, true) => "finished",
+      (false, false) => "paused",
+    };
+    format!(
+      "replaying {}, {} of {} messages, {}: space plays or pauses, left and right step, esc stops",
+      self.session_id,
+      self.position,
+      self.messages.len(),
+      state
+    )
+  }
+
+  fn stream_chunks(&self, index: usize) -> Option<&[CreateChatCompletionStreamResponse]> {
+    match &self.messages.get(index)?.receive_buffer {
+      Some(ReceiveBuffer::StreamResponse(chunks)) if chunks.len() > 1 => Some(chunks.as_slice()),
+      _ => None,
+    }
+  }
+
+  // the request a response was received for, the last one sent by the time the response arrived
+  fn transaction(&self, message: &MessageContainer) -> Option<&Transaction> {
+    let created_at = message.created_at?;
+    message.receive_buffer.as_ref()?;
+    self.transactions.iter().rev().find(|t| t.error.is_none() && t.timestamp <= created_at)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, ChatCompletionResponseStreamMessage,
+    ChatCompletionStreamResponseDelta, Role,
+  };
+
+  use super::*;
+
+  fn chunk(content: &str) -> CreateChatCompletionStreamResponse {
+    CreateChatCompletionStreamResponse {
+      id: "chatcmpl-1".to_string(),
+      object: "chat.completion.chunk".to_string(),
+      created: 0,
+      model: "gpt-4".to_string(),
+      system_fingerprint: None,
+      choices: vec![ChatCompletionResponseStreamMessage {
+        index: 0,
+        delta: ChatCompletionStreamResponseDelta {
+          role: Some(Role::Assistant),
+          content: Some(content.to_string()),
+          function_call: None,
+          tool_calls: None,
+        },
+        finish_reason: None,
+      }],
+    }
+  }
+
+  #[test]
+  fn test_playback() {
+    let mut data = SessionData::default();
+    data.add_message(ChatMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text("hello".to_string())),
+    }));
+    ["Hi", " there", "!"]
+      .iter()
+      .for_each(|content| data.add_message(ChatMessage::StreamResponse(vec![chunk(content)])));
+    (data.messages[0].created_at, data.messages[1].created_at) = (Some(100), Some(102));
+    let mut transaction = Transaction::failed(&Default::default(), 100, Duration::from_millis(4000), String::new());
+    (transaction.error, transaction.first_token_ms) = (None, Some(1000));
+    data.transactions.push(transaction);
+
+    let mut playback = Playback::new("1700000000", data.clone(), true, SessionData::default());
+    assert!(playback.toggle());
+    assert_eq!(playback.delay(), Duration::from_millis(PLAYBACK_STEP_MS));
+    playback.advance();
+    assert_eq!(playback.shown().messages.len(), 1);
+    // the response starts after the model's first token latency, and its chunks stream over the rest
+    assert_eq!(playback.delay(), Duration::from_millis(1000));
+    playback.advance();
+    assert_eq!(playback.delay(), Duration::from_millis(1000));
+    assert!(!playback.shown().messages[1].to_string().contains("Hi there"));
+    playback.advance();
+    assert_eq!(playback.chunks, Some(2));
+    playback.advance();
+    assert!(playback.is_finished() && !playback.playing);
+    assert!(playback.shown().messages[1].to_string().contains("Hi there!"));
+
+    playback.step_back();
+    assert_eq!((playback.position, playback.chunks), (1, None));
+    playback.step_forward();
+    assert!(playback.describe().contains("2 of 2 messages, finished"));
+  }
+}
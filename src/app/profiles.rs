@@ -0,0 +1,86 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::{errors::SazidError, session_config::SessionConfig, types::Model};
+
+// a named bundle of settings for a kind of work, such as code review or writing, chosen with --profile or the profile
+// command, unset settings are left as they are
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Profile {
+  pub model: Option<String>,
+  pub prompt: Option<String>,
+  pub temperature: Option<f32>,
+  // the only functions offered to the model
+  pub tools: Option<Vec<String>>,
+}
+
+impl Profile {
+  pub fn describe(&self) -> String {
+    let mut parts = vec![];
+    parts.extend(self.model.clone());
+    parts.extend(self.temperature.map(|temperature| format!("temperature {}", temperature)));
+    parts.extend(self.tools.as_ref().map(|tools| format!("tools: {}", tools.join(", "))));
+    match parts.is_empty() {
+      true => "the session's settings".to_string(),
+      false => parts.join(", "),
+    }
+  }
+}
+
+impl SessionConfig {
+  // applies the profile and records its name with the session
+  pub fn apply_profile(&mut self, name: &str) -> Result<&Profile, SazidError> {
+    let Some(profile) = self.profiles.get(name).cloned() else {
+      let mut names = self.profiles.keys().map(String::as_str).collect::<Vec<_>>();
+      names.sort();
+      return Err(SazidError::Other(format!("no profile named {}, defined: {}", name, names.join(", "))));
+    };
+    if let Some(temperature) = profile.temperature {
+      self.set_request_parameter("temperature", Some(&temperature.to_string()))?;
+    }
+    if let Some(model) = &profile.model {
+      self.model = Model::from_name(model);
+    }
+    if let Some(prompt) = &profile.prompt {
+      self.prompt = prompt.clone();
+    }
+    if profile.tools.is_some() {
+      self.tools = profile.tools.clone();
+    }
+    self.profile = Some(name.to_string());
+    Ok(&self.profiles[name])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_apply_profile() {
+    let mut config = SessionConfig::default();
+    let review = Profile {
+      model: Some("gpt-4".to_string()),
+      prompt: Some("Review the code for bugs.".to_string()),
+      temperature: Some(0.2),
+      tools: Some(vec!["read_file".to_string()]),
+    };
+    config.profiles.insert("code-review".to_string(), review);
+    config.profiles.insert("writing".to_string(), Profile { temperature: Some(0.9), ..Default::default() });
+
+    assert_eq!(config.apply_profile("code-review").unwrap().describe(), "gpt-4, temperature 0.2, tools: read_file");
+    assert_eq!(config.model.name, "gpt-4");
+    assert_eq!(config.prompt, "Review the code for bugs.");
+    assert_eq!(config.request_parameters.temperature, Some(0.2));
+    assert_eq!(config.profile.as_deref(), Some("code-review"));
+
+    config.apply_profile("writing").unwrap();
+    assert_eq!(config.request_parameters.temperature, Some(0.9));
+    assert_eq!(config.model.name, "gpt-4");
+    assert_eq!(config.tools, Some(vec!["read_file".to_string()]));
+    assert_eq!(config.profile.as_deref(), Some("writing"));
+
+    let error = config.apply_profile("sql-analyst").unwrap_err().to_string();
+    assert!(error.contains("no profile named sql-analyst, defined: code-review, writing"));
+  }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use super::errors::SazidError;
+
+// the settings of a project, in its root or any directory above where sazid is launched. only these keys are read
+// from it, since a cloned repository could otherwise point plugins_dir or hooks_dir at code of its own to run
+//   prompt = "..."                          the system prompt of new sessions
+//   model = "gpt-4"                         the chat model
+//   tools = ["read_file", "file_search"]    the only functions offered to the model
+//   ingest_globs = ["src/**/*.rs"]          the only files ingested from a directory
+//   collection = "sazid"                    the collection ingested into and searched, unless one is given
+pub const PROJECT_CONFIG_FILE: &str = ".sazid.toml";
+
+pub const PROJECT_CONFIG_KEYS: [&str; 5] = ["prompt", "model", "tools", "ingest_globs", "collection"];
+
+// the nearest project config, in dir or one of its ancestors
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+  dir.ancestors().map(|dir| dir.join(PROJECT_CONFIG_FILE)).find(|path| path.is_file())
+}
+
+// the project config with only the keys in PROJECT_CONFIG_KEYS, and the keys that were left out
+pub fn read_project_config(path: &Path) -> Result<(toml::Table, Vec<String>), SazidError> {
+  let contents = std::fs::read_to_string(path)?;
+  let table =
+    contents.parse::<toml::Table>().map_err(|e| SazidError::Other(format!("invalid {}: {}", path.display(), e)))?;
+  let (kept, ignored): (toml::Table, toml::Table) =
+    table.into_iter().partition(|(key, _)| PROJECT_CONFIG_KEYS.contains(&key.as_str()));
+  Ok((kept, ignored.into_iter().map(|(key, _)| key).collect()))
+}
+
+// the files of a directory to ingest, matched against their paths relative to it, None includes every file
+pub fn ingest_filter(globs: &[String]) -> Result<Option<GlobSet>, SazidError> {
+  if globs.is_empty() {
+    return Ok(None);
+  }
+  let mut builder = GlobSetBuilder::new();
+  for glob in globs {
+    builder.add(Glob::new(glob).map_err(|e| SazidError::Other(format!("invalid ingest glob {}: {}", glob, e)))?);
+  }
+  builder.build().map(Some).map_err(|e| SazidError::Other(format!("invalid ingest globs: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_find_project_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir.path().join("src/app");
+    std::fs::create_dir_all(&nested).unwrap();
+    assert_eq!(find_project_config(&nested), None);
+    std::fs::write(dir.path().join(PROJECT_CONFIG_FILE), "model = \"gpt-4\"\n").unwrap();
+    assert_eq!(find_project_config(&nested), Some(dir.path().join(PROJECT_CONFIG_FILE)));
+
+    assert!(ingest_filter(&[]).unwrap().is_none());
+    let filter = ingest_filter(&["src/**/*.rs".to_string(), "*.md".to_string()]).unwrap().unwrap();
+    assert!(filter.is_match("src/app/project.rs"));
+    assert!(filter.is_match("README.md"));
+    assert!(!filter.is_match("Cargo.lock"));
+    assert!(ingest_filter(&["src/[".to_string()]).is_err());
+  }
+
+  #[test]
+  fn test_read_project_config_keeps_project_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(PROJECT_CONFIG_FILE);
+    std::fs::write(&path, "model = \"gpt-4\"\nplugins_dir = \"tools\"\n\n[sandbox.commands]\nallow = [\"sh\"]\n")
+      .unwrap();
+    let (kept, ignored) = read_project_config(&path).unwrap();
+    assert_eq!(kept.keys().collect::<Vec<_>>(), vec!["model"]);
+    assert_eq!(ignored, vec!["plugins_dir", "sandbox"]);
+  }
+}
@@ -0,0 +1,104 @@
+use async_openai::config::{Config, OpenAIConfig};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_derive::{Deserialize, Serialize};
+
+use super::errors::SazidError;
+
+pub const OPENROUTER_API_BASE: &str = "https://openrouter.ai/api/v1";
+// lets openrouter pick a model for each request, the model that answered is reported in the response
+pub const OPENROUTER_AUTO_MODEL: &str = "openrouter/auto";
+const OPENROUTER_REFERER: &str = "https://github.com/kdheepak/sazid";
+const OPENROUTER_TITLE: &str = "sazid";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+  #[default]
+  OpenAI,
+  OpenRouter,
+  Local,
+  // replays scripted responses, for tests that should not need a network or api keys
+  Mock,
+}
+
+impl Provider {
+  // openrouter names models as vendor/model, bare openai model names are qualified with the openai vendor
+  pub fn model_id(&self, model_name: &str) -> String {
+    match self {
+      Provider::OpenRouter if model_name == "auto" => OPENROUTER_AUTO_MODEL.to_string(),
+      Provider::OpenRouter if !model_name.contains('/') => format!("openai/{}", model_name),
+      _ => model_name.to_string(),
+    }
+  }
+
+  // the environment variable the api key is read from, None for providers that don't need a key
+  pub fn api_key_env(&self) -> Option<&'static str> {
+    match self {
+      Provider::OpenAI => Some("OPENAI_API_KEY"),
+      Provider::OpenRouter => Some("OPENROUTER_API_KEY"),
+      Provider::Local | Provider::Mock => None,
+    }
+  }
+}
+
+pub fn is_openrouter(openai_config: &OpenAIConfig) -> bool {
+  openai_config.api_base().starts_with(OPENROUTER_API_BASE)
+}
+
+// openrouter uses these to attribute requests to the application
+pub fn openrouter_headers() -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  headers.insert("HTTP-Referer", HeaderValue::from_static(OPENROUTER_REFERER));
+  headers.insert("X-Title", HeaderValue::from_static(OPENROUTER_TITLE));
+  headers
+}
+
+// token counts and cost as accounted by openrouter for a single generation
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GenerationStats {
+  pub model: String,
+  #[serde(default)]
+  pub total_cost: f64,
+  #[serde(default)]
+  pub native_tokens_prompt: Option<u32>,
+  #[serde(default)]
+  pub native_tokens_completion: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct GenerationStatsResponse {
+  data: GenerationStats,
+}
+
+pub async fn fetch_generation_stats(
+  openai_config: &OpenAIConfig,
+  generation_id: &str,
+) -> Result<GenerationStats, SazidError> {
+  let response = reqwest::Client::new()
+    .get(format!("{}/generation", openai_config.api_base()))
+    .headers(openai_config.headers())
+    .query(&[("id", generation_id)])
+    .send()
+    .await
+    .map_err(|e| SazidError::Other(format!("failed to fetch generation stats: {}", e)))?
+    .error_for_status()
+    .map_err(|e| SazidError::Other(format!("failed to fetch generation stats: {}", e)))?;
+  let stats = response
+    .json::<GenerationStatsResponse>()
+    .await
+    .map_err(|e| SazidError::Other(format!("failed to parse generation stats: {}", e)))?;
+  Ok(stats.data)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_openrouter_model_id() {
+    assert_eq!(Provider::OpenRouter.model_id("gpt-4"), "openai/gpt-4");
+    assert_eq!(Provider::OpenRouter.model_id("anthropic/claude-2"), "anthropic/claude-2");
+    assert_eq!(Provider::OpenRouter.model_id("auto"), OPENROUTER_AUTO_MODEL);
+    assert_eq!(Provider::OpenAI.model_id("gpt-4"), "gpt-4");
+  }
+}
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  consts::{data_path, RECOVERY_DIR},
+  encryption,
+  errors::SazidError,
+  helpers::list_files_ordered_by_date,
+};
+
+// the input draft and the transcript as they were at the last autosave, removed when sazid exits cleanly,
+// so a file left behind means the previous run crashed or its terminal died
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Recovery {
+  pub session_id: String,
+  #[serde(default)]
+  pub draft: String,
+  // the serialized session, restored the same way as a saved session
+  #[serde(default)]
+  pub session: String,
+}
+
+impl Recovery {
+  pub fn default_dir() -> Option<PathBuf> {
+    data_path(RECOVERY_DIR)
+  }
+
+  pub fn path(dir: &Path, session_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", session_id))
+  }
+
+  pub fn save(&self, dir: &Path) -> Result<(), SazidError> {
+    std::fs::create_dir_all(dir)?;
+    let data = serde_json::to_string(self).map_err(|e| SazidError::Other(e.to_string()))?;
+    let path = Recovery::path(dir, &self.session_id);
+    let tmp_path = path.with_extension("json.tmp");
+    encryption::write(&tmp_path, data)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+  }
+
+  pub fn remove(dir: &Path, session_id: &str) -> Result<(), SazidError> {
+    let path = Recovery::path(dir, session_id);
+    if path.exists() {
+      std::fs::remove_file(path)?;
+    }
+    Ok(())
+  }
+
+  // the most recent autosave left behind by another run, unreadable files are skipped
+  pub fn find_abandoned(dir: &Path, session_id: &str) -> Option<Recovery> {
+    list_files_ordered_by_date(dir)
+      .ok()?
+      .iter()
+      .rev()
+      .filter(|entry| entry.path().extension().map_or(false, |e| e == "json"))
+      .filter(|entry| entry.path() != Recovery::path(dir, session_id))
+      .filter_map(|entry| encryption::read_to_string(&entry.path()).ok())
+      .find_map(|data| serde_json::from_str::<Recovery>(&data).ok())
+  }
+
+  pub fn describe(&self) -> String {
+    let draft = match self.draft.trim().is_empty() {
+      true => String::new(),
+      false => format!(" and a draft of {} characters", self.draft.chars().count()),
+    };
+    format!(
+      "sazid did not exit cleanly, session {}{} can be restored. recover to restore it, or discard",
+      self.session_id, draft
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_find_abandoned_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let recovery =
+      Recovery { session_id: "crashed".to_string(), draft: "half a prompt".to_string(), session: "{}".to_string() };
+    recovery.save(dir.path()).unwrap();
+    Recovery { session_id: "running".to_string(), ..Default::default() }.save(dir.path()).unwrap();
+
+    assert_eq!(Recovery::find_abandoned(dir.path(), "running"), Some(recovery));
+    Recovery::remove(dir.path(), "crashed").unwrap();
+    assert_eq!(Recovery::find_abandoned(dir.path(), "running"), None);
+    assert!(Recovery::remove(dir.path(), "crashed").is_ok());
+  }
+}
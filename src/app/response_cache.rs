@@ -0,0 +1,186 @@
+use std::{
+  path::PathBuf,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_openai::types::CreateChatCompletionRequest;
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::trace_dbg;
+
+use super::{
+  consts::{data_path, CACHE_DIR},
+  encryption,
+  errors::SazidError,
+  messages::ChatMessage,
+  middleware::Middleware,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+  pub enabled: bool,
+  // cached responses older than this are requested again
+  pub ttl_secs: u64,
+}
+
+impl Default for ResponseCacheConfig {
+  fn default() -> Self {
+    ResponseCacheConfig { enabled: false, ttl_secs: 24 * 60 * 60 }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct CachedResponse {
+  created_at: u64,
+  responses: Vec<ChatMessage>,
+}
+
+fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+// the key covers the whole request, so a change to the model, parameters or any message is a cache miss
+pub fn request_hash(request: &CreateChatCompletionRequest) -> String {
+  let request_json = serde_json::to_string(request).unwrap_or_default();
+  blake3::hash(request_json.as_bytes()).to_hex().to_string()
+}
+
+pub struct ResponseCache {
+  pub dir: PathBuf,
+  pub ttl_secs: u64,
+}
+
+impl ResponseCache {
+  pub fn new(dir: PathBuf, ttl_secs: u64) -> Self {
+    ResponseCache { dir, ttl_secs }
+  }
+
+  pub fn default_dir() -> Result<PathBuf, SazidError> {
+    Ok(data_path(CACHE_DIR).ok_or(SazidError::Other("home directory not found".to_string()))?)
+  }
+
+  fn entry_path(&self, request: &CreateChatCompletionRequest) -> PathBuf {
+    self.dir.join(format!("{}.json", request_hash(request)))
+  }
+
+  // returns None for a missing or expired entry, expired entries are removed
+  pub fn get(&self, request: &CreateChatCompletionRequest) -> Option<Vec<ChatMessage>> {
+    let path = self.entry_path(request);
+    let entry: CachedResponse = serde_json::from_str(&encryption::read_to_string(&path).ok()?).ok()?;
+    match now().saturating_sub(entry.created_at) < self.ttl_secs {
+      true => Some(entry.responses),
+      false => {
+        std::fs::remove_file(&path).ok();
+        None
+      },
+    }
+  }
+
+  pub fn put(&self, request: &CreateChatCompletionRequest, responses: &[ChatMessage]) -> Result<(), SazidError> {
+    std::fs::create_dir_all(&self.dir)?;
+    let entry = CachedResponse { created_at: now(), responses: responses.to_vec() };
+    let data = serde_json::to_string(&entry).map_err(|e| SazidError::Other(e.to_string()))?;
+    encryption::write(&self.entry_path(request), data)?;
+    Ok(())
+  }
+
+  // removes every entry, returns the number removed
+  pub fn clear(&self) -> Result<usize, SazidError> {
+    let mut count = 0;
+    if self.dir.exists() {
+      for entry in std::fs::read_dir(&self.dir)? {
+        let path = entry?.path();
+        if path.extension().map(|e| e == "json").unwrap_or(false) {
+          std::fs::remove_file(path)?;
+          count += 1;
+        }
+      }
+    }
+    Ok(count)
+  }
+}
+
+// answers repeated requests from the cache, only active when response_cache.enabled is set
+pub struct CacheMiddleware {
+  pub cache: Option<ResponseCache>,
+}
+
+impl CacheMiddleware {
+  pub fn new(config: &ResponseCacheConfig) -> Result<Self, SazidError> {
+    Ok(CacheMiddleware {
+      cache: match config.enabled {
+        true => Some(ResponseCache::new(ResponseCache::default_dir()?, config.ttl_secs)),
+        false => None,
+      },
+    })
+  }
+}
+
+#[async_trait]
+impl Middleware for CacheMiddleware {
+  fn name(&self) -> &'static str {
+    "cache"
+  }
+
+  async fn pre_request(
+    &self,
+    request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    let responses = self.cache.as_ref().and_then(|cache| cache.get(request));
+    if responses.is_some() {
+      trace_dbg!("response cache hit for {}", request_hash(request));
+    }
+    Ok(responses)
+  }
+
+  async fn on_complete(
+    &self,
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+  ) -> Result<(), SazidError> {
+    match &self.cache {
+      // a hit is not written back, so that its age is kept
+      Some(cache) if cache.get(request).is_none() => cache.put(request, responses),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
+  };
+
+  use super::*;
+
+  fn request(content: &str) -> CreateChatCompletionRequest {
+    CreateChatCompletionRequest {
+      model: "gpt-4".to_string(),
+      messages: vec![ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text(content.to_string())),
+      })],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_response_cache_hit_miss_and_expiry() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ResponseCache::new(dir.path().to_path_buf(), 60);
+    let response = ChatMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text("cached".to_string())),
+    });
+    assert_eq!(cache.get(&request("hello")), None);
+    cache.put(&request("hello"), &[response.clone()]).unwrap();
+    assert_eq!(cache.get(&request("hello")), Some(vec![response]));
+    assert_eq!(cache.get(&request("hello again")), None);
+    let expired = ResponseCache::new(dir.path().to_path_buf(), 0);
+    assert_eq!(expired.get(&request("hello")), None);
+    assert_eq!(cache.clear().unwrap(), 0);
+  }
+}
@@ -0,0 +1,230 @@
+use std::{
+  fmt,
+  io::IsTerminal,
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  consts::{data_path, INGESTED_DIR, SESSIONS_DIR},
+  errors::SazidError,
+  helpers::list_files_ordered_by_date,
+  session_list::set_archived,
+};
+
+const MB: u64 = 1024 * 1024;
+
+// what happens to a session older than max_age_days
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+  #[default]
+  Archive,
+  Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct RetentionPolicy {
+  // sessions not changed for this many days are archived or deleted, None keeps them
+  pub max_age_days: Option<u64>,
+  pub action: RetentionAction,
+  // the most the saved sessions may take on disk, the oldest are deleted past it
+  pub max_sessions_mb: Option<u64>,
+  // the most the copies of ingested files may take on disk, the oldest are deleted past it
+  pub max_ingested_mb: Option<u64>,
+  // applies the policy each time the interface starts, without asking
+  pub automatic: bool,
+}
+
+// a file in one of the directories the policy covers
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredFile {
+  pub path: PathBuf,
+  pub size: u64,
+  pub modified: SystemTime,
+}
+
+// the files oldest first, sessions only when json is set
+pub fn stored_files(dir: &Path, json: bool) -> Vec<StoredFile> {
+  list_files_ordered_by_date(dir)
+    .unwrap_or_default()
+    .iter()
+    .filter(|f| !json || f.path().extension().map_or(false, |e| e == "json"))
+    .filter_map(|f| {
+      let metadata = f.metadata().ok().filter(|m| m.is_file())?;
+      Some(StoredFile { path: f.path(), size: metadata.len(), modified: metadata.modified().ok()? })
+    })
+    .collect()
+}
+
+// what a policy would do, worked out before anything is changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcPlan {
+  pub archive: Vec<PathBuf>,
+  pub delete: Vec<StoredFile>,
+}
+
+impl GcPlan {
+  pub fn new(policy: &RetentionPolicy, sessions: &[StoredFile], ingested: &[StoredFile], now: SystemTime) -> Self {
+    let mut plan = GcPlan::default();
+    let cutoff = policy.max_age_days.map(|days| now - Duration::from_secs(days * 24 * 60 * 60));
+    let mut kept = vec![];
+    for session in sessions {
+      match (cutoff, policy.action) {
+        (Some(cutoff), RetentionAction::Delete) if session.modified < cutoff => plan.delete.push(session.clone()),
+        (Some(cutoff), RetentionAction::Archive) if session.modified < cutoff => {
+          plan.archive.push(session.path.clone());
+          kept.push(session);
+        },
+        _ => kept.push(session),
+      }
+    }
+    plan.delete_oldest_past(&kept, policy.max_sessions_mb);
+    plan.delete_oldest_past(&ingested.iter().collect::<Vec<_>>(), policy.max_ingested_mb);
+    // a session deleted for the size cap needn't be archived first
+    plan.archive.retain(|path| !plan.delete.iter().any(|file| &file.path == path));
+    plan
+  }
+
+  // the oldest files until the rest fit in max_mb
+  fn delete_oldest_past(&mut self, files: &[&StoredFile], max_mb: Option<u64>) {
+    let Some(max_bytes) = max_mb.map(|mb| mb * MB) else {
+      return;
+    };
+    let mut total = files.iter().map(|file| file.size).sum::<u64>();
+    for file in files {
+      if total <= max_bytes {
+        break;
+      }
+      total -= file.size;
+      self.delete.push((*file).clone());
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.archive.is_empty() && self.delete.is_empty()
+  }
+
+  pub fn reclaimed_bytes(&self) -> u64 {
+    self.delete.iter().map(|file| file.size).sum()
+  }
+
+  pub fn apply(&self) -> Result<(), SazidError> {
+    for path in &self.archive {
+      set_archived(path, true)?;
+    }
+    for file in &self.delete {
+      std::fs::remove_file(&file.path)?;
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for GcPlan {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for path in &self.archive {
+      writeln!(f, "archive {}", path.display())?;
+    }
+    for file in &self.delete {
+      writeln!(f, "delete {} ({:.1} MB)", file.path.display(), file.size as f64 / MB as f64)?;
+    }
+    write!(
+      f,
+      "{} session(s) to archive, {} file(s) to delete, {:.1} MB reclaimed",
+      self.archive.len(),
+      self.delete.len(),
+      self.reclaimed_bytes() as f64 / MB as f64
+    )
+  }
+}
+
+fn current_plan(policy: &RetentionPolicy) -> Result<GcPlan, SazidError> {
+  let sessions_dir = data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()))?;
+  let ingested_dir = data_path(INGESTED_DIR).ok_or_else(|| SazidError::Other("no ingested directory".to_string()))?;
+  Ok(GcPlan::new(policy, &stored_files(&sessions_dir, true), &stored_files(&ingested_dir, false), SystemTime::now()))
+}
+
+// reports what the retention policy would reclaim, then applies it with --yes or once confirmed on the terminal
+pub fn run_gc(policy: Option<&RetentionPolicy>, yes: bool) -> Result<(), SazidError> {
+  let policy = policy.ok_or_else(|| SazidError::Other("no retention policy is configured".to_string()))?;
+  let plan = current_plan(policy)?;
+  println!("{}", plan);
+  if plan.is_empty() {
+    return Ok(());
+  }
+  let confirmed = match (yes, std::io::stdin().is_terminal()) {
+    (true, _) => true,
+    (false, true) => dialoguer::Confirm::new().with_prompt("Reclaim it?").default(false).interact()?,
+    (false, false) => {
+      eprintln!("run with --yes to reclaim it");
+      false
+    },
+  };
+  if confirmed {
+    plan.apply()?;
+    eprintln!("reclaimed {:.1} MB", plan.reclaimed_bytes() as f64 / MB as f64);
+  }
+  Ok(())
+}
+
+// applies a policy set to run automatically when the interface starts
+pub fn apply_automatic_retention(policy: Option<&RetentionPolicy>) {
+  let Some(policy) = policy.filter(|policy| policy.automatic) else {
+    return;
+  };
+  match current_plan(policy).and_then(|plan| plan.apply().map(|_| plan)) {
+    Ok(plan) if !plan.is_empty() => log::info!("retention: {}", plan.to_string().lines().last().unwrap_or_default()),
+    Ok(_) => {},
+    Err(e) => log::warn!("failed to apply the retention policy: {}", e),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file(name: &str, size_mb: u64, days_old: u64, now: SystemTime) -> StoredFile {
+    let modified = now - Duration::from_secs(days_old * 24 * 60 * 60);
+    StoredFile { path: PathBuf::from(name), size: size_mb * MB, modified }
+  }
+
+  #[test]
+  fn test_gc_plan() {
+    let now = SystemTime::now();
+    let sessions = vec![file("1.json", 3, 90, now), file("2.json", 2, 40, now), file("3.json", 1, 1, now)];
+    let ingested = vec![file("a.pdf", 5, 10, now), file("b.md", 1, 2, now)];
+    let policy = RetentionPolicy { max_age_days: Some(30), ..Default::default() };
+    let plan = GcPlan::new(&policy, &sessions, &ingested, now);
+    assert_eq!(plan.archive, vec![PathBuf::from("1.json"), PathBuf::from("2.json")]);
+    assert!(plan.delete.is_empty());
+
+    let policy = RetentionPolicy {
+      max_age_days: Some(60),
+      action: RetentionAction::Delete,
+      max_sessions_mb: Some(1),
+      max_ingested_mb: Some(4),
+      automatic: false,
+    };
+    let plan = GcPlan::new(&policy, &sessions, &ingested, now);
+    let deleted = plan.delete.iter().map(|file| file.path.to_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(deleted, vec!["1.json", "2.json", "a.pdf"]);
+    assert_eq!(plan.reclaimed_bytes(), 10 * MB);
+    assert!(plan.to_string().ends_with("0 session(s) to archive, 3 file(s) to delete, 10.0 MB reclaimed"));
+  }
+
+  #[test]
+  fn test_apply_gc_plan() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("1.json"), r#"{"data": {"messages": []}}"#).unwrap();
+    std::fs::write(dir.path().join("2.json"), r#"{"data": {"messages": []}}"#).unwrap();
+    let sessions = stored_files(dir.path(), true);
+    assert_eq!(sessions.len(), 2);
+    let plan = GcPlan { archive: vec![sessions[0].path.clone()], delete: vec![sessions[1].clone()] };
+    plan.apply().unwrap();
+    assert!(std::fs::read_to_string(&sessions[0].path).unwrap().contains("\"archived\":true"));
+    assert!(!sessions[1].path.exists());
+  }
+}
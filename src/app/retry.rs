@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+
+use async_openai::{
+  config::OpenAIConfig,
+  error::OpenAIError,
+  types::{ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionResponse},
+  Client,
+};
+use backoff::{exponential::ExponentialBackoffBuilder, ExponentialBackoff};
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+lazy_static! {
+  // async-openai does not expose response headers, so Retry-After is read from the delay the api includes in
+  // rate limit messages, e.g. "Please try again in 1.5s"
+  static ref RETRY_AFTER: Regex = Regex::new(r"(?i)try again in (\d+(?:\.\d+)?)(ms|s)").unwrap();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+  // including the first attempt
+  pub max_attempts: u32,
+  pub max_elapsed_secs: u64,
+  pub initial_interval_ms: u64,
+  pub max_interval_secs: u64,
+  // each delay is randomized by up to this fraction, so that concurrent requests don't retry in lockstep
+  pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy { max_attempts: 5, max_elapsed_secs: 60, initial_interval_ms: 500, max_interval_secs: 20, jitter: 0.5 }
+  }
+}
+
+impl RetryPolicy {
+  pub fn disabled() -> Self {
+    RetryPolicy { max_attempts: 1, max_elapsed_secs: 0, ..Default::default() }
+  }
+
+  // the backoff used by the client itself, for requests not made through create_stream_with_retry or create_with_retry
+  pub fn backoff(&self) -> ExponentialBackoff {
+    ExponentialBackoffBuilder::new()
+      .with_initial_interval(Duration::from_millis(self.initial_interval_ms))
+      .with_max_interval(Duration::from_secs(self.max_interval_secs))
+      .with_randomization_factor(self.jitter)
+      .with_max_elapsed_time(Some(Duration::from_secs(self.max_elapsed_secs)))
+      .build()
+  }
+
+  // how long to wait before the next attempt, None when the error is not transient or the policy is exhausted
+  pub fn retry_delay(&self, attempt: u32, elapsed: Duration, error: &OpenAIError) -> Option<Duration> {
+    if attempt >= self.max_attempts || !is_transient(error) {
+      return None;
+    }
+    let delay = retry_after(error).unwrap_or_else(|| {
+      let exponential = self.initial_interval_ms as f64 * 2f64.powi(attempt.saturating_sub(1) as i32);
+      let jitter = 1.0 + self.jitter * (2.0 * rand::random::<f64>() - 1.0);
+      Duration::from_millis((exponential * jitter) as u64).min(Duration::from_secs(self.max_interval_secs))
+    });
+    match elapsed + delay > Duration::from_secs(self.max_elapsed_secs) {
+      true => None,
+      false => Some(delay),
+    }
+  }
+}
+
+// zero disables a timeout
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RequestTimeouts {
+  // from sending the request to the end of the response, including retries
+  pub request_secs: u64,
+  // the longest wait for the next chunk of a streamed response
+  pub stall_secs: u64,
+}
+
+impl Default for RequestTimeouts {
+  fn default() -> Self {
+    RequestTimeouts { request_secs: 300, stall_secs: 30 }
+  }
+}
+
+impl RequestTimeouts {
+  // resolves when the request has run for too long, or when no chunk arrived for too long since last_chunk
+  pub async fn expired(&self, started: Instant, last_chunk: Option<Instant>) {
+    let request_deadline =
+      Some(self.request_secs).filter(|secs| *secs > 0).map(|secs| started + Duration::from_secs(secs));
+    let stall_deadline = last_chunk
+      .filter(|_| self.stall_secs > 0)
+      .map(|last_chunk| last_chunk + Duration::from_secs(self.stall_secs));
+    match request_deadline.into_iter().chain(stall_deadline).min() {
+      Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+      None => std::future::pending().await,
+    }
+  }
+}
+
+// rate limits, server errors and dropped connections are worth retrying, anything else will fail again
+pub fn is_transient(error: &OpenAIError) -> bool {
+  match error {
+    OpenAIError::Reqwest(e) => {
+      e.is_timeout() || e.is_connect() || e.status().map(|s| s.as_u16() == 429 || s.is_server_error()).unwrap_or(false)
+    },
+    OpenAIError::ApiError(e) => {
+      let code = e.code.as_ref().map(|c| c.to_string()).unwrap_or_default();
+      code.contains("rate_limit_exceeded") || matches!(e.r#type.as_deref(), Some("server_error") | Some("requests"))
+    },
+    OpenAIError::StreamError(e) => ["429", "500", "502", "503", "504"].iter().any(|status| e.contains(status)),
+    _ => false,
+  }
+}
+
+pub fn retry_after(error: &OpenAIError) -> Option<Duration> {
+  let message = error.to_string();
+  let captures = RETRY_AFTER.captures(&message)?;
+  let value = captures[1].parse::<f64>().ok()?;
+  match &captures[2] {
+    "ms" => Some(Duration::from_secs_f64(value / 1000.0)),
+    _ => Some(Duration::from_secs_f64(value)),
+  }
+}
+
+pub fn retry_status(attempt: u32, policy: &RetryPolicy, delay: Duration, error: &OpenAIError) -> String {
+  let reason = match retry_after(error) {
+    Some(_) => "rate limited",
+    None => "request failed",
+  };
+  format!("{}, retrying in {:.1}s (attempt {} of {})", reason, delay.as_secs_f64(), attempt + 1, policy.max_attempts)
+}
+
+// opens a chat completion stream, retrying transient failures until the first chunk arrives
+// on_retry is called with the attempt that failed, the delay before the next attempt and the error
+pub async fn create_stream_with_retry(
+  client: &Client<OpenAIConfig>,
+  request: &CreateChatCompletionRequest,
+  policy: &RetryPolicy,
+  on_retry: impl Fn(u32, Duration, &OpenAIError),
+) -> Result<ChatCompletionResponseStream, OpenAIError> {
+  let started = Instant::now();
+  let mut attempt = 1;
+  loop {
+    let error = match client.chat().create_stream(request.clone()).await {
+      // a failed stream reports its error as the first item, so the first chunk is read before returning
+      Ok(mut stream) => match stream.next().await {
+        Some(Ok(first)) => return Ok(Box::pin(futures::stream::once(async { Ok(first) }).chain(stream))),
+        Some(Err(e)) => e,
+        None => return Ok(stream),
+      },
+      Err(e) => e,
+    };
+    match policy.retry_delay(attempt, started.elapsed(), &error) {
+      Some(delay) => {
+        on_retry(attempt, delay, &error);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+      },
+      None => return Err(error),
+    }
+  }
+}
+
+pub async fn create_with_retry(
+  client: &Client<OpenAIConfig>,
+  request: &CreateChatCompletionRequest,
+  policy: &RetryPolicy,
+  on_retry: impl Fn(u32, Duration, &OpenAIError),
+) -> Result<CreateChatCompletionResponse, OpenAIError> {
+  let started = Instant::now();
+  let mut attempt = 1;
+  loop {
+    match client.chat().create(request.clone()).await {
+      Ok(response) => return Ok(response),
+      Err(error) => match policy.retry_delay(attempt, started.elapsed(), &error) {
+        Some(delay) => {
+          on_retry(attempt, delay, &error);
+          tokio::time::sleep(delay).await;
+          attempt += 1;
+        },
+        None => return Err(error),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::error::ApiError;
+
+  use super::*;
+
+  fn rate_limit_error(message: &str) -> OpenAIError {
+    OpenAIError::ApiError(ApiError {
+      message: message.to_string(),
+      r#type: Some("requests".to_string()),
+      param: None,
+      code: Some(serde_json::Value::String("rate_limit_exceeded".to_string())),
+    })
+  }
+
+  #[test]
+  fn test_retry_delay_respects_retry_after_and_limits() {
+    let policy = RetryPolicy::default();
+    let error = rate_limit_error("Rate limit reached for gpt-4. Please try again in 1.5s.");
+    assert_eq!(policy.retry_delay(1, Duration::ZERO, &error), Some(Duration::from_millis(1500)));
+    assert_eq!(policy.retry_delay(policy.max_attempts, Duration::ZERO, &error), None);
+    assert_eq!(policy.retry_delay(1, Duration::from_secs(59), &error), None);
+    assert_eq!(policy.retry_delay(1, Duration::ZERO, &OpenAIError::InvalidArgument("bad".to_string())), None);
+  }
+
+  #[test]
+  fn test_retry_delay_backs_off_with_jitter() {
+    let policy = RetryPolicy { jitter: 0.5, ..Default::default() };
+    let error = OpenAIError::StreamError("Invalid status code: 503 Service Unavailable".to_string());
+    let delay = policy.retry_delay(3, Duration::ZERO, &error).unwrap();
+    assert!(delay >= Duration::from_millis(1000) && delay <= Duration::from_millis(3000));
+  }
+
+  #[tokio::test]
+  async fn test_request_timeouts() {
+    let wait = Duration::from_millis(50);
+    let stalled = Instant::now() - Duration::from_secs(60);
+    let timeouts = RequestTimeouts::default();
+    assert!(tokio::time::timeout(wait, timeouts.expired(Instant::now(), Some(stalled))).await.is_ok());
+    assert!(tokio::time::timeout(wait, timeouts.expired(Instant::now(), None)).await.is_err());
+    let disabled = RequestTimeouts { request_secs: 0, stall_secs: 0 };
+    assert!(tokio::time::timeout(wait, disabled.expired(stalled, Some(stalled))).await.is_err());
+  }
+}
@@ -0,0 +1,339 @@
+use std::{
+  os::unix::fs::{DirBuilderExt, MetadataExt},
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use async_openai::types::{ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role};
+use once_cell::sync::OnceCell;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+  net::{UnixListener, UnixStream},
+  sync::{
+    broadcast,
+    mpsc::{self, UnboundedSender},
+  },
+};
+
+use crate::{action::Action, config::Config};
+
+use super::{
+  batch::response_text,
+  citations::{retrieve_citations, RetrievalSettings, DEFAULT_RETRIEVED_CHUNKS},
+  errors::SazidError,
+  messages::ChatMessage,
+};
+
+// json-rpc error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+// the responses of the session are sent to every connected client, a client that falls this far behind misses some
+const NOTIFICATION_BUFFER: usize = 1024;
+
+static NOTIFICATIONS: OnceCell<broadcast::Sender<String>> = OnceCell::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct RpcConfig {
+  pub enabled: bool,
+  // None for sazid.sock in XDG_RUNTIME_DIR, or in a directory of the temporary directory that only the user can open
+  pub socket: Option<PathBuf>,
+}
+
+pub fn default_socket_path() -> Result<PathBuf, SazidError> {
+  let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+    // already only the user's
+    Some(dir) => PathBuf::from(dir),
+    None => {
+      let dir = std::env::temp_dir().join(format!("sazid-{}", unsafe { libc::getuid() }));
+      private_dir(&dir)?;
+      dir
+    },
+  };
+  Ok(dir.join("sazid.sock"))
+}
+
+// creates the directory so that only the user can open it, or checks that an existing one is the user's alone, since
+// anyone can create a directory of that name in the shared temporary directory first
+fn private_dir(dir: &Path) -> Result<(), SazidError> {
+  match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+    Ok(()) => return Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {},
+    Err(e) => return Err(e.into()),
+  }
+  let metadata = std::fs::symlink_metadata(dir)?;
+  match metadata.is_dir() && metadata.uid() == unsafe { libc::getuid() } && metadata.mode() & 0o077 == 0 {
+    true => Ok(()),
+    false => Err(SazidError::Other(format!("{} is not a directory only this user can open", dir.display()))),
+  }
+}
+
+// the socket file, removed when the session ends
+pub struct RpcSocket {
+  pub path: PathBuf,
+}
+
+impl Drop for RpcSocket {
+  fn drop(&mut self) {
+    std::fs::remove_file(&self.path).ok();
+  }
+}
+
+// a buffer or a visual selection sent from the editor
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Selection {
+  pub path: Option<String>,
+  pub filetype: Option<String>,
+  // 1 based and inclusive, as the editor numbers them
+  pub start_line: Option<usize>,
+  pub end_line: Option<usize>,
+  pub text: String,
+}
+
+impl Selection {
+  pub fn to_markdown(&self) -> String {
+    let lines = match (self.start_line, self.end_line) {
+      (Some(start), Some(end)) => format!(" lines {}-{}", start, end),
+      _ => String::new(),
+    };
+    let header = match &self.path {
+      Some(path) => format!("{}{}:\n", path, lines),
+      None => String::new(),
+    };
+    format!("{}```{}\n{}\n```", header, self.filetype.as_deref().unwrap_or_default(), self.text.trim_end())
+  }
+}
+
+#[derive(Deserialize)]
+struct SendParams {
+  text: String,
+  #[serde(default)]
+  selection: Option<Selection>,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+  query: String,
+  limit: Option<usize>,
+  collection: Option<String>,
+}
+
+struct RpcError(i64, String);
+
+impl From<SazidError> for RpcError {
+  fn from(e: SazidError) -> Self {
+    RpcError(INTERNAL_ERROR, e.to_string())
+  }
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+  serde_json::from_value(params).map_err(|e| RpcError(INVALID_PARAMS, e.to_string()))
+}
+
+fn notify(method: &str, params: Value) {
+  if let Some(notifications) = NOTIFICATIONS.get() {
+    notifications.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }).to_string()).ok();
+  }
+}
+
+// sends the text a response adds to the connected editors, as chat.delta notifications
+pub fn publish_response(message: &ChatMessage) {
+  if matches!(message, ChatMessage::StreamResponse(_) | ChatMessage::Response(_)) {
+    let text = response_text(message);
+    if !text.is_empty() {
+      notify("chat.delta", json!({ "text": text }));
+    }
+  }
+}
+
+// the whole response once it is complete, as a chat.done notification
+pub fn publish_done(content: Option<&str>) {
+  notify("chat.done", json!({ "content": content }));
+}
+
+// listens for editors on a unix socket that only the user can connect to, one json-rpc message per line
+pub fn start(rpc: &RpcConfig, config: Config, action_tx: UnboundedSender<Action>) -> Result<RpcSocket, SazidError> {
+  let path = match &rpc.socket {
+    Some(path) => path.clone(),
+    None => default_socket_path()?,
+  };
+  if path.exists() {
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+      return Err(SazidError::Other(format!("another sazid is listening on {}", path.display())));
+    }
+    // left behind by a run that crashed
+    std::fs::remove_file(&path)?;
+  }
+  let listener = bind(&path)?;
+  let socket = RpcSocket { path };
+  let notifications = NOTIFICATIONS.get_or_init(|| broadcast::channel(NOTIFICATION_BUFFER).0).clone();
+  let config = Arc::new(config);
+  tokio::spawn(async move {
+    loop {
+      match listener.accept().await {
+        Ok((stream, _)) => {
+          tokio::spawn(handle_connection(stream, action_tx.clone(), config.clone(), notifications.subscribe()));
+        },
+        Err(e) => {
+          log::error!("rpc socket stopped accepting connections: {}", e);
+          break;
+        },
+      }
+    }
+  });
+  Ok(socket)
+}
+
+// the socket is created without permissions for anyone else, rather than restricted after it is bound, so that no one
+// can connect in between
+fn bind(path: &Path) -> Result<UnixListener, SazidError> {
+  let umask = unsafe { libc::umask(0o177) };
+  let listener = UnixListener::bind(path);
+  unsafe { libc::umask(umask) };
+  listener.map_err(SazidError::from)
+}
+
+async fn handle_connection(
+  stream: UnixStream,
+  action_tx: UnboundedSender<Action>,
+  config: Arc<Config>,
+  mut notifications: broadcast::Receiver<String>,
+) {
+  let (reader, mut writer) = stream.into_split();
+  let (out_tx, mut out_rx) = mpsc::unbounded_channel::<String>();
+  tokio::spawn(async move {
+    while let Some(line) = out_rx.recv().await {
+      if writer.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+        break;
+      }
+    }
+  });
+  let notify_tx = out_tx.clone();
+  let forwarder = tokio::spawn(async move {
+    loop {
+      match notifications.recv().await {
+        Ok(notification) if notify_tx.send(notification).is_ok() => {},
+        Err(broadcast::error::RecvError::Lagged(_)) => {},
+        _ => break,
+      }
+    }
+  });
+  let mut lines = BufReader::new(reader).lines();
+  while let Ok(Some(line)) = lines.next_line().await {
+    if let Some(response) = handle_request(&line, &action_tx, &config).await {
+      out_tx.send(response.to_string()).ok();
+    }
+  }
+  forwarder.abort();
+}
+
+// the response to a request, None for notifications, which have no id
+async fn handle_request(line: &str, action_tx: &UnboundedSender<Action>, config: &Config) -> Option<Value> {
+  let request = match serde_json::from_str::<Value>(line) {
+    Ok(request) => request,
+    Err(e) => return Some(error_response(Value::Null, RpcError(PARSE_ERROR, e.to_string()))),
+  };
+  let method = request["method"].as_str().unwrap_or_default();
+  let result = call(method, request.get("params").cloned().unwrap_or(Value::Null), action_tx, config).await;
+  let id = request.get("id").cloned()?;
+  Some(match result {
+    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+    Err(e) => error_response(id, e),
+  })
+}
+
+fn error_response(id: Value, RpcError(code, message): RpcError) -> Value {
+  json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+async fn call(
+  method: &str,
+  params_value: Value,
+  action_tx: &UnboundedSender<Action>,
+  config: &Config,
+) -> Result<Value, RpcError> {
+  let send =
+    |action: Action| action_tx.send(action).map_err(|_| RpcError(INTERNAL_ERROR, "sazid is exiting".to_string()));
+  match method {
+    // submits the text as the next input of the active session, with the selection ahead of it
+    "chat.send" => {
+      let SendParams { text, selection } = params(params_value)?;
+      let input = match selection {
+        Some(selection) => format!("{}\n\n{}", selection.to_markdown(), text),
+        None => text,
+      };
+      send(Action::SubmitInput(input))?;
+      Ok(json!({ "accepted": true }))
+    },
+    // adds the selection to the session as context, without sending a request
+    "context.attach" => {
+      let selection: Selection = params(params_value)?;
+      send(Action::AddMessage(ChatMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text(selection.to_markdown())),
+      })))?;
+      Ok(json!({ "attached": true }))
+    },
+    "index.search" => {
+      let SearchParams { query, limit, collection } = params(params_value)?;
+      let settings = RetrievalSettings { collection, chunks: limit.unwrap_or(DEFAULT_RETRIEVED_CHUNKS) };
+      Ok(json!(retrieve_citations(config, &query, &settings).await?))
+    },
+    _ => Err(RpcError(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::os::unix::fs::PermissionsExt;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_handle_request() {
+    let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+    let config = Config::default();
+    let request = r#"{"jsonrpc":"2.0","id":1,"method":"chat.send","params":{"text":"explain this",
+      "selection":{"path":"src/main.rs","filetype":"rust","start_line":3,"end_line":4,"text":"fn main() {}\n"}}}"#;
+    let response = handle_request(&request.replace('\n', ""), &action_tx, &config).await.unwrap();
+    assert_eq!(response["result"]["accepted"], json!(true));
+    assert_eq!(
+      action_rx.try_recv().unwrap(),
+      Action::SubmitInput("src/main.rs lines 3-4:\n```rust\nfn main() {}\n```\n\nexplain this".to_string())
+    );
+
+    let response = handle_request(r#"{"jsonrpc":"2.0","id":2,"method":"buffer.open"}"#, &action_tx, &config).await;
+    assert_eq!(response.unwrap()["error"]["code"], json!(METHOD_NOT_FOUND));
+    assert!(handle_request(r#"{"jsonrpc":"2.0","method":"chat.send","params":{"text":"hi"}}"#, &action_tx, &config)
+      .await
+      .is_none());
+    assert_eq!(handle_request("{", &action_tx, &config).await.unwrap()["error"]["code"], json!(PARSE_ERROR));
+  }
+
+  #[tokio::test]
+  async fn test_socket_is_private() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_dir = dir.path().join("sazid-socket");
+    private_dir(&socket_dir).unwrap();
+    assert_eq!(std::fs::metadata(&socket_dir).unwrap().permissions().mode() & 0o777, 0o700);
+    private_dir(&socket_dir).unwrap();
+    let shared = dir.path().join("shared");
+    std::fs::create_dir(&shared).unwrap();
+    std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+    assert!(private_dir(&shared).is_err());
+
+    let rpc = RpcConfig { enabled: true, socket: Some(socket_dir.join("sazid.sock")) };
+    let (action_tx, _action_rx) = mpsc::unbounded_channel();
+    let socket = start(&rpc, Config::default(), action_tx).unwrap();
+    assert_eq!(std::fs::metadata(&socket.path).unwrap().permissions().mode() & 0o777, 0o600);
+    let path = socket.path.clone();
+    drop(socket);
+    assert!(!path.exists());
+  }
+}
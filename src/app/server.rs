@@ -0,0 +1,355 @@
+use std::{
+  collections::HashMap,
+  convert::Infallible,
+  net::SocketAddr,
+  path::{Path as FilePath, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use async_openai::types::{ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role};
+use axum::{
+  extract::{Path, Query, State},
+  http::{header::AUTHORIZATION, HeaderMap, Request, StatusCode},
+  middleware::{self, Next},
+  response::{
+    sse::{Event, KeepAlive, Sse},
+    IntoResponse, Response,
+  },
+  routing::{get, post},
+  Json, Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+
+use crate::config::Config;
+
+use super::{
+  batch::{response_text, BatchSession},
+  citations::{retrieve_citations, RetrievalSettings, DEFAULT_RETRIEVED_CHUNKS},
+  consts::{data_path, SESSIONS_DIR},
+  credentials::{find_secret, SERVE_TOKEN_ENV},
+  errors::SazidError,
+  export::Transcript,
+  messages::ChatMessage,
+  session_config::SessionConfig,
+  session_search::load_session_summaries,
+  types::Model,
+};
+
+pub const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:7878";
+
+// https is served when both are set, http otherwise
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ServeConfig {
+  // pem certificate chain
+  pub tls_cert: Option<PathBuf>,
+  // pem private key of the certificate
+  pub tls_key: Option<PathBuf>,
+}
+
+#[derive(Clone)]
+struct ServerState {
+  config: Arc<Config>,
+  // one request at a time per session, so that concurrent messages don't overwrite each other's responses
+  session_locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+}
+
+impl ServerState {
+  fn session_lock(&self, session_id: &str) -> Arc<AsyncMutex<()>> {
+    self.session_locks.lock().unwrap().entry(session_id.to_string()).or_default().clone()
+  }
+}
+
+struct ApiError(StatusCode, String);
+
+impl From<SazidError> for ApiError {
+  fn from(e: SazidError) -> Self {
+    ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    (self.0, Json(json!({ "error": self.1 }))).into_response()
+  }
+}
+
+fn not_found(e: SazidError) -> ApiError {
+  ApiError(StatusCode::NOT_FOUND, e.to_string())
+}
+
+fn sessions_dir() -> Result<PathBuf, ApiError> {
+  data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()).into())
+}
+
+// session ids are unix timestamps, anything else could name a file outside the sessions directory
+fn check_session_id(session_id: &str) -> Result<(), ApiError> {
+  match !session_id.is_empty() && session_id.chars().all(|c| c.is_ascii_digit()) {
+    true => Ok(()),
+    false => Err(ApiError(StatusCode::BAD_REQUEST, format!("{} is not a session id", session_id))),
+  }
+}
+
+#[derive(Deserialize, Default)]
+struct NewSession {
+  model: Option<String>,
+  // replaces the configured system prompt
+  prompt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NewMessage {
+  content: String,
+  // the response is sent as server sent events as it streams in, instead of as one json response
+  #[serde(default)]
+  stream: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+  q: String,
+  limit: Option<usize>,
+  collection: Option<String>,
+}
+
+// session ids are unix timestamps, so sessions created in the same second get the next unused one
+pub fn unused_session_id(sessions_dir: &FilePath, session_id: &str) -> String {
+  let mut id = session_id.parse::<u64>().unwrap_or_default();
+  while sessions_dir.join(format!("{}.json", id)).exists() {
+    id += 1;
+  }
+  id.to_string()
+}
+
+async fn list_sessions() -> Result<Json<Value>, ApiError> {
+  let sessions_dir = sessions_dir()?;
+  let sessions = load_session_summaries(&sessions_dir)
+    .iter()
+    .filter(|summary| summary.is_listed(None, false))
+    .map(|summary| json!({ "session_id": summary.session_id, "title": summary.title(), "tags": summary.tags }))
+    .collect::<Vec<_>>();
+  Ok(Json(json!(sessions)))
+}
+
+async fn create_session(
+  State(state): State<ServerState>,
+  body: Option<Json<NewSession>>,
+) -> Result<Json<Value>, ApiError> {
+  let new_session = body.map(|Json(new_session)| new_session).unwrap_or_default();
+  let sessions_dir = sessions_dir()?;
+  let mut config = state.config.session_config.clone();
+  config.session_id = unused_session_id(&sessions_dir, &SessionConfig::generate_session_id());
+  if let Some(model) = &new_session.model {
+    config.model = Model::from_name(model);
+  }
+  if let Some(prompt) = new_session.prompt {
+    config.prompt = prompt;
+  }
+  let mut session = BatchSession::new(config);
+  session.add_initial_messages();
+  session.save()?;
+  Ok(Json(json!({ "session_id": session.config.session_id, "model": session.config.model.name })))
+}
+
+async fn get_session(Path(session_id): Path<String>) -> Result<Json<Transcript>, ApiError> {
+  check_session_id(&session_id)?;
+  sessions_dir()?;
+  Ok(Json(Transcript::load(&session_id).map_err(not_found)?))
+}
+
+async fn send_message(
+  State(state): State<ServerState>,
+  Path(session_id): Path<String>,
+  Json(message): Json<NewMessage>,
+) -> Result<Response, ApiError> {
+  check_session_id(&session_id)?;
+  sessions_dir()?;
+  let lock = state.session_lock(&session_id).lock_owned().await;
+  let mut session = BatchSession::load(&session_id, state.config.session_config.clone()).map_err(not_found)?;
+  session.data.add_message(ChatMessage::User(ChatCompletionRequestUserMessage {
+    role: Role::User,
+    content: Some(ChatCompletionRequestUserMessageContent::Text(message.content)),
+  }));
+  let (request, mut notices) = session.prepare_request()?;
+
+  if !message.stream {
+    let mut content = String::new();
+    session
+      .send_request(
+        request,
+        |notice| notices.push(notice.to_string()),
+        |response| {
+          content.push_str(&response_text(response));
+          Ok(())
+        },
+      )
+      .await?;
+    session.save()?;
+    return Ok(Json(json!({ "content": content, "notices": notices })).into_response());
+  }
+
+  // the response is read in a task that holds the session lock until it is saved, the events are sent as they come
+  let (tx, rx) = mpsc::unbounded_channel::<Event>();
+  tokio::spawn(async move {
+    let _lock = lock;
+    notices.iter().for_each(|notice| {
+      tx.send(Event::default().event("notice").data(notice)).ok();
+    });
+    let mut content = String::new();
+    let result = session
+      .send_request(
+        request,
+        |notice| {
+          tx.send(Event::default().event("notice").data(notice)).ok();
+        },
+        |response| {
+          let text = response_text(response);
+          content.push_str(&text);
+          tx.send(Event::default().event("delta").data(text)).ok();
+          Ok(())
+        },
+      )
+      .await
+      .and_then(|_| session.save());
+    let event = match result {
+      Ok(()) => Event::default().event("done").data(content),
+      Err(e) => Event::default().event("error").data(e.to_string()),
+    };
+    tx.send(event).ok();
+  });
+  let events =
+    futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|event| (Ok::<_, Infallible>(event), rx)) });
+  Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
+
+async fn search(State(state): State<ServerState>, Query(query): Query<SearchQuery>) -> Result<Json<Value>, ApiError> {
+  let settings =
+    RetrievalSettings { collection: query.collection, chunks: query.limit.unwrap_or(DEFAULT_RETRIEVED_CHUNKS) };
+  let citations = retrieve_citations(&state.config, &query.q, &settings).await?;
+  Ok(Json(json!(citations)))
+}
+
+// whether the request sends the token as a bearer token, compared as hashes so that the time taken doesn't tell how
+// much of it matched
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+  headers
+    .get(AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .map_or(false, |given| blake3::hash(given.as_bytes()) == blake3::hash(token.as_bytes()))
+}
+
+async fn require_token<B>(State(token): State<Arc<String>>, request: Request<B>, next: Next<B>) -> Response {
+  match is_authorized(request.headers(), &token) {
+    true => next.run(request).await,
+    false => ApiError(StatusCode::UNAUTHORIZED, "a valid bearer token is required".to_string()).into_response(),
+  }
+}
+
+fn router(config: Config, token: Option<String>) -> Router {
+  let state = ServerState { config: Arc::new(config), session_locks: Arc::new(Mutex::new(HashMap::new())) };
+  let router = Router::new()
+    .route("/sessions", get(list_sessions).post(create_session))
+    .route("/sessions/:session_id", get(get_session))
+    .route("/sessions/:session_id/messages", post(send_message))
+    .route("/search", get(search))
+    .with_state(state);
+  match token {
+    Some(token) => router.layer(middleware::from_fn_with_state(Arc::new(token), require_token)),
+    None => router,
+  }
+}
+
+// serves the sessions the tui uses to editors and scripts. without a token anyone who can connect can use it, so
+// only addresses on this machine can be listened on
+// a loopback address is only reachable from this machine, any other address needs tls and a token, so that neither
+// the token nor the transcripts cross the network in plaintext
+fn check_listen_address(addr: &SocketAddr, has_token: bool, has_tls: bool) -> Result<(), SazidError> {
+  if addr.ip().is_loopback() {
+    return Ok(());
+  }
+  if !has_tls {
+    return Err(SazidError::Other(format!(
+      "{} is not a loopback address, set tls_cert and tls_key to listen on it over https",
+      addr
+    )));
+  }
+  if !has_token {
+    return Err(SazidError::Other(format!(
+      "{} is not a loopback address, store a token with `sazid auth set serve` or set {} to listen on it",
+      addr, SERVE_TOKEN_ENV
+    )));
+  }
+  Ok(())
+}
+
+pub async fn run_serve(config: Config, addr: SocketAddr, serve: ServeConfig) -> Result<(), SazidError> {
+  let token = find_secret(SERVE_TOKEN_ENV).map(|(token, _)| token).filter(|token| !token.trim().is_empty());
+  check_listen_address(&addr, token.is_some(), serve.tls_cert.is_some() && serve.tls_key.is_some())?;
+  let app = router(config, token).into_make_service();
+  match (serve.tls_cert, serve.tls_key) {
+    (Some(cert), Some(key)) => {
+      let tls = RustlsConfig::from_pem_file(&cert, &key)
+        .await
+        .map_err(|e| SazidError::Other(format!("failed to load {} and {}: {}", cert.display(), key.display(), e)))?;
+      eprintln!("serving sessions on https://{}", addr);
+      axum_server::bind_rustls(addr, tls)
+        .serve(app)
+        .await
+        .map_err(|e| SazidError::Other(format!("server error: {}", e)))
+    },
+    (None, None) => {
+      eprintln!("serving sessions on http://{}", addr);
+      axum::Server::try_bind(&addr)
+        .map_err(|e| SazidError::Other(format!("failed to listen on {}: {}", addr, e)))?
+        .serve(app)
+        .await
+        .map_err(|e| SazidError::Other(format!("server error: {}", e)))
+    },
+    _ => Err(SazidError::Other("tls_cert and tls_key have to be set together".to_string())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_unused_session_id() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(unused_session_id(dir.path(), "1700000000"), "1700000000");
+    std::fs::write(dir.path().join("1700000000.json"), "{}").unwrap();
+    std::fs::write(dir.path().join("1700000001.json"), "{}").unwrap();
+    assert_eq!(unused_session_id(dir.path(), "1700000000"), "1700000002");
+  }
+
+  #[test]
+  fn test_check_listen_address() {
+    let loopback: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+    let public: SocketAddr = "0.0.0.0:3000".parse().unwrap();
+    assert!(check_listen_address(&loopback, false, false).is_ok());
+    assert!(check_listen_address(&public, true, false).is_err());
+    assert!(check_listen_address(&public, false, true).is_err());
+    assert!(check_listen_address(&public, true, true).is_ok());
+  }
+
+  #[test]
+  fn test_is_authorized() {
+    let mut headers = HeaderMap::new();
+    assert!(!is_authorized(&headers, "secret"));
+    headers.insert(AUTHORIZATION, "Bearer wrong".parse().unwrap());
+    assert!(!is_authorized(&headers, "secret"));
+    headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+    assert!(is_authorized(&headers, "secret"));
+  }
+
+  #[test]
+  fn test_check_session_id() {
+    assert!(check_session_id("1700000000").is_ok());
+    for session_id in ["", "../../something", "..%2F..%2Fsomething", "1700000000.json", "/etc/passwd"] {
+      assert_eq!(check_session_id(session_id).unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+  }
+}
@@ -1,15 +1,36 @@
 use std::{
+  collections::HashMap,
   path::PathBuf,
   time::{SystemTime, UNIX_EPOCH},
 };
 
 use async_openai::{
   config::OpenAIConfig,
-  types::{ChatCompletionRequestSystemMessage},
+  types::{ChatCompletionRequestSystemMessage, CreateChatCompletionRequest, Stop},
 };
 use serde_derive::{Deserialize, Serialize};
 
-use super::{consts::*, functions::CallableFunction, types::Model};
+use super::{
+  agent::DEFAULT_AGENT_MAX_ITERATIONS,
+  attachments::{Attachment, DEFAULT_ATTACHMENT_MAX_TOKENS},
+  citations::RetrievalSettings,
+  consts::*,
+  database::DatabaseConfig,
+  errors::SazidError,
+  functions::{sandbox::SandboxPolicy, CallableFunction},
+  guardrails::ConfirmThresholds,
+  middleware::DEFAULT_MIDDLEWARE,
+  notifications::NotificationConfig,
+  personas::Persona,
+  profiles::Profile,
+  providers::{Provider, OPENROUTER_API_BASE},
+  redaction::RedactionConfig,
+  response_cache::ResponseCacheConfig,
+  retry::{RequestTimeouts, RetryPolicy},
+  tee::TeeConfig,
+  types::Model,
+  vcr::VcrMode,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SessionConfig {
@@ -17,13 +38,106 @@ pub struct SessionConfig {
   pub session_id: String,
   pub session_dir: PathBuf,
   pub available_functions: Vec<CallableFunction>,
+  // the paths, commands and hosts the available functions may use
+  #[serde(default)]
+  pub sandbox: SandboxPolicy,
+  // executables that become functions, see PluginFunction
+  #[serde(default)]
+  pub plugins_dir: Option<PathBuf>,
+  // rhai scripts run at points in the session, see Hooks
+  #[serde(default)]
+  pub hooks_dir: Option<PathBuf>,
+  // the only functions offered to the model, by name, None for every function
+  #[serde(default)]
+  pub tools: Option<Vec<String>>,
+  // the collection retrieval searches when none is given, usually set by the project config
+  #[serde(default)]
+  pub collection: Option<String>,
   pub list_file_paths: Vec<PathBuf>,
+  // files inlined into every request, added with the attach command
+  #[serde(default)]
+  pub attached_files: Vec<Attachment>,
+  // an attached file over this is summarized, and the summary is sent in its place
+  #[serde(default = "default_attachment_max_tokens")]
+  pub attachment_max_tokens: usize,
+  // the language the model is asked to respond in, a code such as "de" or a name, None for the user's language
+  #[serde(default)]
+  pub response_language: Option<String>,
   pub model: Model,
   pub name: String,
   pub include_functions: bool,
   pub stream_response: bool,
   pub function_result_max_tokens: usize,
   pub response_max_tokens: usize,
+  // continuations requested without asking for a response cut off at response_max_tokens, 0 waits for c
+  #[serde(default = "default_auto_continue")]
+  pub auto_continue: usize,
+  // requests an agent may send toward its goal before it is stopped
+  #[serde(default = "default_agent_max_iterations")]
+  pub agent_max_iterations: usize,
+  // the personas the personas command can start a panel with, by name
+  #[serde(default)]
+  pub personas: HashMap<String, Persona>,
+  // the profiles --profile and the profile command choose from, by name
+  #[serde(default)]
+  pub profiles: HashMap<String, Profile>,
+  // the profile last applied to the session
+  #[serde(default)]
+  pub profile: Option<String>,
+  #[serde(default = "default_middleware")]
+  pub middleware: Vec<String>,
+  #[serde(default)]
+  pub requests_per_minute: u32,
+  #[serde(default)]
+  pub compress_prompt: bool,
+  #[serde(default = "default_compression_threshold_tokens")]
+  pub compression_threshold_tokens: usize,
+  #[serde(default)]
+  pub request_parameters: RequestParameters,
+  #[serde(default)]
+  pub provider: Provider,
+  #[serde(default)]
+  pub confirm_thresholds: ConfirmThresholds,
+  #[serde(default)]
+  pub retry_policy: RetryPolicy,
+  #[serde(default)]
+  pub timeouts: RequestTimeouts,
+  #[serde(default)]
+  pub response_cache: ResponseCacheConfig,
+  #[serde(default)]
+  pub redaction: RedactionConfig,
+  #[serde(default)]
+  pub tee: TeeConfig,
+  // how agents report that they finished
+  #[serde(default)]
+  pub notifications: NotificationConfig,
+  // files, such as project briefs, added to the start of every new session as context
+  #[serde(default)]
+  pub auto_context: Vec<PathBuf>,
+  // disables every network call, chat requests only go to the local api
+  #[serde(default)]
+  pub offline: bool,
+  // when set, the ingested chunks that best match each input are added to the request, to be cited in the answer
+  #[serde(default)]
+  pub retrieval: Option<RetrievalSettings>,
+  // requests are shown with their token counts instead of being sent, set with --dry-run or :dryrun
+  #[serde(skip)]
+  pub dry_run: bool,
+  // the responses replayed by the mock provider, read from SAZID_MOCK_FIXTURES
+  #[serde(skip)]
+  pub mock_fixtures: Option<PathBuf>,
+  // records requests to a cassette or replays them from one, set with --record or --replay
+  #[serde(skip)]
+  pub vcr: Option<VcrMode>,
+  // the api in use before switching to offline mode, restored when switching back
+  #[serde(skip)]
+  pub online_api: Option<(OpenAIConfig, Provider)>,
+  // set while an agent runs, tool calls that write anything wait for the user's confirmation
+  #[serde(skip)]
+  pub confirm_side_effects: bool,
+  // the databases query_database can read, kept out of saved sessions since their urls can hold passwords
+  #[serde(skip)]
+  pub databases: HashMap<String, DatabaseConfig>,
   #[serde(skip)]
   pub openai_config: OpenAIConfig,
 }
@@ -35,34 +149,228 @@ impl Default for SessionConfig {
       session_id: Self::generate_session_id(),
       session_dir: PathBuf::new(),
       available_functions: vec![],
+      sandbox: SandboxPolicy::default(),
+      plugins_dir: None,
+      hooks_dir: None,
+      tools: None,
+      collection: None,
       openai_config: OpenAIConfig::default(),
       list_file_paths: vec![],
+      attached_files: vec![],
+      attachment_max_tokens: default_attachment_max_tokens(),
+      response_language: None,
       model: GPT4_TURBO.clone(),
       name: "Sazid Test".to_string(),
       function_result_max_tokens: 8192,
       response_max_tokens: 4095,
+      auto_continue: default_auto_continue(),
+      agent_max_iterations: default_agent_max_iterations(),
+      personas: HashMap::new(),
+      profiles: HashMap::new(),
+      profile: None,
       include_functions: true,
       stream_response: true,
+      middleware: default_middleware(),
+      requests_per_minute: 0,
+      compress_prompt: false,
+      compression_threshold_tokens: default_compression_threshold_tokens(),
+      request_parameters: RequestParameters::default(),
+      provider: Provider::default(),
+      confirm_thresholds: ConfirmThresholds::default(),
+      retry_policy: RetryPolicy::default(),
+      timeouts: RequestTimeouts::default(),
+      response_cache: ResponseCacheConfig::default(),
+      redaction: RedactionConfig::default(),
+      tee: TeeConfig::default(),
+      notifications: NotificationConfig::default(),
+      auto_context: vec![],
+      offline: false,
+      retrieval: None,
+      dry_run: false,
+      mock_fixtures: None,
+      vcr: None,
+      online_api: None,
+      confirm_side_effects: false,
+      databases: HashMap::new(),
     }
   }
 }
+
+// optional sampling parameters, left unset so that the api defaults apply
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RequestParameters {
+  pub temperature: Option<f32>,
+  pub top_p: Option<f32>,
+  pub presence_penalty: Option<f32>,
+  pub frequency_penalty: Option<f32>,
+  #[serde(default)]
+  pub stop: Vec<String>,
+  // the number of choices each response has, compared side by side to keep one
+  #[serde(default)]
+  pub n: Option<u8>,
+}
+
+impl RequestParameters {
+  pub fn apply(&self, request: &mut CreateChatCompletionRequest) {
+    request.temperature = self.temperature;
+    request.top_p = self.top_p;
+    request.presence_penalty = self.presence_penalty;
+    request.frequency_penalty = self.frequency_penalty;
+    request.n = self.n;
+    request.stop = match self.stop.is_empty() {
+      true => None,
+      false => Some(Stop::StringArray(self.stop.clone())),
+    };
+  }
+}
+
+impl std::fmt::Display for RequestParameters {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let display = |value: Option<f32>| value.map(|v| v.to_string()).unwrap_or("default".to_string());
+    write!(
+      f,
+      "temperature: {}, top_p: {}, presence_penalty: {}, frequency_penalty: {}, stop: {:?}, n: {}",
+      display(self.temperature),
+      display(self.top_p),
+      display(self.presence_penalty),
+      display(self.frequency_penalty),
+      self.stop,
+      self.n.unwrap_or(1)
+    )
+  }
+}
+
+fn parse_parameter(name: &str, value: &str, min: f32, max: f32) -> Result<f32, SazidError> {
+  match value.parse::<f32>() {
+    Ok(v) if (min..=max).contains(&v) => Ok(v),
+    _ => Err(SazidError::Other(format!("{} must be a number between {} and {}", name, min, max))),
+  }
+}
+
+fn default_middleware() -> Vec<String> {
+  DEFAULT_MIDDLEWARE.iter().map(|m| m.to_string()).collect()
+}
+
+fn default_compression_threshold_tokens() -> usize {
+  8192
+}
+
+fn default_auto_continue() -> usize {
+  3
+}
+
+fn default_attachment_max_tokens() -> usize {
+  DEFAULT_ATTACHMENT_MAX_TOKENS
+}
+
+fn default_agent_max_iterations() -> usize {
+  DEFAULT_AGENT_MAX_ITERATIONS
+}
+
 impl SessionConfig {
   pub fn with_local_api(mut self) -> Self {
     log::info!("Using local API");
     self.openai_config = OpenAIConfig::new().with_api_base("http://localhost:1234/v1".to_string());
+    self.provider = Provider::Local;
+    self
+  }
+
+  pub fn set_offline(&mut self, offline: bool) {
+    match (offline, self.online_api.take()) {
+      (true, None) if !matches!(self.provider, Provider::Local | Provider::Mock) => {
+        self.online_api = Some((self.openai_config.clone(), self.provider));
+        *self = self.clone().with_local_api();
+      },
+      (false, Some((openai_config, provider))) => {
+        self.openai_config = openai_config;
+        self.provider = provider;
+      },
+      (_, online_api) => self.online_api = online_api,
+    }
+    self.offline = offline;
+  }
+
+  pub fn with_mock_provider(mut self, fixtures: Option<PathBuf>) -> Self {
+    log::info!("Using mock provider");
+    self.openai_config = OpenAIConfig::new().with_api_base("http://localhost/mock".to_string());
+    self.provider = Provider::Mock;
+    self.mock_fixtures = fixtures;
     self
   }
 
   pub fn with_openai_api_key<S: Into<String>>(mut self, api_key: S) -> Self {
     log::info!("Using default OpenAI remote API");
     self.openai_config = OpenAIConfig::new().with_api_key(api_key).with_org_id("org-WagBLu0vLgiuEL12dylmcPFj");
+    self.provider = Provider::OpenAI;
+    self
+  }
+
+  pub fn with_openrouter_api_key<S: Into<String>>(mut self, api_key: S) -> Self {
+    log::info!("Using OpenRouter API");
+    self.openai_config = OpenAIConfig::new().with_api_key(api_key).with_api_base(OPENROUTER_API_BASE);
+    self.provider = Provider::OpenRouter;
     self
   }
 
+  // sets a request parameter by name, a value of None restores the default
+  pub fn set_request_parameter(&mut self, name: &str, value: Option<&str>) -> Result<(), SazidError> {
+    let parameters = &mut self.request_parameters;
+    match (name, value) {
+      ("temperature", Some(v)) => parameters.temperature = Some(parse_parameter(name, v, 0.0, 2.0)?),
+      ("temperature", None) => parameters.temperature = None,
+      ("top_p", Some(v)) => parameters.top_p = Some(parse_parameter(name, v, 0.0, 1.0)?),
+      ("top_p", None) => parameters.top_p = None,
+      ("presence_penalty", Some(v)) => parameters.presence_penalty = Some(parse_parameter(name, v, -2.0, 2.0)?),
+      ("presence_penalty", None) => parameters.presence_penalty = None,
+      ("frequency_penalty", Some(v)) => parameters.frequency_penalty = Some(parse_parameter(name, v, -2.0, 2.0)?),
+      ("frequency_penalty", None) => parameters.frequency_penalty = None,
+      // the api accepts up to 4 stop sequences
+      ("stop", Some(v)) => {
+        if parameters.stop.len() >= 4 {
+          return Err(SazidError::Other("at most 4 stop sequences are supported".to_string()));
+        }
+        parameters.stop.push(v.to_string())
+      },
+      ("stop", None) => parameters.stop.clear(),
+      // more choices than fit side by side can't be compared
+      ("n", Some(v)) => match v.parse::<u8>() {
+        Ok(1) => parameters.n = None,
+        Ok(n) if n <= MAX_CHOICES => parameters.n = Some(n),
+        _ => return Err(SazidError::Other(format!("n must be a number between 1 and {}", MAX_CHOICES))),
+      },
+      ("n", None) => parameters.n = None,
+      ("max_tokens", Some(v)) => match v.parse::<usize>() {
+        Ok(max_tokens) if max_tokens > 0 && max_tokens <= u16::MAX as usize => self.response_max_tokens = max_tokens,
+        _ => return Err(SazidError::Other(format!("max_tokens must be a number between 1 and {}", u16::MAX))),
+      },
+      ("max_tokens", None) => self.response_max_tokens = SessionConfig::default().response_max_tokens,
+      _ => return Err(SazidError::Other(format!("unknown request parameter: {}", name))),
+    }
+    Ok(())
+  }
+
   pub fn prompt_message(&self) -> ChatCompletionRequestSystemMessage {
     ChatCompletionRequestSystemMessage { content: Some(self.prompt.clone()), ..Default::default() }
   }
 
+  // the contents of each auto_context file, files that can't be read are skipped
+  pub fn auto_context_messages(&self) -> Vec<ChatCompletionRequestSystemMessage> {
+    self
+      .auto_context
+      .iter()
+      .filter_map(|path| match std::fs::read_to_string(path) {
+        Ok(content) => Some(ChatCompletionRequestSystemMessage {
+          content: Some(format!("context from {}:\n{}", path.display(), content)),
+          ..Default::default()
+        }),
+        Err(e) => {
+          log::warn!("failed to read auto context {}: {}", path.display(), e);
+          None
+        },
+      })
+      .collect()
+  }
+
   pub fn generate_session_id() -> String {
     // Get the current time since UNIX_EPOCH in seconds.
     let start = SystemTime::now();
@@ -75,3 +383,37 @@ impl SessionConfig {
     since_the_epoch.to_string()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_set_request_parameter() {
+    let mut config = SessionConfig::default();
+    config.set_request_parameter("temperature", Some("0.2")).unwrap();
+    config.set_request_parameter("stop", Some("###")).unwrap();
+    config.set_request_parameter("max_tokens", Some("512")).unwrap();
+    assert!(config.set_request_parameter("top_p", Some("1.5")).is_err());
+    assert!(config.set_request_parameter("seed", Some("1")).is_err());
+
+    let mut request = CreateChatCompletionRequest::default();
+    config.request_parameters.apply(&mut request);
+    assert_eq!(request.temperature, Some(0.2));
+    assert_eq!(request.top_p, None);
+    assert_eq!(request.stop, Some(Stop::StringArray(vec!["###".to_string()])));
+    assert_eq!(config.response_max_tokens, 512);
+    assert_eq!(request.n, None);
+
+    config.set_request_parameter("n", Some("3")).unwrap();
+    assert!(config.set_request_parameter("n", Some("0")).is_err());
+    config.request_parameters.apply(&mut request);
+    assert_eq!(request.n, Some(3));
+
+    config.set_request_parameter("temperature", None).unwrap();
+    config.set_request_parameter("stop", None).unwrap();
+    config.request_parameters.apply(&mut request);
+    assert_eq!(request.temperature, None);
+    assert_eq!(request.stop, None);
+  }
+}
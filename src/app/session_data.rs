@@ -1,16 +1,40 @@
+use async_openai::types::ChatCompletionRequestMessage;
 use serde_derive::{Deserialize, Serialize};
 
-use super::messages::{ChatMessage, MessageContainer, ReceiveBuffer};
+use super::{
+  citations::{cited_numbers, Citation},
+  messages::{ChatMessage, Feedback, MessageContainer, ReceiveBuffer},
+  session_stats::Transaction,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SessionData {
   pub messages: Vec<MessageContainer>,
   pub window_width: usize,
+  // every chunk retrieved for a request in this session, numbered from 1 in the order they were retrieved
+  #[serde(default)]
+  pub citations: Vec<Citation>,
+  // every chat completion request made in this session, for the stats view
+  #[serde(default)]
+  pub transactions: Vec<Transaction>,
+  // added with the tag command or by hooks, and included in exports
+  #[serde(default)]
+  pub tags: Vec<String>,
+  // hidden from the session lists unless archived sessions are asked for
+  #[serde(default)]
+  pub archived: bool,
 }
 
 impl Default for SessionData {
   fn default() -> Self {
-    SessionData { messages: vec![], window_width: 80 }
+    SessionData {
+      messages: vec![],
+      window_width: 80,
+      citations: vec![],
+      transactions: vec![],
+      tags: vec![],
+      archived: false,
+    }
   }
 }
 
@@ -35,6 +59,374 @@ impl SessionData {
         self.messages.push(message.into());
       },
     };
+    self.cite_sources();
     // return a vec of any functions that need to be called
   }
+
+  // lists the sources a completed response cites under it
+  fn cite_sources(&mut self) {
+    let Some(message) = self.messages.last_mut() else {
+      return;
+    };
+    if let ChatCompletionRequestMessage::Assistant(assistant) = &message.message {
+      if message.receive_complete && message.cited_sources.is_empty() {
+        message.cited_sources = cited_numbers(assistant.content.as_deref().unwrap_or_default())
+          .into_iter()
+          .filter_map(|number| self.citations.iter().find(|c| c.number == number).map(|c| c.label()))
+          .collect();
+      }
+    }
+  }
+
+  // numbers retrieved chunks after the ones already retrieved in the session, and returns them renumbered
+  pub fn add_citations(&mut self, citations: Vec<Citation>) -> Vec<Citation> {
+    let first = self.citations.len() + 1;
+    let citations = citations
+      .into_iter()
+      .enumerate()
+      .map(|(i, citation)| Citation { number: first + i, ..citation })
+      .collect::<Vec<Citation>>();
+    self.citations.extend(citations.clone());
+    citations
+  }
+
+  pub fn add_tag(&mut self, tag: &str) {
+    if !self.tags.iter().any(|t| t == tag) {
+      self.tags.push(tag.to_string());
+    }
+  }
+
+  pub fn remove_tag(&mut self, tag: &str) {
+    self.tags.retain(|t| t != tag);
+  }
+
+  pub fn citation(&self, number: usize) -> Option<&Citation> {
+    self.citations.iter().find(|citation| citation.number == number)
+  }
+
+  pub fn has_pending(&self) -> bool {
+    self.messages.iter().any(|m| m.pending)
+  }
+
+  // marks the user messages after the last response as pending, returns the number marked
+  pub fn mark_pending(&mut self) -> usize {
+    let unanswered = self.messages.iter().rposition(|m| m.receive_buffer.is_some()).map(|i| i + 1).unwrap_or(0);
+    let mut count = 0;
+    for message in self.messages[unanswered..].iter_mut() {
+      if matches!(message.message, ChatCompletionRequestMessage::User(_)) {
+        message.pending = true;
+        message.stylize_complete = false;
+        count += 1;
+      }
+    }
+    count
+  }
+
+  pub fn clear_pending(&mut self) {
+    self.messages.iter_mut().filter(|m| m.pending).for_each(|m| {
+      m.pending = false;
+      m.stylize_complete = false;
+    });
+  }
+
+  // the message at index with the tool calls or tool results that have to stay with it in a request
+  pub fn message_group(&self, index: usize) -> Vec<usize> {
+    let calls = |m: &MessageContainer| match &m.message {
+      ChatCompletionRequestMessage::Assistant(assistant) => {
+        assistant.tool_calls.iter().flatten().map(|call| call.id.clone()).collect::<Vec<String>>()
+      },
+      _ => vec![],
+    };
+    let start = match &self.messages[index].message {
+      ChatCompletionRequestMessage::Tool(tool) => {
+        self.messages[..index].iter().rposition(|m| calls(m).contains(&tool.tool_call_id)).unwrap_or(index)
+      },
+      _ => index,
+    };
+    let ids = calls(&self.messages[start]);
+    let mut group = vec![start];
+    group.extend(
+      self.messages.iter().enumerate().skip(start + 1).filter_map(|(i, m)| match &m.message {
+        ChatCompletionRequestMessage::Tool(tool) if ids.contains(&tool.tool_call_id) => Some(i),
+        _ => None,
+      }),
+    );
+    group
+  }
+
+  // excludes the message from requests, or includes it again, returns whether it is now excluded
+  pub fn toggle_excluded(&mut self, index: usize) -> bool {
+    let excluded = !self.messages[index].excluded;
+    for i in self.message_group(index) {
+      self.messages[i].excluded = excluded;
+      // a pinned message is always in the context, so excluding it unpins it
+      self.messages[i].pinned &= !excluded;
+      self.messages[i].stylize_complete = false;
+    }
+    excluded
+  }
+
+  // pins the message so that it is always sent as it is, or unpins it, returns whether it is now pinned
+  pub fn toggle_pinned(&mut self, index: usize) -> bool {
+    let pinned = !self.messages[index].pinned;
+    for i in self.message_group(index) {
+      self.messages[i].pinned = pinned;
+      self.messages[i].excluded &= !pinned;
+      self.messages[i].stylize_complete = false;
+    }
+    pinned
+  }
+
+  // removes the message from the transcript, returns the number of messages removed
+  pub fn delete_message(&mut self, index: usize) -> usize {
+    let group = self.message_group(index);
+    let mut i = 0;
+    self.messages.retain(|_| {
+      i += 1;
+      !group.contains(&(i - 1))
+    });
+    group.len()
+  }
+
+  // keeps the partial content of a response that was cancelled or timed out while streaming, returns false when
+  // there was none, partial tool calls are dropped since their arguments are incomplete
+  pub fn interrupt_response(&mut self, timed_out: bool) -> bool {
+    match self
+      .messages
+      .iter_mut()
+      .rev()
+      .find(|m| !m.receive_complete && matches!(m.message, ChatCompletionRequestMessage::Assistant(_)))
+    {
+      Some(message) => {
+        if let ChatCompletionRequestMessage::Assistant(assistant) = &mut message.message {
+          assistant.tool_calls = None;
+        }
+        message.interrupted = !timed_out;
+        message.timed_out = timed_out;
+        message.receive_complete = true;
+        message.stylize_complete = false;
+        true
+      },
+      None => false,
+    }
+  }
+
+  fn last_response(&self) -> Option<usize> {
+    self.messages.iter().rposition(|m| matches!(m.message, ChatCompletionRequestMessage::Assistant(_)))
+  }
+
+  // the index of the most recent response when it was cut off by the token limit, a timeout or a cancellation
+  pub fn truncated_response(&self) -> Option<usize> {
+    self
+      .last_response()
+      .filter(|i| self.messages[*i].interrupted || self.messages[*i].timed_out || self.messages[*i].hit_token_limit())
+  }
+
+  // appends the response after the message at index to it, so that a continued response reads as one message
+  // returns false when there is no response to append
+  pub fn stitch_continuation(&mut self, index: usize) -> bool {
+    let continuation = match self.last_response() {
+      Some(i) if i > index => self.messages.remove(i),
+      _ => return false,
+    };
+    let message = &mut self.messages[index];
+    if let (
+      ChatCompletionRequestMessage::Assistant(assistant),
+      ChatCompletionRequestMessage::Assistant(continued),
+    ) = (&mut message.message, continuation.message)
+    {
+      let content = [assistant.content.take(), continued.content].into_iter().flatten().collect::<String>();
+      assistant.content = Some(content);
+      assistant.tool_calls = continued.tool_calls;
+    }
+    message.tools_called = continuation.tools_called;
+    // the flags follow the continuation, which may itself have been cut off
+    message.receive_buffer = continuation.receive_buffer;
+    message.interrupted = continuation.interrupted;
+    message.timed_out = continuation.timed_out;
+    message.stylize_complete = false;
+    true
+  }
+
+  // rates the most recent complete response, returns false when there is no response to rate
+  pub fn rate_last_response(&mut self, feedback: Feedback) -> bool {
+    match self
+      .messages
+      .iter_mut()
+      .rev()
+      .find(|m| m.receive_complete && matches!(m.message, ChatCompletionRequestMessage::Assistant(_)))
+    {
+      Some(message) => {
+        message.feedback = Some(feedback);
+        message.stylize_complete = false;
+        true
+      },
+      None => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatChoice, ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage, ChatCompletionRequestToolMessage,
+    ChatCompletionResponseMessage, ChatCompletionResponseStreamMessage, ChatCompletionStreamResponseDelta,
+    ChatCompletionToolType, CreateChatCompletionResponse, CreateChatCompletionStreamResponse, FinishReason,
+    FunctionCall, Role,
+  };
+
+  use super::*;
+  use crate::app::messages::RenderedChatMessage;
+
+  fn tool_call(id: &str) -> ChatCompletionMessageToolCall {
+    ChatCompletionMessageToolCall {
+      id: id.to_string(),
+      r#type: ChatCompletionToolType::Function,
+      function: FunctionCall { name: "read_file".to_string(), arguments: "{}".to_string() },
+    }
+  }
+
+  fn tool_result(id: &str) -> ChatMessage {
+    ChatMessage::Tool(ChatCompletionRequestToolMessage {
+      role: Role::Tool,
+      content: Some("fn main() {}".to_string()),
+      tool_call_id: id.to_string(),
+    })
+  }
+
+  #[test]
+  fn test_tool_results_stay_with_their_calls() {
+    let mut data = SessionData::default();
+    data.add_message(ChatMessage::Assistant(ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      tool_calls: Some(vec![tool_call("a"), tool_call("b")]),
+      ..Default::default()
+    }));
+    data.add_message(tool_result("a"));
+    data.add_message(tool_result("b"));
+    data.add_message(ChatMessage::Assistant(ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      content: Some("main is empty".to_string()),
+      ..Default::default()
+    }));
+
+    assert_eq!(data.message_group(2), vec![0, 1, 2]);
+    assert!(data.toggle_excluded(1));
+    assert!(data.messages[..3].iter().all(|m| m.excluded) && !data.messages[3].excluded);
+    assert!(data.toggle_pinned(0));
+    assert!(data.messages[..3].iter().all(|m| m.pinned && !m.excluded));
+    assert_eq!(data.delete_message(0), 3);
+    assert_eq!(data.messages.len(), 1);
+  }
+
+  #[test]
+  fn test_interrupted_response_keeps_partial_content() {
+    let mut data = SessionData::default();
+    data.add_message(ChatMessage::StreamResponse(vec![CreateChatCompletionStreamResponse {
+      id: "chatcmpl-1".to_string(),
+      object: "chat.completion.chunk".to_string(),
+      created: 0,
+      model: "gpt-4".to_string(),
+      system_fingerprint: None,
+      choices: vec![ChatCompletionResponseStreamMessage {
+        index: 0,
+        delta: ChatCompletionStreamResponseDelta {
+          role: Some(Role::Assistant),
+          content: Some("the answer is".to_string()),
+          function_call: None,
+          tool_calls: None,
+        },
+        finish_reason: None,
+      }],
+    }]));
+    assert!(!data.messages[0].receive_complete);
+
+    assert!(data.interrupt_response(false));
+    assert!(data.messages[0].interrupted && data.messages[0].receive_complete);
+    assert!(data.messages[0].to_string().contains("(interrupted)"));
+    assert!(data.messages[0].to_string().contains("the answer is"));
+    assert_eq!(data.truncated_response(), Some(0));
+    assert!(!data.interrupt_response(true));
+  }
+
+  fn response(content: &str, finish_reason: FinishReason) -> ChatMessage {
+    ChatMessage::Response(CreateChatCompletionResponse {
+      id: "chatcmpl-1".to_string(),
+      object: "chat.completion".to_string(),
+      created: 0,
+      model: "gpt-4".to_string(),
+      choices: vec![ChatChoice {
+        index: 0,
+        message: ChatCompletionResponseMessage {
+          role: Role::Assistant,
+          content: Some(content.to_string()),
+          tool_calls: None,
+          function_call: None,
+        },
+        finish_reason: Some(finish_reason),
+      }],
+      usage: None,
+      system_fingerprint: None,
+    })
+  }
+
+  #[test]
+  fn test_continuation_is_stitched_onto_the_truncated_response() {
+    let mut data = SessionData::default();
+    data.add_message(response("fn main() {\n", FinishReason::Length));
+    assert_eq!(data.truncated_response(), Some(0));
+    assert!(!data.stitch_continuation(0));
+
+    data.add_message(response("}\n", FinishReason::Stop));
+    assert!(data.stitch_continuation(0));
+    assert_eq!(data.messages.len(), 1);
+    assert_eq!(data.truncated_response(), None);
+    match &data.messages[0].message {
+      ChatCompletionRequestMessage::Assistant(assistant) => {
+        assert_eq!(assistant.content.as_deref(), Some("fn main() {\n}\n"))
+      },
+      _ => panic!("expected an assistant message"),
+    }
+  }
+
+  #[test]
+  fn test_finish_reason_is_shown_with_the_response() {
+    let mut data = SessionData::default();
+    data.add_message(response("fn main() {}\n", FinishReason::Stop));
+    data.add_message(response("", FinishReason::ContentFilter));
+    assert_eq!(data.messages[0].finish_reason(), Some(FinishReason::Stop));
+    assert!(data.messages[0].block_title().ends_with("stop"));
+    assert!(!data.messages[0].refused());
+
+    assert!(data.messages[1].refused());
+    assert!(data.messages[1].block_title().starts_with("Assistant (refused)"));
+    assert!(data.messages[1].block_title().ends_with("content filter"));
+    assert!(data.messages[1].to_string().contains("the response was withheld by the content filter"));
+    assert_eq!(RenderedChatMessage::from(&data.messages[1]).finish_reason, Some(FinishReason::ContentFilter));
+  }
+
+  #[test]
+  fn test_keep_one_of_several_choices() {
+    let mut data = SessionData::default();
+    let recorded = include_str!("../../tests/assets/interleaved_stream_response.json");
+    serde_json::Deserializer::from_str(recorded)
+      .into_iter::<CreateChatCompletionStreamResponse>()
+      .for_each(|response| data.add_message(ChatMessage::StreamResponse(vec![response.unwrap()])));
+    assert_eq!(data.messages.len(), 1);
+    assert!(data.messages[0].receive_complete);
+    let choices = data.messages[0].choices();
+    assert_eq!(choices.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(choices[0].1.content.as_deref(), Some("Roses are red."));
+
+    assert!(!data.messages[0].keep_choice(2));
+    assert!(data.messages[0].keep_choice(1));
+    assert_eq!(data.messages[0].choices().len(), 1);
+    assert_eq!(data.messages[0].finish_reason(), Some(FinishReason::Stop));
+    match &data.messages[0].message {
+      ChatCompletionRequestMessage::Assistant(assistant) => {
+        assert_eq!(assistant.content.as_deref(), Some("Violets are blue."))
+      },
+      _ => panic!("expected an assistant message"),
+    }
+  }
 }
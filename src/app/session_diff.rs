@@ -0,0 +1,230 @@
+use std::{
+  io::{IsTerminal, Write},
+  path::Path,
+};
+
+use async_openai::types::Role;
+use nu_ansi_term::Color::{Cyan, Green, Red};
+use similar::{capture_diff_slices, Algorithm, ChangeTag, DiffTag, TextDiff};
+
+use super::{
+  errors::SazidError,
+  export::{role_label, Transcript},
+};
+
+// a prompt with the messages that followed it until the next prompt, the system messages before the first prompt are
+// a turn without one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Turn {
+  pub prompt: Option<String>,
+  pub replies: Vec<String>,
+}
+
+impl Turn {
+  fn replies_text(&self) -> String {
+    self.replies.join("\n")
+  }
+}
+
+pub fn turns(transcript: &Transcript) -> Vec<Turn> {
+  let mut turns: Vec<Turn> = vec![];
+  for message in &transcript.messages {
+    match message.role {
+      Some(Role::User) => turns.push(Turn { prompt: Some(message.content.clone()), replies: vec![] }),
+      _ => {
+        if turns.is_empty() {
+          turns.push(Turn::default());
+        }
+        let label = match (&message.role, &message.model) {
+          (Some(Role::Assistant), Some(model)) => format!("{} ({})", role_label(&message.role), model),
+          _ => role_label(&message.role).to_string(),
+        };
+        turns.last_mut().unwrap().replies.push(format!("{}: {}", label, message.content));
+      },
+    }
+  }
+  turns
+}
+
+// the turns of the two sessions paired by their prompts, a turn of one session without a matching prompt in the other
+// is paired with the turn in its place if there is one, otherwise it stands alone
+pub fn align_turns<'a>(a: &'a [Turn], b: &'a [Turn]) -> Vec<(Option<&'a Turn>, Option<&'a Turn>)> {
+  let (prompts_a, prompts_b): (Vec<_>, Vec<_>) =
+    (a.iter().map(|turn| &turn.prompt).collect(), b.iter().map(|turn| &turn.prompt).collect());
+  let mut pairs = vec![];
+  for op in capture_diff_slices(Algorithm::Myers, &prompts_a, &prompts_b) {
+    let (tag, old, new) = op.as_tag_tuple();
+    match tag {
+      DiffTag::Equal => pairs.extend(old.zip(new).map(|(i, j)| (Some(&a[i]), Some(&b[j])))),
+      DiffTag::Delete => pairs.extend(old.map(|i| (Some(&a[i]), None))),
+      DiffTag::Insert => pairs.extend(new.map(|j| (None, Some(&b[j])))),
+      DiffTag::Replace => {
+        let (old, new) = (old.collect::<Vec<_>>(), new.collect::<Vec<_>>());
+        for k in 0..old.len().max(new.len()) {
+          pairs.push((old.get(k).map(|i| &a[*i]), new.get(k).map(|j| &b[*j])));
+        }
+      },
+    }
+  }
+  pairs
+}
+
+struct DiffWriter {
+  color: bool,
+  output: String,
+}
+
+impl DiffWriter {
+  fn line(&mut self, sign: &str, text: &str) {
+    let line = format!("{} {}", sign, text);
+    let line = match (self.color, sign) {
+      (false, _) => line,
+      (true, "-") => Red.paint(line).to_string(),
+      (true, "+") => Green.paint(line).to_string(),
+      (true, "@@") => Cyan.bold().paint(line).to_string(),
+      (true, _) => line,
+    };
+    self.output += &line;
+    self.output.push('\n');
+  }
+
+  fn text(&mut self, sign: &str, text: &str) {
+    text.lines().for_each(|line| self.line(sign, line));
+  }
+
+  // the lines of the two texts, with the removed and added lines marked
+  fn diff(&mut self, old: &str, new: &str) {
+    for change in TextDiff::from_lines(old, new).iter_all_changes() {
+      let sign = match change.tag() {
+        ChangeTag::Delete => "-",
+        ChangeTag::Insert => "+",
+        ChangeTag::Equal => " ",
+      };
+      self.line(sign, change.value().trim_end_matches('\n'));
+    }
+  }
+}
+
+// the turns of session a against those of session b, a turn with the same prompt and replies is only counted
+pub fn render_diff(a: &Transcript, b: &Transcript, color: bool) -> String {
+  let (turns_a, turns_b) = (turns(a), turns(b));
+  let mut writer = DiffWriter { color, output: String::new() };
+  writer.line("---", &format!("{} {} ({})", a.session_id, a.title(), a.model));
+  writer.line("+++", &format!("{} {} ({})", b.session_id, b.title(), b.model));
+  let (mut same, mut changed, mut removed, mut added) = (0, 0, 0, 0);
+  for (number, pair) in align_turns(&turns_a, &turns_b).into_iter().enumerate() {
+    let heading = format!("turn {}", number + 1);
+    match pair {
+      (Some(turn_a), Some(turn_b)) if turn_a == turn_b => same += 1,
+      (Some(turn_a), Some(turn_b)) => {
+        changed += 1;
+        writer.line("@@", &format!("{} @@", heading));
+        match (&turn_a.prompt, &turn_b.prompt) {
+          (prompt_a, prompt_b) if prompt_a == prompt_b => writer.text(" ", prompt_a.as_deref().unwrap_or_default()),
+          (prompt_a, prompt_b) => {
+            writer.diff(prompt_a.as_deref().unwrap_or_default(), prompt_b.as_deref().unwrap_or_default())
+          },
+        }
+        writer.diff(&turn_a.replies_text(), &turn_b.replies_text());
+      },
+      (Some(turn), None) => {
+        removed += 1;
+        writer.line("@@", &format!("{}, only in {} @@", heading, a.session_id));
+        writer.text("-", turn.prompt.as_deref().unwrap_or_default());
+        writer.text("-", &turn.replies_text());
+      },
+      (None, Some(turn)) => {
+        added += 1;
+        writer.line("@@", &format!("{}, only in {} @@", heading, b.session_id));
+        writer.text("+", turn.prompt.as_deref().unwrap_or_default());
+        writer.text("+", &turn.replies_text());
+      },
+      (None, None) => {},
+    }
+  }
+  writer.output += &format!(
+    "{} turns the same, {} changed, {} only in {}, {} only in {}\n",
+    same, changed, removed, a.session_id, added, b.session_id
+  );
+  writer.output
+}
+
+// a session id, or the path of a session file
+fn load_transcript(session: &str) -> Result<Transcript, SazidError> {
+  match Path::new(session).is_file() {
+    true => Transcript::load_file(Path::new(session)),
+    false => Transcript::load(session),
+  }
+}
+
+// prints the differences between two saved sessions, colored when stdout is a terminal
+pub fn run_diff(session_a: &str, session_b: &str) -> Result<(), SazidError> {
+  let (a, b) = (load_transcript(session_a)?, load_transcript(session_b)?);
+  let diff = render_diff(&a, &b, std::io::stdout().is_terminal());
+  std::io::stdout().write_all(diff.as_bytes())?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::app::messages::RenderedChatMessage;
+
+  use super::*;
+
+  fn transcript(session_id: &str, model: &str, messages: &[(Role, &str)]) -> Transcript {
+    Transcript {
+      session_id: session_id.to_string(),
+      name: String::new(),
+      model: model.to_string(),
+      messages: messages
+        .iter()
+        .map(|(role, content)| RenderedChatMessage {
+          role: Some(role.clone()),
+          content: content.to_string(),
+          model: None,
+          finish_reason: None,
+        })
+        .collect(),
+      tags: vec![],
+      profile: None,
+      source_files: vec![],
+    }
+  }
+
+  #[test]
+  fn test_render_diff() {
+    let a = transcript(
+      "1700000000",
+      "gpt-3.5-turbo",
+      &[
+        (Role::System, "be brief"),
+        (Role::User, "what is 1 + 1?"),
+        (Role::Assistant, "2"),
+        (Role::User, "and 2 + 2?"),
+        (Role::Assistant, "four"),
+        (Role::User, "thanks"),
+        (Role::Assistant, "you're welcome"),
+      ],
+    );
+    let b = transcript(
+      "1700000100",
+      "gpt-4",
+      &[
+        (Role::System, "be brief"),
+        (Role::User, "what is 1 + 1?"),
+        (Role::Assistant, "2"),
+        (Role::User, "and 2 + 2?"),
+        (Role::Assistant, "4"),
+        (Role::User, "and 3 + 3?"),
+        (Role::Assistant, "6"),
+      ],
+    );
+    assert_eq!(align_turns(&turns(&a), &turns(&b)).len(), 4);
+
+    let diff = render_diff(&a, &b, false);
+    assert!(diff.starts_with("--- 1700000000 what is 1 + 1? (gpt-3.5-turbo)\n+++ 1700000100"));
+    assert!(diff.contains("@@ turn 3 @@\n  and 2 + 2?\n- Assistant: four\n+ Assistant: 4\n"));
+    assert!(diff.contains("@@ turn 4 @@\n- thanks\n+ and 3 + 3?\n- Assistant: you're welcome\n+ Assistant: 6\n"));
+    assert!(diff.ends_with("2 turns the same, 2 changed, 0 only in 1700000000, 0 only in 1700000100\n"));
+  }
+}
@@ -0,0 +1,93 @@
+use std::{
+  path::Path,
+  time::{Duration, SystemTime},
+};
+
+use serde_json::Value;
+
+use super::{
+  consts::{data_path, SESSIONS_DIR},
+  encryption,
+  errors::SazidError,
+  helpers::list_files_ordered_by_date,
+  session_search::load_session_summaries,
+};
+
+// prints the saved sessions, oldest first, with a tag only those tagged with it, and archived sessions only when asked
+pub fn run_list_sessions(tag: Option<&str>, include_archived: bool) -> Result<(), SazidError> {
+  let sessions_dir = data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()))?;
+  let summaries = load_session_summaries(&sessions_dir);
+  let listed = summaries.iter().filter(|s| s.is_listed(tag, include_archived)).collect::<Vec<_>>();
+  listed.iter().for_each(|summary| println!("{}", summary.list_entry()));
+  let hidden = summaries.iter().filter(|s| s.archived && s.is_listed(tag, true)).count();
+  if !include_archived && hidden > 0 {
+    eprintln!("{} archived session(s) hidden, --archived to list them", hidden);
+  }
+  Ok(())
+}
+
+// marks a saved session archived or not, leaving the rest of it as it was saved
+pub fn set_archived(session_file_path: &Path, archived: bool) -> Result<(), SazidError> {
+  let session_json = encryption::read_to_string(session_file_path)
+    .map_err(|e| SazidError::Other(format!("Failed to load session {}: {}", session_file_path.display(), e)))?;
+  let mut session: Value = serde_json::from_str(&session_json)
+    .map_err(|e| SazidError::Other(format!("Failed to parse session {}: {}", session_file_path.display(), e)))?;
+  let data = session
+    .get_mut("data")
+    .and_then(|data| data.as_object_mut())
+    .ok_or_else(|| SazidError::Other(format!("session {} has no data", session_file_path.display())))?;
+  data.insert("archived".to_string(), Value::Bool(archived));
+  let tmp_path = session_file_path.with_extension("json.tmp");
+  encryption::write(&tmp_path, session.to_string())?;
+  std::fs::rename(tmp_path, session_file_path)?;
+  Ok(())
+}
+
+// archives the sessions given by id and those not changed for older_than days, or takes them out of the archive
+pub fn run_archive(session_ids: &[String], older_than: Option<u64>, restore: bool) -> Result<(), SazidError> {
+  let sessions_dir = data_path(SESSIONS_DIR).ok_or_else(|| SazidError::Other("no sessions directory".to_string()))?;
+  if session_ids.is_empty() && older_than.is_none() {
+    return Err(SazidError::Other("give the ids of the sessions to archive, or --older-than".to_string()));
+  }
+  let mut paths = session_ids.iter().map(|id| sessions_dir.join(format!("{}.json", id))).collect::<Vec<_>>();
+  if let Some(days) = older_than {
+    let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+    let old = list_files_ordered_by_date(&sessions_dir)?
+      .iter()
+      .filter(|f| f.path().extension().map_or(false, |e| e == "json"))
+      .filter(|f| f.metadata().and_then(|m| m.modified()).map_or(false, |modified| modified < cutoff))
+      .map(|f| f.path())
+      .collect::<Vec<_>>();
+    for path in old {
+      if !paths.contains(&path) {
+        paths.push(path);
+      }
+    }
+  }
+  for path in &paths {
+    set_archived(path, !restore)?;
+  }
+  let verb = if restore { "restored" } else { "archived" };
+  eprintln!("{} {} session(s)", verb, paths.len());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_set_archived() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("42.json");
+    std::fs::write(&path, r#"{"schema_version": 3, "data": {"messages": [], "tags": ["rust"]}, "config": {}}"#)
+      .unwrap();
+    set_archived(&path, true).unwrap();
+    let session: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(session["data"]["archived"], Value::Bool(true));
+    assert_eq!(session["data"]["tags"][0], "rust");
+    assert_eq!(session["schema_version"], 3);
+    set_archived(&path, false).unwrap();
+    assert!(std::fs::read_to_string(&path).unwrap().contains("\"archived\":false"));
+  }
+}
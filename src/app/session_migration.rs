@@ -0,0 +1,91 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use super::errors::SazidError;
+
+// saved as schema_version in every session file, bumped with a migration below whenever a change to the
+// session types or the async-openai types they contain would stop older files from loading
+pub const SESSION_SCHEMA_VERSION: u64 = 1;
+
+// parses a session file saved with any schema version up to the current one, upgrading it on the way
+pub fn migrate_session<T: DeserializeOwned>(session_json: &str) -> Result<T, SazidError> {
+  let mut session: Value =
+    serde_json::from_str(session_json).map_err(|e| SazidError::Other(format!("invalid session json: {}", e)))?;
+  if !session.is_object() {
+    return Err(SazidError::Other("invalid session json: expected an object".to_string()));
+  }
+  let version = session.get("schema_version").and_then(Value::as_u64).unwrap_or(0);
+  if version > SESSION_SCHEMA_VERSION {
+    return Err(SazidError::Other(format!(
+      "the session was saved with schema version {}, this version of sazid reads up to {}",
+      version, SESSION_SCHEMA_VERSION
+    )));
+  }
+  if version < 1 {
+    migrate_v0(&mut session);
+  }
+  session["schema_version"] = json!(SESSION_SCHEMA_VERSION);
+  serde_json::from_value(session)
+    .map_err(|e| SazidError::Other(format!("session schema version {} could not be read: {}", version, e)))
+}
+
+// files from before the schema was versioned can hold the request messages as they were sent, without a
+// container, and predate most of the container fields
+fn migrate_v0(session: &mut Value) {
+  if session.pointer("/data/window_width").is_none() {
+    if let Some(data) = session.get_mut("data").and_then(Value::as_object_mut) {
+      data.insert("window_width".to_string(), json!(80));
+    }
+  }
+  let Some(messages) = session.pointer_mut("/data/messages").and_then(Value::as_array_mut) else {
+    return;
+  };
+  for message in messages.iter_mut() {
+    if message.get("message").is_none() {
+      *message = json!({ "message": message.take() });
+    }
+    let defaults = json!({
+      "receive_buffer": null,
+      "tool_calls": [],
+      "stream_id": null,
+      "selected_choice": 0,
+      "tools_called": false,
+      "receive_complete": true,
+      "stylize_complete": false,
+      "response_count": 0,
+      "wrapped_content": "",
+      "token_usage": 0,
+    });
+    if let (Some(container), Value::Object(defaults)) = (message.as_object_mut(), defaults) {
+      for (field, value) in defaults {
+        container.entry(field).or_insert(value);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::session_data::SessionData;
+
+  #[test]
+  fn test_migrate_unversioned_session() {
+    let session_json = r#"{
+      "data": { "messages": [
+        { "role": "user", "content": "what is a lifetime?" },
+        { "message": { "role": "assistant", "content": "a region of code" }, "tool_calls": [] }
+      ] },
+      "config": {}
+    }"#;
+    let session = migrate_session::<Value>(session_json).unwrap();
+    assert_eq!(session["schema_version"], json!(SESSION_SCHEMA_VERSION));
+    let data = serde_json::from_value::<SessionData>(session["data"].clone()).unwrap();
+    assert_eq!(data.messages.len(), 2);
+    assert_eq!(data.window_width, 80);
+    assert!(data.messages.iter().all(|m| m.receive_complete && m.tool_calls.is_empty()));
+
+    let newer = json!({ "schema_version": SESSION_SCHEMA_VERSION + 1, "data": {} }).to_string();
+    assert!(migrate_session::<Value>(&newer).unwrap_err().to_string().contains("schema version"));
+  }
+}
@@ -0,0 +1,229 @@
+use std::{
+  collections::{HashMap, HashSet},
+  path::Path,
+};
+
+use serde_json::Value;
+
+use super::{encryption, functions::argument_validation::count_tokens, helpers::list_files_ordered_by_date};
+
+// related sessions must share at least this much of their vocabulary with the first message
+pub const RELATED_SESSION_MIN_SCORE: f64 = 0.25;
+pub const RELATED_SESSION_LIMIT: usize = 3;
+const SUMMARY_MAX_TOKENS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+  pub session_id: String,
+  pub user_prompts: Vec<String>,
+  pub last_response: Option<String>,
+  pub tags: Vec<String>,
+  pub archived: bool,
+}
+
+impl SessionSummary {
+  pub fn from_session_json(session_id: &str, session_json: &str) -> Option<Self> {
+    let session = serde_json::from_str::<Value>(session_json).ok()?;
+    let messages = session["data"]["messages"].as_array()?;
+    let content_for_role = |role: &str| -> Vec<String> {
+      messages
+        .iter()
+        .filter(|m| m["message"]["role"] == role)
+        .filter_map(|m| m["message"]["content"].as_str())
+        .map(|c| c.to_string())
+        .collect()
+    };
+    let user_prompts = content_for_role("user");
+    if user_prompts.is_empty() {
+      return None;
+    }
+    let tags = session["data"]["tags"]
+      .as_array()
+      .map_or(vec![], |tags| tags.iter().filter_map(|tag| tag.as_str()).map(|tag| tag.to_string()).collect());
+    Some(SessionSummary {
+      session_id: session_id.to_string(),
+      user_prompts,
+      last_response: content_for_role("assistant").pop(),
+      tags,
+      archived: session["data"]["archived"].as_bool().unwrap_or(false),
+    })
+  }
+
+  // archived sessions are listed only when asked for
+  pub fn is_listed(&self, tag: Option<&str>, include_archived: bool) -> bool {
+    (include_archived || !self.archived) && tag.map_or(true, |tag| self.tags.iter().any(|t| t == tag))
+  }
+
+  // the id, the title and the tags, for session lists
+  pub fn list_entry(&self) -> String {
+    let mut entry = format!("{}: {}", self.session_id, self.title());
+    if !self.tags.is_empty() {
+      entry += &format!(" [{}]", self.tags.join(", "));
+    }
+    if self.archived {
+      entry += " (archived)";
+    }
+    entry
+  }
+
+  pub fn title(&self) -> String {
+    let first_line = self.user_prompts[0].lines().next().unwrap_or_default();
+    match first_line.char_indices().nth(60) {
+      Some((i, _)) => format!("{}...", &first_line[..i]),
+      None => first_line.to_string(),
+    }
+  }
+
+  // an extractive summary of the session, used as context when continuing the topic in a new session
+  pub fn summary(&self) -> String {
+    let mut summary = format!("Summary of a previous session ({}):\nQuestions asked:\n", self.session_id);
+    for prompt in self.user_prompts.iter() {
+      let line = format!("- {}\n", prompt.lines().next().unwrap_or_default());
+      if count_tokens(&summary) + count_tokens(&line) > SUMMARY_MAX_TOKENS / 2 {
+        break;
+      }
+      summary.push_str(&line);
+    }
+    if let Some(response) = &self.last_response {
+      let mut response_summary = String::new();
+      for line in response.lines() {
+        if count_tokens(&summary) + count_tokens(&response_summary) + count_tokens(line) > SUMMARY_MAX_TOKENS {
+          break;
+        }
+        response_summary.push_str(line);
+        response_summary.push('\n');
+      }
+      summary.push_str(&format!("Final answer:\n{}", response_summary));
+    }
+    summary
+  }
+}
+
+pub fn load_session_summaries(sessions_dir: &Path) -> Vec<SessionSummary> {
+  match list_files_ordered_by_date(sessions_dir) {
+    Ok(session_files) => session_files
+      .iter()
+      .filter(|f| f.path().extension().map(|e| e == "json").unwrap_or(false))
+      .filter_map(|f| {
+        let session_id = f.path().file_stem()?.to_string_lossy().to_string();
+        let session_json = encryption::read_to_string(&f.path()).ok()?;
+        SessionSummary::from_session_json(&session_id, &session_json)
+      })
+      .collect(),
+    Err(_) => vec![],
+  }
+}
+
+fn terms(text: &str) -> Vec<String> {
+  text
+    .split(|c: char| !c.is_alphanumeric() && c != '_')
+    .filter(|t| t.len() > 2)
+    .map(|t| t.to_lowercase())
+    .collect()
+}
+
+// ranks sessions by tf-idf cosine similarity between the query and the prompts of each session
+pub fn find_related_sessions<'a>(
+  query: &str,
+  sessions: &'a [SessionSummary],
+  limit: usize,
+  min_score: f64,
+) -> Vec<(f64, &'a SessionSummary)> {
+  let documents: Vec<Vec<String>> = sessions.iter().map(|s| terms(&s.user_prompts.join("\n"))).collect();
+  let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+  for document in documents.iter() {
+    document.iter().map(|t| t.as_str()).collect::<HashSet<&str>>().into_iter().for_each(|t| {
+      *document_frequency.entry(t).or_default() += 1;
+    });
+  }
+  let idf = |term: &str| {
+    let df = document_frequency.get(term).copied().unwrap_or(0) as f64;
+    ((sessions.len() as f64 + 1.0) / (df + 1.0)).ln() + 1.0
+  };
+  let vector = |document: &[String]| -> HashMap<String, f64> {
+    let mut vector: HashMap<String, f64> = HashMap::new();
+    document.iter().for_each(|t| *vector.entry(t.clone()).or_default() += 1.0);
+    vector.iter_mut().for_each(|(t, w)| *w *= idf(t));
+    vector
+  };
+  let norm = |v: &HashMap<String, f64>| v.values().map(|w| w * w).sum::<f64>().sqrt();
+
+  let query_vector = vector(&terms(query));
+  let query_norm = norm(&query_vector);
+  if query_norm == 0.0 {
+    return vec![];
+  }
+  let mut scored: Vec<(f64, &SessionSummary)> = documents
+    .iter()
+    .zip(sessions.iter())
+    .filter_map(|(document, session)| {
+      let document_vector = vector(document);
+      let document_norm = norm(&document_vector);
+      if document_norm == 0.0 {
+        return None;
+      }
+      let dot: f64 = query_vector.iter().filter_map(|(t, w)| document_vector.get(t).map(|d| w * d)).sum();
+      Some((dot / (query_norm * document_norm), session))
+    })
+    .filter(|(score, _)| *score >= min_score)
+    .collect();
+  scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+  scored.truncate(limit);
+  scored
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn session(id: &str, prompts: &[&str]) -> SessionSummary {
+    SessionSummary {
+      session_id: id.to_string(),
+      user_prompts: prompts.iter().map(|p| p.to_string()).collect(),
+      last_response: None,
+      tags: vec![],
+      archived: false,
+    }
+  }
+
+  #[test]
+  fn test_find_related_sessions() {
+    let sessions = vec![
+      session("1", &["how do I configure the tokio runtime", "tokio runtime worker threads"]),
+      session("2", &["write a haiku about autumn leaves"]),
+    ];
+    let related = find_related_sessions("tokio runtime threads", &sessions, 3, RELATED_SESSION_MIN_SCORE);
+    assert_eq!(related.len(), 1);
+    assert_eq!(related[0].1.session_id, "1");
+    assert!(find_related_sessions("", &sessions, 3, 0.0).is_empty());
+  }
+
+  #[test]
+  fn test_from_session_json() {
+    let summary = SessionSummary::from_session_json(
+      "42",
+      r#"{"data": {"messages": [
+        {"message": {"role": "user", "content": "explain lifetimes"}},
+        {"message": {"role": "assistant", "content": "lifetimes describe how long references are valid"}}
+      ]}}"#,
+    )
+    .unwrap();
+    assert_eq!(summary.title(), "explain lifetimes");
+    assert!(summary.summary().contains("lifetimes describe"));
+    assert!(summary.is_listed(None, false) && !summary.is_listed(Some("rust"), true));
+  }
+
+  #[test]
+  fn test_tags_and_archive() {
+    let summary = SessionSummary::from_session_json(
+      "42",
+      r#"{"data": {"messages": [{"message": {"role": "user", "content": "explain lifetimes"}}],
+        "tags": ["rust", "learning"], "archived": true}}"#,
+    )
+    .unwrap();
+    assert_eq!(summary.list_entry(), "42: explain lifetimes [rust, learning] (archived)");
+    assert!(!summary.is_listed(Some("rust"), false));
+    assert!(summary.is_listed(Some("rust"), true));
+    assert!(!summary.is_listed(Some("python"), true));
+  }
+}
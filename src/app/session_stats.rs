@@ -0,0 +1,366 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt,
+  time::Duration,
+};
+
+use async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequest};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  compression::count_message_tokens, functions::argument_validation::count_tokens, inspector::RawExchange,
+  messages::ChatMessage, model_comparison::ComparedResponse, model_list::ModelPricing, session_data::SessionData,
+};
+
+// a chat completion request and its response, kept with the session for the stats view
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Transaction {
+  // unix seconds when the request was sent
+  pub timestamp: i64,
+  pub model: String,
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+  // from sending the request to receiving the end of the response
+  pub latency_ms: u64,
+  // from sending the request to receiving the first chunk, for streamed responses
+  #[serde(default)]
+  pub first_token_ms: Option<u64>,
+  // dollars, None when pricing is not configured for the model
+  pub cost: Option<f64>,
+  // why the request failed, a failed request has no tokens
+  #[serde(default)]
+  pub error: Option<String>,
+  // the response stalled or took longer than the request timeout, what arrived until then was kept
+  #[serde(default)]
+  pub timed_out: bool,
+  // in a/b mode, what the session's model and then the second model responded to the same request
+  #[serde(default)]
+  pub compared: Vec<ComparedResponse>,
+  // only kept while the program runs, since the request repeats the whole transcript
+  #[serde(skip)]
+  pub raw: Option<RawExchange>,
+}
+
+// requests considered for the api health
+const HEALTH_WINDOW: usize = 5;
+
+// a request that takes longer than this to start responding marks the api as degraded
+const SLOW_RESPONSE_MS: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ApiHealth {
+  Healthy,
+  // a recent request failed or was slow to respond
+  Degraded,
+  // the last request failed
+  Down,
+}
+
+impl fmt::Display for ApiHealth {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ApiHealth::Healthy => write!(f, "healthy"),
+      ApiHealth::Degraded => write!(f, "degraded"),
+      ApiHealth::Down => write!(f, "down"),
+    }
+  }
+}
+
+// what the status line shows about the last request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiStatus {
+  pub health: ApiHealth,
+  pub last_latency_ms: u64,
+  pub tokens_per_second: Option<f64>,
+}
+
+impl ApiStatus {
+  pub fn new(transactions: &[Transaction]) -> Option<Self> {
+    let recent = &transactions[transactions.len().saturating_sub(HEALTH_WINDOW)..];
+    let last = recent.last()?;
+    let health = if last.error.is_some() {
+      ApiHealth::Down
+    } else if recent.iter().any(|t| t.error.is_some() || t.timed_out || t.response_latency_ms() > SLOW_RESPONSE_MS) {
+      ApiHealth::Degraded
+    } else {
+      ApiHealth::Healthy
+    };
+    Some(ApiStatus { health, last_latency_ms: last.latency_ms, tokens_per_second: last.tokens_per_second() })
+  }
+}
+
+impl Transaction {
+  // token counts reported by the api are used when the response has them, otherwise they are counted
+  pub fn new(
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+    timestamp: i64,
+    latency: Duration,
+    pricing: &HashMap<String, ModelPricing>,
+  ) -> Self {
+    let usage = responses.iter().find_map(|response| match response {
+      ChatMessage::Response(response) => response.usage.clone(),
+      _ => None,
+    });
+    let (prompt_tokens, completion_tokens) = match usage {
+      Some(usage) => (usage.prompt_tokens as usize, usage.completion_tokens as usize),
+      None => (count_message_tokens(&request.messages), count_tokens(&response_text(responses))),
+    };
+    let model = responses
+      .iter()
+      .find_map(|response| match response {
+        ChatMessage::Response(response) => Some(response.model.clone()),
+        ChatMessage::StreamResponse(chunks) => chunks.first().map(|chunk| chunk.model.clone()),
+        _ => None,
+      })
+      .unwrap_or_else(|| request.model.clone());
+    let unqualified_model = model.rsplit('/').next().unwrap_or(&model);
+    let cost = pricing.get(&model).or_else(|| pricing.get(unqualified_model)).map(|pricing| {
+      (prompt_tokens as f64 * pricing.prompt + completion_tokens as f64 * pricing.completion) / 1000.0
+    });
+    let latency_ms = latency.as_millis() as u64;
+    let first_token_ms = None;
+    Transaction {
+      timestamp,
+      model,
+      prompt_tokens,
+      completion_tokens,
+      latency_ms,
+      first_token_ms,
+      cost,
+      error: None,
+      timed_out: false,
+      compared: Vec::new(),
+      raw: None,
+    }
+  }
+
+  pub fn failed(request: &CreateChatCompletionRequest, timestamp: i64, latency: Duration, error: String) -> Self {
+    Transaction {
+      timestamp,
+      model: request.model.clone(),
+      prompt_tokens: 0,
+      completion_tokens: 0,
+      latency_ms: latency.as_millis() as u64,
+      first_token_ms: None,
+      cost: None,
+      error: Some(error),
+      timed_out: false,
+      compared: Vec::new(),
+      raw: None,
+    }
+  }
+
+  // how long the api took to start responding
+  pub fn response_latency_ms(&self) -> u64 {
+    self.first_token_ms.unwrap_or(self.latency_ms)
+  }
+
+  // completion tokens over the time spent streaming them, None when the response wasn't streamed
+  pub fn tokens_per_second(&self) -> Option<f64> {
+    let first_token_ms = self.first_token_ms?;
+    let streaming_ms = self.latency_ms.saturating_sub(first_token_ms).max(1);
+    Some(self.completion_tokens as f64 * 1000.0 / streaming_ms as f64)
+  }
+}
+
+// the content and tool call arguments of the responses, streamed chunks are joined before they are counted
+fn response_text(responses: &[ChatMessage]) -> String {
+  let mut text = String::new();
+  for response in responses {
+    match response {
+      ChatMessage::Response(response) => response.choices.iter().for_each(|choice| {
+        text.push_str(choice.message.content.as_deref().unwrap_or_default());
+        choice.message.tool_calls.iter().flatten().for_each(|call| text.push_str(&call.function.arguments));
+      }),
+      ChatMessage::StreamResponse(chunks) => chunks.iter().flat_map(|chunk| &chunk.choices).for_each(|choice| {
+        text.push_str(choice.delta.content.as_deref().unwrap_or_default());
+        choice
+          .delta
+          .tool_calls
+          .iter()
+          .flatten()
+          .filter_map(|call| call.function.as_ref().and_then(|f| f.arguments.as_deref()))
+          .for_each(|arguments| text.push_str(arguments));
+      }),
+      _ => {},
+    }
+  }
+  text
+}
+
+// totals for a session, computed from its messages and transactions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionStats {
+  // message counts by role
+  pub messages: BTreeMap<&'static str, usize>,
+  // the number of times each function was called
+  pub tool_calls: BTreeMap<String, usize>,
+  // the number of requests made to each model
+  pub models: BTreeMap<String, usize>,
+  pub requests: usize,
+  pub failed_requests: usize,
+  pub timed_out_requests: usize,
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+  pub average_latency_ms: Option<u64>,
+  pub average_tokens_per_second: Option<f64>,
+  pub api_status: Option<ApiStatus>,
+  // only requests to models with configured pricing are counted
+  pub cost: f64,
+  // prompt and completion tokens of each request, in the order they were made
+  pub token_history: Vec<u64>,
+}
+
+impl SessionStats {
+  pub fn new(data: &SessionData) -> Self {
+    let mut stats = SessionStats::default();
+    for message in &data.messages {
+      *stats.messages.entry(message.role()).or_default() += 1;
+      if let ChatCompletionRequestMessage::Assistant(assistant) = &message.message {
+        for call in assistant.tool_calls.iter().flatten() {
+          *stats.tool_calls.entry(call.function.name.clone()).or_default() += 1;
+        }
+      }
+    }
+    for transaction in &data.transactions {
+      *stats.models.entry(transaction.model.clone()).or_default() += 1;
+      stats.prompt_tokens += transaction.prompt_tokens;
+      stats.completion_tokens += transaction.completion_tokens;
+      stats.cost += transaction.cost.unwrap_or_default();
+      stats.token_history.push((transaction.prompt_tokens + transaction.completion_tokens) as u64);
+      // the second model of an a/b comparison was sent the same request
+      for compared in transaction.compared.iter().skip(1) {
+        *stats.models.entry(compared.model.clone()).or_default() += 1;
+        stats.prompt_tokens += compared.prompt_tokens;
+        stats.completion_tokens += compared.completion_tokens;
+        stats.cost += compared.cost.unwrap_or_default();
+      }
+    }
+    stats.requests = data.transactions.len();
+    stats.failed_requests = data.transactions.iter().filter(|transaction| transaction.error.is_some()).count();
+    stats.timed_out_requests = data.transactions.iter().filter(|transaction| transaction.timed_out).count();
+    if stats.requests > 0 {
+      let total_latency: u64 = data.transactions.iter().map(|transaction| transaction.latency_ms).sum();
+      stats.average_latency_ms = Some(total_latency / stats.requests as u64);
+    }
+    let rates = data.transactions.iter().filter_map(Transaction::tokens_per_second).collect::<Vec<f64>>();
+    if !rates.is_empty() {
+      stats.average_tokens_per_second = Some(rates.iter().sum::<f64>() / rates.len() as f64);
+    }
+    stats.api_status = ApiStatus::new(&data.transactions);
+    stats
+  }
+}
+
+fn counts(counts: &BTreeMap<impl fmt::Display, usize>) -> String {
+  match counts.is_empty() {
+    true => "none".to_string(),
+    false => counts.iter().map(|(name, count)| format!("{} {}", name, count)).collect::<Vec<String>>().join(", "),
+  }
+}
+
+impl fmt::Display for SessionStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "messages: {}", counts(&self.messages))?;
+    writeln!(f, "requests: {}, {} failed, {} timed out", self.requests, self.failed_requests, self.timed_out_requests)?;
+    writeln!(f, "tokens: {} prompt, {} completion", self.prompt_tokens, self.completion_tokens)?;
+    match self.average_latency_ms {
+      Some(latency) => writeln!(f, "average latency: {} ms", latency)?,
+      None => writeln!(f, "average latency: n/a")?,
+    }
+    if let Some(rate) = self.average_tokens_per_second {
+      writeln!(f, "average streaming rate: {:.1} tokens/s", rate)?;
+    }
+    if let Some(status) = &self.api_status {
+      writeln!(f, "api health: {}, last request took {} ms", status.health, status.last_latency_ms)?;
+    }
+    writeln!(f, "cost: ${:.4}", self.cost)?;
+    writeln!(f, "models: {}", counts(&self.models))?;
+    write!(f, "tool calls: {}", counts(&self.tool_calls))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage, ChatCompletionRequestUserMessage,
+    ChatCompletionRequestUserMessageContent, ChatCompletionToolType, FunctionCall, Role,
+  };
+
+  use super::*;
+
+  fn transaction(model: &str, tokens: usize, latency_ms: u64, cost: Option<f64>) -> Transaction {
+    let model = model.to_string();
+    Transaction {
+      timestamp: 0,
+      model,
+      prompt_tokens: tokens,
+      completion_tokens: 10,
+      latency_ms,
+      first_token_ms: None,
+      cost,
+      error: None,
+      timed_out: false,
+      compared: Vec::new(),
+      raw: None,
+    }
+  }
+
+  #[test]
+  fn test_session_stats() {
+    let mut data = SessionData::default();
+    data.add_message(ChatMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text("what's in src?".to_string())),
+    }));
+    data.add_message(ChatMessage::Assistant(ChatCompletionRequestAssistantMessage {
+      role: Role::Assistant,
+      tool_calls: Some(vec![ChatCompletionMessageToolCall {
+        id: "a".to_string(),
+        r#type: ChatCompletionToolType::Function,
+        function: FunctionCall { name: "file_search".to_string(), arguments: "{}".to_string() },
+      }]),
+      ..Default::default()
+    }));
+    data.transactions.push(transaction("gpt-4", 100, 1000, Some(0.01)));
+    data.transactions.push(transaction("gpt-3.5-turbo", 200, 2000, None));
+
+    let stats = SessionStats::new(&data);
+    assert_eq!(stats.messages, BTreeMap::from([("assistant", 1), ("user", 1)]));
+    assert_eq!(stats.tool_calls, BTreeMap::from([("file_search".to_string(), 1)]));
+    assert_eq!((stats.requests, stats.prompt_tokens, stats.completion_tokens), (2, 300, 20));
+    assert_eq!(stats.average_latency_ms, Some(1500));
+    assert_eq!(stats.token_history, vec![110, 210]);
+    assert!((stats.cost - 0.01).abs() < f64::EPSILON);
+
+    // the tokens and cost of the second model of an a/b comparison are added
+    let mut compared = transaction("gpt-4", 100, 1000, Some(0.01));
+    let alternative = transaction("claude-2", 50, 800, Some(0.02));
+    compared.compared = vec![
+      ComparedResponse::new(&compared, "first".to_string()),
+      ComparedResponse::new(&alternative, "second".to_string()),
+    ];
+    data.transactions = vec![compared];
+    let stats = SessionStats::new(&data);
+    assert_eq!(stats.models, BTreeMap::from([("claude-2".to_string(), 1), ("gpt-4".to_string(), 1)]));
+    assert_eq!((stats.prompt_tokens, stats.completion_tokens), (150, 20));
+    assert!((stats.cost - 0.03).abs() < 1e-9);
+  }
+
+  #[test]
+  fn test_api_status() {
+    assert_eq!(ApiStatus::new(&[]), None);
+    let mut streamed = transaction("gpt-4", 100, 2000, None);
+    streamed.first_token_ms = Some(1000);
+    let status = ApiStatus::new(&[streamed.clone()]).unwrap();
+    assert_eq!((status.health, status.tokens_per_second), (ApiHealth::Healthy, Some(10.0)));
+
+    let request = CreateChatCompletionRequest { model: "gpt-4".to_string(), ..Default::default() };
+    let failed = Transaction::failed(&request, 0, Duration::from_secs(1), "502 bad gateway".to_string());
+    assert_eq!(ApiStatus::new(&[streamed.clone(), failed.clone()]).unwrap().health, ApiHealth::Down);
+    assert_eq!(ApiStatus::new(&[failed, streamed.clone()]).unwrap().health, ApiHealth::Degraded);
+    let timed_out = Transaction { timed_out: true, ..streamed.clone() };
+    assert_eq!(ApiStatus::new(&[timed_out, streamed]).unwrap().health, ApiHealth::Degraded);
+  }
+}
@@ -16,8 +16,13 @@ use crate::action::Action;
 use crate::trace_dbg;
 use tui_textarea::{CursorMove, Input, Key, Scrolling, TextArea};
 
+use super::compression::count_message_tokens;
 use super::errors::SazidError;
-use super::{messages::MessageContainer, session_data::SessionData};
+use super::theme::{self, paint, Theme, ThemeColor};
+use super::{
+  messages::{fold_code_blocks, Fold, MessageContainer},
+  session_data::SessionData,
+};
 use ropey::Rope;
 
 #[derive(Default, Debug)]
@@ -30,30 +35,69 @@ pub struct SessionView<'a> {
   pub selected_text: Option<String>,
   pub new_data: bool,
   pub rendered_text: Rope,
+  // one line per message, except the messages expanded with enter
+  pub compact: bool,
 }
 
 impl<'a> SessionView<'a> {
   pub fn unfocus_textarea(&mut self) {
-    use ratatui::style::{Color, Style};
+    use ratatui::style::Style;
     self.text_area.set_cursor_line_style(Style::default());
     self.text_area.set_cursor_style(Style::default());
     self.text_area.set_block(
-      Block::default()
-        .borders(Borders::ALL)
-        .style(Style::default().fg(Color::DarkGray))
-        .title(" Inactive (^X to switch) "),
+      Block::default().borders(Borders::ALL).style(theme::current().hint()).title(" Inactive (^X to switch) "),
     );
   }
 
+  pub fn set_compact(&mut self, compact: bool, session_data: &mut SessionData) {
+    self.compact = compact;
+    session_data.messages.iter_mut().for_each(|message| message.expanded = false);
+    self.rerender(session_data);
+  }
+
+  // the index of the message rendered on a line of the transcript
+  pub fn message_at_line(&self, session_data: &SessionData, line: usize) -> Option<usize> {
+    let mut end = 0;
+    session_data.messages.iter().position(|message| {
+      end += message.stylized.len_lines().saturating_sub(1);
+      line < end
+    })
+  }
+
+  // the first line of a message in the transcript
+  pub fn message_start_line(&self, session_data: &SessionData, index: usize) -> usize {
+    session_data.messages.iter().take(index).map(|message| message.stylized.len_lines().saturating_sub(1)).sum()
+  }
+
+  // the markdown colors of the theme, false when bat has no such theme and keeps the colors it had
+  pub fn apply_theme(&mut self, theme: &Theme) -> bool {
+    self.renderer.set_theme(&theme.markdown)
+  }
+
+  // drops everything rendered so far and renders the messages again, after the transcript is replaced
+  pub fn rerender(&mut self, session_data: &mut SessionData) {
+    self.text_area.select_all();
+    self.text_area.cut();
+    self.rendered_text = Rope::new();
+    session_data.messages.iter_mut().for_each(|message| {
+      message.stylized = Rope::new();
+      message.stylize_complete = false;
+    });
+    self.post_process_new_messages(session_data);
+  }
+
   pub fn focus_textarea(&mut self) {
-    use ratatui::style::{Color, Style};
+    use ratatui::style::Style;
+    let theme = theme::current();
     self.text_area.move_cursor(CursorMove::Top);
     self.text_area.move_cursor(CursorMove::Head);
     self
       .text_area
       .set_cursor_line_style(Style::default().add_modifier(Modifier::UNDERLINED).add_modifier(Modifier::SLOW_BLINK));
-    self.text_area.set_cursor_style(Style::default().bg(Color::Yellow));
-    self.text_area.set_block(Block::default().borders(Borders::ALL).style(Style::default()).title(" Active "));
+    self.text_area.set_cursor_style(Style::default().bg(theme.border_focused.0));
+    self
+      .text_area
+      .set_block(Block::default().borders(Borders::ALL).border_style(theme.border_focused()).title(" Active "));
   }
 
   pub fn set_window_width(&mut self, width: usize, _messages: &mut [MessageContainer]) {
@@ -95,7 +139,6 @@ impl<'a> SessionView<'a> {
   }
 
   pub fn post_process_new_messages(&mut self, session_data: &mut SessionData) {
-    let dividing_newlines_count = 2;
     session_data.messages.iter_mut().for_each(|message| {
       let rendered_text_message_start_index = self.rendered_text.len_chars() - message.stylized.len_chars();
       let original_message_length = message.stylized.len_chars();
@@ -105,20 +148,47 @@ impl<'a> SessionView<'a> {
         let text_width = self.window_width.min(80);
         let left_padding = self.window_width.saturating_sub(text_width) / 2;
         trace_dbg!("left_padding: {}\ttext_width: {}, window_width: {}", left_padding, text_width, self.window_width);
-        let stylized = self.renderer.render_message_bat(format!("{}", &message).as_str());
+        let folded = message.fold == Fold::Message;
+        let collapsed = (self.compact && !message.expanded) || folded;
+        let theme = theme::current();
+        let blocks = theme.blocks && !collapsed;
+        if blocks && message.receive_complete && message.token_usage == 0 {
+          message.token_usage = count_message_tokens(std::slice::from_ref(&message.message));
+        }
+        let stylized = match (collapsed, folded) {
+          (true, true) => message.fold_preview(text_width - 10),
+          (true, false) => message.preview(text_width - 10),
+          (false, _) => {
+            let text = if blocks { message.body() } else { message.to_string() };
+            match message.fold {
+              Fold::CodeBlocks => self.renderer.render_message_bat(&fold_code_blocks(&text)),
+              _ => self.renderer.render_message_bat(&text),
+            }
+          },
+        };
+        let dividing_newlines_count = if collapsed { 1 } else { 2 };
         let options = Options::new(text_width-10)
           //.break_words(false)
           .word_splitter(WordSplitter::NoHyphenation)
           .word_separator(WordSeparator::AsciiSpace)
         .wrap_algorithm(WrapAlgorithm::new_optimal_fit());
         let wrapped = textwrap::wrap(stylized.as_str(), options);
+        let wrapped = match blocks {
+          true => message_block(
+            &paint(message.role_color(&theme), &message.block_title()),
+            &wrapped,
+            text_width - 10,
+            theme.border,
+          ),
+          false => wrapped.iter().map(|l| l.to_string()).collect(),
+        };
 
         message.stylized = Rope::from_str(
           wrapped
             .iter()
             .enumerate()
             .map(|(i, l)| {
-              if i == 0 {
+              if i == 0 || blocks {
                 format!("{}{}", " ".repeat(left_padding + 2), l)
               } else {
                 format!("{}{}", " ".repeat(left_padding + 4), l)
@@ -153,6 +223,23 @@ impl<'a> SessionView<'a> {
   }
 }
 
+// frames the lines of a message in a rounded border of the given inner width, with the title in the top border
+fn message_block(title: &str, lines: &[std::borrow::Cow<'_, str>], width: usize, border: ThemeColor) -> Vec<String> {
+  let title_width = textwrap::core::display_width(title);
+  let mut block = vec![format!(
+    "{}{}{}",
+    paint(border, "╭─ "),
+    title,
+    paint(border, &format!(" {}╮", "─".repeat(width.saturating_sub(title_width + 1))))
+  )];
+  block.extend(lines.iter().map(|line| {
+    let padding = " ".repeat(width.saturating_sub(textwrap::core::display_width(line)));
+    format!("{} {}{} {}", paint(border, "│"), line, padding, paint(border, "│"))
+  }));
+  block.push(paint(border, &format!("╰{}╯", "─".repeat(width + 2))));
+  block
+}
+
 pub struct BatRenderer<'a> {
   assets: HighlightingAssets,
   config: Config<'a>,
@@ -202,6 +289,17 @@ impl<'a> BatRenderer<'a> {
     BatRenderer { config, assets }
   }
 
+  fn set_theme(&mut self, name: &str) -> bool {
+    // bat warns on stderr about unknown themes, which would garble the tui
+    match self.assets.themes().any(|theme| theme == name) {
+      true => {
+        self.config.theme = name.to_string();
+        true
+      },
+      false => false,
+    }
+  }
+
   fn render_error(err: &bat::error::Error, _write: &mut dyn std::io::Write) {
     trace_dbg!("bat rendering error: {}", err);
   }
@@ -0,0 +1,184 @@
+use std::{
+  io::IsTerminal,
+  path::{Path, PathBuf},
+};
+
+use dirs_next::home_dir;
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::{
+  components::setup_form::{SetupForm, SetupOutcome},
+  config::CONFIG,
+  tui::{Event, Tui},
+};
+
+use super::{
+  consts::DATA_DIR,
+  credentials::{load_api_key, store_api_key},
+  errors::SazidError,
+  providers::Provider,
+};
+
+// written by the setup wizard, the other names are the formats the config is also read from
+pub const CONFIG_FILE: &str = "config.json5";
+const CONFIG_FILES: [&str; 5] = ["config.json5", "config.json", "config.yaml", "config.toml", "config.ini"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupAnswers {
+  pub provider: Provider,
+  pub model: String,
+  // None keeps the default data directory
+  pub data_dir: Option<PathBuf>,
+}
+
+pub fn has_config(config_dir: &Path) -> bool {
+  CONFIG_FILES.iter().any(|file| config_dir.join(file).exists())
+}
+
+// the first run asks for setup when there is no config to read and someone at the terminal to answer
+pub fn should_run_setup(config_dir: &Path) -> bool {
+  !has_config(config_dir) && std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+// shows the setup form for the provider, its api key, the default model and the data directory, stores the key in
+// the system keyring and writes the rest to the config file
+pub async fn run_setup(config_dir: &Path) -> Result<PathBuf, SazidError> {
+  let config_path = config_dir.join(CONFIG_FILE);
+  let default_data_dir = home_dir().map(|home_dir| home_dir.join(DATA_DIR)).unwrap_or_else(|| PathBuf::from(DATA_DIR));
+  let mut form = SetupForm::new(default_data_dir, |provider| load_api_key(provider).is_some());
+  let outcome = run_form(&mut form).await.map_err(|e| SazidError::Other(format!("setup failed: {}", e)))?;
+  if outcome == SetupOutcome::Cancelled {
+    return Err(SazidError::Other("setup was cancelled, nothing was written".to_string()));
+  }
+  let (answers, api_key) = form.answers();
+  if let (Some(api_key), Some(env)) = (api_key, answers.provider.api_key_env()) {
+    match store_api_key(answers.provider, &api_key) {
+      Ok(()) => println!("The API key is stored in the system keyring"),
+      Err(e) => println!("{}, set {} instead", e, env),
+    }
+  }
+  write_config(config_dir, &answers)?;
+  Ok(config_path)
+}
+
+async fn run_form(form: &mut SetupForm) -> color_eyre::eyre::Result<SetupOutcome> {
+  let mut tui = Tui::new()?;
+  tui.paste(true);
+  tui.enter()?;
+  let outcome = loop {
+    match tui.next().await {
+      Some(Event::Render) | Some(Event::Resize(_, _)) => {
+        tui.draw(|f| form.draw(f, f.size()))?;
+      },
+      Some(Event::Paste(text)) => form.paste(&text),
+      Some(Event::Key(key)) => {
+        if let Some(outcome) = form.handle_key(key) {
+          break outcome;
+        }
+      },
+      Some(_) => {},
+      None => break SetupOutcome::Cancelled,
+    }
+  };
+  tui.exit()?;
+  Ok(outcome)
+}
+
+// an existing config file keeps everything but the answered settings, otherwise the default config is the template
+pub fn write_config(config_dir: &Path, answers: &SetupAnswers) -> Result<(), SazidError> {
+  let config_path = config_dir.join(CONFIG_FILE);
+  let template = match config_path.exists() {
+    true => std::fs::read_to_string(&config_path)?,
+    false => CONFIG.to_string(),
+  };
+  std::fs::create_dir_all(config_dir)?;
+  let tmp_path = config_path.with_extension("json5.tmp");
+  std::fs::write(&tmp_path, render_config(&template, answers))?;
+  std::fs::rename(tmp_path, config_path)?;
+  Ok(())
+}
+
+// replaces the top level provider, model and data_dir settings in a json5 config, adding the missing ones
+pub fn render_config(template: &str, answers: &SetupAnswers) -> String {
+  let settings = [
+    ("provider", serde_json::to_value(answers.provider).unwrap_or(Value::Null)),
+    ("model", json!(answers.model)),
+    ("data_dir", json!(answers.data_dir)),
+  ];
+  settings.into_iter().fold(template.to_string(), |config, (key, value)| {
+    let setting = Regex::new(&format!(r#"(?m)^( {{0,2}})"{}"\s*:.*?(,?)[ \t]*$"#, key)).unwrap();
+    match setting.is_match(&config) {
+      true => setting.replace(&config, format!(r#"${{1}}"{}": {}${{2}}"#, key, value).as_str()).to_string(),
+      false => config.replacen('{', &format!("{{\n  \"{}\": {},", key, value), 1),
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+  use super::*;
+  use crate::{app::providers::OPENROUTER_AUTO_MODEL, components::setup_form::SetupField, config::Config};
+
+  #[test]
+  fn test_render_config() {
+    let answers = SetupAnswers {
+      provider: Provider::OpenRouter,
+      model: "anthropic/claude-2".to_string(),
+      data_dir: Some(PathBuf::from("/srv/sazid")),
+    };
+    let config: Config = json5::from_str(&render_config(CONFIG, &answers)).unwrap();
+    assert_eq!(config.provider, Provider::OpenRouter);
+    assert_eq!(config.model.as_deref(), Some("anthropic/claude-2"));
+    assert_eq!(config.data_dir, Some(PathBuf::from("/srv/sazid")));
+    assert!(!config.keybindings.is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(CONFIG_FILE), "{ \"offline\": true }").unwrap();
+    assert!(has_config(dir.path()));
+    write_config(dir.path(), &SetupAnswers { data_dir: None, ..answers }).unwrap();
+    let config: Config = json5::from_str(&std::fs::read_to_string(dir.path().join(CONFIG_FILE)).unwrap()).unwrap();
+    assert!(config.offline);
+    assert_eq!(config.model.as_deref(), Some("anthropic/claude-2"));
+    assert_eq!(config.data_dir, None);
+  }
+
+  #[test]
+  fn test_setup_form() {
+    let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+    let mut form = SetupForm::new(PathBuf::from("/home/me/data"), |provider| provider == Provider::OpenRouter);
+    // openai has no key yet, so the form can't be saved without one
+    for _ in 0..3 {
+      assert_eq!(form.handle_key(key(KeyCode::Tab)), None);
+    }
+    assert_eq!(form.handle_key(key(KeyCode::Enter)), None);
+    assert_eq!(form.focus, SetupField::ApiKey);
+    assert!(form.error.is_some());
+
+    // openrouter already has a key, and the model follows the provider
+    form.handle_key(key(KeyCode::BackTab));
+    form.handle_key(key(KeyCode::Char('l')));
+    assert_eq!(form.provider(), Provider::OpenRouter);
+    assert_eq!(form.model, OPENROUTER_AUTO_MODEL);
+    for _ in 0..3 {
+      assert_eq!(form.handle_key(key(KeyCode::Tab)), None);
+    }
+    form.data_dir.clear();
+    form.paste("/srv/sazid");
+    assert_eq!(form.handle_key(key(KeyCode::Enter)), Some(SetupOutcome::Submitted));
+    let (answers, api_key) = form.answers();
+    assert_eq!(answers.provider, Provider::OpenRouter);
+    assert_eq!(answers.data_dir, Some(PathBuf::from("/srv/sazid")));
+    assert_eq!(api_key, None);
+
+    // the local server needs no key, so its field is skipped
+    let mut form = SetupForm::new(PathBuf::from("/home/me/data"), |_| false);
+    form.handle_key(key(KeyCode::Right));
+    form.handle_key(key(KeyCode::Right));
+    form.handle_key(key(KeyCode::Tab));
+    assert_eq!(form.focus, SetupField::Model);
+    assert_eq!(form.handle_key(key(KeyCode::Esc)), Some(SetupOutcome::Cancelled));
+  }
+}
@@ -0,0 +1,341 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use async_openai::{
+  config::OpenAIConfig,
+  types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage,
+    ChatCompletionRequestUserMessageContent, CreateChatCompletionRequest, Role,
+  },
+  Client,
+};
+
+use walkdir::WalkDir;
+
+use crate::{components::session::create_openai_client, config::Config};
+
+use super::{
+  consts::CHUNK_TOKEN_LIMIT,
+  embeddings::{embeddings_models::EmbeddingModel, EmbeddingsManager},
+  errors::SazidError,
+  functions::argument_validation::count_tokens,
+  model_list::ModelPricing,
+  tools::chunkifier::chunkify_text,
+  types::Model,
+};
+
+const SUMMARY_MAX_TOKENS: u16 = 1024;
+
+// directories that hold build output or dependencies rather than the project itself
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "dist", "build", "vendor"];
+pub const MAX_FILE_BYTES: u64 = 512 * 1024;
+
+const SUMMARIZE_MAP_PROMPT: &str = "Summarize this text. Keep names, numbers, decisions and open questions exact, \
+and skip boilerplate.";
+
+const SUMMARIZE_REDUCE_PROMPT: &str = "Merge these partial summaries of the same source into a single summary in \
+markdown. Remove duplicates, keep names, numbers, decisions and open questions exact, and keep it terse.";
+
+// a named piece of text to summarize, such as a file and its contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+  pub name: String,
+  pub content: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SummaryStage {
+  Map,
+  // the round of reduction, starting at 1
+  Reduce(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SummaryProgress {
+  pub stage: SummaryStage,
+  pub completed: usize,
+  pub total: usize,
+}
+
+impl fmt::Display for SummaryProgress {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.stage {
+      SummaryStage::Map => write!(f, "summarizing chunk {} of {}", self.completed, self.total),
+      SummaryStage::Reduce(round) => {
+        write!(f, "merging summaries {} of {} (round {})", self.completed, self.total, round)
+      },
+    }
+  }
+}
+
+// the requests a map_reduce run will make, assuming every summary uses all of its max tokens
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryEstimate {
+  pub chunks: usize,
+  pub requests: usize,
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+  // dollars, None when the model has no configured pricing
+  pub cost: Option<f64>,
+}
+
+impl fmt::Display for SummaryEstimate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} chunks, {} requests, up to {} prompt and {} completion tokens",
+      self.chunks, self.requests, self.prompt_tokens, self.completion_tokens
+    )?;
+    match self.cost {
+      Some(cost) => write!(f, ", up to ${:.2}", cost),
+      None => Ok(()),
+    }
+  }
+}
+
+// reads the text files under path, skipping hidden directories, build output and binary files
+pub fn collect_documents(path: &Path) -> Vec<Document> {
+  WalkDir::new(path)
+    .sort_by_file_name()
+    .into_iter()
+    .filter_entry(|entry| {
+      let name = entry.file_name().to_string_lossy();
+      entry.depth() == 0 || !(name.starts_with('.') || (entry.file_type().is_dir() && SKIPPED_DIRS.contains(&&*name)))
+    })
+    .flatten()
+    .filter(|entry| entry.file_type().is_file())
+    .filter(|entry| entry.metadata().map(|m| m.len() <= MAX_FILE_BYTES).unwrap_or(false))
+    .filter_map(|entry| {
+      let content = std::fs::read_to_string(entry.path()).ok()?;
+      let name = match entry.depth() {
+        0 => entry.file_name().to_string_lossy().to_string(),
+        _ => entry.path().strip_prefix(path).unwrap_or(entry.path()).to_string_lossy().to_string(),
+      };
+      Some(Document { name, content })
+    })
+    .collect()
+}
+
+// whether a path under root is in a hidden or build directory, and so would be skipped by collect_documents
+pub fn is_skipped_path(root: &Path, path: &Path) -> bool {
+  let relative = path.strip_prefix(root).unwrap_or(path);
+  let components = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect::<Vec<_>>();
+  components.iter().enumerate().any(|(i, name)| {
+    name.starts_with('.') || (i + 1 < components.len() && SKIPPED_DIRS.contains(&name.as_str()))
+  })
+}
+
+// the pages of an ingested collection, with the pages of each file joined back together
+async fn collection_documents(config: &Config, collection: &str) -> Result<Vec<Document>, SazidError> {
+  let model = EmbeddingModel::Ada002(config.session_config.openai_config.clone());
+  let mut manager = EmbeddingsManager::init(config.clone(), model).await?;
+  let mut documents: Vec<Document> = vec![];
+  for page in manager.get_collection_pages(collection).await? {
+    match documents.last_mut() {
+      Some(document) if document.name == page.filepath => document.content.push_str(&page.content),
+      _ => documents.push(Document { name: page.filepath, content: page.content }),
+    }
+  }
+  Ok(documents)
+}
+
+// a file or directory when source is a path, otherwise the ingested collection with that name
+pub async fn source_documents(config: &Config, source: &str) -> Result<Vec<Document>, SazidError> {
+  let documents = match Path::new(source).exists() {
+    true => collect_documents(Path::new(source)),
+    false => collection_documents(config, source).await?,
+  };
+  match documents.is_empty() {
+    true => Err(SazidError::Other(format!("no documents found in {}", source))),
+    false => Ok(documents),
+  }
+}
+
+// splits each document into chunks small enough to summarize in a single request, prefixed with the document name
+pub fn document_chunks(documents: &[Document], tokens_per_chunk: usize) -> Vec<String> {
+  documents
+    .iter()
+    .flat_map(|document| {
+      let chunks = chunkify_text(&document.content, tokens_per_chunk);
+      let chunk_count = chunks.len();
+      chunks.into_iter().enumerate().map(move |(i, chunk)| match chunk_count {
+        1 => format!("{}:\n{}", document.name, chunk),
+        _ => format!("{} (part {} of {}):\n{}", document.name, i + 1, chunk_count, chunk),
+      })
+    })
+    .collect()
+}
+
+// groups summaries so that each group fits in a single reduce request
+// every group takes at least two summaries, so that each round of reduction makes progress
+fn group_by_tokens(summaries: Vec<String>, max_tokens: usize) -> Vec<Vec<String>> {
+  let mut groups: Vec<Vec<String>> = vec![];
+  let mut group_tokens = 0;
+  for summary in summaries {
+    let tokens = count_tokens(&summary);
+    match groups.last_mut() {
+      Some(group) if group.len() < 2 || group_tokens + tokens <= max_tokens => group.push(summary),
+      _ => {
+        groups.push(vec![summary]);
+        group_tokens = 0;
+      },
+    }
+    group_tokens += tokens;
+  }
+  groups
+}
+
+fn reduce_budget(model: &Model) -> usize {
+  (model.token_limit as usize / 2).saturating_sub(SUMMARY_MAX_TOKENS as usize)
+}
+
+pub fn estimate_map_reduce(
+  model: &Model,
+  documents: &[Document],
+  map_prompt: &str,
+  reduce_prompt: &str,
+  pricing: &HashMap<String, ModelPricing>,
+) -> SummaryEstimate {
+  let chunks = document_chunks(documents, CHUNK_TOKEN_LIMIT as usize);
+  let summary_tokens = SUMMARY_MAX_TOKENS as usize;
+  let mut requests = chunks.len();
+  let mut prompt_tokens =
+    chunks.iter().map(|chunk| count_tokens(chunk)).sum::<usize>() + chunks.len() * count_tokens(map_prompt);
+  // mirrors group_by_tokens with every summary at its maximum size
+  let summaries_per_group = (reduce_budget(model) / summary_tokens).max(2);
+  let mut summaries = chunks.len();
+  loop {
+    let groups = ((summaries + summaries_per_group - 1) / summaries_per_group).max(1);
+    requests += groups;
+    prompt_tokens += summaries * summary_tokens + groups * count_tokens(reduce_prompt);
+    if groups <= 1 {
+      break;
+    }
+    summaries = groups;
+  }
+  let completion_tokens = requests * summary_tokens;
+  let unqualified_model = model.name.rsplit('/').next().unwrap_or(&model.name);
+  let cost = pricing
+    .get(&model.name)
+    .or_else(|| pricing.get(unqualified_model))
+    .map(|pricing| (prompt_tokens as f64 * pricing.prompt + completion_tokens as f64 * pricing.completion) / 1000.0);
+  SummaryEstimate { chunks: chunks.len(), requests, prompt_tokens, completion_tokens, cost }
+}
+
+pub async fn complete(
+  client: &Client<OpenAIConfig>,
+  model: &Model,
+  instructions: &str,
+  content: &str,
+) -> Result<String, SazidError> {
+  let request = CreateChatCompletionRequest {
+    model: model.name.clone(),
+    messages: vec![
+      ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+        content: Some(instructions.to_string()),
+        ..Default::default()
+      }),
+      ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text(content.to_string())),
+      }),
+    ],
+    max_tokens: Some(SUMMARY_MAX_TOKENS),
+    ..Default::default()
+  };
+  let response = client.chat().create(request).await?;
+  Ok(response.choices.into_iter().next().and_then(|choice| choice.message.content).unwrap_or_default())
+}
+
+// summarizes each chunk with map_prompt, then merges the partial summaries with reduce_prompt
+// partial summaries that don't fit in a single request are merged in groups until they do
+pub async fn map_reduce(
+  client: &Client<OpenAIConfig>,
+  model: &Model,
+  documents: &[Document],
+  map_prompt: &str,
+  reduce_prompt: &str,
+  on_progress: impl Fn(SummaryProgress),
+) -> Result<String, SazidError> {
+  let chunks = document_chunks(documents, CHUNK_TOKEN_LIMIT as usize);
+  let mut summaries = vec![];
+  for (i, chunk) in chunks.iter().enumerate() {
+    on_progress(SummaryProgress { stage: SummaryStage::Map, completed: i + 1, total: chunks.len() });
+    summaries.push(complete(client, model, map_prompt, chunk).await?);
+  }
+  let mut round = 1;
+  loop {
+    let groups = group_by_tokens(summaries, reduce_budget(model));
+    let mut merged = vec![];
+    for (i, group) in groups.iter().enumerate() {
+      on_progress(SummaryProgress { stage: SummaryStage::Reduce(round), completed: i + 1, total: groups.len() });
+      merged.push(complete(client, model, reduce_prompt, &group.join("\n\n")).await?);
+    }
+    if merged.len() <= 1 {
+      return Ok(merged.pop().unwrap_or_default());
+    }
+    summaries = merged;
+    round += 1;
+  }
+}
+
+// summarizes a file, directory or ingested collection with the configured model
+// on_estimate is called once the documents are read and before any request is made
+pub async fn summarize_source(
+  config: &Config,
+  source: &str,
+  on_estimate: impl Fn(&SummaryEstimate),
+  on_progress: impl Fn(SummaryProgress),
+) -> Result<String, SazidError> {
+  let documents = source_documents(config, source).await?;
+  let model = &config.session_config.model;
+  let estimate =
+    estimate_map_reduce(model, &documents, SUMMARIZE_MAP_PROMPT, SUMMARIZE_REDUCE_PROMPT, &config.model_pricing);
+  on_estimate(&estimate);
+  let client = create_openai_client(&config.session_config.openai_config);
+  map_reduce(&client, model, &documents, SUMMARIZE_MAP_PROMPT, SUMMARIZE_REDUCE_PROMPT, on_progress).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_document_chunks_and_groups() {
+    let documents = vec![
+      Document { name: "a.rs".to_string(), content: "fn a() {}".to_string() },
+      Document { name: "b.rs".to_string(), content: "word ".repeat(300) },
+    ];
+    let chunks = document_chunks(&documents, 100);
+    assert_eq!(chunks[0], "a.rs:\nfn a() {}");
+    assert!(chunks[1].starts_with("b.rs (part 1 of 3):"));
+    let groups = group_by_tokens(chunks, 150);
+    assert_eq!(groups.iter().map(|g| g.len()).collect::<Vec<usize>>(), vec![2, 2]);
+  }
+
+  #[test]
+  fn test_estimate_map_reduce() {
+    let model = Model::from_name("gpt-4");
+    let documents = vec![Document { name: "notes.md".to_string(), content: "word ".repeat(100) }];
+    let pricing = HashMap::from([("gpt-4".to_string(), ModelPricing { prompt: 0.03, completion: 0.06 })]);
+    let estimate = estimate_map_reduce(&model, &documents, "map", "reduce", &pricing);
+    assert_eq!((estimate.chunks, estimate.requests), (1, 2));
+    assert_eq!(estimate.completion_tokens, 2 * SUMMARY_MAX_TOKENS as usize);
+    assert!(estimate.cost.unwrap() > 0.12);
+    assert_eq!(estimate_map_reduce(&model, &documents, "map", "reduce", &HashMap::new()).cost, None);
+  }
+
+  #[test]
+  fn test_collect_documents_skips_hidden_and_build_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("src")).unwrap();
+    std::fs::create_dir_all(dir.path().join("target")).unwrap();
+    std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+    std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+    std::fs::write(dir.path().join("target/out.rs"), "generated").unwrap();
+    std::fs::write(dir.path().join(".git/HEAD"), "ref").unwrap();
+    std::fs::write(dir.path().join("logo.png"), [0xff, 0xfe, 0x00, 0x80]).unwrap();
+    let documents = collect_documents(dir.path());
+    assert_eq!(documents.len(), 1);
+    assert_eq!(documents[0].name, "src/main.rs");
+  }
+}
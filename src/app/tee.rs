@@ -0,0 +1,124 @@
+use std::{
+  fs::{File, OpenOptions},
+  io::Write,
+  path::PathBuf,
+};
+
+use async_openai::types::{ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+  consts::{data_path, TEE_DIR},
+  errors::SazidError,
+  messages::ChatMessage,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TeeConfig {
+  pub enabled: bool,
+  // the prompts are written before each response
+  pub prompts: bool,
+  // None for the tee directory under the data directory
+  pub dir: Option<PathBuf>,
+}
+
+impl Default for TeeConfig {
+  fn default() -> Self {
+    TeeConfig { enabled: false, prompts: false, dir: None }
+  }
+}
+
+// appends the responses of a session to <dir>/<session_id>.md as they stream, in plain text even when sessions are
+// encrypted, so that they can be followed with tail -f or kept after the session is deleted
+#[derive(Debug)]
+pub struct Tee {
+  pub session_id: String,
+  pub path: PathBuf,
+  file: File,
+  // the id of the response being written, a chunk with another id starts a new response
+  response_id: Option<String>,
+}
+
+impl Tee {
+  pub fn open(config: &TeeConfig, session_id: &str) -> Result<Self, SazidError> {
+    let dir = match &config.dir {
+      Some(dir) => dir.clone(),
+      None => data_path(TEE_DIR).ok_or_else(|| SazidError::Other("no home directory for the tee file".to_string()))?,
+    };
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.md", session_id));
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    Ok(Tee { session_id: session_id.to_string(), path, file, response_id: None })
+  }
+
+  // writes what a message adds to the transcript, prompts only when include_prompts is set
+  pub fn write_message(&mut self, message: &ChatMessage, include_prompts: bool) -> Result<(), SazidError> {
+    match message {
+      ChatMessage::User(message) if include_prompts => self.write_prompt(message),
+      ChatMessage::StreamResponse(responses) => {
+        for response in responses {
+          if self.response_id.as_ref() != Some(&response.id) {
+            self.end_response()?;
+            write!(self.file, "## Assistant ({})\n\n", response.model)?;
+            self.response_id = Some(response.id.clone());
+          }
+          for choice in response.choices.iter().filter(|choice| choice.index == 0) {
+            if let Some(content) = &choice.delta.content {
+              self.file.write_all(content.as_bytes())?;
+            }
+            if choice.finish_reason.is_some() {
+              self.end_response()?;
+            }
+          }
+        }
+        Ok(())
+      },
+      ChatMessage::Response(response) => {
+        self.end_response()?;
+        let content = response.choices.first().and_then(|choice| choice.message.content.clone()).unwrap_or_default();
+        write!(self.file, "## Assistant ({})\n\n{}\n\n", response.model, content)?;
+        Ok(())
+      },
+      _ => Ok(()),
+    }
+  }
+
+  fn write_prompt(&mut self, message: &ChatCompletionRequestUserMessage) -> Result<(), SazidError> {
+    if let Some(ChatCompletionRequestUserMessageContent::Text(text)) = &message.content {
+      self.end_response()?;
+      write!(self.file, "## You\n\n{}\n\n", text)?;
+    }
+    Ok(())
+  }
+
+  fn end_response(&mut self) -> Result<(), SazidError> {
+    if self.response_id.take().is_some() {
+      self.file.write_all(b"\n\n")?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use async_openai::types::Role;
+
+  #[test]
+  fn test_tee_prompts() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = TeeConfig { enabled: true, prompts: true, dir: Some(dir.path().to_path_buf()) };
+    let mut tee = Tee::open(&config, "session").unwrap();
+    let prompt = ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text("hello".to_string())),
+    };
+    tee.write_message(&ChatMessage::User(prompt.clone()), true).unwrap();
+    tee.write_message(&ChatMessage::User(prompt), false).unwrap();
+    let mut tee = Tee::open(&config, "session").unwrap();
+    let empty = ChatCompletionRequestUserMessage { role: Role::User, content: None };
+    tee.write_message(&ChatMessage::User(empty), true).unwrap();
+    assert_eq!(std::fs::read_to_string(dir.path().join("session.md")).unwrap(), "## You\n\nhello\n\n");
+  }
+}
@@ -0,0 +1,324 @@
+use std::{
+  path::{Path, PathBuf},
+  str::FromStr,
+  sync::RwLock,
+};
+
+use once_cell::sync::Lazy;
+use ratatui::style::{Color, Modifier, Style};
+use serde::{de::Deserializer, Deserialize};
+
+use super::errors::SazidError;
+
+pub const BUILTIN_THEMES: [&str; 3] = ["dark", "light", "solarized"];
+
+static CURRENT: Lazy<RwLock<Theme>> = Lazy::new(|| RwLock::new(Theme::default()));
+
+// a color name such as "lightblue" or "dark gray", a hex color such as "#268bd2", or an ansi color index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThemeColor(pub Color);
+
+impl<'de> Deserialize<'de> for ThemeColor {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let name = String::deserialize(deserializer)?;
+    Color::from_str(&name).map(ThemeColor).map_err(|_| serde::de::Error::custom(format!("invalid color {}", name)))
+  }
+}
+
+// the headers of each message in the transcript
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RoleColors {
+  pub user: ThemeColor,
+  pub assistant: ThemeColor,
+  pub system: ThemeColor,
+  pub tool: ThemeColor,
+  pub sources: ThemeColor,
+  // responses withheld by the content filter
+  pub refusal: ThemeColor,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StatusBarColors {
+  pub fg: ThemeColor,
+  pub bg: ThemeColor,
+  // the latest status message
+  pub message: ThemeColor,
+}
+
+// the selected entry of lists such as the model picker
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HighlightColors {
+  pub fg: ThemeColor,
+  pub bg: ThemeColor,
+}
+
+// the colors of the tui, a theme file only needs the colors that differ from the dark theme
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Theme {
+  pub name: String,
+  pub roles: RoleColors,
+  // popup borders, and the transcript border while it has focus
+  pub border: ThemeColor,
+  pub border_focused: ThemeColor,
+  pub highlight: HighlightColors,
+  pub status_bar: StatusBarColors,
+  // key hints, and the keys in them
+  pub hint: ThemeColor,
+  pub hint_key: ThemeColor,
+  // the bat syntax highlighting theme for markdown and code blocks in the transcript
+  pub markdown: String,
+  // each message is framed by a border with its role, time, model and token count in a header, instead of a
+  // colored role line
+  pub blocks: bool,
+}
+
+impl Default for Theme {
+  fn default() -> Self {
+    Theme::dark()
+  }
+}
+
+impl Default for RoleColors {
+  fn default() -> Self {
+    Theme::dark().roles
+  }
+}
+
+impl Default for StatusBarColors {
+  fn default() -> Self {
+    Theme::dark().status_bar
+  }
+}
+
+impl Default for HighlightColors {
+  fn default() -> Self {
+    Theme::dark().highlight
+  }
+}
+
+impl Default for ThemeColor {
+  fn default() -> Self {
+    ThemeColor(Color::Reset)
+  }
+}
+
+impl Theme {
+  pub fn dark() -> Self {
+    Theme {
+      name: "dark".to_string(),
+      roles: RoleColors {
+        user: ThemeColor(Color::LightBlue),
+        assistant: ThemeColor(Color::LightYellow),
+        system: ThemeColor(Color::LightMagenta),
+        tool: ThemeColor(Color::LightGreen),
+        sources: ThemeColor(Color::LightCyan),
+        refusal: ThemeColor(Color::LightRed),
+      },
+      border: ThemeColor(Color::Cyan),
+      border_focused: ThemeColor(Color::Yellow),
+      highlight: HighlightColors { fg: ThemeColor(Color::Black), bg: ThemeColor(Color::LightCyan) },
+      status_bar: StatusBarColors {
+        fg: ThemeColor(Color::Reset),
+        bg: ThemeColor(Color::Reset),
+        message: ThemeColor(Color::Yellow),
+      },
+      hint: ThemeColor(Color::DarkGray),
+      hint_key: ThemeColor(Color::Gray),
+      markdown: "Monokai Extended".to_string(),
+      blocks: true,
+    }
+  }
+
+  pub fn light() -> Self {
+    Theme {
+      name: "light".to_string(),
+      roles: RoleColors {
+        user: ThemeColor(Color::Blue),
+        assistant: ThemeColor(Color::Rgb(0x9a, 0x6a, 0x00)),
+        system: ThemeColor(Color::Magenta),
+        tool: ThemeColor(Color::Green),
+        sources: ThemeColor(Color::Cyan),
+        refusal: ThemeColor(Color::Red),
+      },
+      border: ThemeColor(Color::Blue),
+      border_focused: ThemeColor(Color::Magenta),
+      highlight: HighlightColors { fg: ThemeColor(Color::White), bg: ThemeColor(Color::Blue) },
+      status_bar: StatusBarColors {
+        fg: ThemeColor(Color::Black),
+        bg: ThemeColor(Color::Reset),
+        message: ThemeColor(Color::Red),
+      },
+      hint: ThemeColor(Color::Gray),
+      hint_key: ThemeColor(Color::DarkGray),
+      markdown: "Monokai Extended Light".to_string(),
+      blocks: true,
+    }
+  }
+
+  pub fn solarized() -> Self {
+    Theme {
+      name: "solarized".to_string(),
+      roles: RoleColors {
+        user: ThemeColor(Color::Rgb(0x26, 0x8b, 0xd2)),
+        assistant: ThemeColor(Color::Rgb(0xb5, 0x89, 0x00)),
+        system: ThemeColor(Color::Rgb(0x6c, 0x71, 0xc4)),
+        tool: ThemeColor(Color::Rgb(0x85, 0x99, 0x00)),
+        sources: ThemeColor(Color::Rgb(0x2a, 0xa1, 0x98)),
+        refusal: ThemeColor(Color::Rgb(0xdc, 0x32, 0x2f)),
+      },
+      border: ThemeColor(Color::Rgb(0x58, 0x6e, 0x75)),
+      border_focused: ThemeColor(Color::Rgb(0xb5, 0x89, 0x00)),
+      highlight: HighlightColors {
+        fg: ThemeColor(Color::Rgb(0xfd, 0xf6, 0xe3)),
+        bg: ThemeColor(Color::Rgb(0x26, 0x8b, 0xd2)),
+      },
+      status_bar: StatusBarColors {
+        fg: ThemeColor(Color::Rgb(0x93, 0xa1, 0xa1)),
+        bg: ThemeColor(Color::Rgb(0x07, 0x36, 0x42)),
+        message: ThemeColor(Color::Rgb(0xcb, 0x4b, 0x16)),
+      },
+      hint: ThemeColor(Color::Rgb(0x58, 0x6e, 0x75)),
+      hint_key: ThemeColor(Color::Rgb(0x93, 0xa1, 0xa1)),
+      markdown: "Solarized (dark)".to_string(),
+      blocks: true,
+    }
+  }
+
+  pub fn builtin(name: &str) -> Option<Self> {
+    match name {
+      "dark" => Some(Theme::dark()),
+      "light" => Some(Theme::light()),
+      "solarized" => Some(Theme::solarized()),
+      _ => None,
+    }
+  }
+
+  // a built in theme, or <themes_dir>/<name>.toml
+  pub fn load(name: &str, themes_dir: &Path) -> Result<Self, SazidError> {
+    if let Some(theme) = Theme::builtin(name) {
+      return Ok(theme);
+    }
+    let path = themes_dir.join(format!("{}.toml", name));
+    let contents = std::fs::read_to_string(&path)
+      .map_err(|e| SazidError::Other(format!("unknown theme {}, {}: {}", name, path.display(), e)))?;
+    let mut theme: Theme =
+      toml::from_str(&contents).map_err(|e| SazidError::Other(format!("invalid theme {}: {}", path.display(), e)))?;
+    theme.name = name.to_string();
+    Ok(theme)
+  }
+
+  // the built in themes and the theme files in themes_dir
+  pub fn available(themes_dir: &Path) -> Vec<String> {
+    let mut names = BUILTIN_THEMES.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+    if let Ok(entries) = std::fs::read_dir(themes_dir) {
+      let mut files = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "toml"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .filter(|name| !names.contains(name))
+        .collect::<Vec<_>>();
+      files.sort();
+      names.extend(files);
+    }
+    names
+  }
+
+  pub fn hint(&self) -> Style {
+    Style::default().fg(self.hint.0)
+  }
+
+  pub fn hint_key(&self) -> Style {
+    Style::default().add_modifier(Modifier::BOLD).fg(self.hint_key.0)
+  }
+
+  pub fn border(&self) -> Style {
+    Style::default().fg(self.border.0)
+  }
+
+  pub fn border_focused(&self) -> Style {
+    Style::default().fg(self.border_focused.0)
+  }
+
+  pub fn highlight(&self) -> Style {
+    Style::default().fg(self.highlight.fg.0).bg(self.highlight.bg.0)
+  }
+
+  pub fn status_bar(&self) -> Style {
+    Style::default().fg(self.status_bar.fg.0).bg(self.status_bar.bg.0)
+  }
+
+  pub fn status_message(&self) -> Style {
+    Style::default().fg(self.status_bar.message.0)
+  }
+}
+
+pub fn themes_dir() -> PathBuf {
+  crate::utils::get_config_dir().join("themes")
+}
+
+// the theme everything is drawn with, switched with the theme command
+pub fn current() -> Theme {
+  CURRENT.read().unwrap().clone()
+}
+
+pub fn set_current(theme: Theme) {
+  *CURRENT.write().unwrap() = theme;
+}
+
+// the text in the color, for the ansi text of the transcript
+pub fn paint(color: ThemeColor, text: &str) -> String {
+  use nu_ansi_term::Color as Ansi;
+  let ansi = match color.0 {
+    Color::Reset => return text.to_string(),
+    Color::Black => Ansi::Black,
+    Color::Red => Ansi::Red,
+    Color::Green => Ansi::Green,
+    Color::Yellow => Ansi::Yellow,
+    Color::Blue => Ansi::Blue,
+    Color::Magenta => Ansi::Purple,
+    Color::Cyan => Ansi::Cyan,
+    Color::Gray => Ansi::LightGray,
+    Color::DarkGray => Ansi::DarkGray,
+    Color::LightRed => Ansi::LightRed,
+    Color::LightGreen => Ansi::LightGreen,
+    Color::LightYellow => Ansi::LightYellow,
+    Color::LightBlue => Ansi::LightBlue,
+    Color::LightMagenta => Ansi::LightPurple,
+    Color::LightCyan => Ansi::LightCyan,
+    Color::White => Ansi::White,
+    Color::Rgb(r, g, b) => Ansi::Rgb(r, g, b),
+    Color::Indexed(i) => Ansi::Fixed(i),
+  };
+  ansi.paint(text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_load_theme() {
+    let dir = tempfile::tempdir().unwrap();
+    let theme_file = "markdown = \"GitHub\"\nborder = \"#ff8800\"\nblocks = false\n[roles]\nuser = \"lightred\"\n";
+    std::fs::write(dir.path().join("ember.toml"), theme_file).unwrap();
+    let theme = Theme::load("ember", dir.path()).unwrap();
+    assert_eq!(theme.name, "ember");
+    assert_eq!(theme.border, ThemeColor(Color::Rgb(0xff, 0x88, 0x00)));
+    assert_eq!(theme.roles.user, ThemeColor(Color::LightRed));
+    assert_eq!(theme.roles.assistant, Theme::dark().roles.assistant);
+    assert_eq!(theme.markdown, "GitHub");
+    assert!(!theme.blocks);
+
+    assert_eq!(Theme::load("solarized", dir.path()).unwrap(), Theme::solarized());
+    assert!(Theme::load("missing", dir.path()).is_err());
+    assert_eq!(Theme::available(dir.path()), vec!["dark", "light", "solarized", "ember"]);
+    assert_eq!(paint(ThemeColor(Color::LightBlue), "You:"), "\u{1b}[94mYou:\u{1b}[0m");
+  }
+}
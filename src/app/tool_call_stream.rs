@@ -0,0 +1,140 @@
+use async_openai::types::{
+  ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk, ChatCompletionToolType,
+  CreateChatCompletionStreamResponse, FunctionCall,
+};
+
+use super::errors::ParseError;
+
+// the tool calls of a streamed response, put together from their fragments as they arrive. each fragment carries the
+// index of its call, the first usually has the id and the name and the rest pieces of the arguments, but some
+// providers repeat the id and the name in every fragment or interleave the fragments of parallel calls
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolCallAssembler {
+  // by index, in the order the calls started
+  calls: Vec<(u32, ChatCompletionMessageToolCall)>,
+}
+
+impl ToolCallAssembler {
+  pub fn push(&mut self, chunk: &ChatCompletionMessageToolCallChunk) {
+    let position = match self.calls.iter().position(|(index, _)| *index == chunk.index) {
+      Some(position) => position,
+      None => {
+        let call = ChatCompletionMessageToolCall {
+          id: String::new(),
+          r#type: ChatCompletionToolType::Function,
+          function: FunctionCall { name: String::new(), arguments: String::new() },
+        };
+        self.calls.push((chunk.index, call));
+        self.calls.len() - 1
+      },
+    };
+    let call = &mut self.calls[position].1;
+    if let Some(id) = chunk.id.as_ref().filter(|_| call.id.is_empty()) {
+      call.id = id.clone();
+    }
+    if let Some(function) = &chunk.function {
+      if let Some(name) = function.name.as_ref().filter(|_| call.function.name.is_empty()) {
+        call.function.name = name.clone();
+      }
+      call.function.arguments += function.arguments.as_deref().unwrap_or_default();
+    }
+  }
+
+  // the fragments of the choice in a streamed response
+  pub fn push_response(&mut self, response: &CreateChatCompletionStreamResponse, choice_index: u32) {
+    response
+      .choices
+      .iter()
+      .filter(|choice| choice.index == choice_index)
+      .filter_map(|choice| choice.delta.tool_calls.as_ref())
+      .flatten()
+      .for_each(|chunk| self.push(chunk));
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.calls.is_empty()
+  }
+
+  // the call being received, for the status bar
+  pub fn describe(&self) -> Option<String> {
+    let (_, call) = self.calls.last()?;
+    Some(format!("receiving the {} call, {} bytes of arguments", call.function.name, call.function.arguments.len()))
+  }
+
+  // the calls as they are so far, arguments that haven't started are an empty object
+  pub fn tool_calls(&self) -> Option<Vec<ChatCompletionMessageToolCall>> {
+    let calls = self
+      .calls
+      .iter()
+      .map(|(_, call)| match call.function.arguments.trim().is_empty() {
+        true => ChatCompletionMessageToolCall {
+          function: FunctionCall { arguments: "{}".to_string(), ..call.function.clone() },
+          ..call.clone()
+        },
+        false => call.clone(),
+      })
+      .collect::<Vec<_>>();
+    (!calls.is_empty()).then_some(calls)
+  }
+
+  // the problems of the calls once the stream has ended, such as arguments cut off by the token limit
+  pub fn validate(&self) -> Vec<ParseError> {
+    self.tool_calls().unwrap_or_default().iter().filter_map(|call| validate_tool_call(call).err()).collect()
+  }
+}
+
+pub fn validate_tool_call(call: &ChatCompletionMessageToolCall) -> Result<(), ParseError> {
+  if call.id.is_empty() || call.function.name.is_empty() {
+    return Err(ParseError::new(&format!("a tool call is missing its id or name: {:?}", call)));
+  }
+  match serde_json::from_str::<serde_json::Value>(&call.function.arguments) {
+    Ok(serde_json::Value::Object(_)) => Ok(()),
+    Ok(_) => Err(ParseError::new(&format!("the arguments of the {} call aren't an object", call.function.name))),
+    Err(e) => {
+      Err(ParseError::new(&format!("the arguments of the {} call aren't valid json: {}", call.function.name, e)))
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::FunctionCallStream;
+
+  use super::*;
+
+  fn chunk(index: u32, id: Option<&str>, name: Option<&str>, arguments: &str) -> ChatCompletionMessageToolCallChunk {
+    ChatCompletionMessageToolCallChunk {
+      index,
+      id: id.map(str::to_string),
+      r#type: None,
+      function: Some(FunctionCallStream { name: name.map(str::to_string), arguments: Some(arguments.to_string()) }),
+    }
+  }
+
+  #[test]
+  fn test_assemble_tool_calls() {
+    let mut assembler = ToolCallAssembler::default();
+    assert!(assembler.tool_calls().is_none());
+    assembler.push(&chunk(0, Some("call_1"), Some("read_file"), ""));
+    assembler.push(&chunk(1, Some("call_2"), Some("git_diff"), ""));
+    assembler.push(&chunk(0, None, None, "{\"path\": "));
+    // repeated ids and names are kept once
+    assembler.push(&chunk(1, Some("call_2"), Some("git_diff"), "{}"));
+    assembler.push(&chunk(0, None, None, "\"src/main.rs\"}"));
+    assert_eq!(assembler.describe().unwrap(), "receiving the git_diff call, 2 bytes of arguments");
+    let calls = assembler.tool_calls().unwrap();
+    assert_eq!(calls.len(), 2);
+    assert_eq!((calls[0].id.as_str(), calls[0].function.name.as_str()), ("call_1", "read_file"));
+    assert_eq!(calls[0].function.arguments, "{\"path\": \"src/main.rs\"}");
+    assert_eq!((calls[1].id.as_str(), calls[1].function.arguments.as_str()), ("call_2", "{}"));
+    assert!(assembler.validate().is_empty());
+
+    let mut assembler = ToolCallAssembler::default();
+    assembler.push(&chunk(0, Some("call_3"), Some("file_search"), ""));
+    assert_eq!(assembler.tool_calls().unwrap()[0].function.arguments, "{}");
+    assembler.push(&chunk(0, None, None, "{\"search_term\": \"ma"));
+    let errors = assembler.validate();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].to_string().contains("the arguments of the file_search call aren't valid json"));
+  }
+}
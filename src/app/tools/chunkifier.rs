@@ -1,7 +1,8 @@
 use crate::app::consts::*;
+use crate::app::encryption;
 use crate::app::errors::ChunkifierError;
-use crate::app::tools::utils::ensure_directory_exists;
 
+use crate::app::tools::ocr::{document_text, is_image_file};
 use crate::app::types::*;
 use std::fs::{self, File};
 use std::io::Read;
@@ -122,15 +123,18 @@ pub fn chunkify_input(input: &str, tokens_per_chunk: usize) -> Result<Vec<String
 fn chunkify_file(file_path: &PathBuf, tokens_per_chunk: usize) -> Result<Vec<String>, ChunkifierError> {
   let content = extract_file_text(file_path)?;
   let chunks = chunkify_text(&content, tokens_per_chunk);
-  ensure_directory_exists(INGESTED_DIR).unwrap();
+  // under the data directory rather than the working directory, where the retention policy can cap them
+  let ingested_dir = data_path(INGESTED_DIR).unwrap_or_else(|| PathBuf::from(INGESTED_DIR));
+  fs::create_dir_all(&ingested_dir)?;
   if file_path.is_file() {
-    let dest_path = Path::new(INGESTED_DIR).join(file_path.file_name().unwrap());
-    fs::copy(file_path, dest_path)?;
+    let dest_path = ingested_dir.join(file_path.file_name().unwrap());
+    // written rather than copied so that the copy is encrypted when encryption is enabled
+    encryption::write(&dest_path, fs::read(file_path)?)?;
   }
   Ok(chunks)
 }
 
-fn chunkify_text(text: &str, tokens_per_chunk: usize) -> Vec<String> {
+pub fn chunkify_text(text: &str, tokens_per_chunk: usize) -> Vec<String> {
   let tokens: Vec<&str> = text.split_whitespace().collect();
   let bpe = cl100k_base().unwrap();
   let mut chunks = Vec::new();
@@ -158,7 +162,7 @@ fn chunkify_text(text: &str, tokens_per_chunk: usize) -> Vec<String> {
 }
 
 /// Check if the given file is a PDF.
-fn is_pdf_file(file_path: &Path) -> bool {
+pub fn is_pdf_file(file_path: &Path) -> bool {
   file_path.extension().and_then(|s| s.to_str()) == Some("pdf")
 }
 
@@ -173,10 +177,8 @@ fn is_binary_file(file_path: &PathBuf) -> bool {
 }
 
 fn extract_file_text(file_path: &PathBuf) -> Result<String, ChunkifierError> {
-  if is_pdf_file(file_path) {
-    PdfText::from_pdf(file_path)
-      .and_then(|pdf_text| pdf_text.get_text())
-      .map_err(|_| ChunkifierError::Other("Failed to extract text from PDF".to_string()))
+  if is_pdf_file(file_path) || is_image_file(file_path) {
+    document_text(file_path).map_err(|e| ChunkifierError::Other(format!("Failed to extract text: {}", e)))
   } else if is_binary_file(file_path) {
     Err(ChunkifierError::Other("Binary file detected".to_string()))
   } else {
@@ -0,0 +1,203 @@
+use std::{path::Path, process::Stdio, time::Duration};
+
+use tokio::{
+  io::{AsyncRead, AsyncReadExt},
+  process::Command,
+};
+
+use crate::app::errors::SazidError;
+
+pub const EXAMPLE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const EXAMPLE_OUTPUT_MAX_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+  pub language: String,
+  pub code: String,
+  // byte offset just past the closing fence
+  pub end: usize,
+}
+
+impl CodeBlock {
+  // the program that runs the example, checked against the commands of the sandbox policy
+  pub fn program(&self) -> Option<&'static str> {
+    match self.language.as_str() {
+      "sh" | "bash" | "shell" => Some("sh"),
+      "python" | "py" => Some("python3"),
+      "rust" | "rs" => Some("rustc"),
+      _ => None,
+    }
+  }
+
+  pub fn is_runnable(&self) -> bool {
+    self.program().is_some()
+  }
+}
+
+// fenced code blocks in the order they appear in the text
+pub fn extract_code_blocks(text: &str) -> Vec<CodeBlock> {
+  let mut blocks = vec![];
+  let mut current: Option<(String, Vec<&str>)> = None;
+  let mut offset = 0;
+  for line in text.split_inclusive('\n') {
+    offset += line.len();
+    let trimmed = line.trim();
+    if let Some(info) = trimmed.strip_prefix("```") {
+      match current.take() {
+        Some((language, lines)) if info.is_empty() => {
+          blocks.push(CodeBlock { language, code: lines.concat(), end: offset });
+        },
+        Some(block) => current = Some(block),
+        None => current = Some((info.split_whitespace().next().unwrap_or("").to_lowercase(), vec![])),
+      }
+    } else if let Some((_, lines)) = current.as_mut() {
+      lines.push(line);
+    }
+  }
+  blocks
+}
+
+// runs the example in a scratch directory with a cleared environment, killing it after the timeout
+pub async fn run_example(block: &CodeBlock, timeout: Duration) -> Result<String, SazidError> {
+  let dir = tempfile::tempdir()?;
+  let mut command = match block.language.as_str() {
+    "sh" | "bash" | "shell" => {
+      let path = dir.path().join("example.sh");
+      std::fs::write(&path, &block.code)?;
+      let mut command = Command::new("sh");
+      command.arg(&path);
+      command
+    },
+    "python" | "py" => {
+      let path = dir.path().join("example.py");
+      std::fs::write(&path, &block.code)?;
+      let mut command = Command::new("python3");
+      command.arg(&path);
+      command
+    },
+    "rust" | "rs" => {
+      let path = dir.path().join("example.rs");
+      std::fs::write(&path, &block.code)?;
+      let binary = dir.path().join("example");
+      let compile = run_with_timeout(
+        Command::new("rustc").arg("--edition=2021").arg(&path).arg("-o").arg(&binary),
+        dir.path(),
+        timeout,
+      )
+      .await?;
+      if !binary.exists() {
+        return Ok(compile);
+      }
+      Command::new(binary)
+    },
+    language => return Err(SazidError::Other(format!("{} examples can not be run", language))),
+  };
+  run_with_timeout(&mut command, dir.path(), timeout).await
+}
+
+// reads until the pipe closes, so that the child never blocks on a full pipe, and keeps what fits in the output
+async fn read_capped(mut pipe: impl AsyncRead + Unpin) -> Vec<u8> {
+  let mut output = vec![];
+  let mut buffer = [0; 8192];
+  while let Ok(n) = pipe.read(&mut buffer).await {
+    if n == 0 {
+      break;
+    }
+    let kept = n.min((EXAMPLE_OUTPUT_MAX_CHARS + 1).saturating_sub(output.len()));
+    output.extend_from_slice(&buffer[..kept]);
+  }
+  output
+}
+
+async fn run_with_timeout(command: &mut Command, dir: &Path, timeout: Duration) -> Result<String, SazidError> {
+  command
+    .current_dir(dir)
+    .env_clear()
+    .env("PATH", std::env::var("PATH").unwrap_or_default())
+    .env("HOME", dir)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .kill_on_drop(true);
+  // in a process group of its own, so that whatever the example starts is killed with it
+  #[cfg(unix)]
+  command.process_group(0);
+  let mut child = command.spawn()?;
+  let pid = child.id();
+  let stdout = tokio::spawn(read_capped(child.stdout.take().unwrap()));
+  let stderr = tokio::spawn(read_capped(child.stderr.take().unwrap()));
+  let status = match tokio::time::timeout(timeout, child.wait()).await {
+    Ok(status) => Some(status?),
+    Err(_) => {
+      child.kill().await?;
+      None
+    },
+  };
+  #[cfg(unix)]
+  if let Some(pid) = pid {
+    // SAFETY: kill only sends a signal, a negative pid is the process group the example was started in
+    unsafe { libc::kill(-(pid as i32), libc::SIGKILL) };
+  }
+  // the pipes close once the process group is gone, they are only waited on briefly in case something escaped it
+  let pipes = async { (stdout.await.unwrap_or_default(), stderr.await.unwrap_or_default()) };
+  let (stdout, stderr) = tokio::time::timeout(Duration::from_secs(1), pipes).await.unwrap_or_default();
+  let mut output = String::from_utf8_lossy(&stdout).to_string();
+  output.push_str(&String::from_utf8_lossy(&stderr));
+  if output.len() > EXAMPLE_OUTPUT_MAX_CHARS {
+    let mut end = EXAMPLE_OUTPUT_MAX_CHARS;
+    while !output.is_char_boundary(end) {
+      end -= 1;
+    }
+    output.truncate(end);
+    output.push_str("\n[output truncated]");
+  }
+  match status {
+    Some(status) if status.success() => Ok(output),
+    Some(status) => Ok(format!("{}\n[exited with {}]", output.trim_end(), status)),
+    None => Ok(format!("{}\n[timed out after {}s]", output.trim_end(), timeout.as_secs())),
+  }
+}
+
+// places the captured output directly beneath the code block it came from
+pub fn insert_example_output(text: &str, block: &CodeBlock, output: &str) -> String {
+  let (before, after) = text.split_at(block.end);
+  let separator = if before.ends_with('\n') { "" } else { "\n" };
+  format!("{}{}```output\n{}\n```\n{}", before, separator, output.trim_end(), after)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RESPONSE: &str = "Run this:\n```sh\necho hello\n```\nand this:\n```text\nnot code\n```\n";
+
+  #[test]
+  fn test_extract_code_blocks() {
+    let blocks = extract_code_blocks(RESPONSE);
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].language, "sh");
+    assert_eq!(blocks[0].code, "echo hello\n");
+    assert!(blocks[0].is_runnable());
+    assert!(!blocks[1].is_runnable());
+  }
+
+  #[test]
+  fn test_insert_example_output() {
+    let blocks = extract_code_blocks(RESPONSE);
+    let text = insert_example_output(RESPONSE, &blocks[0], "hello\n");
+    assert_eq!(text, "Run this:\n```sh\necho hello\n```\n```output\nhello\n```\nand this:\n```text\nnot code\n```\n");
+  }
+
+  #[tokio::test]
+  async fn test_run_example_drains_output_and_times_out() {
+    let block = |code: &str| CodeBlock { language: "sh".to_string(), code: code.to_string(), end: 0 };
+    // more output than a pipe holds doesn't block the example
+    let output = run_example(&block("head -c 200000 /dev/zero | tr '\\0' x\n"), Duration::from_secs(5)).await.unwrap();
+    assert!(output.ends_with("[output truncated]"));
+    assert_eq!(output.len(), EXAMPLE_OUTPUT_MAX_CHARS + "\n[output truncated]".len());
+
+    // the sleep it started in the background doesn't hold the output back once it times out
+    let output = run_example(&block("echo started\nsleep 5 &\nsleep 5\n"), Duration::from_secs(1)).await.unwrap();
+    assert_eq!(output, "started\n[timed out after 1s]");
+  }
+}
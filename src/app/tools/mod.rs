@@ -1,3 +1,5 @@
 pub mod chunkifier;
+pub mod example_runner;
+pub mod ocr;
 pub mod pdf_extractor;
 pub mod utils;
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use crate::app::{errors::SazidError, types::PdfText};
+
+use super::chunkifier::is_pdf_file;
+
+// the resolution scanned pdf pages are rendered at before ocr, tesseract reads text best at 300 dpi
+#[cfg(feature = "ocr")]
+const PDF_RENDER_DPI: u32 = 300;
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff", "bmp", "gif", "webp"];
+
+pub fn is_image_file(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .map_or(false, |extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+#[cfg(feature = "ocr")]
+pub fn ocr_image(path: &Path) -> Result<String, SazidError> {
+  let ocr_error = |e: rusty_tesseract::TessError| SazidError::Other(format!("ocr of {}: {}", path.display(), e));
+  let image = rusty_tesseract::Image::from_path(path).map_err(ocr_error)?;
+  rusty_tesseract::image_to_string(&image, &rusty_tesseract::Args::default()).map_err(ocr_error)
+}
+
+#[cfg(not(feature = "ocr"))]
+pub fn ocr_image(path: &Path) -> Result<String, SazidError> {
+  Err(SazidError::Other(format!("{} needs ocr, and sazid was built without the ocr feature", path.display())))
+}
+
+// the pages of a pdf rendered to images with pdftoppm, then read with tesseract
+#[cfg(feature = "ocr")]
+pub fn ocr_pdf(path: &Path) -> Result<String, SazidError> {
+  let dir = tempfile::tempdir()?;
+  let status = std::process::Command::new("pdftoppm")
+    .args(["-r", &PDF_RENDER_DPI.to_string(), "-png"])
+    .arg(path)
+    .arg(dir.path().join("page"))
+    .status()
+    .map_err(|e| SazidError::Other(format!("failed to run pdftoppm, which renders pdf pages for ocr: {}", e)))?;
+  if !status.success() {
+    return Err(SazidError::Other(format!("pdftoppm failed to render {}: {}", path.display(), status)));
+  }
+  // pdftoppm pads the page numbers to the same width, so the names sort in page order
+  let mut pages = std::fs::read_dir(dir.path())?.flatten().map(|entry| entry.path()).collect::<Vec<_>>();
+  pages.sort();
+  let pages = pages.iter().map(|page| ocr_image(page)).collect::<Result<Vec<String>, SazidError>>()?;
+  Ok(pages.join("\n\n"))
+}
+
+#[cfg(not(feature = "ocr"))]
+pub fn ocr_pdf(path: &Path) -> Result<String, SazidError> {
+  ocr_image(path)
+}
+
+// the text layer of a pdf, or its pages read with ocr when it has none, as in a scan
+pub fn pdf_text(path: &Path) -> Result<String, SazidError> {
+  match PdfText::from_pdf(path).and_then(|pdf| pdf.get_text()) {
+    Ok(text) if !text.trim().is_empty() => Ok(text),
+    Ok(_) => ocr_pdf(path),
+    Err(e) => {
+      log::warn!("{}: {}, reading it with ocr", path.display(), e);
+      ocr_pdf(path)
+    },
+  }
+}
+
+// the text of a file to ingest, with pdfs and images read by pdf_text and ocr
+pub fn document_text(path: &Path) -> Result<String, SazidError> {
+  if is_pdf_file(path) {
+    pdf_text(path)
+  } else if is_image_file(path) {
+    ocr_image(path)
+  } else {
+    Ok(std::fs::read_to_string(path)?)
+  }
+}
+
+// the step recorded in provenance for how the text of a file was read
+pub fn extraction_step(path: &Path) -> &'static str {
+  if is_pdf_file(path) {
+    "extracted the text layer of the pdf, or read its pages with tesseract ocr when it has none"
+  } else if is_image_file(path) {
+    "read with tesseract ocr"
+  } else {
+    "read as utf-8"
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_document_text() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.txt");
+    std::fs::write(&path, "plain text").unwrap();
+    assert_eq!(document_text(&path).unwrap(), "plain text");
+    assert_eq!(extraction_step(&path), "read as utf-8");
+    assert!(is_image_file(Path::new("scan.JPG")));
+    assert!(!is_image_file(Path::new("scan.pdf")));
+  }
+
+  #[cfg(not(feature = "ocr"))]
+  #[test]
+  fn test_ocr_needs_feature() {
+    let error = document_text(Path::new("scan.png")).unwrap_err();
+    assert!(error.to_string().contains("built without the ocr feature"));
+  }
+}
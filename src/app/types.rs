@@ -79,6 +79,22 @@ pub struct Model {
   pub endpoint: String,
   pub token_limit: u32,
 }
+impl Model {
+  // look up a known model by name, falling back to the default endpoint and token limit for unknown names
+  // vendor qualified names such as openai/gpt-4 use the limits of the unqualified model
+  pub fn from_name(name: &str) -> Model {
+    let unqualified = name.rsplit('/').next().unwrap_or(name);
+    match AVAILABLE_MODELS.iter().find(|m| m.name == name || m.name == unqualified) {
+      Some(model) => Model { name: name.to_string(), ..model.clone() },
+      None => Model {
+        name: name.to_string(),
+        endpoint: GPT4_TURBO.endpoint.clone(),
+        token_limit: GPT4_TURBO.token_limit,
+      },
+    }
+  }
+}
+
 impl AsRef<Model> for Model {
   fn as_ref(&self) -> &Model {
     self
@@ -0,0 +1,65 @@
+// states of the transcript saved before each change, with what the change was, so that it can be undone and redone
+#[derive(Debug)]
+pub struct UndoHistory<T> {
+  undo: Vec<(String, T)>,
+  redo: Vec<(String, T)>,
+  // the oldest states are dropped past this many
+  limit: usize,
+}
+
+pub const DEFAULT_UNDO_LIMIT: usize = 100;
+
+impl<T> Default for UndoHistory<T> {
+  fn default() -> Self {
+    UndoHistory { undo: vec![], redo: vec![], limit: DEFAULT_UNDO_LIMIT }
+  }
+}
+
+impl<T> UndoHistory<T> {
+  // saves the state before a change, a new change can't be redone past
+  pub fn record(&mut self, change: &str, state: T) {
+    self.undo.push((change.to_string(), state));
+    if self.undo.len() > self.limit {
+      self.undo.remove(0);
+    }
+    self.redo.clear();
+  }
+
+  // the state before the last change, current is kept to redo it
+  pub fn undo(&mut self, current: T) -> Option<(String, T)> {
+    let (change, state) = self.undo.pop()?;
+    self.redo.push((change.clone(), current));
+    Some((change, state))
+  }
+
+  // the state after the last undone change, current is kept to undo it again
+  pub fn redo(&mut self, current: T) -> Option<(String, T)> {
+    let (change, state) = self.redo.pop()?;
+    self.undo.push((change.clone(), current));
+    Some((change, state))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_undo_and_redo() {
+    let mut history = UndoHistory::default();
+    history.record("new exchange", vec!["system"]);
+    history.record("clear", vec!["system", "question", "answer"]);
+    let current = vec!["system"];
+
+    let (change, current) = history.undo(current).unwrap();
+    assert_eq!((change.as_str(), current.len()), ("clear", 3));
+    let (change, current) = history.undo(current).unwrap();
+    assert_eq!((change.as_str(), current.len()), ("new exchange", 1));
+    assert!(history.undo(current.clone()).is_none());
+
+    let (change, current) = history.redo(current).unwrap();
+    assert_eq!((change.as_str(), current.len()), ("new exchange", 3));
+    history.record("new exchange", current);
+    assert!(history.redo(vec![]).is_none());
+  }
+}
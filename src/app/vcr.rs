@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use async_openai::{error::OpenAIError, types::CreateChatCompletionRequest};
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{errors::SazidError, messages::ChatMessage, middleware::Middleware, response_cache::request_hash};
+
+// set with --record or --replay
+#[derive(Debug, Clone, PartialEq)]
+pub enum VcrMode {
+  // every request and what the api returned is appended to the cassette
+  Record(PathBuf),
+  // requests are answered from the cassette and never sent
+  Replay(PathBuf),
+}
+
+// a request and the responses, or the error, that came back, as received including every streamed chunk
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+  pub request: CreateChatCompletionRequest,
+  #[serde(default)]
+  pub responses: Vec<ChatMessage>,
+  #[serde(default)]
+  pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Cassette {
+  pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+  // a missing cassette is empty, so that recording can start a new one
+  pub fn load(path: &Path) -> Result<Self, SazidError> {
+    match path.exists() {
+      true => serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|e| SazidError::Other(format!("invalid cassette {}: {}", path.display(), e))),
+      false => Ok(Cassette::default()),
+    }
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), SazidError> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+      std::fs::create_dir_all(parent)?;
+    }
+    let data = serde_json::to_string_pretty(self).map_err(|e| SazidError::Other(e.to_string()))?;
+    std::fs::write(path, data)?;
+    Ok(())
+  }
+
+  // matched on the whole request, like the response cache, so an identical request replays the first recording
+  pub fn find(&self, request: &CreateChatCompletionRequest) -> Option<&Interaction> {
+    let hash = request_hash(request);
+    self.interactions.iter().find(|interaction| request_hash(&interaction.request) == hash)
+  }
+
+  pub fn append(path: &Path, interaction: Interaction) -> Result<(), SazidError> {
+    let mut cassette = Cassette::load(path)?;
+    cassette.interactions.push(interaction);
+    cassette.save(path)
+  }
+}
+
+// records requests to a cassette or replays them from one, for reproducible bug reports and snapshot tests
+pub struct VcrMiddleware {
+  pub mode: VcrMode,
+}
+
+#[async_trait]
+impl Middleware for VcrMiddleware {
+  fn name(&self) -> &'static str {
+    "vcr"
+  }
+
+  async fn pre_request(
+    &self,
+    request: &mut CreateChatCompletionRequest,
+  ) -> Result<Option<Vec<ChatMessage>>, SazidError> {
+    match &self.mode {
+      VcrMode::Record(_) => Ok(None),
+      VcrMode::Replay(path) => match Cassette::load(path)?.find(request) {
+        Some(Interaction { error: Some(error), .. }) => Err(SazidError::Other(format!("replayed error: {}", error))),
+        Some(interaction) => Ok(Some(interaction.responses.clone())),
+        None => Err(SazidError::Other(format!("no request in cassette {} matches this request", path.display()))),
+      },
+    }
+  }
+
+  async fn on_complete(
+    &self,
+    request: &CreateChatCompletionRequest,
+    responses: &[ChatMessage],
+  ) -> Result<(), SazidError> {
+    match &self.mode {
+      VcrMode::Record(path) => {
+        Cassette::append(path, Interaction { request: request.clone(), responses: responses.to_vec(), error: None })
+      },
+      VcrMode::Replay(_) => Ok(()),
+    }
+  }
+
+  async fn on_error(&self, request: &CreateChatCompletionRequest, error: &OpenAIError) {
+    if let VcrMode::Record(path) = &self.mode {
+      let interaction = Interaction { request: request.clone(), responses: vec![], error: Some(error.to_string()) };
+      if let Err(e) = Cassette::append(path, interaction) {
+        log::error!("could not record to cassette {}: {}", path.display(), e);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+    ChatCompletionResponseStreamMessage, ChatCompletionStreamResponseDelta, CreateChatCompletionStreamResponse, Role,
+  };
+
+  use super::*;
+
+  fn request(content: &str) -> CreateChatCompletionRequest {
+    CreateChatCompletionRequest {
+      model: "gpt-4".to_string(),
+      messages: vec![ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text(content.to_string())),
+      })],
+      stream: Some(true),
+      ..Default::default()
+    }
+  }
+
+  fn chunk(content: &str) -> ChatMessage {
+    ChatMessage::StreamResponse(vec![CreateChatCompletionStreamResponse {
+      id: "chatcmpl-1".to_string(),
+      object: "chat.completion.chunk".to_string(),
+      created: 0,
+      model: "gpt-4".to_string(),
+      system_fingerprint: None,
+      choices: vec![ChatCompletionResponseStreamMessage {
+        index: 0,
+        delta: ChatCompletionStreamResponseDelta {
+          role: None,
+          content: Some(content.to_string()),
+          function_call: None,
+          tool_calls: None,
+        },
+        finish_reason: None,
+      }],
+    }])
+  }
+
+  #[tokio::test]
+  async fn test_record_and_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cassettes/session.json");
+    let recorder = VcrMiddleware { mode: VcrMode::Record(path.clone()) };
+    let chunks = vec![chunk("hel"), chunk("lo")];
+    assert_eq!(recorder.pre_request(&mut request("hi")).await.unwrap(), None);
+    recorder.on_complete(&request("hi"), &chunks).await.unwrap();
+    recorder.on_error(&request("fail"), &OpenAIError::InvalidArgument("bad request".to_string())).await;
+    assert_eq!(Cassette::load(&path).unwrap().interactions.len(), 2);
+
+    let player = VcrMiddleware { mode: VcrMode::Replay(path) };
+    assert_eq!(player.pre_request(&mut request("hi")).await.unwrap(), Some(chunks));
+    assert!(player.pre_request(&mut request("fail")).await.unwrap_err().to_string().contains("bad request"));
+    assert!(player.pre_request(&mut request("unrecorded")).await.is_err());
+  }
+}
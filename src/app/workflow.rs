@@ -0,0 +1,382 @@
+use std::{
+  collections::HashMap,
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+use async_openai::types::{ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role};
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+
+use super::{
+  batch::{response_text, BatchSession},
+  errors::SazidError,
+  functions::{
+    all_functions, call_function, call_resources, configured_functions, plugin_function::load_plugins,
+    types::FunctionCall, CallableFunction,
+  },
+  messages::ChatMessage,
+  summarize::collect_documents,
+  types::Model,
+};
+
+// functions that wait for the user to confirm or review them, which a workflow can't do
+const CONFIRMED_FUNCTIONS: [&str; 2] = ["git_commit", "apply_patch"];
+
+// a chain of steps for `sazid run`, written in yaml
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Workflow {
+  pub name: Option<String>,
+  // used in place of the configured model and system prompt
+  pub model: Option<String>,
+  pub system: Option<String>,
+  // the initial {{variables}}, each can be replaced with --var name=value
+  pub vars: HashMap<String, String>,
+  pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Step {
+  // the result is kept in {{<name>}} for the steps after it, and the latest result is always in {{result}}
+  pub name: Option<String>,
+  // a template sent as the next message of the workflow's conversation
+  pub prompt: Option<String>,
+  // a function called with args, whose strings are templates
+  pub tool: Option<String>,
+  pub args: HashMap<String, Value>,
+  // the step is skipped unless the condition holds, see condition_holds
+  pub when: Option<String>,
+  // runs the step once for each text file under the directory, with {{path}}, {{file}}, {{stem}} and {{content}} set
+  pub for_each: Option<String>,
+  // only the files whose names end with one of these, such as .rs
+  pub extensions: Vec<String>,
+  // a path template the result is written to
+  pub output: Option<String>,
+  // the prompt is sent with only the system prompt ahead of it, and is left out of the conversation, prompts run for
+  // each file are always fresh
+  pub fresh: bool,
+}
+
+impl Step {
+  fn label(&self, index: usize) -> String {
+    match &self.name {
+      Some(name) => format!("step {} ({})", index + 1, name),
+      None => format!("step {}", index + 1),
+    }
+  }
+}
+
+impl Workflow {
+  pub fn parse(yaml: &str) -> Result<Self, SazidError> {
+    let workflow: Workflow =
+      serde_yaml::from_str(yaml).map_err(|e| SazidError::Other(format!("invalid workflow: {}", e)))?;
+    // checked up front, so that a mistake in the last step doesn't waste the requests of the steps before it
+    for (i, step) in workflow.steps.iter().enumerate() {
+      match (&step.prompt, &step.tool) {
+        (Some(_), None) => {},
+        (None, Some(tool)) if CONFIRMED_FUNCTIONS.contains(&tool.as_str()) => {
+          return Err(SazidError::Other(format!("{} calls {}, which needs confirmation", step.label(i), tool)));
+        },
+        (None, Some(_)) => {},
+        _ => return Err(SazidError::Other(format!("{} needs either a prompt or a tool", step.label(i)))),
+      }
+    }
+    Ok(workflow)
+  }
+
+  pub fn load(path: &Path) -> Result<Self, SazidError> {
+    let yaml = std::fs::read_to_string(path)
+      .map_err(|e| SazidError::Other(format!("failed to read workflow {}: {}", path.display(), e)))?;
+    Self::parse(&yaml)
+  }
+}
+
+// replaces each {{name}} with its value, a name without a value is an error so that a typo doesn't reach the model
+pub fn render(template: &str, vars: &HashMap<String, String>) -> Result<String, SazidError> {
+  let mut rendered = String::new();
+  let mut rest = template;
+  while let Some(start) = rest.find("{{") {
+    let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+      break;
+    };
+    let name = rest[start + 2..end].trim();
+    let value = vars.get(name).ok_or_else(|| SazidError::Other(format!("{{{{{}}}}} has no value", name)))?;
+    rendered.push_str(&rest[..start]);
+    rendered.push_str(value);
+    rest = &rest[end + 2..];
+  }
+  rendered.push_str(rest);
+  Ok(rendered)
+}
+
+fn render_value(value: &Value, vars: &HashMap<String, String>) -> Result<Value, SazidError> {
+  Ok(match value {
+    Value::String(s) => Value::String(render(s, vars)?),
+    Value::Array(values) => Value::Array(values.iter().map(|v| render_value(v, vars)).collect::<Result<_, _>>()?),
+    Value::Object(map) => Value::Object(
+      map.iter().map(|(k, v)| Ok((k.clone(), render_value(v, vars)?))).collect::<Result<_, SazidError>>()?,
+    ),
+    _ => value.clone(),
+  })
+}
+
+// a condition such as `{{result}} contains TODO`, `{{stem}} != main`, or `{{flag}}`, which holds unless it is empty,
+// false, no or 0
+pub fn condition_holds(condition: &str, vars: &HashMap<String, String>) -> Result<bool, SazidError> {
+  for operator in [" contains ", " == ", " != "] {
+    if let Some((left, right)) = condition.split_once(operator) {
+      let (left, right) = (render(left, vars)?, render(right, vars)?);
+      let (left, right) = (left.trim(), right.trim().trim_matches('"'));
+      return Ok(match operator {
+        " contains " => left.contains(right),
+        " == " => left == right,
+        _ => left != right,
+      });
+    }
+  }
+  Ok(!matches!(render(condition, vars)?.trim().to_lowercase().as_str(), "" | "false" | "no" | "0"))
+}
+
+pub struct WorkflowRunner {
+  // the workflow's conversation, saved once it finishes
+  session: BatchSession,
+  vars: HashMap<String, String>,
+}
+
+impl WorkflowRunner {
+  pub fn new(workflow: &Workflow, config: &Config, vars: HashMap<String, String>) -> Self {
+    let mut session_config = config.session_config.clone();
+    if let Some(model) = &workflow.model {
+      session_config.model = Model::from_name(model);
+    }
+    if let Some(system) = &workflow.system {
+      session_config.prompt = system.clone();
+    }
+    session_config.available_functions = all_functions();
+    session_config.available_functions.extend(configured_functions(&session_config));
+    if let Some(plugins_dir) = &config.session_config.plugins_dir {
+      session_config
+        .available_functions
+        .extend(load_plugins(plugins_dir).into_iter().map(CallableFunction::PluginFunction));
+    }
+    let mut session = BatchSession::new(session_config);
+    session.add_initial_messages();
+    let mut all_vars = workflow.vars.clone();
+    all_vars.extend(vars);
+    WorkflowRunner { session, vars: all_vars }
+  }
+
+  // runs the steps in order, stopping at the first that fails, and returns the result of the last step that ran
+  pub async fn run(&mut self, workflow: &Workflow) -> Result<Option<String>, SazidError> {
+    for (i, step) in workflow.steps.iter().enumerate() {
+      let label = step.label(i);
+      match &step.for_each {
+        Some(dir) => {
+          let root = PathBuf::from(render(dir, &self.vars)?);
+          let documents = collect_documents(&root).into_iter().filter(|document| {
+            step.extensions.is_empty() || step.extensions.iter().any(|e| document.name.ends_with(e))
+          });
+          for document in documents {
+            let path = match root.is_file() {
+              true => root.clone(),
+              false => root.join(&document.name),
+            };
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+            self.vars.insert("path".to_string(), path.to_string_lossy().to_string());
+            self.vars.insert("file".to_string(), document.name.clone());
+            self.vars.insert("stem".to_string(), stem);
+            self.vars.insert("content".to_string(), document.content);
+            eprintln!("{}: {}", label, document.name);
+            self.run_step(step, true).await.map_err(|e| step_error(&label, e))?;
+          }
+        },
+        None => {
+          eprintln!("{}", label);
+          self.run_step(step, step.fresh).await.map_err(|e| step_error(&label, e))?;
+        },
+      }
+    }
+    if self.session.data.messages.iter().any(|m| m.role() == "user") {
+      self.session.save()?;
+      eprintln!("the conversation is saved as session {}", self.session.config.session_id);
+    }
+    Ok(self.vars.get("result").cloned())
+  }
+
+  async fn run_step(&mut self, step: &Step, fresh: bool) -> Result<(), SazidError> {
+    if let Some(when) = &step.when {
+      if !condition_holds(when, &self.vars)? {
+        eprintln!("  skipped, {} doesn't hold", when);
+        return Ok(());
+      }
+    }
+    let result = match (&step.prompt, &step.tool) {
+      (Some(prompt), _) => self.send_prompt(render(prompt, &self.vars)?, fresh).await?,
+      (_, Some(tool)) => self.call_tool(tool, &step.args)?,
+      (None, None) => String::new(),
+    };
+    if let Some(output) = &step.output {
+      let path = PathBuf::from(render(output, &self.vars)?);
+      if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      std::fs::write(&path, &result)?;
+      eprintln!("  wrote {}", path.display());
+    }
+    if let Some(name) = &step.name {
+      self.vars.insert(name.clone(), result.clone());
+    }
+    self.vars.insert("result".to_string(), result);
+    Ok(())
+  }
+
+  async fn send_prompt(&mut self, prompt: String, fresh: bool) -> Result<String, SazidError> {
+    let mut scratch;
+    let session = match fresh {
+      true => {
+        scratch = BatchSession::new(self.session.config.clone());
+        scratch.add_initial_messages();
+        &mut scratch
+      },
+      false => &mut self.session,
+    };
+    session.data.add_message(ChatMessage::User(ChatCompletionRequestUserMessage {
+      role: Role::User,
+      content: Some(ChatCompletionRequestUserMessageContent::Text(prompt)),
+    }));
+    let (request, notices) = session.prepare_request()?;
+    notices.iter().for_each(|notice| eprintln!("  {}", notice));
+    let mut response = String::new();
+    session
+      .send_request(
+        request,
+        |notice| eprintln!("  {}", notice),
+        |message| {
+          response.push_str(&response_text(message));
+          Ok(())
+        },
+      )
+      .await?;
+    Ok(response)
+  }
+
+  fn call_tool(&self, tool: &str, args: &HashMap<String, Value>) -> Result<String, SazidError> {
+    let config = &self.session.config;
+    if !config.available_functions.iter().map(FunctionCall::from).any(|f| f.name == tool) {
+      return Err(SazidError::Other(format!("there is no function named {}", tool)));
+    }
+    let args = args
+      .iter()
+      .map(|(name, value)| Ok((name.clone(), render_value(value, &self.vars)?)))
+      .collect::<Result<HashMap<String, Value>, SazidError>>()?;
+    // the user wrote the call, so only what the sandbox policy denies is refused, nothing is asked
+    if let Err(resource) = config.sandbox.check(&call_resources(config, tool, &args)) {
+      return Err(SazidError::Other(format!("access to {} is denied by the sandbox policy", resource)));
+    }
+    let output = call_function(tool, args, config.clone()).map_err(SazidError::FunctionCallError)?;
+    Ok(output.unwrap_or_default())
+  }
+}
+
+fn step_error(label: &str, e: SazidError) -> SazidError {
+  SazidError::Other(format!("{} failed: {}", label, e))
+}
+
+// name=value pairs from --var
+pub fn parse_vars(vars: &[String]) -> Result<HashMap<String, String>, SazidError> {
+  vars
+    .iter()
+    .map(|var| match var.split_once('=') {
+      Some((name, value)) => Ok((name.trim().to_string(), value.to_string())),
+      None => Err(SazidError::Other(format!("invalid variable {}, expected name=value", var))),
+    })
+    .collect()
+}
+
+// the result of the last step is printed, unless it was written to a file
+pub async fn run_workflow(config: Config, path: &Path, vars: &[String]) -> Result<(), SazidError> {
+  let workflow = Workflow::load(path)?;
+  if let Some(name) = &workflow.name {
+    eprintln!("running {}", name);
+  }
+  let mut runner = WorkflowRunner::new(&workflow, &config, parse_vars(vars)?);
+  let result = runner.run(&workflow).await?;
+  if let (Some(result), Some(None)) = (result, workflow.steps.last().map(|step| &step.output)) {
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", result.trim_end())?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::functions::argument_validation::count_tokens;
+
+  #[test]
+  fn test_workflow_templates() {
+    let yaml = r#"
+name: docs
+vars:
+  dir: src
+steps:
+  - for_each: "{{dir}}"
+    extensions: [".rs"]
+    when: "{{content}} contains pub fn"
+    prompt: "Summarize {{file}}:\n{{content}}"
+    output: "docs/{{stem}}.md"
+  - name: listing
+    tool: file_search
+    args: { pattern: "{{dir}}/*.rs" }
+"#;
+    let workflow = Workflow::parse(yaml).unwrap();
+    assert_eq!(workflow.steps.len(), 2);
+    assert_eq!(workflow.steps[0].extensions, vec![".rs"]);
+    assert!(Workflow::parse("steps:\n  - name: empty\n").is_err());
+    assert!(Workflow::parse("steps:\n  - tool: git_commit\n").is_err());
+
+    let vars = HashMap::from([("stem".to_string(), "main".to_string()), ("result".to_string(), "a TODO".to_string())]);
+    assert_eq!(render("docs/{{ stem }}.md", &vars).unwrap(), "docs/main.md");
+    assert!(render("{{missing}}", &vars).is_err());
+    assert_eq!(render("{{result", &vars).unwrap(), "{{result");
+    assert!(condition_holds("{{result}} contains TODO", &vars).unwrap());
+    assert!(condition_holds("{{stem}} == \"main\"", &vars).unwrap());
+    assert!(!condition_holds("{{stem}} != main", &vars).unwrap());
+    assert!(!condition_holds("no", &vars).unwrap());
+    assert_eq!(parse_vars(&["dir=lib".to_string()]).unwrap()["dir"], "lib");
+    assert!(parse_vars(&["dir".to_string()]).is_err());
+  }
+
+  #[test]
+  fn test_tool_output_is_truncated() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("flood");
+    let script = r#"#!/bin/sh
+if [ "$1" = "--manifest" ]; then
+  echo '{"name": "flood", "description": "prints a lot",
+    "parameters": {"type": "object", "required": [], "properties": {}}}'
+else
+  seq 1 20000
+fi
+"#;
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let mut config = Config::default();
+    config.session_config.plugins_dir = Some(dir.path().to_path_buf());
+    config.session_config.function_result_max_tokens = 200;
+
+    let workflow = Workflow::parse("steps:\n  - tool: flood\n").unwrap();
+    let runner = WorkflowRunner::new(&workflow, &config, HashMap::new());
+    let output = runner.call_tool("flood", &HashMap::new()).unwrap();
+    assert!(output.starts_with("1\n2\n"));
+    assert!(output.contains("[truncated "));
+    assert!(output.trim_end().ends_with("20000"));
+    assert!(count_tokens(&output) <= 200);
+  }
+}
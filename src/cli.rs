@@ -1,6 +1,11 @@
-use clap::Parser;
+use std::{net::SocketAddr, path::PathBuf};
 
-use crate::utils::version;
+use clap::{Parser, Subcommand};
+
+use crate::{
+  app::{embeddings::index::VectorIndexKind, server::DEFAULT_SERVE_ADDR},
+  utils::version,
+};
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version = version(), about)]
@@ -33,11 +38,28 @@ pub struct Cli {
   #[arg(
     short = 'f',
     long = "textfile",
+    visible_alias = "ingest",
     value_name = "STRING",
-    help = "read a text file, generate embeddings, and load into vector database"
+    help = "read a text file, pdf, image, mp3, wav or m4a recording, directory or zip or tar archive, generate \
+            embeddings, and load into vector database, skipping stored chunks"
   )]
   pub add_text_file_embeddings: Option<String>,
 
+  #[arg(
+    long = "include",
+    value_name = "GLOB",
+    help = "only ingest the files of a directory or archive matching this glob, relative to it, instead of the \
+            ingest_globs setting, can be repeated"
+  )]
+  pub include: Vec<String>,
+
+  #[arg(
+    long = "exclude",
+    value_name = "GLOB",
+    help = "leave out the files of a directory or archive matching this glob, relative to it, can be repeated"
+  )]
+  pub exclude: Vec<String>,
+
   #[arg(short, long, value_name = "BOOL", help = "delete all embeddings from the database")]
   pub delete_all_embeddings: bool,
 
@@ -49,6 +71,78 @@ pub struct Cli {
   )]
   pub add_text_embeddings: Option<String>,
 
+  #[arg(
+    long = "collection",
+    value_name = "NAME",
+    help = "add files ingested with --textfile to this collection, and limit --search-embeddings and the session to it"
+  )]
+  pub collection: Option<String>,
+
+  #[arg(
+    long = "ingest-git",
+    value_name = "REPO",
+    help = "ingest the files of a git repository and the message and diff of each commit, skipping stored commits"
+  )]
+  pub ingest_git: Option<PathBuf>,
+
+  #[arg(long = "git-max-commits", value_name = "N", help = "most recent commits ingested with --ingest-git")]
+  pub git_max_commits: Option<usize>,
+
+  #[arg(
+    long = "ingest-database",
+    value_name = "NAME",
+    help = "ingest the tables, columns and comments of a configured database, for writing queries with query_database"
+  )]
+  pub ingest_database: Option<String>,
+
+  #[arg(
+    long = "include-versions",
+    help = "include superseded versions of changed files in --search-embeddings results",
+    default_value_t = false
+  )]
+  pub include_versions: bool,
+
+  #[arg(
+    long = "watch",
+    help = "keep ingested directories in sync, re-embedding changed files and removing deleted ones",
+    default_value_t = false
+  )]
+  pub watch: bool,
+
+  #[arg(
+    long = "export-provenance",
+    value_name = "PATH",
+    help = "write the provenance of every ingested chunk to a json lines file for auditing"
+  )]
+  pub export_provenance: Option<PathBuf>,
+
+  #[arg(
+    long = "create-index",
+    value_name = "KIND",
+    help = "replace the similarity index with an hnsw or ivfflat index, using parameters suited to the stored chunks"
+  )]
+  pub create_index: Option<VectorIndexKind>,
+
+  #[arg(long = "index-lists", value_name = "N", help = "number of lists of an ivfflat index built with --create-index")]
+  pub index_lists: Option<u32>,
+
+  #[arg(long = "index-m", value_name = "N", help = "connections per node of an hnsw index built with --create-index")]
+  pub index_m: Option<u32>,
+
+  #[arg(
+    long = "index-ef-construction",
+    value_name = "N",
+    help = "candidate list size while building an hnsw index with --create-index"
+  )]
+  pub index_ef_construction: Option<u32>,
+
+  #[arg(
+    long = "reindex",
+    help = "analyze the embeddings table and rebuild the similarity index, then show its size",
+    default_value_t = false
+  )]
+  pub reindex: bool,
+
   #[arg(
     short = 'i',
     long,
@@ -69,4 +163,302 @@ pub struct Cli {
 
   #[arg(short = 'a', long, help = "Connect to localhost LLVM API endpoint", default_value_t = false)]
   pub local_api: bool,
+
+  #[arg(
+    short = 'b',
+    long = "batch",
+    help = "Respond to a prompt from arguments or stdin, stream the response to stdout and exit",
+    default_value_t = false
+  )]
+  pub batch: bool,
+
+  #[arg(
+    long = "with",
+    value_name = "PATH",
+    help = "Answer the prompt from this file, indexed in memory for the question and not stored, implies --batch"
+  )]
+  pub with: Option<PathBuf>,
+
+  #[arg(
+    long = "offline",
+    help = "Disable every network call, chat requests go to the local API endpoint",
+    default_value_t = false
+  )]
+  pub offline: bool,
+
+  #[arg(
+    long = "notify",
+    help = "Notify when this job finishes, however long it takes, on the desktop unless a webhook is configured",
+    default_value_t = false
+  )]
+  pub notify: bool,
+
+  #[arg(long = "profile", value_name = "NAME", help = "Apply a profile from the config: its model, prompt and tools")]
+  pub profile: Option<String>,
+
+  #[arg(long = "no-cache", help = "Always send requests, ignoring the response cache", default_value_t = false)]
+  pub no_cache: bool,
+
+  #[arg(
+    long = "dry-run",
+    help = "Show each request with its token counts instead of sending it",
+    default_value_t = false
+  )]
+  pub dry_run: bool,
+
+  #[arg(
+    long = "record",
+    value_name = "CASSETTE",
+    conflicts_with = "replay",
+    help = "Append every request and its response to a cassette file, to be replayed with --replay"
+  )]
+  pub record: Option<PathBuf>,
+
+  #[arg(long = "replay", value_name = "CASSETTE", help = "Answer requests from a cassette recorded with --record")]
+  pub replay: Option<PathBuf>,
+
+  #[arg(
+    long = "list-models",
+    help = "List the available models with their context sizes and pricing",
+    default_value_t = false
+  )]
+  pub list_models: bool,
+
+  #[arg(
+    short = 'm',
+    long = "model",
+    value_name = "MODEL_NAME",
+    help = "Specify the model to use (e.g., gpt-4, gpt-3.5-turbo-16k)"
+  )]
+  pub model: Option<String>,
+
+  #[arg(long = "session", value_name = "SESSION_ID", help = "Continue from a specified session file")]
+  pub session: Option<String>,
+
+  #[arg(value_name = "PROMPT", help = "prompt to submit in batch mode, read from stdin when omitted or -")]
+  pub prompt: Option<String>,
+
+  #[arg(
+    value_name = "-",
+    value_parser = ["-"],
+    help = "attach stdin as context sent ahead of the prompt, as in `cat error.log | sazid -b \"explain this log\" -`"
+  )]
+  pub attach_stdin: Option<String>,
+
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+  #[command(about = "Summarize a directory or an ingested collection into a project brief")]
+  Brief {
+    #[arg(value_name = "PATH|COLLECTION", help = "directory to read, or the name of an ingested collection")]
+    target: String,
+
+    #[arg(
+      short = 'o',
+      long,
+      value_name = "PATH",
+      help = "file to write the brief to, defaults to the briefs directory"
+    )]
+    output: Option<PathBuf>,
+  },
+
+  #[command(about = "Export a saved session transcript")]
+  Export {
+    #[arg(
+      short = 'F',
+      long,
+      value_name = "FORMAT",
+      help = "markdown, html, json, org, obsidian or pdf",
+      default_value = "markdown"
+    )]
+    format: String,
+
+    #[arg(
+      short = 'o',
+      long,
+      value_name = "PATH",
+      help = "file or directory, such as an obsidian vault, to write to, stdout when omitted"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(long, help = "export every saved session, requires --output to be a directory", default_value_t = false)]
+    all: bool,
+
+    #[arg(value_name = "SESSION_ID", help = "session to export, defaults to the most recent session")]
+    session_id: Option<String>,
+  },
+
+  #[command(about = "Show how the prompts and responses of two saved sessions differ, such as after regenerating one")]
+  Diff {
+    #[arg(value_name = "SESSION_A", help = "id or file of the first session")]
+    session_a: String,
+
+    #[arg(value_name = "SESSION_B", help = "id or file of the session compared with it")]
+    session_b: String,
+  },
+
+  #[command(about = "List the saved sessions with their tags, leaving out archived sessions")]
+  Sessions {
+    #[arg(long, value_name = "TAG", help = "only the sessions tagged with TAG")]
+    tag: Option<String>,
+
+    #[arg(long, help = "list archived sessions too", default_value_t = false)]
+    archived: bool,
+  },
+
+  #[command(about = "Archive saved sessions, which hides them from session lists without deleting them")]
+  Archive {
+    #[arg(value_name = "SESSION_ID", help = "sessions to archive")]
+    session_ids: Vec<String>,
+
+    #[arg(long, value_name = "DAYS", help = "also archive every session not changed for DAYS days")]
+    older_than: Option<u64>,
+
+    #[arg(long, help = "take the sessions out of the archive instead", default_value_t = false)]
+    restore: bool,
+  },
+
+  #[command(about = "Show what the retention policy would archive and delete, then apply it once confirmed")]
+  Gc {
+    #[arg(long, help = "apply the policy without asking", default_value_t = false)]
+    yes: bool,
+  },
+
+  #[command(about = "Upload a saved session transcript as a GitHub gist or a paste and print the link")]
+  Share {
+    #[arg(
+      long,
+      value_name = "TARGET",
+      help = "gist, with the token from `sazid auth set github`, or paste",
+      default_value = "gist"
+    )]
+    to: String,
+
+    #[arg(short = 'm', long, value_name = "RANGE", help = "only the messages in a range such as 3 or 3-7")]
+    messages: Option<String>,
+
+    #[arg(long, help = "make the gist public instead of secret", default_value_t = false)]
+    public: bool,
+
+    #[arg(value_name = "SESSION_ID", help = "session to share, defaults to the most recent session")]
+    session_id: Option<String>,
+  },
+
+  #[command(about = "Export responses rated up with the rate command as an OpenAI fine-tuning JSONL dataset")]
+  ExportFinetune {
+    #[arg(short = 'o', long, value_name = "PATH", help = "file to write the dataset to, stdout when omitted")]
+    output: Option<PathBuf>,
+  },
+
+  #[command(about = "Serve an HTTP API for creating sessions, sending messages and searching ingested content")]
+  Serve {
+    #[arg(long, value_name = "ADDR", help = "address to listen on", default_value = DEFAULT_SERVE_ADDR)]
+    addr: SocketAddr,
+
+    #[arg(
+      long = "tls-cert",
+      value_name = "PATH",
+      help = "pem certificate chain to serve https with, instead of serve.tls_cert"
+    )]
+    tls_cert: Option<PathBuf>,
+
+    #[arg(long = "tls-key", value_name = "PATH", help = "pem private key of the certificate, instead of serve.tls_key")]
+    tls_key: Option<PathBuf>,
+  },
+
+  #[command(about = "Run the steps of a workflow file, such as prompts for every file in a directory")]
+  Run {
+    #[arg(value_name = "WORKFLOW", help = "yaml file with the steps to run")]
+    workflow: PathBuf,
+
+    #[arg(long = "var", value_name = "NAME=VALUE", help = "sets a variable of the workflow, can be repeated")]
+    vars: Vec<String>,
+  },
+
+  #[command(about = "Create, list, delete and show the size of the collections ingested content is kept in")]
+  Index {
+    #[command(subcommand)]
+    command: IndexCommand,
+  },
+
+  #[command(about = "Choose the provider, API key, default model and data directory, and write the config file")]
+  Setup,
+
+  #[command(about = "Manage the API keys and the GitHub token stored in the system keyring")]
+  Auth {
+    #[command(subcommand)]
+    command: AuthCommand,
+  },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum IndexCommand {
+  #[command(about = "Create an empty collection to ingest into with --collection")]
+  Create {
+    #[arg(value_name = "COLLECTION", help = "name of the collection")]
+    collection: String,
+  },
+
+  #[command(about = "List the collections with their sources, chunks and embedding models")]
+  List,
+
+  #[command(about = "Delete a collection with the sources that are in no other collection")]
+  Delete {
+    #[arg(value_name = "COLLECTION", help = "name of the collection")]
+    collection: String,
+
+    #[arg(long, help = "only delete the collection, keeping its sources", default_value_t = false)]
+    keep_sources: bool,
+
+    #[arg(long, help = "delete without asking", default_value_t = false)]
+    yes: bool,
+  },
+
+  #[command(about = "Embed the stored chunks of a collection again, after changing the model configured for it")]
+  Reembed {
+    #[arg(value_name = "COLLECTION", help = "collection to re-embed, the sources in no collection when omitted")]
+    collection: Option<String>,
+
+    #[arg(
+      long,
+      value_name = "COLLECTION",
+      help = "move the re-embedded sources into this collection, embedding with the model configured for it"
+    )]
+    into: Option<String>,
+  },
+
+  #[command(about = "Show the size of a collection and the sources in it")]
+  Stats {
+    #[arg(value_name = "COLLECTION", help = "name of the collection")]
+    collection: String,
+  },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuthCommand {
+  #[command(about = "Store an API key in the keyring, read from the terminal or from stdin")]
+  Set {
+    #[arg(
+      value_name = "PROVIDER",
+      help = "openai, openrouter, github for sharing gists, or serve for the token of sazid serve",
+      default_value = "openai"
+    )]
+    provider: String,
+  },
+
+  #[command(about = "Show where each provider's API key is read from")]
+  Status,
+
+  #[command(about = "Remove an API key from the keyring")]
+  Remove {
+    #[arg(
+      value_name = "PROVIDER",
+      help = "openai, openrouter, github for sharing gists, or serve for the token of sazid serve",
+      default_value = "openai"
+    )]
+    provider: String,
+  },
 }
@@ -11,8 +11,17 @@ use crate::{
   tui::{Event, Frame},
 };
 
+pub mod attachments;
+pub mod choices;
 pub mod home;
+pub mod inspector;
+pub mod log_viewer;
+pub mod patch_review;
+pub mod scratchpad;
 pub mod session;
+pub mod setup_form;
+pub mod sources;
+pub mod stats;
 
 pub trait Component {
   #[allow(unused_variables)]
@@ -0,0 +1,46 @@
+use ratatui::{prelude::*, widgets::*};
+
+use crate::app::{attachments::AttachedFile, i18n::tr, theme};
+
+// an overlay with the files attached to the session, how each is sent and what it adds to every request
+pub fn draw_attachments(f: &mut Frame<'_>, area: Rect, files: &[AttachedFile]) {
+  let theme = theme::current();
+  let popup_width = area.width.saturating_sub(4).min(100);
+  let popup_height = (files.len().max(1) as u16 + 4).min(area.height.saturating_sub(2));
+  let popup = Rect::new(
+    area.x + (area.width.saturating_sub(popup_width)) / 2,
+    area.y + (area.height.saturating_sub(popup_height)) / 2,
+    popup_width,
+    popup_height,
+  );
+  let block =
+    Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).border_style(theme.border()).title(
+      Line::from(vec![
+        Span::raw(tr("Attached Files ")),
+        Span::styled("(", Style::default().fg(Color::DarkGray)),
+        Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" to close)"), Style::default().fg(Color::DarkGray)),
+      ]),
+    );
+  let mut lines = files
+    .iter()
+    .map(|file| {
+      Line::from(vec![
+        Span::styled(format!("{:>7} ", file.tokens), Style::default().fg(Color::Cyan)),
+        Span::raw(file.path.display().to_string()),
+        Span::styled(format!(" ({})", file.describe()), theme.hint()),
+      ])
+    })
+    .collect::<Vec<Line>>();
+  if files.is_empty() {
+    lines.push(Line::styled(tr("no files attached, :attach <path> adds one"), theme.hint()));
+  }
+  let total = files.iter().map(|file| file.tokens).sum::<usize>();
+  lines.push(Line::from(""));
+  lines.push(Line::from(vec![
+    Span::styled(format!("{:>7} ", total), Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+    Span::raw(tr("tokens added to every request")),
+  ]));
+  f.render_widget(Clear, popup);
+  f.render_widget(Paragraph::new(lines).block(block), popup);
+}
@@ -0,0 +1,83 @@
+use ratatui::{prelude::*, widgets::*};
+
+use crate::app::{i18n::tr, theme};
+
+// the choices of a response asked for with n > 1, or the responses of two models in a/b mode, side by side until
+// one is kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChoiceComparison {
+  pub title: String,
+  // the response in the transcript
+  pub message: usize,
+  // the index and the text of each choice
+  pub choices: Vec<(usize, String)>,
+  // the header of each choice's pane
+  pub titles: Vec<String>,
+  pub highlighted: usize,
+  pub scroll: u16,
+}
+
+impl ChoiceComparison {
+  pub fn new(message: usize, choices: Vec<(usize, String)>) -> Self {
+    let titles = choices.iter().map(|(index, _)| format!("{} {}", tr("Choice"), index + 1)).collect();
+    ChoiceComparison { title: tr("Compare Choices "), message, choices, titles, highlighted: 0, scroll: 0 }
+  }
+
+  // the annotation and the text of each model's response, the session's model first
+  pub fn models(message: usize, responses: Vec<(String, String)>) -> Self {
+    let (titles, texts): (Vec<String>, Vec<String>) = responses.into_iter().unzip();
+    let choices = texts.into_iter().enumerate().collect();
+    ChoiceComparison { title: tr("Compare Models "), message, choices, titles, highlighted: 0, scroll: 0 }
+  }
+
+  pub fn select_next(&mut self) {
+    self.highlighted = (self.highlighted + 1).min(self.choices.len().saturating_sub(1));
+  }
+
+  pub fn select_previous(&mut self) {
+    self.highlighted = self.highlighted.saturating_sub(1);
+  }
+
+  // the index of the highlighted choice
+  pub fn selected(&self) -> Option<usize> {
+    self.choices.get(self.highlighted).map(|(index, _)| *index)
+  }
+
+  pub fn draw(&self, f: &mut Frame<'_>, area: Rect) {
+    let theme = theme::current();
+    let popup = Rect::new(area.x + 1, area.y + 1, area.width.saturating_sub(2), area.height.saturating_sub(2));
+    let block =
+      Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).border_style(theme.border()).title(
+        Line::from(vec![
+          Span::raw(self.title.as_str()),
+          Span::styled("(", theme.hint()),
+          Span::styled("h/l", theme.hint_key()),
+          Span::styled(tr(" to move, "), theme.hint()),
+          Span::styled("Enter", theme.hint_key()),
+          Span::styled(tr(" to keep one, "), theme.hint()),
+          Span::styled("ESC", theme.hint_key()),
+          Span::styled(tr(" to keep the first)"), theme.hint()),
+        ]),
+      );
+    f.render_widget(Clear, popup);
+    let inner = block.inner(popup);
+    f.render_widget(block, popup);
+    let columns = Layout::default()
+      .direction(Direction::Horizontal)
+      .constraints(vec![Constraint::Ratio(1, self.choices.len().max(1) as u32); self.choices.len()])
+      .split(inner);
+    for (position, (((_, text), title), column)) in
+      self.choices.iter().zip(self.titles.iter()).zip(columns.iter()).enumerate()
+    {
+      let border = match position == self.highlighted {
+        true => theme.border_focused(),
+        false => theme.border(),
+      };
+      let choice = Block::default().borders(Borders::ALL).border_style(border).title(format!(" {} ", title));
+      f.render_widget(
+        Paragraph::new(text.as_str()).block(choice).wrap(Wrap { trim: false }).scroll((self.scroll, 0)),
+        *column,
+      );
+    }
+  }
+}
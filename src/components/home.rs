@@ -1,19 +1,46 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use super::{Component, Frame};
 use crate::{
   action::Action,
-  app::{color_math::get_rainbow_and_inverse_colors, errors::SazidError},
-  components::session::Session,
+  app::{
+    attachments::summarize_attachment,
+    autosuggest::PromptSuggester,
+    citations::{retrieve_citations, Citation},
+    functions::sandbox::Resource,
+    color_math::get_rainbow_and_inverse_colors,
+    errors::SazidError,
+    i18n::tr,
+    input_history::InputHistory,
+    messages::ChatMessage,
+    model_list::{fetch_model_listings, ModelListing},
+    session_stats::{ApiHealth, ApiStatus},
+    summarize::{summarize_source, SummaryEstimate, SummaryProgress},
+    theme,
+  },
+  components::{
+    patch_review::PatchReviewPane,
+    session::Session,
+    sources::{spawn_source_operation, SourceManager, SourceOperation},
+  },
   config::Config,
   trace_dbg,
 };
 
+use async_openai::types::{
+  ChatCompletionMessageToolCall, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
+};
 use color_eyre::eyre::Result;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use log::error;
 use rand;
-use ratatui::{prelude::*, widgets::*};
+use ratatui::{
+  prelude::*,
+  widgets::{
+    block::{Position, Title},
+    *,
+  },
+};
 
 use tokio::sync::mpsc::UnboundedSender;
 use tui_textarea::{CursorMove, TextArea};
@@ -44,6 +71,96 @@ pub struct Home<'a> {
   pub color_counter: u32,
   pub rgb: Color,
   pub inv_rgb: Color,
+  pub suggester: PromptSuggester,
+  pub suggestion: Option<String>,
+  pub history: InputHistory,
+  pub history_search: Option<HistorySearch>,
+  // the input last sent to the session for autosave
+  pub autosaved_draft: String,
+  pub model_picker: Option<ModelPicker>,
+  pub source_manager: Option<SourceManager>,
+  // why the pending request needs to be confirmed before it is sent
+  pub confirm_request: Option<String>,
+  // a tool call that changes the repository, with what it will do, waiting for the user
+  pub confirm_tool_call: Option<(ChatCompletionMessageToolCall, String)>,
+  // an example of the last response waiting for the user before it runs
+  pub confirm_example: Option<(usize, String)>,
+  pub patch_review: Option<PatchReviewPane>,
+  // a tool call waiting for the user to allow what the sandbox policy doesn't cover yet
+  pub request_permission: Option<(ChatCompletionMessageToolCall, Vec<Resource>)>,
+  // tool calls of the same response that need the user, shown one at a time
+  pub queued_tool_dialogs: VecDeque<Action>,
+  // the retrieved chunk shown after pressing its citation number
+  pub citation: Option<Citation>,
+  // latency and health of the last request, shown in the status line
+  pub api_status: Option<ApiStatus>,
+}
+
+#[derive(Debug, Default)]
+pub struct ModelPicker {
+  pub listings: Vec<ModelListing>,
+  pub state: ListState,
+}
+
+impl ModelPicker {
+  pub fn new(listings: Vec<ModelListing>) -> Self {
+    let mut state = ListState::default();
+    state.select(if listings.is_empty() { None } else { Some(0) });
+    ModelPicker { listings, state }
+  }
+
+  pub fn select_next(&mut self) {
+    if let Some(i) = self.state.selected() {
+      self.state.select(Some((i + 1).min(self.listings.len().saturating_sub(1))));
+    }
+  }
+
+  pub fn select_previous(&mut self) {
+    if let Some(i) = self.state.selected() {
+      self.state.select(Some(i.saturating_sub(1)));
+    }
+  }
+
+  pub fn selected(&self) -> Option<&ModelListing> {
+    self.state.selected().and_then(|i| self.listings.get(i))
+  }
+}
+
+// ctrl-r search over the prompt history, typing narrows the matches
+#[derive(Debug, Default)]
+pub struct HistorySearch {
+  pub query: String,
+  pub matches: Vec<String>,
+  pub state: ListState,
+}
+
+impl HistorySearch {
+  pub fn new(query: String, history: &InputHistory) -> Self {
+    let mut search = HistorySearch { query, ..Default::default() };
+    search.update(history);
+    search
+  }
+
+  pub fn update(&mut self, history: &InputHistory) {
+    self.matches = history.search(&self.query);
+    self.state.select(if self.matches.is_empty() { None } else { Some(0) });
+  }
+
+  pub fn select_next(&mut self) {
+    if let Some(i) = self.state.selected() {
+      self.state.select(Some((i + 1).min(self.matches.len().saturating_sub(1))));
+    }
+  }
+
+  pub fn select_previous(&mut self) {
+    if let Some(i) = self.state.selected() {
+      self.state.select(Some(i.saturating_sub(1)));
+    }
+  }
+
+  pub fn selected(&self) -> Option<&String> {
+    self.state.selected().and_then(|i| self.matches.get(i))
+  }
 }
 
 const MAX24BIT: u32 = 16777216;
@@ -69,6 +186,63 @@ impl<'a> Home<'a> {
       self.input.move_cursor(CursorMove::End);
       self.input.delete_line_by_head();
     }
+    self.suggestion = None;
+  }
+
+  pub fn update_suggestion(&mut self) {
+    let (row, col) = self.input.cursor();
+    let lines = self.input.lines();
+    // only suggest when the cursor is at the end of the input
+    self.suggestion = if row + 1 == lines.len() && col == lines[row].chars().count() {
+      self.suggester.suggest(&lines.join("\n"))
+    } else {
+      None
+    };
+  }
+
+  fn tool_dialog_open(&self) -> bool {
+    self.confirm_tool_call.is_some() || self.request_permission.is_some() || self.patch_review.is_some()
+  }
+
+  fn show_queued_tool_dialog(&mut self) -> Result<(), SazidError> {
+    if let Some(action) = self.queued_tool_dialogs.pop_front() {
+      self.update(action)?;
+    }
+    Ok(())
+  }
+
+  fn record_history(&mut self, input: &str) {
+    self.suggester.record(input);
+    if let Err(e) = self.history.record(input) {
+      error!("Failed to save the prompt history: {}", e);
+    }
+  }
+
+  // up on the first line and down on the last line of the input browse the prompt history
+  fn recall_history(&mut self, older: bool) -> bool {
+    let (row, _) = self.input.cursor();
+    let recalled = match older {
+      true if row == 0 => self.history.previous(&self.input.lines().join("\n")),
+      false if row + 1 == self.input.lines().len() => self.history.next(),
+      _ => None,
+    };
+    match recalled {
+      Some(prompt) => {
+        self.replace_input(prompt);
+        true
+      },
+      None => false,
+    }
+  }
+
+  pub fn accept_suggestion(&mut self) -> bool {
+    match self.suggestion.take() {
+      Some(suggestion) => {
+        self.input.insert_str(&suggestion);
+        true
+      },
+      None => false,
+    }
   }
 }
 
@@ -76,11 +250,13 @@ impl Component for Home<'static> {
   fn init(&mut self, _area: Rect) -> Result<(), SazidError> {
     self.color_counter = rand::random::<u32>() % MAX24BIT;
     self.input = TextArea::default();
-    self.input.set_placeholder_text("press i to enter input mode");
+    self.input.set_placeholder_text(tr("press i to enter input mode"));
     self.input.set_placeholder_style(Style::reset().fg(Color::Magenta));
     self.input.set_cursor_line_style(Style::reset().fg(Color::Yellow));
 
     self.input.set_cursor_style(Style::default().add_modifier(Modifier::SLOW_BLINK));
+    self.suggester = PromptSuggester::load();
+    self.history = InputHistory::load();
     Ok(())
   }
   fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<(), SazidError> {
@@ -101,7 +277,14 @@ impl Component for Home<'static> {
         (self.rgb, self.inv_rgb) = get_rainbow_and_inverse_colors(self.color_counter, MAX24BIT);
         self.input.set_cursor_style(self.input.cursor_style().bg(self.rgb).fg(self.inv_rgb));
       },
-      Action::Tick => self.tick(),
+      Action::Tick => {
+        self.tick();
+        let draft = self.input.lines().join("\n");
+        if self.mode != Mode::Command && draft != self.autosaved_draft {
+          self.autosaved_draft = draft.clone();
+          return Ok(Some(Action::AutosaveDraft(draft)));
+        }
+      },
       // Action::Render => self.render_tick(),
       // Action::ToggleShowHelp => self.show_help = !self.show_help,
       // Action::ScheduleIncrement => self.schedule_increment(1),
@@ -113,6 +296,7 @@ impl Component for Home<'static> {
         trace_dbg!("update status: {:?}", s);
         self.status = s;
       },
+      Action::UpdateApiStatus(api_status) => self.api_status = Some(api_status),
       Action::EnterCommand => {
         self.mode = Mode::Command;
       },
@@ -126,10 +310,136 @@ impl Component for Home<'static> {
         trace_dbg!("enter insert mode");
         self.mode = Mode::Insert;
       },
+      Action::RestoreDraft(draft) => {
+        self.replace_input(draft);
+        self.mode = Mode::Insert;
+      },
       Action::CommandResult(result) => {
         self.replace_input(result);
         self.mode = Mode::Command;
       },
+      Action::SetOffline(offline) => {
+        self.config.offline = offline;
+        self.config.session_config.set_offline(offline);
+      },
+      Action::OpenModelPicker => {
+        let tx = self.action_tx.clone().unwrap();
+        let openai_config = self.config.session_config.openai_config.clone();
+        let pricing = self.config.model_pricing.clone();
+        self.status = Some("fetching models".to_string());
+        tokio::spawn(async move {
+          match fetch_model_listings(&openai_config, &pricing).await {
+            Ok(listings) => tx.send(Action::ModelListings(listings)).unwrap(),
+            Err(e) => tx.send(Action::Error(format!("Failed to fetch models: {}", e))).unwrap(),
+          }
+        });
+      },
+      Action::ModelListings(listings) => {
+        self.status = None;
+        self.model_picker = Some(ModelPicker::new(listings));
+      },
+      Action::ConfirmRequest(description) => {
+        self.status = Some("confirm request".to_string());
+        self.confirm_request = Some(description);
+      },
+      Action::ConfirmExample(example_index, description) => {
+        self.status = Some("confirm example".to_string());
+        self.confirm_example = Some((example_index, description));
+      },
+      Action::ConfirmToolCall(..) | Action::RequestPermission(..) | Action::ReviewPatch(..)
+        if self.tool_dialog_open() =>
+      {
+        self.queued_tool_dialogs.push_back(action)
+      },
+      Action::ConfirmToolCall(tool_call, description) => {
+        self.status = Some("confirm tool call".to_string());
+        self.confirm_tool_call = Some((tool_call, description));
+      },
+      Action::RequestPermission(tool_call, resources) => {
+        self.status = Some("allow tool access".to_string());
+        self.request_permission = Some((tool_call, resources));
+      },
+      Action::ReviewPatch(tool_call, review) => {
+        self.status = Some("review patch".to_string());
+        self.patch_review = Some(PatchReviewPane::new(tool_call, review));
+      },
+      Action::OpenSourceManager => {
+        self.status = Some("loading ingested sources".to_string());
+        spawn_source_operation(self.config.clone(), self.action_tx.clone().unwrap(), SourceOperation::List);
+      },
+      Action::IngestedSources(sources) => {
+        self.status = None;
+        match self.source_manager.as_mut() {
+          Some(source_manager) => source_manager.replace_sources(sources, &self.config),
+          None => self.source_manager = Some(SourceManager::new(sources, &self.config)),
+        }
+      },
+      Action::SourceChunks(source_id, chunks) => {
+        self.status = None;
+        if let Some(source_manager) = self.source_manager.as_mut() {
+          source_manager.show_chunks(source_id, chunks);
+        }
+      },
+      Action::SummarizeSource(source) => {
+        let tx = self.action_tx.clone().unwrap();
+        let config = self.config.clone();
+        self.status = Some(format!("reading {}", source));
+        tokio::spawn(async move {
+          let on_estimate = |estimate: &SummaryEstimate| {
+            tx.send(Action::UpdateStatus(Some(format!("summarizing {}: {}", source, estimate)))).unwrap();
+          };
+          let on_progress = |progress: SummaryProgress| {
+            tx.send(Action::UpdateStatus(Some(progress.to_string()))).unwrap();
+          };
+          match summarize_source(&config, &source, on_estimate, on_progress).await {
+            Ok(summary) => {
+              let content = format!("Summary of {}:\n{}", source, summary);
+              tx.send(Action::AddMessage(ChatMessage::User(ChatCompletionRequestUserMessage {
+                role: Role::User,
+                content: Some(ChatCompletionRequestUserMessageContent::Text(content)),
+              })))
+              .unwrap();
+              tx.send(Action::UpdateStatus(Some(format!("attached summary of {}", source)))).unwrap();
+            },
+            Err(e) => tx.send(Action::Error(format!("Failed to summarize {}: {}", source, e))).unwrap(),
+          }
+        });
+      },
+      Action::RetrieveContext(input, settings) => {
+        let tx = self.action_tx.clone().unwrap();
+        let config = self.config.clone();
+        self.status = Some("retrieving sources".to_string());
+        tokio::spawn(async move {
+          // the request is still sent without sources when retrieval fails
+          let citations = match retrieve_citations(&config, &input, &settings).await {
+            Ok(citations) => citations,
+            Err(e) => {
+              tx.send(Action::UpdateStatus(Some(format!("Failed to retrieve sources: {}", e)))).unwrap();
+              vec![]
+            },
+          };
+          tx.send(Action::AddCitations(citations)).unwrap();
+        });
+      },
+      Action::SummarizeAttachment(path) => {
+        let tx = self.action_tx.clone().unwrap();
+        let config = self.config.clone();
+        tokio::spawn(async move {
+          // the excerpt is sent in the meantime, and the summary is tried again with the next request on failure
+          match summarize_attachment(&config, &path).await {
+            Ok(summary) => tx.send(Action::AttachmentSummarized(path, Some(summary))).unwrap(),
+            Err(e) => {
+              tx.send(Action::UpdateStatus(Some(format!("Failed to summarize {}: {}", path.display(), e)))).unwrap();
+              tx.send(Action::AttachmentSummarized(path, None)).unwrap();
+            },
+          }
+        });
+      },
+      Action::ShowCitation(citation) => self.citation = Some(citation),
+      Action::SelectModel(model) => {
+        self.status = Some(format!("using {}", model.name));
+        self.config.session_config.model = model;
+      },
       Action::EnterProcessing => {
         self.clear_input();
         self.mode = Mode::Processing;
@@ -147,6 +457,131 @@ impl Component for Home<'static> {
     let tx = self.action_tx.clone().unwrap();
     self.last_events.push(key);
 
+    if let Some(model_picker) = self.model_picker.as_mut() {
+      let action = match key.code {
+        KeyCode::Down | KeyCode::Char('j') => {
+          model_picker.select_next();
+          Action::Update
+        },
+        KeyCode::Up | KeyCode::Char('k') => {
+          model_picker.select_previous();
+          Action::Update
+        },
+        KeyCode::Enter => {
+          let action = match model_picker.selected() {
+            Some(listing) => Action::SelectModel(listing.model.clone()),
+            None => Action::Update,
+          };
+          self.model_picker = None;
+          action
+        },
+        KeyCode::Esc => {
+          self.model_picker = None;
+          Action::Update
+        },
+        _ => Action::Update,
+      };
+      return Ok(Some(action));
+    }
+
+    if let Some(history_search) = self.history_search.as_mut() {
+      match key {
+        KeyEvent { code: KeyCode::Down, .. } => history_search.select_next(),
+        KeyEvent { code: KeyCode::Up, .. } => history_search.select_previous(),
+        KeyEvent { code: KeyCode::Char('r'), modifiers: KeyModifiers::CONTROL, .. } => history_search.select_next(),
+        KeyEvent { code: KeyCode::Enter, .. } => {
+          if let Some(prompt) = history_search.selected().cloned() {
+            self.replace_input(prompt);
+          }
+          self.history_search = None;
+        },
+        KeyEvent { code: KeyCode::Esc, .. } => self.history_search = None,
+        KeyEvent { code: KeyCode::Backspace, .. } => {
+          history_search.query.pop();
+          history_search.update(&self.history);
+        },
+        KeyEvent { code: KeyCode::Char(c), modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT, .. } => {
+          history_search.query.push(c);
+          history_search.update(&self.history);
+        },
+        _ => {},
+      }
+      return Ok(Some(Action::Update));
+    }
+
+    if self.citation.is_some() {
+      if matches!(key.code, KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter) {
+        self.citation = None;
+      }
+      return Ok(Some(Action::Update));
+    }
+
+    if self.confirm_request.is_some() {
+      let confirmed = match key.code {
+        KeyCode::Char('y') | KeyCode::Enter => true,
+        KeyCode::Char('n') | KeyCode::Esc => false,
+        _ => return Ok(Some(Action::Update)),
+      };
+      self.confirm_request = None;
+      self.status = None;
+      return Ok(Some(Action::RequestConfirmed(confirmed)));
+    }
+
+    if self.confirm_example.is_some() {
+      let confirmed = match key.code {
+        KeyCode::Char('y') | KeyCode::Enter => true,
+        KeyCode::Char('n') | KeyCode::Esc => false,
+        _ => return Ok(Some(Action::Update)),
+      };
+      let (example_index, _) = self.confirm_example.take().unwrap();
+      self.status = None;
+      return Ok(Some(Action::ExampleConfirmed(example_index, confirmed)));
+    }
+
+    if self.confirm_tool_call.is_some() {
+      let confirmed = match key.code {
+        KeyCode::Char('y') | KeyCode::Enter => true,
+        KeyCode::Char('n') | KeyCode::Esc => false,
+        _ => return Ok(Some(Action::Update)),
+      };
+      let (tool_call, _) = self.confirm_tool_call.take().unwrap();
+      self.status = None;
+      self.show_queued_tool_dialog()?;
+      return Ok(Some(Action::ToolCallConfirmed(tool_call, confirmed)));
+    }
+
+    if self.request_permission.is_some() {
+      let allowed = match key.code {
+        KeyCode::Char('y') | KeyCode::Enter => true,
+        KeyCode::Char('n') | KeyCode::Esc => false,
+        _ => return Ok(Some(Action::Update)),
+      };
+      let (tool_call, resources) = self.request_permission.take().unwrap();
+      self.status = None;
+      self.show_queued_tool_dialog()?;
+      return Ok(Some(Action::PermissionAnswered(tool_call, resources, allowed)));
+    }
+
+    if let Some(patch_review) = self.patch_review.as_mut() {
+      if let Some(action) = patch_review.handle_key_event(key) {
+        self.patch_review = None;
+        self.status = None;
+        self.show_queued_tool_dialog()?;
+        return Ok(Some(action));
+      }
+      return Ok(Some(Action::Update));
+    }
+
+    if let Some(source_manager) = self.source_manager.as_mut() {
+      if key.code == KeyCode::Esc && !source_manager.is_editing() {
+        self.source_manager = None;
+      } else if let Some(operation) = source_manager.handle_key_event(key) {
+        self.status = Some("updating sources".to_string());
+        spawn_source_operation(self.config.clone(), tx, operation);
+      }
+      return Ok(Some(Action::Update));
+    }
+
     //trace_dbg!("key: {:#?}\n{:#?}", key, crossterm::event::Event::Key(key));
     //trace_dbg!("insert key: {:?}\n{:?}", key, self.input.cursor());
     let action = match self.mode {
@@ -181,6 +616,7 @@ impl Component for Home<'static> {
           self.input.move_cursor(CursorMove::End);
           self.input.move_cursor(CursorMove::Bottom);
           let input = self.input.lines().join("\n");
+          self.record_history(&input);
 
           if let Err(e) = tx.send(Action::SubmitInput(input)) {
             error!("Failed to send action: {:?}", e);
@@ -191,11 +627,30 @@ impl Component for Home<'static> {
       },
       Mode::Normal | Mode::Processing => return Ok(None),
       Mode::Insert => match key {
-        KeyEvent { code: KeyCode::Esc, .. } => Action::EnterVisual,
+        KeyEvent { code: KeyCode::Esc, .. } => {
+          self.suggestion = None;
+          Action::EnterVisual
+        },
+        KeyEvent { code: KeyCode::Right, modifiers: KeyModifiers::NONE, .. } if self.accept_suggestion() => {
+          Action::Update
+        },
+        KeyEvent { code: KeyCode::Up, modifiers: KeyModifiers::NONE, .. } if self.recall_history(true) => {
+          Action::Update
+        },
+        KeyEvent { code: KeyCode::Down, modifiers: KeyModifiers::NONE, .. } if self.recall_history(false) => {
+          Action::Update
+        },
+        KeyEvent { code: KeyCode::Char('r'), modifiers: KeyModifiers::CONTROL, .. } => {
+          self.suggestion = None;
+          self.history.reset();
+          self.history_search = Some(HistorySearch::new(self.input.lines().join("\n"), &self.history));
+          Action::Update
+        },
         KeyEvent { code: KeyCode::Enter, modifiers: KeyModifiers::ALT, .. } => {
           self.input.move_cursor(CursorMove::End);
           self.input.move_cursor(CursorMove::Bottom);
           let input = self.input.lines().join("\n");
+          self.record_history(&input);
 
           if let Err(e) = tx.send(Action::SubmitInput(input)) {
             error!("Failed to send action: {:?}", e);
@@ -203,7 +658,9 @@ impl Component for Home<'static> {
           Action::EnterNormal
         },
         _ => {
+          self.history.reset();
           self.input.input(crossterm::event::Event::Key(key));
+          self.update_suggestion();
           Action::Update
         },
       },
@@ -212,26 +669,50 @@ impl Component for Home<'static> {
   }
 
   fn draw(&mut self, f: &mut Frame<'_>, area: Rect) -> Result<(), SazidError> {
+    let theme = theme::current();
     let input_length = self.input.clone().into_lines().len() as u16 + 2;
     let tx = self.action_tx.clone().unwrap();
     tx.send(Action::SetInputVsize(input_length)).unwrap();
     let rects =
       Layout::default().constraints([Constraint::Percentage(100), Constraint::Min(input_length)].as_ref()).split(area);
     // let text: Vec<Line> = self.text.clone().iter().map(|l| Line::from(l.clone())).collect();
-    let title_text = Line::from(vec![
+    let offline_indicator = match self.config.session_config.offline {
+      true => {
+        Span::styled(tr(" OFFLINE "), Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD))
+      },
+      false => Span::raw(""),
+    };
+    let api_indicator = match &self.api_status {
+      Some(api_status) => {
+        let color = match api_status.health {
+          ApiHealth::Healthy => Color::Green,
+          ApiHealth::Degraded => Color::Yellow,
+          ApiHealth::Down => Color::Red,
+        };
+        let rate = api_status.tokens_per_second.map(|rate| format!(" {:.0} tok/s", rate)).unwrap_or_default();
+        let latency = format!("{:.1}s{} ", api_status.last_latency_ms as f64 / 1000.0, rate);
+        vec![Span::styled("● ", Style::default().fg(color)), Span::styled(latency, theme.hint())]
+      },
+      None => vec![],
+    };
+    let mut title_spans = api_indicator;
+    title_spans.extend(vec![
+      offline_indicator,
       Span::raw("sazid semantic llvm console "),
       match self.mode {
-        Mode::Command => Span::styled("Command Mode", Style::default().fg(self.rgb)),
-        Mode::Visual => Span::styled("Visual Mode", Style::default().fg(Color::Magenta)),
-        Mode::Normal => Span::styled("Normal Mode", Style::default().fg(Color::Green)),
-        Mode::Insert => Span::styled("Insert Mode", Style::default().fg(Color::Yellow)),
-        Mode::Processing => Span::styled("Processing", Style::default().fg(self.rgb)),
+        Mode::Command => Span::styled(tr("Command Mode"), Style::default().fg(self.rgb)),
+        Mode::Visual => Span::styled(tr("Visual Mode"), Style::default().fg(Color::Magenta)),
+        Mode::Normal => Span::styled(tr("Normal Mode"), Style::default().fg(Color::Green)),
+        Mode::Insert => Span::styled(tr("Insert Mode"), Style::default().fg(Color::Yellow)),
+        Mode::Processing => Span::styled(tr("Processing"), Style::default().fg(self.rgb)),
       },
       match self.status {
-        Some(ref s) => Span::styled(format!(": {}", s), Style::default().fg(Color::Yellow)),
+        Some(ref s) => Span::styled(format!(": {}", s), theme.status_message()),
         None => Span::raw(""),
       },
     ]);
+    title_spans.iter_mut().for_each(|span| span.style = theme.status_bar().patch(span.style));
+    let title_text = Line::from(title_spans);
     f.render_widget(
       Block::default()
         .title(title_text)
@@ -247,45 +728,56 @@ impl Component for Home<'static> {
 
     self.input.set_placeholder_text({
       match self.mode {
-        Mode::Insert => "",
-        _ => "press i to enter input mode",
+        Mode::Insert => String::new(),
+        _ => tr("press i to enter input mode"),
       }
     });
 
+    let suggestion_title = match (&self.mode, &self.suggestion) {
+      (Mode::Insert, Some(suggestion)) => Line::from(vec![
+        Span::styled(suggestion.lines().next().unwrap_or_default().to_string(), theme.hint()),
+        Span::styled(tr(" (press "), theme.hint()),
+        Span::styled("<right>", theme.hint_key()),
+        Span::styled(tr(" to accept)"), theme.hint()),
+      ]),
+      _ => Line::default(),
+    };
+
     self.input.set_block(
       Block::default()
         .borders(Borders::ALL)
+        .title(Title::from(suggestion_title).position(Position::Bottom))
         .title(match self.mode {
           Mode::Command => Line::from(vec![
-            Span::styled("Command Mode", Style::default().fg(self.rgb)),
-            Span::styled("(press ", Style::default().fg(Color::DarkGray)),
-            Span::styled("<alt>-<enter>", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to execute command, ", Style::default().fg(Color::DarkGray)),
-            Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to enter Insert mode)", Style::default().fg(Color::DarkGray)),
+            Span::styled(tr("Command Mode"), Style::default().fg(self.rgb)),
+            Span::styled(tr("(press "), theme.hint()),
+            Span::styled("<alt>-<enter>", theme.hint_key()),
+            Span::styled(tr(" to execute command, "), theme.hint()),
+            Span::styled("ESC", theme.hint_key()),
+            Span::styled(tr(" to enter Insert mode)"), theme.hint()),
           ]),
           Mode::Insert => Line::from(vec![
-            Span::raw("Input Mode"),
-            Span::styled("(press", Style::default().fg(Color::DarkGray)),
-            Span::styled("<alt>-<enter>", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to submit input, ", Style::default().fg(Color::DarkGray)),
-            Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to enter Visual mode)", Style::default().fg(Color::DarkGray)),
+            Span::raw(tr("Input Mode")),
+            Span::styled(tr("(press"), theme.hint()),
+            Span::styled("<alt>-<enter>", theme.hint_key()),
+            Span::styled(tr(" to submit input, "), theme.hint()),
+            Span::styled("ESC", theme.hint_key()),
+            Span::styled(tr(" to enter Visual mode)"), theme.hint()),
           ]),
           Mode::Visual => Line::from(vec![
-            Span::raw("Visual Mode "),
-            Span::styled("(Press ", Style::default().fg(Color::DarkGray)),
-            Span::styled("i", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to enter text, ", Style::default().fg(Color::DarkGray)),
+            Span::raw(tr("Visual Mode ")),
+            Span::styled(tr("(Press "), theme.hint()),
+            Span::styled("i", theme.hint_key()),
+            Span::styled(tr(" to enter text, "), theme.hint()),
           ]),
-          Mode::Processing => Line::from(vec![Span::raw("Awaiting Chat Completion")]),
+          Mode::Processing => Line::from(vec![Span::raw(tr("Awaiting Chat Completion"))]),
           _ => Line::from(vec![
-            Span::raw("Enter Input Mode "),
-            Span::styled("(Press ", Style::default().fg(Color::DarkGray)),
-            Span::styled("i", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to start, ", Style::default().fg(Color::DarkGray)),
-            Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
-            Span::styled(" to finish)", Style::default().fg(Color::DarkGray)),
+            Span::raw(tr("Enter Input Mode ")),
+            Span::styled(tr("(Press "), theme.hint()),
+            Span::styled("i", theme.hint_key()),
+            Span::styled(tr(" to start, "), theme.hint()),
+            Span::styled("ESC", theme.hint_key()),
+            Span::styled(tr(" to finish)"), theme.hint()),
           ]),
         })
         .style(match self.mode {
@@ -312,6 +804,128 @@ impl Component for Home<'static> {
     //     Span::styled(" to finish)", Style::default().fg(Color::DarkGray)),
     //   ])));
     //f.render_widget(input, rects[1]);
+    if let Some(model_picker) = self.model_picker.as_mut() {
+      let current_model = &self.config.session_config.model.name;
+      let items: Vec<ListItem> = model_picker
+        .listings
+        .iter()
+        .map(|listing| {
+          let style = match (listing.available, &listing.model.name == current_model) {
+            (_, true) => Style::default().fg(Color::Green),
+            (true, false) => Style::default(),
+            (false, false) => theme.hint(),
+          };
+          ListItem::new(Line::from(Span::styled(listing.to_string(), style)))
+        })
+        .collect();
+      let popup_width = area.width.saturating_sub(4).min(100);
+      let popup_height = (items.len() as u16 + 2).min(area.height.saturating_sub(2));
+      let popup = Rect::new(
+        area.x + (area.width.saturating_sub(popup_width)) / 2,
+        area.y + (area.height.saturating_sub(popup_height)) / 2,
+        popup_width,
+        popup_height,
+      );
+      let list = List::new(items)
+        .block(
+          Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .title(Line::from(vec![
+              Span::raw(tr("Select Model ")),
+              Span::styled(tr("(press "), theme.hint()),
+              Span::styled("<enter>", theme.hint_key()),
+              Span::styled(tr(" to select, "), theme.hint()),
+              Span::styled("ESC", theme.hint_key()),
+              Span::styled(tr(" to cancel)"), theme.hint()),
+            ])),
+        )
+        .highlight_style(theme.highlight())
+        .highlight_symbol("> ");
+      f.render_widget(Clear, popup);
+      f.render_stateful_widget(list, popup, &mut model_picker.state);
+    }
+    if let Some(history_search) = self.history_search.as_mut() {
+      let items: Vec<ListItem> = history_search
+        .matches
+        .iter()
+        .map(|prompt| ListItem::new(Line::from(prompt.replace('\n', " ⏎ "))))
+        .collect();
+      let popup_width = area.width.saturating_sub(4).min(100);
+      let popup_height = (items.len() as u16 + 2).clamp(3, area.height.saturating_sub(2).max(3));
+      let popup = Rect::new(
+        area.x + (area.width.saturating_sub(popup_width)) / 2,
+        area.y + (area.height.saturating_sub(popup_height)) / 2,
+        popup_width,
+        popup_height.min(area.height),
+      );
+      let list = List::new(items)
+        .block(
+          Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .title(Line::from(vec![
+              Span::raw(format!("{}: {} ", tr("Prompt History"), history_search.query)),
+              Span::styled(tr("(press "), theme.hint()),
+              Span::styled("<enter>", theme.hint_key()),
+              Span::styled(tr(" to use, "), theme.hint()),
+              Span::styled("ESC", theme.hint_key()),
+              Span::styled(tr(" to cancel)"), theme.hint()),
+            ])),
+        )
+        .highlight_style(theme.highlight())
+        .highlight_symbol("> ");
+      f.render_widget(Clear, popup);
+      f.render_stateful_widget(list, popup, &mut history_search.state);
+    }
+    if let Some(source_manager) = self.source_manager.as_mut() {
+      source_manager.draw(f, area);
+    }
+    if let Some(citation) = &self.citation {
+      let popup_width = area.width.saturating_sub(4).min(100);
+      let popup_height = (citation.content.lines().count() as u16 + 2).min(area.height.saturating_sub(2));
+      let popup = Rect::new(
+        area.x + (area.width.saturating_sub(popup_width)) / 2,
+        area.y + (area.height.saturating_sub(popup_height)) / 2,
+        popup_width,
+        popup_height,
+      );
+      let dialog = Paragraph::new(citation.content.as_str()).wrap(Wrap { trim: false }).block(
+        Block::default()
+          .borders(Borders::ALL)
+          .border_type(BorderType::Rounded)
+          .border_style(theme.border())
+          .title(Line::from(vec![
+            Span::raw(format!("{} ", citation.label())),
+            Span::styled("(", theme.hint()),
+            Span::styled("ESC", theme.hint_key()),
+            Span::styled(tr(" to close)"), theme.hint()),
+          ])),
+      );
+      f.render_widget(Clear, popup);
+      f.render_widget(dialog, popup);
+    }
+    if let Some(description) = &self.confirm_request {
+      draw_confirm_dialog(f, area, &tr("Send Large Request? "), "send", description);
+    }
+    if let Some(patch_review) = self.patch_review.as_mut() {
+      patch_review.draw(f, area);
+    }
+    if let Some((tool_call, resources)) = &self.request_permission {
+      let resources = resources.iter().map(|resource| format!("  {}", resource)).collect::<Vec<String>>();
+      let description = format!(
+        "{} wants to use:\n{}\n\nthe answer is kept for the rest of the session",
+        tool_call.function.name,
+        resources.join("\n")
+      );
+      draw_confirm_dialog(f, area, &tr("Allow Tool Access? "), "allow", &description);
+    }
+    if let Some((_, description)) = &self.confirm_tool_call {
+      draw_confirm_dialog(f, area, &tr("Run Tool Call? "), "run", description);
+    }
+    if let Some((_, description)) = &self.confirm_example {
+      draw_confirm_dialog(f, area, &tr("Run Example? "), "run", description);
+    }
     if self.mode == Mode::Insert {
       //f.set_cursor((rects[1].x + 1).min(rects[1].x + rects[1].width - 2), rects[1].y + 1)
       //f.set_cursor((rects[1].x + 1 + self.input.cursor() as u16).min(rects[1].x + rects[1].width - 2), rects[1].y + 1)
@@ -319,3 +933,32 @@ impl Component for Home<'static> {
     Ok(())
   }
 }
+
+// a yes or no question over the session, answered with y or n
+fn draw_confirm_dialog(f: &mut Frame<'_>, area: Rect, title: &str, confirm: &str, description: &str) {
+  let theme = theme::current();
+  let popup_width = area.width.saturating_sub(4).min(90);
+  let popup_height = (description.lines().count() as u16 + 2).min(area.height.saturating_sub(2));
+  let popup = Rect::new(
+    area.x + (area.width.saturating_sub(popup_width)) / 2,
+    area.y + (area.height.saturating_sub(popup_height)) / 2,
+    popup_width,
+    popup_height,
+  );
+  let dialog = Paragraph::new(description).wrap(Wrap { trim: false }).block(
+    Block::default()
+      .borders(Borders::ALL)
+      .border_type(BorderType::Rounded)
+      .border_style(theme.border_focused())
+      .title(Line::from(vec![
+        Span::raw(title.to_string()),
+        Span::styled("(", theme.hint()),
+        Span::styled("y", theme.hint_key()),
+        Span::styled(tr(&format!(" to {}, ", confirm)), theme.hint()),
+        Span::styled("n", theme.hint_key()),
+        Span::styled(tr(" to cancel)"), theme.hint()),
+      ])),
+  );
+  f.render_widget(Clear, popup);
+  f.render_widget(dialog, popup);
+}
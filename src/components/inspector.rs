@@ -0,0 +1,113 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+
+use crate::app::i18n::tr;
+use crate::app::session_stats::Transaction;
+
+// an overlay with the exact json of a request and its response, for debugging function calls and prompt sizes
+#[derive(Debug, Default)]
+pub struct Inspector {
+  // index into the session's transactions
+  pub selected: usize,
+  pub scroll: u16,
+  pub show_response: bool,
+}
+
+impl Inspector {
+  // starts at the most recent request
+  pub fn new(transactions: &[Transaction]) -> Self {
+    Inspector { selected: transactions.len().saturating_sub(1), scroll: 0, show_response: false }
+  }
+
+  // whether the inspector should stay open
+  pub fn handle_key_event(&mut self, key: KeyEvent, transaction_count: usize) -> bool {
+    match key.code {
+      KeyCode::Down | KeyCode::Char('j') => self.scroll = self.scroll.saturating_add(1),
+      KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+      KeyCode::PageDown => self.scroll = self.scroll.saturating_add(20),
+      KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(20),
+      KeyCode::Char('h') | KeyCode::Left => {
+        self.selected = self.selected.saturating_sub(1);
+        self.scroll = 0;
+      },
+      KeyCode::Char('l') | KeyCode::Right => {
+        self.selected = (self.selected + 1).min(transaction_count.saturating_sub(1));
+        self.scroll = 0;
+      },
+      KeyCode::Tab => {
+        self.show_response = !self.show_response;
+        self.scroll = 0;
+      },
+      KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('I') => return false,
+      _ => {},
+    }
+    true
+  }
+
+  pub fn draw(&mut self, f: &mut Frame<'_>, area: Rect, transactions: &[Transaction]) {
+    let popup_width = area.width.saturating_sub(4);
+    let popup_height = area.height.saturating_sub(2);
+    let popup = Rect::new(
+      area.x + (area.width.saturating_sub(popup_width)) / 2,
+      area.y + (area.height.saturating_sub(popup_height)) / 2,
+      popup_width,
+      popup_height,
+    );
+    let mut lines: Vec<Line> = vec![];
+    let heading = Style::default().fg(Color::Cyan);
+    match transactions.get(self.selected) {
+      None => lines.push(Line::from("no requests have been made in this session")),
+      Some(transaction) => {
+        lines.push(Line::from(Span::styled(
+          format!(
+            "{}, {} prompt and {} completion tokens, {} ms",
+            transaction.model, transaction.prompt_tokens, transaction.completion_tokens, transaction.latency_ms
+          ),
+          heading,
+        )));
+        match &transaction.raw {
+          None => lines.push(Line::from("the raw request is only kept for requests made since the program started")),
+          Some(raw) if self.show_response => {
+            lines.push(Line::from(Span::styled("response", heading)));
+            lines.extend(raw.response.lines().map(|line| Line::from(line.to_string())));
+          },
+          Some(raw) => {
+            lines.push(Line::from(Span::styled(format!("POST {}", raw.url), heading)));
+            lines.extend(raw.headers.iter().map(|(name, value)| {
+              Line::from(vec![
+                Span::styled(format!("{}: ", name), Style::default().fg(Color::DarkGray)),
+                Span::raw(value.clone()),
+              ])
+            }));
+            lines.push(Line::default());
+            lines.extend(raw.request.lines().map(|line| Line::from(line.to_string())));
+          },
+        }
+      },
+    }
+    let position = format!(
+      "Inspector, {} {} of {} ",
+      if self.show_response { "response" } else { "request" },
+      (self.selected + 1).min(transactions.len()),
+      transactions.len()
+    );
+    let title = Line::from(vec![
+      Span::raw(position),
+      Span::styled("(", Style::default().fg(Color::DarkGray)),
+      Span::styled("h", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled("/", Style::default().fg(Color::DarkGray)),
+      Span::styled("l", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" previous/next request, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("<tab>", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" request/response, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" to close)"), Style::default().fg(Color::DarkGray)),
+    ]);
+    self.scroll = self.scroll.min(lines.len().saturating_sub(1) as u16);
+    let pane = Paragraph::new(lines)
+      .scroll((self.scroll, 0))
+      .block(Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).title(title));
+    f.render_widget(Clear, popup);
+    f.render_widget(pane, popup);
+  }
+}
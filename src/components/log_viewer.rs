@@ -0,0 +1,70 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+use tracing::Level;
+
+use crate::app::i18n::tr;
+use crate::utils::{recent_logs, LogEntry};
+
+// an overlay with the recent warnings and errors, newest at the bottom
+#[derive(Debug, Default)]
+pub struct LogViewer {
+  // lines scrolled up from the newest entry
+  pub scroll: usize,
+}
+
+impl LogViewer {
+  // whether the viewer should stay open
+  pub fn handle_key_event(&mut self, key: KeyEvent) -> bool {
+    match key.code {
+      KeyCode::Up | KeyCode::Char('k') => self.scroll += 1,
+      KeyCode::Down | KeyCode::Char('j') => self.scroll = self.scroll.saturating_sub(1),
+      KeyCode::Char('G') => self.scroll = 0,
+      KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('L') => return false,
+      _ => {},
+    }
+    true
+  }
+
+  pub fn draw(&mut self, f: &mut Frame<'_>, area: Rect) {
+    let entries = recent_logs();
+    let popup_width = area.width.saturating_sub(4);
+    let popup_height = area.height.saturating_sub(2);
+    let popup = Rect::new(
+      area.x + (area.width.saturating_sub(popup_width)) / 2,
+      area.y + (area.height.saturating_sub(popup_height)) / 2,
+      popup_width,
+      popup_height,
+    );
+    let lines = entries.iter().map(log_line).collect::<Vec<Line>>();
+    let visible = popup_height.saturating_sub(2) as usize;
+    self.scroll = self.scroll.min(lines.len().saturating_sub(visible));
+    let offset = lines.len().saturating_sub(visible + self.scroll);
+    let title = Line::from(vec![
+      Span::raw(format!("Log, {} warnings and errors ", entries.len())),
+      Span::styled("(", Style::default().fg(Color::DarkGray)),
+      Span::styled("j", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled("/", Style::default().fg(Color::DarkGray)),
+      Span::styled("k", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" to scroll, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" to close)"), Style::default().fg(Color::DarkGray)),
+    ]);
+    let pane = Paragraph::new(lines)
+      .scroll((offset as u16, 0))
+      .block(Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).title(title));
+    f.render_widget(Clear, popup);
+    f.render_widget(pane, popup);
+  }
+}
+
+fn log_line(entry: &LogEntry) -> Line<'static> {
+  let color = match entry.level {
+    Level::ERROR => Color::Red,
+    _ => Color::Yellow,
+  };
+  Line::from(vec![
+    Span::styled(format!("{:<5} ", entry.level), Style::default().fg(color)),
+    Span::styled(format!("{} ", entry.target), Style::default().fg(Color::DarkGray)),
+    Span::raw(entry.message.clone()),
+  ])
+}
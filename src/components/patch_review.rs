@@ -0,0 +1,131 @@
+use async_openai::types::ChatCompletionMessageToolCall;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+
+use crate::{
+  action::Action,
+  app::{
+    functions::unified_diff::{FileChange, PatchReview},
+    i18n::tr,
+  },
+};
+
+// an overlay showing a patch the model proposed, each hunk is accepted or rejected before anything is written
+#[derive(Debug)]
+pub struct PatchReviewPane {
+  pub tool_call: ChatCompletionMessageToolCall,
+  pub review: PatchReview,
+  // index of the selected hunk across all files
+  pub selected: usize,
+}
+
+impl PatchReviewPane {
+  pub fn new(tool_call: ChatCompletionMessageToolCall, review: PatchReview) -> Self {
+    PatchReviewPane { tool_call, review, selected: 0 }
+  }
+
+  fn set_selected_accepted(&mut self, accepted: Option<bool>) {
+    if let Some(hunk) = self.review.hunk_mut(self.selected) {
+      hunk.accepted = accepted.unwrap_or(!hunk.accepted);
+    }
+  }
+
+  // the action that closes the pane, once the review is finished
+  pub fn handle_key_event(&mut self, key: KeyEvent) -> Option<Action> {
+    match key.code {
+      KeyCode::Down | KeyCode::Char('j') => {
+        self.selected = (self.selected + 1).min(self.review.hunk_count().saturating_sub(1));
+      },
+      KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+      KeyCode::Char('y') => {
+        self.set_selected_accepted(Some(true));
+        self.selected = (self.selected + 1).min(self.review.hunk_count().saturating_sub(1));
+      },
+      KeyCode::Char('n') => {
+        self.set_selected_accepted(Some(false));
+        self.selected = (self.selected + 1).min(self.review.hunk_count().saturating_sub(1));
+      },
+      KeyCode::Char(' ') => self.set_selected_accepted(None),
+      KeyCode::Char('A') => self.review.set_all_accepted(true),
+      KeyCode::Char('R') => self.review.set_all_accepted(false),
+      KeyCode::Enter => return Some(Action::PatchReviewed(self.tool_call.clone(), self.review.clone())),
+      KeyCode::Esc => {
+        self.review.set_all_accepted(false);
+        return Some(Action::PatchReviewed(self.tool_call.clone(), self.review.clone()));
+      },
+      _ => {},
+    }
+    None
+  }
+
+  pub fn draw(&mut self, f: &mut Frame<'_>, area: Rect) {
+    let mut lines: Vec<Line> = vec![];
+    let mut selected_line = 0;
+    let mut index = 0;
+    for file in &self.review.files {
+      let change = match file.change {
+        FileChange::Modify => "",
+        FileChange::Create => " (new file)",
+        FileChange::Delete => " (deleted)",
+      };
+      let header = format!("{}{}", file.path, change);
+      lines.push(Line::from(Span::styled(header, Style::default().add_modifier(Modifier::BOLD))));
+      for hunk in &file.hunks {
+        let marker = if hunk.accepted { "[x]" } else { "[ ]" };
+        let mut style = Style::default().fg(Color::Cyan);
+        if index == self.selected {
+          selected_line = lines.len();
+          style = style.add_modifier(Modifier::REVERSED);
+        }
+        lines.push(Line::from(Span::styled(format!("{} {}", marker, hunk.header), style)));
+        for diff_line in &hunk.diff_lines {
+          let style = match diff_line.chars().next() {
+            Some('+') => Style::default().fg(Color::Green),
+            Some('-') => Style::default().fg(Color::Red),
+            _ => Style::default(),
+          };
+          let style = if hunk.accepted { style } else { style.add_modifier(Modifier::DIM) };
+          lines.push(Line::from(Span::styled(diff_line.clone(), style)));
+        }
+        index += 1;
+      }
+    }
+    let popup_width = area.width.saturating_sub(4).min(140);
+    let popup_height = (lines.len() as u16 + 2).max(6).min(area.height.saturating_sub(2));
+    let popup = Rect::new(
+      area.x + (area.width.saturating_sub(popup_width)) / 2,
+      area.y + (area.height.saturating_sub(popup_height)) / 2,
+      popup_width,
+      popup_height,
+    );
+    // keeps the selected hunk header near the top
+    let scroll = (selected_line as u16).saturating_sub(1);
+    let (accepted, hunks) = (self.review.accepted_count(), self.review.hunk_count());
+    let accepted = format!("Review Patch, {} of {} hunks accepted ", accepted, hunks);
+    let title = Line::from(vec![
+      Span::raw(accepted),
+      Span::styled("(", Style::default().fg(Color::DarkGray)),
+      Span::styled("y", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" accept, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("n", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" reject, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("A", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled("/", Style::default().fg(Color::DarkGray)),
+      Span::styled("R", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" all, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("<enter>", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" to write, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" to reject the patch)"), Style::default().fg(Color::DarkGray)),
+    ]);
+    let pane = Paragraph::new(lines).scroll((scroll, 0)).block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(Color::Yellow))
+        .title(title),
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(pane, popup);
+  }
+}
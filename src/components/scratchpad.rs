@@ -0,0 +1,35 @@
+use ratatui::{prelude::*, widgets::*};
+
+use crate::app::{
+  agent::{Agent, EntryKind},
+  i18n::tr,
+  theme,
+};
+
+// the agent's goal and its notes, calls and their output, beside the transcript, the latest entries stay in view
+pub fn draw_scratchpad(f: &mut Frame<'_>, area: Rect, agent: &Agent) {
+  let theme = theme::current();
+  let block =
+    Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).border_style(theme.border()).title(
+      Line::from(vec![Span::raw(tr("Scratchpad ")), Span::styled(format!("({})", agent.describe()), theme.hint())]),
+    );
+  let inner = block.inner(area);
+  let width = inner.width.max(1) as usize;
+  let mut lines = textwrap::wrap(&format!("goal: {}", agent.goal), width)
+    .into_iter()
+    .map(|line| Line::styled(line.to_string(), Style::default().add_modifier(Modifier::BOLD)))
+    .collect::<Vec<Line>>();
+  for entry in agent.scratchpad.iter() {
+    let (label, color) = match entry.kind {
+      EntryKind::Thought => ("thought", theme.roles.assistant),
+      EntryKind::Action => ("action", theme.roles.tool),
+      EntryKind::Observation => ("observation", theme.roles.system),
+    };
+    lines.push(Line::from(""));
+    lines.push(Line::styled(format!("{} {}", entry.iteration, label), Style::default().fg(color.0)));
+    lines.extend(textwrap::wrap(&entry.text, width).into_iter().map(|line| Line::raw(line.to_string())));
+  }
+  let offset = lines.len().saturating_sub(inner.height as usize);
+  f.render_widget(Clear, area);
+  f.render_widget(Paragraph::new(lines).scroll((offset as u16, 0)).block(block), area);
+}
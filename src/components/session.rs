@@ -1,8 +1,8 @@
 use ansi_to_tui::IntoText;
 use async_openai::types::{
-  ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage, ChatCompletionRequestUserMessage,
-  ChatCompletionRequestUserMessageContent, CreateChatCompletionRequest, CreateEmbeddingRequestArgs,
-  CreateEmbeddingResponse, Role,
+  ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+  ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, CreateChatCompletionRequest,
+  CreateEmbeddingRequestArgs, CreateEmbeddingResponse, FinishReason, Role,
 };
 use clipboard::{ClipboardContext, ClipboardProvider};
 use color_eyre::owo_colors::OwoColorize;
@@ -14,37 +14,93 @@ use nu_ansi_term::Style;
 use ratatui::layout::Rect;
 use ratatui::{prelude::*, widgets::block::*, widgets::*};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::default::Default;
 use std::path::{Path, PathBuf};
 use std::result::Result;
+use std::time::Instant;
 use std::{fs, io};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
 use tui_textarea::TextArea;
 use tui_textarea::{CursorMove, Scrolling};
 
-use async_openai::{config::OpenAIConfig, Client};
+use async_openai::{
+  config::{Config as _, OpenAIConfig},
+  error::OpenAIError,
+  Client,
+};
 
 use super::{Component, Frame};
-use crate::app::functions::{all_functions, handle_tool_call};
+use crate::app::agent::{Agent, AgentStatus};
+use crate::app::attachments::{attachments_message, AttachedFile, Attachment};
+use crate::app::citations::{context_message, RetrievalSettings, DEFAULT_RETRIEVED_CHUNKS};
+use crate::app::compression::{compress_messages, message_text};
+use crate::app::dry_run::describe_request;
+use crate::app::export::{
+  share::{share, ShareTarget},
+  Transcript,
+};
+use crate::app::encryption;
+use crate::app::functions::{
+  all_functions, configured_functions, decline_tool_call, handle_confirmed_tool_call, handle_reviewed_patch,
+  handle_tool_call, plugin_function::load_plugins, sandbox::Resource, types::FunctionCall, CallableFunction,
+};
+use crate::app::guardrails::RequestEstimate;
 use crate::app::helpers::list_files_ordered_by_date;
-use crate::app::messages::ChatMessage;
+use crate::app::hooks::{HookEffect, Hooks};
+use crate::app::i18n::{self, tr, Catalog};
+use crate::app::inspector::RawExchange;
+use crate::app::messages::{ChatMessage, Feedback, Fold, MessageContainer, Rating, RenderedChatMessage};
+use crate::app::middleware::MiddlewareChain;
+use crate::app::model_comparison::{request_alternative, ComparedResponse, ModelComparison};
+use crate::app::notifications::{notify, Job, Notification};
+use crate::app::offline::{is_network_error, wait_for_connectivity};
+use crate::app::personas::Panel;
+use crate::app::playback::Playback;
+use crate::app::recovery::Recovery;
+use crate::app::model_list::ModelPricing;
+use crate::app::providers::{is_openrouter, openrouter_headers};
 use crate::app::request_validation::debug_request_validation;
+use crate::app::response_cache::ResponseCache;
+use crate::app::retry::{create_stream_with_retry, create_with_retry, retry_status, RetryPolicy};
+use crate::app::rpc;
 use crate::app::session_config::SessionConfig;
 use crate::app::session_data::SessionData;
+use crate::app::session_migration::{migrate_session, SESSION_SCHEMA_VERSION};
+use crate::app::session_search::{
+  find_related_sessions, load_session_summaries, SessionSummary, RELATED_SESSION_LIMIT, RELATED_SESSION_MIN_SCORE,
+};
+use crate::app::session_stats::{ApiStatus, SessionStats, Transaction};
 use crate::app::session_view::SessionView;
+use crate::app::tee::Tee;
+use crate::app::theme::{self, themes_dir, Theme};
+use crate::app::tool_call_stream::ToolCallAssembler;
+use crate::app::undo::UndoHistory;
+use crate::app::tools::example_runner::{
+  extract_code_blocks, insert_example_output, run_example, CodeBlock, EXAMPLE_TIMEOUT,
+};
 use crate::app::{consts::*, errors::*, tools::chunkifier::*, types::*};
 use crate::trace_dbg;
+use crate::utils::set_session_log;
 use crate::tui::Event;
 use crate::{action::Action, config::Config};
-use backoff::exponential::ExponentialBackoffBuilder;
-use dirs_next::home_dir;
 
 use crate::app::gpt_interface::create_chat_completion_tool_args;
 use crate::app::tools::utils::ensure_directory_exists;
+use crate::components::attachments::draw_attachments;
+use crate::components::choices::ChoiceComparison;
 use crate::components::home::Mode;
+use crate::components::inspector::Inspector;
+use crate::components::log_viewer::LogViewer;
+use crate::components::scratchpad::draw_scratchpad;
+use crate::components::stats::draw_stats;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Session<'a> {
+  // see session_migration, files without it are upgraded when they are loaded
+  #[serde(default)]
+  pub schema_version: u64,
   pub data: SessionData,
   pub config: SessionConfig,
   #[serde(skip)]
@@ -89,11 +145,100 @@ pub struct Session<'a> {
   pub select_start_coords: Option<(usize, usize)>,
   #[serde(skip)]
   pub select_end_coords: Option<(usize, usize)>,
+  // used in place of the configured model until the next input is submitted
+  #[serde(skip)]
+  pub model_override: Option<Model>,
+  // the first message of a new session, held while the user decides whether to continue a related session
+  #[serde(skip)]
+  pub pending_input: Option<String>,
+  #[serde(skip)]
+  pub related_sessions_offered: bool,
+  #[serde(skip)]
+  pub model_pricing: HashMap<String, ModelPricing>,
+  // set once the user confirms a request over the confirm thresholds, and cleared when it is sent
+  #[serde(skip)]
+  pub request_confirmed: bool,
+  // waits for the api to become reachable, then sends the queued request
+  #[serde(skip)]
+  pub connectivity_watch: Option<JoinHandle<()>>,
+  // ids of the tool calls of the last response that have no result yet, the results go back in one request
+  #[serde(skip)]
+  pub pending_tool_calls: Vec<String>,
+  // the transcript before each change, restored with u and ctrl-r
+  #[serde(skip)]
+  pub history: UndoHistory<SessionData>,
+  // the message selected in the message list, opened with m
+  #[serde(skip)]
+  pub message_selection: Option<usize>,
+  // whether the stats overlay is open, toggled with S
+  #[serde(skip)]
+  pub show_stats: bool,
+  // recent warnings and errors, toggled with L
+  #[serde(skip)]
+  pub log_viewer: Option<LogViewer>,
+  // the raw json of each request, toggled with I
+  #[serde(skip)]
+  pub inspector: Option<Inspector>,
+  // the request that dry run mode held back, with how far it is scrolled
+  #[serde(skip)]
+  pub dry_run_request: Option<(String, u16)>,
+  // cancels the request in flight, with esc or ctrl-c while processing
+  #[serde(skip)]
+  pub cancellation: Option<CancellationToken>,
+  // the response being continued, the next response is stitched onto it
+  #[serde(skip)]
+  pub continuation_of: Option<usize>,
+  // continuations sent since the last input, automatic ones stop at auto_continue
+  #[serde(skip)]
+  pub continuations: usize,
+  // the input box contents, autosaved with the transcript
+  #[serde(skip)]
+  pub draft: String,
+  #[serde(skip)]
+  pub last_autosave: Option<Instant>,
+  #[serde(skip)]
+  pub autosaved: Option<Recovery>,
+  // left behind by a run that crashed, until it is recovered or discarded
+  #[serde(skip)]
+  pub recovery: Option<Recovery>,
+  // the file responses are teed to, reopened when the session changes
+  #[serde(skip)]
+  pub tee: Option<Tee>,
+  // the scripts in the hooks directory, loaded when the session starts
+  #[serde(skip)]
+  pub hooks: Option<Hooks>,
+  // the agent started with the agent command, kept once it stops so that its scratchpad stays in view
+  #[serde(skip)]
+  pub agent: Option<Agent>,
+  // the personas started with the personas command, answering each input in turn
+  #[serde(skip)]
+  pub panel: Option<Panel>,
+  // the attached files whose summaries are being made
+  #[serde(skip)]
+  pub summarizing: Vec<PathBuf>,
+  // whether the attached files overlay is open, toggled with the attachments command
+  #[serde(skip)]
+  pub show_attachments: bool,
+  // where the last request's added system messages, a persona's prompt, the attached files and the response
+  // language, were inserted among
+  // the transcript's messages, and how many there were
+  #[serde(skip)]
+  pub context_messages: (usize, usize),
+  // the choices of a response asked for with n > 1, open until one is kept
+  #[serde(skip)]
+  pub comparison: Option<ChoiceComparison>,
+  // a/b mode, started with the ab command
+  #[serde(skip)]
+  pub ab: Option<ModelComparison>,
+  // a saved session being replayed, started with the replay command
+  #[serde(skip)]
+  pub playback: Option<Playback>,
 }
 
 impl<'a> Default for Session<'a> {
   fn default() -> Self {
     Session {
+      schema_version: SESSION_SCHEMA_VERSION,
       data: SessionData::default(),
       config: SessionConfig::default(),
       action_tx: None,
@@ -117,6 +262,36 @@ impl<'a> Default for Session<'a> {
       cursor_coords: None,
       select_start_coords: None,
       select_end_coords: None,
+      model_override: None,
+      pending_input: None,
+      related_sessions_offered: false,
+      model_pricing: HashMap::new(),
+      request_confirmed: false,
+      connectivity_watch: None,
+      pending_tool_calls: vec![],
+      history: UndoHistory::default(),
+      message_selection: None,
+      show_stats: false,
+      log_viewer: None,
+      inspector: None,
+      dry_run_request: None,
+      cancellation: None,
+      continuation_of: None,
+      continuations: 0,
+      draft: String::new(),
+      last_autosave: None,
+      autosaved: None,
+      recovery: None,
+      tee: None,
+      hooks: None,
+      agent: None,
+      panel: None,
+      summarizing: vec![],
+      show_attachments: false,
+      comparison: None,
+      ab: None,
+      playback: None,
+      context_messages: (0, 0),
     }
   }
 }
@@ -127,29 +302,51 @@ impl Component for Session<'static> {
     //let model_preference: Vec<Model> = vec![GPT4.clone(), GPT3_TURBO.clone(), WIZARDLM.clone()];
     //Session::select_model(model_preference, create_openai_client(self.config.openai_config.clone()));
     trace_dbg!("init session");
-    self.config.prompt =
-        [
-    "- act as a rust programming assistant",
-    "- you write full code when requested",
-    "- your responses are conscise and terse",
-    "- Use the functions available to execute with the user inquiry.",
-    "- Provide ==your responses== as markdown formatted text.",
-    "- Make sure to properly entabulate any code blocks",
-    "- Do not try and execute arbitrary python code.",
-    "- Do not try to infer a path to a file, if you have not been provided a path with the root ./, use the file_search function to verify the file path before you execute a function call.",
-    "- If the user asks you to create a file, use the create_file function",
-    // "- if the user asks you in a any way to modify a file, use the patch_file function",
-    "- Before you ask the user a question, consider if this information exist in the context",
-    "- Before you respond, consider if your response is applicable to the current query, ",
-    "- Before you respond, consider if your response is appropriate to further the intent of the request",
-    "- If you require additional information about the codebase, you can use pcre2grep to gather information about the codebase",
-    "- When evaluating function tests, make it a priority to determine if the problems exist in the source code, or if the test code itself is not properly designed"].join("\n").to_string();
+    if let Err(e) = set_session_log(&self.config.session_id) {
+      log::warn!("failed to open the session log: {}", e);
+    }
+    // a prompt from the config, such as a project's, is kept
+    if self.config.prompt.is_empty() {
+      self.config.prompt =
+          [
+      "- act as a rust programming assistant",
+      "- you write full code when requested",
+      "- your responses are conscise and terse",
+      "- Use the functions available to execute with the user inquiry.",
+      "- Provide ==your responses== as markdown formatted text.",
+      "- Make sure to properly entabulate any code blocks",
+      "- Do not try and execute arbitrary python code.",
+      "- Do not try to infer a path to a file, if you have not been provided a path with the root ./, use the file_search function to verify the file path before you execute a function call.",
+      "- If the user asks you to create a file, use the create_file function",
+      // "- if the user asks you in a any way to modify a file, use the patch_file function",
+      "- Before you ask the user a question, consider if this information exist in the context",
+      "- Before you respond, consider if your response is applicable to the current query, ",
+      "- Before you respond, consider if your response is appropriate to further the intent of the request",
+      "- If you require additional information about the codebase, you can use pcre2grep to gather information about the codebase",
+      "- When evaluating function tests, make it a priority to determine if the problems exist in the source code, or if the test code itself is not properly designed"].join("\n").to_string();
+    }
     // self.config.prompt = "act as a very terse assistant".into();
     self.view.set_window_width(area.width as usize, &mut self.data.messages);
     tx.send(Action::AddMessage(ChatMessage::System(self.config.prompt_message()))).unwrap();
+    for message in self.config.auto_context_messages() {
+      tx.send(Action::AddMessage(ChatMessage::System(message))).unwrap();
+    }
     self.view.post_process_new_messages(&mut self.data);
+    self.recovery = Recovery::default_dir().and_then(|dir| Recovery::find_abandoned(&dir, &self.config.session_id));
+    if let Some(recovery) = &self.recovery {
+      tx.send(Action::CommandResult(recovery.describe())).unwrap();
+    }
     // self.text_area = TextArea::new(self.view.rendered_text.lines().map(|l| l.to_string()).collect());
-    self.config.available_functions = all_functions();
+    self.load_functions();
+    if let Some(hooks_dir) = &self.config.hooks_dir {
+      match Hooks::load(hooks_dir) {
+        Ok(hooks) => self.hooks = hooks,
+        Err(e) => {
+          log::error!("{}", e);
+          tx.send(Action::UpdateStatus(Some(format!("hooks are disabled: {}", e)))).unwrap();
+        },
+      }
+    }
     Ok(())
   }
   fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<(), SazidError> {
@@ -159,6 +356,8 @@ impl Component for Session<'static> {
   }
   fn register_config_handler(&mut self, config: Config) -> Result<(), SazidError> {
     self.config = config.session_config;
+    self.model_pricing = config.model_pricing;
+    self.view.apply_theme(&theme::current());
     Ok(())
   }
   fn update(&mut self, action: Action) -> Result<Option<Action>, SazidError> {
@@ -166,25 +365,163 @@ impl Component for Session<'static> {
     match action {
       Action::AddMessage(chat_message) => {
         //trace_dbg!(level: tracing::Level::INFO, "adding message to session");
+        let is_response = matches!(chat_message, ChatMessage::StreamResponse(_) | ChatMessage::Response(_));
+        if is_response && self.data.has_pending() {
+          self.data.clear_pending();
+        }
+        self.tee_message(&chat_message);
+        rpc::publish_response(&chat_message);
+        if let (Some(agent), ChatMessage::Tool(tool)) = (self.agent.as_mut(), &chat_message) {
+          if agent.is_running() {
+            agent.record_observation(&tool.tool_call_id, tool.content.as_deref().unwrap_or_default());
+          }
+        }
         self.data.add_message(chat_message);
+        if let Some((name, _)) = self.panel.as_ref().and_then(Panel::speaker) {
+          if let Some(message) =
+            self.data.messages.last_mut().filter(|m| m.role() == "assistant" && m.persona.is_none())
+          {
+            message.persona = Some(name.clone());
+          }
+        }
         self.view.post_process_new_messages(&mut self.data);
-        self.execute_tool_calls();
         self.add_new_messages_to_request_buffer();
       },
       Action::ExecuteCommand(command) => {
         tx.send(Action::CommandResult(self.execute_command(command).unwrap())).unwrap();
       },
+      Action::SetOffline(offline) => self.config.set_offline(offline),
+      Action::AddCitations(citations) => {
+        let citations = self.data.add_citations(citations);
+        if !citations.is_empty() {
+          self.update(Action::AddMessage(ChatMessage::System(ChatCompletionRequestSystemMessage {
+            role: Role::System,
+            content: Some(context_message(&citations)),
+          })))?;
+        }
+        tx.send(Action::RequestChatCompletion()).unwrap();
+      },
+      Action::AttachmentSummarized(path, summary) => {
+        self.summarizing.retain(|summarizing| summarizing != &path);
+        let attachment = self.config.attached_files.iter_mut().find(|attachment| attachment.path == path);
+        if let (Some(attachment), Some(summary)) = (attachment, summary) {
+          attachment.summary = Some(summary);
+          tx.send(Action::UpdateStatus(Some(format!("summarized {}", path.display())))).unwrap();
+        }
+      },
+      // the replayed transcript stands in for the session's own until the playback ends
+      Action::SaveSession if self.playback.is_some() => {},
       Action::SaveSession => {
-        self.save_session().unwrap();
+        if let Err(e) = self.save_session() {
+          log::error!("failed to save session {}: {}", self.config.session_id, e);
+          tx.send(Action::UpdateStatus(Some(format!("failed to save the session: {}", e)))).unwrap();
+        }
       },
+      Action::Tick if self.playback.is_none() => self.autosave(),
+      Action::AutosaveDraft(draft) => self.draft = draft,
+      Action::Quit => self.remove_autosave(),
       Action::SubmitInput(s) => {
+        let s = self.hook_message_submit(s);
         self.scroll_sticky_end = true;
-        self.submit_chat_completion_request(s, tx);
+        self.model_override = None;
+        if let Some(panel) = self.panel.as_mut() {
+          panel.start_round();
+        }
+        if let Some(offer) = self.offer_related_sessions(&s) {
+          self.pending_input = Some(s);
+          tx.send(Action::CommandResult(offer)).unwrap();
+        } else {
+          self.submit_chat_completion_request(s, tx);
+        }
+      },
+      Action::ToolCallFinished(tool_call_id) => {
+        self.pending_tool_calls.retain(|id| id != &tool_call_id);
+        if self.pending_tool_calls.is_empty() {
+          self.request_chat_completion(tx.clone())
+        }
       },
       Action::RequestChatCompletion() => {
         trace_dbg!(level: tracing::Level::INFO, "requesting chat completion");
         self.request_chat_completion(tx.clone())
       },
+      Action::RequestConfirmed(true) => {
+        self.request_confirmed = true;
+        self.request_chat_completion(tx.clone())
+      },
+      Action::RequestQueued(error) => {
+        let count = self.data.mark_pending();
+        self.view.post_process_new_messages(&mut self.data);
+        self.watch_connectivity();
+        tx.send(Action::UpdateStatus(Some(format!(
+          "offline, {} message(s) queued until the api can be reached, :retry to send now ({})",
+          count, error
+        ))))
+        .unwrap();
+      },
+      Action::CancelRequest => {
+        if let Some(agent) = self.agent.as_mut() {
+          agent.stop("the request was cancelled");
+          self.config.confirm_side_effects = false;
+        }
+        if let Some(panel) = self.panel.as_mut() {
+          panel.end_round();
+        }
+        if let Some(cancellation) = self.cancellation.take() {
+          cancellation.cancel();
+          tx.send(Action::UpdateStatus(Some("cancelling request".to_string()))).unwrap();
+        }
+      },
+      interruption @ (Action::ResponseInterrupted | Action::ResponseTimedOut) => {
+        if self.data.interrupt_response(interruption == Action::ResponseTimedOut) {
+          self.view.post_process_new_messages(&mut self.data);
+          self.add_new_messages_to_request_buffer();
+        }
+      },
+      Action::PlaybackStep(generation) => {
+        let Some(playback) = self.playback.as_mut().filter(|p| p.playing && p.generation == generation) else {
+          return Ok(None);
+        };
+        playback.advance();
+        let status = playback.describe();
+        self.data = playback.shown();
+        self.view.rerender(&mut self.data);
+        self.schedule_playback_step();
+        tx.send(Action::UpdateStatus(Some(status))).unwrap();
+      },
+      Action::AlternativeResponse(compared, response) => {
+        if let Some(ab) = self.ab.as_mut().filter(|ab| ab.awaiting) {
+          ab.alternative = Some((compared, response));
+          if ab.is_ready() {
+            self.dispatch_finish_reason();
+          }
+        }
+      },
+      Action::RecordTransaction(transaction) => {
+        self.data.transactions.push(transaction);
+        if let Some(status) = ApiStatus::new(&self.data.transactions) {
+          tx.send(Action::UpdateApiStatus(status)).unwrap();
+        }
+      },
+      Action::RequestConfirmed(false) => {
+        tx.send(Action::UpdateStatus(Some("request cancelled".to_string()))).unwrap();
+      },
+      Action::ToolCallConfirmed(tool_call, confirmed) => {
+        handle_confirmed_tool_call(tx.clone(), tool_call, confirmed, self.config.clone())
+      },
+      Action::PatchReviewed(tool_call, review) => handle_reviewed_patch(tx.clone(), tool_call, review),
+      Action::ExampleConfirmed(example_index, true) => self.start_example(example_index, tx.clone()),
+      Action::ExampleConfirmed(_, false) => {
+        tx.send(Action::UpdateStatus(Some("example not run".to_string()))).unwrap();
+      },
+      Action::ExampleOutput(example_index, output) => {
+        let status = self.add_example_output(example_index, &output);
+        tx.send(Action::UpdateStatus(Some(status))).unwrap();
+      },
+      // the tool call is handled again, now that the policy covers what it uses
+      Action::PermissionAnswered(tool_call, resources, allowed) => {
+        resources.iter().for_each(|resource| self.config.sandbox.remember(resource, allowed));
+        handle_tool_call(tx.clone(), &tool_call, self.config.clone())
+      },
       Action::Resize(width, _height) => {
         self.view.set_window_width(width.into(), &mut self.data.messages);
         self.redraw_messages()
@@ -216,6 +553,23 @@ impl Component for Session<'static> {
       Action::ExitProcessing => {
         self.view.focus_textarea();
         self.mode = Mode::Normal;
+        if let Some(index) = self.continuation_of.take() {
+          if self.data.stitch_continuation(index) {
+            self.rebuild_request_buffer();
+            self.view.rerender(&mut self.data);
+          }
+        }
+        // every finished exchange is saved, so that a crash loses at most the request in flight
+        tx.send(Action::SaveSession).unwrap();
+        // None when the request failed before a response arrived
+        let response = self.data.messages.last().filter(|m| m.role() == "assistant");
+        let response = response.and_then(|m| message_text(&m.message)).cloned();
+        rpc::publish_done(response.as_deref());
+        if let Some(response) = response {
+          self.hook_response(&response);
+        }
+        self.finish_agent_response();
+        self.dispatch_finish_reason();
       },
       _ => (),
     }
@@ -258,6 +612,64 @@ impl Component for Session<'static> {
 
   fn handle_key_events(&mut self, key: KeyEvent) -> Result<Option<Action>, SazidError> {
     self.last_events.push(key);
+    if self.message_selection.is_some() {
+      return Ok(self.handle_message_selection_key(key));
+    }
+    if let Some((_, scroll)) = self.dry_run_request.as_mut() {
+      match key.code {
+        KeyCode::Down | KeyCode::Char('j') => *scroll = scroll.saturating_add(1),
+        KeyCode::Up | KeyCode::Char('k') => *scroll = scroll.saturating_sub(1),
+        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter => self.dry_run_request = None,
+        _ => {},
+      }
+      return Ok(Some(Action::Update));
+    }
+    if let Some(inspector) = self.inspector.as_mut() {
+      if !inspector.handle_key_event(key, self.data.transactions.len()) {
+        self.inspector = None;
+      }
+      return Ok(Some(Action::Update));
+    }
+    if let Some(log_viewer) = self.log_viewer.as_mut() {
+      if !log_viewer.handle_key_event(key) {
+        self.log_viewer = None;
+      }
+      return Ok(Some(Action::Update));
+    }
+    if self.show_stats {
+      if matches!(key.code, KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('S')) {
+        self.show_stats = false;
+      }
+      return Ok(Some(Action::Update));
+    }
+    if self.show_attachments {
+      if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+        self.show_attachments = false;
+      }
+      return Ok(Some(Action::Update));
+    }
+    if self.playback.is_some() {
+      self.handle_playback_key(key);
+      return Ok(Some(Action::Update));
+    }
+    if let Some(comparison) = self.comparison.as_mut() {
+      match key.code {
+        KeyCode::Right | KeyCode::Char('l') => comparison.select_next(),
+        KeyCode::Left | KeyCode::Char('h') => comparison.select_previous(),
+        KeyCode::Down | KeyCode::Char('j') => comparison.scroll = comparison.scroll.saturating_add(1),
+        KeyCode::Up | KeyCode::Char('k') => comparison.scroll = comparison.scroll.saturating_sub(1),
+        KeyCode::Enter => {
+          let choice = comparison.selected();
+          self.keep_choice(choice)
+        },
+        KeyCode::Esc | KeyCode::Char('q') => {
+          let choice = comparison.choices.first().map(|(index, _)| *index);
+          self.keep_choice(choice)
+        },
+        _ => {},
+      }
+      return Ok(Some(Action::Update));
+    }
     Ok(match self.mode {
       Mode::Normal => match key {
         KeyEvent { code: KeyCode::Char('d'), modifiers: KeyModifiers::CONTROL, .. } => {
@@ -324,6 +736,48 @@ impl Component for Session<'static> {
           self.view.text_area.cancel_selection();
           Some(Action::Update)
         },
+        KeyEvent { code: KeyCode::Char(c @ '1'..='9'), modifiers: KeyModifiers::NONE, .. } => {
+          let number = c.to_digit(10).unwrap() as usize;
+          self.data.citation(number).map(|citation| Action::ShowCitation(citation.clone()))
+        },
+        KeyEvent { code: KeyCode::Char('m'), modifiers: KeyModifiers::NONE, .. } => {
+          self.message_selection = self.data.messages.len().checked_sub(1);
+          Some(Action::Update)
+        },
+        KeyEvent { code: KeyCode::Char('S'), .. } => {
+          self.show_stats = true;
+          Some(Action::Update)
+        },
+        KeyEvent { code: KeyCode::Char('C'), .. } => Some(Action::ExecuteCommand("display".to_string())),
+        KeyEvent { code: KeyCode::Enter, modifiers: KeyModifiers::NONE, .. } if self.view.compact => {
+          let line = self.view.text_area.cursor().0;
+          self.view.message_at_line(&self.data, line).and_then(|index| self.toggle_expanded(index))
+        },
+        KeyEvent { code: KeyCode::Char('z'), modifiers: KeyModifiers::NONE, .. } => {
+          let line = self.view.text_area.cursor().0;
+          self.view.message_at_line(&self.data, line).and_then(|index| self.toggle_fold(index, Fold::Message))
+        },
+        KeyEvent { code: KeyCode::Char('Z'), .. } => {
+          let line = self.view.text_area.cursor().0;
+          self.view.message_at_line(&self.data, line).and_then(|index| self.toggle_fold(index, Fold::CodeBlocks))
+        },
+        KeyEvent { code: KeyCode::Char('L'), .. } => {
+          self.log_viewer = Some(LogViewer::default());
+          Some(Action::Update)
+        },
+        KeyEvent { code: KeyCode::Char('I'), .. } => {
+          self.inspector = Some(Inspector::new(&self.data.transactions));
+          Some(Action::Update)
+        },
+        KeyEvent { code: KeyCode::Char('u'), modifiers: KeyModifiers::NONE, .. } => {
+          Some(Action::ExecuteCommand("undo".to_string()))
+        },
+        KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::NONE, .. } => {
+          Some(Action::ExecuteCommand("continue".to_string()))
+        },
+        KeyEvent { code: KeyCode::Char('r'), modifiers: KeyModifiers::CONTROL, .. } => {
+          Some(Action::ExecuteCommand("redo".to_string()))
+        },
         KeyEvent { code: KeyCode::Char('V'), modifiers: KeyModifiers::SHIFT, .. } => {
           self.view.text_area.start_selection();
           self.view.text_area.move_cursor(CursorMove::Head);
@@ -357,11 +811,22 @@ impl Component for Session<'static> {
     //   .split(inner_a[1]);
 
     let block = Block::default().borders(Borders::NONE).gray();
+    // the agent's scratchpad takes the right of the transcript
+    let (transcript_area, scratchpad_area) = match self.agent {
+      Some(_) => {
+        let columns = Layout::default()
+          .direction(Direction::Horizontal)
+          .constraints([Constraint::Percentage(65), Constraint::Percentage(35)])
+          .split(inner[1]);
+        (columns[0], Some(columns[1]))
+      },
+      None => (inner[1], None),
+    };
 
-    self.vertical_viewport_height = inner[1].height as usize;
+    self.vertical_viewport_height = transcript_area.height as usize;
     self.vertical_content_height = self.view.rendered_text.len_lines();
     self.vertical_scroll_state = self.vertical_scroll_state.content_length(self.vertical_content_height);
-    self.view.set_window_width(inner[1].width as usize, &mut self.data.messages);
+    self.view.set_window_width(transcript_area.width as usize, &mut self.data.messages);
     self.scroll_max = self.view.rendered_text.len_lines().saturating_sub(self.vertical_viewport_height);
     // + self.vertical_viewport_height.min(3);
     self.vertical_scroll_state = self.vertical_scroll_state.viewport_content_length(self.vertical_content_height);
@@ -389,7 +854,31 @@ impl Component for Session<'static> {
     //   .begin_symbol(Some("󰶼"))
     //   .end_symbol(Some("󰶹"));
     // f.render_widget(paragraph, inner[1]);
-    f.render_widget(self.view.text_area.widget(), inner[1]);
+    f.render_widget(self.view.text_area.widget(), transcript_area);
+    if let (Some(agent), Some(scratchpad_area)) = (&self.agent, scratchpad_area) {
+      draw_scratchpad(f, scratchpad_area, agent);
+    }
+    if let Some(selected) = self.message_selection {
+      self.draw_message_selection(f, inner[1], selected);
+    }
+    if self.show_stats {
+      draw_stats(f, inner[1], &SessionStats::new(&self.data));
+    }
+    if self.show_attachments {
+      draw_attachments(f, inner[1], &self.config.read_attachments());
+    }
+    if let Some(comparison) = &self.comparison {
+      comparison.draw(f, inner[1]);
+    }
+    if let Some(log_viewer) = self.log_viewer.as_mut() {
+      log_viewer.draw(f, inner[1]);
+    }
+    if let Some(inspector) = self.inspector.as_mut() {
+      inspector.draw(f, inner[1], &self.data.transactions);
+    }
+    if let Some((description, scroll)) = &self.dry_run_request {
+      self.draw_dry_run(f, inner[1], description, *scroll);
+    }
     // f.render_stateful_widget(scrollbar, inner[2], &mut self.vertical_scroll_state);
     //self.render = false;
     Ok(())
@@ -430,153 +919,1595 @@ impl Session<'static> {
       .for_each(|m| {
         trace_dbg!("executing tool calls");
         if let ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+          content,
           tool_calls: Some(tool_calls),
           ..
         }) = &m.message
         {
+          if let Some(agent) = self.agent.as_mut().filter(|agent| agent.is_running()) {
+            agent.record_response(content.as_deref(), tool_calls);
+          }
+          self.pending_tool_calls.extend(tool_calls.iter().map(|tc| tc.id.clone()));
           tool_calls.iter().for_each(|tc| {
             let debug_text = format!("calling tool: {:?}", tc);
             trace_dbg!(level: tracing::Level::INFO, debug_text);
-            handle_tool_call(tx.clone(), tc, self.config.clone());
+            let mut tc = tc.clone();
+            match self.hooks.as_ref().map(|hooks| hooks.on_tool_call(&tc.function.name, &tc.function.arguments)) {
+              Some(Ok(Some(arguments))) => tc.function.arguments = arguments,
+              Some(Ok(None)) => {
+                decline_tool_call(&tx, &tc, &format!("the {} call was refused by a hook", tc.function.name));
+                return;
+              },
+              // the call runs as the model asked, a broken hook shouldn't stall the exchange
+              Some(Err(e)) => {
+                log::error!("{}", e);
+                tx.send(Action::UpdateStatus(Some(format!("hook error: {}", e)))).unwrap();
+              },
+              None => {},
+            }
+            handle_tool_call(tx.clone(), &tc, self.config.clone());
           });
           m.tools_called = true;
         }
-      })
+      });
+    self.apply_hook_effects();
   }
 
-  fn redraw_messages(&mut self) {
-    trace_dbg!("redrawing messages");
-    self.data.messages.iter_mut().for_each(|m| {
-      m.stylize_complete = false;
-    });
-    self.view.post_process_new_messages(&mut self.data);
+  // the built in functions and plugins, only those in tools when it is set
+  fn load_functions(&mut self) {
+    self.config.available_functions = all_functions();
+    self.config.available_functions.extend(configured_functions(&self.config));
+    if let Some(plugins_dir) = &self.config.plugins_dir {
+      for plugin in load_plugins(plugins_dir) {
+        let names = self.config.available_functions.iter().map(FunctionCall::from).map(|f| f.name).collect::<Vec<_>>();
+        match names.iter().any(|name| name == plugin.name()) {
+          true => log::warn!("plugin {} is skipped, a function with that name already exists", plugin.path.display()),
+          false => self.config.available_functions.push(CallableFunction::PluginFunction(plugin)),
+        }
+      }
+    }
+    if let Some(tools) = &self.config.tools {
+      self.config.available_functions.retain(|function| tools.contains(&FunctionCall::from(function).name));
+    }
   }
 
-  pub fn scroll_up(&mut self) -> Result<Option<Action>, SazidError> {
-    self.vertical_scroll = self.vertical_scroll.saturating_sub(1);
-    self.scroll_sticky_end = false;
-    // trace_dbg!(
-    //   "next scroll {} content height: {} vertical_viewport_height: {}",
-    //   self.vertical_scroll,
-    //   self.vertical_content_height,
-    //   self.vertical_viewport_height
-    // );
-    Ok(Some(Action::Update))
+  // profile <name> applies a profile to the session, its prompt is added as a system message
+  fn profile_command(&mut self, args: &[&str]) -> String {
+    let Some(name) = args.first() else {
+      let mut names = self.config.profiles.keys().map(String::as_str).collect::<Vec<_>>();
+      names.sort();
+      let current = self.config.profile.as_deref().unwrap_or("none");
+      return format!("profile: {}, usage: profile <name>, defined: {}", current, names.join(", "));
+    };
+    let prompt = self.config.prompt.clone();
+    let description = match self.config.apply_profile(name) {
+      Ok(profile) => profile.describe(),
+      Err(e) => return e.to_string(),
+    };
+    self.load_functions();
+    if self.config.prompt != prompt {
+      let message = ChatMessage::System(self.config.prompt_message());
+      self.action_tx.as_ref().unwrap().send(Action::AddMessage(message)).unwrap();
+    }
+    format!("profile {}: {}", name, description)
   }
 
-  pub fn scroll_down(&mut self) -> Result<Option<Action>, SazidError> {
-    self.vertical_scroll = self.vertical_scroll.saturating_add(1).min(self.scroll_max);
-    self.vertical_scroll_state = self.vertical_scroll_state.position(self.vertical_scroll);
-    if self.vertical_scroll_state == self.vertical_scroll_state.position(self.scroll_max) {
-      if !self.scroll_sticky_end {
-        let mut debug_string = String::new();
-        for (idx, line) in self.view.rendered_text.lines().enumerate() {
-          debug_string.push_str(format!("{:02}\t", idx).as_str());
-          debug_string.push_str(line.to_string().as_str());
-        }
+  // tag lists the session's tags, tag <tag>... adds tags and untag <tag>... removes them
+  fn tag_command(&mut self, args: &[&str], add: bool) -> String {
+    for tag in args {
+      match add {
+        true => self.data.add_tag(tag),
+        false => self.data.remove_tag(tag),
       }
-      self.scroll_sticky_end = true;
     }
-    // trace_dbg!(
-    //   "previous scroll {} content height: {} vertical_viewport_height: {}",
-    //   self.vertical_scroll,
-    //   self.vertical_content_height,
-    //   self.vertical_viewport_height
-    // );
-
-    Ok(Some(Action::Update))
+    if !args.is_empty() {
+      self.action_tx.as_ref().unwrap().send(Action::SaveSession).unwrap();
+    }
+    match self.data.tags.is_empty() {
+      true => "no tags, usage: tag <tag>... | untag <tag>...".to_string(),
+      false => format!("tags: {}", self.data.tags.join(", ")),
+    }
   }
 
-  pub fn execute_command(&mut self, command: String) -> Result<String, SazidError> {
-    let args = command.split_whitespace().collect::<Vec<&str>>();
-    match args[0] {
-      "exit" => std::process::exit(0),
-      "load" => {
-        if args.len() > 1 {
-          self.load_session_by_id(args[1].to_string())?;
-          Ok(format!("session {} loaded successfully!", args[1]))
-        } else {
-          self.load_last_session()?;
-          Ok("last session loaded successfully!".to_string())
-        }
-      },
-      _ => Ok("invalid command".to_string()),
+  // sessions lists the saved sessions, most recent first, sessions <tag> only those tagged with it, and all includes
+  // archived sessions
+  fn sessions_command(&self, args: &[&str]) -> String {
+    let include_archived = args.contains(&"all");
+    let tag = args.iter().find(|arg| **arg != "all").copied();
+    let Some(sessions_dir) = data_path(SESSIONS_DIR) else {
+      return "no sessions directory".to_string();
+    };
+    let listed = load_session_summaries(&sessions_dir)
+      .into_iter()
+      .rev()
+      .filter(|summary| summary.is_listed(tag, include_archived))
+      .map(|summary| summary.list_entry())
+      .collect::<Vec<_>>();
+    match listed.is_empty() {
+      true => "no sessions found".to_string(),
+      false => format!("{} -- load <id> to open one", listed.join(" | ")),
     }
   }
 
-  pub fn add_chunked_chat_completion_request_messages(
-    &mut self,
-    content: &str,
-    _name: &str,
-    role: Role,
-    model: &Model,
-  ) -> Result<(), SazidError> {
-    match parse_input(content, CHUNK_TOKEN_LIMIT as usize, model.token_limit as usize) {
-      Ok(chunks) => {
-        chunks.iter().for_each(|chunk| {
-          // explicitly calling update because we need this to be blocking, since it can't move on until the input is processed
-          self
-            .update(Action::AddMessage(ChatMessage::User(ChatCompletionRequestUserMessage {
-              role,
-              content: Some(ChatCompletionRequestUserMessageContent::Text(chunk.clone())),
-            })))
-            .unwrap();
-        });
-        Ok(())
+  // agent <goal> starts an agent, agent stop stops it, and agent close hides its scratchpad
+  fn agent_command(&mut self, args: &[&str]) -> String {
+    match args.first().copied() {
+      None => self.agent.as_ref().map_or("usage: agent <goal> | stop | close".to_string(), Agent::describe),
+      Some("stop") => {
+        if let Some(cancellation) = self.cancellation.take() {
+          cancellation.cancel();
+        }
+        self.config.confirm_side_effects = false;
+        match self.agent.as_mut() {
+          Some(agent) => {
+            agent.stop("stopped by the user");
+            agent.describe()
+          },
+          None => "no agent is running".to_string(),
+        }
+      },
+      Some("close") => {
+        self.agent = None;
+        self.config.confirm_side_effects = false;
+        "scratchpad closed".to_string()
+      },
+      Some(_) => {
+        let goal = args.join(" ");
+        let agent = Agent::new(&goal, self.config.agent_max_iterations);
+        let tx = self.action_tx.clone().unwrap();
+        tx.send(Action::AddMessage(ChatMessage::System(ChatCompletionRequestSystemMessage {
+          role: Role::System,
+          content: Some(agent.instructions()),
+        })))
+        .unwrap();
+        tx.send(Action::SubmitInput(goal)).unwrap();
+        self.agent = Some(agent);
+        self.config.confirm_side_effects = true;
+        format!("agent started, up to {} iterations, :agent stop to stop it", self.config.agent_max_iterations)
       },
-      Err(e) => Err(SazidError::ChunkifierError(e)),
     }
   }
 
-  pub fn add_new_messages_to_request_buffer(&mut self) {
-    // add new request messages to the request buffer
-    let new_requests: Vec<ChatCompletionRequestMessage> = self
-      .data
-      .messages
-      .iter()
-      .skip(self.request_buffer.len())
-      .filter(|m| m.receive_complete)
-      .map(|m| {
-        // let debug = format!("message: {:#?}", m.message).bright_red().to_string();
-        // trace_dbg!(debug);
-        m.message.clone()
-      })
-      .collect();
-    self.request_buffer.extend(new_requests);
-    trace_dbg!("request_buffer: {:#?}", self.request_buffer);
+  // an agent's response without tool calls is its final answer
+  fn finish_agent_response(&mut self) {
+    let Some(agent) = self.agent.as_mut().filter(|agent| agent.is_running()) else {
+      return;
+    };
+    if let Some(ChatCompletionRequestMessage::Assistant(assistant)) =
+      self.data.messages.last().filter(|m| m.receive_complete).map(|m| &m.message)
+    {
+      if assistant.tool_calls.as_ref().map_or(true, |tool_calls| tool_calls.is_empty()) {
+        agent.record_response(assistant.content.as_deref(), &[]);
+      }
+    }
+    if !agent.is_running() {
+      self.config.confirm_side_effects = false;
+      self.action_tx.as_ref().unwrap().send(Action::UpdateStatus(Some(agent.describe()))).unwrap();
+      self.notify_agent_finished();
+    }
   }
 
-  pub fn construct_request(&mut self) -> CreateChatCompletionRequest {
-    let tools = match self.config.available_functions.is_empty() {
-      true => None,
-      false => {
-        Some(create_chat_completion_tool_args(self.config.available_functions.iter().map(|f| f.into()).collect()))
-      },
+  // the agent reached its goal, gave up or ran out of iterations, stopping it by hand doesn't notify
+  fn notify_agent_finished(&self) {
+    let Some(agent) = &self.agent else {
+      return;
     };
-    self.add_new_messages_to_request_buffer();
-    let _token_count = 0;
-    // let debug = format!("{:#?}", self.request_buffer).bright_cyan().to_string();
-    // trace_dbg!("constructing request {}", debug);
-
-    let request = CreateChatCompletionRequest {
-      model: self.config.model.name.clone(),
-      messages: self.request_buffer.clone().into_iter().collect(),
-      stream: Some(self.config.stream_response),
-      max_tokens: Some(self.config.response_max_tokens as u16),
-      // todo: put the user information in here
-      user: Some("testing testing".to_string()),
-      tools,
-      ..Default::default()
+    let notification = Notification {
+      job: Job::Agent,
+      succeeded: agent.status == AgentStatus::Done,
+      detail: format!("{}\n{}", agent.goal, agent.describe()),
+      elapsed: agent.started.elapsed(),
     };
-    // trace_dbg!("request:\n{:#?}", request);
-    request
+    let (notifications, offline) = (self.config.notifications.clone(), self.config.offline);
+    tokio::spawn(async move { notify(&notifications, notification, offline).await });
   }
 
-  fn filter_non_ascii(s: &str) -> String {
-    s.chars().filter(|c| c.is_ascii()).collect()
-  }
+  // personas <name> <name>... starts a panel of personas that answer each input in turn, personas next runs another
+  // round without input, and personas off goes back to one assistant
+  fn personas_command(&mut self, args: &[&str]) -> String {
+    match args {
+      [] => match &self.panel {
+        Some(panel) => panel.describe(),
+        None => {
+          let mut names = self.config.personas.keys().map(String::as_str).collect::<Vec<_>>();
+          names.sort();
+          format!("usage: personas <name> <name>... | next | off, defined: {}", names.join(", "))
+        },
+      },
+      ["off"] => {
+        self.panel = None;
+        "personas off".to_string()
+      },
+      ["next"] => match self.panel.as_mut() {
+        Some(panel) if panel.speaker().is_some() => "wait for the round to finish, or cancel it".to_string(),
+        Some(panel) => {
+          panel.start_round();
+          self.action_tx.as_ref().unwrap().send(Action::RequestChatCompletion()).unwrap();
+          panel.describe()
+        },
+        None => "no personas are set, personas <name> <name>... to start".to_string(),
+      },
+      [_] => "personas take turns, name at least two".to_string(),
+      names => {
+        let mut personas = vec![];
+        for name in names {
+          match self.config.personas.get(*name) {
+            Some(persona) => personas.push((name.to_string(), persona.clone())),
+            None => return format!("no persona named {}, define it under personas in the config", name),
+          }
+        }
+        let panel = Panel::new(personas);
+        let status = format!("{}, each input is answered by every persona in turn", panel.describe());
+        self.panel = Some(panel);
+        status
+      },
+    }
+  }
+
+  // ab <model> also sends each prompt to <model> and compares the two responses, ab <model a> <model b> first switches
+  // the session to <model a>, and ab off stops comparing
+  fn ab_command(&mut self, args: &[&str]) -> String {
+    let model = match args {
+      [] => {
+        return match &self.ab {
+          Some(ab) => format!("comparing {} with {}, :ab off to stop", self.config.model.name, ab.model),
+          None => "usage: ab <model> | <model a> <model b> | off".to_string(),
+        }
+      },
+      ["off"] => {
+        return match self.ab.take() {
+          Some(_) => "a/b comparison stopped".to_string(),
+          None => "a/b comparison is not on".to_string(),
+        }
+      },
+      [model] => model,
+      [first, model] => {
+        self.config.model = Model::from_name(first);
+        model
+      },
+      _ => return "usage: ab <model> | <model a> <model b> | off".to_string(),
+    };
+    if *model == self.config.model.name {
+      return format!("{} is already the session's model, compare it with another", model);
+    }
+    self.ab = Some(ModelComparison::new(model));
+    format!("each prompt goes to {} and {}, enter keeps one of the responses", self.config.model.name, model)
+  }
+
+  // replay plays the session back from its first message, replay <id> plays a saved session back, and timed follows
+  // the original pauses and streaming. nothing is sent while it plays
+  fn replay_command(&mut self, args: &[&str]) -> String {
+    let (id, timed) = match args {
+      [] => (None, false),
+      ["timed"] => (None, true),
+      [id] => (Some(*id), false),
+      [id, "timed"] | ["timed", id] => (Some(*id), true),
+      _ => return "usage: replay [<session id>] [timed]".to_string(),
+    };
+    if self.mode == Mode::Processing || self.comparison.is_some() {
+      return "wait for the response before replaying".to_string();
+    }
+    let (session_id, data) = match id {
+      Some(id) => match encryption::read_to_string(&Self::get_session_filepath(id.to_string()))
+        .map_err(|e| SazidError::Other(format!("failed to read session {}: {}", id, e)))
+        .and_then(|json| migrate_session(&json))
+      {
+        Ok(session) => (id.to_string(), session.data),
+        Err(e) => return e.to_string(),
+      },
+      None => (self.config.session_id.clone(), self.data.clone()),
+    };
+    if data.messages.is_empty() {
+      return format!("session {} has no messages to replay", session_id);
+    }
+    let original = std::mem::take(&mut self.data);
+    let playback = Playback::new(&session_id, data, timed, original);
+    let status = playback.describe();
+    self.data = playback.shown();
+    self.view.rerender(&mut self.data);
+    self.playback = Some(playback);
+    status
+  }
+
+  // space plays or pauses, the arrows step through the messages and esc puts the session's transcript back
+  fn handle_playback_key(&mut self, key: KeyEvent) {
+    let Some(playback) = self.playback.as_mut() else {
+      return;
+    };
+    match key.code {
+      KeyCode::Char(' ') => {
+        playback.toggle();
+      },
+      KeyCode::Right | KeyCode::Char('l') | KeyCode::Char('j') => playback.step_forward(),
+      KeyCode::Left | KeyCode::Char('h') | KeyCode::Char('k') => playback.step_back(),
+      KeyCode::Esc | KeyCode::Char('q') => {
+        let playback = self.playback.take().unwrap();
+        self.data = playback.original;
+        self.view.rerender(&mut self.data);
+        if let Some(tx) = &self.action_tx {
+          tx.send(Action::UpdateStatus(Some(format!("stopped replaying {}", playback.session_id)))).unwrap();
+        }
+        return;
+      },
+      _ => return,
+    }
+    let status = playback.describe();
+    self.data = playback.shown();
+    self.view.rerender(&mut self.data);
+    // a step while playing goes on playing from the new position
+    self.schedule_playback_step();
+    if let Some(tx) = &self.action_tx {
+      tx.send(Action::UpdateStatus(Some(status))).unwrap();
+    }
+  }
+
+  // the next step of a playing playback, after the pause before it
+  fn schedule_playback_step(&self) {
+    let (Some(playback), Some(tx)) = (self.playback.as_ref().filter(|p| p.playing), self.action_tx.clone()) else {
+      return;
+    };
+    let (delay, generation) = (playback.delay(), playback.generation);
+    tokio::spawn(async move {
+      tokio::time::sleep(delay).await;
+      let _ = tx.send(Action::PlaybackStep(generation));
+    });
+  }
+
+  // lang <language> switches the interface and the responses to the language, lang auto goes back to the
+  // environment's language for the interface and the user's for responses, and lang off stops asking for a language
+  fn lang_command(&mut self, args: &[&str]) -> String {
+    match args {
+      [] => {
+        let responses = match &self.config.response_language {
+          Some(language) => i18n::language_name(language).to_string(),
+          None => "the user's language".to_string(),
+        };
+        format!(
+          "interface in {}, responses in {}, available: {}",
+          i18n::language_name(&i18n::current().language),
+          responses,
+          Catalog::available(&i18n::locales_dir()).join(", ")
+        )
+      },
+      ["off"] => {
+        self.config.response_language = None;
+        "responses in the user's language".to_string()
+      },
+      ["auto"] => {
+        let language = i18n::detect_language().unwrap_or_else(|| "en".to_string());
+        i18n::set_current(Catalog::load(&language, &i18n::locales_dir()).unwrap_or_default());
+        self.config.response_language = None;
+        format!("interface in {}, responses in the user's language", i18n::language_name(&i18n::current().language))
+      },
+      [language] => {
+        self.config.response_language = Some(language.to_string());
+        match Catalog::load(language, &i18n::locales_dir()) {
+          Ok(catalog) => {
+            i18n::set_current(catalog);
+            format!("interface and responses in {}", i18n::language_name(language))
+          },
+          Err(_) => format!("responses in {}, the interface has no translation", i18n::language_name(language)),
+        }
+      },
+      _ => "usage: lang [<language> | auto | off]".to_string(),
+    }
+  }
+
+  // attach <path>... inlines files into every request, read again each time, large ones as their summary
+  fn attach_command(&mut self, args: &[&str]) -> String {
+    if args.is_empty() {
+      return "usage: attach <path>...".to_string();
+    }
+    let mut attached = vec![];
+    for arg in args {
+      let path = PathBuf::from(arg);
+      if !path.is_file() {
+        return format!("{} isn't a file", arg);
+      }
+      if self.config.attached_files.iter().any(|attachment| attachment.path == path) {
+        continue;
+      }
+      // the functions can read attached files too
+      if !self.config.list_file_paths.contains(&path) {
+        self.config.list_file_paths.push(path.clone());
+      }
+      let attachment = Attachment::new(&path);
+      let file = attachment.read(self.config.attachment_max_tokens);
+      attached.push(format!("{} ({} tokens, {})", arg, file.tokens, file.describe()));
+      self.config.attached_files.push(attachment);
+    }
+    match attached.is_empty() {
+      true => "already attached".to_string(),
+      false => format!("attached {}", attached.join(", ")),
+    }
+  }
+
+  fn detach_command(&mut self, args: &[&str]) -> String {
+    match args {
+      [] => "usage: detach <path>... | all".to_string(),
+      ["all"] => {
+        let count = self.config.attached_files.len();
+        self.config.attached_files.clear();
+        format!("detached {} files", count)
+      },
+      paths => {
+        for path in paths {
+          let path = PathBuf::from(path);
+          if !self.config.attached_files.iter().any(|attachment| attachment.path == path) {
+            return format!("{} isn't attached", path.display());
+          }
+          self.config.attached_files.retain(|attachment| attachment.path != path);
+        }
+        format!("detached {}", paths.join(", "))
+      },
+    }
+  }
+
+  // once a persona has answered, the next one responds, until every persona has in this round
+  fn next_persona_turn(&mut self) {
+    let Some(panel) = self.panel.as_mut().filter(|panel| panel.speaker().is_some()) else {
+      return;
+    };
+    let tx = self.action_tx.clone().unwrap();
+    match self.data.messages.last().filter(|m| m.receive_complete).map(|m| &m.message) {
+      // the same persona responds again once its tool calls are answered
+      Some(ChatCompletionRequestMessage::Assistant(assistant))
+        if assistant.tool_calls.as_ref().map_or(false, |tool_calls| !tool_calls.is_empty()) => {},
+      Some(ChatCompletionRequestMessage::Assistant(_)) => match panel.next_turn() {
+        true => {
+          tx.send(Action::UpdateStatus(Some(panel.describe()))).unwrap();
+          tx.send(Action::RequestChatCompletion()).unwrap();
+        },
+        false => tx.send(Action::UpdateStatus(Some(panel.describe()))).unwrap(),
+      },
+      // the request failed, the round stops here
+      _ => panel.end_round(),
+    }
+  }
+
+  // the input after the on_message_submit hooks, or as it was typed when a hook fails
+  fn hook_message_submit(&mut self, input: String) -> String {
+    let input = match self.hooks.as_ref().map(|hooks| hooks.on_message_submit(&input)) {
+      Some(Ok(rewritten)) => rewritten,
+      Some(Err(e)) => {
+        self.report_hook_error(e);
+        input
+      },
+      None => input,
+    };
+    self.apply_hook_effects();
+    input
+  }
+
+  fn hook_response(&mut self, response: &str) {
+    if let Some(Err(e)) = self.hooks.as_ref().map(|hooks| hooks.on_response(response)) {
+      self.report_hook_error(e);
+    }
+    self.apply_hook_effects();
+  }
+
+  fn report_hook_error(&self, e: SazidError) {
+    log::error!("{}", e);
+    self.action_tx.as_ref().unwrap().send(Action::UpdateStatus(Some(format!("hook error: {}", e)))).unwrap();
+  }
+
+  // tags are saved with the session, statuses and commands are sent as actions
+  fn apply_hook_effects(&mut self) {
+    let Some(hooks) = &self.hooks else {
+      return;
+    };
+    let tx = self.action_tx.clone().unwrap();
+    for effect in hooks.take_effects() {
+      match effect {
+        HookEffect::Tag(tag) => self.data.add_tag(&tag),
+        HookEffect::Status(status) => tx.send(Action::UpdateStatus(Some(status))).unwrap(),
+        HookEffect::Command(command) if !command.trim().is_empty() => tx.send(Action::ExecuteCommand(command)).unwrap(),
+        HookEffect::Command(_) => {},
+      }
+    }
+  }
+
+  // sends the queued request once the api is reachable, unless a watch is already running
+  fn watch_connectivity(&mut self) {
+    if self.connectivity_watch.as_ref().map(|watch| !watch.is_finished()).unwrap_or(false) {
+      return;
+    }
+    let tx = self.action_tx.clone().unwrap();
+    let api_base = self.config.openai_config.api_base().to_string();
+    self.connectivity_watch = Some(tokio::spawn(async move {
+      wait_for_connectivity(&api_base).await;
+      tx.send(Action::UpdateStatus(Some("connectivity restored, sending queued request".to_string()))).unwrap();
+      tx.send(Action::RequestChatCompletion()).unwrap();
+    }));
+  }
+
+  fn redraw_messages(&mut self) {
+    trace_dbg!("redrawing messages");
+    self.data.messages.iter_mut().for_each(|m| {
+      m.stylize_complete = false;
+    });
+    self.view.post_process_new_messages(&mut self.data);
+  }
+
+  pub fn scroll_up(&mut self) -> Result<Option<Action>, SazidError> {
+    self.vertical_scroll = self.vertical_scroll.saturating_sub(1);
+    self.scroll_sticky_end = false;
+    // trace_dbg!(
+    //   "next scroll {} content height: {} vertical_viewport_height: {}",
+    //   self.vertical_scroll,
+    //   self.vertical_content_height,
+    //   self.vertical_viewport_height
+    // );
+    Ok(Some(Action::Update))
+  }
+
+  pub fn scroll_down(&mut self) -> Result<Option<Action>, SazidError> {
+    self.vertical_scroll = self.vertical_scroll.saturating_add(1).min(self.scroll_max);
+    self.vertical_scroll_state = self.vertical_scroll_state.position(self.vertical_scroll);
+    if self.vertical_scroll_state == self.vertical_scroll_state.position(self.scroll_max) {
+      if !self.scroll_sticky_end {
+        let mut debug_string = String::new();
+        for (idx, line) in self.view.rendered_text.lines().enumerate() {
+          debug_string.push_str(format!("{:02}\t", idx).as_str());
+          debug_string.push_str(line.to_string().as_str());
+        }
+      }
+      self.scroll_sticky_end = true;
+    }
+    // trace_dbg!(
+    //   "previous scroll {} content height: {} vertical_viewport_height: {}",
+    //   self.vertical_scroll,
+    //   self.vertical_content_height,
+    //   self.vertical_viewport_height
+    // );
+
+    Ok(Some(Action::Update))
+  }
+
+  pub fn execute_command(&mut self, command: String) -> Result<String, SazidError> {
+    let args = command.split_whitespace().collect::<Vec<&str>>();
+    // commands can also be typed with a leading slash, as in /theme
+    match args[0].trim_start_matches('/') {
+      "exit" => {
+        self.remove_autosave();
+        std::process::exit(0)
+      },
+      "load" => {
+        if args.len() > 1 {
+          self.load_session_by_id(args[1].to_string())?;
+          Ok(format!("session {} loaded successfully!", args[1]))
+        } else {
+          self.load_last_session()?;
+          Ok("last session loaded successfully!".to_string())
+        }
+      },
+      "set" | "unset" => match args.get(1) {
+        Some(name) => {
+          let value = args[2..].join(" ");
+          let value = match args[0] {
+            "set" if !value.is_empty() => Some(value.as_str()),
+            "set" => return Ok(format!("usage: set {} <value>", name)),
+            _ => None,
+          };
+          match self.config.set_request_parameter(name, value) {
+            Ok(()) => {
+              Ok(format!("{}, max_tokens: {}", self.config.request_parameters, self.config.response_max_tokens))
+            },
+            Err(e) => Ok(e.to_string()),
+          }
+        },
+        None => Ok(format!("usage: {} <parameter> [value]", args[0])),
+      },
+      "continue" if self.pending_input.is_none() => Ok(self.continue_response()),
+      "continue" | "attach" | "new" => self.resolve_pending_input(args[0], args.get(1).copied()),
+      "models" => {
+        self.action_tx.clone().unwrap().send(Action::OpenModelPicker).unwrap();
+        Ok("select a model".to_string())
+      },
+      "cache" => match args.get(1) {
+        Some(&"on") => {
+          self.config.response_cache.enabled = true;
+          Ok(format!("response cache enabled, entries expire after {}s", self.config.response_cache.ttl_secs))
+        },
+        Some(&"off") => {
+          self.config.response_cache.enabled = false;
+          Ok("response cache disabled".to_string())
+        },
+        Some(&"clear") => match ResponseCache::default_dir().and_then(|dir| ResponseCache::new(dir, 0).clear()) {
+          Ok(count) => Ok(format!("removed {} cached responses", count)),
+          Err(e) => Ok(format!("failed to clear the response cache: {}", e)),
+        },
+        _ => Ok("usage: cache [on|off|clear]".to_string()),
+      },
+      "retry" => match self.data.has_pending() {
+        true => {
+          self.action_tx.clone().unwrap().send(Action::RequestChatCompletion()).unwrap();
+          Ok("sending queued request".to_string())
+        },
+        false => Ok("no queued requests".to_string()),
+      },
+      "sandbox" => Ok(self.config.sandbox.to_string()),
+      "display" => {
+        let compact = match args.get(1) {
+          Some(&"compact") => true,
+          Some(&"expanded") => false,
+          Some(_) => return Ok("usage: display [compact|expanded]".to_string()),
+          None => !self.view.compact,
+        };
+        self.view.set_compact(compact, &mut self.data);
+        Ok(match compact {
+          true => "compact transcript, enter on a message shows it in full".to_string(),
+          false => "expanded transcript".to_string(),
+        })
+      },
+      "theme" => match args.get(1) {
+        Some(name) => match Theme::load(name, &themes_dir()) {
+          Ok(theme) => {
+            let message = match self.view.apply_theme(&theme) {
+              true => format!("switched to the {} theme", theme.name),
+              false => format!("switched to the {} theme, bat has no {} markdown theme", theme.name, theme.markdown),
+            };
+            theme::set_current(theme);
+            self.view.rerender(&mut self.data);
+            Ok(message)
+          },
+          Err(e) => Ok(e.to_string()),
+        },
+        None => Ok(format!(
+          "theme {}, available: {}",
+          theme::current().name,
+          Theme::available(&themes_dir()).join(", ")
+        )),
+      },
+      "share" => self.share(&args[1..]),
+      "agent" => Ok(self.agent_command(&args[1..])),
+      "personas" => Ok(self.personas_command(&args[1..])),
+      "attach" => Ok(self.attach_command(&args[1..])),
+      "lang" => Ok(self.lang_command(&args[1..])),
+      "ab" => Ok(self.ab_command(&args[1..])),
+      "replay" => Ok(self.replay_command(&args[1..])),
+      "detach" => Ok(self.detach_command(&args[1..])),
+      "attachments" => {
+        self.show_attachments = !self.show_attachments;
+        Ok(String::new())
+      },
+      "profile" => Ok(self.profile_command(&args[1..])),
+      "tag" => Ok(self.tag_command(&args[1..], true)),
+      "untag" => Ok(self.tag_command(&args[1..], false)),
+      "sessions" => Ok(self.sessions_command(&args[1..])),
+      command @ ("archive" | "unarchive") => {
+        self.data.archived = command == "archive";
+        self.action_tx.as_ref().unwrap().send(Action::SaveSession).unwrap();
+        Ok(match self.data.archived {
+          true => "session archived, it is left out of session lists until unarchived".to_string(),
+          false => "session taken out of the archive".to_string(),
+        })
+      },
+      "stats" => Ok(SessionStats::new(&self.data).to_string()),
+      "recover" => self.recover(),
+      "discard" => match (self.recovery.take(), Recovery::default_dir()) {
+        (Some(recovery), Some(dir)) => {
+          Recovery::remove(&dir, &recovery.session_id)?;
+          Ok(format!("discarded the autosave of session {}", recovery.session_id))
+        },
+        _ => Ok("nothing to discard".to_string()),
+      },
+      "undo" => Ok(self.restore_transcript(false)),
+      "redo" => Ok(self.restore_transcript(true)),
+      "clear" => {
+        self.record_change("clear");
+        let mut data = self.data.clone();
+        data.messages.retain(|m| matches!(m.message, ChatCompletionRequestMessage::System(_)));
+        self.replace_transcript(data);
+        Ok("transcript cleared, u to undo".to_string())
+      },
+      "offline" => {
+        let offline = match args.get(1) {
+          Some(&"on") => true,
+          Some(&"off") => false,
+          Some(_) => return Ok("usage: offline [on|off]".to_string()),
+          None => !self.config.offline,
+        };
+        self.action_tx.clone().unwrap().send(Action::SetOffline(offline)).unwrap();
+        Ok(match offline {
+          true => "offline mode on, chat requests go to the local api".to_string(),
+          false => "offline mode off".to_string(),
+        })
+      },
+      "rate" => {
+        let rating = match args.get(1) {
+          Some(&"up") => Rating::Up,
+          Some(&"down") => Rating::Down,
+          _ => return Ok("usage: rate [up|down] [comment]".to_string()),
+        };
+        let comment = Some(args[2..].join(" ")).filter(|c| !c.is_empty());
+        match self.data.rate_last_response(Feedback { rating, comment }) {
+          true => {
+            self.save_session()?;
+            Ok("response rated".to_string())
+          },
+          false => Ok("no response to rate".to_string()),
+        }
+      },
+      "sources" => {
+        self.action_tx.clone().unwrap().send(Action::OpenSourceManager).unwrap();
+        Ok("loading ingested sources".to_string())
+      },
+      "rag" => match args.get(1) {
+        Some(&"off") => {
+          self.config.retrieval = None;
+          Ok("retrieval disabled".to_string())
+        },
+        Some(&"on") | None => {
+          let collection = args.get(2).map(|c| c.to_string()).or_else(|| self.config.collection.clone());
+          let description = match &collection {
+            Some(collection) => format!("retrieval enabled from collection {}", collection),
+            None => "retrieval enabled from every ingested source".to_string(),
+          };
+          self.config.retrieval = Some(RetrievalSettings { collection, chunks: DEFAULT_RETRIEVED_CHUNKS });
+          Ok(format!("{}, press a citation number or use cite <number> to see a source", description))
+        },
+        Some(_) => Ok("usage: rag [on [collection]|off]".to_string()),
+      },
+      // binds the session to a collection, which retrieval searches when it is given none
+      "collection" => match args.get(1) {
+        Some(&"off") => {
+          self.config.collection = None;
+          if let Some(retrieval) = self.config.retrieval.as_mut() {
+            retrieval.collection = None;
+          }
+          self.save_session()?;
+          Ok("session no longer bound to a collection".to_string())
+        },
+        Some(collection) => {
+          self.config.collection = Some(collection.to_string());
+          if let Some(retrieval) = self.config.retrieval.as_mut() {
+            retrieval.collection = Some(collection.to_string());
+          }
+          self.save_session()?;
+          Ok(format!("session bound to collection {}", collection))
+        },
+        None => match &self.config.collection {
+          Some(collection) => Ok(format!("session bound to collection {}, collection off unbinds it", collection)),
+          None => Ok("usage: collection <name>|off".to_string()),
+        },
+      },
+      "cite" => match args.get(1).and_then(|n| n.parse::<usize>().ok()).and_then(|n| self.data.citation(n)) {
+        Some(citation) => {
+          self.action_tx.clone().unwrap().send(Action::ShowCitation(citation.clone())).unwrap();
+          Ok(citation.label())
+        },
+        None => Ok(format!("usage: cite <number>, this session has {} citations", self.data.citations.len())),
+      },
+      "summarize" => match args.get(1) {
+        Some(_) => {
+          let source = args[1..].join(" ");
+          self.action_tx.clone().unwrap().send(Action::SummarizeSource(source.clone())).unwrap();
+          Ok(format!("summarizing {}", source))
+        },
+        None => Ok("usage: summarize <file, directory or collection>".to_string()),
+      },
+      "ask" => match args.get(1) {
+        Some(model_name) if args.len() > 2 => {
+          let model = Model::from_name(model_name);
+          let tx = self.action_tx.clone().unwrap();
+          self.scroll_sticky_end = true;
+          self.submit_chat_completion_request(args[2..].join(" "), tx);
+          // the request is sent once the submitted message is processed, so the override is still in place
+          self.model_override = Some(model.clone());
+          Ok(format!("asking {}", model.name))
+        },
+        _ => Ok("usage: ask <model> <question>".to_string()),
+      },
+      "run" => match args.get(1).map(|n| n.parse::<usize>()) {
+        Some(Err(_)) | Some(Ok(0)) => Ok("usage: run [example number]".to_string()),
+        Some(Ok(n)) => self.run_response_example(n - 1),
+        None => self.run_response_example(0),
+      },
+      "params" => Ok(format!("{}, max_tokens: {}", self.config.request_parameters, self.config.response_max_tokens)),
+      "dryrun" => match args.get(1) {
+        Some(&"on") => {
+          self.config.dry_run = true;
+          Ok("dry run enabled, requests are shown instead of sent".to_string())
+        },
+        Some(&"off") => {
+          self.config.dry_run = false;
+          Ok("dry run disabled".to_string())
+        },
+        Some(_) => Ok("usage: dryrun [on|off]".to_string()),
+        None => {
+          self.config.dry_run = !self.config.dry_run;
+          Ok(format!("dry run is {}", if self.config.dry_run { "on" } else { "off" }))
+        },
+      },
+      "compress" => match args.get(1) {
+        Some(&"on") => {
+          self.config.compress_prompt = true;
+          Ok(format!("prompt compression enabled above {} tokens", self.config.compression_threshold_tokens))
+        },
+        Some(&"off") => {
+          self.config.compress_prompt = false;
+          Ok("prompt compression disabled".to_string())
+        },
+        Some(threshold) => match threshold.parse::<usize>() {
+          Ok(threshold) => {
+            self.config.compress_prompt = true;
+            self.config.compression_threshold_tokens = threshold;
+            Ok(format!("prompt compression enabled above {} tokens", threshold))
+          },
+          Err(_) => Ok("usage: compress [on|off|<threshold tokens>]".to_string()),
+        },
+        None => Ok(format!(
+          "prompt compression is {} with a threshold of {} tokens",
+          if self.config.compress_prompt { "on" } else { "off" },
+          self.config.compression_threshold_tokens
+        )),
+      },
+      _ => Ok("invalid command".to_string()),
+    }
+  }
+
+  // searches past sessions when the first message of a new session is submitted
+  fn offer_related_sessions(&mut self, input: &str) -> Option<String> {
+    let has_user_messages =
+      self.data.messages.iter().any(|m| matches!(m.message, ChatCompletionRequestMessage::User(_)));
+    if self.related_sessions_offered || has_user_messages {
+      return None;
+    }
+    self.related_sessions_offered = true;
+    let sessions: Vec<SessionSummary> = load_session_summaries(&data_path(SESSIONS_DIR)?)
+      .into_iter()
+      .filter(|s| s.session_id != self.config.session_id)
+      .collect();
+    let related = find_related_sessions(input, &sessions, RELATED_SESSION_LIMIT, RELATED_SESSION_MIN_SCORE);
+    if related.is_empty() {
+      return None;
+    }
+    let related_list =
+      related.iter().map(|(_, s)| format!("{}: {}", s.session_id, s.title())).collect::<Vec<String>>().join(" | ");
+    Some(format!("related sessions {} -- continue <id>, attach <id> or new", related_list))
+  }
+
+  // saves the draft and the transcript every few seconds, so that they can be restored after a crash
+  fn autosave(&mut self) {
+    if self.last_autosave.map_or(false, |saved| saved.elapsed().as_secs() < AUTOSAVE_INTERVAL_SECS) {
+      return;
+    }
+    self.last_autosave = Some(Instant::now());
+    let Some(dir) = Recovery::default_dir() else {
+      return;
+    };
+    let has_prompts = self.data.messages.iter().any(|m| matches!(m.message, ChatCompletionRequestMessage::User(_)));
+    if !has_prompts && self.draft.trim().is_empty() {
+      return;
+    }
+    let session = match serde_json::to_string(&self) {
+      Ok(session) => session,
+      Err(e) => {
+        log::warn!("failed to serialize the session for autosave: {}", e);
+        return;
+      },
+    };
+    let recovery = Recovery { session_id: self.config.session_id.clone(), draft: self.draft.clone(), session };
+    if self.autosaved.as_ref() == Some(&recovery) {
+      return;
+    }
+    match recovery.save(&dir) {
+      Ok(()) => self.autosaved = Some(recovery),
+      Err(e) => log::warn!("failed to autosave the session: {}", e),
+    }
+  }
+
+  // on a clean exit, so that the next run doesn't offer to recover this session
+  fn remove_autosave(&self) {
+    if let Some(dir) = Recovery::default_dir() {
+      if let Err(e) = Recovery::remove(&dir, &self.config.session_id) {
+        log::warn!("failed to remove the autosave: {}", e);
+      }
+    }
+  }
+
+  // replaces this session with the one a crashed run left behind, and puts its draft back in the input
+  fn recover(&mut self) -> Result<String, SazidError> {
+    let (Some(recovery), Some(dir)) = (self.recovery.take(), Recovery::default_dir()) else {
+      return Ok("nothing to recover".to_string());
+    };
+    Recovery::remove(&dir, &self.config.session_id)?;
+    let openai_config = self.config.openai_config.clone();
+    self.load_session(recovery.session.clone())?;
+    self.config.openai_config = openai_config;
+    // a response that was streaming when it crashed can be continued with c
+    self.data.interrupt_response(false);
+    self.request_buffer.clear();
+    self.redraw_messages();
+    Recovery::remove(&dir, &recovery.session_id)?;
+    self.autosaved = None;
+    if !recovery.draft.is_empty() {
+      self.action_tx.clone().unwrap().send(Action::RestoreDraft(recovery.draft.clone())).unwrap();
+    }
+    Ok(format!("recovered session {}", recovery.session_id))
+  }
+
+  // what follows a response depends on why it stopped: its tool calls run, a response cut off by the token limit is
+  // continued, a refusal is reported, and otherwise the next persona responds. some providers stop with tool calls
+  // without saying so, so the calls of any finished response run
+  fn dispatch_finish_reason(&mut self) {
+    let tx = self.action_tx.clone().unwrap();
+    // in a/b mode the responses of both models are compared before anything follows them
+    if let Some(ab) = self.ab.as_mut().filter(|ab| ab.awaiting) {
+      ab.first_done = true;
+      let waiting = format!("waiting for the response of {}", ab.model);
+      // the session's model failed to respond, there is nothing to compare
+      if self.data.messages.last().map_or(true, |m| m.role() != "assistant") {
+        ab.finish();
+      } else {
+        match self.compare_models() {
+          Some(comparison) => self.comparison = Some(comparison),
+          None => tx.send(Action::UpdateStatus(Some(waiting))).unwrap(),
+        }
+        return;
+      }
+    }
+    // a response with several choices waits until one of them is kept
+    if let Some(comparison) = self.compare_choices() {
+      self.comparison = Some(comparison);
+      tx.send(Action::UpdateStatus(Some("the response has several choices, enter keeps one".to_string()))).unwrap();
+      return;
+    }
+    let response = self.data.messages.iter().rev().find(|m| m.role() == "assistant");
+    match response.and_then(|m| m.finish_reason()) {
+      Some(FinishReason::Length) => match self.continuations < self.config.auto_continue {
+        true => {
+          let status = self.continue_response();
+          tx.send(Action::UpdateStatus(Some(status))).unwrap();
+        },
+        false => {
+          tx.send(Action::UpdateStatus(Some("response hit the token limit, c to continue".to_string()))).unwrap()
+        },
+      },
+      Some(FinishReason::ContentFilter) => {
+        if let Some(panel) = self.panel.as_mut() {
+          panel.end_round();
+        }
+        tx.send(Action::UpdateStatus(Some("the response was withheld by the content filter".to_string()))).unwrap();
+      },
+      Some(FinishReason::ToolCalls | FinishReason::FunctionCall | FinishReason::Stop) | None => {
+        self.execute_tool_calls();
+        self.next_persona_turn();
+      },
+    }
+  }
+
+  // the choices of the last response, when it has more than one
+  fn compare_choices(&self) -> Option<ChoiceComparison> {
+    let index = self.data.messages.iter().rposition(|m| m.role() == "assistant")?;
+    let choices = self.data.messages[index].choices();
+    if choices.len() < 2 {
+      return None;
+    }
+    let choices = choices
+      .into_iter()
+      .map(|(choice, message)| {
+        let message = MessageContainer::new_from_completed_message(ChatCompletionRequestMessage::Assistant(message));
+        (choice, RenderedChatMessage::from(&message).content)
+      })
+      .collect();
+    Some(ChoiceComparison::new(index, choices))
+  }
+
+  // what the session's model responded in a/b mode, from the response and its transaction
+  fn first_compared_response(&self, index: usize) -> Option<ComparedResponse> {
+    let content = RenderedChatMessage::from(self.data.messages.get(index)?).content;
+    Some(ComparedResponse::new(self.data.transactions.last()?, content))
+  }
+
+  // the responses of both models in a/b mode, once both have arrived
+  fn compare_models(&self) -> Option<ChoiceComparison> {
+    let (alternative, _) = self.ab.as_ref().filter(|ab| ab.is_ready())?.alternative.as_ref()?;
+    let index = self.data.messages.len().checked_sub(1)?;
+    let first = self.first_compared_response(index)?;
+    let responses = vec![(first.annotation(), first.content), (alternative.annotation(), alternative.content.clone())];
+    Some(ChoiceComparison::models(index, responses))
+  }
+
+  // keeps one model's response in the transcript, both are recorded with the transaction of the prompt
+  fn keep_model(&mut self, comparison: ChoiceComparison, choice: Option<usize>) {
+    let tx = self.action_tx.clone().unwrap();
+    let Some(mut ab) = self.ab.take() else {
+      return;
+    };
+    let first = self.first_compared_response(comparison.message);
+    if let (Some(mut first), Some((mut alternative, response))) = (first, ab.alternative.take()) {
+      let response = response.filter(|_| choice == Some(1));
+      (first.kept, alternative.kept) = (response.is_none(), response.is_some());
+      let kept = match response.is_some() {
+        true => alternative.model.clone(),
+        false => first.model.clone(),
+      };
+      if let Some(transaction) = self.data.transactions.last_mut() {
+        transaction.compared = vec![first, alternative];
+      }
+      if let Some(response) = response {
+        self.record_change("kept the other model's response");
+        let mut message = MessageContainer::from(ChatMessage::Response(response));
+        message.check_if_receive_is_complete();
+        self.data.messages[comparison.message] = message;
+        self.rebuild_request_buffer();
+        self.view.rerender(&mut self.data);
+      }
+      tx.send(Action::SaveSession).unwrap();
+      tx.send(Action::UpdateStatus(Some(format!("kept the response of {}", kept)))).unwrap();
+    }
+    ab.finish();
+    self.ab = Some(ab);
+    self.dispatch_finish_reason();
+  }
+
+  // keeps one choice of the compared response in the transcript, the others are discarded
+  fn keep_choice(&mut self, choice: Option<usize>) {
+    let Some(comparison) = self.comparison.take() else {
+      return;
+    };
+    if self.ab.as_ref().map_or(false, ModelComparison::is_ready) {
+      self.keep_model(comparison, choice);
+      return;
+    }
+    let tx = self.action_tx.clone().unwrap();
+    if choice.is_some() {
+      self.record_change("kept choice");
+    }
+    let kept = choice.map_or(false, |choice| {
+      self.data.messages.get_mut(comparison.message).map_or(false, |message| message.keep_choice(choice))
+    });
+    if kept {
+      self.rebuild_request_buffer();
+      self.view.rerender(&mut self.data);
+      tx.send(Action::SaveSession).unwrap();
+      tx.send(Action::UpdateStatus(Some(format!("kept choice {}", choice.unwrap_or_default() + 1)))).unwrap();
+    }
+    self.dispatch_finish_reason();
+  }
+
+  // asks the model to pick up where a response that was cut off stopped, the continuation is added to it
+  fn continue_response(&mut self) -> String {
+    match self.data.truncated_response() {
+      Some(index) => {
+        self.record_change("continuation");
+        self.continuation_of = Some(index);
+        self.continuations += 1;
+        self.request_chat_completion(self.action_tx.clone().unwrap());
+        format!("continuing the response ({})", self.continuations)
+      },
+      None => "the last response was not cut off".to_string(),
+    }
+  }
+
+  fn resolve_pending_input(&mut self, command: &str, session_id: Option<&str>) -> Result<String, SazidError> {
+    let Some(input) = self.pending_input.take() else {
+      return Ok(format!("{} is only available when a related session is offered", command));
+    };
+    let result = match (command, session_id) {
+      ("new", _) => Ok("starting a new session".to_string()),
+      (command, None) => Err(format!("usage: {} <session id>", command)),
+      ("continue", Some(session_id)) => {
+        let openai_config = self.config.openai_config.clone();
+        let session_path = data_path(SESSIONS_DIR).unwrap().join(Self::get_session_filename(session_id.into()));
+        match self.load_session_by_path(session_path.to_string_lossy().to_string()) {
+          Ok(()) => {
+            self.config.openai_config = openai_config;
+            self.request_buffer.clear();
+            self.redraw_messages();
+            Ok(format!("continuing session {}", session_id))
+          },
+          Err(e) => Err(format!("failed to continue session {}: {}", session_id, e)),
+        }
+      },
+      (_, Some(session_id)) => {
+        let session_path = data_path(SESSIONS_DIR).unwrap().join(Self::get_session_filename(session_id.into()));
+        let session_json = encryption::read_to_string(&session_path).ok();
+        match session_json.and_then(|j| SessionSummary::from_session_json(session_id, &j)) {
+          Some(summary) => {
+            self.update(Action::AddMessage(ChatMessage::User(ChatCompletionRequestUserMessage {
+              role: Role::User,
+              content: Some(ChatCompletionRequestUserMessageContent::Text(summary.summary())),
+            })))?;
+            Ok(format!("attached summary of session {}", session_id))
+          },
+          None => Err(format!("session {} not found", session_id)),
+        }
+      },
+    };
+    match result {
+      Ok(result) => {
+        self.submit_chat_completion_request(input, self.action_tx.clone().unwrap());
+        Ok(result)
+      },
+      // keep the input so that the user can pick another option
+      Err(e) => {
+        self.pending_input = Some(input);
+        Ok(e)
+      },
+    }
+  }
+
+  // x excludes the selected message from requests or includes it again, p pins it so that compression leaves it
+  // intact, D deletes it, all can be undone, enter expands it in the compact transcript, z folds it and Z folds its
+  // code blocks
+  fn handle_message_selection_key(&mut self, key: KeyEvent) -> Option<Action> {
+    let selected = self.message_selection?;
+    let last = self.data.messages.len().saturating_sub(1);
+    match key.code {
+      KeyCode::Down | KeyCode::Char('j') => self.message_selection = Some((selected + 1).min(last)),
+      KeyCode::Up | KeyCode::Char('k') => self.message_selection = Some(selected.saturating_sub(1)),
+      KeyCode::Char('x') => {
+        self.record_change("exclude message");
+        let mut data = self.data.clone();
+        let excluded = data.toggle_excluded(selected);
+        self.replace_transcript(data);
+        let status = if excluded { "message excluded from context" } else { "message included in context" };
+        return Some(Action::UpdateStatus(Some(status.to_string())));
+      },
+      KeyCode::Char('p') => {
+        self.record_change("pin message");
+        let mut data = self.data.clone();
+        let pinned = data.toggle_pinned(selected);
+        self.replace_transcript(data);
+        let status = if pinned { "message pinned in context" } else { "message unpinned" };
+        return Some(Action::UpdateStatus(Some(status.to_string())));
+      },
+      KeyCode::Char('D') => {
+        self.record_change("delete message");
+        let mut data = self.data.clone();
+        let count = data.delete_message(selected);
+        self.replace_transcript(data);
+        self.message_selection = match self.data.messages.len() {
+          0 => None,
+          len => Some(selected.min(len - 1)),
+        };
+        return Some(Action::UpdateStatus(Some(format!("deleted {} message(s), u to undo", count))));
+      },
+      KeyCode::Enter => {
+        self.message_selection = None;
+        return self.toggle_expanded(selected);
+      },
+      KeyCode::Char('z') => {
+        self.message_selection = None;
+        return self.toggle_fold(selected, Fold::Message);
+      },
+      KeyCode::Char('Z') => {
+        self.message_selection = None;
+        return self.toggle_fold(selected, Fold::CodeBlocks);
+      },
+      KeyCode::Esc | KeyCode::Char('q') => self.message_selection = None,
+      _ => {},
+    }
+    Some(Action::Update)
+  }
+
+  // shows a message of the compact transcript in full, or collapses it again
+  fn toggle_expanded(&mut self, index: usize) -> Option<Action> {
+    if !self.view.compact {
+      return Some(Action::UpdateStatus(Some("the transcript is already expanded, C for compact".to_string())));
+    }
+    let message = self.data.messages.get_mut(index)?;
+    message.expanded = !message.expanded;
+    self.view.rerender(&mut self.data);
+    let line = self.view.message_start_line(&self.data, index);
+    self.view.text_area.move_cursor(CursorMove::Jump(line as u16, 0));
+    Some(Action::Update)
+  }
+
+  // folds a message or its code blocks to one line, or unfolds it again
+  fn toggle_fold(&mut self, index: usize, fold: Fold) -> Option<Action> {
+    let message = self.data.messages.get_mut(index)?;
+    message.fold = if message.fold == fold { Fold::Open } else { fold };
+    self.view.rerender(&mut self.data);
+    let line = self.view.message_start_line(&self.data, index);
+    self.view.text_area.move_cursor(CursorMove::Jump(line as u16, 0));
+    Some(Action::Update)
+  }
+
+  fn draw_dry_run(&self, f: &mut Frame<'_>, area: Rect, description: &str, scroll: u16) {
+    let popup_width = area.width.saturating_sub(4).min(140);
+    let popup_height = area.height.saturating_sub(2);
+    let popup = Rect::new(
+      area.x + (area.width.saturating_sub(popup_width)) / 2,
+      area.y + (area.height.saturating_sub(popup_height)) / 2,
+      popup_width,
+      popup_height,
+    );
+    let theme = theme::current();
+    let pane = Paragraph::new(description).scroll((scroll, 0)).block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(theme.border())
+        .title(Line::from(vec![
+          Span::raw(tr("Dry Run ")),
+          Span::styled("(", theme.hint()),
+          Span::styled("j", theme.hint_key()),
+          Span::styled("/", theme.hint()),
+          Span::styled("k", theme.hint_key()),
+          Span::styled(tr(" to scroll, "), theme.hint()),
+          Span::styled("ESC", theme.hint_key()),
+          Span::styled(tr(" to close)"), theme.hint()),
+        ])),
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(pane, popup);
+  }
+
+  fn draw_message_selection(&self, f: &mut Frame<'_>, area: Rect, selected: usize) {
+    let theme = theme::current();
+    let items = self
+      .data
+      .messages
+      .iter()
+      .map(|m| {
+        let style = if m.excluded { theme.hint() } else { Style::default() };
+        let marker = match (m.excluded, m.pinned) {
+          (true, _) => "[excluded] ",
+          (_, true) => "[pinned] ",
+          _ => "",
+        };
+        ListItem::new(format!("{}{}", marker, m.summary())).style(style)
+      })
+      .collect::<Vec<ListItem>>();
+    let popup_width = area.width.saturating_sub(4).min(120);
+    let popup_height = (items.len() as u16 + 2).min(area.height.saturating_sub(2));
+    let popup = Rect::new(
+      area.x + (area.width.saturating_sub(popup_width)) / 2,
+      area.y + (area.height.saturating_sub(popup_height)) / 2,
+      popup_width,
+      popup_height,
+    );
+    let list = List::new(items)
+      .block(
+        Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).title(Line::from(vec![
+          Span::raw(tr("Messages ")),
+          Span::styled("(", theme.hint()),
+          Span::styled("x", theme.hint_key()),
+          Span::styled(tr(" exclude from context, "), theme.hint()),
+          Span::styled("p", theme.hint_key()),
+          Span::styled(tr(" pin, "), theme.hint()),
+          Span::styled("D", theme.hint_key()),
+          Span::styled(tr(" delete, "), theme.hint()),
+          Span::styled("enter", theme.hint_key()),
+          Span::styled(tr(" expand, "), theme.hint()),
+          Span::styled("z", theme.hint_key()),
+          Span::styled(tr(" fold, "), theme.hint()),
+          Span::styled("Z", theme.hint_key()),
+          Span::styled(tr(" fold code, "), theme.hint()),
+          Span::styled("ESC", theme.hint_key()),
+          Span::styled(tr(" to close)"), theme.hint()),
+        ])),
+      )
+      .highlight_style(theme.highlight())
+      .highlight_symbol("> ");
+    let mut state = ListState::default();
+    state.select(Some(selected));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+  }
+
+  // uploads the transcript, or a range of its messages, in the background and shows the link in the status bar
+  fn share(&mut self, args: &[&str]) -> Result<String, SazidError> {
+    let usage = "usage: share [gist|paste] [N|N-M] [public]";
+    let (mut target, mut range, mut public) = (ShareTarget::Gist, None, false);
+    for arg in args {
+      match *arg {
+        "gist" => target = ShareTarget::Gist,
+        "paste" => target = ShareTarget::Paste,
+        "public" => public = true,
+        arg if arg.chars().next().map_or(false, |c| c.is_ascii_digit()) => range = Some(arg),
+        _ => return Ok(usage.to_string()),
+      }
+    }
+    let transcript = Transcript::from_session_data(&self.config, &self.data);
+    let transcript = match range {
+      Some(range) => match transcript.select(range) {
+        Ok(transcript) => transcript,
+        Err(e) => return Ok(e.to_string()),
+      },
+      None => transcript,
+    };
+    let tx = self.action_tx.clone().unwrap();
+    let (offline, redaction) = (self.config.offline, self.config.redaction.clone());
+    tokio::spawn(async move {
+      let status = match share(&transcript, target, public, offline, &redaction).await {
+        Ok(url) => format!("shared at {}", url),
+        Err(e) => e.to_string(),
+      };
+      tx.send(Action::UpdateStatus(Some(status))).unwrap();
+    });
+    Ok(format!("sharing {} messages...", range.unwrap_or("all")))
+  }
+
+  // appends the message to the tee file when teeing is enabled
+  fn tee_message(&mut self, message: &ChatMessage) {
+    if !self.config.tee.enabled {
+      return;
+    }
+    if self.tee.as_ref().map_or(true, |tee| tee.session_id != self.config.session_id) {
+      match Tee::open(&self.config.tee, &self.config.session_id) {
+        Ok(tee) => self.tee = Some(tee),
+        Err(e) => {
+          // disabled for the rest of the run, rather than failing again for every chunk
+          log::error!("failed to open the tee file, teeing is disabled: {}", e);
+          self.config.tee.enabled = false;
+          return;
+        },
+      }
+    }
+    if let Some(tee) = self.tee.as_mut() {
+      if let Err(e) = tee.write_message(message, self.config.tee.prompts) {
+        log::error!("failed to write to the tee file {}: {}", tee.path.display(), e);
+      }
+    }
+  }
+
+  // saves the transcript before a change, so that it can be undone
+  fn record_change(&mut self, change: &str) {
+    self.history.record(change, self.data.clone());
+  }
+
+  fn replace_transcript(&mut self, data: SessionData) {
+    // requests that were made stay in the stats when the transcript changes
+    let transactions = std::mem::take(&mut self.data.transactions);
+    self.data = data;
+    self.data.transactions = transactions;
+    self.rebuild_request_buffer();
+    self.view.rerender(&mut self.data);
+  }
+
+  fn rebuild_request_buffer(&mut self) {
+    self.request_buffer = self.data.messages.iter().filter(|m| m.receive_complete).map(|m| m.message.clone()).collect();
+  }
+
+  // the transcript before the last change, or after the last undone change
+  fn restore_transcript(&mut self, redo: bool) -> String {
+    let restored = match redo {
+      false => self.history.undo(self.data.clone()),
+      true => self.history.redo(self.data.clone()),
+    };
+    match (restored, redo) {
+      (Some((change, data)), _) => {
+        self.replace_transcript(data);
+        format!("{} {}", if redo { "redid" } else { "undid" }, change)
+      },
+      (None, false) => "nothing to undo".to_string(),
+      (None, true) => "nothing to redo".to_string(),
+    }
+  }
+
+  // the most recent assistant response, its content and its runnable example at example_index, or why there is none
+  fn response_example(&self, example_index: usize) -> Result<(usize, String, CodeBlock), String> {
+    let Some(message_index) = self.data.messages.iter().rposition(|m| {
+      m.receive_complete && matches!(&m.message, ChatCompletionRequestMessage::Assistant(a) if a.content.is_some())
+    }) else {
+      return Err("no assistant response to run examples from".to_string());
+    };
+    let ChatCompletionRequestMessage::Assistant(assistant_message) = &self.data.messages[message_index].message
+    else {
+      return Err("no assistant response to run examples from".to_string());
+    };
+    let content = assistant_message.content.clone().unwrap_or_default();
+    let mut examples = extract_code_blocks(&content).into_iter().filter(|b| b.is_runnable()).collect::<Vec<_>>();
+    if example_index >= examples.len() {
+      return Err(format!(
+        "example {} not found, the response has {} runnable examples",
+        example_index + 1,
+        examples.len()
+      ));
+    }
+    Ok((message_index, content, examples.swap_remove(example_index)))
+  }
+
+  // asks the user before running a code example from the most recent assistant response, unless the sandbox policy
+  // denies the program that runs it
+  pub fn run_response_example(&mut self, example_index: usize) -> Result<String, SazidError> {
+    let example = match self.response_example(example_index) {
+      Ok((_, _, example)) => example,
+      Err(reason) => return Ok(reason),
+    };
+    let program = example.program().unwrap_or_default();
+    if let Err(resource) = self.config.sandbox.check(&[Resource::Command(program.to_string())]) {
+      return Ok(format!("access to {} is denied by the sandbox policy", resource));
+    }
+    let description = format!(
+      "the {} example runs with {} in a scratch directory, on this machine:\n\n{}",
+      example.language,
+      program,
+      example.code.trim_end()
+    );
+    self.action_tx.as_ref().unwrap().send(Action::ConfirmExample(example_index, description)).unwrap();
+    Ok(format!("confirm running {} example {}", example.language, example_index + 1))
+  }
+
+  // runs a confirmed example in the background, its output comes back as Action::ExampleOutput
+  fn start_example(&mut self, example_index: usize, tx: UnboundedSender<Action>) {
+    let example = match self.response_example(example_index) {
+      Ok((_, _, example)) => example,
+      Err(reason) => return tx.send(Action::UpdateStatus(Some(reason))).unwrap(),
+    };
+    tx.send(Action::UpdateStatus(Some(format!("running {} example {}", example.language, example_index + 1)))).unwrap();
+    tokio::spawn(async move {
+      match run_example(&example, EXAMPLE_TIMEOUT).await {
+        Ok(output) => tx.send(Action::ExampleOutput(example_index, output)).unwrap(),
+        Err(e) => tx.send(Action::UpdateStatus(Some(format!("failed to run example: {}", e)))).unwrap(),
+      }
+    });
+  }
+
+  // appends the output of an example beneath it in the most recent assistant response
+  fn add_example_output(&mut self, example_index: usize, output: &str) -> String {
+    let (message_index, content, example) = match self.response_example(example_index) {
+      Ok(found) => found,
+      Err(reason) => return reason,
+    };
+    self.record_change("run example");
+    if let ChatCompletionRequestMessage::Assistant(assistant_message) = &mut self.data.messages[message_index].message {
+      assistant_message.content = Some(insert_example_output(&content, &example, output));
+    }
+    // keep the request buffer in sync so that the model sees the captured output
+    if message_index < self.request_buffer.len() {
+      self.request_buffer[message_index] = self.data.messages[message_index].message.clone();
+    }
+    self.redraw_messages();
+    format!("ran {} example {}", example.language, example_index + 1)
+  }
+
+  pub fn add_chunked_chat_completion_request_messages(
+    &mut self,
+    content: &str,
+    _name: &str,
+    role: Role,
+    model: &Model,
+  ) -> Result<(), SazidError> {
+    match parse_input(content, CHUNK_TOKEN_LIMIT as usize, model.token_limit as usize) {
+      Ok(chunks) => {
+        chunks.iter().for_each(|chunk| {
+          // explicitly calling update because we need this to be blocking, since it can't move on until the input is processed
+          self
+            .update(Action::AddMessage(ChatMessage::User(ChatCompletionRequestUserMessage {
+              role,
+              content: Some(ChatCompletionRequestUserMessageContent::Text(chunk.clone())),
+            })))
+            .unwrap();
+        });
+        Ok(())
+      },
+      Err(e) => Err(SazidError::ChunkifierError(e)),
+    }
+  }
+
+  pub fn add_new_messages_to_request_buffer(&mut self) {
+    // add new request messages to the request buffer
+    let new_requests: Vec<ChatCompletionRequestMessage> = self
+      .data
+      .messages
+      .iter()
+      .skip(self.request_buffer.len())
+      .filter(|m| m.receive_complete)
+      .map(|m| {
+        // let debug = format!("message: {:#?}", m.message).bright_red().to_string();
+        // trace_dbg!(debug);
+        m.message.clone()
+      })
+      .collect();
+    self.request_buffer.extend(new_requests);
+    trace_dbg!("request_buffer: {:#?}", self.request_buffer);
+  }
+
+  // indices of the pinned messages in the request, which skips excluded messages and counts the system messages
+  // construct_request added
+  pub fn pinned_request_indices(&self) -> Vec<usize> {
+    let (position, count) = self.context_messages;
+    self
+      .data
+      .messages
+      .iter()
+      .take(self.request_buffer.len())
+      .filter(|m| !m.excluded)
+      .enumerate()
+      .filter(|(_, m)| m.pinned)
+      .map(|(i, _)| if i >= position { i + count } else { i })
+      .collect()
+  }
+
+  pub fn construct_request(&mut self) -> CreateChatCompletionRequest {
+    let tools = match self.config.available_functions.is_empty() {
+      true => None,
+      false => {
+        Some(create_chat_completion_tool_args(self.config.available_functions.iter().map(|f| f.into()).collect()))
+      },
+    };
+    self.add_new_messages_to_request_buffer();
+    let _token_count = 0;
+    // let debug = format!("{:#?}", self.request_buffer).bright_cyan().to_string();
+    // trace_dbg!("constructing request {}", debug);
+
+    // the persona responding uses its own model, unless the input asked for one
+    let persona_model = self.panel.as_ref().and_then(Panel::speaker).and_then(|(_, persona)| persona.model.as_deref());
+    let model = match (&self.model_override, persona_model) {
+      (Some(model), _) => model.clone(),
+      (None, Some(name)) => Model::from_name(name),
+      (None, None) => self.config.model.clone(),
+    };
+    // the request buffer follows the transcript, so excluded messages and the persona that wrote each are found by
+    // index
+    let messages = self
+      .request_buffer
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| !self.data.messages.get(*i).map(|m| m.excluded).unwrap_or(false))
+      .map(|(i, message)| (self.data.messages.get(i).and_then(|m| m.persona.as_deref()), message.clone()))
+      .collect::<Vec<_>>();
+    let transcript_messages = messages.len();
+    let position = messages.iter().take_while(|(_, m)| matches!(m, ChatCompletionRequestMessage::System(_))).count();
+    let mut messages = match &self.panel {
+      Some(panel) => panel.address(messages),
+      None => messages.into_iter().map(|(_, message)| message).collect(),
+    };
+    // the attached files are read again for every request, and follow the leading system messages
+    let files = self.config.read_attachments();
+    self.summarize_attachments(&files);
+    if let Some(message) = attachments_message(&files) {
+      messages.insert(position, ChatCompletionRequestMessage::System(message));
+    }
+    if let Some(language) = &self.config.response_language {
+      messages.insert(
+        position,
+        ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+          role: Role::System,
+          content: Some(i18n::response_language_prompt(language)),
+        }),
+      );
+    }
+    self.context_messages = (position, messages.len() - transcript_messages);
+    let mut request = CreateChatCompletionRequest {
+      model: self.config.provider.model_id(&model.name),
+      messages,
+      stream: Some(self.config.stream_response),
+      max_tokens: Some(self.config.response_max_tokens as u16),
+      // todo: put the user information in here
+      user: Some("testing testing".to_string()),
+      tools,
+      ..Default::default()
+    };
+    self.config.request_parameters.apply(&mut request);
+    // the request to continue is only in the request, the transcript shows the continued response as one message
+    if self.continuation_of.is_some() {
+      request.n = None;
+      request.messages.push(ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+        role: Role::User,
+        content: Some(ChatCompletionRequestUserMessageContent::Text(CONTINUE_PROMPT.to_string())),
+      }));
+    }
+    // trace_dbg!("request:\n{:#?}", request);
+    request
+  }
+
+  // large attached files without a current summary are summarized for the requests that follow
+  fn summarize_attachments(&mut self, files: &[AttachedFile]) {
+    let Some(tx) = self.action_tx.clone() else {
+      return;
+    };
+    for file in files.iter().filter(|file| file.needs_summary()) {
+      if !self.summarizing.contains(&file.path) {
+        self.summarizing.push(file.path.clone());
+        tx.send(Action::SummarizeAttachment(file.path.clone())).unwrap();
+      }
+    }
+  }
+
+  fn filter_non_ascii(s: &str) -> String {
+    s.chars().filter(|c| c.is_ascii()).collect()
+  }
 
   pub fn submit_chat_completion_request(&mut self, input: String, tx: UnboundedSender<Action>) {
+    self.record_change("new exchange");
+    (self.continuation_of, self.continuations) = (None, 0);
     let config = self.config.clone();
     tx.send(Action::UpdateStatus(Some("submitting input".to_string()))).unwrap();
     match self.add_chunked_chat_completion_request_messages(
@@ -585,8 +2516,10 @@ impl Session<'static> {
       Role::User,
       &config.model,
     ) {
-      Ok(_) => {
-        tx.send(Action::RequestChatCompletion()).unwrap();
+      // the request is sent once the retrieved chunks are added
+      Ok(_) => match &config.retrieval {
+        Some(settings) => tx.send(Action::RetrieveContext(input, settings.clone())).unwrap(),
+        None => tx.send(Action::RequestChatCompletion()).unwrap(),
       },
       Err(e) => {
         tx.send(Action::Error(format!("Error: {:?}", e))).unwrap();
@@ -595,67 +2528,281 @@ impl Session<'static> {
   }
 
   pub fn request_chat_completion(&mut self, tx: UnboundedSender<Action>) {
+    if let Some(agent) = self.agent.as_mut().filter(|agent| agent.is_running()) {
+      if !agent.start_iteration() {
+        self.config.confirm_side_effects = false;
+        tx.send(Action::UpdateStatus(Some(agent.describe()))).unwrap();
+        self.notify_agent_finished();
+        return;
+      }
+    }
     tx.send(Action::UpdateStatus(Some("Configuring Client".to_string()))).unwrap();
     let stream_response = self.config.stream_response;
     let openai_config = self.config.openai_config.clone();
+    let retry_policy = self.config.retry_policy.clone();
+    let timeouts = self.config.timeouts.clone();
+    let pricing = self.model_pricing.clone();
+    let middleware = match MiddlewareChain::from_config(&self.config) {
+      Ok(middleware) => middleware,
+      Err(e) => {
+        tx.send(Action::Error(format!("Error: {}", e))).unwrap();
+        return;
+      },
+    };
 
-    let request = self.construct_request();
+    let mut request = self.construct_request();
+    let compression_report = match self.config.compress_prompt {
+      true => {
+        let pinned = self.pinned_request_indices();
+        compress_messages(&mut request.messages, self.config.compression_threshold_tokens, &pinned)
+      },
+      false => None,
+    };
+    if self.config.dry_run {
+      // the unsent messages are queued so that :retry sends them once dry run is off
+      self.data.mark_pending();
+      self.view.post_process_new_messages(&mut self.data);
+      self.dry_run_request = Some((describe_request(&request), 0));
+      tx.send(Action::UpdateStatus(Some("dry run, :dryrun off then :retry to send".to_string()))).unwrap();
+      return;
+    }
+    if !std::mem::take(&mut self.request_confirmed) {
+      let estimate = RequestEstimate::new(&request, &self.model_pricing);
+      let reasons = estimate.exceeded(&self.config.confirm_thresholds);
+      if !reasons.is_empty() {
+        tx.send(Action::ConfirmRequest(format!("{}\n\n{}", reasons.join("\n"), estimate))).unwrap();
+        return;
+      }
+    }
     debug_request_validation(&request);
     // let request = self.request_message_buffer.clone().unwrap();
     // let token_count = self.request_buffer_token_count;
     tx.send(Action::UpdateStatus(Some("Assembling request...".to_string()))).unwrap();
+    // in a/b mode a prompt also goes to the second model, but tool results and continuations only to the session's
+    let prompted = self.continuation_of.is_none()
+      && self.data.messages.last().map_or(false, |m| matches!(m.role(), "user" | "system"));
+    if let Some(ab) = self.ab.as_mut().filter(|_| prompted) {
+      // the second model's request goes through its own chain, so that it is checked, cached and counted the same way
+      let alternative_middleware = match MiddlewareChain::from_config(&self.config) {
+        Ok(middleware) => middleware,
+        Err(e) => {
+          tx.send(Action::Error(format!("Error: {}", e))).unwrap();
+          return;
+        },
+      };
+      ab.start();
+      let mut alternative = request.clone();
+      alternative.model = self.config.provider.model_id(&ab.model);
+      (alternative.stream, alternative.n) = (None, None);
+      let (tx, openai_config, retry_policy, pricing) =
+        (tx.clone(), openai_config.clone(), retry_policy.clone(), pricing.clone());
+      tokio::spawn(async move {
+        let client = create_openai_client(&openai_config).with_backoff(RetryPolicy::disabled().backoff());
+        let (compared, response) =
+          request_alternative(&client, &alternative_middleware, alternative, &retry_policy, &pricing).await;
+        tx.send(Action::AlternativeResponse(compared, response)).unwrap();
+      });
+    }
+    let cancellation = CancellationToken::new();
+    self.cancellation = Some(cancellation.clone());
     tokio::spawn(async move {
       tx.send(Action::UpdateStatus(Some("Establishing Client Connection".to_string()))).unwrap();
       tx.send(Action::EnterProcessing).unwrap();
-      let client = create_openai_client(&openai_config);
-      trace_dbg!("client connection established");
-      // tx.send(Action::AddMessage(ChatMessage::SazidSystemMessage(format!("Request Token Count: {}", token_count))))
-      //   .unwrap();
-      match stream_response {
-        true => {
-          tx.send(Action::UpdateStatus(Some("Sending Request to OpenAI API...".to_string()))).unwrap();
-          trace_dbg!("Sending Request to API");
-          let mut stream = client.chat().create_stream(request).await.unwrap();
-          tx.send(Action::UpdateStatus(Some("Request submitted. Awaiting Response...".to_string()))).unwrap();
-          while let Some(response_result) = stream.next().await {
-            match response_result {
-              Ok(response) => {
-                trace_dbg!("Response: {:#?}", response.bright_yellow());
-                //tx.send(Action::UpdateStatus(Some(format!("Received responses: {}", count).to_string()))).unwrap();
-                tx.send(Action::AddMessage(ChatMessage::StreamResponse(vec![response]))).unwrap();
+      let (timestamp, started) = (chrono::Utc::now().timestamp(), Instant::now());
+      let mut first_token = None;
+      // recorded with the request so that the api health reflects failures
+      let mut request_error: Option<String> = None;
+      let mut responses: Vec<ChatMessage> = vec![];
+      // set when the request could not reach the api, so it is queued instead of reported as an error
+      let mut queued_error: Option<String> = None;
+      // set when the request was cancelled or timed out, the content streamed until then is kept
+      let mut interrupted = false;
+      let mut timed_out = false;
+      let pre_request = middleware.pre_request(&mut request).await;
+      // shown now and again once the request completes, the status changes while the response streams in
+      let notices = middleware.take_notices();
+      notices.iter().for_each(|notice| tx.send(Action::UpdateStatus(Some(notice.clone()))).unwrap());
+      match pre_request {
+        Ok(Some(middleware_responses)) => {
+          middleware_responses.iter().for_each(|r| tx.send(Action::AddMessage(r.clone())).unwrap());
+          tx.send(Action::Update).unwrap();
+          responses = middleware_responses;
+        },
+        Ok(None) => {
+          // retries are made by create_stream_with_retry and create_with_retry, so that they can be reported
+          let client = create_openai_client(&openai_config).with_backoff(RetryPolicy::disabled().backoff());
+          let on_retry = |attempt, delay, error: &OpenAIError| {
+            tx.send(Action::UpdateStatus(Some(retry_status(attempt, &retry_policy, delay, error)))).unwrap();
+          };
+          trace_dbg!("client connection established");
+          // tx.send(Action::AddMessage(ChatMessage::SazidSystemMessage(format!("Request Token Count: {}", token_count))))
+          //   .unwrap();
+          match stream_response {
+            true => {
+              tx.send(Action::UpdateStatus(Some("Sending Request to OpenAI API...".to_string()))).unwrap();
+              trace_dbg!("Sending Request to API");
+              let stream = tokio::select! {
+                _ = cancellation.cancelled() => {
+                  interrupted = true;
+                  None
+                },
+                _ = timeouts.expired(started, None) => {
+                  timed_out = true;
+                  None
+                },
+                stream = create_stream_with_retry(&client, &request, &retry_policy, on_retry) => Some(stream),
+              };
+              match stream {
+                None => {},
+                Some(Ok(mut stream)) => {
+                  tx.send(Action::UpdateStatus(Some("Request submitted. Awaiting Response...".to_string()))).unwrap();
+                  let mut last_chunk = Instant::now();
+                  let mut tool_calls = ToolCallAssembler::default();
+                  // ending the loop drops the stream, which closes the connection
+                  while let Some(response_result) = tokio::select! {
+                    _ = cancellation.cancelled() => {
+                      interrupted = true;
+                      None
+                    },
+                    _ = timeouts.expired(started, Some(last_chunk)) => {
+                      timed_out = true;
+                      None
+                    },
+                    response_result = stream.next() => response_result,
+                  } {
+                    match response_result {
+                      Ok(response) => {
+                        first_token.get_or_insert(started.elapsed());
+                        last_chunk = Instant::now();
+                        trace_dbg!("Response: {:#?}", response.bright_yellow());
+                        //tx.send(Action::UpdateStatus(Some(format!("Received responses: {}", count).to_string()))).unwrap();
+                        tool_calls.push_response(&response, 0);
+                        if let Some(status) = tool_calls.describe() {
+                          tx.send(Action::UpdateStatus(Some(status))).unwrap();
+                        }
+                        let mut message = ChatMessage::StreamResponse(vec![response]);
+                        if let Err(e) = middleware.post_response(&request, &mut message).await {
+                          tx.send(Action::Error(format!("Error: {}", e))).unwrap();
+                        }
+                        tx.send(Action::AddMessage(message.clone())).unwrap();
+                        tx.send(Action::Update).unwrap();
+                        responses.push(message);
+                      },
+                      Err(e) => {
+                        trace_dbg!("Error: {:#?} -- check https://status.openai.com", e.bright_red());
+                        middleware.on_error(&request, &e).await;
+                        request_error = Some(e.to_string());
+
+                        // let reqtext =
+                        //   format!("Request: \n{}", to_string_pretty(&request).unwrap_or("can't prettify result".to_string()));
+                        // trace_dbg!(&reqtext);
+                        // tx.send(Action::AddMessage(ChatMessage::SazidSystemMessage(reqtext))).unwrap();
+                        tx.send(Action::Error(format!("Error: {:?} -- check https://status.openai.com/", e))).unwrap();
+                      },
+                    }
+                  }
+                  // a call that doesn't parse still runs, and its error goes back to the model to correct
+                  if !interrupted && !timed_out {
+                    for error in tool_calls.validate() {
+                      log::warn!("{}", error);
+                      tx.send(Action::UpdateStatus(Some(error.to_string()))).unwrap();
+                    }
+                  }
+                },
+                Some(Err(e)) if is_network_error(&e) => queued_error = Some(e.to_string()),
+                Some(Err(e)) => {
+                  middleware.on_error(&request, &e).await;
+                  request_error = Some(e.to_string());
+                  tx.send(Action::Error(format!("Error: {:?} -- check https://status.openai.com/", e))).unwrap();
+                },
+              }
+            },
+            false => match tokio::select! {
+              _ = cancellation.cancelled() => {
+                interrupted = true;
+                None
+              },
+              _ = timeouts.expired(started, None) => {
+                timed_out = true;
+                None
+              },
+              response = create_with_retry(&client, &request, &retry_policy, on_retry) => Some(response),
+            } {
+              None => {},
+              Some(Ok(response)) => {
+                let mut message = ChatMessage::Response(response);
+                if let Err(e) = middleware.post_response(&request, &mut message).await {
+                  tx.send(Action::Error(format!("Error: {}", e))).unwrap();
+                }
+                tx.send(Action::AddMessage(message.clone())).unwrap();
                 tx.send(Action::Update).unwrap();
+                responses.push(message);
               },
-              Err(e) => {
-                trace_dbg!("Error: {:#?} -- check https://status.openai.com", e.bright_red());
-
-                // let reqtext =
-                //   format!("Request: \n{}", to_string_pretty(&request).unwrap_or("can't prettify result".to_string()));
-                // trace_dbg!(&reqtext);
-                // tx.send(Action::AddMessage(ChatMessage::SazidSystemMessage(reqtext))).unwrap();
-                tx.send(Action::Error(format!("Error: {:?} -- check https://status.openai.com/", e))).unwrap();
+              Some(Err(e)) if is_network_error(&e) => queued_error = Some(e.to_string()),
+              Some(Err(e)) => {
+                trace_dbg!("Error: {}", e);
+                middleware.on_error(&request, &e).await;
+                request_error = Some(e.to_string());
+                tx.send(Action::Error(format!("Error: {:#?} -- check https://status.openai.com/", e))).unwrap();
               },
-            }
-          }
+            },
+          };
         },
-        false => match client.chat().create(request).await {
-          Ok(response) => {
-            tx.send(Action::AddMessage(ChatMessage::Response(response))).unwrap();
-            tx.send(Action::Update).unwrap();
-          },
-          Err(e) => {
-            trace_dbg!("Error: {}", e);
-            tx.send(Action::Error(format!("Error: {:#?} -- check https://status.openai.com/", e))).unwrap();
-          },
+        Err(e) => {
+          tx.send(Action::Error(format!("Error: {}", e))).unwrap();
+        },
+      }
+      if timed_out && responses.is_empty() {
+        request_error = Some(format!("timed out after {}s", started.elapsed().as_secs()));
+      }
+      // a partial response is not passed on, so that it is neither cached nor recorded
+      if interrupted {
+        tx.send(Action::ResponseInterrupted).unwrap();
+      } else if timed_out {
+        tx.send(Action::ResponseTimedOut).unwrap();
+      } else if !responses.is_empty() {
+        if let Err(e) = middleware.on_complete(&request, &responses).await {
+          tx.send(Action::Error(format!("Error: {}", e))).unwrap();
+        }
+      }
+      let transaction = match (request_error.or_else(|| queued_error.clone()), responses.is_empty()) {
+        (Some(error), _) => Some(Transaction::failed(&request, timestamp, started.elapsed(), error)),
+        (None, false) => {
+          let mut transaction = Transaction::new(&request, &responses, timestamp, started.elapsed(), &pricing);
+          transaction.first_token_ms = first_token.map(|latency| latency.as_millis() as u64);
+          Some(transaction)
         },
+        (None, true) => None,
       };
-      tx.send(Action::UpdateStatus(Some("Chat Request Complete".to_string()))).unwrap();
-      tx.send(Action::SaveSession).unwrap();
+      if let Some(mut transaction) = transaction {
+        transaction.timed_out = timed_out;
+        let raw = RawExchange::new(&openai_config, &request, &responses, transaction.error.as_deref());
+        transaction.raw = Some(raw);
+        tx.send(Action::RecordTransaction(transaction)).unwrap();
+      }
+      let mut reports = compression_report.iter().map(|report| report.to_string()).collect::<Vec<String>>();
+      reports.extend(notices);
+      match (queued_error, reports.is_empty()) {
+        _ if interrupted => tx.send(Action::UpdateStatus(Some("request cancelled".to_string()))).unwrap(),
+        _ if timed_out => {
+          tx.send(Action::UpdateStatus(Some("request timed out, c asks the model to continue".to_string()))).unwrap()
+        },
+        (Some(error), _) => tx.send(Action::RequestQueued(error)).unwrap(),
+        (None, false) => {
+          trace_dbg!("{}", reports.join(", "));
+          tx.send(Action::UpdateStatus(Some(format!("Chat Request Complete, {}", reports.join(", "))))).unwrap()
+        },
+        (None, true) => tx.send(Action::UpdateStatus(Some("Chat Request Complete".to_string()))).unwrap(),
+      }
       tx.send(Action::ExitProcessing).unwrap();
+      if interrupted {
+        tx.send(Action::EnterInsert).unwrap();
+      }
     });
   }
 
   pub fn get_session_filepath(session_id: String) -> PathBuf {
-    Path::new(SESSIONS_DIR).join(Self::get_session_filename(session_id))
+    data_path(SESSIONS_DIR).unwrap().join(Self::get_session_filename(session_id))
   }
 
   pub fn get_session_filename(session_id: String) -> String {
@@ -673,25 +2820,39 @@ impl Session<'static> {
   }
 
   fn load_session(&mut self, session_serde: String) -> Result<(), SazidError> {
-    let incoming_session: Session = serde_json::from_str(session_serde.as_str()).unwrap();
+    let incoming_session: Session = migrate_session(&session_serde)?;
     self.data = incoming_session.data;
+    // dry run, the cassette, the mock fixtures and the databases are set for the whole run rather than saved with the
+    // session
+    let (dry_run, vcr) = (self.config.dry_run, self.config.vcr.take());
+    let (mock_fixtures, databases) = (self.config.mock_fixtures.take(), std::mem::take(&mut self.config.databases));
     self.config = incoming_session.config;
+    self.config.dry_run = dry_run;
+    self.config.vcr = vcr;
+    self.config.mock_fixtures = mock_fixtures;
+    self.config.databases = databases;
+    self.load_functions();
+    if let Err(e) = set_session_log(&self.config.session_id) {
+      log::warn!("failed to open the session log: {}", e);
+    }
     self.data.messages.iter_mut().for_each(|m| {
       m.stylize_complete = false;
     });
+    if self.data.has_pending() && self.action_tx.is_some() {
+      self.watch_connectivity();
+    }
     Ok(())
   }
   pub fn load_session_by_id(&mut self, session_id: String) -> Result<(), SazidError> {
     Self::get_session_filepath(session_id.clone());
-    let load_result = fs::read_to_string(Self::get_session_filepath(session_id.clone()));
+    let load_result = encryption::read_to_string(&Self::get_session_filepath(session_id.clone()));
     match load_result {
       Ok(load_session) => self.load_session(load_session),
       Err(e) => Err(SazidError::Other(format!("Failed to load session data: {:?}", e))),
     }
   }
   pub fn load_last_session(&mut self) -> Result<(), SazidError> {
-    let home_dir = home_dir().unwrap();
-    let save_dir = home_dir.join(SESSIONS_DIR);
+    let save_dir = data_path(SESSIONS_DIR).unwrap();
     let session_files = list_files_ordered_by_date(save_dir).unwrap();
     let last_session_file = session_files.iter().last().unwrap();
     if last_session_file.path().is_file() {
@@ -704,21 +2865,23 @@ impl Session<'static> {
   fn load_session_by_path(&mut self, session_file_path: String) -> Result<(), SazidError> {
     trace_dbg!("loading session from {}", session_file_path);
 
-    let load_result = fs::read_to_string(session_file_path);
+    let load_result = encryption::read_to_string(Path::new(&session_file_path));
     match load_result {
       Ok(load_session) => self.load_session(load_session),
       Err(e) => Err(SazidError::Other(format!("Failed to load session data: {:?}", e))),
     }
   }
   fn save_session(&self) -> io::Result<()> {
-    let home_dir = home_dir().unwrap();
-    let save_dir = home_dir.join(SESSIONS_DIR);
+    let save_dir = data_path(SESSIONS_DIR).unwrap();
     if !save_dir.exists() {
       fs::create_dir_all(save_dir.clone())?;
     }
     let session_file_path = save_dir.join(Self::get_session_filename(self.config.session_id.clone()));
     let data = serde_json::to_string(&self)?;
-    fs::write(session_file_path.clone(), data)?;
+    // written to a temporary file first so that a crash while saving can't leave a truncated session
+    let tmp_path = session_file_path.with_extension("json.tmp");
+    encryption::write(&tmp_path, data)?;
+    fs::rename(tmp_path, &session_file_path)?;
     trace_dbg!("session saved to {}", &session_file_path.clone().display());
     Ok(())
   }
@@ -755,10 +2918,14 @@ impl Session<'static> {
 }
 
 pub fn create_openai_client(openai_config: &OpenAIConfig) -> async_openai::Client<OpenAIConfig> {
-  let backoff = ExponentialBackoffBuilder::new() // Ensure backoff crate is added to Cargo.toml
-    .with_max_elapsed_time(Some(std::time::Duration::from_secs(60)))
-    .build();
-  Client::with_config(openai_config.clone()).with_backoff(backoff)
+  let client = Client::with_config(openai_config.clone()).with_backoff(RetryPolicy::default().backoff());
+  match is_openrouter(openai_config) {
+    true => match reqwest::Client::builder().default_headers(openrouter_headers()).build() {
+      Ok(http_client) => client.with_http_client(http_client),
+      Err(_) => client,
+    },
+    false => client,
+  }
 }
 
 pub async fn create_embedding_request(
@@ -773,3 +2940,27 @@ pub async fn create_embedding_request(
 
   Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::database::DatabaseConfig;
+
+  #[test]
+  fn test_load_session_keeps_databases() {
+    let database = DatabaseConfig { url: "sqlite://shop.db".to_string(), max_rows: 50 };
+    let mut saved = Session::new();
+    saved.config.databases.insert("shop".to_string(), database.clone());
+    let session_json = serde_json::to_string(&saved).unwrap();
+    assert!(!session_json.contains("sqlite://shop.db"));
+
+    let mut session = Session::new();
+    session.config.databases.insert("shop".to_string(), database);
+    session.config.mock_fixtures = Some(PathBuf::from("fixtures.json5"));
+    session.load_session(session_json).unwrap();
+    assert_eq!(session.config.databases.len(), 1);
+    assert_eq!(session.config.mock_fixtures, Some(PathBuf::from("fixtures.json5")));
+    let names = session.config.available_functions.iter().map(|f| FunctionCall::from(f).name).collect::<Vec<_>>();
+    assert!(names.contains(&"query_database".to_string()));
+  }
+}
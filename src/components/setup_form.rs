@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{prelude::*, widgets::*};
+
+use crate::app::{
+  consts::{GPT4_TURBO, WIZARDLM},
+  i18n::tr,
+  providers::{Provider, OPENROUTER_AUTO_MODEL},
+  setup::SetupAnswers,
+  theme,
+};
+
+const PROVIDERS: [Provider; 3] = [Provider::OpenAI, Provider::OpenRouter, Provider::Local];
+const PROVIDER_LABELS: [&str; 3] = ["openai", "openrouter", "local, an openai compatible server on localhost:1234"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetupField {
+  Provider,
+  ApiKey,
+  Model,
+  DataDir,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetupOutcome {
+  Submitted,
+  Cancelled,
+}
+
+// the first run form, the provider, its api key, the default model and the data directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupForm {
+  pub provider: usize,
+  pub api_key: String,
+  // whether each provider already has a key in the keyring or the environment, an empty key keeps it
+  pub has_key: Vec<bool>,
+  pub model: String,
+  // the model follows the provider until it is edited
+  model_edited: bool,
+  pub data_dir: String,
+  pub default_data_dir: PathBuf,
+  pub focus: SetupField,
+  pub error: Option<String>,
+}
+
+fn default_model(provider: Provider) -> String {
+  match provider {
+    Provider::OpenRouter => OPENROUTER_AUTO_MODEL.to_string(),
+    Provider::Local => WIZARDLM.name.clone(),
+    _ => GPT4_TURBO.name.clone(),
+  }
+}
+
+impl SetupForm {
+  pub fn new(default_data_dir: PathBuf, has_key: impl Fn(Provider) -> bool) -> Self {
+    SetupForm {
+      provider: 0,
+      api_key: String::new(),
+      has_key: PROVIDERS.iter().map(|provider| has_key(*provider)).collect(),
+      model: default_model(PROVIDERS[0]),
+      model_edited: false,
+      data_dir: default_data_dir.display().to_string(),
+      default_data_dir,
+      focus: SetupField::Provider,
+      error: None,
+    }
+  }
+
+  pub fn provider(&self) -> Provider {
+    PROVIDERS[self.provider]
+  }
+
+  // the api key field is skipped for providers that don't need one
+  fn fields(&self) -> Vec<SetupField> {
+    match self.provider().api_key_env() {
+      Some(_) => vec![SetupField::Provider, SetupField::ApiKey, SetupField::Model, SetupField::DataDir],
+      None => vec![SetupField::Provider, SetupField::Model, SetupField::DataDir],
+    }
+  }
+
+  fn move_focus(&mut self, forward: bool) {
+    let fields = self.fields();
+    let position = fields.iter().position(|field| *field == self.focus).unwrap_or(0);
+    self.focus = match forward {
+      true => fields[(position + 1).min(fields.len() - 1)],
+      false => fields[position.saturating_sub(1)],
+    };
+  }
+
+  fn select_provider(&mut self, provider: usize) {
+    self.provider = provider.min(PROVIDERS.len() - 1);
+    self.api_key.clear();
+    if !self.model_edited {
+      self.model = default_model(self.provider());
+    }
+  }
+
+  fn field_text(&mut self) -> Option<&mut String> {
+    match self.focus {
+      SetupField::Provider => None,
+      SetupField::ApiKey => Some(&mut self.api_key),
+      SetupField::Model => {
+        self.model_edited = true;
+        Some(&mut self.model)
+      },
+      SetupField::DataDir => Some(&mut self.data_dir),
+    }
+  }
+
+  pub fn paste(&mut self, text: &str) {
+    if let Some(field) = self.field_text() {
+      field.push_str(text.trim_end_matches(['\r', '\n']));
+    }
+  }
+
+  // why the answers can't be written yet, with the field to fix
+  fn validate(&self) -> Result<(), (SetupField, String)> {
+    if self.provider().api_key_env().is_some() && self.api_key.trim().is_empty() && !self.has_key[self.provider] {
+      return Err((SetupField::ApiKey, tr("enter the API key")));
+    }
+    if self.model.trim().is_empty() {
+      return Err((SetupField::Model, tr("enter a model")));
+    }
+    if self.data_dir.trim().is_empty() {
+      return Err((SetupField::DataDir, tr("enter a data directory")));
+    }
+    Ok(())
+  }
+
+  pub fn handle_key(&mut self, key: KeyEvent) -> Option<SetupOutcome> {
+    self.error = None;
+    match key.code {
+      KeyCode::Esc => return Some(SetupOutcome::Cancelled),
+      KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(SetupOutcome::Cancelled),
+      KeyCode::Tab | KeyCode::Down => self.move_focus(true),
+      KeyCode::BackTab | KeyCode::Up => self.move_focus(false),
+      KeyCode::Left | KeyCode::Char('h') if self.focus == SetupField::Provider => {
+        self.select_provider(self.provider.saturating_sub(1))
+      },
+      KeyCode::Right | KeyCode::Char('l') if self.focus == SetupField::Provider => {
+        self.select_provider(self.provider + 1)
+      },
+      KeyCode::Enter if self.focus == SetupField::DataDir => match self.validate() {
+        Ok(()) => return Some(SetupOutcome::Submitted),
+        Err((field, error)) => {
+          self.focus = field;
+          self.error = Some(error);
+        },
+      },
+      KeyCode::Enter => self.move_focus(true),
+      KeyCode::Backspace => {
+        if let Some(field) = self.field_text() {
+          field.pop();
+        }
+      },
+      KeyCode::Char(c) => {
+        if let Some(field) = self.field_text() {
+          field.push(c);
+        }
+      },
+      _ => {},
+    }
+    None
+  }
+
+  // the answers, and the api key to store when one was entered
+  pub fn answers(&self) -> (SetupAnswers, Option<String>) {
+    let data_dir = Some(PathBuf::from(self.data_dir.trim())).filter(|data_dir| *data_dir != self.default_data_dir);
+    let api_key = Some(self.api_key.trim().to_string()).filter(|api_key| !api_key.is_empty());
+    (SetupAnswers { provider: self.provider(), model: self.model.trim().to_string(), data_dir }, api_key)
+  }
+
+  pub fn draw(&self, f: &mut Frame<'_>, area: Rect) {
+    let theme = theme::current();
+    let width = area.width.saturating_sub(4).min(80);
+    let popup = Rect::new(area.x + (area.width - width) / 2, area.y + 1, width, area.height.saturating_sub(2).min(16));
+    let block =
+      Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).border_style(theme.border()).title(
+        Line::from(vec![
+          Span::raw(tr("Set up sazid ")),
+          Span::styled("(", theme.hint()),
+          Span::styled("Tab", theme.hint_key()),
+          Span::styled(tr(" to move, "), theme.hint()),
+          Span::styled("h/l", theme.hint_key()),
+          Span::styled(tr(" to pick the provider, "), theme.hint()),
+          Span::styled("Enter", theme.hint_key()),
+          Span::styled(tr(" on the last field to save, "), theme.hint()),
+          Span::styled("ESC", theme.hint_key()),
+          Span::styled(tr(" to cancel)"), theme.hint()),
+        ]),
+      );
+    f.render_widget(Clear, popup);
+    let inner = block.inner(popup);
+    f.render_widget(block, popup);
+
+    let key_label = match self.has_key.get(self.provider).copied().unwrap_or(false) {
+      true => tr("API key, empty to keep the current one"),
+      false => tr("API key"),
+    };
+    let fields = self.fields();
+    let mut lines = vec![];
+    for field in fields {
+      let (label, value) = match field {
+        SetupField::Provider => (tr("Provider"), format!("< {} >", PROVIDER_LABELS[self.provider])),
+        SetupField::ApiKey => (key_label.clone(), "*".repeat(self.api_key.chars().count())),
+        SetupField::Model => (tr("Default model"), self.model.clone()),
+        SetupField::DataDir => (tr("Data directory"), self.data_dir.clone()),
+      };
+      let style = match field == self.focus {
+        true => theme.border_focused(),
+        false => theme.hint(),
+      };
+      lines.push(Line::from(Span::styled(label, style)));
+      let cursor = if field == self.focus && field != SetupField::Provider { "_" } else { "" };
+      lines.push(Line::from(format!("  {}{}", value, cursor)));
+      lines.push(Line::from(""));
+    }
+    if let Some(error) = &self.error {
+      lines.push(Line::from(Span::styled(error.clone(), theme.status_message())));
+    }
+    f.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), inner);
+  }
+}
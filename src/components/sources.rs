@@ -0,0 +1,322 @@
+use chrono::NaiveDateTime;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+  action::Action,
+  app::{
+    embeddings::{
+      configured_embedding_model,
+      embeddings_models::EmbeddingModel,
+      types::{EmbeddingStatus, IngestedSource, SourceStatus},
+      EmbeddingsManager,
+    },
+    errors::SazidError,
+    i18n::tr,
+  },
+  config::Config,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceOperation {
+  List,
+  Reingest(IngestedSource),
+  Delete(IngestedSource),
+  SetCollection(IngestedSource, String),
+  Preview(IngestedSource),
+}
+
+// the stored chunks of a source, one at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkPreview {
+  pub source_id: i64,
+  pub chunks: Vec<String>,
+  pub index: usize,
+  pub scroll: u16,
+}
+
+// an overlay listing everything ingested into the embeddings database
+#[derive(Debug, Default)]
+pub struct SourceManager {
+  pub sources: Vec<(IngestedSource, SourceStatus, EmbeddingStatus)>,
+  pub state: TableState,
+  // the collection being typed after pressing c
+  pub collection_input: Option<String>,
+  // delete is only performed when D is pressed twice on the same source
+  pub confirm_delete: Option<i64>,
+  pub preview: Option<ChunkPreview>,
+}
+
+impl SourceManager {
+  pub fn new(sources: Vec<IngestedSource>, config: &Config) -> Self {
+    let mut state = TableState::default();
+    state.select(if sources.is_empty() { None } else { Some(0) });
+    let default_model = EmbeddingModel::Ada002(config.session_config.openai_config.clone());
+    let sources = sources
+      .into_iter()
+      .map(|source| {
+        let status = source.status();
+        let model = configured_embedding_model(config, source.collection(), default_model.clone());
+        let embedding_status = source.embedding_status(&model.model_string());
+        (source, status, embedding_status)
+      })
+      .collect();
+    SourceManager { sources, state, collection_input: None, confirm_delete: None, preview: None }
+  }
+
+  // keeps the selection in place when the list is refreshed after an operation
+  pub fn replace_sources(&mut self, sources: Vec<IngestedSource>, config: &Config) {
+    let selected = self.state.selected().unwrap_or(0);
+    *self = SourceManager::new(sources, config);
+    if !self.sources.is_empty() {
+      self.state.select(Some(selected.min(self.sources.len() - 1)));
+    }
+  }
+
+  pub fn select_next(&mut self) {
+    if let Some(i) = self.state.selected() {
+      self.state.select(Some((i + 1).min(self.sources.len().saturating_sub(1))));
+    }
+  }
+
+  pub fn select_previous(&mut self) {
+    if let Some(i) = self.state.selected() {
+      self.state.select(Some(i.saturating_sub(1)));
+    }
+  }
+
+  pub fn selected(&self) -> Option<&IngestedSource> {
+    self.state.selected().and_then(|i| self.sources.get(i)).map(|(source, _, _)| source)
+  }
+
+  // whether esc should close the manager rather than cancel editing a collection or close a preview
+  pub fn is_editing(&self) -> bool {
+    self.collection_input.is_some() || self.preview.is_some()
+  }
+
+  pub fn show_chunks(&mut self, source_id: i64, chunks: Vec<String>) {
+    self.preview = Some(ChunkPreview { source_id, chunks, index: 0, scroll: 0 });
+  }
+
+  pub fn handle_key_event(&mut self, key: KeyEvent) -> Option<SourceOperation> {
+    if let Some(preview) = self.preview.as_mut() {
+      match key.code {
+        KeyCode::Right | KeyCode::Char('l') | KeyCode::Char('n') => {
+          preview.index = (preview.index + 1).min(preview.chunks.len().saturating_sub(1));
+          preview.scroll = 0;
+        },
+        KeyCode::Left | KeyCode::Char('h') | KeyCode::Char('p') => {
+          preview.index = preview.index.saturating_sub(1);
+          preview.scroll = 0;
+        },
+        KeyCode::Down | KeyCode::Char('j') => preview.scroll = preview.scroll.saturating_add(1),
+        KeyCode::Up | KeyCode::Char('k') => preview.scroll = preview.scroll.saturating_sub(1),
+        KeyCode::Esc | KeyCode::Char('q') => self.preview = None,
+        _ => {},
+      }
+      return None;
+    }
+    if let Some(collection) = self.collection_input.as_mut() {
+      match key.code {
+        KeyCode::Enter => {
+          let collection = collection.trim().to_string();
+          self.collection_input = None;
+          return self.selected().map(|s| SourceOperation::SetCollection(s.clone(), collection));
+        },
+        KeyCode::Esc => self.collection_input = None,
+        KeyCode::Backspace => {
+          collection.pop();
+        },
+        KeyCode::Char(c) => collection.push(c),
+        _ => {},
+      }
+      return None;
+    }
+    let confirm_delete = self.confirm_delete.take();
+    let selected = self.selected().cloned();
+    match key.code {
+      KeyCode::Down | KeyCode::Char('j') => {
+        self.select_next();
+        None
+      },
+      KeyCode::Up | KeyCode::Char('k') => {
+        self.select_previous();
+        None
+      },
+      KeyCode::Char('g') => Some(SourceOperation::List),
+      KeyCode::Enter | KeyCode::Char('p') => selected.map(SourceOperation::Preview),
+      KeyCode::Char('r') => match selected {
+        Some(source) if matches!(source.status(), SourceStatus::Missing | SourceStatus::Remote) => None,
+        Some(source) => Some(SourceOperation::Reingest(source)),
+        None => None,
+      },
+      KeyCode::Char('D') => match selected {
+        Some(source) if confirm_delete == Some(source.id) => Some(SourceOperation::Delete(source)),
+        Some(source) => {
+          self.confirm_delete = Some(source.id);
+          None
+        },
+        None => None,
+      },
+      KeyCode::Char('c') => {
+        self.collection_input = selected.map(|s| s.collections);
+        None
+      },
+      _ => None,
+    }
+  }
+
+  pub fn draw(&mut self, f: &mut Frame<'_>, area: Rect) {
+    let rows: Vec<Row> = self
+      .sources
+      .iter()
+      .map(|(source, status, embedding_status)| {
+        let updated_at = NaiveDateTime::from_timestamp_opt(source.updated_at, 0)
+          .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+          .unwrap_or_default();
+        let style = match status {
+          SourceStatus::Current | SourceStatus::Remote => Style::default(),
+          SourceStatus::Modified => Style::default().fg(Color::Yellow),
+          SourceStatus::Missing => Style::default().fg(Color::Red),
+        };
+        let marker = if self.confirm_delete == Some(source.id) { "delete? " } else { "" };
+        let version = match (source.version, source.superseded) {
+          (version, true) => format!(" (v{}, superseded)", version),
+          (1, false) => String::new(),
+          (version, false) => format!(" (v{})", version),
+        };
+        let embedding_style = match embedding_status {
+          EmbeddingStatus::Outdated(_) => Style::default().fg(Color::Yellow),
+          EmbeddingStatus::Current | EmbeddingStatus::Unrecorded => Style::default(),
+        };
+        let embedding = match (embedding_status, source.embedding_model.as_str()) {
+          (EmbeddingStatus::Unrecorded, _) => embedding_status.to_string(),
+          (_, model) => format!("{} ({})", model, embedding_status),
+        };
+        Row::new(vec![
+          Cell::from(format!("{}{}{}", marker, source.filepath, version)),
+          Cell::from(source.chunk_count.to_string()),
+          Cell::from(updated_at),
+          Cell::from(status.to_string()),
+          Cell::from(embedding).style(embedding_style),
+          Cell::from(source.collections.clone()),
+        ])
+        .style(style)
+      })
+      .collect();
+    let popup_width = area.width.saturating_sub(4).min(140);
+    let popup_height = (rows.len() as u16 + 4).max(6).min(area.height.saturating_sub(2));
+    let popup = Rect::new(
+      area.x + (area.width.saturating_sub(popup_width)) / 2,
+      area.y + (area.height.saturating_sub(popup_height)) / 2,
+      popup_width,
+      popup_height,
+    );
+    let title = match &self.collection_input {
+      Some(collection) => Line::from(vec![
+        Span::raw(tr("Collection: ")),
+        Span::styled(collection.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        Span::styled(tr(" (<enter> to save, ESC to cancel)"), Style::default().fg(Color::DarkGray)),
+      ]),
+      None => Line::from(vec![
+        Span::raw(tr("Ingested Sources ")),
+        Span::styled("(", Style::default().fg(Color::DarkGray)),
+        Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" preview, "), Style::default().fg(Color::DarkGray)),
+        Span::styled("r", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" re-ingest, "), Style::default().fg(Color::DarkGray)),
+        Span::styled("D", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" delete, "), Style::default().fg(Color::DarkGray)),
+        Span::styled("c", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" collection, "), Style::default().fg(Color::DarkGray)),
+        Span::styled("g", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" refresh, "), Style::default().fg(Color::DarkGray)),
+        Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+        Span::styled(tr(" to close)"), Style::default().fg(Color::DarkGray)),
+      ]),
+    };
+    let widths = [
+      Constraint::Percentage(35),
+      Constraint::Length(7),
+      Constraint::Length(17),
+      Constraint::Length(8),
+      Constraint::Percentage(25),
+      Constraint::Percentage(15),
+    ];
+    let table = Table::new(rows)
+      .header(
+        Row::new(vec!["source", "chunks", "updated", "status", "embedding", "collections"])
+          .style(Style::default().add_modifier(Modifier::BOLD)),
+      )
+      .widths(&widths)
+      .block(Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).title(title))
+      .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+      .highlight_symbol("> ");
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(table, popup, &mut self.state);
+    if let Some(preview) = &self.preview {
+      self.draw_preview(f, area, preview);
+    }
+  }
+
+  fn draw_preview(&self, f: &mut Frame<'_>, area: Rect, preview: &ChunkPreview) {
+    let filepath = self.sources.iter().find(|(s, _, _)| s.id == preview.source_id).map(|(s, _, _)| s.filepath.as_str());
+    let popup = Rect::new(area.x + 4, area.y + 2, area.width.saturating_sub(8), area.height.saturating_sub(4));
+    let title = Line::from(vec![
+      Span::raw(format!("{} ", filepath.unwrap_or_default())),
+      Span::styled(
+        format!("{} {}/{} ", tr("chunk"), (preview.index + 1).min(preview.chunks.len()), preview.chunks.len()),
+        Style::default().add_modifier(Modifier::BOLD),
+      ),
+      Span::styled("(", Style::default().fg(Color::DarkGray)),
+      Span::styled("h/l", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" previous/next chunk, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("j/k", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" scroll, "), Style::default().fg(Color::DarkGray)),
+      Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+      Span::styled(tr(" to close)"), Style::default().fg(Color::DarkGray)),
+    ]);
+    let text = preview.chunks.get(preview.index).map(String::as_str).unwrap_or_default();
+    let paragraph = Paragraph::new(text)
+      .block(Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).title(title))
+      .wrap(Wrap { trim: false })
+      .scroll((preview.scroll, 0));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+  }
+}
+
+async fn perform_operation(config: Config, operation: SourceOperation) -> Result<Action, SazidError> {
+  let model = EmbeddingModel::Ada002(config.session_config.openai_config.clone());
+  let mut manager = EmbeddingsManager::init(config, model).await?;
+  match operation {
+    SourceOperation::List => {},
+    SourceOperation::Reingest(source) => {
+      manager.reingest_source(&source).await?;
+    },
+    SourceOperation::Delete(source) => manager.delete_source(source.id).await?,
+    SourceOperation::SetCollection(source, collection) => manager.set_source_collection(source.id, &collection).await?,
+    SourceOperation::Preview(source) => {
+      return Ok(Action::SourceChunks(source.id, manager.source_chunks(source.id).await?))
+    },
+  }
+  Ok(Action::IngestedSources(manager.list_ingested_sources().await?))
+}
+
+// runs the operation in the background, then sends the refreshed source list or the chunks to preview
+pub fn spawn_source_operation(config: Config, tx: UnboundedSender<Action>, operation: SourceOperation) {
+  tokio::spawn(async move {
+    let description = match &operation {
+      SourceOperation::List => "list sources".to_string(),
+      SourceOperation::Reingest(source) => format!("re-ingest {}", source.filepath),
+      SourceOperation::Delete(source) => format!("delete {}", source.filepath),
+      SourceOperation::SetCollection(source, _) => format!("change collection of {}", source.filepath),
+      SourceOperation::Preview(source) => format!("preview {}", source.filepath),
+    };
+    match perform_operation(config, operation).await {
+      Ok(action) => tx.send(action).unwrap(),
+      Err(e) => tx.send(Action::Error(format!("Failed to {}: {}", description, e))).unwrap(),
+    }
+  });
+}
@@ -0,0 +1,47 @@
+use ratatui::{prelude::*, widgets::*};
+
+use crate::app::{i18n::tr, session_stats::SessionStats};
+
+// an overlay with the totals for the session and the tokens of each request over time
+pub fn draw_stats(f: &mut Frame<'_>, area: Rect, stats: &SessionStats) {
+  let text = stats.to_string();
+  let popup_width = area.width.saturating_sub(4).min(100);
+  let popup_height = (text.lines().count() as u16 + 9).min(area.height.saturating_sub(2));
+  let popup = Rect::new(
+    area.x + (area.width.saturating_sub(popup_width)) / 2,
+    area.y + (area.height.saturating_sub(popup_height)) / 2,
+    popup_width,
+    popup_height,
+  );
+  let block = Block::default().borders(Borders::ALL).border_type(BorderType::Rounded).title(Line::from(vec![
+    Span::raw(tr("Session Stats ")),
+    Span::styled("(", Style::default().fg(Color::DarkGray)),
+    Span::styled("ESC", Style::default().add_modifier(Modifier::BOLD).fg(Color::Gray)),
+    Span::styled(tr(" to close)"), Style::default().fg(Color::DarkGray)),
+  ]));
+  let inner = block.inner(popup);
+  let rects = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Min(1), Constraint::Length(7)].as_ref())
+    .split(inner);
+  let lines = text
+    .lines()
+    .map(|line| match line.split_once(": ") {
+      Some((name, value)) => Line::from(vec![
+        Span::styled(format!("{}: ", name), Style::default().fg(Color::Cyan)),
+        Span::raw(value.to_string()),
+      ]),
+      None => Line::from(line.to_string()),
+    })
+    .collect::<Vec<Line>>();
+  // the most recent requests that fit in the width
+  let history = &stats.token_history[stats.token_history.len().saturating_sub(rects[1].width as usize)..];
+  let sparkline = Sparkline::default()
+    .block(Block::default().borders(Borders::TOP).title(tr("tokens per request")))
+    .data(history)
+    .style(Style::default().fg(Color::Yellow));
+  f.render_widget(Clear, popup);
+  f.render_widget(block, popup);
+  f.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), rects[0]);
+  f.render_widget(sparkline, rects[1]);
+}
@@ -9,11 +9,38 @@ use serde::{de::Deserializer, Deserialize};
 
 use crate::{
   action::Action,
-  app::{session_config::SessionConfig, Mode},
+  app::{
+    credentials::{load_api_key, missing_api_key_message},
+    database::DatabaseConfig,
+    embeddings::{
+      embeddings_models::EmbeddingModelSettings, hybrid::HybridSearchConfig, index::VectorSearchConfig,
+      ingest::DuplicateDetection, pipeline::EmbeddingPipelineConfig, store::VectorStoreKind, versions::ReingestPolicy,
+    },
+    encryption::EncryptionConfig,
+    functions::sandbox::SandboxPolicy,
+    guardrails::ConfirmThresholds,
+    mock_provider::MOCK_FIXTURES_ENV,
+    model_list::ModelPricing,
+    notifications::NotificationConfig,
+    personas::Persona,
+    profiles::Profile,
+    project::{find_project_config, read_project_config, PROJECT_CONFIG_FILE},
+    providers::Provider,
+    redaction::RedactionConfig,
+    response_cache::ResponseCacheConfig,
+    server::ServeConfig,
+    retention::RetentionPolicy,
+    retry::{RequestTimeouts, RetryPolicy},
+    rpc::RpcConfig,
+    session_config::{RequestParameters, SessionConfig},
+    tee::TeeConfig,
+    types::Model,
+    Mode,
+  },
   trace_dbg,
 };
 
-const CONFIG: &str = include_str!("../.config/config.json5");
+pub const CONFIG: &str = include_str!("../.config/config.json5");
 
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct AppConfig {
@@ -37,6 +64,113 @@ pub struct Config {
   pub list_file_paths: Vec<PathBuf>,
   #[serde(default)]
   pub session_dir: PathBuf,
+  #[serde(default)]
+  pub middleware: Option<Vec<String>>,
+  #[serde(default)]
+  pub requests_per_minute: u32,
+  #[serde(default)]
+  pub request_parameters: RequestParameters,
+  #[serde(default)]
+  pub response_max_tokens: Option<usize>,
+  #[serde(default)]
+  pub auto_continue: Option<usize>,
+  #[serde(default)]
+  pub agent_max_iterations: Option<usize>,
+  #[serde(default)]
+  pub attachment_max_tokens: Option<usize>,
+  #[serde(default)]
+  pub function_result_max_tokens: Option<usize>,
+  #[serde(default)]
+  pub personas: HashMap<String, Persona>,
+  #[serde(default)]
+  pub profiles: HashMap<String, Profile>,
+  // the profile applied at startup when --profile gives none
+  #[serde(default)]
+  pub profile: Option<String>,
+  #[serde(default)]
+  pub model_pricing: HashMap<String, ModelPricing>,
+  #[serde(default)]
+  pub provider: Provider,
+  #[serde(default)]
+  pub model: Option<String>,
+  #[serde(default)]
+  pub data_dir: Option<PathBuf>,
+  #[serde(default)]
+  pub theme: Option<String>,
+  // the interface's language, "auto" or None for the environment's
+  #[serde(default)]
+  pub language: Option<String>,
+  #[serde(default)]
+  pub response_language: Option<String>,
+  #[serde(default)]
+  pub rpc: Option<RpcConfig>,
+  #[serde(default)]
+  pub confirm_thresholds: Option<ConfirmThresholds>,
+  #[serde(default)]
+  pub sandbox: Option<SandboxPolicy>,
+  #[serde(default)]
+  pub plugins_dir: Option<PathBuf>,
+  #[serde(default)]
+  pub hooks_dir: Option<PathBuf>,
+  #[serde(default)]
+  pub retry_policy: Option<RetryPolicy>,
+  #[serde(default)]
+  pub timeouts: Option<RequestTimeouts>,
+  #[serde(default)]
+  pub response_cache: Option<ResponseCacheConfig>,
+  #[serde(default)]
+  pub encryption: Option<EncryptionConfig>,
+  #[serde(default)]
+  pub retention: Option<RetentionPolicy>,
+  #[serde(default)]
+  pub redaction: Option<RedactionConfig>,
+  #[serde(default)]
+  pub tee: Option<TeeConfig>,
+  #[serde(default)]
+  pub notifications: Option<NotificationConfig>,
+  #[serde(default)]
+  pub auto_context: Vec<PathBuf>,
+  #[serde(default)]
+  pub ingest_concurrency: Option<usize>,
+  #[serde(default)]
+  pub offline: bool,
+  #[serde(default)]
+  pub reingest_policy: Option<ReingestPolicy>,
+  #[serde(default)]
+  pub duplicate_detection: Option<DuplicateDetection>,
+  #[serde(default)]
+  pub vector_search: Option<VectorSearchConfig>,
+  #[serde(default)]
+  pub vector_store: VectorStoreKind,
+  #[serde(default)]
+  pub hybrid_search: Option<HybridSearchConfig>,
+  #[serde(default)]
+  pub embedding_pipeline: Option<EmbeddingPipelineConfig>,
+  #[serde(default)]
+  pub embedding_model: Option<EmbeddingModelSettings>,
+  #[serde(default)]
+  pub collection_embedding_models: HashMap<String, EmbeddingModelSettings>,
+  // the system prompt of new sessions, None for the built in one
+  #[serde(default)]
+  pub prompt: Option<String>,
+  // the only functions offered to the model, None for every function
+  #[serde(default)]
+  pub tools: Option<Vec<String>>,
+  // the only files ingested from a directory, relative to it, empty for every file
+  #[serde(default)]
+  pub ingest_globs: Vec<String>,
+  // the collection ingested into and searched when none is given
+  #[serde(default)]
+  pub collection: Option<String>,
+  // the databases query_database can read and --ingest-database can index, by name
+  #[serde(default)]
+  pub databases: HashMap<String, DatabaseConfig>,
+  // the certificate and key `sazid serve` uses for https
+  #[serde(default)]
+  pub serve: ServeConfig,
+  // the .sazid.toml layered over the global config, found in the working directory or above it
+  #[serde(skip)]
+  pub project_config: Option<PathBuf>,
 }
 
 impl Config {
@@ -63,28 +197,119 @@ impl Config {
       }
     }
     if !found_config {
-      log::error!("No configuration file found. Application may not behave as expected");
+      log::error!("No configuration file found, run `sazid setup` to write one");
+    }
+    let project_config = env::current_dir().ok().and_then(|dir| find_project_config(&dir));
+    if let Some(path) = &project_config {
+      log::info!("loading the {} of {}", PROJECT_CONFIG_FILE, path.display());
+      let (settings, ignored) = read_project_config(path).map_err(|e| config::ConfigError::Message(e.to_string()))?;
+      if !ignored.is_empty() {
+        log::warn!("{} can't set {}, they are ignored", path.display(), ignored.join(", "));
+      }
+      let settings = toml::to_string(&settings).map_err(|e| config::ConfigError::Message(e.to_string()))?;
+      builder = builder.add_source(config::File::from_str(&settings, config::FileFormat::Toml));
     }
 
     let mut cfg: Self = builder.build()?.try_deserialize()?;
+    cfg.project_config = project_config;
+    if let Ok(provider) = env::var("SAZID_PROVIDER") {
+      cfg.provider = serde_json::from_value(serde_json::Value::String(provider.to_lowercase()))
+        .map_err(|_| config::ConfigError::Message(format!("unknown provider in SAZID_PROVIDER: {}", provider)))?;
+    }
 
-    cfg.session_config = match local_api {
-      true => SessionConfig::default().with_local_api(),
-      false => {
-        let api_key: String = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
+    cfg.session_config = match (local_api || cfg.offline, cfg.provider) {
+      (_, Provider::Mock) => {
+        SessionConfig::default().with_mock_provider(env::var_os(MOCK_FIXTURES_ENV).map(PathBuf::from))
+      },
+      (true, _) | (false, Provider::Local) => SessionConfig::default().with_local_api(),
+      (false, Provider::OpenRouter) => {
+        let api_key = load_api_key(Provider::OpenRouter)
+          .ok_or_else(|| config::ConfigError::Message(missing_api_key_message(Provider::OpenRouter)))?;
+        SessionConfig::default().with_openrouter_api_key(api_key)
+      },
+      (false, Provider::OpenAI) => {
+        let api_key = load_api_key(Provider::OpenAI)
+          .ok_or_else(|| config::ConfigError::Message(missing_api_key_message(Provider::OpenAI)))?;
 
         trace_dbg!("api_key: {:?}", api_key);
         SessionConfig::default().with_openai_api_key(api_key)
       },
     };
+    if let Some(model) = &cfg.model {
+      cfg.session_config.model = Model::from_name(model);
+    }
     cfg.session_config.list_file_paths = cfg.list_file_paths.clone();
     cfg.session_config.session_dir = cfg.session_dir.clone();
+    cfg.session_config.auto_context = cfg.auto_context.clone();
+    cfg.session_config.offline = cfg.offline;
+    if let Some(middleware) = &cfg.middleware {
+      cfg.session_config.middleware = middleware.clone();
+    }
+    cfg.session_config.requests_per_minute = cfg.requests_per_minute;
+    cfg.session_config.request_parameters = cfg.request_parameters.clone();
+    if let Some(confirm_thresholds) = &cfg.confirm_thresholds {
+      cfg.session_config.confirm_thresholds = confirm_thresholds.clone();
+    }
+    cfg.session_config.plugins_dir =
+      Some(cfg.plugins_dir.clone().unwrap_or_else(|| cfg.app_config._config_dir.join("plugins")));
+    cfg.session_config.hooks_dir =
+      Some(cfg.hooks_dir.clone().unwrap_or_else(|| cfg.app_config._config_dir.join("hooks")));
+    if let Some(sandbox) = &cfg.sandbox {
+      cfg.session_config.sandbox = sandbox.clone();
+    }
+    if let Some(retry_policy) = &cfg.retry_policy {
+      cfg.session_config.retry_policy = retry_policy.clone();
+    }
+    if let Some(timeouts) = &cfg.timeouts {
+      cfg.session_config.timeouts = timeouts.clone();
+    }
+    if let Some(response_cache) = &cfg.response_cache {
+      cfg.session_config.response_cache = response_cache.clone();
+    }
+    if let Some(redaction) = &cfg.redaction {
+      cfg.session_config.redaction = redaction.clone();
+    }
+    if let Some(tee) = &cfg.tee {
+      cfg.session_config.tee = tee.clone();
+    }
+    if let Some(notifications) = &cfg.notifications {
+      cfg.session_config.notifications = notifications.clone();
+    }
+    if let Some(response_max_tokens) = cfg.response_max_tokens {
+      cfg.session_config.response_max_tokens = response_max_tokens;
+    }
+    if let Some(agent_max_iterations) = cfg.agent_max_iterations {
+      cfg.session_config.agent_max_iterations = agent_max_iterations;
+    }
+    if let Some(attachment_max_tokens) = cfg.attachment_max_tokens {
+      cfg.session_config.attachment_max_tokens = attachment_max_tokens;
+    }
+    if let Some(function_result_max_tokens) = cfg.function_result_max_tokens {
+      cfg.session_config.function_result_max_tokens = function_result_max_tokens;
+    }
+    if let Some(prompt) = &cfg.prompt {
+      cfg.session_config.prompt = prompt.clone();
+    }
+    cfg.session_config.tools = cfg.tools.clone();
+    cfg.session_config.databases = cfg.databases.clone();
+    cfg.session_config.response_language = cfg.response_language.clone();
+    cfg.session_config.collection = cfg.collection.clone();
+    cfg.session_config.profiles = cfg.profiles.clone();
+    if !cfg.personas.is_empty() {
+      cfg.session_config.personas = cfg.personas.clone();
+    }
+    if let Some(auto_continue) = cfg.auto_continue {
+      cfg.session_config.auto_continue = auto_continue;
+    }
     for (mode, default_bindings) in default_config.keybindings.iter() {
       let user_bindings = cfg.keybindings.entry(*mode).or_default();
       for (key, cmd) in default_bindings.iter() {
         user_bindings.entry(key.clone()).or_insert_with(|| cmd.clone());
       }
     }
+    for (model, pricing) in default_config.model_pricing.iter() {
+      cfg.model_pricing.entry(model.clone()).or_insert_with(|| pricing.clone());
+    }
     for (mode, default_styles) in default_config.styles.iter() {
       let user_styles = cfg.styles.entry(*mode).or_default();
       for (style_key, style) in default_styles.iter() {
@@ -4,7 +4,7 @@
 
 extern crate lazy_static;
 
-use std::env;
+use std::time::Instant;
 
 use async_openai::config::OpenAIConfig;
 use clap::Parser;
@@ -12,14 +12,34 @@ use color_eyre::eyre::Result;
 
 use sazid::{
   app::{
-    embeddings::{embeddings_models::EmbeddingModel, EmbeddingsManager},
+    auth::run_auth,
+    batch::run_batch,
+    brief::run_brief,
+    consts::set_data_dir,
+    credentials::{load_api_key, missing_api_key_message},
+    embeddings::{collections::run_index, embeddings_models::EmbeddingModel, EmbeddingsManager},
+    encryption,
     errors::SazidError,
+    export::{run_export, share::run_share},
+    finetune::run_export_finetune,
+    model_list::fetch_model_listings,
+    notifications::{notify, Job, Notification},
+    providers::Provider,
+    retention::{apply_automatic_retention, run_gc},
+    server::{run_serve, ServeConfig},
+    session_diff::run_diff,
+    session_list::{run_archive, run_list_sessions},
+    setup::{run_setup, should_run_setup},
+    i18n::{self, detect_language, locales_dir, Catalog},
+    theme::{self, themes_dir, Theme},
+    vcr::VcrMode,
+    workflow::run_workflow,
     App,
   },
-  cli::Cli,
+  cli::{Cli, Command},
   config::Config,
   trace_dbg,
-  utils::{initialize_logging, initialize_panic_handler},
+  utils::{get_config_dir, initialize_logging, initialize_panic_handler},
 };
 
 async fn tokio_main() -> Result<(), SazidError> {
@@ -27,18 +47,160 @@ async fn tokio_main() -> Result<(), SazidError> {
   initialize_panic_handler().map_err(SazidError::PanicHandlerError)?;
   trace_dbg!("app start");
   let args = Cli::parse();
-  let config = Config::new(args.local_api).unwrap();
-  let api_key: String = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-  let openai_config = OpenAIConfig::new().with_api_key(api_key).with_org_id("org-WagBLu0vLgiuEL12dylmcPFj");
+  // works without a config or an api key, so it runs before either is needed
+  if let Some(Command::Auth { command }) = &args.command {
+    return run_auth(command);
+  }
+  let run_setup_command = matches!(args.command, Some(Command::Setup));
+  if run_setup_command || should_run_setup(&get_config_dir()) {
+    let config_path = run_setup(&get_config_dir()).await?;
+    println!("Wrote {}", config_path.display());
+    if run_setup_command {
+      return Ok(());
+    }
+  }
+  let mut config = Config::new(args.local_api || args.offline).map_err(|e| {
+    eprintln!("{} error: {}", env!("CARGO_PKG_NAME"), e);
+    SazidError::ConfigError(e)
+  })?;
+  if let Some(data_dir) = &config.data_dir {
+    set_data_dir(data_dir.clone());
+  }
+  if let Some(name) = &config.theme {
+    match Theme::load(name, &themes_dir()) {
+      Ok(theme) => theme::set_current(theme),
+      Err(e) => eprintln!("{} warning: {}, using the dark theme", env!("CARGO_PKG_NAME"), e),
+    }
+  }
+  // a language detected from the environment without a catalog quietly stays english
+  match config.language.as_deref().filter(|language| *language != "auto") {
+    Some(language) => match Catalog::load(language, &locales_dir()) {
+      Ok(catalog) => i18n::set_current(catalog),
+      Err(e) => eprintln!("{} warning: {}, using english", env!("CARGO_PKG_NAME"), e),
+    },
+    None => {
+      if let Some(catalog) = detect_language().and_then(|language| Catalog::load(&language, &locales_dir()).ok()) {
+        i18n::set_current(catalog);
+      }
+    },
+  }
+  encryption::init(config.encryption.as_ref())?;
+  // before any command runs, so that the commands that upload, such as share, are blocked too
+  if args.offline {
+    config.offline = true;
+    config.session_config.offline = true;
+  }
+  if let Some(Command::Export { format, output, session_id, all }) = &args.command {
+    return run_export(format, session_id.as_deref(), output.as_ref(), *all);
+  }
+  if let Some(Command::Diff { session_a, session_b }) = &args.command {
+    return run_diff(session_a, session_b);
+  }
+  if let Some(Command::Sessions { tag, archived }) = &args.command {
+    return run_list_sessions(tag.as_deref(), *archived);
+  }
+  if let Some(Command::Archive { session_ids, older_than, restore }) = &args.command {
+    return run_archive(session_ids, *older_than, *restore);
+  }
+  if let Some(Command::Gc { yes }) = &args.command {
+    return run_gc(config.retention.as_ref(), *yes);
+  }
+  if let Some(Command::Share { to, messages, public, session_id }) = &args.command {
+    let session_config = &config.session_config;
+    return run_share(to, session_id.as_deref(), messages.as_deref(), *public, session_config).await;
+  }
+  if let Some(Command::ExportFinetune { output }) = &args.command {
+    return run_export_finetune(output.as_ref());
+  }
+  if args.no_cache {
+    config.session_config.response_cache.enabled = false;
+  }
+  config.session_config.dry_run = args.dry_run;
+  if let Some(collection) = &args.collection {
+    config.session_config.collection = Some(collection.clone());
+  }
+  if args.notify {
+    config.session_config.notifications = config.session_config.notifications.clone().forced();
+  }
+  let (notifications, offline) = (config.session_config.notifications.clone(), config.offline);
+  if let Some(profile) = args.profile.as_ref().or(config.profile.as_ref()) {
+    config.session_config.apply_profile(profile).map_err(|e| {
+      eprintln!("{} error: {}", env!("CARGO_PKG_NAME"), e);
+      e
+    })?;
+  }
+  config.session_config.vcr = match (&args.record, &args.replay) {
+    (Some(cassette), _) => Some(VcrMode::Record(cassette.clone())),
+    (None, Some(cassette)) => Some(VcrMode::Replay(cassette.clone())),
+    (None, None) => None,
+  };
+  if let Some(Command::Brief { target, output }) = &args.command {
+    let path = run_brief(target, output.as_ref(), &config).await?;
+    println!("{}", path.display());
+    eprintln!("add the brief to auto_context in the config to include it in new sessions");
+    return Ok(());
+  }
+  if let Some(Command::Index { command }) = &args.command {
+    return run_index(command, &config).await;
+  }
+  if let Some(Command::Serve { addr, tls_cert, tls_key }) = &args.command {
+    let serve = ServeConfig {
+      tls_cert: tls_cert.clone().or(config.serve.tls_cert.clone()),
+      tls_key: tls_key.clone().or(config.serve.tls_key.clone()),
+    };
+    return run_serve(config, *addr, serve).await;
+  }
+  if let Some(Command::Run { workflow, vars }) = &args.command {
+    let started = Instant::now();
+    let result = run_workflow(config, workflow, vars).await;
+    let notification = Notification::new(Job::Workflow, started, &result, &format!("ran {}", workflow.display()));
+    notify(&notifications, notification, offline).await;
+    return result;
+  }
+  if args.batch || args.with.is_some() {
+    let started = Instant::now();
+    let result = run_batch(args, config).await;
+    notify(&notifications, Notification::new(Job::Batch, started, &result, "answered"), offline).await;
+    return result.map_err(|e| {
+      eprintln!("{} error: {}", env!("CARGO_PKG_NAME"), e);
+      e
+    });
+  }
+  if args.list_models {
+    let listings = fetch_model_listings(&config.session_config.openai_config, &config.model_pricing).await?;
+    listings.iter().for_each(|listing| println!("{}", listing));
+    return Ok(());
+  }
+  // the chat only needs an openai key when openai is the provider, the key is otherwise only used for embeddings
+  let api_key = load_api_key(Provider::OpenAI);
+  if api_key.is_none() && !config.offline && config.provider == Provider::OpenAI {
+    return Err(SazidError::Other(missing_api_key_message(Provider::OpenAI)));
+  }
+  let openai_config =
+    OpenAIConfig::new().with_api_key(api_key.clone().unwrap_or_default()).with_org_id("org-WagBLu0vLgiuEL12dylmcPFj");
   let mut embeddings_manager = EmbeddingsManager::init(config.clone(), EmbeddingModel::Ada002(openai_config)).await?;
+  // embedding is disabled in offline mode anyway
+  if api_key.is_none() && !config.offline && config.embedding_model.is_none() {
+    let reason = "there is no OpenAI API key, set OPENAI_API_KEY or an embedding_model in the config to embed";
+    eprintln!("{}: embeddings are disabled, {}", env!("CARGO_PKG_NAME"), reason);
+    log::warn!("embeddings are disabled, {}", reason);
+    embeddings_manager.disable_openai_embeddings(reason);
+  }
 
-  match embeddings_manager.run(args.clone()).await {
+  let started = Instant::now();
+  let result = embeddings_manager.run(args.clone()).await;
+  if args.add_text_file_embeddings.is_some() || args.ingest_git.is_some() || args.ingest_database.is_some() {
+    let detail = result.as_ref().ok().and_then(Option::as_ref).cloned().unwrap_or_default();
+    notify(&notifications, Notification::new(Job::Ingest, started, &result, &detail), offline).await;
+  }
+  match result {
     Ok(Some(output)) => {
       println!("{}", output);
       Ok(())
     },
     Ok(None) => {
       println!("No output");
+      apply_automatic_retention(config.retention.as_ref());
       let mut app = App::new(args.tick_rate, args.frame_rate, config).unwrap();
       app.run().await.unwrap();
       Ok(())
@@ -1,13 +1,14 @@
-use std::path::PathBuf;
+use std::{collections::VecDeque, fmt::Write as _, io::Write, path::PathBuf, sync::Mutex};
 
 use color_eyre::eyre::Result;
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
-use tracing::error;
+use tracing::{error, field::Field, Event, Level, Subscriber};
 
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{
-  self, fmt::format, prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, Layer,
+  self, fmt::format, layer::Context, prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, Layer,
 };
 
 lazy_static! {
@@ -20,8 +21,17 @@ lazy_static! {
     std::env::var(format!("{}_GIT_INFO", PROJECT_NAME.clone())).unwrap_or_else(|_| String::from("UNKNOWN"));
   pub static ref LOG_ENV: String = format!("{}_LOG_LEVEL", PROJECT_NAME.clone());
   pub static ref LOG_FILE: String = format!("{}.log", env!("CARGO_PKG_NAME"));
+  // where log lines are written, switched to the session's log file once a session starts
+  static ref LOG_WRITER: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+  static ref RECENT_LOGS: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
 }
 
+// rotated log files kept for each session
+const SESSION_LOG_FILES: usize = 5;
+
+// warnings and errors kept for the log viewer
+const RECENT_LOG_LIMIT: usize = 500;
+
 fn project_directory() -> Option<ProjectDirs> {
   ProjectDirs::from("com", "kdheepak", env!("CARGO_PKG_NAME"))
 }
@@ -98,11 +108,111 @@ pub fn get_config_dir() -> PathBuf {
   directory
 }
 
+// forwards to the current log file, so that the file can change after the subscriber is installed
+struct LogWriter;
+
+impl Write for LogWriter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    match LOG_WRITER.lock().unwrap().as_mut() {
+      Some(writer) => writer.write(buf),
+      None => Ok(buf.len()),
+    }
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    match LOG_WRITER.lock().unwrap().as_mut() {
+      Some(writer) => writer.flush(),
+      None => Ok(()),
+    }
+  }
+}
+
+pub fn get_log_dir() -> PathBuf {
+  get_data_dir().join("logs")
+}
+
+// logs the rest of the run to <session id>.log in the log directory, rotated daily
+pub fn set_session_log(session_id: &str) -> Result<()> {
+  let appender = RollingFileAppender::builder()
+    .rotation(Rotation::DAILY)
+    .filename_prefix(session_id)
+    .filename_suffix("log")
+    .max_log_files(SESSION_LOG_FILES)
+    .build(get_log_dir())?;
+  *LOG_WRITER.lock().unwrap() = Some(Box::new(appender));
+  Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+  pub level: Level,
+  pub target: String,
+  // the message followed by the other fields of the event
+  pub message: String,
+}
+
+// the most recent warnings and errors, oldest first
+pub fn recent_logs() -> Vec<LogEntry> {
+  RECENT_LOGS.lock().unwrap().iter().cloned().collect()
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+  message: String,
+  fields: String,
+}
+
+impl tracing::field::Visit for MessageVisitor {
+  fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+    match field.name() {
+      "message" => self.message = format!("{:?}", value),
+      name => {
+        let _ = write!(self.fields, " {}={:?}", name, value);
+      },
+    }
+  }
+
+  fn record_str(&mut self, field: &Field, value: &str) {
+    match field.name() {
+      "message" => self.message = value.to_string(),
+      name => {
+        let _ = write!(self.fields, " {}={}", name, value);
+      },
+    }
+  }
+}
+
+// keeps warnings and errors in memory for the log viewer
+struct RecentLogsLayer;
+
+impl<S: Subscriber> Layer<S> for RecentLogsLayer {
+  fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+    let metadata = event.metadata();
+    if *metadata.level() > Level::WARN {
+      return;
+    }
+    let mut visitor = MessageVisitor::default();
+    event.record(&mut visitor);
+    let entry = LogEntry {
+      level: *metadata.level(),
+      target: metadata.target().to_string(),
+      message: format!("{}{}", visitor.message, visitor.fields),
+    };
+    let mut logs = RECENT_LOGS.lock().unwrap();
+    logs.push_back(entry);
+    if logs.len() > RECENT_LOG_LIMIT {
+      logs.pop_front();
+    }
+  }
+}
+
 pub fn initialize_logging() -> Result<()> {
   let directory = get_data_dir();
   std::fs::create_dir_all(directory.clone())?;
+  std::fs::create_dir_all(get_log_dir())?;
   let log_path = directory.join(LOG_FILE.clone());
-  let log_file = std::fs::File::create(log_path)?;
+  // until a session starts, lines go to the log file for the whole program
+  *LOG_WRITER.lock().unwrap() = Some(Box::new(std::fs::File::create(log_path)?));
   std::env::set_var(
     "RUST_LOG",
     std::env::var("RUST_LOG")
@@ -113,7 +223,7 @@ pub fn initialize_logging() -> Result<()> {
   let file_subscriber = tracing_subscriber::fmt::layer()
     .with_file(true)
     .with_line_number(true)
-    .with_writer(log_file)
+    .with_writer(|| LogWriter)
     .with_target(false)
     .pretty()
     .fmt_fields(format::PrettyFields::new())
@@ -124,7 +234,12 @@ pub fn initialize_logging() -> Result<()> {
   //  .with_filter(filter::filter_fn(|metadata| {
   //              !metadata.().starts_with("metrics")
   //          }))
-  tracing_subscriber::registry().with(file_subscriber).with(console_layer).with(ErrorLayer::default()).init();
+  tracing_subscriber::registry()
+    .with(file_subscriber)
+    .with(RecentLogsLayer)
+    .with(console_layer)
+    .with(ErrorLayer::default())
+    .init();
   Ok(())
 }
 
@@ -179,6 +294,7 @@ pub fn version() -> String {
   // let current_exe_path = PathBuf::from(clap::crate_name!()).display().to_string();
   let config_dir_path = get_config_dir().display().to_string();
   let data_dir_path = get_data_dir().display().to_string();
+  let log_dir_path = get_log_dir().display().to_string();
 
   format!(
     "\
@@ -187,6 +303,7 @@ pub fn version() -> String {
 Authors: {author}
 
 Config directory: {config_dir_path}
-Data directory: {data_dir_path}"
+Data directory: {data_dir_path}
+Log directory: {log_dir_path}"
   )
 }